@@ -43,7 +43,8 @@ int main(int argc, char **argv) {
 
   // Read pairing file
   IdevicePairingFile *pairing = NULL;
-  IdeviceErrorCode err = idevice_pairing_file_read(pairing_file, &pairing);
+  IdeviceErrorCode err =
+      idevice_pairing_file_read(pairing_file, &pairing, NULL);
   if (err != IdeviceSuccess) {
     fprintf(stderr, "Failed to read pairing file: %d\n", err);
     return 1;
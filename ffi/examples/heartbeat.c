@@ -11,6 +11,15 @@ int main() {
   // Initialize logger
   idevice_init_logger(Debug, Disabled, NULL);
 
+  // A heartbeat doesn't need a thread per core
+  IdeviceConfig config = idevice_config_default();
+  config.worker_threads = 1;
+  config.connect_timeout_ms = 5000;
+  if (idevice_init(&config) != IdeviceSuccess) {
+    fprintf(stderr, "Failed to configure the runtime\n");
+    return 1;
+  }
+
   // Create the socket address (replace with your device's IP)
   struct sockaddr_in addr;
   memset(&addr, 0, sizeof(addr));
@@ -21,7 +30,7 @@ int main() {
   // Read pairing file (replace with your pairing file path)
   IdevicePairingFile *pairing_file = NULL;
   IdeviceErrorCode err =
-      idevice_pairing_file_read("pairing_file.plist", &pairing_file);
+      idevice_pairing_file_read("pairing_file.plist", &pairing_file, NULL);
   if (err != IdeviceSuccess) {
     fprintf(stderr, "Failed to read pairing file: %d\n", err);
     return 1;
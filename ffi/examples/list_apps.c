@@ -19,10 +19,14 @@ int main() {
 
   // Read pairing file (replace with your pairing file path)
   IdevicePairingFile *pairing_file = NULL;
-  IdeviceErrorCode err =
-      idevice_pairing_file_read("pairing_file.plist", &pairing_file);
+  IdevicePairingFileError pairing_error = {0};
+  IdeviceErrorCode err = idevice_pairing_file_read(
+      "pairing_file.plist", &pairing_file, &pairing_error);
   if (err != IdeviceSuccess) {
-    fprintf(stderr, "Failed to read pairing file: %d\n", err);
+    fprintf(stderr, "Failed to read pairing file: %s\n",
+            pairing_error.message ? pairing_error.message : "unknown error");
+    idevice_string_free(pairing_error.key);
+    idevice_string_free(pairing_error.message);
     return 1;
   }
 
@@ -105,7 +105,7 @@ int main(int argc, char **argv) {
   // Read pairing file
   IdevicePairingFile *pairing_file = NULL;
   IdeviceErrorCode err =
-      idevice_pairing_file_read(pairing_file_path, &pairing_file);
+      idevice_pairing_file_read(pairing_file_path, &pairing_file, NULL);
   if (err != IdeviceSuccess) {
     fprintf(stderr, "Failed to read pairing file: %d\n", err);
     return 1;
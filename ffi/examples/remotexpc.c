@@ -38,7 +38,7 @@ int main() {
   // Read pairing file (replace with your pairing file path)
   IdevicePairingFile *pairing_file = NULL;
   IdeviceErrorCode err =
-      idevice_pairing_file_read("pairing_file.plist", &pairing_file);
+      idevice_pairing_file_read("pairing_file.plist", &pairing_file, NULL);
   if (err != IdeviceSuccess) {
     fprintf(stderr, "Failed to read pairing file: %d\n", err);
     return 1;
@@ -0,0 +1,157 @@
+// Jackson Coxson
+
+use std::ffi::{CStr, c_char, c_void};
+
+use idevice::{IdeviceService, afc::AfcClient};
+
+use crate::{
+    IdeviceErrorCode, RUNTIME,
+    provider::{TcpProviderHandle, UsbmuxdProviderHandle},
+};
+
+pub struct AfcClientHandle(pub AfcClient);
+
+/// Called with each chunk of a file as it arrives
+///
+/// # Arguments
+/// * `data` - The chunk, only valid until the callback returns
+/// * `len` - Length of the chunk in bytes
+/// * `context` - The context passed to the read function
+///
+/// # Returns
+/// true to keep reading, false to stop
+pub type AfcChunkCallback =
+    extern "C" fn(data: *const u8, len: usize, context: *mut c_void) -> bool;
+
+/// Automatically creates and connects to AFC, returning a client handle
+///
+/// # Arguments
+/// * [`provider`] - A TcpProvider
+/// * [`client`] - On success, will be set to point to a newly allocated AfcClient handle
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `provider` must be a valid pointer to a handle allocated by this library
+/// `client` must be a valid, non-null pointer to a location where the handle will be stored
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn afc_client_connect_tcp(
+    provider: *mut TcpProviderHandle,
+    client: *mut *mut AfcClientHandle,
+) -> IdeviceErrorCode {
+    if provider.is_null() || client.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let provider_ref = unsafe { &(*provider).0 };
+    match RUNTIME.block_on(AfcClient::connect(provider_ref)) {
+        Ok(r) => {
+            let boxed = Box::new(AfcClientHandle(r));
+            unsafe { *client = Box::into_raw(boxed) };
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Automatically creates and connects to AFC, returning a client handle
+///
+/// # Arguments
+/// * [`provider`] - A UsbmuxdProvider
+/// * [`client`] - On success, will be set to point to a newly allocated AfcClient handle
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `provider` must be a valid pointer to a handle allocated by this library
+/// `client` must be a valid, non-null pointer to a location where the handle will be stored
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn afc_client_connect_usbmuxd(
+    provider: *mut UsbmuxdProviderHandle,
+    client: *mut *mut AfcClientHandle,
+) -> IdeviceErrorCode {
+    if provider.is_null() || client.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let provider_ref = unsafe { &(*provider).0 };
+    match RUNTIME.block_on(AfcClient::connect(provider_ref)) {
+        Ok(r) => {
+            let boxed = Box::new(AfcClientHandle(r));
+            unsafe { *client = Box::into_raw(boxed) };
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Reads a file, handing each chunk to the callback as it arrives so the file never has
+/// to fit in memory. The callback runs on the calling thread.
+///
+/// # Arguments
+/// * [`client`] - A valid AfcClient handle
+/// * [`path`] - Path to the file on the device
+/// * [`callback`] - Called with each chunk
+/// * [`context`] - User context to pass to callback
+/// * [`bytes_read`] - If not NULL, will be set to how many bytes were handed to the callback
+///
+/// # Returns
+/// An error code indicating success or failure. Stopping early from the callback is a success.
+///
+/// # Safety
+/// `client` must be a valid pointer to a handle allocated by this library
+/// `path` must be a valid null-terminated C string
+/// `bytes_read` must be NULL or a valid pointer to a u64
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn afc_read_file_streamed(
+    client: *mut AfcClientHandle,
+    path: *const c_char,
+    callback: AfcChunkCallback,
+    context: *mut c_void,
+    bytes_read: *mut u64,
+) -> IdeviceErrorCode {
+    if client.is_null() || path.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let path = match unsafe { CStr::from_ptr(path) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return IdeviceErrorCode::InvalidString,
+    };
+
+    let client_ref = unsafe { &mut (*client).0 };
+    let res = RUNTIME.block_on(
+        client_ref.read_file_streamed(path, |chunk| callback(chunk.as_ptr(), chunk.len(), context)),
+    );
+
+    match res {
+        Ok(read) => {
+            if !bytes_read.is_null() {
+                unsafe { *bytes_read = read };
+            }
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Frees a handle
+///
+/// # Arguments
+/// * [`handle`] - The handle to free
+///
+/// # Safety
+/// `handle` must be a valid pointer to the handle that was allocated by this library,
+/// or NULL (in which case this function does nothing)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn afc_client_free(handle: *mut AfcClientHandle) {
+    if !handle.is_null() {
+        log::debug!("Freeing afc_client");
+        let _ = unsafe { Box::from_raw(handle) };
+    }
+}
@@ -0,0 +1,155 @@
+// Jackson Coxson
+// Tuning for the runtime every call blocks on.
+// The runtime is built the first time anything needs it, with whatever config was set by
+// then. Hosts that want fewer threads or a connect timeout call idevice_init first.
+
+use std::{io, net::SocketAddr, sync::OnceLock, time::Duration};
+
+#[cfg(unix)]
+use std::path::Path;
+
+use idevice::{
+    ReadWrite,
+    runtime::{BoxFuture, Runtime, TokioRuntime},
+};
+use log::LevelFilter;
+use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
+use tokio::runtime;
+
+use crate::{IdeviceErrorCode, RUNTIME, logging::IdeviceLogLevel};
+
+static CONFIG: OnceLock<IdeviceConfig> = OnceLock::new();
+
+/// Options for the shared runtime. Zero leaves an option at its default.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IdeviceConfig {
+    /// Threads the runtime runs tasks on, one per core by default
+    pub worker_threads: u32,
+    /// Level logged to the console. Disabled leaves any logger already set up alone.
+    pub log_level: IdeviceLogLevel,
+    /// How long to wait for a socket to the device or usbmuxd to connect, in milliseconds
+    pub connect_timeout_ms: u64,
+}
+
+impl Default for IdeviceConfig {
+    fn default() -> Self {
+        Self {
+            worker_threads: 0,
+            log_level: IdeviceLogLevel::Disabled,
+            connect_timeout_ms: 0,
+        }
+    }
+}
+
+pub(crate) fn build_runtime() -> runtime::Runtime {
+    let config = CONFIG.get_or_init(IdeviceConfig::default);
+    let mut builder = runtime::Builder::new_multi_thread();
+    if config.worker_threads > 0 {
+        builder.worker_threads(config.worker_threads as usize);
+    }
+    builder.enable_io().enable_time().build().unwrap()
+}
+
+/// Gives up on connecting after a while, otherwise the same as tokio
+struct TimeoutRuntime {
+    timeout: Duration,
+}
+
+impl TimeoutRuntime {
+    fn connect(
+        &self,
+        connect: BoxFuture<'static, io::Result<Box<dyn ReadWrite>>>,
+    ) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        let timeout = self.timeout;
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, connect).await {
+                Ok(res) => res,
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out connecting",
+                )),
+            }
+        })
+    }
+}
+
+impl Runtime for TimeoutRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        TokioRuntime.spawn(task)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        TokioRuntime.sleep(duration)
+    }
+
+    fn connect_tcp(&self, addr: SocketAddr) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        self.connect(TokioRuntime.connect_tcp(addr))
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(&self, path: &Path) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        self.connect(TokioRuntime.connect_unix(path))
+    }
+}
+
+/// Returns the default config, to change before passing it to idevice_init
+#[unsafe(no_mangle)]
+pub extern "C" fn idevice_config_default() -> IdeviceConfig {
+    IdeviceConfig::default()
+}
+
+/// Configures the shared runtime. This has to be called before anything else in the library
+/// is used, and only once. It can be called from any thread.
+///
+/// # Arguments
+/// * [`config`] - The options to use
+///
+/// # Returns
+/// An error code indicating success or failure. AlreadyInitialized if the runtime is already
+/// running or idevice_init was called before.
+///
+/// # Safety
+/// `config` must be a valid pointer to an IdeviceConfig
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn idevice_init(config: *const IdeviceConfig) -> IdeviceErrorCode {
+    if config.is_null() {
+        return IdeviceErrorCode::InvalidArg;
+    }
+    let config = unsafe { *config };
+
+    // The runtime takes the default config when it's built, so this fails once it exists
+    if CONFIG.set(config).is_err() {
+        log::warn!("idevice_init called after the runtime was configured");
+        return IdeviceErrorCode::AlreadyInitialized;
+    }
+
+    if config.log_level != IdeviceLogLevel::Disabled {
+        let level: LevelFilter = config.log_level.into();
+        // An embedder may have set up its own logger, which only needs the level raised
+        if TermLogger::init(
+            level,
+            Config::default(),
+            TerminalMode::Mixed,
+            ColorChoice::Auto,
+        )
+        .is_err()
+        {
+            log::set_max_level(level);
+        }
+    }
+
+    if config.connect_timeout_ms > 0 {
+        let runtime = TimeoutRuntime {
+            timeout: Duration::from_millis(config.connect_timeout_ms),
+        };
+        if idevice::runtime::set_runtime(Box::new(runtime)).is_err() {
+            log::warn!("The connect timeout can't be set, something already connected");
+            return IdeviceErrorCode::AlreadyInitialized;
+        }
+    }
+
+    // Build it now so the first real call doesn't pay for it
+    once_cell::sync::Lazy::force(&RUNTIME);
+    IdeviceErrorCode::IdeviceSuccess
+}
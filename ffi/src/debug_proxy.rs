@@ -1,13 +1,16 @@
 // Jackson Coxson
 
-use std::ffi::{CStr, CString, c_char};
+use std::ffi::{CStr, CString, c_char, c_void};
 use std::os::raw::c_int;
 use std::ptr;
 
+use idevice::debug_proxy::jit::{JitStep, enable_jit};
 use idevice::debug_proxy::{DebugProxyClient, DebugserverCommand};
+use idevice::provider::IdeviceProvider;
 use idevice::tcp::adapter::Adapter;
 
 use crate::core_device_proxy::AdapterHandle;
+use crate::provider::{TcpProviderHandle, UsbmuxdProviderHandle};
 use crate::{IdeviceErrorCode, RUNTIME};
 
 /// Opaque handle to a DebugProxyClient
@@ -465,3 +468,259 @@ pub unsafe extern "C" fn debug_proxy_adapter_into_inner(
     unsafe { *adapter = Box::into_raw(boxed) };
     IdeviceErrorCode::IdeviceSuccess
 }
+
+/// Launches a process under debugserver with its output forwarded. It starts stopped.
+///
+/// # Arguments
+/// * [`handle`] - The DebugProxyClient handle
+/// * [`argv`] - The arguments, starting with the path of the executable
+/// * [`argv_count`] - Number of arguments
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `handle` must be a valid pointer
+/// `argv` must be a valid pointer to `argv_count` C strings
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn debugserver_launch(
+    handle: *mut DebugProxyAdapterHandle,
+    argv: *const *const c_char,
+    argv_count: usize,
+) -> IdeviceErrorCode {
+    if handle.is_null() || argv.is_null() || argv_count == 0 {
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let argv_slice = unsafe { std::slice::from_raw_parts(argv, argv_count) };
+    let mut argv_vec = Vec::with_capacity(argv_count);
+    for &arg in argv_slice {
+        if arg.is_null() {
+            return IdeviceErrorCode::InvalidArg;
+        }
+        match unsafe { CStr::from_ptr(arg) }.to_str() {
+            Ok(s) => argv_vec.push(s.to_string()),
+            Err(_) => return IdeviceErrorCode::InvalidString,
+        }
+    }
+
+    let client = unsafe { &mut (*handle).0 };
+    let res = RUNTIME.block_on(async move { client.launch(&argv_vec).await });
+
+    match res {
+        Ok(_) => IdeviceErrorCode::IdeviceSuccess,
+        Err(e) => e.into(),
+    }
+}
+
+/// Attaches debugserver to a running process, which stops it
+///
+/// # Arguments
+/// * [`handle`] - The DebugProxyClient handle
+/// * [`pid`] - The process to attach to
+/// * [`signal`] - If not NULL, will be set to the signal the process stopped with
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `handle` must be a valid pointer
+/// `signal` must be NULL or a valid pointer to a u8
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn debugserver_attach_pid(
+    handle: *mut DebugProxyAdapterHandle,
+    pid: u64,
+    signal: *mut u8,
+) -> IdeviceErrorCode {
+    if handle.is_null() {
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let client = unsafe { &mut (*handle).0 };
+    let res = RUNTIME.block_on(async move { client.attach(pid).await });
+
+    match res {
+        Ok(stop) => {
+            if !signal.is_null() {
+                unsafe { *signal = stop.signal };
+            }
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Detaches debugserver from the process, letting it run on
+///
+/// # Arguments
+/// * [`handle`] - The DebugProxyClient handle
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `handle` must be a valid pointer
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn debugserver_detach(
+    handle: *mut DebugProxyAdapterHandle,
+) -> IdeviceErrorCode {
+    if handle.is_null() {
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let client = unsafe { &mut (*handle).0 };
+    let res = RUNTIME.block_on(async move { client.detach().await });
+
+    match res {
+        Ok(_) => IdeviceErrorCode::IdeviceSuccess,
+        Err(e) => e.into(),
+    }
+}
+
+/// How far enabling JIT has got
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdeviceJitStep {
+    JitLaunching = 0,
+    JitAttaching = 1,
+    JitDetaching = 2,
+}
+
+/// Called as enabling JIT moves on to the next step
+///
+/// # Arguments
+/// * `step` - The step that is starting
+/// * `pid` - The launched process, or 0 while it's still launching
+/// * `context` - The context passed to enable_jit
+pub type JitProgressCallback = extern "C" fn(step: IdeviceJitStep, pid: u64, context: *mut c_void);
+
+/// Called once if enabling JIT fails
+///
+/// # Arguments
+/// * `step` - The step that failed
+/// * `error` - Why it failed
+/// * `message` - A description of the error, only valid until the callback returns
+/// * `context` - The context passed to enable_jit
+pub type JitErrorCallback = extern "C" fn(
+    step: IdeviceJitStep,
+    error: IdeviceErrorCode,
+    message: *const c_char,
+    context: *mut c_void,
+);
+
+fn run_enable_jit(
+    provider: &dyn IdeviceProvider,
+    bundle_id: *const c_char,
+    on_progress: Option<JitProgressCallback>,
+    on_error: Option<JitErrorCallback>,
+    context: *mut c_void,
+    pid: *mut u64,
+) -> IdeviceErrorCode {
+    let bundle_id = match unsafe { CStr::from_ptr(bundle_id) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return IdeviceErrorCode::InvalidString,
+    };
+
+    let mut last_step = IdeviceJitStep::JitLaunching;
+    let res = RUNTIME.block_on(enable_jit(provider, bundle_id, |step| {
+        let (step, step_pid) = match step {
+            JitStep::Launching => (IdeviceJitStep::JitLaunching, 0),
+            JitStep::Attaching { pid } => (IdeviceJitStep::JitAttaching, pid),
+            JitStep::Detaching { pid } => (IdeviceJitStep::JitDetaching, pid),
+        };
+        last_step = step;
+        if let Some(cb) = on_progress {
+            cb(step, step_pid, context);
+        }
+    }));
+
+    match res {
+        Ok(p) => {
+            if !pid.is_null() {
+                unsafe { *pid = p };
+            }
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => {
+            log::error!("Enabling JIT for {bundle_id} failed: {e:?}");
+            let message = CString::new(e.to_string()).unwrap_or_default();
+            let code: IdeviceErrorCode = e.into();
+            if let Some(cb) = on_error {
+                cb(last_step, code, message.as_ptr(), context);
+            }
+            code
+        }
+    }
+}
+
+/// Launches an app with JIT allowed. The app is launched suspended, then debugserver attaches
+/// to it and detaches again. It has to be signed with the get-task-allow entitlement.
+///
+/// # Arguments
+/// * [`provider`] - A TcpProvider
+/// * [`bundle_id`] - The app to launch
+/// * [`on_progress`] - Called as each step starts, or NULL
+/// * [`on_error`] - Called if a step fails, or NULL
+/// * [`context`] - User context to pass to the callbacks
+/// * [`pid`] - If not NULL, will be set to the launched process
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `provider` must be a valid pointer to a handle allocated by this library
+/// `bundle_id` must be a valid null-terminated C string
+/// `pid` must be NULL or a valid pointer to a u64
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn enable_jit_tcp(
+    provider: *mut TcpProviderHandle,
+    bundle_id: *const c_char,
+    on_progress: Option<JitProgressCallback>,
+    on_error: Option<JitErrorCallback>,
+    context: *mut c_void,
+    pid: *mut u64,
+) -> IdeviceErrorCode {
+    if provider.is_null() || bundle_id.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let provider_ref = unsafe { &(*provider).0 };
+    run_enable_jit(provider_ref, bundle_id, on_progress, on_error, context, pid)
+}
+
+/// Launches an app with JIT allowed. The app is launched suspended, then debugserver attaches
+/// to it and detaches again. It has to be signed with the get-task-allow entitlement.
+///
+/// # Arguments
+/// * [`provider`] - A UsbmuxdProvider
+/// * [`bundle_id`] - The app to launch
+/// * [`on_progress`] - Called as each step starts, or NULL
+/// * [`on_error`] - Called if a step fails, or NULL
+/// * [`context`] - User context to pass to the callbacks
+/// * [`pid`] - If not NULL, will be set to the launched process
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `provider` must be a valid pointer to a handle allocated by this library
+/// `bundle_id` must be a valid null-terminated C string
+/// `pid` must be NULL or a valid pointer to a u64
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn enable_jit_usbmuxd(
+    provider: *mut UsbmuxdProviderHandle,
+    bundle_id: *const c_char,
+    on_progress: Option<JitProgressCallback>,
+    on_error: Option<JitErrorCallback>,
+    context: *mut c_void,
+    pid: *mut u64,
+) -> IdeviceErrorCode {
+    if provider.is_null() || bundle_id.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let provider_ref = unsafe { &(*provider).0 };
+    run_enable_jit(provider_ref, bundle_id, on_progress, on_error, context, pid)
+}
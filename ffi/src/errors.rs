@@ -3,6 +3,7 @@
 use idevice::IdeviceError;
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IdeviceErrorCode {
     IdeviceSuccess = 0,
     // Main library
@@ -43,7 +44,15 @@ pub enum IdeviceErrorCode {
     Utf8Error = -35,
     InvalidArgument = -36,
     UnknownErrorType = -37,
+    InvalidPairingOffer = -38,
+    PairingOfferMismatch = -39,
+    Timeout = -40,
+    InvalidPairingFile = -41,
+    MessageTooLarge = -42,
+    DebugserverError = -43,
+    InvalidAfcPath = -44,
     // FFI specific bindings
+    AlreadyInitialized = -995,
     AdapterIOFailed = -996,
     ServiceNotFound = -997,
     BufferTooSmall = -998,
@@ -62,7 +71,9 @@ impl From<IdeviceError> for IdeviceErrorCode {
             IdeviceError::UnexpectedResponse => IdeviceErrorCode::UnexpectedResponse,
             IdeviceError::GetProhibited => IdeviceErrorCode::GetProhibited,
             IdeviceError::SessionInactive => IdeviceErrorCode::SessionInactive,
-            IdeviceError::InvalidHostID => IdeviceErrorCode::InvalidHostID,
+            IdeviceError::InvalidHostID | IdeviceError::InvalidPairRecord => {
+                IdeviceErrorCode::InvalidHostID
+            }
             IdeviceError::NoEstablishedConnection => IdeviceErrorCode::NoEstablishedConnection,
             IdeviceError::HeartbeatSleepyTime => IdeviceErrorCode::HeartbeatSleepyTime,
             IdeviceError::HeartbeatTimeout => IdeviceErrorCode::HeartbeatTimeout,
@@ -93,6 +104,13 @@ impl From<IdeviceError> for IdeviceErrorCode {
             IdeviceError::Utf8Error => IdeviceErrorCode::Utf8Error,
             IdeviceError::InvalidArgument => IdeviceErrorCode::InvalidArgument,
             IdeviceError::UnknownErrorType(_) => IdeviceErrorCode::UnknownErrorType,
+            IdeviceError::InvalidPairingOffer => IdeviceErrorCode::InvalidPairingOffer,
+            IdeviceError::PairingOfferMismatch => IdeviceErrorCode::PairingOfferMismatch,
+            IdeviceError::Timeout => IdeviceErrorCode::Timeout,
+            IdeviceError::InvalidPairingFile(_) => IdeviceErrorCode::InvalidPairingFile,
+            IdeviceError::MessageTooLarge(_) => IdeviceErrorCode::MessageTooLarge,
+            IdeviceError::DebugserverError(_) => IdeviceErrorCode::DebugserverError,
+            IdeviceError::InvalidAfcPath(_) => IdeviceErrorCode::InvalidAfcPath,
             _ => IdeviceErrorCode::InternalError,
         }
     }
@@ -1,6 +1,9 @@
 // Jackson Coxson
 
+use std::ffi::c_void;
+
 use idevice::{IdeviceError, IdeviceService, heartbeat::HeartbeatClient};
+use tokio::task::JoinHandle;
 
 use crate::{
     IdeviceErrorCode, IdeviceHandle, RUNTIME,
@@ -8,6 +11,8 @@ use crate::{
 };
 
 pub struct HeartbeatClientHandle(pub HeartbeatClient);
+/// A heartbeat being answered in the background
+pub struct HeartbeatKeepaliveHandle(JoinHandle<()>);
 #[allow(non_camel_case_types)]
 pub struct plist_t;
 
@@ -221,3 +226,149 @@ pub unsafe extern "C" fn heartbeat_client_free(handle: *mut HeartbeatClientHandl
         let _ = unsafe { Box::from_raw(handle) };
     }
 }
+
+/// Called when the device's heartbeat is missed or the keepalive stops
+///
+/// # Arguments
+/// * `missed` - How many heartbeats in a row have been missed
+/// * `error` - `HeartbeatTimeout` while the keepalive is still waiting, any other code
+///   means it has stopped and the connection should be re-established
+/// * `context` - The context passed to the start function
+pub type HeartbeatMissedCallback =
+    extern "C" fn(missed: u32, error: IdeviceErrorCode, context: *mut c_void);
+
+/// The caller promises the context can be used from the runtime's threads
+struct CallbackContext(*mut c_void);
+unsafe impl Send for CallbackContext {}
+
+fn start_keepalive(
+    mut client: HeartbeatClient,
+    callback: HeartbeatMissedCallback,
+    context: *mut c_void,
+) -> *mut HeartbeatKeepaliveHandle {
+    let context = CallbackContext(context);
+    let task = RUNTIME.spawn(async move {
+        // Moves the whole wrapper in rather than just the pointer inside it
+        let context = context;
+        let mut interval = 15;
+        let mut missed = 0;
+        loop {
+            // Give the device some slack past the interval it asked for
+            match client.get_marco(interval + 5).await {
+                Ok(i) => {
+                    interval = i;
+                    missed = 0;
+                    if let Err(e) = client.send_polo().await {
+                        callback(missed, e.into(), context.0);
+                        return;
+                    }
+                }
+                Err(IdeviceError::HeartbeatTimeout) => {
+                    missed += 1;
+                    callback(missed, IdeviceErrorCode::HeartbeatTimeout, context.0);
+                }
+                Err(e) => {
+                    callback(missed, e.into(), context.0);
+                    return;
+                }
+            }
+        }
+    });
+    Box::into_raw(Box::new(HeartbeatKeepaliveHandle(task)))
+}
+
+/// Connects to the heartbeat service and answers it in the background until stopped,
+/// which keeps the device from dropping the connection and any tunnels over it
+///
+/// # Arguments
+/// * [`provider`] - A TcpProvider
+/// * [`callback`] - Called on the runtime's threads when a heartbeat is missed
+/// * [`context`] - User context to pass to callback
+/// * [`handle`] - On success, will be set to a handle to pass to `heartbeat_stop`
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `provider` must be a valid pointer to a handle allocated by this library
+/// `handle` must be a valid, non-null pointer to a location where the handle will be stored
+/// `context` must stay valid until `heartbeat_stop` is called
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn heartbeat_start_tcp(
+    provider: *mut TcpProviderHandle,
+    callback: HeartbeatMissedCallback,
+    context: *mut c_void,
+    handle: *mut *mut HeartbeatKeepaliveHandle,
+) -> IdeviceErrorCode {
+    if provider.is_null() || handle.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let provider_ref = unsafe { &(*provider).0 };
+    match RUNTIME.block_on(HeartbeatClient::connect(provider_ref)) {
+        Ok(client) => {
+            unsafe { *handle = start_keepalive(client, callback, context) };
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Connects to the heartbeat service and answers it in the background until stopped,
+/// which keeps the device from dropping the connection and any tunnels over it
+///
+/// # Arguments
+/// * [`provider`] - A UsbmuxdProvider
+/// * [`callback`] - Called on the runtime's threads when a heartbeat is missed
+/// * [`context`] - User context to pass to callback
+/// * [`handle`] - On success, will be set to a handle to pass to `heartbeat_stop`
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// `provider` must be a valid pointer to a handle allocated by this library
+/// `handle` must be a valid, non-null pointer to a location where the handle will be stored
+/// `context` must stay valid until `heartbeat_stop` is called
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn heartbeat_start_usbmuxd(
+    provider: *mut UsbmuxdProviderHandle,
+    callback: HeartbeatMissedCallback,
+    context: *mut c_void,
+    handle: *mut *mut HeartbeatKeepaliveHandle,
+) -> IdeviceErrorCode {
+    if provider.is_null() || handle.is_null() {
+        log::error!("Null pointer provided");
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let provider_ref = unsafe { &(*provider).0 };
+    match RUNTIME.block_on(HeartbeatClient::connect(provider_ref)) {
+        Ok(client) => {
+            unsafe { *handle = start_keepalive(client, callback, context) };
+            IdeviceErrorCode::IdeviceSuccess
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Stops answering the heartbeat and frees the handle.
+/// The callback won't be called once this returns. Must not be called from the callback.
+///
+/// # Arguments
+/// * [`handle`] - The handle to stop
+///
+/// # Safety
+/// `handle` must be a valid pointer to the handle that was allocated by this library,
+/// or NULL (in which case this function does nothing)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn heartbeat_stop(handle: *mut HeartbeatKeepaliveHandle) {
+    if !handle.is_null() {
+        log::debug!("Stopping heartbeat keepalive");
+        let handle = unsafe { Box::from_raw(handle) };
+        handle.0.abort();
+        // Wait for the abort, so a callback isn't running while the context is freed
+        let _ = RUNTIME.block_on(handle.0);
+    }
+}
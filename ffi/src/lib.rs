@@ -1,6 +1,8 @@
 // Jackson Coxson
 
 pub mod adapter;
+pub mod afc;
+pub mod config;
 pub mod core_device_proxy;
 pub mod debug_proxy;
 mod errors;
@@ -22,15 +24,9 @@ pub use pairing_file::*;
 use idevice::{Idevice, IdeviceSocket};
 use once_cell::sync::Lazy;
 use std::ffi::{CStr, CString, c_char};
-use tokio::runtime::{self, Runtime};
-
-static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
-    runtime::Builder::new_multi_thread()
-        .enable_io()
-        .enable_time()
-        .build()
-        .unwrap()
-});
+use tokio::runtime::Runtime;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(config::build_runtime);
 
 pub const LOCKDOWN_PORT: u16 = 62078;
 
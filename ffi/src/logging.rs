@@ -82,7 +82,7 @@ pub enum IdeviceLoggerError {
 }
 
 #[repr(C)]
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IdeviceLogLevel {
     Disabled = 0,
     ErrorLevel = 1,
@@ -294,6 +294,74 @@ pub unsafe extern "C" fn image_mounter_upload_image(
     }
 }
 
+/// Uploads an image to the device by streaming it from a file, reporting progress
+///
+/// # Arguments
+/// * [`client`] - A valid ImageMounter handle
+/// * [`image_type`] - The type of image being uploaded
+/// * [`path`] - Path to the image on the host's filesystem
+/// * [`signature`] - Pointer to the signature data
+/// * [`signature_len`] - Length of the signature data
+/// * [`callback`] - Progress callback function
+/// * [`context`] - User context to pass to callback
+///
+/// # Returns
+/// An error code indicating success or failure
+///
+/// # Safety
+/// All pointers except `context` must be valid and non-null
+/// `image_type` and `path` must be valid null-terminated C strings
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn image_mounter_upload_image_from_path(
+    client: *mut ImageMounterHandle,
+    image_type: *const libc::c_char,
+    path: *const libc::c_char,
+    signature: *const u8,
+    signature_len: libc::size_t,
+    callback: extern "C" fn(progress: libc::size_t, total: libc::size_t, context: *mut c_void),
+    context: *mut c_void,
+) -> IdeviceErrorCode {
+    if client.is_null() || image_type.is_null() || path.is_null() || signature.is_null() {
+        return IdeviceErrorCode::InvalidArg;
+    }
+
+    let image_type = match unsafe { std::ffi::CStr::from_ptr(image_type) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return IdeviceErrorCode::InvalidArg,
+    };
+    let path = match unsafe { std::ffi::CStr::from_ptr(path) }.to_str() {
+        Ok(s) => s,
+        Err(_) => return IdeviceErrorCode::InvalidString,
+    };
+    let signature_slice = unsafe { std::slice::from_raw_parts(signature, signature_len) };
+
+    let res: Result<(), IdeviceError> = RUNTIME.block_on(async move {
+        let mut client_box = unsafe { Box::from_raw(client) };
+        let client_ref = &mut client_box.0;
+
+        let callback_wrapper = |((progress, total), context)| async move {
+            callback(progress, total, context);
+        };
+
+        let result = client_ref
+            .upload_image_from_path(
+                image_type,
+                path,
+                signature_slice.to_vec(),
+                callback_wrapper,
+                context,
+            )
+            .await;
+        std::mem::forget(client_box);
+        result
+    });
+
+    match res {
+        Ok(_) => IdeviceErrorCode::IdeviceSuccess,
+        Err(e) => e.into(),
+    }
+}
+
 /// Mounts an image on the device
 ///
 /// # Arguments
@@ -1,18 +1,113 @@
 // Jackson Coxson
 
-use idevice::pairing_file::PairingFile;
-use std::ffi::{CStr, c_char};
+use idevice::pairing_file::{PairingFile, PairingFileProblem};
+use std::ffi::{CStr, CString, c_char};
 
 use crate::IdeviceErrorCode;
 
 /// Opaque C-compatible handle to a PairingFile
 pub struct IdevicePairingFile(pub PairingFile);
 
-/// Reads a pairing file from the specified path
+/// Why a pairing file was rejected
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdevicePairingFileErrorReason {
+    PairingFileOk = 0,
+    /// The file couldn't be opened or read
+    PairingFileUnreadable = 1,
+    /// The file isn't a plist dictionary
+    PairingFileMalformedPlist = 2,
+    /// A required key is missing
+    PairingFileMissingKey = 3,
+    /// A key holds the wrong kind of value
+    PairingFileWrongType = 4,
+    /// A certificate isn't valid PEM
+    PairingFileBadCertificate = 5,
+    /// A private key isn't valid PEM
+    PairingFileBadKey = 6,
+    /// A certificate doesn't belong to the private key stored with it
+    PairingFileKeyMismatch = 7,
+    /// A certificate wasn't issued by the root certificate
+    PairingFileNotSigned = 8,
+    /// A certificate has expired and the device must be paired again
+    PairingFileExpired = 9,
+}
+
+/// Details on why a pairing file was rejected, for guiding the user through fixing it
+#[repr(C)]
+pub struct IdevicePairingFileError {
+    pub reason: IdevicePairingFileErrorReason,
+    /// The key at fault as named in the file, or NULL
+    pub key: *mut c_char,
+    /// A description to show the user, or NULL on success
+    pub message: *mut c_char,
+}
+
+impl IdevicePairingFileError {
+    fn new(problem: Option<&PairingFileProblem>) -> Self {
+        let problem = match problem {
+            Some(p) => p,
+            None => {
+                return Self {
+                    reason: IdevicePairingFileErrorReason::PairingFileOk,
+                    key: std::ptr::null_mut(),
+                    message: std::ptr::null_mut(),
+                };
+            }
+        };
+        let (reason, key) = match problem {
+            PairingFileProblem::Unreadable(_) => {
+                (IdevicePairingFileErrorReason::PairingFileUnreadable, None)
+            }
+            PairingFileProblem::MalformedPlist(_) => (
+                IdevicePairingFileErrorReason::PairingFileMalformedPlist,
+                None,
+            ),
+            PairingFileProblem::MissingKey(k) => (
+                IdevicePairingFileErrorReason::PairingFileMissingKey,
+                Some(k),
+            ),
+            PairingFileProblem::WrongType(k) => {
+                (IdevicePairingFileErrorReason::PairingFileWrongType, Some(k))
+            }
+            PairingFileProblem::BadCertificate(k) => (
+                IdevicePairingFileErrorReason::PairingFileBadCertificate,
+                Some(k),
+            ),
+            PairingFileProblem::BadKey(k) => {
+                (IdevicePairingFileErrorReason::PairingFileBadKey, Some(k))
+            }
+            PairingFileProblem::KeyMismatch(k) => (
+                IdevicePairingFileErrorReason::PairingFileKeyMismatch,
+                Some(k),
+            ),
+            PairingFileProblem::NotSigned(k) => {
+                (IdevicePairingFileErrorReason::PairingFileNotSigned, Some(k))
+            }
+            PairingFileProblem::Expired(k) => {
+                (IdevicePairingFileErrorReason::PairingFileExpired, Some(k))
+            }
+        };
+        let c_string = |s: &str| match CString::new(s) {
+            Ok(s) => s.into_raw(),
+            Err(_) => std::ptr::null_mut(),
+        };
+        Self {
+            reason,
+            key: key.map(|k| c_string(k)).unwrap_or(std::ptr::null_mut()),
+            message: c_string(&problem.to_string()),
+        }
+    }
+}
+
+/// Reads a pairing file from the specified path, checking that its keys and certificates
+/// belong together and haven't expired
 ///
 /// # Arguments
 /// * [`path`] - Path to the pairing file
 /// * [`pairing_file`] - On success, will be set to point to a newly allocated pairing file instance
+/// * [`error_detail`] - If not NULL, will be filled in with why the file was rejected.
+///   Its strings must be freed with `idevice_string_free`
 ///
 /// # Returns
 /// An error code indicating success or failure
@@ -20,10 +115,12 @@ pub struct IdevicePairingFile(pub PairingFile);
 /// # Safety
 /// `path` must be a valid null-terminated C string
 /// `pairing_file` must be a valid, non-null pointer to a location where the handle will be stored
+/// `error_detail` must be NULL or a valid pointer to an `IdevicePairingFileError`
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn idevice_pairing_file_read(
     path: *const c_char,
     pairing_file: *mut *mut IdevicePairingFile,
+    error_detail: *mut IdevicePairingFileError,
 ) -> IdeviceErrorCode {
     if path.is_null() || pairing_file.is_null() {
         return IdeviceErrorCode::InvalidArg;
@@ -36,7 +133,11 @@ pub unsafe extern "C" fn idevice_pairing_file_read(
     };
 
     // Read the pairing file
-    match PairingFile::read_from_file(c_str) {
+    let res = PairingFile::check_file(c_str);
+    if !error_detail.is_null() {
+        unsafe { *error_detail = IdevicePairingFileError::new(res.as_ref().err()) };
+    }
+    match res {
         Ok(pf) => {
             let boxed = Box::new(IdevicePairingFile(pf));
             unsafe {
@@ -44,7 +145,14 @@ pub unsafe extern "C" fn idevice_pairing_file_read(
             }
             IdeviceErrorCode::IdeviceSuccess
         }
-        Err(e) => e.into(),
+        Err(PairingFileProblem::Unreadable(e)) => {
+            log::error!("Unable to read pairing file: {e}");
+            IdeviceErrorCode::Socket
+        }
+        Err(e) => {
+            log::error!("Invalid pairing file: {e}");
+            IdeviceErrorCode::InvalidPairingFile
+        }
     }
 }
 
@@ -0,0 +1,51 @@
+// Jackson Coxson
+// cargo bench --features bench
+// Only the time spent on transfers is measured, not setting up the mock device.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use idevice::{bench, progress::TransferStats};
+use tokio::runtime::Runtime;
+
+const AFC_SIZES: [usize; 2] = [64 * 1024, 8 * 1024 * 1024];
+const PLIST_MESSAGES: usize = 100;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn total(iters: u64, mut run: impl FnMut() -> TransferStats) -> Duration {
+    (0..iters).map(|_| run().elapsed).sum()
+}
+
+fn afc(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("afc");
+    for size in AFC_SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("read", size), &size, |b, &size| {
+            b.iter_custom(|iters| total(iters, || rt.block_on(bench::afc_read(size)).unwrap()))
+        });
+        group.bench_with_input(BenchmarkId::new("write", size), &size, |b, &size| {
+            b.iter_custom(|iters| total(iters, || rt.block_on(bench::afc_write(size)).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+fn plist_codec(c: &mut Criterion) {
+    let bytes = bench::plist_codec(1).unwrap().bytes;
+    let mut group = c.benchmark_group("plist_codec");
+    group.throughput(Throughput::Bytes(bytes * PLIST_MESSAGES as u64));
+    group.bench_function("round_trip", |b| {
+        b.iter_custom(|iters| total(iters, || bench::plist_codec(PLIST_MESSAGES).unwrap()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, afc, plist_codec);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+
+use bytes::BytesMut;
+use idevice::codec::Codec;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = BytesMut::from(data);
+    while let Ok(Some(_)) = idevice::dvt::message::Message::decode(&mut buf) {}
+});
@@ -0,0 +1,331 @@
+//! A file handle on the device
+//!
+//! The other helpers open a file, go through it from start to end and close it. An
+//! [`AfcFile`] keeps the handle open so a caller can move around in it, for patching a few
+//! pages of a database or reading the tail of a log. Every call is one round trip, except
+//! reads and writes, which are split into chunks.
+
+use std::io::SeekFrom;
+
+use super::{AfcClient, AfcOperations, AfcPacket, AfcPath, AFC_OP_WOULD_BLOCK};
+use crate::IdeviceError;
+
+/// Most that's read or written in one request, the same as the other helpers
+pub(super) const CHUNK_SIZE: usize = 65536;
+
+/// How a file is opened, like the modes of ``fopen``
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AfcFopenMode {
+    /// ``r``, the file has to exist
+    RdOnly = 1,
+    /// ``r+``, the file has to exist
+    Rw = 2,
+    /// ``w``, the file is created or cut to nothing
+    WrOnly = 3,
+    /// ``w+``, the file is created or cut to nothing
+    Wr = 4,
+    /// ``a``, writes go to the end
+    Append = 5,
+    /// ``a+``, writes go to the end
+    RdAppend = 6,
+}
+
+/// An advisory lock on an open file, like ``flock``
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AfcLock {
+    Shared,
+    Exclusive,
+}
+
+// flock operations, always without blocking since afcd would hold up the whole connection
+const LOCK_SH: u64 = 1;
+const LOCK_EX: u64 = 2;
+const LOCK_NB: u64 = 4;
+const LOCK_UN: u64 = 8;
+
+/// An open file, from [`AfcFile::open`]
+///
+/// The client can't be used for anything else while the file is open. Dropping the file
+/// without calling ``close`` leaves it open until ``AfcClient::close``.
+pub struct AfcFile<'a> {
+    client: &'a mut AfcClient,
+    handle: u64,
+}
+
+impl<'a> AfcFile<'a> {
+    pub async fn open(
+        client: &'a mut AfcClient,
+        path: &str,
+        mode: AfcFopenMode,
+    ) -> Result<Self, IdeviceError> {
+        let mut open_data = (mode as u64).to_le_bytes().to_vec(); // mode + path + null
+        open_data.extend_from_slice(&AfcPath::new(path)?.to_bytes_with_nul());
+
+        client
+            .send_packet(AfcOperations::FileRefOpen, &open_data)
+            .await?;
+        let response = expect(
+            client.receive_packet().await?,
+            AfcOperations::FileRefOpen,
+            AfcOperations::FileRefOpenResult,
+        )?;
+        if response.len() < 8 {
+            return Err(IdeviceError::AfcError("Failed to open file".to_string()));
+        }
+
+        Ok(Self {
+            client,
+            handle: u64::from_le_bytes(response[..8].try_into().unwrap()),
+        })
+    }
+
+    /// Picks up a file kept open with ``into_handle``
+    pub fn from_handle(client: &'a mut AfcClient, handle: u64) -> Self {
+        Self { client, handle }
+    }
+
+    /// The handle afcd gave out for the file
+    pub fn handle(&self) -> u64 {
+        self.handle
+    }
+
+    /// Gives the client back and leaves the file open, for holding a lock while the
+    /// client does other things
+    pub fn into_handle(self) -> u64 {
+        self.handle
+    }
+
+    /// Reads up to ``len`` bytes from the current position, fewer at the end of the file
+    pub async fn read(&mut self, len: usize) -> Result<Vec<u8>, IdeviceError> {
+        let mut data = Vec::new();
+        while data.len() < len {
+            let want = (len - data.len()).min(CHUNK_SIZE) as u64;
+            let mut request = self.handle.to_le_bytes().to_vec();
+            request.extend_from_slice(&want.to_le_bytes());
+            let chunk = self.request(AfcOperations::FileRefRead, &request).await?;
+            let short = (chunk.len() as u64) < want;
+            data.extend_from_slice(&chunk);
+            if short {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Writes all of ``buf`` at the current position
+    pub async fn write(&mut self, buf: &[u8]) -> Result<(), IdeviceError> {
+        for chunk in buf.chunks(CHUNK_SIZE) {
+            let mut request = self.handle.to_le_bytes().to_vec();
+            request.extend_from_slice(chunk);
+            self.request(AfcOperations::FileRefWrite, &request).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the position. Use ``tell`` to find out where it ended up.
+    pub async fn seek(&mut self, pos: SeekFrom) -> Result<(), IdeviceError> {
+        // SEEK_SET, SEEK_CUR and SEEK_END
+        let (whence, offset) = match pos {
+            SeekFrom::Start(offset) => match i64::try_from(offset) {
+                Ok(offset) => (0u64, offset),
+                Err(_) => return Err(IdeviceError::InvalidArgument),
+            },
+            SeekFrom::Current(offset) => (1, offset),
+            SeekFrom::End(offset) => (2, offset),
+        };
+        let mut request = self.handle.to_le_bytes().to_vec();
+        request.extend_from_slice(&whence.to_le_bytes());
+        request.extend_from_slice(&offset.to_le_bytes());
+        self.request(AfcOperations::FileRefSeek, &request).await?;
+        Ok(())
+    }
+
+    /// The current position
+    pub async fn tell(&mut self) -> Result<u64, IdeviceError> {
+        let response = self
+            .request(AfcOperations::FileRefTell, &self.handle.to_le_bytes())
+            .await?;
+        match response.get(..8) {
+            Some(pos) => Ok(u64::from_le_bytes(pos.try_into().unwrap())),
+            None => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Truncates or extends the file to ``size`` bytes
+    pub async fn set_size(&mut self, size: u64) -> Result<(), IdeviceError> {
+        let mut request = self.handle.to_le_bytes().to_vec();
+        request.extend_from_slice(&size.to_le_bytes());
+        self.request(AfcOperations::FileRefSetSize, &request)
+            .await?;
+        Ok(())
+    }
+
+    /// Takes the lock if nothing else holds one that conflicts.
+    /// Returns ``false`` instead of waiting when something does.
+    pub async fn try_lock(&mut self, lock: AfcLock) -> Result<bool, IdeviceError> {
+        let operation = match lock {
+            AfcLock::Shared => LOCK_SH,
+            AfcLock::Exclusive => LOCK_EX,
+        };
+        let mut request = self.handle.to_le_bytes().to_vec();
+        request.extend_from_slice(&(operation | LOCK_NB).to_le_bytes());
+        self.client
+            .send_packet(AfcOperations::FileRefLock, &request)
+            .await?;
+        let packet = self.client.receive_packet().await?;
+        if packet.operation == AfcOperations::Status as u64
+            && packet.data.get(..8) == Some(&AFC_OP_WOULD_BLOCK.to_le_bytes()[..])
+        {
+            return Ok(false);
+        }
+        expect(packet, AfcOperations::FileRefLock, AfcOperations::Status)?;
+        Ok(true)
+    }
+
+    /// Gives up a lock from ``try_lock``. Closing the file does too.
+    pub async fn unlock(&mut self) -> Result<(), IdeviceError> {
+        let mut request = self.handle.to_le_bytes().to_vec();
+        request.extend_from_slice(&(LOCK_UN | LOCK_NB).to_le_bytes());
+        self.request(AfcOperations::FileRefLock, &request).await?;
+        Ok(())
+    }
+
+    /// Closes the file. Writes aren't guaranteed to be on the device until this returns.
+    pub async fn close(mut self) -> Result<(), IdeviceError> {
+        self.request(AfcOperations::FileRefClose, &self.handle.to_le_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Closes the file once ``res``, the result of working on it, is known. An error in
+    /// ``res`` is returned ahead of one from closing, which is still attempted.
+    pub(crate) async fn close_after<T>(
+        self,
+        res: Result<T, IdeviceError>,
+    ) -> Result<T, IdeviceError> {
+        let closed = self.close().await;
+        let value = res?;
+        closed?;
+        Ok(value)
+    }
+
+    async fn request(
+        &mut self,
+        operation: AfcOperations,
+        data: &[u8],
+    ) -> Result<Vec<u8>, IdeviceError> {
+        self.client.send_packet(operation, data).await?;
+        let result = match operation {
+            AfcOperations::FileRefRead => AfcOperations::Data,
+            AfcOperations::FileRefTell => AfcOperations::FileRefTellResult,
+            _ => AfcOperations::Status,
+        };
+        expect(self.client.receive_packet().await?, operation, result)
+    }
+}
+
+/// The data of a ``result`` packet, or the status afcd sent instead as an error
+pub(super) fn expect(
+    packet: AfcPacket,
+    operation: AfcOperations,
+    result: AfcOperations,
+) -> Result<Vec<u8>, IdeviceError> {
+    if packet.operation == AfcOperations::Status as u64 {
+        let status = match packet.data.get(..8) {
+            Some(s) => u64::from_le_bytes(s.try_into().unwrap()),
+            None => return Err(IdeviceError::UnexpectedResponse),
+        };
+        return match status {
+            0 if result == AfcOperations::Status => Ok(Vec::new()),
+            _ => Err(IdeviceError::AfcError(format!(
+                "{operation:?} failed with status {status}"
+            ))),
+        };
+    }
+    if packet.operation != result as u64 {
+        return Err(IdeviceError::UnexpectedResponse);
+    }
+    Ok(packet.data)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn afc_file_random_access() {
+        let responder = AfcResponder::new().with_file("/Downloads/db.sqlite", b"0123456789");
+        let mut afc = client(&responder);
+
+        let mut file = AfcFile::open(&mut afc, "/Downloads/db.sqlite", AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        file.seek(SeekFrom::Start(4)).await.unwrap();
+        assert_eq!(file.read(3).await.unwrap(), b"456");
+        assert_eq!(file.tell().await.unwrap(), 7);
+        file.seek(SeekFrom::Current(-5)).await.unwrap();
+        file.write(b"ab").await.unwrap();
+        file.seek(SeekFrom::End(-1)).await.unwrap();
+        assert_eq!(file.read(100).await.unwrap(), b"9");
+        file.set_size(12).await.unwrap();
+        assert!(file.seek(SeekFrom::Current(-20)).await.is_err());
+        file.close().await.unwrap();
+        assert_eq!(
+            responder.file("/Downloads/db.sqlite").unwrap(),
+            b"01ab456789\0\0"
+        );
+
+        let mut file = AfcFile::open(&mut afc, "/Downloads/db.sqlite", AfcFopenMode::RdOnly)
+            .await
+            .unwrap();
+        assert!(file.write(b"x").await.is_err());
+        file.close().await.unwrap();
+        assert!(
+            AfcFile::open(&mut afc, "/Downloads/missing", AfcFopenMode::RdOnly)
+                .await
+                .is_err()
+        );
+
+        assert_eq!(
+            afc.read_file_range("/Downloads/db.sqlite", 2, 3)
+                .await
+                .unwrap(),
+            b"ab4"
+        );
+        assert_eq!(
+            afc.read_file_range("/Downloads/db.sqlite", 8, 100)
+                .await
+                .unwrap(),
+            b"89\0\0"
+        );
+        assert!(afc
+            .read_file_range("/Downloads/missing", 0, 1)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn try_lock_gives_up_at_once() {
+        let responder = AfcResponder::new().with_file("/Downloads/db.sqlite", b"");
+        let mut holder = client(&responder);
+        let mut held = AfcFile::open(&mut holder, "/Downloads/db.sqlite", AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        assert!(held.try_lock(AfcLock::Exclusive).await.unwrap());
+
+        let mut afc = client(&responder);
+        let mut file = AfcFile::open(&mut afc, "/Downloads/db.sqlite", AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        assert!(!file.try_lock(AfcLock::Shared).await.unwrap());
+        held.unlock().await.unwrap();
+        assert!(file.try_lock(AfcLock::Shared).await.unwrap());
+        file.close().await.unwrap();
+        // Would block isn't taken for busy and sent again
+        assert_eq!(afc.transfer_stats().retries, 0);
+        held.close().await.unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+// Jackson Coxson
+// Typed file info, from the string map AFC answers ``GetFileInfo`` with.
+
+use std::{collections::HashMap, fmt};
+
+use crate::pretty::{format_unix_time, Pretty};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AfcFileKind {
+    File,
+    Directory,
+    Symlink,
+    /// Any other ``st_ifmt``, such as ``S_IFCHR``
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AfcFileInfo {
+    pub kind: AfcFileKind,
+    pub size: u64,
+    pub blocks: u64,
+    pub links: u64,
+    /// Nanoseconds since the epoch
+    pub modified: u64,
+    /// Nanoseconds since the epoch
+    pub created: u64,
+    pub link_target: Option<String>,
+}
+
+impl From<&HashMap<String, String>> for AfcFileInfo {
+    /// Missing or malformed fields are left as 0
+    fn from(info: &HashMap<String, String>) -> Self {
+        let number = |key: &str| info.get(key).and_then(|v| v.parse().ok()).unwrap_or(0);
+        let kind = match info.get("st_ifmt").map(|s| s.as_str()) {
+            Some("S_IFREG") => AfcFileKind::File,
+            Some("S_IFDIR") => AfcFileKind::Directory,
+            Some("S_IFLNK") => AfcFileKind::Symlink,
+            other => AfcFileKind::Other(other.unwrap_or_default().to_string()),
+        };
+        Self {
+            kind,
+            size: number("st_size"),
+            blocks: number("st_blocks"),
+            links: number("st_nlink"),
+            modified: number("st_mtime"),
+            created: number("st_birthtime"),
+            link_target: info
+                .get("st_linktarget")
+                .or_else(|| info.get("LinkTarget"))
+                .cloned(),
+        }
+    }
+}
+
+impl fmt::Display for AfcFileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Directory => write!(f, "directory"),
+            Self::Symlink => write!(f, "symlink"),
+            Self::Other(kind) => write!(f, "{kind}"),
+        }
+    }
+}
+
+/// ``file, 1024 bytes`` or ``symlink to /var/mobile``
+impl fmt::Display for AfcFileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.kind, &self.link_target) {
+            (AfcFileKind::Symlink, Some(target)) => write!(f, "symlink to {target}"),
+            (AfcFileKind::Directory, _) => write!(f, "directory"),
+            (kind, _) => write!(f, "{kind}, {} bytes", self.size),
+        }
+    }
+}
+
+impl Pretty for AfcFileInfo {
+    fn pretty(&self) -> String {
+        let mut out = format!(
+            "Kind: {}\nSize: {} bytes\nBlocks: {}\nLinks: {}\nModified: {}\nCreated: {}",
+            self.kind,
+            self.size,
+            self.blocks,
+            self.links,
+            format_unix_time(self.modified / 1_000_000_000),
+            format_unix_time(self.created / 1_000_000_000),
+        );
+        if let Some(target) = &self.link_target {
+            out.push_str(&format!("\nLink target: {target}"));
+        }
+        out
+    }
+}
@@ -0,0 +1,107 @@
+//! Making links on the device
+//!
+//! App data directories often hold symlinks, so restoring one file by file loses them
+//! unless they're made again with ``MakeLink``.
+
+use super::{file::expect, AfcClient, AfcOperations, AfcPath, AfcPathError};
+use crate::IdeviceError;
+
+/// The kind of link made by [`AfcClient::make_link`]
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// Another name for the same file. The target has to exist.
+    Hard = 1,
+    /// A path that's followed when the link is opened. It can be relative to the link's
+    /// directory and doesn't have to exist.
+    Symbolic = 2,
+}
+
+impl AfcClient {
+    /// Makes ``link_path`` a link to ``target``.
+    /// Fails if something is already at ``link_path``.
+    pub async fn make_link(
+        &mut self,
+        kind: LinkKind,
+        target: &str,
+        link_path: &str,
+    ) -> Result<(), IdeviceError> {
+        let mut data = (kind as u64).to_le_bytes().to_vec(); // kind + target + null + link + null
+        match kind {
+            LinkKind::Hard => data.extend_from_slice(&AfcPath::new(target)?.to_bytes_with_nul()),
+            // Stored as written, so it isn't normalized like a path to something that exists
+            LinkKind::Symbolic => {
+                if target.contains('\0') {
+                    return Err(AfcPathError::ContainsNul.into());
+                }
+                data.extend_from_slice(target.as_bytes());
+                data.push(0);
+            }
+        }
+        data.extend_from_slice(&AfcPath::new(link_path)?.to_bytes_with_nul());
+
+        self.send_packet(AfcOperations::MakeLink, &data).await?;
+        expect(
+            self.receive_packet().await?,
+            AfcOperations::MakeLink,
+            AfcOperations::Status,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::afc::{AfcFileInfo, AfcFileKind};
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn afc_client_makes_links() {
+        let responder = AfcResponder::new().with_file("/Library/Preferences/app.plist", b"prefs");
+        let mut afc = client(&responder);
+
+        afc.make_link(
+            LinkKind::Symbolic,
+            "Preferences/app.plist",
+            "/Library/current",
+        )
+        .await
+        .unwrap();
+        let info = afc.get_file_info("/Library/current").await.unwrap();
+        assert_eq!(info["st_linktarget"], "Preferences/app.plist");
+        let info = AfcFileInfo::from(&info);
+        assert_eq!(info.kind, AfcFileKind::Symlink);
+        assert_eq!(info.link_target.as_deref(), Some("Preferences/app.plist"));
+
+        // Symlink targets are sent as written, not normalized like paths
+        afc.make_link(LinkKind::Symbolic, "../Caches//", "/Library/up")
+            .await
+            .unwrap();
+        let info = afc.get_file_info("/Library/up").await.unwrap();
+        assert_eq!(info["st_linktarget"], "../Caches//");
+        assert!(afc
+            .make_link(LinkKind::Symbolic, "a\0b", "/Library/nul")
+            .await
+            .is_err());
+
+        afc.make_link(
+            LinkKind::Hard,
+            "/Library/Preferences/app.plist",
+            "/Library/hard",
+        )
+        .await
+        .unwrap();
+        assert_eq!(responder.file("/Library/hard").unwrap(), b"prefs");
+
+        assert!(afc
+            .make_link(LinkKind::Symbolic, "elsewhere", "/Library/current")
+            .await
+            .is_err());
+        assert!(afc
+            .make_link(LinkKind::Hard, "/Library/missing", "/Library/other")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Mirrored sync of a local directory onto the device
+//!
+//! Only files that differ are uploaded, compared either by size and modification time
+//! or by SHA1. With ``delete``, whatever is on the device but not in the local
+//! directory is removed, so the device ends up as a copy of the local tree.
+
+use super::{normalize_name, AfcClient, AfcPath};
+use crate::IdeviceError;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// How files that exist on both sides are compared
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncCompare {
+    /// Size and modification time. Uploads copy the local modification time to the
+    /// device, so files are only sent again once they change locally.
+    #[default]
+    Mtime,
+    /// Size, then a SHA1 of the contents. Slower, but doesn't trust timestamps.
+    Checksum,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Remove anything on the device that isn't in the local directory
+    pub delete: bool,
+    pub compare: SyncCompare,
+    /// Work out the changes without making them
+    pub dry_run: bool,
+}
+
+/// What a sync changed, or would change on a dry run.
+/// Paths are relative to the synced directories and use ``/`` as the separator.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    pub created_directories: Vec<String>,
+    pub uploaded: Vec<String>,
+    /// Removed from the device, either with ``delete`` or to replace a file with a
+    /// directory or the other way around. Removing a directory removes its contents.
+    pub deleted: Vec<String>,
+    /// Files that were already the same on the device
+    pub unchanged: usize,
+    /// Bytes uploaded
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Entry {
+    Directory,
+    File {
+        size: u64,
+        /// Nanoseconds since the unix epoch
+        mtime: u64,
+    },
+}
+
+/// Makes ``device_dir`` a copy of ``local_dir``, transferring only what differs
+pub async fn sync(
+    afc: &mut AfcClient,
+    local_dir: &Path,
+    device_dir: &str,
+    options: SyncOptions,
+) -> Result<SyncSummary, IdeviceError> {
+    let device_dir = AfcPath::new(device_dir)?;
+    let local = list_local(local_dir).await?;
+    let device = list_device(afc, &device_dir).await?;
+
+    let mut summary = SyncSummary::default();
+
+    // Parents sort before their children, so a removed directory is seen before its contents
+    for (path, entry) in &device {
+        if is_removed(&summary.deleted, path) {
+            continue;
+        }
+        let remove = match local.get(path) {
+            Some((local_entry, _)) => !same_kind(local_entry, entry),
+            None => options.delete,
+        };
+        if remove {
+            summary.deleted.push(path.clone());
+        }
+    }
+
+    for (path, (entry, local_path)) in &local {
+        let on_device = match device.get(path) {
+            Some(e) if !is_removed(&summary.deleted, path) => Some(e),
+            _ => None,
+        };
+        match (entry, on_device) {
+            (Entry::Directory, Some(_)) => {}
+            (Entry::Directory, None) => summary.created_directories.push(path.clone()),
+            (
+                Entry::File { size, mtime },
+                Some(Entry::File {
+                    size: device_size,
+                    mtime: device_mtime,
+                }),
+            ) => {
+                let unchanged = size == device_size
+                    && match options.compare {
+                        // Compared in seconds, as not every filesystem keeps nanoseconds
+                        SyncCompare::Mtime => mtime / 1_000_000_000 == device_mtime / 1_000_000_000,
+                        SyncCompare::Checksum => {
+                            let local_hash =
+                                openssl::sha::sha1(&tokio::fs::read(local_path).await?);
+                            afc.get_file_hash(&device_dir.join(path)?).await? == local_hash
+                        }
+                    };
+                if unchanged {
+                    summary.unchanged += 1;
+                } else {
+                    summary.uploaded.push(path.clone());
+                    summary.bytes += size;
+                }
+            }
+            (Entry::File { size, .. }, _) => {
+                summary.uploaded.push(path.clone());
+                summary.bytes += size;
+            }
+        }
+    }
+
+    if options.dry_run {
+        return Ok(summary);
+    }
+
+    for path in &summary.deleted {
+        afc.remove_path_and_contents(&device_dir.join(path)?)
+            .await?;
+    }
+    afc.make_directory(&device_dir).await?;
+    for path in &summary.created_directories {
+        afc.make_directory(&device_dir.join(path)?).await?;
+    }
+    for path in &summary.uploaded {
+        let remote = device_dir.join(path)?;
+        let Some((entry, local_path)) = local.get(path) else {
+            continue;
+        };
+        let mut file = tokio::fs::File::open(local_path).await?;
+        afc.write_file_from_reader(&remote, &mut file, |_| async {}, ())
+            .await?;
+        if let Entry::File { mtime, .. } = entry {
+            afc.set_mod_time(&remote, *mtime).await?;
+        }
+    }
+
+    Ok(summary)
+}
+
+pub(super) fn same_kind(a: &Entry, b: &Entry) -> bool {
+    matches!(
+        (a, b),
+        (Entry::Directory, Entry::Directory) | (Entry::File { .. }, Entry::File { .. })
+    )
+}
+
+/// Whether the path or one of its parents is being removed
+fn is_removed(deleted: &[String], path: &str) -> bool {
+    deleted.iter().any(|d| {
+        path.strip_prefix(d.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Every directory and regular file under ``root`` and where it is, keyed by relative path.
+/// Keys are in NFC to match device listings, whatever form the host stores names in.
+/// Symlinks aren't followed or copied.
+async fn list_local(root: &Path) -> Result<BTreeMap<String, (Entry, PathBuf)>, IdeviceError> {
+    let mut entries = BTreeMap::new();
+    let mut pending: Vec<(PathBuf, String)> = vec![(root.to_path_buf(), String::new())];
+
+    while let Some((dir, relative)) = pending.pop() {
+        let mut dir_entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = dir_entries.next_entry().await? {
+            let name = normalize_name(&entry.file_name().to_string_lossy());
+            let path = if relative.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative, name)
+            };
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push((entry.path(), path.clone()));
+                entries.insert(path, (Entry::Directory, entry.path()));
+            } else if file_type.is_file() {
+                let metadata = entry.metadata().await?;
+                let mtime = metadata
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64;
+                let file = Entry::File {
+                    size: metadata.len(),
+                    mtime,
+                };
+                entries.insert(path, (file, entry.path()));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Every directory and regular file under ``root`` on the device, keyed by relative path.
+/// A root that doesn't exist is empty.
+pub(super) async fn list_device(
+    afc: &mut AfcClient,
+    root: &AfcPath,
+) -> Result<BTreeMap<String, Entry>, IdeviceError> {
+    let mut entries = BTreeMap::new();
+    let root_info = afc.get_file_info(root).await?;
+    if root_info.get("st_ifmt").map(|s| s.as_str()) != Some("S_IFDIR") {
+        return Ok(entries);
+    }
+
+    let mut pending = vec![String::new()];
+    while let Some(relative) = pending.pop() {
+        let dir = root.join(&relative)?;
+        for name in afc.read_directory(&dir).await? {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = if relative.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative, name)
+            };
+            let info = afc.get_file_info(&root.join(&path)?).await?;
+            let number = |key: &str| {
+                info.get(key)
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+            match info.get("st_ifmt").map(|s| s.as_str()) {
+                Some("S_IFDIR") => {
+                    pending.push(path.clone());
+                    entries.insert(path, Entry::Directory);
+                }
+                Some("S_IFREG") => {
+                    let entry = Entry::File {
+                        size: number("st_size"),
+                        mtime: number("st_mtime"),
+                    };
+                    entries.insert(path, entry);
+                }
+                // Links and devices are skipped
+                _ => continue,
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn sync_only_sends_differences() {
+        let local_dir = std::env::temp_dir().join(format!("idevice-sync-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&local_dir);
+        std::fs::create_dir_all(local_dir.join("sub")).unwrap();
+        std::fs::write(local_dir.join("same.txt"), b"same").unwrap();
+        std::fs::write(local_dir.join("changed.txt"), b"new!").unwrap();
+        std::fs::write(local_dir.join("sub/added.txt"), b"added").unwrap();
+        std::fs::write(local_dir.join("replaced"), b"file").unwrap();
+        // Stored decomposed on the host and composed on the device
+        std::fs::write(local_dir.join("\u{304B}\u{3099}.txt"), b"ga").unwrap();
+
+        let responder = AfcResponder::new()
+            .with_file("/Sync/same.txt", b"same")
+            .with_file("/Sync/\u{304C}.txt", b"ga")
+            .with_file("/Sync/changed.txt", b"old!")
+            .with_file("/Sync/stale.txt", b"stale")
+            .with_file("/Sync/replaced/inner.txt", b"dir")
+            .with_file("/Sync/old/nested/file.txt", b"old");
+        let mut afc = client(&responder);
+
+        let options = SyncOptions {
+            delete: true,
+            compare: SyncCompare::Checksum,
+            dry_run: true,
+        };
+        let expected = SyncSummary {
+            created_directories: vec!["sub".into()],
+            uploaded: vec![
+                "changed.txt".into(),
+                "replaced".into(),
+                "sub/added.txt".into(),
+            ],
+            deleted: vec!["old".into(), "replaced".into(), "stale.txt".into()],
+            unchanged: 2,
+            bytes: 13,
+        };
+        let before = responder.paths();
+        assert_eq!(
+            sync(&mut afc, &local_dir, "/Sync", options.clone())
+                .await
+                .unwrap(),
+            expected
+        );
+        assert_eq!(responder.paths(), before);
+
+        let options = SyncOptions {
+            dry_run: false,
+            ..options
+        };
+        assert_eq!(
+            sync(&mut afc, &local_dir, "/Sync/", options).await.unwrap(),
+            expected
+        );
+        assert_eq!(
+            responder.paths(),
+            [
+                "/",
+                "/Sync",
+                "/Sync/changed.txt",
+                "/Sync/replaced",
+                "/Sync/same.txt",
+                "/Sync/sub",
+                "/Sync/sub/added.txt",
+                "/Sync/\u{304C}.txt",
+            ]
+        );
+        assert_eq!(responder.file("/Sync/changed.txt").unwrap(), b"new!");
+        assert_eq!(responder.file("/Sync/replaced").unwrap(), b"file");
+
+        // Only the files that were never uploaded still have the mock's own timestamp
+        let options = SyncOptions::default();
+        let summary = sync(&mut afc, &local_dir, "/Sync", options.clone())
+            .await
+            .unwrap();
+        assert_eq!(summary.uploaded, ["same.txt", "\u{304C}.txt"]);
+        assert_eq!(summary.unchanged, 3);
+        let summary = sync(&mut afc, &local_dir, "/Sync", options).await.unwrap();
+        assert!(summary.uploaded.is_empty());
+
+        std::fs::remove_dir_all(&local_dir).unwrap();
+    }
+}
@@ -3,15 +3,57 @@
 //! This module provides functionality to interact with the iOS device's filesystem
 //! through the AFC protocol.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
-use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use std::collections::HashMap;
+use crate::codec::{read_frame, Codec};
+use crate::progress::{ProgressEvent, ProgressObserver, TransferStats};
+use crate::throttle::RateLimiter;
+use crate::{provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
+use bytes::Bytes;
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use std::collections::{HashMap, HashSet};
+
+mod file;
+mod info;
+mod link;
+mod mirror;
+mod packet;
+mod path;
+mod pool;
+mod staging;
+mod walk;
+mod watch;
+mod writer;
+
+pub use file::{AfcFile, AfcFopenMode, AfcLock};
+use file::{expect, CHUNK_SIZE};
+pub use info::{AfcFileInfo, AfcFileKind};
+pub use link::LinkKind;
+pub use mirror::{sync, SyncCompare, SyncOptions, SyncSummary};
+pub use packet::{AfcPacket, AFC_MAGIC};
+pub use path::{normalize_name, AfcPath, AfcPathError, AFC_NAME_MAX, AFC_PATH_MAX};
+pub use pool::AfcPool;
+pub use staging::{Staging, PUBLIC_STAGING, STAGING_PREFIX};
+pub use walk::{AfcWalk, AfcWalkEntry};
+pub use watch::{watch, DirectoryWatcher, WatchEvent};
+pub use writer::AfcFileWriter;
 
 const AFC_SERVICE_NAME: &str = "com.apple.afc";
 
+/// How many times a request is sent again while the device says it's busy
+const MAX_RETRIES: u32 = 3;
+/// Waited before the first retry, and doubled for each one after
+const RETRY_DELAY: Duration = Duration::from_millis(20);
+
+// Statuses that mean the request can be sent again as is
+const AFC_OBJECT_BUSY: u64 = 17;
+const AFC_OP_WOULD_BLOCK: u64 = 19;
+const AFC_OP_INTERRUPTED: u64 = 21;
+
 /// AFC operation codes
 #[repr(u64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum AfcOperations {
     Status = 0x00000001,
     Data = 0x00000002,
@@ -26,90 +68,121 @@ enum AfcOperations {
     GetDeviceInfo = 0x0000000b,
     WriteFileAtomic = 0x0000000c,
     FileRefOpen = 0x0000000d,
-    FileRefRead = 0x0000000e,
-    FileRefWrite = 0x0000000f,
-    FileRefSeek = 0x00000010,
-    FileRefTell = 0x00000011,
-    FileRefClose = 0x00000012,
-    FileRefSetSize = 0x00000013,
-    GetConnectionInfo = 0x00000014,
-    SetConnectionOptions = 0x00000015,
-    RenamePath = 0x00000016,
-    SetFSBlockSize = 0x00000017,
-    SetSocketBlockSize = 0x00000018,
-    FileRefLock = 0x00000019,
-    MakeLink = 0x0000001a,
-    GetFileHash = 0x0000001b,
-    SetModTime = 0x0000001c,
-    GetFileHashWithRange = 0x0000001d,
-    FileRefSetImmutableHint = 0x0000001e,
-    GetSizeOfPathContents = 0x0000001f,
-    RemovePathAndContents = 0x00000020,
-    DirectoryEnumeratorRefOpen = 0x00000021,
-    DirectoryEnumeratorRefOpenDirectory = 0x00000022,
-    DirectoryEnumeratorRefRead = 0x00000023,
-    DirectoryEnumeratorRefClose = 0x00000024,
+    FileRefOpenResult = 0x0000000e,
+    FileRefRead = 0x0000000f,
+    FileRefWrite = 0x00000010,
+    FileRefSeek = 0x00000011,
+    FileRefTell = 0x00000012,
+    FileRefTellResult = 0x00000013,
+    FileRefClose = 0x00000014,
+    FileRefSetSize = 0x00000015,
+    GetConnectionInfo = 0x00000016,
+    SetConnectionOptions = 0x00000017,
+    RenamePath = 0x00000018,
+    SetFSBlockSize = 0x00000019,
+    SetSocketBlockSize = 0x0000001a,
+    FileRefLock = 0x0000001b,
+    MakeLink = 0x0000001c,
+    GetFileHash = 0x0000001d,
+    SetModTime = 0x0000001e,
+    GetFileHashWithRange = 0x0000001f,
+    FileRefSetImmutableHint = 0x00000020,
+    GetSizeOfPathContents = 0x00000021,
+    RemovePathAndContents = 0x00000022,
+    DirectoryEnumeratorRefOpen = 0x00000023,
+    DirectoryEnumeratorRefOpenResult = 0x00000024,
+    DirectoryEnumeratorRefRead = 0x00000025,
+    DirectoryEnumeratorRefClose = 0x00000026,
 }
 
-/// AFC packet header
-#[derive(Debug)]
-struct AfcPacketHeader {
-    entire_length: u64,
-    this_length: u64,
+/// AFC client for interacting with the iOS device's filesystem
+///
+/// Dropping the client, or an operation part way through, leaves afcd holding any files
+/// that were open until it notices the socket is gone. ``close`` closes them first.
+pub struct AfcClient {
+    socket: Box<dyn ReadWrite>,
     packet_num: u64,
-    operation: u64,
+    rate_limiter: Option<RateLimiter>,
+    /// Handles afcd has given out that haven't been closed yet
+    open_files: HashSet<u64>,
+    /// The request whose response hasn't been read yet
+    pending: Option<PendingRequest>,
+    stats: TransferStats,
 }
 
-impl AfcPacketHeader {
-    fn new(operation: AfcOperations, data_length: u64) -> Self {
+/// A request that's been sent, kept to send again if the device is busy
+struct PendingRequest {
+    operation: AfcOperations,
+    packet: Bytes,
+    sent: Instant,
+}
+
+impl IdeviceService for AfcClient {
+    fn service_name() -> &'static str {
+        AFC_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Self::connect_to_service(provider, AFC_SERVICE_NAME).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
+}
+
+impl AfcClient {
+    /// Connect to another service that speaks AFC, such as ``com.apple.crashreportcopymobile``
+    pub async fn connect_to_service(
+        provider: &dyn IdeviceProvider,
+        service_name: &str,
+    ) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(service_name).await?))
+    }
+
+    /// Create an AFC client over an existing connection to an AFC service
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
         Self {
-            entire_length: 40 + data_length, // header (40 bytes) + data length
-            this_length: 40 + data_length,
+            socket,
             packet_num: 0,
-            operation: operation as u64,
+            rate_limiter: None,
+            open_files: HashSet::new(),
+            pending: None,
+            stats: TransferStats::default(),
         }
     }
 
-    async fn serialize(&self, writer: &mut tokio::net::TcpStream) -> Result<(), IdeviceError> {
-        writer.write_u64(self.entire_length).await?;
-        writer.write_u64(self.this_length).await?;
-        writer.write_u64(self.packet_num).await?;
-        writer.write_u64(self.operation).await?;
-        writer.write_u64(0).await?; // Reserved
-        Ok(())
+    /// Throughput and retries of file reads and writes since the client was created or
+    /// the stats were last reset
+    pub fn transfer_stats(&self) -> TransferStats {
+        self.stats
     }
 
-    async fn deserialize(reader: &mut tokio::net::TcpStream) -> Result<Self, IdeviceError> {
-        let entire_length = reader.read_u64().await?;
-        let this_length = reader.read_u64().await?;
-        let packet_num = reader.read_u64().await?;
-        let operation = reader.read_u64().await?;
-        let _reserved = reader.read_u64().await?;
-
-        Ok(Self {
-            entire_length,
-            this_length,
-            packet_num,
-            operation,
-        })
+    pub fn reset_transfer_stats(&mut self) {
+        self.stats = TransferStats::default();
     }
-}
 
-/// AFC client for interacting with the iOS device's filesystem
-pub struct AfcClient {
-    socket: tokio::net::TcpStream,
-    packet_num: u64,
-}
+    /// Closes any files left open by an operation that failed or was cancelled, then shuts
+    /// the connection down
+    pub async fn close(mut self) -> Result<(), IdeviceError> {
+        // A cancelled operation may not have read its response, and it could be a handle
+        if self.pending.is_some() {
+            self.receive_response().await?;
+        }
+        let handles = self.open_files.iter().copied().collect::<Vec<u64>>();
+        for handle in handles {
+            self.send_packet(AfcOperations::FileRefClose, &handle.to_le_bytes())
+                .await?;
+            self.receive_response().await?;
+        }
+        self.socket.shutdown().await?;
+        Ok(())
+    }
 
-impl AfcClient {
-    /// Connect to the AFC service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(AFC_SERVICE_NAME).await?;
-        
-        Ok(Self {
-            socket: service.socket,
-            packet_num: 0,
-        })
+    /// Limit how fast packets are sent and received, or ``None`` to go as fast as the link allows.
+    /// Sharing a limiter between clients limits their combined rate.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<RateLimiter>) {
+        self.rate_limiter = rate_limiter;
     }
 
     /// Get device info
@@ -137,11 +210,9 @@ impl AfcClient {
         Ok(info)
     }
 
-    /// Read directory contents
+    /// Read directory contents. Names are returned in NFC, whatever form the device stores.
     pub async fn read_directory(&mut self, path: &str) -> Result<Vec<String>, IdeviceError> {
-        let path_bytes = path.as_bytes();
-        let mut data = vec![0; path_bytes.len() + 1]; // +1 for null terminator
-        data[..path_bytes.len()].copy_from_slice(path_bytes);
+        let data = AfcPath::new(path)?.to_bytes_with_nul();
         
         self.send_packet(AfcOperations::ReadDir, &data).await?;
         let response = self.receive_response().await?;
@@ -149,7 +220,7 @@ impl AfcClient {
         let mut entries = Vec::new();
         for item in response.split(|&b| b == 0) {
             if !item.is_empty() {
-                entries.push(String::from_utf8_lossy(item).to_string());
+                entries.push(normalize_name(&String::from_utf8_lossy(item)));
             }
         }
         
@@ -161,11 +232,10 @@ impl AfcClient {
         Ok(entries)
     }
 
-    /// Get file info
+    /// Get file info.
+    /// A symlink's target is under ``st_linktarget``, and ``LinkTarget`` where afcd puts it.
     pub async fn get_file_info(&mut self, path: &str) -> Result<HashMap<String, String>, IdeviceError> {
-        let path_bytes = path.as_bytes();
-        let mut data = vec![0; path_bytes.len() + 1]; // +1 for null terminator
-        data[..path_bytes.len()].copy_from_slice(path_bytes);
+        let data = AfcPath::new(path)?.to_bytes_with_nul();
         
         self.send_packet(AfcOperations::GetFileInfo, &data).await?;
         let response = self.receive_response().await?;
@@ -186,15 +256,40 @@ impl AfcClient {
                 info.insert(k, s);
             }
         }
+        if let Some(target) = info.get("LinkTarget").cloned() {
+            info.entry("st_linktarget".to_string()).or_insert(target);
+        }
         
         Ok(info)
     }
 
+    /// Get the total size in bytes of a file, or of everything under a directory.
+    /// Paths that don't exist count as 0.
+    pub async fn path_contents_size(&mut self, path: &str) -> Result<u64, IdeviceError> {
+        let mut total = 0;
+        let mut pending = vec![AfcPath::new(path)?];
+        
+        while let Some(path) = pending.pop() {
+            let info = self.get_file_info(&path).await?;
+            
+            if info.get("st_ifmt").map(|s| s.as_str()) == Some("S_IFDIR") {
+                for entry in self.read_directory(&path).await? {
+                    if entry == "." || entry == ".." {
+                        continue;
+                    }
+                    pending.push(path.join(&entry)?);
+                }
+            } else if let Some(size) = info.get("st_size").and_then(|s| s.parse::<u64>().ok()) {
+                total += size;
+            }
+        }
+        
+        Ok(total)
+    }
+
     /// Create directory
     pub async fn make_directory(&mut self, path: &str) -> Result<(), IdeviceError> {
-        let path_bytes = path.as_bytes();
-        let mut data = vec![0; path_bytes.len() + 1]; // +1 for null terminator
-        data[..path_bytes.len()].copy_from_slice(path_bytes);
+        let data = AfcPath::new(path)?.to_bytes_with_nul();
         
         self.send_packet(AfcOperations::MakeDir, &data).await?;
         let _ = self.receive_response().await?;
@@ -204,9 +299,7 @@ impl AfcClient {
 
     /// Remove path (file or empty directory)
     pub async fn remove_path(&mut self, path: &str) -> Result<(), IdeviceError> {
-        let path_bytes = path.as_bytes();
-        let mut data = vec![0; path_bytes.len() + 1]; // +1 for null terminator
-        data[..path_bytes.len()].copy_from_slice(path_bytes);
+        let data = AfcPath::new(path)?.to_bytes_with_nul();
         
         self.send_packet(AfcOperations::RemovePath, &data).await?;
         let _ = self.receive_response().await?;
@@ -214,14 +307,85 @@ impl AfcClient {
         Ok(())
     }
 
+    /// Remove a path and everything under it
+    pub async fn remove_path_and_contents(&mut self, path: &str) -> Result<(), IdeviceError> {
+        let data = AfcPath::new(path)?.to_bytes_with_nul();
+        
+        self.send_packet(AfcOperations::RemovePathAndContents, &data).await?;
+        expect(
+            self.receive_packet().await?,
+            AfcOperations::RemovePathAndContents,
+            AfcOperations::Status,
+        )?;
+        
+        Ok(())
+    }
+
+    /// Get the SHA1 of a file, hashed on the device
+    pub async fn get_file_hash(&mut self, path: &str) -> Result<Vec<u8>, IdeviceError> {
+        let data = AfcPath::new(path)?.to_bytes_with_nul();
+        
+        self.send_packet(AfcOperations::GetFileHash, &data).await?;
+        let response = expect(
+            self.receive_packet().await?,
+            AfcOperations::GetFileHash,
+            AfcOperations::Data,
+        )?;
+        
+        if response.len() != 20 {
+            return Err(IdeviceError::AfcError(format!("Failed to hash {}", path)));
+        }
+        
+        Ok(response)
+    }
+
+    /// Checks a file on the device has the given contents, using the device's SHA-1 of it so
+    /// it doesn't have to be read back
+    pub async fn verify_file(&mut self, path: &str, data: &[u8]) -> Result<(), IdeviceError> {
+        let expected = openssl::sha::sha1(data).to_vec();
+        let actual = self.get_file_hash(path).await?;
+        if actual != expected {
+            return Err(IdeviceError::HashMismatch {
+                path: path.to_string(),
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write a file and check it arrived intact, writing it once more if it didn't
+    pub async fn write_file_verified(&mut self, path: &str, data: &[u8]) -> Result<(), IdeviceError> {
+        self.write_file(path, data).await?;
+        match self.verify_file(path, data).await {
+            Err(IdeviceError::HashMismatch { .. }) => {
+                warn!("{path} was corrupted in transit, writing it again");
+                self.write_file(path, data).await?;
+                self.verify_file(path, data).await
+            }
+            res => res,
+        }
+    }
+
+    /// Set the modification time of a path, in nanoseconds since the unix epoch
+    pub async fn set_mod_time(&mut self, path: &str, mtime: u64) -> Result<(), IdeviceError> {
+        let mut data = mtime.to_le_bytes().to_vec(); // time + path + null
+        data.extend_from_slice(&AfcPath::new(path)?.to_bytes_with_nul());
+        
+        self.send_packet(AfcOperations::SetModTime, &data).await?;
+        expect(
+            self.receive_packet().await?,
+            AfcOperations::SetModTime,
+            AfcOperations::Status,
+        )?;
+        
+        Ok(())
+    }
+
     /// Rename path
     pub async fn rename_path(&mut self, from_path: &str, to_path: &str) -> Result<(), IdeviceError> {
-        let from_bytes = from_path.as_bytes();
-        let to_bytes = to_path.as_bytes();
-        
-        let mut data = vec![0; from_bytes.len() + 1 + to_bytes.len() + 1];
-        data[..from_bytes.len()].copy_from_slice(from_bytes);
-        data[from_bytes.len() + 1..from_bytes.len() + 1 + to_bytes.len()].copy_from_slice(to_bytes);
+        let mut data = AfcPath::new(from_path)?.to_bytes_with_nul();
+        data.extend_from_slice(&AfcPath::new(to_path)?.to_bytes_with_nul());
         
         self.send_packet(AfcOperations::RenamePath, &data).await?;
         let _ = self.receive_response().await?;
@@ -231,118 +395,648 @@ impl AfcClient {
 
     /// Read file
     pub async fn read_file(&mut self, path: &str) -> Result<Vec<u8>, IdeviceError> {
-        // Open file
-        let path_bytes = path.as_bytes();
-        let mut data = vec![0; path_bytes.len() + 1]; // +1 for null terminator
-        data[..path_bytes.len()].copy_from_slice(path_bytes);
-        
-        self.send_packet(AfcOperations::FileRefOpen, &data).await?;
-        let response = self.receive_response().await?;
-        
-        if response.len() < 8 {
-            return Err(IdeviceError::AfcError("Failed to open file".to_string()));
+        let mut content = Vec::new();
+        self.read_file_streamed(path, |chunk| {
+            content.extend_from_slice(chunk);
+            true
+        })
+        .await?;
+        Ok(content)
+    }
+
+    /// Read at most `len` bytes from the start of a file, e.g. to sniff headers
+    pub async fn read_file_prefix(&mut self, path: &str, len: u64) -> Result<Vec<u8>, IdeviceError> {
+        let len = usize::try_from(len).map_err(|_| IdeviceError::InvalidArgument)?;
+        let mut file = AfcFile::open(self, path, AfcFopenMode::RdOnly).await?;
+        let res = file.read(len).await;
+        file.close_after(res).await
+    }
+
+    /// Read at most `len` bytes starting at `offset`, without reading what comes before
+    pub async fn read_file_range(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, IdeviceError> {
+        let len = usize::try_from(len).map_err(|_| IdeviceError::InvalidArgument)?;
+        let mut file = AfcFile::open(self, path, AfcFopenMode::RdOnly).await?;
+        let res = async {
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.read(len).await
         }
-        
-        let file_handle = u64::from_le_bytes([
-            response[0], response[1], response[2], response[3],
-            response[4], response[5], response[6], response[7],
-        ]);
-        
-        // Read file content
-        let mut file_content = Vec::new();
-        let chunk_size = 65536; // 64KB chunks
-        
-        loop {
-            let mut read_data = vec![0; 8 + 8];
-            read_data[..8].copy_from_slice(&file_handle.to_le_bytes());
-            read_data[8..].copy_from_slice(&chunk_size.to_le_bytes());
-            
-            self.send_packet(AfcOperations::FileRefRead, &read_data).await?;
-            let chunk = self.receive_response().await?;
-            
-            if chunk.is_empty() {
-                break;
+        .await;
+        file.close_after(res).await
+    }
+
+    /// Read file, calling the callback with ((bytes read, total bytes), state) after each chunk
+    pub async fn read_file_with_progress<Fut, S>(
+        &mut self,
+        path: &str,
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<Vec<u8>, IdeviceError>
+    where
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let mut data = Vec::new();
+        self.read_file_to_writer(path, &mut data, callback, state)
+            .await?;
+        Ok(data)
+    }
+
+    /// Read file into a writer a chunk at a time, so a large file never has to be held in
+    /// memory. Progress is reported like ``read_file_with_progress``.
+    /// Returns how many bytes were written
+    pub async fn read_file_to_writer<W, Fut, S>(
+        &mut self,
+        path: &str,
+        writer: &mut W,
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<u64, IdeviceError>
+    where
+        W: AsyncWrite + Unpin,
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let total = self
+            .get_file_info(path)
+            .await?
+            .get("st_size")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        let mut file = AfcFile::open(self, path, AfcFopenMode::RdOnly).await?;
+        let res: Result<u64, IdeviceError> = async {
+            let mut read = 0;
+            loop {
+                let chunk = file.read(CHUNK_SIZE).await?;
+                if chunk.is_empty() {
+                    break;
+                }
+                writer.write_all(&chunk).await?;
+                read += chunk.len();
+                callback(((read, total.max(read)), state.clone())).await;
+                if chunk.len() < CHUNK_SIZE {
+                    break;
+                }
             }
-            
-            file_content.extend_from_slice(&chunk);
-            
-            if chunk.len() < chunk_size as usize {
-                break;
+            writer.flush().await?;
+            Ok(read as u64)
+        }
+        .await;
+        file.close_after(res).await
+    }
+
+    /// Read file, handing each chunk to the callback as it arrives instead of collecting
+    /// the whole file. Stops early if the callback returns false.
+    /// Returns how many bytes were handed to the callback
+    pub async fn read_file_streamed(
+        &mut self,
+        path: &str,
+        mut on_chunk: impl FnMut(&[u8]) -> bool,
+    ) -> Result<u64, IdeviceError> {
+        let mut file = AfcFile::open(self, path, AfcFopenMode::RdOnly).await?;
+        // The file is closed even when stopping early
+        let res = async {
+            let mut read = 0;
+            loop {
+                let chunk = file.read(CHUNK_SIZE).await?;
+                if chunk.is_empty() {
+                    break;
+                }
+                read += chunk.len() as u64;
+                if !on_chunk(&chunk) || chunk.len() < CHUNK_SIZE {
+                    break;
+                }
             }
+            Ok(read)
         }
-        
-        // Close file
-        let close_data = file_handle.to_le_bytes().to_vec();
-        self.send_packet(AfcOperations::FileRefClose, &close_data).await?;
-        let _ = self.receive_response().await?;
-        
-        Ok(file_content)
+        .await;
+        file.close_after(res).await
     }
 
-    /// Write file
+    /// Write file.
+    /// A memory-mapped file derefs to a slice and can be passed as is, which sends it straight
+    /// from the mapping without reading it into memory first.
     pub async fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), IdeviceError> {
-        // Open file with write mode (3)
-        let path_bytes = path.as_bytes();
-        let mut open_data = vec![0; path_bytes.len() + 1 + 8]; // path + null + mode
-        open_data[..path_bytes.len()].copy_from_slice(path_bytes);
-        // Write mode (3) at the end
-        open_data[path_bytes.len() + 1..].copy_from_slice(&3u64.to_le_bytes());
-        
-        self.send_packet(AfcOperations::FileRefOpen, &open_data).await?;
-        let response = self.receive_response().await?;
+        let mut file = AfcFile::open(self, path, AfcFopenMode::WrOnly).await?;
+        let res = file.write(data).await;
+        file.close_after(res).await
+    }
+
+    /// Write a file from a reader, a chunk at a time, so a large file never has to be held in
+    /// memory. Everything from the reader's current position to its end is written.
+    /// Progress is reported as bytes written out of the total.
+    pub async fn write_file_from_reader<R, Fut, S>(
+        &mut self,
+        path: &str,
+        reader: &mut R,
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<u64, IdeviceError>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let start = reader.stream_position().await?;
+        let total = reader.seek(std::io::SeekFrom::End(0)).await? - start;
+        reader.seek(std::io::SeekFrom::Start(start)).await?;
+
+        let mut file = AfcFile::open(self, path, AfcFopenMode::WrOnly).await?;
+        let res = async {
+            let mut buf = vec![0; (total as usize).min(CHUNK_SIZE)];
+            let mut written = 0;
+            while written < total {
+                let len = (total - written).min(CHUNK_SIZE as u64) as usize;
+                reader.read_exact(&mut buf[..len]).await?;
+                file.write(&buf[..len]).await?;
+                written += len as u64;
+                callback(((written as usize, total as usize), state.clone())).await;
+            }
+            Ok(written)
+        }
+        .await;
+        file.close_after(res).await
+    }
+
+    /// Write file, calling the callback with ((bytes written, total bytes), state) after each chunk
+    pub async fn write_file_with_progress<Fut, S>(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<(), IdeviceError>
+    where
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let mut file = AfcFile::open(self, path, AfcFopenMode::WrOnly).await?;
+        let res = async {
+            let mut written = 0;
+            for chunk in data.chunks(CHUNK_SIZE) {
+                file.write(chunk).await?;
+                written += chunk.len();
+                callback(((written, data.len()), state.clone())).await;
+            }
+            Ok(())
+        }
+        .await;
+        file.close_after(res).await
+    }
+
+    /// Download a directory tree into `local_dir`, reporting bytes copied to the observer
+    pub async fn pull_tree(
+        &mut self,
+        remote_dir: &str,
+        local_dir: &Path,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        let res = self.pull_tree_inner(remote_dir, local_dir, &observer).await;
+        observer.complete(res)
+    }
+
+    async fn pull_tree_inner(
+        &mut self,
+        remote_dir: &str,
+        local_dir: &Path,
+        observer: &ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.phase("Listing");
+        let files = self.list_tree(remote_dir, local_dir).await?;
         
-        if response.len() < 8 {
-            return Err(IdeviceError::AfcError("Failed to open file for writing".to_string()));
+        observer.phase("Copying");
+        let total = files.iter().map(|(_, _, size)| size).sum();
+        let mut done = 0;
+        for (remote, local, size) in files {
+            let data = self.read_file(&remote).await?;
+            tokio::fs::write(&local, data).await?;
+            done += size;
+            observer.emit(ProgressEvent::Progress { done, total });
         }
         
-        let file_handle = u64::from_le_bytes([
-            response[0], response[1], response[2], response[3],
-            response[4], response[5], response[6], response[7],
-        ]);
+        Ok(())
+    }
+
+    /// Creates the local directories for a remote tree and lists the files to copy into
+    /// them, as the remote path, local path and size
+    async fn list_tree(
+        &mut self,
+        remote_dir: &str,
+        local_dir: &Path,
+    ) -> Result<Vec<(String, PathBuf, u64)>, IdeviceError> {
+        let mut files = Vec::new();
+        let mut pending = vec![(AfcPath::new(remote_dir)?, local_dir.to_path_buf())];
         
-        // Write data in chunks
-        let chunk_size = 65536; // 64KB chunks
+        while let Some((remote, local)) = pending.pop() {
+            tokio::fs::create_dir_all(&local).await?;
+            for entry in self.read_directory(&remote).await? {
+                if entry == "." || entry == ".." {
+                    continue;
+                }
+                let remote_path = remote.join(&entry)?;
+                let info = self.get_file_info(&remote_path).await?;
+                match info.get("st_ifmt").map(|s| s.as_str()) {
+                    Some("S_IFDIR") => pending.push((remote_path, local.join(&entry))),
+                    Some("S_IFREG") => {
+                        let size = info.get("st_size").and_then(|s| s.parse::<u64>().ok()).unwrap_or(0);
+                        files.push((remote_path.to_string(), local.join(&entry), size));
+                    }
+                    // Links and devices aren't copied
+                    _ => continue,
+                }
+            }
+        }
         
-        for chunk in data.chunks(chunk_size) {
-            let mut write_data = vec![0; 8];
-            write_data[..8].copy_from_slice(&file_handle.to_le_bytes());
-            write_data.extend_from_slice(chunk);
-            
-            self.send_packet(AfcOperations::FileRefWrite, &write_data).await?;
-            let _ = self.receive_response().await?;
+        Ok(files)
+    }
+
+    /// Upload a local directory tree into `remote_dir`, reporting bytes copied to the observer.
+    ///
+    /// Files are sent uncompressed, and there's no option to compress them. afcd stores what
+    /// it's sent byte for byte and has nothing on the device side to decompress with, so a
+    /// compressed file would land on the device compressed.
+    /// For repeated syncs over Wi-Fi, ``sync`` only sends the files that changed.
+    pub async fn push_tree(
+        &mut self,
+        local_dir: &Path,
+        remote_dir: &str,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        let res = self.push_tree_inner(local_dir, remote_dir, &observer).await;
+        observer.complete(res)
+    }
+
+    async fn push_tree_inner(
+        &mut self,
+        local_dir: &Path,
+        remote_dir: &str,
+        observer: &ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.phase("Listing");
+        let mut files = Vec::new();
+        let mut pending = vec![(local_dir.to_path_buf(), AfcPath::new(remote_dir)?)];
+        
+        while let Some((local, remote)) = pending.pop() {
+            self.make_directory(&remote).await?;
+            let mut entries = tokio::fs::read_dir(&local).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let remote_path = remote.join(&name)?;
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    pending.push((entry.path(), remote_path));
+                } else if file_type.is_file() {
+                    files.push((entry.path(), remote_path.to_string(), entry.metadata().await?.len()));
+                }
+            }
         }
         
-        // Close file
-        let close_data = file_handle.to_le_bytes().to_vec();
-        self.send_packet(AfcOperations::FileRefClose, &close_data).await?;
-        let _ = self.receive_response().await?;
+        observer.phase("Copying");
+        let total = files.iter().map(|(_, _, size)| size).sum();
+        let mut done = 0;
+        for (local, remote, size) in files {
+            let mut file = tokio::fs::File::open(&local).await?;
+            self.write_file_from_reader(&remote, &mut file, |_| async {}, ())
+                .await?;
+            done += size;
+            observer.emit(ProgressEvent::Progress { done, total });
+        }
         
         Ok(())
     }
 
     // Helper methods
     async fn send_packet(&mut self, operation: AfcOperations, data: &[u8]) -> Result<(), IdeviceError> {
-        let header = AfcPacketHeader::new(operation, data.len() as u64);
-        header.serialize(&mut self.socket).await?;
-        
-        if !data.is_empty() {
-            self.socket.write_all(data).await?;
+        if operation == AfcOperations::FileRefClose && data.len() >= 8 {
+            let handle = u64::from_le_bytes(data[..8].try_into().unwrap());
+            self.open_files.remove(&handle);
         }
+        let packet = AfcPacket {
+            operation: operation as u64,
+            packet_num: self.packet_num,
+            data: data.to_vec(),
+        };
+        let packet = packet.encode()?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(packet.len()).await;
+        }
+        self.socket.write_all(&packet).await?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::sent(packet.len());
+        self.pending = Some(PendingRequest {
+            operation,
+            packet,
+            sent: Instant::now(),
+        });
         
         self.packet_num += 1;
         Ok(())
     }
 
     async fn receive_response(&mut self) -> Result<Vec<u8>, IdeviceError> {
-        let header = AfcPacketHeader::deserialize(&mut self.socket).await?;
-        
-        let data_length = (header.entire_length - 40) as usize;
-        if data_length > 0 {
-            let mut data = vec![0; data_length];
-            self.socket.read_exact(&mut data).await?;
-            Ok(data)
-        } else {
-            Ok(Vec::new())
+        Ok(self.receive_packet().await?.data)
+    }
+
+    /// Like ``receive_response``, keeping the operation to tell a status from data
+    async fn receive_packet(&mut self) -> Result<AfcPacket, IdeviceError> {
+        let mut retries = 0;
+        let packet = loop {
+            // Only cleared once the read finishes, so a cancelled read leaves it for close
+            let packet: Result<AfcPacket, _> = read_frame(&mut self.socket).await;
+            let pending = self.pending.take();
+            let packet = packet?;
+            let pending = match pending {
+                Some(p) => p,
+                None => break packet,
+            };
+
+            // A lock that would block is the answer ``try_lock`` asked for, not a reason to wait
+            if retries < MAX_RETRIES
+                && pending.operation != AfcOperations::FileRefLock
+                && is_busy(&packet)
+            {
+                retries += 1;
+                self.stats.retries += 1;
+                debug!("AFC is busy, sending {:?} again", pending.operation);
+                crate::runtime::sleep(RETRY_DELAY * 2u32.pow(retries - 1)).await;
+                self.socket.write_all(&pending.packet).await?;
+                self.pending = Some(pending);
+                continue;
+            }
+
+            match pending.operation {
+                AfcOperations::FileRefOpen
+                    if packet.operation == AfcOperations::FileRefOpenResult as u64
+                        && packet.data.len() >= 8 =>
+                {
+                    self.open_files
+                        .insert(u64::from_le_bytes(packet.data[..8].try_into().unwrap()));
+                }
+                AfcOperations::FileRefRead if packet.operation == AfcOperations::Data as u64 => {
+                    self.stats.bytes += packet.data.len() as u64;
+                    self.stats.elapsed += pending.sent.elapsed();
+                }
+                AfcOperations::FileRefWrite => {
+                    // The request is the header and the file handle, then the contents
+                    let written = pending.packet.len().saturating_sub(AfcPacket::HEADER_LEN + 8);
+                    self.stats.bytes += written as u64;
+                    self.stats.elapsed += pending.sent.elapsed();
+                }
+                _ => {}
+            }
+            break packet;
+        };
+        // The packet has already arrived, so this holds back the next request instead
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(AfcPacket::HEADER_LEN + packet.data.len()).await;
         }
+        Ok(packet)
     }
-}
\ No newline at end of file
+}
+
+fn is_busy(packet: &AfcPacket) -> bool {
+    if packet.operation != AfcOperations::Status as u64 || packet.data.len() < 8 {
+        return false;
+    }
+    let status = u64::from_le_bytes(packet.data[..8].try_into().unwrap());
+    matches!(status, AFC_OBJECT_BUSY | AFC_OP_WOULD_BLOCK | AFC_OP_INTERRUPTED)
+}
+
+#[cfg(all(test, feature = "testing"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::progress::forward;
+    use crate::testing::{AfcResponder, MockTransport, Responder};
+
+    /// A client talking to ``responder`` over a mock connection
+    pub(crate) fn client(responder: &AfcResponder) -> AfcClient {
+        let (host, device) = MockTransport::pair();
+        tokio::spawn(responder.serve(device));
+        AfcClient::new(Box::new(host))
+    }
+
+    #[tokio::test]
+    async fn afc_client_round_trip() {
+        let responder = AfcResponder::new().with_file("/DCIM/100APPLE/IMG_0001.JPG", b"jpeg");
+        let mut afc = client(&responder);
+
+        let mut entries = afc.read_directory("/DCIM/100APPLE").await.unwrap();
+        entries.sort();
+        assert_eq!(entries, vec![".", "..", "IMG_0001.JPG"]);
+        assert_eq!(
+            afc.read_file("/DCIM/100APPLE/IMG_0001.JPG").await.unwrap(),
+            b"jpeg"
+        );
+
+        afc.make_directory("/Downloads/nested").await.unwrap();
+        afc.write_file("/Downloads/nested/a.txt", b"hello")
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.file("/Downloads/nested/a.txt").as_deref(),
+            Some(&b"hello"[..])
+        );
+        let info = afc.get_file_info("/Downloads/nested/a.txt").await.unwrap();
+        assert_eq!(info.get("st_size").map(|s| s.as_str()), Some("5"));
+        assert_eq!(info.get("st_ifmt").map(|s| s.as_str()), Some("S_IFREG"));
+        assert_eq!(AfcFileInfo::from(&info).to_string(), "file, 5 bytes");
+
+        afc.rename_path("/Downloads/nested", "/Downloads/moved")
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.file("/Downloads/moved/a.txt").as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(responder.file("/Downloads/nested/a.txt"), None);
+
+        // Failures come back as errors instead of being taken for success
+        assert!(afc.remove_path_and_contents("/Missing").await.is_err());
+        assert!(afc.set_mod_time("/Missing", 0).await.is_err());
+        assert!(afc.get_file_hash("/Downloads").await.is_err());
+        afc.set_mod_time("/Downloads/moved/a.txt", 5).await.unwrap();
+        afc.remove_path_and_contents("/Downloads").await.unwrap();
+        assert_eq!(
+            responder.paths(),
+            [
+                "/",
+                "/DCIM",
+                "/DCIM/100APPLE",
+                "/DCIM/100APPLE/IMG_0001.JPG"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn afc_client_streams_chunks() {
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let responder = AfcResponder::new().with_file("/Downloads/big.bin", data.clone());
+        let mut afc = client(&responder);
+
+        let mut chunks = Vec::new();
+        let read = afc
+            .read_file_streamed("/Downloads/big.bin", |c| {
+                chunks.push(c.to_vec());
+                true
+            })
+            .await
+            .unwrap();
+        assert_eq!(read, 150_000);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), data);
+
+        let read = afc
+            .read_file_streamed("/Downloads/big.bin", |_| false)
+            .await
+            .unwrap();
+        assert_eq!(read, 65536);
+
+        let mut written = Vec::new();
+        let read = afc
+            .read_file_to_writer("/Downloads/big.bin", &mut written, |_| async {}, ())
+            .await
+            .unwrap();
+        assert_eq!(read, 150_000);
+        assert_eq!(written, data);
+
+        let stats = afc.transfer_stats();
+        assert_eq!(stats.bytes, 2 * 150_000 + 65536);
+        assert_eq!(stats.retries, 0);
+    }
+
+    #[tokio::test]
+    async fn afc_client_writes_from_reader() {
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let responder = AfcResponder::new().with_directory("/Downloads");
+        let mut afc = client(&responder);
+
+        // Starts from where the reader is, not the beginning
+        let mut reader = std::io::Cursor::new(data.clone());
+        reader.set_position(100);
+        let (observer, mut events) = ProgressObserver::new();
+        let written = afc
+            .write_file_from_reader("/Downloads/big.bin", &mut reader, forward, observer)
+            .await
+            .unwrap();
+        assert_eq!(written, 149_900);
+        assert_eq!(responder.file("/Downloads/big.bin").unwrap(), &data[100..]);
+
+        let mut last = None;
+        while let Ok(event) = events.try_recv() {
+            last = Some(event);
+        }
+        assert_eq!(
+            last,
+            Some(ProgressEvent::Progress {
+                done: 149_900,
+                total: 149_900
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn afc_client_verifies_writes() {
+        let responder = AfcResponder::new().with_directory("/PublicStaging");
+        let mut afc = client(&responder);
+
+        responder.set_corrupt_writes(1);
+        afc.write_file_verified("/PublicStaging/app.ipa", b"package")
+            .await
+            .unwrap();
+        assert_eq!(
+            responder.file("/PublicStaging/app.ipa").as_deref(),
+            Some(&b"package"[..])
+        );
+
+        responder.set_corrupt_writes(2);
+        let res = afc
+            .write_file_verified("/PublicStaging/app.ipa", b"package")
+            .await;
+        match res {
+            Err(IdeviceError::HashMismatch {
+                path,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(path, "/PublicStaging/app.ipa");
+                assert_eq!(expected, openssl::sha::sha1(b"package"));
+                assert_ne!(actual, expected);
+            }
+            r => panic!("expected a hash mismatch, got {r:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn afc_client_retries_when_busy() {
+        let responder = AfcResponder::new().with_file("/Downloads/a.txt", b"hello");
+        let mut afc = client(&responder);
+
+        responder.set_busy(2);
+        assert_eq!(afc.read_file("/Downloads/a.txt").await.unwrap(), b"hello");
+        afc.write_file("/Downloads/b.txt", b"world").await.unwrap();
+        let stats = afc.transfer_stats();
+        assert_eq!(stats.retries, 2);
+        assert_eq!(stats.bytes, 10);
+        assert_eq!(
+            responder.file("/Downloads/b.txt").as_deref(),
+            Some(&b"world"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn afc_close_releases_abandoned_files() {
+        let responder = AfcResponder::new().with_directory("/Downloads");
+        let mut afc = client(&responder);
+
+        // Give up on the write after it has sent the open, before the handle comes back
+        tokio::select! {
+            biased;
+            _ = afc.write_file("/Downloads/partial.txt", b"hello") => panic!("write finished"),
+            _ = async {} => {}
+        }
+        assert_eq!(responder.file("/Downloads/partial.txt"), None);
+
+        // Closing the abandoned handle is what creates the file
+        afc.close().await.unwrap();
+        assert_eq!(responder.file("/Downloads/partial.txt"), Some(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn afc_trees_can_start_at_the_root() {
+        let responder = AfcResponder::new()
+            .with_file("/a.txt", b"abc")
+            .with_file("/Media/b.txt", b"de");
+        let mut afc = client(&responder);
+        assert_eq!(afc.path_contents_size("/").await.unwrap(), 5);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("root");
+        afc.pull_tree("/", &dir, ProgressObserver::new().0)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"abc");
+        assert_eq!(std::fs::read(dir.join("Media/b.txt")).unwrap(), b"de");
+
+        let mut pool = AfcPool::from_clients(vec![afc]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        pool.pull_tree("/", &dir, ProgressObserver::new().0)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dir.join("Media/b.txt")).unwrap(), b"de");
+        pool.close().await.unwrap();
+
+        let target = AfcResponder::new();
+        let mut afc = client(&target);
+        afc.push_tree(&dir, "/", ProgressObserver::new().0)
+            .await
+            .unwrap();
+        assert_eq!(target.file("/Media/b.txt").unwrap(), b"de");
+
+        std::fs::write(dir.join("c.txt"), b"f").unwrap();
+        let summary = sync(&mut afc, &dir, "/", Default::default()).await.unwrap();
+        assert_eq!(summary.uploaded, ["c.txt"]);
+        assert_eq!(target.file("/c.txt").unwrap(), b"f");
+    }
+}
@@ -0,0 +1,107 @@
+// Jackson Coxson
+// AFC packet framing, kept apart from the client so it builds without a socket.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{codec::Codec, IdeviceError};
+
+/// Every AFC packet starts with this
+pub const AFC_MAGIC: &[u8; 8] = b"CFA6LPAA";
+
+/// Size of the packet header, magic included
+const AFC_HEADER_LEN: usize = 40;
+
+/// A single AFC packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AfcPacket {
+    /// One of the AFC operation codes
+    pub operation: u64,
+    pub packet_num: u64,
+    pub data: Vec<u8>,
+}
+
+impl AfcPacket {
+    /// Name of the packet's operation, for logging
+    pub fn operation_name(&self) -> &'static str {
+        match self.operation {
+            0x01 => "Status",
+            0x02 => "Data",
+            0x03 => "ReadDir",
+            0x04 => "ReadFile",
+            0x05 => "WriteFile",
+            0x06 => "WritePart",
+            0x07 => "TruncFile",
+            0x08 => "RemovePath",
+            0x09 => "MakeDir",
+            0x0a => "GetFileInfo",
+            0x0b => "GetDeviceInfo",
+            0x0c => "WriteFileAtomic",
+            0x0d => "FileRefOpen",
+            0x0e => "FileRefOpenResult",
+            0x0f => "FileRefRead",
+            0x10 => "FileRefWrite",
+            0x11 => "FileRefSeek",
+            0x12 => "FileRefTell",
+            0x13 => "FileRefTellResult",
+            0x14 => "FileRefClose",
+            0x15 => "FileRefSetSize",
+            0x16 => "GetConnectionInfo",
+            0x17 => "SetConnectionOptions",
+            0x18 => "RenamePath",
+            0x19 => "SetFSBlockSize",
+            0x1a => "SetSocketBlockSize",
+            0x1b => "FileRefLock",
+            0x1c => "MakeLink",
+            0x1d => "GetFileHash",
+            0x1e => "SetModTime",
+            0x1f => "GetFileHashWithRange",
+            0x20 => "FileRefSetImmutableHint",
+            0x21 => "GetSizeOfPathContents",
+            0x22 => "RemovePathAndContents",
+            0x23 => "DirectoryEnumeratorRefOpen",
+            0x24 => "DirectoryEnumeratorRefOpenResult",
+            0x25 => "DirectoryEnumeratorRefRead",
+            0x26 => "DirectoryEnumeratorRefClose",
+            _ => "Unknown",
+        }
+    }
+}
+
+impl Codec for AfcPacket {
+    const HEADER_LEN: usize = AFC_HEADER_LEN;
+
+    fn frame_length(mut header: &[u8]) -> Result<usize, IdeviceError> {
+        if &header[..8] != AFC_MAGIC {
+            return Err(IdeviceError::AfcError("Invalid packet magic".to_string()));
+        }
+        header.advance(8);
+        let entire_length = header.get_u64_le();
+        usize::try_from(entire_length).map_err(|_| IdeviceError::MessageTooLarge(usize::MAX))
+    }
+
+    fn decode_frame(mut frame: &[u8]) -> Result<Self, IdeviceError> {
+        frame.advance(8);
+        let _entire_length = frame.get_u64_le();
+        let _this_length = frame.get_u64_le();
+        let packet_num = frame.get_u64_le();
+        let operation = frame.get_u64_le();
+
+        Ok(Self {
+            operation,
+            packet_num,
+            data: frame.to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Result<Bytes, IdeviceError> {
+        let length = (AFC_HEADER_LEN + self.data.len()) as u64;
+        let mut packet = BytesMut::with_capacity(length as usize);
+        packet.put_slice(AFC_MAGIC);
+        packet.put_u64_le(length); // entire length
+        packet.put_u64_le(length); // this length
+        packet.put_u64_le(self.packet_num);
+        packet.put_u64_le(self.operation);
+        packet.put_slice(&self.data);
+        Ok(packet.freeze())
+    }
+}
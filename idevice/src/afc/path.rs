@@ -0,0 +1,210 @@
+//! Paths on the device
+//!
+//! Names are kept in NFC. macOS and some archives hand out names in NFD, so the same
+//! Korean or Japanese name can arrive in either form, and without normalizing a name read
+//! from the host wouldn't match the same name listed from the device.
+
+use std::fmt;
+use std::ops::Deref;
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest path the device accepts, in bytes
+pub const AFC_PATH_MAX: usize = 1024;
+/// Longest single name the device accepts, in bytes
+pub const AFC_NAME_MAX: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AfcPathError {
+    #[error("path is empty")]
+    Empty,
+    #[error("path contains a NUL byte")]
+    ContainsNul,
+    #[error("path isn't valid UTF-8")]
+    InvalidUtf8,
+    #[error("path is {0} bytes, over the limit of {AFC_PATH_MAX}")]
+    TooLong(usize),
+    #[error("name is {0} bytes, over the limit of {AFC_NAME_MAX}")]
+    NameTooLong(usize),
+}
+
+/// A validated path on the device, in NFC, without repeated or trailing slashes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AfcPath(String);
+
+impl AfcPath {
+    pub fn new(path: impl AsRef<str>) -> Result<Self, AfcPathError> {
+        let path = path.as_ref();
+        if path.is_empty() {
+            return Err(AfcPathError::Empty);
+        }
+        if path.contains('\0') {
+            return Err(AfcPathError::ContainsNul);
+        }
+
+        let absolute = path.starts_with('/');
+        let mut normalized = String::with_capacity(path.len());
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let name = normalize_name(name);
+            if name.len() > AFC_NAME_MAX {
+                return Err(AfcPathError::NameTooLong(name.len()));
+            }
+            if absolute || !normalized.is_empty() {
+                normalized.push('/');
+            }
+            normalized.push_str(&name);
+        }
+        if normalized.is_empty() {
+            normalized.push('/');
+        }
+
+        if normalized.len() > AFC_PATH_MAX {
+            return Err(AfcPathError::TooLong(normalized.len()));
+        }
+        Ok(Self(normalized))
+    }
+
+    /// Parses a path received from the device
+    pub fn from_bytes(path: &[u8]) -> Result<Self, AfcPathError> {
+        Self::new(std::str::from_utf8(path).map_err(|_| AfcPathError::InvalidUtf8)?)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The path followed by the NUL the protocol ends strings with
+    pub fn to_bytes_with_nul(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.0.len() + 1);
+        bytes.extend_from_slice(self.0.as_bytes());
+        bytes.push(0);
+        bytes
+    }
+
+    /// Appends a relative path
+    pub fn join(&self, path: impl AsRef<str>) -> Result<Self, AfcPathError> {
+        let path = path.as_ref();
+        if path.is_empty() {
+            return Ok(self.clone());
+        }
+        Self::new(format!("{}/{}", self.0, path))
+    }
+
+    /// The last name in the path, or ``None`` for the root
+    pub fn file_name(&self) -> Option<&str> {
+        match self.0.rsplit('/').next() {
+            Some("") | None => None,
+            Some(n) => Some(n),
+        }
+    }
+
+    /// The path without its last name, or ``None`` for the root and single relative names
+    pub fn parent(&self) -> Option<Self> {
+        match self.0.rfind('/') {
+            Some(0) if self.0.len() > 1 => Some(Self("/".to_string())),
+            Some(0) | None => None,
+            Some(i) => Some(Self(self.0[..i].to_string())),
+        }
+    }
+}
+
+/// Normalizes a single name, such as one listed from a directory, to NFC
+pub fn normalize_name(name: &str) -> String {
+    if unicode_normalization::is_nfc_quick(name.chars()) == unicode_normalization::IsNormalized::Yes
+    {
+        name.to_string()
+    } else {
+        name.nfc().collect()
+    }
+}
+
+impl Deref for AfcPath {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for AfcPath {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AfcPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<&str> for AfcPath {
+    type Error = AfcPathError;
+
+    fn try_from(path: &str) -> Result<Self, Self::Error> {
+        Self::new(path)
+    }
+}
+
+impl TryFrom<String> for AfcPath {
+    type Error = AfcPathError;
+
+    fn try_from(path: String) -> Result<Self, Self::Error> {
+        Self::new(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized() {
+        // 한 written as its jamo, then as one syllable
+        let decomposed = "/Documents/\u{1112}\u{1161}\u{11AB}.txt";
+        let composed = "/Documents/\u{D55C}.txt";
+        assert_eq!(AfcPath::new(decomposed).unwrap().as_str(), composed);
+        // が as か and a combining voiced mark
+        assert_eq!(
+            AfcPath::new("\u{304B}\u{3099}").unwrap(),
+            AfcPath::new("\u{304C}").unwrap()
+        );
+
+        assert_eq!(AfcPath::new("//a//b/").unwrap().as_str(), "/a/b");
+        assert_eq!(AfcPath::new("a/b").unwrap().as_str(), "a/b");
+        assert_eq!(AfcPath::new("/").unwrap().as_str(), "/");
+        assert_eq!(
+            AfcPath::new("/a").unwrap().join("b/c").unwrap().as_str(),
+            "/a/b/c"
+        );
+        assert_eq!(AfcPath::new("/a/b").unwrap().file_name(), Some("b"));
+        assert_eq!(
+            AfcPath::new("/a/b").unwrap().parent(),
+            Some(AfcPath::new("/a").unwrap())
+        );
+        assert_eq!(
+            AfcPath::new("/a").unwrap().parent(),
+            Some(AfcPath::new("/").unwrap())
+        );
+        assert_eq!(AfcPath::new("/").unwrap().parent(), None);
+        assert_eq!(
+            AfcPath::new("/a").unwrap().to_bytes_with_nul(),
+            b"/a\0".to_vec()
+        );
+    }
+
+    #[test]
+    fn invalid_paths() {
+        assert_eq!(AfcPath::new(""), Err(AfcPathError::Empty));
+        assert_eq!(AfcPath::new("/a\0b"), Err(AfcPathError::ContainsNul));
+        assert_eq!(
+            AfcPath::from_bytes(b"/\xff"),
+            Err(AfcPathError::InvalidUtf8)
+        );
+        assert_eq!(
+            AfcPath::new("a".repeat(256)),
+            Err(AfcPathError::NameTooLong(256))
+        );
+        let long = "/abc".repeat(300);
+        assert_eq!(AfcPath::new(&long), Err(AfcPathError::TooLong(1200)));
+    }
+}
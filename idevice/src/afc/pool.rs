@@ -0,0 +1,184 @@
+//! Several AFC connections working through one transfer
+//!
+//! afcd answers one request at a time on a connection, and the client waits for each
+//! answer before sending the next, so a single connection spends most of a pull waiting
+//! and tops out well below what USB can carry. A pool spreads the files over several
+//! connections, each taking the next file as soon as it's done with the last.
+
+use super::AfcClient;
+use crate::progress::{ProgressEvent, ProgressObserver};
+use crate::{provider::IdeviceProvider, IdeviceError, IdeviceService};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// A file to copy, as the remote path, local path and size
+type Job = (String, PathBuf, u64);
+
+enum WorkerMessage {
+    Copied(u64),
+    /// The worker ran out of files or failed, and hands its connection back
+    Finished(Box<AfcClient>, Result<(), IdeviceError>),
+}
+
+/// A fixed number of AFC connections to one device
+pub struct AfcPool {
+    clients: Vec<AfcClient>,
+}
+
+impl AfcPool {
+    /// Opens ``n`` connections to AFC, or one if ``n`` is 0
+    pub async fn new(provider: &dyn IdeviceProvider, n: usize) -> Result<Self, IdeviceError> {
+        let mut clients = Vec::with_capacity(n.max(1));
+        for _ in 0..n.max(1) {
+            clients.push(AfcClient::connect(provider).await?);
+        }
+        Ok(Self { clients })
+    }
+
+    /// A pool over connections that are already open, such as to another AFC service
+    pub fn from_clients(clients: Vec<AfcClient>) -> Self {
+        Self { clients }
+    }
+
+    /// How many connections the pool has
+    pub fn connections(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn into_inner(self) -> Vec<AfcClient> {
+        self.clients
+    }
+
+    /// Closes every connection, see ``AfcClient::close``
+    pub async fn close(self) -> Result<(), IdeviceError> {
+        for client in self.clients {
+            client.close().await?;
+        }
+        Ok(())
+    }
+
+    /// Download a directory tree into `local_dir` over every connection at once, reporting
+    /// bytes copied to the observer.
+    ///
+    /// The tree is listed over one connection, then each connection copies whole files
+    /// until none are left. After a failure the other connections finish the file they're
+    /// on and stop. The connections are handed to background tasks while copying, so
+    /// cancelling the pull leaves the pool empty.
+    pub async fn pull_tree(
+        &mut self,
+        remote_dir: &str,
+        local_dir: &Path,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        let res = self.pull_tree_inner(remote_dir, local_dir, &observer).await;
+        observer.complete(res)
+    }
+
+    async fn pull_tree_inner(
+        &mut self,
+        remote_dir: &str,
+        local_dir: &Path,
+        observer: &ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        let first = match self.clients.first_mut() {
+            Some(c) => c,
+            None => return Err(IdeviceError::NoEstablishedConnection),
+        };
+        observer.phase("Listing");
+        let files = first.list_tree(remote_dir, local_dir).await?;
+
+        observer.phase("Copying");
+        let total = files.iter().map(|(_, _, size)| size).sum();
+        let queue = Arc::new(Mutex::new(VecDeque::from(files)));
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        for client in self.clients.drain(..) {
+            crate::runtime::runtime().spawn(Box::pin(copy_files(
+                client,
+                queue.clone(),
+                sender.clone(),
+            )));
+        }
+        drop(sender);
+
+        let mut done = 0;
+        let mut res = Ok(());
+        while let Some(message) = receiver.recv().await {
+            match message {
+                WorkerMessage::Copied(size) => {
+                    done += size;
+                    observer.emit(ProgressEvent::Progress { done, total });
+                }
+                WorkerMessage::Finished(client, worker_res) => {
+                    self.clients.push(*client);
+                    if let Err(e) = worker_res {
+                        queue.lock().unwrap().clear();
+                        if res.is_ok() {
+                            res = Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Copies files off the queue until it's empty or one fails
+async fn copy_files(
+    mut client: AfcClient,
+    queue: Arc<Mutex<VecDeque<Job>>>,
+    sender: mpsc::UnboundedSender<WorkerMessage>,
+) {
+    let res = async {
+        loop {
+            let job = queue.lock().unwrap().pop_front();
+            let (remote, local, size) = match job {
+                Some(j) => j,
+                None => return Ok(()),
+            };
+            let data = client.read_file(&remote).await?;
+            tokio::fs::write(&local, data).await?;
+            let _ = sender.send(WorkerMessage::Copied(size));
+        }
+    }
+    .await;
+    let _ = sender.send(WorkerMessage::Finished(Box::new(client), res));
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn afc_pool_pulls_over_every_connection() {
+        let mut responder = AfcResponder::new();
+        for i in 0..12 {
+            responder =
+                responder.with_file(&format!("/DCIM/10{}APPLE/IMG_{i}.JPG", i % 3), vec![i; 10]);
+        }
+        let clients = (0..3).map(|_| client(&responder)).collect();
+        let mut pool = AfcPool::from_clients(clients);
+
+        let dir = tempfile::tempdir().unwrap();
+        let (observer, mut events) = ProgressObserver::new();
+        pool.pull_tree("/DCIM", dir.path(), observer).await.unwrap();
+        for i in 0..12u8 {
+            let path = dir.path().join(format!("10{}APPLE/IMG_{i}.JPG", i % 3));
+            assert_eq!(std::fs::read(path).unwrap(), vec![i; 10]);
+        }
+        let mut last = None;
+        while let Ok(event) = events.try_recv() {
+            if let ProgressEvent::Progress { done, total } = event {
+                last = Some((done, total));
+            }
+        }
+        assert_eq!(last, Some((120, 120)));
+        assert_eq!(pool.connections(), 3);
+        pool.close().await.unwrap();
+    }
+}
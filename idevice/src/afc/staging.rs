@@ -0,0 +1,218 @@
+//! Staging directories for uploads
+//!
+//! Installers upload into ``/PublicStaging`` and are meant to remove what they put there,
+//! but a failed or cancelled install leaves its files behind, and they add up over time.
+//! [`Staging`] gives each upload a directory of its own, remembers the ones it made and
+//! removes them when it's done or dropped. ``cleanup_stale`` clears what earlier sessions
+//! left behind.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+
+use super::{AfcClient, AfcFileInfo, AfcPath};
+use crate::IdeviceError;
+
+/// Where installd picks packages up from
+pub const PUBLIC_STAGING: &str = "/PublicStaging";
+
+/// Starts the name of every directory made here, so cleaning up leaves other tools' files be
+pub const STAGING_PREFIX: &str = "idevice-";
+
+/// Staging directories for one session
+///
+/// Dropping it inside a tokio runtime removes the directories in the background. Dropped
+/// anywhere else they're left for ``cleanup_stale``. ``clean_up`` removes them and waits,
+/// handing the client back.
+pub struct Staging {
+    /// Only taken by ``clean_up`` and ``drop``
+    afc: Option<AfcClient>,
+    root: AfcPath,
+    created: Vec<AfcPath>,
+}
+
+impl Staging {
+    /// Stages under ``/PublicStaging``
+    pub fn new(afc: AfcClient) -> Self {
+        Self {
+            afc: Some(afc),
+            root: AfcPath::new(PUBLIC_STAGING).unwrap(),
+            created: Vec::new(),
+        }
+    }
+
+    /// Stages under another directory, for services other than installd
+    pub fn with_root(afc: AfcClient, root: &str) -> Result<Self, IdeviceError> {
+        Ok(Self {
+            afc: Some(afc),
+            root: AfcPath::new(root)?,
+            created: Vec::new(),
+        })
+    }
+
+    /// The client, for uploading into the directories
+    pub fn afc(&mut self) -> &mut AfcClient {
+        self.afc.as_mut().unwrap()
+    }
+
+    /// The directories made so far that haven't been removed
+    pub fn directories(&self) -> &[AfcPath] {
+        &self.created
+    }
+
+    /// Makes a new, empty directory such as ``/PublicStaging/idevice-<uuid>``
+    pub async fn create(&mut self) -> Result<AfcPath, IdeviceError> {
+        let dir = self
+            .root
+            .join(format!("{STAGING_PREFIX}{}", uuid::Uuid::new_v4()))?;
+        self.afc().make_directory(&dir).await?;
+        debug!("Staging into {dir}");
+        self.created.push(dir.clone());
+        Ok(dir)
+    }
+
+    /// Removes one of the directories and everything in it
+    pub async fn remove(&mut self, dir: &AfcPath) -> Result<(), IdeviceError> {
+        self.afc().remove_path_and_contents(dir).await?;
+        self.created.retain(|d| d != dir);
+        Ok(())
+    }
+
+    /// Removes staging directories older than ``age`` that this session didn't make,
+    /// returning how many were removed. Only directories named with ``STAGING_PREFIX`` are
+    /// touched, since another tool's upload could still be going.
+    pub async fn cleanup_stale(&mut self, age: Duration) -> Result<usize, IdeviceError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let age = u64::try_from(age.as_nanos()).unwrap_or(u64::MAX);
+        let root = self.root.clone();
+        let mut removed = 0;
+        for name in self.afc().read_directory(&root).await? {
+            if !name.starts_with(STAGING_PREFIX) {
+                continue;
+            }
+            let dir = root.join(&name)?;
+            if self.created.contains(&dir) {
+                continue;
+            }
+            let info = AfcFileInfo::from(&self.afc().get_file_info(&dir).await?);
+            if now.saturating_sub(info.modified) < age {
+                continue;
+            }
+            self.afc().remove_path_and_contents(&dir).await?;
+            debug!("Removed stale staging directory {dir}");
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Removes every directory this session made, and hands the client back
+    pub async fn clean_up(mut self) -> Result<AfcClient, IdeviceError> {
+        let mut afc = self.afc.take().unwrap();
+        for dir in std::mem::take(&mut self.created) {
+            afc.remove_path_and_contents(&dir).await?;
+        }
+        Ok(afc)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if self.created.is_empty() {
+            return;
+        }
+        let Some(mut afc) = self.afc.take() else {
+            return;
+        };
+        let created = std::mem::take(&mut self.created);
+        // Drop can't wait for the removal. Without a runtime to run it on, the directories
+        // are left for cleanup_stale.
+        crate::runtime::runtime().spawn(Box::pin(async move {
+            for dir in created {
+                if let Err(e) = afc.remove_path_and_contents(&dir).await {
+                    warn!("Failed to remove staging directory {dir}: {e:?}");
+                }
+            }
+        }));
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn staging_directories_are_cleaned_up() {
+        let responder = AfcResponder::new()
+            .with_file("/PublicStaging/idevice-old/app.ipa", b"left behind")
+            .with_file("/PublicStaging/other.ipa", b"another tool's");
+        let mut staging = Staging::new(client(&responder));
+
+        let dir = staging.create().await.unwrap();
+        assert!(dir.file_name().unwrap().starts_with(STAGING_PREFIX));
+        staging
+            .afc()
+            .write_file(&dir.join("app.ipa").unwrap(), b"package")
+            .await
+            .unwrap();
+        let removed = staging.create().await.unwrap();
+        staging.remove(&removed).await.unwrap();
+        assert_eq!(staging.directories(), std::slice::from_ref(&dir));
+
+        // Everything in the mock was last modified at the epoch
+        assert_eq!(
+            staging
+                .cleanup_stale(Duration::from_secs(60))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            responder.paths(),
+            [
+                "/".to_string(),
+                "/PublicStaging".to_string(),
+                dir.to_string(),
+                format!("{dir}/app.ipa"),
+                "/PublicStaging/other.ipa".to_string(),
+            ]
+        );
+
+        staging.clean_up().await.unwrap();
+        assert_eq!(
+            responder.paths(),
+            ["/", "/PublicStaging", "/PublicStaging/other.ipa"]
+        );
+
+        // Dropping cleans up in the background
+        let mut staging = Staging::new(client(&responder));
+        staging.create().await.unwrap();
+        drop(staging);
+        crate::runtime::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            responder.paths(),
+            ["/", "/PublicStaging", "/PublicStaging/other.ipa"]
+        );
+    }
+
+    #[test]
+    fn staging_dropped_outside_a_runtime_is_left() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let responder = AfcResponder::new();
+        let (staging, dir) = runtime.block_on(async {
+            let mut staging = Staging::new(client(&responder));
+            let dir = staging.create().await.unwrap();
+            (staging, dir)
+        });
+        // Would panic if it tried to spawn the removal
+        drop(staging);
+        assert!(responder.paths().contains(&dir.to_string()));
+    }
+}
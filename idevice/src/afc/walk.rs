@@ -0,0 +1,168 @@
+//! Walking a directory tree on the device
+//!
+//! AFC only lists one directory at a time, so anything that works on a whole tree has to
+//! list, look up each entry's info and go into the subdirectories itself. [`AfcWalk`] does
+//! that and hands out every entry under a path with its info already parsed. Symlinks are
+//! returned but not followed, so a link back up the tree can't loop.
+
+use std::collections::VecDeque;
+
+use super::{AfcClient, AfcFileInfo, AfcFileKind, AfcPath};
+use crate::IdeviceError;
+
+/// Something found by [`AfcClient::walk`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AfcWalkEntry {
+    /// The full path on the device
+    pub path: AfcPath,
+    pub info: AfcFileInfo,
+    /// 1 for what's directly in the walked directory, 2 for what's in its subdirectories
+    /// and so on
+    pub depth: usize,
+}
+
+/// Goes through a tree one entry at a time, from [`AfcClient::walk`]
+///
+/// A directory's entries come in name order, each directory before what's in it.
+/// The client can't be used for anything else until the walk is dropped.
+pub struct AfcWalk<'a> {
+    client: &'a mut AfcClient,
+    /// Directories still to be listed, the next one last
+    pending: Vec<(AfcPath, usize)>,
+    /// Entries of the last directory listed that haven't been returned yet
+    listed: VecDeque<Result<AfcWalkEntry, IdeviceError>>,
+}
+
+impl AfcClient {
+    /// Walks everything under ``path``, which isn't returned itself
+    pub fn walk(&mut self, path: &str) -> Result<AfcWalk<'_>, IdeviceError> {
+        Ok(AfcWalk {
+            client: self,
+            pending: vec![(AfcPath::new(path)?, 0)],
+            listed: VecDeque::new(),
+        })
+    }
+}
+
+impl AfcWalk<'_> {
+    /// The next entry, or ``None`` once the whole tree has been returned.
+    /// A directory that can't be listed, or an entry whose info can't be read, is returned
+    /// as an error in its place, and the walk carries on with the rest of the tree.
+    pub async fn next(&mut self) -> Option<Result<AfcWalkEntry, IdeviceError>> {
+        loop {
+            if let Some(entry) = self.listed.pop_front() {
+                return Some(entry);
+            }
+            let (dir, depth) = self.pending.pop()?;
+            if let Err(e) = self.list(&dir, depth + 1).await {
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Collects the rest of the walk, stopping at the first error
+    pub async fn collect(mut self) -> Result<Vec<AfcWalkEntry>, IdeviceError> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next().await {
+            entries.push(entry?);
+        }
+        Ok(entries)
+    }
+
+    async fn list(&mut self, dir: &AfcPath, depth: usize) -> Result<(), IdeviceError> {
+        // Subdirectories are known to be directories from their info, but the root isn't
+        if depth == 1 {
+            let info = AfcFileInfo::from(&self.client.get_file_info(dir).await?);
+            if info.kind != AfcFileKind::Directory {
+                return Err(IdeviceError::AfcError(format!("{dir} isn't a directory")));
+            }
+        }
+        let mut names = self.client.read_directory(dir).await?;
+        names.retain(|name| name != "." && name != "..");
+        names.sort();
+
+        let mut listed = VecDeque::with_capacity(names.len());
+        for name in names {
+            let entry: Result<_, IdeviceError> = async {
+                let path = dir.join(&name)?;
+                let info = self.client.get_file_info(&path).await?;
+                // afcd answers with a status instead when the info can't be read
+                if info.is_empty() {
+                    return Err(IdeviceError::AfcError(format!(
+                        "couldn't read the info of {path}"
+                    )));
+                }
+                let info = AfcFileInfo::from(&info);
+                Ok(AfcWalkEntry { path, info, depth })
+            }
+            .await;
+            listed.push_back(entry);
+        }
+
+        // Pushed backwards so the first subdirectory is listed first
+        for entry in listed.iter().rev().flatten() {
+            if entry.info.kind == AfcFileKind::Directory {
+                self.pending.push((entry.path.clone(), depth));
+            }
+        }
+        self.listed = listed;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn afc_client_walks_trees() {
+        let responder = AfcResponder::new()
+            .with_file("/DCIM/100APPLE/IMG_0001.JPG", b"jpeg")
+            .with_file("/DCIM/100APPLE/IMG_0002.JPG", b"jpg")
+            .with_directory("/DCIM/101APPLE")
+            .with_file("/DCIM/.MISC/Info.plist", b"plist");
+        let mut afc = client(&responder);
+
+        let entries = afc.walk("/DCIM").unwrap().collect().await.unwrap();
+        let walked: Vec<_> = entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.info.kind.clone(), e.info.size, e.depth))
+            .collect();
+        assert_eq!(
+            walked,
+            vec![
+                ("/DCIM/.MISC", AfcFileKind::Directory, 0, 1),
+                ("/DCIM/100APPLE", AfcFileKind::Directory, 0, 1),
+                ("/DCIM/101APPLE", AfcFileKind::Directory, 0, 1),
+                ("/DCIM/.MISC/Info.plist", AfcFileKind::File, 5, 2),
+                ("/DCIM/100APPLE/IMG_0001.JPG", AfcFileKind::File, 4, 2),
+                ("/DCIM/100APPLE/IMG_0002.JPG", AfcFileKind::File, 3, 2),
+            ]
+        );
+
+        let mut walk = afc.walk("/Missing").unwrap();
+        assert!(walk.next().await.unwrap().is_err());
+        assert!(walk.next().await.is_none());
+
+        // An entry whose info can't be read doesn't hide the rest of its directory
+        let responder = AfcResponder::new()
+            .with_file("/Logs/a.log", b"a")
+            .with_file("/Logs/b.log", b"b")
+            .with_file("/Logs/c.log", b"c")
+            .with_protected("/Logs/b.log");
+        let mut afc = client(&responder);
+        let mut walk = afc.walk("/Logs").unwrap();
+        assert_eq!(
+            walk.next().await.unwrap().unwrap().path.as_str(),
+            "/Logs/a.log"
+        );
+        assert!(walk.next().await.unwrap().is_err());
+        assert_eq!(
+            walk.next().await.unwrap().unwrap().path.as_str(),
+            "/Logs/c.log"
+        );
+        assert!(walk.next().await.is_none());
+    }
+}
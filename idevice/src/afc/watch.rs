@@ -0,0 +1,134 @@
+//! Polling a directory on the device for changes
+//!
+//! AFC has no change notifications, so the tree is listed every interval and compared
+//! with the previous listing. Files are seen as modified when their size or
+//! modification time changes.
+
+use super::mirror::{list_device, same_kind, Entry};
+use super::{AfcClient, AfcPath};
+use crate::IdeviceError;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    Created(AfcPath),
+    Modified(AfcPath),
+    Removed(AfcPath),
+}
+
+/// Watches a directory and everything under it, created by ``watch``
+pub struct DirectoryWatcher {
+    afc: AfcClient,
+    root: AfcPath,
+    interval: Duration,
+    snapshot: BTreeMap<String, Entry>,
+    pending: VecDeque<WatchEvent>,
+}
+
+/// Starts watching ``path``, listing it every ``interval``.
+/// What's already there when this is called doesn't produce events.
+pub async fn watch(
+    mut afc: AfcClient,
+    path: &str,
+    interval: Duration,
+) -> Result<DirectoryWatcher, IdeviceError> {
+    let root = AfcPath::new(path)?;
+    let snapshot = list_device(&mut afc, &root).await?;
+    Ok(DirectoryWatcher {
+        afc,
+        root,
+        interval,
+        snapshot,
+        pending: VecDeque::new(),
+    })
+}
+
+impl DirectoryWatcher {
+    /// Waits for the next change.
+    /// Changes found in the same listing are returned in path order, removals first.
+    pub async fn next(&mut self) -> Result<WatchEvent, IdeviceError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            crate::runtime::sleep(self.interval).await;
+            let events = self.poll().await?;
+            self.pending.extend(events);
+        }
+    }
+
+    /// Lists the directory now instead of waiting for the interval, returning the changes
+    /// since the last listing. These changes aren't returned by ``next`` as well.
+    pub async fn poll(&mut self) -> Result<Vec<WatchEvent>, IdeviceError> {
+        let snapshot = list_device(&mut self.afc, &self.root).await?;
+        let mut events = Vec::new();
+
+        for (path, entry) in &self.snapshot {
+            match snapshot.get(path) {
+                Some(new) if same_kind(entry, new) => {}
+                _ => events.push(WatchEvent::Removed(self.root.join(path)?)),
+            }
+        }
+        for (path, entry) in &snapshot {
+            match self.snapshot.get(path) {
+                Some(old) if same_kind(old, entry) => {
+                    if old != entry {
+                        events.push(WatchEvent::Modified(self.root.join(path)?));
+                    }
+                }
+                _ => events.push(WatchEvent::Created(self.root.join(path)?)),
+            }
+        }
+
+        self.snapshot = snapshot;
+        Ok(events)
+    }
+
+    pub fn path(&self) -> &AfcPath {
+        &self.root
+    }
+
+    /// Stops watching, returning the client
+    pub fn into_inner(self) -> AfcClient {
+        self.afc
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+
+    #[tokio::test]
+    async fn watch_reports_changes() {
+        let responder = AfcResponder::new().with_file("/Documents/existing.txt", b"old");
+        let mut watcher = watch(client(&responder), "/Documents/", Duration::from_millis(10))
+            .await
+            .unwrap();
+        let mut afc = client(&responder);
+        let path = |p: &str| AfcPath::new(p).unwrap();
+
+        afc.write_file("/Documents/new.txt", b"new").await.unwrap();
+        assert_eq!(
+            watcher.next().await.unwrap(),
+            WatchEvent::Created(path("/Documents/new.txt"))
+        );
+
+        afc.write_file("/Documents/existing.txt", b"changed")
+            .await
+            .unwrap();
+        afc.make_directory("/Documents/Inbox").await.unwrap();
+        afc.remove_path("/Documents/new.txt").await.unwrap();
+        assert_eq!(
+            watcher.poll().await.unwrap(),
+            [
+                WatchEvent::Removed(path("/Documents/new.txt")),
+                WatchEvent::Created(path("/Documents/Inbox")),
+                WatchEvent::Modified(path("/Documents/existing.txt")),
+            ]
+        );
+        assert!(watcher.poll().await.unwrap().is_empty());
+    }
+}
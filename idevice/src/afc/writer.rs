@@ -0,0 +1,160 @@
+//! Writing a file on the device through ``AsyncWrite``
+//!
+//! ``write_file`` needs the whole file in memory and ``write_file_from_reader`` needs
+//! something to seek in, so neither fits a file that's produced as it goes, like one being
+//! decompressed or downloaded. The writer collects writes into chunks and sends each as a
+//! ``FileRefWrite`` when it fills, so only one chunk is held at a time.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::AsyncWrite;
+
+use super::file::CHUNK_SIZE;
+use super::{AfcClient, AfcFile, AfcFopenMode};
+use crate::{runtime::BoxFuture, IdeviceError};
+
+/// A file open for writing on the device, from [`AfcClient::open_write`]
+///
+/// Nothing is guaranteed to be on the device until ``shutdown`` finishes, which sends
+/// what's left and closes the file. Dropping the writer first leaves the file open until
+/// ``AfcClient::close``, like any other cancelled operation.
+pub struct AfcFileWriter<'a> {
+    state: WriterState<'a>,
+    buffer: Vec<u8>,
+    written: u64,
+}
+
+enum WriterState<'a> {
+    Idle(AfcFile<'a>),
+    /// The file is lent to the write until it's answered
+    Writing(BoxFuture<'a, (AfcFile<'a>, Result<(), IdeviceError>)>),
+    Closing(BoxFuture<'a, Result<(), IdeviceError>>),
+    Closed,
+}
+
+impl AfcClient {
+    /// Opens a file for writing, creating it or cutting it to nothing if it exists
+    pub async fn open_write(&mut self, path: &str) -> Result<AfcFileWriter<'_>, IdeviceError> {
+        let file = AfcFile::open(self, path, AfcFopenMode::WrOnly).await?;
+        Ok(AfcFileWriter {
+            state: WriterState::Idle(file),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+        })
+    }
+}
+
+impl AfcFileWriter<'_> {
+    /// How many bytes have been sent to the device so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Waits for the write in flight, if there is one
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let WriterState::Writing(request) = &mut self.state {
+            let (file, res) = ready!(request.as_mut().poll(cx));
+            self.state = WriterState::Idle(file);
+            res.map_err(io::Error::other)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Sends what's been collected. Only called when idle.
+    fn start_write(&mut self) {
+        let mut file = match std::mem::replace(&mut self.state, WriterState::Closed) {
+            WriterState::Idle(f) => f,
+            _ => unreachable!("a write was started while the file was busy"),
+        };
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        self.written += chunk.len() as u64;
+        self.state = WriterState::Writing(Box::pin(async move {
+            let res = file.write(&chunk).await;
+            (file, res)
+        }));
+    }
+}
+
+impl AsyncWrite for AfcFileWriter<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if matches!(this.state, WriterState::Closing(_) | WriterState::Closed) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        ready!(this.poll_idle(cx))?;
+        if this.buffer.len() >= CHUNK_SIZE {
+            this.start_write();
+            ready!(this.poll_idle(cx))?;
+        }
+        let len = buf.len().min(CHUNK_SIZE - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if matches!(this.state, WriterState::Closing(_) | WriterState::Closed) {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(this.poll_idle(cx))?;
+        if !this.buffer.is_empty() {
+            this.start_write();
+        }
+        this.poll_idle(cx)
+    }
+
+    /// Sends what's left and closes the file
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let WriterState::Idle(_) | WriterState::Writing(_) = this.state {
+            ready!(Pin::new(&mut *this).poll_flush(cx))?;
+            if let WriterState::Idle(file) = std::mem::replace(&mut this.state, WriterState::Closed)
+            {
+                this.state = WriterState::Closing(Box::pin(file.close()));
+            }
+        }
+        if let WriterState::Closing(close) = &mut this.state {
+            let res = ready!(close.as_mut().poll(cx));
+            this.state = WriterState::Closed;
+            res.map_err(io::Error::other)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::afc::tests::client;
+    use crate::testing::AfcResponder;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn afc_client_streams_writes() {
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let responder = AfcResponder::new().with_directory("/Downloads");
+        let mut afc = client(&responder);
+
+        let mut writer = afc.open_write("/Downloads/big.bin").await.unwrap();
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        // Only whole chunks have been sent so far
+        assert_eq!(writer.written(), 131_072);
+        writer.shutdown().await.unwrap();
+        assert_eq!(writer.written(), 150_000);
+        assert!(writer.write_all(b"more").await.is_err());
+        drop(writer);
+
+        assert_eq!(responder.file("/Downloads/big.bin").unwrap(), data);
+        assert_eq!(afc.transfer_stats().bytes, 150_000);
+
+        // A failed open is an error, not a writer with the status as its handle
+        assert!(afc.open_write("/Missing/big.bin").await.is_err());
+    }
+}
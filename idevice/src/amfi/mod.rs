@@ -1,29 +1,96 @@
 //! AMFI (Apple Mobile File Integrity) service implementation
+//!
+//! Turning on Developer Mode takes a restart. ``arm_developer_mode`` sets it to turn on,
+//! then after the device restarts ``accept_developer_mode`` answers the prompt it shows.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use log::warn;
 
-const AMFI_SERVICE_NAME: &str = "com.apple.amfi";
+use crate::{lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+
+/// The lockdown domain ``DeveloperModeStatus`` is read from
+pub const AMFI_DOMAIN: &str = "com.apple.security.mac.amfi";
 
 /// AMFI client for interacting with Apple Mobile File Integrity service
 pub struct AmfiClient {
-    socket: tokio::net::TcpStream,
+    pub idevice: Idevice,
+}
+
+impl IdeviceService for AmfiClient {
+    fn service_name() -> &'static str {
+        "com.apple.amfi.lockdown"
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice))
+    }
 }
 
 impl AmfiClient {
-    /// Connect to the AMFI service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(AMFI_SERVICE_NAME).await?;
-        Ok(Self {
-            socket: service.socket,
-        })
-    }
-
-    /// Get developer mode status
-    pub async fn get_developer_mode_status(&mut self) -> Result<bool, IdeviceError> {
-        let mut command = [0u8; 4];
-        self.socket.write_all(b"Q").await?;
-        self.socket.read_exact(&mut command).await?;
-        Ok(command[0] != 0)
-    }
-}
\ No newline at end of file
+    pub fn new(idevice: Idevice) -> Self {
+        Self { idevice }
+    }
+
+    async fn action(&mut self, action: u64) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("action".into(), action.into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        let res = self.idevice.read_plist().await?;
+        if let Some(error) = res.get("Error").and_then(|e| e.as_string()) {
+            warn!("AMFI action {action} failed: {error}");
+            return Err(IdeviceError::AmfiError(error.to_string()));
+        }
+        match res.get("success").and_then(|s| s.as_boolean()) {
+            Some(true) => Ok(()),
+            _ => {
+                warn!("AMFI action {action} didn't succeed: {res:?}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Shows the Developer Mode switch in Settings > Privacy & Security
+    pub async fn reveal_developer_mode(&mut self) -> Result<(), IdeviceError> {
+        self.action(0).await
+    }
+
+    /// Sets Developer Mode to turn on at the next restart.
+    /// Devices with a passcode refuse, the switch in Settings has to be used instead.
+    pub async fn arm_developer_mode(&mut self) -> Result<(), IdeviceError> {
+        self.action(1).await
+    }
+
+    /// Confirms the prompt shown after restarting with Developer Mode armed
+    pub async fn accept_developer_mode(&mut self) -> Result<(), IdeviceError> {
+        self.action(2).await
+    }
+}
+
+/// Whether Developer Mode is on. Devices before iOS 16 don't have it and report ``false``.
+pub async fn developer_mode_status(lockdown: &mut LockdowndClient) -> Result<bool, IdeviceError> {
+    let values = lockdown.get_domain_values(AMFI_DOMAIN).await?;
+    Ok(values
+        .get("DeveloperModeStatus")
+        .and_then(|s| s.as_boolean())
+        .unwrap_or(false))
+}
@@ -0,0 +1,341 @@
+// Jackson Coxson
+// AirTraffic (ATC) sync for ringtones and books.
+// Only what's needed to list and add those is implemented. The device drives the sync:
+// after the metadata is sent it asks for the assets it wants, which are then written over AFC.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+
+use crate::{afc::AfcClient, lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+
+pub struct AtcClient {
+    pub idevice: Idevice,
+}
+
+impl IdeviceService for AtcClient {
+    fn service_name() -> &'static str {
+        "com.apple.atc"
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Ringtone,
+    Book,
+}
+
+impl MediaKind {
+    fn dataclass(&self) -> &'static str {
+        match self {
+            MediaKind::Ringtone => "Ringtone",
+            MediaKind::Book => "Book",
+        }
+    }
+
+    /// Where the files are kept on the media partition
+    pub fn directory(&self) -> &'static str {
+        match self {
+            MediaKind::Ringtone => "/iTunes_Control/Ringtones",
+            MediaKind::Book => "/Books",
+        }
+    }
+
+    /// The plist the device indexes the files in
+    fn index_path(&self) -> &'static str {
+        match self {
+            MediaKind::Ringtone => "/iTunes_Control/iTunes/Ringtones.plist",
+            MediaKind::Book => "/Books/Books.plist",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaItem {
+    pub kind: MediaKind,
+    pub title: String,
+    /// AFC path of the file
+    pub path: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AtcMessage {
+    pub command: String,
+    pub params: plist::Dictionary,
+}
+
+impl AtcClient {
+    pub fn new(idevice: Idevice) -> Self {
+        Self { idevice }
+    }
+
+    /// Messages are binary plists prefixed with their little endian length
+    pub async fn send_message(
+        &mut self,
+        command: &str,
+        params: plist::Dictionary,
+    ) -> Result<(), IdeviceError> {
+        let mut message = plist::Dictionary::new();
+        message.insert("Command".into(), command.into());
+        message.insert("Params".into(), plist::Value::Dictionary(params));
+        message.insert("Session".into(), 0.into());
+
+        let mut buf = Vec::new();
+        plist::to_writer_binary(&mut buf, &plist::Value::Dictionary(message))?;
+
+        let mut packet = (buf.len() as u32).to_le_bytes().to_vec();
+        packet.extend_from_slice(&buf);
+        self.idevice.send_raw(&packet).await
+    }
+
+    pub async fn read_message(&mut self) -> Result<AtcMessage, IdeviceError> {
+        let len = self.idevice.read_raw(4).await?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+        let buf = self.idevice.read_raw(len as usize).await?;
+
+        let mut message: plist::Dictionary = plist::from_bytes(&buf)?;
+        let command = match message.remove("Command") {
+            Some(plist::Value::String(c)) => c,
+            _ => {
+                warn!("ATC message has no command");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        let params = match message.remove("Params") {
+            Some(plist::Value::Dictionary(p)) => p,
+            _ => plist::Dictionary::new(),
+        };
+
+        Ok(AtcMessage { command, params })
+    }
+
+    /// Reads messages until the device sends ``command``, and returns its params
+    async fn wait_for(&mut self, command: &str) -> Result<plist::Dictionary, IdeviceError> {
+        loop {
+            let message = self.read_message().await?;
+            if message.command == command {
+                return Ok(message.params);
+            }
+            if message.command == "SyncFailed" {
+                let reason = message
+                    .params
+                    .get("ErrorDescription")
+                    .and_then(|e| e.as_string())
+                    .unwrap_or("no reason given")
+                    .to_string();
+                return Err(IdeviceError::SyncFailed(reason));
+            }
+            debug!(
+                "Skipping ATC message {} while waiting for {command}",
+                message.command
+            );
+        }
+    }
+
+    /// Adds a ringtone or book to the device
+    /// # Arguments
+    /// `afc` - A connection to the media partition
+    /// `title` - The name shown on the device
+    /// `file_name` - The name to store the file under, such as ``Bell.m4r`` or ``Novel.epub``
+    /// `data` - The file contents
+    pub async fn upload(
+        &mut self,
+        afc: &mut AfcClient,
+        kind: MediaKind,
+        title: impl Into<String>,
+        file_name: &str,
+        data: &[u8],
+    ) -> Result<MediaItem, IdeviceError> {
+        let title = title.into();
+        let dataclass = kind.dataclass();
+        let asset_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64 & i64::MAX as u64)
+            .unwrap_or(1);
+
+        let mut anchors = plist::Dictionary::new();
+        anchors.insert(dataclass.into(), 0.into());
+        let mut host_info = plist::Dictionary::new();
+        host_info.insert("HostName".into(), "idevice".into());
+
+        let mut params = plist::Dictionary::new();
+        params.insert(
+            "Dataclasses".into(),
+            plist::Value::Array(vec![dataclass.into()]),
+        );
+        params.insert("DataclassAnchors".into(), plist::Value::Dictionary(anchors));
+        params.insert("HostInfo".into(), plist::Value::Dictionary(host_info));
+        self.send_message("RequestingSync", params).await?;
+
+        let ready = self.wait_for("ReadyForSync").await?;
+        // Anchors come back as either numbers or strings
+        let anchor = match ready
+            .get("DataclassAnchors")
+            .and_then(|a| a.as_dictionary())
+            .and_then(|a| a.get(dataclass))
+        {
+            Some(plist::Value::Integer(i)) => i.as_unsigned().unwrap_or(0),
+            Some(plist::Value::String(s)) => s.parse().unwrap_or(0),
+            _ => 0,
+        } + 1;
+
+        // The sync plist tells the device which asset IDs to ask for
+        let mut item = plist::Dictionary::new();
+        item.insert("title".into(), title.clone().into());
+        match kind {
+            MediaKind::Ringtone => item.insert("is_ringtone".into(), true.into()),
+            MediaKind::Book => item.insert("is_book".into(), true.into()),
+        };
+        let mut operation = plist::Dictionary::new();
+        operation.insert("operation".into(), "insert_track".into());
+        operation.insert("pid".into(), asset_id.into());
+        operation.insert("item".into(), plist::Value::Dictionary(item));
+
+        let mut sync_plist = plist::Dictionary::new();
+        sync_plist.insert("revision".into(), anchor.into());
+        sync_plist.insert(
+            "operations".into(),
+            plist::Value::Array(vec![plist::Value::Dictionary(operation)]),
+        );
+        let mut buf = Vec::new();
+        plist::to_writer_binary(&mut buf, &plist::Value::Dictionary(sync_plist))?;
+        afc.make_directory(&format!("/iTunes_Control/Sync/{dataclass}"))
+            .await?;
+        afc.write_file(
+            &format!("/iTunes_Control/Sync/{dataclass}/Sync_{anchor:08}.plist"),
+            &buf,
+        )
+        .await?;
+
+        let mut sync_types = plist::Dictionary::new();
+        sync_types.insert(dataclass.into(), 1.into());
+        let mut anchors = plist::Dictionary::new();
+        anchors.insert(dataclass.into(), anchor.to_string().into());
+        let mut params = plist::Dictionary::new();
+        params.insert("SyncTypes".into(), plist::Value::Dictionary(sync_types));
+        params.insert("DataclassAnchors".into(), plist::Value::Dictionary(anchors));
+        self.send_message("FinishedSyncingMetadata", params).await?;
+
+        let manifest = self.wait_for("AssetManifest").await?;
+        let requested = manifest
+            .get("AssetManifest")
+            .and_then(|m| m.as_dictionary())
+            .and_then(|m| m.get(dataclass))
+            .and_then(|a| a.as_array())
+            .map(|a| {
+                a.iter().any(|asset| {
+                    asset
+                        .as_dictionary()
+                        .and_then(|asset| asset.get("AssetID"))
+                        .and_then(|id| id.as_unsigned_integer())
+                        == Some(asset_id)
+                })
+            })
+            .unwrap_or(false);
+        if !requested {
+            warn!("Device did not ask for asset {asset_id}");
+            return Err(IdeviceError::SyncFailed(
+                "asset was not requested by the device".to_string(),
+            ));
+        }
+
+        let path = format!("{}/{file_name}", kind.directory());
+
+        let mut params = plist::Dictionary::new();
+        params.insert("AssetID".into(), asset_id.into());
+        params.insert("Dataclass".into(), dataclass.into());
+        params.insert("FileSize".into(), (data.len() as u64).into());
+        params.insert("TotalSize".into(), (data.len() as u64).into());
+        self.send_message("FileBegin", params).await?;
+
+        afc.make_directory(kind.directory()).await?;
+        afc.write_file(&path, data).await?;
+
+        let mut params = plist::Dictionary::new();
+        params.insert("AssetID".into(), asset_id.into());
+        params.insert("Dataclass".into(), dataclass.into());
+        params.insert("AssetPath".into(), path.clone().into());
+        self.send_message("FileComplete", params).await?;
+
+        self.wait_for("SyncFinished").await?;
+
+        Ok(MediaItem { kind, title, path })
+    }
+}
+
+/// Lists the ringtones or books the device has indexed
+pub async fn list_media(
+    afc: &mut AfcClient,
+    kind: MediaKind,
+) -> Result<Vec<MediaItem>, IdeviceError> {
+    // Devices that were never synced don't have an index
+    if afc.get_file_info(kind.index_path()).await?.is_empty() {
+        return Ok(Vec::new());
+    }
+    let index = afc.read_file(kind.index_path()).await?;
+    let index: plist::Dictionary = plist::from_bytes(&index)?;
+
+    let items = match kind {
+        // file name -> { Name, GUID, PID, ... }
+        MediaKind::Ringtone => index
+            .get("Ringtones")
+            .and_then(|r| r.as_dictionary())
+            .map(|r| {
+                r.iter()
+                    .map(|(file_name, info)| MediaItem {
+                        kind,
+                        title: info
+                            .as_dictionary()
+                            .and_then(|i| i.get("Name"))
+                            .and_then(|n| n.as_string())
+                            .unwrap_or(file_name)
+                            .to_string(),
+                        path: format!("{}/{file_name}", kind.directory()),
+                    })
+                    .collect()
+            }),
+        // [{ Name, Path, ... }], with paths relative to the books directory
+        MediaKind::Book => index.get("Books").and_then(|b| b.as_array()).map(|b| {
+            b.iter()
+                .filter_map(|book| {
+                    let book = book.as_dictionary()?;
+                    let path = book.get("Path")?.as_string()?;
+                    Some(MediaItem {
+                        kind,
+                        title: book
+                            .get("Name")
+                            .and_then(|n| n.as_string())
+                            .unwrap_or(path)
+                            .to_string(),
+                        path: format!("{}/{path}", kind.directory()),
+                    })
+                })
+                .collect()
+        }),
+    };
+
+    Ok(items.unwrap_or_default())
+}
@@ -0,0 +1,108 @@
+// Jackson Coxson
+// Throughput of the crate itself, measured over the in-memory transport.
+// The device and the link are out of the picture, so a change to buffering or framing shows
+// up on its own. The criterion benches run these; save a baseline before a change with
+// `cargo bench --features bench -- --save-baseline before` and compare after with
+// `-- --baseline before`.
+
+use std::time::Instant;
+
+use bytes::BytesMut;
+
+use crate::{
+    afc::AfcClient,
+    codec::Codec,
+    progress::TransferStats,
+    testing::{AfcResponder, MockTransport, Responder},
+    IdeviceError,
+};
+
+const BENCH_PATH: &str = "/Downloads/bench.bin";
+
+/// Reads a file of ``size`` bytes over AFC
+pub async fn afc_read(size: usize) -> Result<TransferStats, IdeviceError> {
+    let responder = AfcResponder::new().with_file(BENCH_PATH, bench_data(size));
+    let mut afc = mock_afc(&responder);
+    let data = afc.read_file(BENCH_PATH).await?;
+    if data.len() != size {
+        return Err(IdeviceError::UnexpectedResponse);
+    }
+    Ok(afc.transfer_stats())
+}
+
+/// Writes a file of ``size`` bytes over AFC
+pub async fn afc_write(size: usize) -> Result<TransferStats, IdeviceError> {
+    let responder = AfcResponder::new().with_directory("/Downloads");
+    let mut afc = mock_afc(&responder);
+    afc.write_file(BENCH_PATH, &bench_data(size)).await?;
+    Ok(afc.transfer_stats())
+}
+
+/// Encodes and decodes a plist the size of an app listing ``count`` times in lockdown's
+/// framing. The bytes are the encoded size of every message.
+pub fn plist_codec(count: usize) -> Result<TransferStats, IdeviceError> {
+    let message = bench_plist();
+    let mut stats = TransferStats::default();
+    let start = Instant::now();
+    for _ in 0..count {
+        let encoded = message.encode()?;
+        stats.bytes += encoded.len() as u64;
+        let mut buf = BytesMut::from(&encoded[..]);
+        if plist::Value::decode(&mut buf)?.as_ref() != Some(&message) {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+    }
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+fn mock_afc(responder: &AfcResponder) -> AfcClient {
+    let (host, device) = MockTransport::pair();
+    let serve = responder.serve(device);
+    crate::runtime::runtime().spawn(Box::pin(async move {
+        if let Err(e) = serve.await {
+            log::warn!("Mock AFC for the bench failed: {e:?}");
+        }
+    }));
+    AfcClient::new(Box::new(host))
+}
+
+fn bench_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| i as u8).collect()
+}
+
+fn bench_plist() -> plist::Value {
+    let apps = (0..100)
+        .map(|i| {
+            let mut app = plist::Dictionary::new();
+            app.insert(
+                "CFBundleIdentifier".into(),
+                format!("com.example.app{i}").into(),
+            );
+            app.insert("CFBundleVersion".into(), "1.0".into());
+            app.insert("ApplicationType".into(), "User".into());
+            app.insert("StaticDiskUsage".into(), (i as u64 * 1024).into());
+            plist::Value::Dictionary(app)
+        })
+        .collect::<Vec<_>>();
+    let mut message = plist::Dictionary::new();
+    message.insert("Status".into(), "BrowsingApplications".into());
+    message.insert("CurrentList".into(), plist::Value::Array(apps));
+    plist::Value::Dictionary(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn benches_move_the_expected_bytes() {
+        let size = 200_000;
+        assert_eq!(afc_read(size).await.unwrap().bytes, size as u64);
+        assert_eq!(afc_write(size).await.unwrap().bytes, size as u64);
+
+        let stats = plist_codec(3).unwrap();
+        assert_eq!(stats.bytes % 3, 0);
+        assert!(stats.bytes > 0);
+    }
+}
@@ -0,0 +1,379 @@
+// Jackson Coxson
+// What a device offers, by iOS version and kind of device.
+// Apple moves services between releases, so clients that work across versions ask here
+// instead of each keeping their own version cutoffs. Apple TVs and watches run the same
+// services under their own version numbers, and watches are only reached through the
+// iPhone they're paired with.
+
+use std::fmt;
+
+use log::warn;
+
+use crate::{lockdownd::LockdowndClient, IdeviceError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IosVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl IosVersion {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses a ``ProductVersion`` such as ``17.4.1``
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.trim().split('.');
+        let major = parts.next()?.parse().ok()?;
+        let mut next = || match parts.next() {
+            Some(p) => p.parse().ok(),
+            None => Some(0),
+        };
+        Some(Self::new(major, next()?, next()?))
+    }
+}
+
+impl fmt::Display for IosVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The kind of device, from lockdown's ``DeviceClass``
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceClass {
+    IPhone,
+    IPad,
+    IPod,
+    /// Paired over the network, since most have no USB port
+    AppleTv,
+    /// Reached through the paired iPhone's companion proxy
+    Watch,
+    Unknown,
+}
+
+impl DeviceClass {
+    pub fn parse(class: &str) -> Self {
+        match class {
+            "iPhone" => Self::IPhone,
+            "iPad" => Self::IPad,
+            "iPod" => Self::IPod,
+            "AppleTV" => Self::AppleTv,
+            "Watch" => Self::Watch,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for DeviceClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::IPhone => "iPhone",
+            Self::IPad => "iPad",
+            Self::IPod => "iPod",
+            Self::AppleTv => "AppleTV",
+            Self::Watch => "Watch",
+            Self::Unknown => "Unknown",
+        })
+    }
+}
+
+/// How developer services are reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceTransport {
+    /// Started through lockdown, like every other service
+    Lockdown,
+    /// Listed by RemoteServiceDiscovery at the other end of a CoreDevice tunnel
+    Rsd,
+}
+
+/// The kind of developer disk image the device mounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeveloperImageKind {
+    /// One image per iOS version, with a signature
+    Developer,
+    /// One image for every version, personalized for the device by Apple's TSS server
+    Personalized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotSource {
+    /// ``com.apple.mobile.screenshotr``, from the developer disk image
+    Screenshotr,
+    /// The screenshot channel of the DVT remote server
+    Dvt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSource {
+    /// ``com.apple.syslog_relay``, plain text lines
+    SyslogRelay,
+    /// ``com.apple.os_trace_relay``, structured entries
+    OsTraceRelay,
+}
+
+/// The services and protocol variants of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    version: IosVersion,
+    class: DeviceClass,
+}
+
+impl Capabilities {
+    /// The capabilities of an iPhone on ``version``
+    pub fn new(version: IosVersion) -> Self {
+        Self {
+            version,
+            class: DeviceClass::IPhone,
+        }
+    }
+
+    /// For another kind of device. ``version`` is the one the device reports, so the
+    /// watchOS version for a watch.
+    pub fn with_device_class(mut self, class: DeviceClass) -> Self {
+        self.class = class;
+        self
+    }
+
+    /// Reads the device's version and class, which lockdown gives out without a session
+    pub async fn from_lockdown(lockdown: &mut LockdowndClient) -> Result<Self, IdeviceError> {
+        let version = lockdown.get_value("ProductVersion").await?;
+        let version = match version.as_string().and_then(IosVersion::parse) {
+            Some(v) => v,
+            None => {
+                warn!("Couldn't parse ProductVersion {version:?}");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        let class = match lockdown.get_value("DeviceClass").await {
+            Ok(class) => DeviceClass::parse(class.as_string().unwrap_or_default()),
+            // Lockdown leaves out values it doesn't have instead of failing
+            Err(IdeviceError::UnexpectedResponse) => DeviceClass::Unknown,
+            Err(e) => return Err(e),
+        };
+        Ok(Self::new(version).with_device_class(class))
+    }
+
+    /// The version the device reports, which is the watchOS or tvOS version on those devices
+    pub fn version(&self) -> IosVersion {
+        self.version
+    }
+
+    pub fn device_class(&self) -> DeviceClass {
+        self.class
+    }
+
+    /// The iOS release the device's services match. tvOS is numbered with iOS. watchOS was
+    /// seven behind it until 11, and from 26 both are numbered by year.
+    pub fn ios_major(&self) -> u16 {
+        match self.class {
+            DeviceClass::Watch if self.version.major < 26 => self.version.major + 7,
+            _ => self.version.major,
+        }
+    }
+
+    fn at_least(&self, major: u16) -> bool {
+        self.ios_major() >= major
+    }
+
+    /// Watches have no connection of their own. Their services are forwarded by the paired
+    /// iPhone, see ``CompanionProxyClient``.
+    pub fn through_companion(&self) -> bool {
+        self.class == DeviceClass::Watch
+    }
+
+    pub fn developer_transport(&self) -> ServiceTransport {
+        if self.at_least(17) {
+            ServiceTransport::Rsd
+        } else {
+            ServiceTransport::Lockdown
+        }
+    }
+
+    pub fn developer_image(&self) -> DeveloperImageKind {
+        if self.at_least(17) {
+            DeveloperImageKind::Personalized
+        } else {
+            DeveloperImageKind::Developer
+        }
+    }
+
+    /// The name of the debugserver service for the device's transport
+    pub fn debugserver_service(&self) -> &'static str {
+        if self.at_least(17) {
+            "com.apple.internal.dt.remote.debugproxy"
+        } else if self.at_least(14) {
+            // Lockdown only hands out the SSL wrapped variant from iOS 14
+            "com.apple.debugserver.DVTSecureSocketProxy"
+        } else {
+            "com.apple.debugserver"
+        }
+    }
+
+    /// The name of the DVT remote server service for the device's transport
+    pub fn instruments_service(&self) -> &'static str {
+        if self.at_least(17) {
+            "com.apple.instruments.dtservicehub"
+        } else if self.at_least(14) {
+            "com.apple.instruments.remoteserver.DVTSecureSocketProxy"
+        } else {
+            "com.apple.instruments.remoteserver"
+        }
+    }
+
+    /// Screenshotr isn't in the personalized image, so newer devices take screenshots over DVT
+    pub fn screenshot(&self) -> ScreenshotSource {
+        if self.at_least(17) {
+            ScreenshotSource::Dvt
+        } else {
+            ScreenshotSource::Screenshotr
+        }
+    }
+
+    pub fn logs(&self) -> LogSource {
+        if self.at_least(10) {
+            LogSource::OsTraceRelay
+        } else {
+            LogSource::SyslogRelay
+        }
+    }
+}
+
+/// Starts a developer service and connects to it, through lockdown before iOS 17 and
+/// through a software CoreDevice tunnel from then on. ``service`` picks the service's name
+/// for the device's version.
+#[cfg(any(feature = "debug_proxy", feature = "dvt"))]
+pub(crate) async fn connect_developer_service(
+    provider: &dyn crate::provider::IdeviceProvider,
+    service: fn(&Capabilities) -> &'static str,
+) -> Result<Box<dyn crate::ReadWrite>, IdeviceError> {
+    use crate::IdeviceService;
+
+    let mut lockdown = LockdowndClient::connect(provider).await?;
+    let capabilities = Capabilities::from_lockdown(&mut lockdown).await?;
+    let service = service(&capabilities);
+    log::debug!(
+        "Connecting to {service} on {} {}",
+        capabilities.device_class(),
+        capabilities.version()
+    );
+
+    match capabilities.developer_transport() {
+        ServiceTransport::Lockdown => {
+            lockdown
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+            let (port, ssl) = lockdown.start_service(service).await?;
+
+            let mut idevice = provider.connect(port).await?;
+            if ssl {
+                idevice
+                    .start_session(&provider.get_pairing_file().await?)
+                    .await?;
+            }
+            idevice.into_inner()
+        }
+        #[cfg(all(
+            feature = "core_device_proxy",
+            feature = "tunnel_tcp_stack",
+            feature = "xpc"
+        ))]
+        ServiceTransport::Rsd => {
+            let proxy = crate::core_device_proxy::CoreDeviceProxy::connect(provider).await?;
+            let rsd_port = proxy.handshake.server_rsd_port;
+            let mut adapter = proxy.create_software_tunnel()?;
+            adapter.connect(rsd_port).await?;
+
+            let client = crate::xpc::XPCDevice::new(Box::new(adapter)).await?;
+            let port = client.service(service)?.port;
+
+            let mut adapter = client.into_inner();
+            adapter.close().await?;
+            adapter.connect(port).await?;
+            Ok(adapter as Box<dyn crate::ReadWrite>)
+        }
+        #[cfg(not(all(
+            feature = "core_device_proxy",
+            feature = "tunnel_tcp_stack",
+            feature = "xpc"
+        )))]
+        ServiceTransport::Rsd => Err(IdeviceError::FeatureDisabled(
+            "core_device_proxy, tunnel_tcp_stack and xpc",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_parsed() {
+        assert_eq!(IosVersion::parse("17.4.1"), Some(IosVersion::new(17, 4, 1)));
+        assert_eq!(IosVersion::parse("16.0"), Some(IosVersion::new(16, 0, 0)));
+        assert_eq!(IosVersion::parse("9"), Some(IosVersion::new(9, 0, 0)));
+        assert_eq!(IosVersion::parse("17.x"), None);
+        assert!(IosVersion::new(16, 7, 10) < IosVersion::new(17, 0, 0));
+        assert_eq!(IosVersion::new(17, 4, 1).to_string(), "17.4.1");
+    }
+
+    #[test]
+    fn services_move_with_the_version() {
+        let caps = |v| Capabilities::new(IosVersion::parse(v).unwrap());
+
+        let old = caps("12.5.7");
+        assert_eq!(old.developer_transport(), ServiceTransport::Lockdown);
+        assert_eq!(old.debugserver_service(), "com.apple.debugserver");
+        assert_eq!(old.screenshot(), ScreenshotSource::Screenshotr);
+
+        let legacy = caps("16.7");
+        assert_eq!(legacy.developer_image(), DeveloperImageKind::Developer);
+        assert_eq!(
+            legacy.instruments_service(),
+            "com.apple.instruments.remoteserver.DVTSecureSocketProxy"
+        );
+
+        let current = caps("17.0");
+        assert_eq!(current.developer_transport(), ServiceTransport::Rsd);
+        assert_eq!(current.developer_image(), DeveloperImageKind::Personalized);
+        assert_eq!(current.screenshot(), ScreenshotSource::Dvt);
+        assert_eq!(current.logs(), LogSource::OsTraceRelay);
+    }
+
+    #[test]
+    fn watches_and_apple_tvs_follow_their_ios_release() {
+        assert_eq!(DeviceClass::parse("AppleTV"), DeviceClass::AppleTv);
+        assert_eq!(DeviceClass::parse("Watch"), DeviceClass::Watch);
+        assert_eq!(DeviceClass::parse("RealityDevice"), DeviceClass::Unknown);
+
+        let caps =
+            |v, class| Capabilities::new(IosVersion::parse(v).unwrap()).with_device_class(class);
+
+        // watchOS 9 came with iOS 16, watchOS 10 with iOS 17
+        let watch = caps("9.6", DeviceClass::Watch);
+        assert_eq!(watch.ios_major(), 16);
+        assert!(watch.through_companion());
+        assert_eq!(watch.developer_image(), DeveloperImageKind::Developer);
+        let watch = caps("10.4", DeviceClass::Watch);
+        assert_eq!(watch.version(), IosVersion::new(10, 4, 0));
+        assert_eq!(watch.developer_transport(), ServiceTransport::Rsd);
+        assert_eq!(watch.screenshot(), ScreenshotSource::Dvt);
+        // watchOS 11 came with iOS 18, then both went to 26
+        assert_eq!(caps("11.5", DeviceClass::Watch).ios_major(), 18);
+        assert_eq!(caps("26.0", DeviceClass::Watch).ios_major(), 26);
+
+        let tv = caps("16.6", DeviceClass::AppleTv);
+        assert!(!tv.through_companion());
+        assert_eq!(tv.screenshot(), ScreenshotSource::Screenshotr);
+        assert_eq!(
+            caps("17.4", DeviceClass::AppleTv).developer_transport(),
+            ServiceTransport::Rsd
+        );
+    }
+}
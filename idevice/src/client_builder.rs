@@ -0,0 +1,170 @@
+// Jackson Coxson
+// Connecting to a service with options other than the defaults.
+// Every client gets a builder through IdeviceService, so an option added here works for all
+// of them instead of each connect growing another argument.
+
+use std::{marker::PhantomData, time::Duration};
+
+use crate::{
+    lock_state::RetryOnLock, provider::IdeviceProvider, re_pairing::RePairPolicy, Idevice,
+    IdeviceError, IdeviceService,
+};
+
+/// Options for connecting to a service, made with ``IdeviceService::builder``
+pub struct ClientBuilder<'a, S> {
+    provider: Option<&'a dyn IdeviceProvider>,
+    timeout: Option<Duration>,
+    service_name: Option<String>,
+    retry_on_lock: Option<RetryOnLock>,
+    re_pair: Option<RePairPolicy>,
+    service: PhantomData<fn() -> S>,
+}
+
+impl<S> std::fmt::Debug for ClientBuilder<'_, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("provider", &self.provider)
+            .field("timeout", &self.timeout)
+            .field("service_name", &self.service_name)
+            .field("retry_on_lock", &self.retry_on_lock)
+            .field("re_pair", &self.re_pair)
+            .finish()
+    }
+}
+
+impl<S> Default for ClientBuilder<'_, S> {
+    fn default() -> Self {
+        Self {
+            provider: None,
+            timeout: None,
+            service_name: None,
+            retry_on_lock: None,
+            re_pair: None,
+            service: PhantomData,
+        }
+    }
+}
+
+impl<'a, S: IdeviceService> ClientBuilder<'a, S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The device to connect to. Required.
+    pub fn provider(mut self, provider: &'a dyn IdeviceProvider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Gives up with ``Timeout`` if connecting takes longer than this, including any time
+    /// spent waiting for the device to be unlocked
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Starts the service under another name that speaks the same protocol, such as
+    /// ``com.apple.afc2`` or ``com.apple.crashreportcopymobile`` for AFC
+    pub fn service_variant(mut self, service_name: impl Into<String>) -> Self {
+        self.service_name = Some(service_name.into());
+        self
+    }
+
+    /// Waits for the device to be unlocked if it refuses the service while locked
+    pub fn retry_on_lock(mut self, retry: RetryOnLock) -> Self {
+        self.retry_on_lock = Some(retry);
+        self
+    }
+
+    /// What to do if the device has forgotten this host and rejects the pairing record
+    pub fn re_pair(mut self, policy: RePairPolicy) -> Self {
+        self.re_pair = Some(policy);
+        self
+    }
+
+    /// The service that will be started
+    pub fn service_name(&self) -> &str {
+        self.service_name.as_deref().unwrap_or(S::service_name())
+    }
+
+    pub async fn connect(self) -> Result<S, IdeviceError> {
+        let provider = match self.provider {
+            Some(p) => p,
+            None => {
+                log::error!("No provider given to connect to {}", self.service_name());
+                return Err(IdeviceError::InvalidArgument);
+            }
+        };
+        let variant = self.service_name.as_deref();
+        let attempt = || async move {
+            match variant {
+                Some(name) => {
+                    let socket = provider.start_service_raw(name).await?;
+                    S::from_idevice(Idevice::new(socket, provider.label())).await
+                }
+                None => S::connect(provider).await,
+            }
+        };
+        let unlocked = || async {
+            match &self.retry_on_lock {
+                Some(retry) => retry.run(provider, self.service_name(), attempt).await,
+                None => attempt().await,
+            }
+        };
+        let connect = async {
+            match &self.re_pair {
+                Some(policy) => policy.run(provider, unlocked).await,
+                None => unlocked().await,
+            }
+        };
+
+        match self.timeout {
+            Some(timeout) => tokio::select! {
+                res = connect => res,
+                _ = crate::runtime::sleep(timeout) => Err(IdeviceError::Timeout),
+            },
+            None => connect.await,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "installation_proxy"))]
+mod tests {
+    use super::*;
+    use crate::{
+        installation_proxy::InstallationProxyClient,
+        testing::{MockProvider, ScriptedResponder},
+    };
+
+    #[tokio::test]
+    async fn builder_applies_options() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service("com.example.variant", ScriptedResponder::new(vec![]));
+
+        let res = InstallationProxyClient::builder().connect().await;
+        assert!(matches!(res, Err(IdeviceError::InvalidArgument)));
+
+        // Only the variant was added, so the default name would be refused
+        let builder = InstallationProxyClient::builder()
+            .provider(&provider)
+            .service_variant("com.example.variant");
+        assert_eq!(builder.service_name(), "com.example.variant");
+        builder.connect().await.unwrap();
+        assert!(InstallationProxyClient::builder()
+            .provider(&provider)
+            .connect()
+            .await
+            .is_err());
+
+        provider.set_locked(true);
+        let res = InstallationProxyClient::builder()
+            .provider(&provider)
+            .service_variant("com.example.variant")
+            .retry_on_lock(RetryOnLock::new().with_poll_interval(Duration::from_millis(10)))
+            .timeout(Duration::from_millis(50))
+            .connect()
+            .await;
+        assert!(matches!(res, Err(IdeviceError::Timeout)));
+    }
+}
@@ -0,0 +1,171 @@
+// Jackson Coxson
+// Framing for the wire formats, kept separate from any socket.
+// Clients read and write through these, and embedders with their own event loops
+// can feed bytes into ``decode`` as they arrive instead.
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{util::check_message_size, IdeviceError};
+
+/// A message with a fixed size header that holds the length of the whole frame
+pub trait Codec: Sized {
+    /// Bytes needed before ``frame_length`` can be called
+    const HEADER_LEN: usize;
+
+    /// Length of the frame, header included, as given by its header
+    fn frame_length(header: &[u8]) -> Result<usize, IdeviceError>;
+
+    /// Parses exactly one frame
+    fn decode_frame(frame: &[u8]) -> Result<Self, IdeviceError>;
+
+    fn encode(&self) -> Result<Bytes, IdeviceError>;
+
+    /// Takes one message off the front of the buffer.
+    /// Returns ``None`` until the whole frame has arrived, leaving the buffer untouched.
+    fn decode(buf: &mut BytesMut) -> Result<Option<Self>, IdeviceError> {
+        if buf.len() < Self::HEADER_LEN {
+            return Ok(None);
+        }
+        let len = checked_frame_length::<Self>(&buf[..Self::HEADER_LEN])?;
+        if buf.len() < len {
+            return Ok(None);
+        }
+        let frame = buf.split_to(len);
+        Self::decode_frame(&frame).map(Some)
+    }
+}
+
+/// Reads exactly one frame, so nothing past it is consumed from the reader.
+/// Any reader works, such as a host's own bridge to a device.
+pub async fn read_frame<T: Codec, R: AsyncRead + Unpin>(reader: &mut R) -> Result<T, IdeviceError> {
+    let mut buf = vec![0; T::HEADER_LEN];
+    reader.read_exact(&mut buf).await?;
+    let len = checked_frame_length::<T>(&buf)?;
+
+    buf.resize(len, 0);
+    reader.read_exact(&mut buf[T::HEADER_LEN..]).await?;
+    #[cfg(feature = "metrics")]
+    crate::metrics::received(len);
+    T::decode_frame(&buf)
+}
+
+fn checked_frame_length<T: Codec>(header: &[u8]) -> Result<usize, IdeviceError> {
+    let len = T::frame_length(header)?;
+    if len < T::HEADER_LEN {
+        return Err(IdeviceError::NotEnoughBytes(len, T::HEADER_LEN));
+    }
+    check_message_size(len)
+}
+
+/// How a plist is written on the wire. Either is accepted when reading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Xml,
+    Binary,
+}
+
+impl WireFormat {
+    /// What the service expects. Most take either, but some newer ones only parse binary.
+    pub fn for_service(service_name: &str) -> Self {
+        match service_name {
+            "com.apple.mobile.heartbeat"
+            | "com.apple.springboardservices"
+            | "com.apple.webinspector" => Self::Binary,
+            _ => Self::Xml,
+        }
+    }
+
+    /// The format to fall back to when this one isn't understood
+    pub fn other(self) -> Self {
+        match self {
+            Self::Xml => Self::Binary,
+            Self::Binary => Self::Xml,
+        }
+    }
+}
+
+/// Frames a plist in lockdown's framing, written in the given format
+pub fn encode_plist(value: &plist::Value, format: WireFormat) -> Result<Bytes, IdeviceError> {
+    let mut buf = vec![0; 4];
+    match format {
+        WireFormat::Xml => value.to_writer_xml(&mut buf)?,
+        WireFormat::Binary => value.to_writer_binary(&mut buf)?,
+    }
+    let len = (buf.len() - 4) as u32;
+    buf[..4].copy_from_slice(&len.to_be_bytes());
+    Ok(buf.into())
+}
+
+/// Lockdown style framing, a big endian u32 length followed by the plist.
+/// Plists are sent as XML, and either format is accepted.
+impl Codec for plist::Value {
+    const HEADER_LEN: usize = 4;
+
+    fn frame_length(mut header: &[u8]) -> Result<usize, IdeviceError> {
+        // A u32 length plus the header doesn't fit a 32 bit usize
+        (header.get_u32() as usize)
+            .checked_add(4)
+            .ok_or(IdeviceError::MessageTooLarge(usize::MAX))
+    }
+
+    fn decode_frame(frame: &[u8]) -> Result<Self, IdeviceError> {
+        Ok(plist::from_bytes(&frame[4..])?)
+    }
+
+    fn encode(&self) -> Result<Bytes, IdeviceError> {
+        encode_plist(self, WireFormat::Xml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plist_partial_frames() {
+        let mut dict = plist::Dictionary::new();
+        dict.insert("Request".into(), "QueryType".into());
+        let value = plist::Value::Dictionary(dict);
+        let encoded = value.encode().unwrap();
+
+        let mut buf = BytesMut::new();
+        for chunk in encoded.chunks(7) {
+            assert_eq!(plist::Value::decode(&mut buf).unwrap(), None);
+            buf.extend_from_slice(chunk);
+        }
+        buf.extend_from_slice(&encoded);
+
+        assert_eq!(plist::Value::decode(&mut buf).unwrap(), Some(value.clone()));
+        assert_eq!(plist::Value::decode(&mut buf).unwrap(), Some(value));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn plist_formats() {
+        let mut dict = plist::Dictionary::new();
+        dict.insert("Request".into(), "QueryType".into());
+        let value = plist::Value::Dictionary(dict);
+
+        let binary = encode_plist(&value, WireFormat::Binary).unwrap();
+        assert_eq!(&binary[4..12], b"bplist00");
+        let xml = encode_plist(&value, WireFormat::Xml).unwrap();
+        assert_eq!(xml, value.encode().unwrap());
+        for encoded in [binary, xml] {
+            let mut buf = BytesMut::from(&encoded[..]);
+            assert_eq!(plist::Value::decode(&mut buf).unwrap(), Some(value.clone()));
+        }
+        assert_eq!(
+            WireFormat::for_service("com.apple.mobile.heartbeat"),
+            WireFormat::Binary
+        );
+        assert_eq!(WireFormat::Binary.other(), WireFormat::Xml);
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut buf = BytesMut::from(&[0xff, 0xff, 0xff, 0xff, 0, 0][..]);
+        assert!(plist::Value::decode(&mut buf).is_err());
+    }
+}
@@ -1,21 +1,196 @@
 //! Companion Proxy service implementation
+//!
+//! A paired Apple Watch has no connection of its own to the host. The iPhone it's paired
+//! with keeps a registry of its watches and forwards ports on them, so a watch's lockdown
+//! and services are reached through the phone's provider.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use log::debug;
+
+use crate::{
+    lockdownd::LockdowndClient, provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService,
+    ReadWrite,
+};
 
 const COMPANION_PROXY_SERVICE_NAME: &str = "com.apple.companion_proxy";
 
 /// Companion Proxy client for device pairing
 pub struct CompanionProxyClient {
-    socket: tokio::net::TcpStream,
+    idevice: Idevice,
+}
+
+impl IdeviceService for CompanionProxyClient {
+    fn service_name() -> &'static str {
+        COMPANION_PROXY_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl CompanionProxyClient {
-    /// Connect to the Companion Proxy service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(COMPANION_PROXY_SERVICE_NAME).await?;
-        Ok(Self {
-            socket: service.socket,
-        })
-    }
-}
\ No newline at end of file
+    /// Create a client over an existing connection to the Companion Proxy service
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        Self {
+            idevice: Idevice::new(socket, "companion_proxy"),
+        }
+    }
+
+    /// The connection, for requests this client doesn't have methods for yet
+    pub fn into_inner(self) -> Result<Box<dyn ReadWrite>, IdeviceError> {
+        self.idevice.into_inner()
+    }
+
+    /// The UDIDs of the watches paired with the phone
+    pub async fn get_device_registry(&mut self) -> Result<Vec<String>, IdeviceError> {
+        let mut res = self
+            .request("GetDeviceRegistry", plist::Dictionary::new())
+            .await?;
+        match res.remove("PairedDevicesArray") {
+            Some(plist::Value::Array(devices)) => Ok(devices
+                .into_iter()
+                .filter_map(|d| d.into_string())
+                .collect()),
+            // Left out when nothing is paired
+            None => Ok(Vec::new()),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Reads a value the phone keeps about a paired watch, such as ``name``
+    pub async fn get_value_from_registry(
+        &mut self,
+        udid: &str,
+        key: &str,
+    ) -> Result<plist::Value, IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("GetValueGizmoUDIDKey".into(), udid.into());
+        req.insert("GetValueKeyKey".into(), key.into());
+        let mut res = self.request("GetValueFromRegistry", req).await?;
+        match res.remove("RetrievedValueDictionary") {
+            Some(plist::Value::Dictionary(mut values)) => {
+                values.remove(key).ok_or(IdeviceError::UnexpectedResponse)
+            }
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Forwards ``remote_port`` on the watch to a port on the phone, which is returned and
+    /// can be connected to with the phone's provider. ``service_name`` is only used by the
+    /// phone to label the connection.
+    pub async fn start_forwarding_service_port(
+        &mut self,
+        remote_port: u16,
+        service_name: Option<&str>,
+    ) -> Result<u16, IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("GizmoRemotePortNumber".into(), (remote_port as u64).into());
+        req.insert("IsServiceLowPriority".into(), false.into());
+        req.insert("PreferWifi".into(), false.into());
+        if let Some(name) = service_name {
+            req.insert("ForwardedServiceName".into(), name.into());
+        }
+        let res = self.request("StartForwardingServicePort", req).await?;
+        match res
+            .get("CompanionProxyServicePort")
+            .and_then(|p| p.as_unsigned_integer())
+        {
+            Some(port) => Ok(port as u16),
+            None => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn stop_forwarding_service_port(
+        &mut self,
+        remote_port: u16,
+    ) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("GizmoRemotePortNumber".into(), (remote_port as u64).into());
+        self.request("StopForwardingServicePort", req).await?;
+        Ok(())
+    }
+
+    /// Connects to the paired watch's lockdown through the phone. Its services are started
+    /// from there and reached with ``start_forwarding_service_port``.
+    pub async fn connect_watch_lockdown(
+        &mut self,
+        provider: &dyn IdeviceProvider,
+    ) -> Result<LockdowndClient, IdeviceError> {
+        let port = self
+            .start_forwarding_service_port(
+                LockdowndClient::LOCKDOWND_PORT,
+                Some("com.apple.mobile.lockdown"),
+            )
+            .await?;
+        debug!("Watch lockdown forwarded to port {port}");
+        Ok(LockdowndClient::new(provider.connect(port).await?))
+    }
+
+    async fn request(
+        &mut self,
+        command: &str,
+        mut req: plist::Dictionary,
+    ) -> Result<plist::Dictionary, IdeviceError> {
+        req.insert("Command".into(), command.into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        // Errors the phone sends back are turned into IdeviceErrors while reading
+        self.idevice.read_plist().await
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{
+        capabilities::{Capabilities, DeviceClass, ServiceTransport},
+        testing::{MockProvider, ScriptedResponder},
+    };
+
+    fn dict(entries: &[(&str, plist::Value)]) -> plist::Dictionary {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn watches_are_reached_through_the_phone() {
+        let watch_udid = "00008301-000A1B2C3D4E5F60";
+        // The companion proxy is the first service, so the watch's lockdown is on the next port
+        let companion = ScriptedResponder::new(vec![
+            dict(&[(
+                "PairedDevicesArray",
+                plist::Value::Array(vec![watch_udid.into()]),
+            )]),
+            dict(&[("Error", "UnsupportedCommand".into())]),
+            dict(&[("CompanionProxyServicePort", 49153u64.into())]),
+        ]);
+        let watch_lockdown = ScriptedResponder::new(vec![
+            dict(&[("Value", "10.4".into())]),
+            dict(&[("Value", "Watch".into())]),
+        ]);
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(COMPANION_PROXY_SERVICE_NAME, companion)
+            .with_service("watch lockdown", watch_lockdown);
+
+        let mut client = CompanionProxyClient::connect(&provider).await.unwrap();
+        assert_eq!(client.get_device_registry().await.unwrap(), [watch_udid]);
+        assert!(matches!(
+            client.get_value_from_registry(watch_udid, "name").await,
+            Err(IdeviceError::UnknownErrorType(e)) if e == "UnsupportedCommand"
+        ));
+
+        let mut lockdown = client.connect_watch_lockdown(&provider).await.unwrap();
+        let capabilities = Capabilities::from_lockdown(&mut lockdown).await.unwrap();
+        assert_eq!(capabilities.device_class(), DeviceClass::Watch);
+        assert!(capabilities.through_companion());
+        assert_eq!(capabilities.developer_transport(), ServiceTransport::Rsd);
+    }
+}
@@ -0,0 +1,196 @@
+// Jackson Coxson
+// Why a device is or isn't reachable.
+// usbmuxd lists a device once for each way it can reach it, USB and the network. A device
+// missing from the network list could have Wi-Fi sync off, be on another subnet, be asleep
+// or have forgotten this host, and each of those looks the same from the outside. The
+// report checks them in the order they'd get in the way.
+
+use std::{fmt, net::IpAddr};
+
+use log::debug;
+
+use crate::{
+    lockdownd::LockdowndClient,
+    pretty::Pretty,
+    re_pairing::is_pairing_rejected,
+    usbmuxd::{Connection, UsbmuxdAddr},
+    IdeviceError, IdeviceService,
+};
+
+/// Something keeping a device from being reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectivityIssue {
+    /// usbmuxd doesn't list the device at all
+    NotListed,
+    /// usbmuxd has no pairing record for the device
+    NotPaired,
+    /// The device rejected the pairing record, so it was told to forget this host
+    HostForgotten,
+    /// Wi-Fi sync is off, so the device is only reachable over USB
+    WifiSyncOff,
+    /// Wi-Fi sync is on, but usbmuxd hasn't found the device on the network
+    NotOnNetwork,
+}
+
+impl fmt::Display for ConnectivityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotListed => {
+                "usbmuxd doesn't see the device. Plug it in, or if it syncs over Wi-Fi, wake it \
+                 up on the same network as this host"
+            }
+            Self::NotPaired => "the device hasn't been paired with this host, pair it over USB",
+            Self::HostForgotten => {
+                "the device no longer trusts this host, pair it again and accept the prompt"
+            }
+            Self::WifiSyncOff => {
+                "Wi-Fi sync is off, turn it on over USB to reach the device over the network"
+            }
+            Self::NotOnNetwork => {
+                "Wi-Fi sync is on, but the device isn't advertising on this network. It has to be \
+                 awake on the same subnet, and the network has to let Bonjour through"
+            }
+        })
+    }
+}
+
+/// How a device can be reached and what's in the way, from [`connectivity_report`]
+#[derive(Debug, Clone)]
+pub struct ConnectivityReport {
+    pub udid: String,
+    /// Each way usbmuxd can reach the device
+    pub connections: Vec<Connection>,
+    /// Whether usbmuxd has a pairing record for the device
+    pub paired: bool,
+    /// Whether the device accepted the pairing record, if it could be asked
+    pub trusted: Option<bool>,
+    /// Whether Wi-Fi sync is on, if a session could be started
+    pub wifi_sync: Option<bool>,
+    /// The MAC address of the device's Wi-Fi interface
+    pub wifi_address: Option<String>,
+    /// Empty when the device is reachable every way it can be
+    pub issues: Vec<ConnectivityIssue>,
+}
+
+impl ConnectivityReport {
+    pub fn usb(&self) -> bool {
+        self.connections
+            .iter()
+            .any(|c| matches!(c, Connection::Usb))
+    }
+
+    /// The addresses usbmuxd reaches the device at over the network
+    pub fn network_addresses(&self) -> Vec<IpAddr> {
+        self.connections
+            .iter()
+            .filter_map(|c| match c {
+                Connection::Network(addr) => Some(*addr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether services can be started on the device at all
+    pub fn reachable(&self) -> bool {
+        !self.connections.is_empty() && self.trusted == Some(true)
+    }
+}
+
+impl Pretty for ConnectivityReport {
+    fn pretty(&self) -> String {
+        let yes_no = |b: Option<bool>| match b {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        };
+        let connections = if self.connections.is_empty() {
+            "none".to_string()
+        } else {
+            self.connections
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut out = format!(
+            "UDID: {}\nConnections: {connections}\nPaired: {}\nTrusted: {}\nWi-Fi sync: {}\n\
+             Wi-Fi address: {}",
+            self.udid,
+            yes_no(Some(self.paired)),
+            yes_no(self.trusted),
+            yes_no(self.wifi_sync),
+            self.wifi_address.as_deref().unwrap_or("unknown"),
+        );
+        for issue in &self.issues {
+            out.push_str(&format!("\n- {issue}"));
+        }
+        out
+    }
+}
+
+/// Checks how a device can be reached through usbmuxd and explains anything in the way.
+/// Only failing to talk to usbmuxd or lockdown is an error, a device that can't be reached
+/// is a report with issues.
+pub async fn connectivity_report(
+    addr: &UsbmuxdAddr,
+    udid: &str,
+) -> Result<ConnectivityReport, IdeviceError> {
+    let devices = addr.connect(0).await?.get_device_connections(udid).await?;
+    let mut report = ConnectivityReport {
+        udid: udid.to_string(),
+        connections: devices.iter().map(|d| d.connection_type.clone()).collect(),
+        paired: false,
+        trusted: None,
+        wifi_sync: None,
+        wifi_address: None,
+        issues: Vec::new(),
+    };
+
+    // USB answers even when the network connection is flaky
+    let device = match devices
+        .iter()
+        .find(|d| matches!(d.connection_type, Connection::Usb))
+        .or(devices.first())
+    {
+        Some(d) => d,
+        None => {
+            report.issues.push(ConnectivityIssue::NotListed);
+            return Ok(report);
+        }
+    };
+
+    let pairing_file = match addr.connect(0).await?.get_pair_record(udid).await {
+        Ok(p) => p,
+        Err(IdeviceError::UnexpectedResponse) => {
+            report.issues.push(ConnectivityIssue::NotPaired);
+            return Ok(report);
+        }
+        Err(e) => return Err(e),
+    };
+    report.paired = true;
+
+    let provider = device.to_provider(addr.clone(), 0, "connectivity_report");
+    let mut lockdown = LockdowndClient::connect(&provider).await?;
+    report.wifi_address = lockdown.wifi_address().await.ok();
+    match lockdown.start_session(&pairing_file).await {
+        Ok(_) => report.trusted = Some(true),
+        Err(e) if is_pairing_rejected(&e) => {
+            debug!("{udid} rejected the pairing record: {e:?}");
+            report.trusted = Some(false);
+            report.issues.push(ConnectivityIssue::HostForgotten);
+            return Ok(report);
+        }
+        Err(e) => return Err(e),
+    }
+
+    let wifi_sync = lockdown.wifi_sync_enabled().await?;
+    report.wifi_sync = Some(wifi_sync);
+    if report.network_addresses().is_empty() {
+        report.issues.push(if wifi_sync {
+            ConnectivityIssue::NotOnNetwork
+        } else {
+            ConnectivityIssue::WifiSyncOff
+        });
+    }
+    Ok(report)
+}
@@ -12,7 +12,15 @@ pub struct CDTunnelPacket {
 }
 
 impl CDTunnelPacket {
-    const MAGIC: &'static [u8] = b"CDTunnel";
+    pub const MAGIC: &'static [u8] = b"CDTunnel";
+
+    pub fn new(body: Vec<u8>) -> Self {
+        Self { body }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
 
     /// Parses a byte slice into a `CDTunnelPacket`.
     pub fn parse(input: &[u8]) -> Result<Self, IdeviceError> {
@@ -87,6 +95,10 @@ impl IdeviceService for CoreDeviceProxy {
                 .await?;
         }
 
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
         Self::new(idevice).await
     }
 }
@@ -0,0 +1,55 @@
+// Jackson Coxson
+// Serving stock debuggers. Packets are passed through to debugserver untouched, so a
+// local port bridged here lets LLDB attach with `process connect connect://localhost:PORT`.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::DebugProxyClient;
+use crate::{IdeviceError, ReadWrite};
+
+impl<R: ReadWrite> DebugProxyClient<R> {
+    /// Passes packets between a debugger and debugserver until either side disconnects.
+    /// The client has to be unused, the debugger negotiates ack mode and the rest itself.
+    pub async fn bridge<S>(mut self, debugger: S) -> Result<(), IdeviceError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (mut debugger_read, mut debugger_write) = tokio::io::split(debugger);
+        let (mut device_read, mut device_write) = tokio::io::split(&mut self.socket);
+        // The other direction is abandoned once one side hangs up, debugserver doesn't
+        // always close its end when the debugger detaches
+        tokio::select! {
+            r = tokio::io::copy(&mut debugger_read, &mut device_write) => r?,
+            r = tokio::io::copy(&mut device_read, &mut debugger_write) => r?,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn packets_pass_through() {
+        let (socket, mut debugserver) = tokio::io::duplex(1024);
+        let (debugger, mut lldb) = tokio::io::duplex(1024);
+        let session = tokio::spawn(DebugProxyClient::new(socket).bridge(debugger));
+
+        lldb.write_all(b"+$QStartNoAckMode#b0").await.unwrap();
+        let mut buf = [0; 20];
+        debugserver.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"+$QStartNoAckMode#b0");
+
+        debugserver.write_all(b"+$OK#9a").await.unwrap();
+        let mut buf = [0; 7];
+        lldb.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"+$OK#9a");
+
+        // Detaching ends the session even though debugserver's end stays open
+        drop(lldb);
+        session.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+// Jackson Coxson
+// Enabling JIT for an app, the way sideloading tools do it.
+// iOS only lets a process map memory executable once a debugger has attached to it, and
+// that stays allowed after the debugger detaches. So the app is launched suspended over
+// DVT, then debugserver attaches to it and detaches again, which lets it run.
+
+use crate::{dvt::process_control::ProcessControlClient, provider::IdeviceProvider, IdeviceError};
+
+/// How far ``enable_jit`` has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JitStep {
+    Launching,
+    Attaching { pid: u64 },
+    Detaching { pid: u64 },
+}
+
+/// Launches the app with JIT allowed, returning its pid.
+/// The app has to be signed with the ``get-task-allow`` entitlement, as development builds
+/// and sideloaded apps are.
+pub async fn enable_jit(
+    provider: &dyn IdeviceProvider,
+    bundle_id: &str,
+    mut on_step: impl FnMut(JitStep),
+) -> Result<u64, IdeviceError> {
+    on_step(JitStep::Launching);
+    let pid = {
+        let mut remote_server = crate::dvt::connect(provider).await?;
+        let mut process_control = ProcessControlClient::new(&mut remote_server).await?;
+        process_control
+            .launch_app(bundle_id, None, None, true, false)
+            .await?
+    };
+
+    on_step(JitStep::Attaching { pid });
+    let mut debugserver = super::connect(provider).await?;
+    debugserver.attach(pid).await?;
+
+    on_step(JitStep::Detaching { pid });
+    debugserver.detach().await?;
+    Ok(pid)
+}
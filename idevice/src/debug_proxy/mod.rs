@@ -0,0 +1,506 @@
+// Jackson Coxson
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Packets.html#Packets
+
+use log::{debug, warn};
+use std::fmt::Write;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use crate::{capabilities::Capabilities, runtime::Task, IdeviceError, ReadWrite};
+
+pub mod bridge;
+#[cfg(feature = "dvt")]
+pub mod jit;
+pub mod snapshot;
+
+pub const SERVICE_NAME: &str = "com.apple.internal.dt.remote.debugproxy";
+
+/// Connects to debugserver the way the device's version offers it, through lockdown before
+/// iOS 17 and through a software CoreDevice tunnel from then on
+pub async fn connect(
+    provider: &dyn crate::provider::IdeviceProvider,
+) -> Result<DebugProxyClient<Box<dyn ReadWrite>>, IdeviceError> {
+    let socket =
+        crate::capabilities::connect_developer_service(provider, Capabilities::debugserver_service)
+            .await?;
+    Ok(DebugProxyClient::new(socket))
+}
+
+pub struct DebugProxyClient<R: ReadWrite> {
+    pub socket: R,
+    pub noack_mode: bool,
+}
+
+pub struct DebugserverCommand {
+    pub name: String,
+    pub argv: Vec<String>,
+}
+
+/// What the launched process did while it was running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessEvent {
+    /// Bytes the process wrote to stdout or stderr, which debugserver doesn't tell apart
+    Output(Vec<u8>),
+    /// The process exited with a status
+    Exited(u8),
+    /// The process was killed by a signal
+    Terminated(u8),
+    /// The process stopped and can be continued
+    Stopped(StopInfo),
+}
+
+/// Why the process stopped, from debugserver's stop reply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StopInfo {
+    pub signal: u8,
+    /// The thread that stopped
+    pub thread: Option<u64>,
+    /// Such as ``exception``, ``breakpoint`` or ``signal``
+    pub reason: Option<String>,
+    pub description: Option<String>,
+    /// The Mach exception type, such as 1 for ``EXC_BAD_ACCESS``
+    pub exception_type: Option<u64>,
+    /// The exception's codes, for a bad access the kind of fault and the address
+    pub exception_data: Vec<u64>,
+}
+
+impl StopInfo {
+    /// Parses a ``T`` or ``S`` packet
+    fn parse(packet: &str) -> Option<Self> {
+        let mut res = Self {
+            signal: u8::from_str_radix(packet.get(1..3)?, 16).ok()?,
+            ..Default::default()
+        };
+        for pair in packet.get(3..)?.split(';') {
+            let Some((key, value)) = pair.split_once(':') else {
+                continue;
+            };
+            match key {
+                "thread" => res.thread = u64::from_str_radix(value, 16).ok(),
+                "reason" => res.reason = Some(value.to_string()),
+                "description" => {
+                    res.description =
+                        hex_decode(value).map(|d| String::from_utf8_lossy(&d).into_owned())
+                }
+                "metype" => res.exception_type = u64::from_str_radix(value, 16).ok(),
+                "medata" => {
+                    if let Ok(d) = u64::from_str_radix(value, 16) {
+                        res.exception_data.push(d);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(res)
+    }
+
+    /// Whether the process stopped because it crashed rather than to be debugged
+    pub fn is_exception(&self) -> bool {
+        self.reason.as_deref() == Some("exception") || self.exception_type.is_some()
+    }
+}
+
+/// Runs the process for ``spawn_output``, ending with the client and how the process stopped
+pub type OutputTask<R> = Task<Result<(DebugProxyClient<R>, ProcessEvent), IdeviceError>>;
+
+/// How much output ``spawn_output`` buffers before waiting for it to be read
+const OUTPUT_BUFFER: usize = 64 * 1024;
+
+impl DebugserverCommand {
+    pub fn new(name: String, argv: Vec<String>) -> Self {
+        Self { name, argv }
+    }
+}
+
+impl<R: ReadWrite> DebugProxyClient<R> {
+    pub fn new(socket: R) -> Self {
+        Self {
+            socket,
+            noack_mode: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.socket
+    }
+
+    pub async fn send_command(
+        &mut self,
+        command: DebugserverCommand,
+    ) -> Result<Option<String>, IdeviceError> {
+        // Hex-encode the arguments
+        let hex_args = command
+            .argv
+            .iter()
+            .map(|arg| hex_encode(arg.as_bytes()))
+            .collect::<Vec<String>>()
+            .join("");
+
+        // Construct the packet data (command + hex-encoded arguments)
+        let packet_data = format!("{}{}", command.name, hex_args);
+        self.send_packet(&packet_data).await?;
+
+        // Read the response
+        let response = self.read_response().await?;
+        Ok(response)
+    }
+
+    /// Sends a packet and returns its response, failing on an ``E`` error response
+    async fn query(&mut self, data: &str) -> Result<String, IdeviceError> {
+        self.send_packet(data).await?;
+        match self.read_response().await? {
+            Some(r) if r.starts_with('E') && r.len() == 3 => Err(IdeviceError::DebugserverError(r)),
+            Some(r) => Ok(r),
+            None => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Frames and sends a packet without waiting for a response
+    async fn send_packet(&mut self, data: &str) -> Result<(), IdeviceError> {
+        let packet = format!("${}#{}", data, calculate_checksum(data));
+        debug!("Sending packet: {}", packet);
+        self.socket.write_all(packet.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Sends a packet and fails unless debugserver answers OK
+    async fn send_expecting_ok(&mut self, data: &str) -> Result<(), IdeviceError> {
+        self.send_packet(data).await?;
+        match self.read_response().await? {
+            Some(r) if r == "OK" => Ok(()),
+            Some(r) => Err(IdeviceError::DebugserverError(r)),
+            None => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Launches a process with console forwarding, so its output arrives as ``O`` packets.
+    /// The first argument is the path of the executable. The process starts stopped, call
+    /// ``continue_process`` to run it.
+    pub async fn launch(&mut self, argv: &[String]) -> Result<(), IdeviceError> {
+        if argv.is_empty() {
+            return Err(IdeviceError::InvalidArgument);
+        }
+        let mut packet = String::from("A");
+        for (i, arg) in argv.iter().enumerate() {
+            if i > 0 {
+                packet.push(',');
+            }
+            let arg = hex_encode(arg.as_bytes());
+            let _ = write!(packet, "{},{},{}", arg.len(), i, arg);
+        }
+        self.send_expecting_ok(&packet).await?;
+        self.send_expecting_ok("qLaunchSuccess").await
+    }
+
+    /// Resumes the process. Its output and how it stops come from ``next_event``.
+    pub async fn continue_process(&mut self) -> Result<(), IdeviceError> {
+        self.send_packet("c").await
+    }
+
+    /// Attaches to a running process, which stops it
+    pub async fn attach(&mut self, pid: u64) -> Result<StopInfo, IdeviceError> {
+        let reply = self.query(&format!("vAttach;{pid:x}")).await?;
+        match StopInfo::parse(&reply) {
+            Some(stop) if reply.starts_with(['T', 'S']) => Ok(stop),
+            _ => Err(IdeviceError::DebugserverError(reply)),
+        }
+    }
+
+    /// Detaches from the process, letting it run on
+    pub async fn detach(&mut self) -> Result<(), IdeviceError> {
+        self.send_expecting_ok("D").await
+    }
+
+    /// Waits for the running process to write something or stop
+    pub async fn next_event(&mut self) -> Result<ProcessEvent, IdeviceError> {
+        loop {
+            let packet = self.read_packet().await?;
+            let mut chars = packet.chars();
+            let kind = chars.next();
+            let rest = chars.as_str();
+            let signal = || {
+                rest.get(..2)
+                    .and_then(|s| u8::from_str_radix(s, 16).ok())
+                    .ok_or(IdeviceError::UnexpectedResponse)
+            };
+            return match kind {
+                Some('O') => Ok(ProcessEvent::Output(
+                    hex_decode(rest).ok_or(IdeviceError::UnexpectedResponse)?,
+                )),
+                Some('W') => Ok(ProcessEvent::Exited(signal()?)),
+                Some('X') => Ok(ProcessEvent::Terminated(signal()?)),
+                Some('T') | Some('S') => Ok(ProcessEvent::Stopped(
+                    StopInfo::parse(&packet).ok_or(IdeviceError::UnexpectedResponse)?,
+                )),
+                Some('E') => Err(IdeviceError::DebugserverError(packet)),
+                _ => {
+                    warn!("Skipping unexpected packet while running: {packet}");
+                    continue;
+                }
+            };
+        }
+    }
+
+    /// Continues the process and hands its output over as a byte stream.
+    /// The task ends when the process exits or stops, returning the client and how it
+    /// stopped. Output that isn't read holds the process up once the buffer fills.
+    pub fn spawn_output(mut self) -> (DuplexStream, OutputTask<R>)
+    where
+        R: 'static,
+    {
+        let (reader, mut writer) = tokio::io::duplex(OUTPUT_BUFFER);
+        let task = crate::runtime::spawn(async move {
+            self.continue_process().await?;
+            loop {
+                match self.next_event().await? {
+                    ProcessEvent::Output(o) => {
+                        // Keep draining the process even if nobody reads its output
+                        let _ = writer.write_all(&o).await;
+                    }
+                    e => return Ok((self, e)),
+                }
+            }
+        });
+        (reader, task)
+    }
+
+    /// Reads the next packet, skipping acks, and acks it if needed
+    async fn read_packet(&mut self) -> Result<String, IdeviceError> {
+        let mut received_char = [0u8; 1];
+        loop {
+            self.socket.read_exact(&mut received_char).await?;
+            match received_char[0] {
+                b'$' => break,
+                b'+' => continue,
+                c => debug!("Skipping {c:#x} between packets"),
+            }
+        }
+        self.read_packet_data().await
+    }
+
+    /// Reads a packet's data after the ``$``, then its checksum
+    async fn read_packet_data(&mut self) -> Result<String, IdeviceError> {
+        let mut buffer = Vec::new();
+        let mut received_char = [0u8; 1];
+        loop {
+            self.socket.read_exact(&mut received_char).await?;
+            if received_char[0] == b'#' {
+                break;
+            }
+            buffer.push(received_char[0]);
+            // A peer that never sends ``#`` would otherwise grow the buffer forever
+            crate::util::check_message_size(buffer.len())?;
+        }
+        let mut checksum = [0u8; 2];
+        self.socket.read_exact(&mut checksum).await?;
+
+        if !self.noack_mode {
+            self.send_ack().await?;
+        }
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    pub async fn read_response(&mut self) -> Result<Option<String>, IdeviceError> {
+        let mut received_char = [0u8; 1];
+
+        if !self.noack_mode {
+            self.socket.read_exact(&mut received_char).await?;
+            if received_char[0] != b'+' {
+                debug!("No + ack");
+                return Ok(None);
+            }
+        }
+
+        self.socket.read_exact(&mut received_char).await?;
+        if received_char[0] != b'$' {
+            debug!("No $ response");
+            return Ok(None);
+        }
+
+        let response = self.read_packet_data().await?;
+        Ok(Some(response))
+    }
+
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<(), IdeviceError> {
+        self.socket.write_all(bytes).await?;
+        Ok(())
+    }
+
+    pub async fn read(&mut self, len: usize) -> Result<String, IdeviceError> {
+        let mut buf = vec![0; len];
+        let r = self.socket.read(&mut buf).await?;
+
+        Ok(String::from_utf8_lossy(&buf[..r]).to_string())
+    }
+
+    pub async fn set_argv(&mut self, argv: Vec<String>) -> Result<String, IdeviceError> {
+        if argv.is_empty() {
+            return Err(IdeviceError::InvalidArgument);
+        }
+
+        // Calculate the total length of the packet
+        let mut pkt_len = 0;
+        for (i, arg) in argv.iter().enumerate() {
+            let prefix = format!(",{},{},", arg.len() * 2, i);
+            pkt_len += prefix.len() + arg.len() * 2;
+        }
+
+        // Allocate and initialize the packet
+        let mut pkt = vec![0u8; pkt_len + 1];
+        let mut pktp = 0;
+
+        for (i, arg) in argv.iter().enumerate() {
+            let prefix = format!(",{},{},", arg.len() * 2, i);
+            let prefix_bytes = prefix.as_bytes();
+
+            // Copy prefix to the packet
+            pkt[pktp..pktp + prefix_bytes.len()].copy_from_slice(prefix_bytes);
+            pktp += prefix_bytes.len();
+
+            // Hex encode the argument
+            for byte in arg.bytes() {
+                let hex = format!("{:02X}", byte);
+                pkt[pktp..pktp + 2].copy_from_slice(hex.as_bytes());
+                pktp += 2;
+            }
+        }
+
+        // Set the first byte of the packet
+        pkt[0] = b'A';
+
+        // Simulate sending the command and receiving a response
+        self.send_raw(&pkt).await?;
+        let response = self.read(16).await?;
+
+        Ok(response)
+    }
+
+    pub async fn send_ack(&mut self) -> Result<(), IdeviceError> {
+        self.socket.write_all(b"+").await?;
+        Ok(())
+    }
+
+    pub async fn send_noack(&mut self) -> Result<(), IdeviceError> {
+        self.socket.write_all(b"-").await?;
+        Ok(())
+    }
+
+    pub fn set_ack_mode(&mut self, enabled: bool) {
+        self.noack_mode = !enabled;
+    }
+}
+
+fn calculate_checksum(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |acc, byte| acc.wrapping_add(byte));
+    format!("{:02x}", checksum)
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02X}");
+        output
+    })
+}
+
+impl From<String> for DebugserverCommand {
+    fn from(s: String) -> Self {
+        // Split string into command and arguments
+        let mut split = s.split_whitespace();
+        let command = split.next().unwrap_or("").to_string();
+        let arguments: Vec<String> = split.map(|s| s.to_string()).collect();
+        Self::new(command, arguments)
+    }
+}
+impl From<&str> for DebugserverCommand {
+    fn from(s: &str) -> DebugserverCommand {
+        s.to_string().into()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub fn packet(data: &str) -> String {
+        format!("${}#{}", data, calculate_checksum(data))
+    }
+
+    /// Reads one packet from the client, acking it
+    pub async fn expect_packet(server: &mut DuplexStream, expected: &str) {
+        let mut buf = vec![0; expected.len() + 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), packet(expected));
+        server.write_all(b"+").await.unwrap();
+    }
+
+    pub async fn expect_ack(server: &mut DuplexStream) {
+        let mut ack = [0u8];
+        server.read_exact(&mut ack).await.unwrap();
+        assert_eq!(ack[0], b'+');
+    }
+
+    #[tokio::test]
+    async fn output_is_streamed() {
+        let (socket, mut server) = tokio::io::duplex(4096);
+        let debugserver = tokio::spawn(async move {
+            expect_packet(&mut server, "A18,0,2F62696E2F6563686F,4,1,6869").await;
+            server.write_all(packet("OK").as_bytes()).await.unwrap();
+            expect_ack(&mut server).await;
+            expect_packet(&mut server, "qLaunchSuccess").await;
+            server.write_all(packet("OK").as_bytes()).await.unwrap();
+            expect_ack(&mut server).await;
+
+            expect_packet(&mut server, "c").await;
+            for data in ["O68690a", "O6279650a", "W00"] {
+                server.write_all(packet(data).as_bytes()).await.unwrap();
+                expect_ack(&mut server).await;
+            }
+        });
+
+        let mut client = DebugProxyClient::new(socket);
+        client
+            .launch(&["/bin/echo".to_string(), "hi".to_string()])
+            .await
+            .unwrap();
+        let (mut output, task) = client.spawn_output();
+        let mut text = String::new();
+        output.read_to_string(&mut text).await.unwrap();
+        assert_eq!(text, "hi\nbye\n");
+
+        let (_client, event) = task.await.unwrap().unwrap();
+        assert_eq!(event, ProcessEvent::Exited(0));
+        debugserver.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn attach_and_detach() {
+        let (socket, mut server) = tokio::io::duplex(4096);
+        let debugserver = tokio::spawn(async move {
+            expect_packet(&mut server, "vAttach;4d2").await;
+            server
+                .write_all(packet("T11thread:1f03;reason:signal;").as_bytes())
+                .await
+                .unwrap();
+            expect_ack(&mut server).await;
+            expect_packet(&mut server, "D").await;
+            server.write_all(packet("OK").as_bytes()).await.unwrap();
+            expect_ack(&mut server).await;
+        });
+
+        let mut client = DebugProxyClient::new(socket);
+        let stop = client.attach(1234).await.unwrap();
+        assert_eq!(stop.signal, 0x11);
+        assert_eq!(stop.thread, Some(0x1f03));
+        client.detach().await.unwrap();
+        debugserver.await.unwrap();
+    }
+}
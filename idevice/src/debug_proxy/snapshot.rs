@@ -0,0 +1,391 @@
+// Jackson Coxson
+// Crash snapshots of a stopped process: the stop reason, every thread's registers and
+// chosen pieces of memory, written out as a JSON report with the memory as raw dumps.
+
+use std::{collections::BTreeMap, path::Path};
+
+use log::{debug, warn};
+use serde_json::{json, Value};
+
+use super::{hex_decode, DebugProxyClient, StopInfo};
+use crate::{IdeviceError, ReadWrite};
+
+/// The most memory asked for in one ``m`` packet
+const MEMORY_CHUNK: u64 = 0x400;
+
+/// A register as debugserver describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterInfo {
+    pub number: u32,
+    pub name: String,
+    pub bitsize: u32,
+    /// Offset of the register in a ``g`` response, in bytes
+    pub offset: usize,
+    /// The role the register plays, such as ``pc``, ``sp`` or ``fp``
+    pub generic: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadSnapshot {
+    pub id: u64,
+    /// Registers up to 64 bits wide, by name
+    pub registers: BTreeMap<String, u64>,
+    pub pc: Option<u64>,
+    pub sp: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDump {
+    pub address: u64,
+    /// Cut short where the memory stopped being readable
+    pub data: Vec<u8>,
+}
+
+/// What to capture besides registers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotOptions {
+    /// Bytes to dump from each thread's stack pointer upwards
+    pub stack_bytes: u64,
+    /// Other memory to dump, as address and length
+    pub regions: Vec<(u64, u64)>,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            stack_bytes: 0x1000,
+            regions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashSnapshot {
+    pub stop: StopInfo,
+    pub threads: Vec<ThreadSnapshot>,
+    pub memory: Vec<MemoryDump>,
+}
+
+impl<R: ReadWrite> DebugProxyClient<R> {
+    /// Describes the registers, asking for them in order until debugserver runs out
+    pub async fn register_info(&mut self) -> Result<Vec<RegisterInfo>, IdeviceError> {
+        let mut registers = Vec::new();
+        let mut offset = 0;
+        for number in 0.. {
+            let res = match self.query(&format!("qRegisterInfo{number:x}")).await {
+                Ok(r) => r,
+                Err(IdeviceError::DebugserverError(_)) => break,
+                Err(e) => return Err(e),
+            };
+            let fields: BTreeMap<&str, &str> =
+                res.split(';').filter_map(|f| f.split_once(':')).collect();
+            let (Some(name), Some(bitsize)) = (
+                fields.get("name"),
+                fields.get("bitsize").and_then(|b| b.parse().ok()),
+            ) else {
+                warn!("Register {number} has no name or size: {res}");
+                return Err(IdeviceError::UnexpectedResponse);
+            };
+            let info = RegisterInfo {
+                number,
+                name: name.to_string(),
+                bitsize,
+                offset: fields
+                    .get("offset")
+                    .and_then(|o| o.parse().ok())
+                    .unwrap_or(offset),
+                generic: fields.get("generic").map(|g| g.to_string()),
+            };
+            offset = info.offset + bitsize as usize / 8;
+            registers.push(info);
+        }
+        Ok(registers)
+    }
+
+    /// The IDs of the process's threads
+    pub async fn thread_ids(&mut self) -> Result<Vec<u64>, IdeviceError> {
+        let mut ids = Vec::new();
+        let mut res = self.query("qfThreadInfo").await?;
+        while let Some(list) = res.strip_prefix('m') {
+            for id in list.split(',') {
+                match u64::from_str_radix(id, 16) {
+                    Ok(id) => ids.push(id),
+                    Err(_) => return Err(IdeviceError::UnexpectedResponse),
+                }
+            }
+            res = self.query("qsThreadInfo").await?;
+        }
+        Ok(ids)
+    }
+
+    /// Reads a thread's registers, leaving out ones wider than 64 bits
+    pub async fn read_registers(
+        &mut self,
+        thread: u64,
+        registers: &[RegisterInfo],
+    ) -> Result<ThreadSnapshot, IdeviceError> {
+        self.send_expecting_ok(&format!("Hg{thread:x}")).await?;
+        let values = self.query("g").await?;
+        let values = hex_decode(&values).ok_or(IdeviceError::UnexpectedResponse)?;
+
+        let mut snapshot = ThreadSnapshot {
+            id: thread,
+            registers: BTreeMap::new(),
+            pc: None,
+            sp: None,
+        };
+        for register in registers {
+            let size = register.bitsize as usize / 8;
+            if size == 0 || size > 8 {
+                continue;
+            }
+            let Some(bytes) = values.get(register.offset..register.offset + size) else {
+                continue;
+            };
+            // Registers come in the target's byte order, little endian on iOS
+            let mut value = [0u8; 8];
+            value[..size].copy_from_slice(bytes);
+            let value = u64::from_le_bytes(value);
+            match register.generic.as_deref() {
+                Some("pc") => snapshot.pc = Some(value),
+                Some("sp") => snapshot.sp = Some(value),
+                _ => {}
+            }
+            snapshot.registers.insert(register.name.clone(), value);
+        }
+        Ok(snapshot)
+    }
+
+    /// Reads process memory, stopping early at the first part that can't be read
+    pub async fn read_memory(
+        &mut self,
+        address: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, IdeviceError> {
+        let mut data = Vec::new();
+        while (data.len() as u64) < length {
+            let at = address + data.len() as u64;
+            let len = (length - data.len() as u64).min(MEMORY_CHUNK);
+            let chunk = match self.query(&format!("m{at:x},{len:x}")).await {
+                Ok(c) => hex_decode(&c).ok_or(IdeviceError::UnexpectedResponse)?,
+                Err(IdeviceError::DebugserverError(e)) => {
+                    debug!("Memory at {at:#x} isn't readable: {e}");
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// Captures the state of a stopped process, for a stop reported by ``next_event``.
+    /// The process stays stopped.
+    pub async fn capture_snapshot(
+        &mut self,
+        stop: &StopInfo,
+        options: &SnapshotOptions,
+    ) -> Result<CrashSnapshot, IdeviceError> {
+        let registers = self.register_info().await?;
+        let mut threads = Vec::new();
+        for id in self.thread_ids().await? {
+            threads.push(self.read_registers(id, &registers).await?);
+        }
+
+        let mut regions = options.regions.clone();
+        if options.stack_bytes > 0 {
+            regions.extend(
+                threads
+                    .iter()
+                    .filter_map(|t| Some((t.sp?, options.stack_bytes))),
+            );
+        }
+        let mut memory = Vec::new();
+        for (address, length) in regions {
+            let data = self.read_memory(address, length).await?;
+            if !data.is_empty() {
+                memory.push(MemoryDump { address, data });
+            }
+        }
+
+        Ok(CrashSnapshot {
+            stop: stop.clone(),
+            threads,
+            memory,
+        })
+    }
+}
+
+impl CrashSnapshot {
+    /// The report, with each memory dump named by the file ``write_to`` puts it in
+    pub fn to_json(&self) -> Value {
+        let threads: Vec<Value> = self
+            .threads
+            .iter()
+            .map(|t| {
+                let registers: serde_json::Map<String, Value> = t
+                    .registers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), format!("{value:#018x}").into()))
+                    .collect();
+                json!({
+                    "id": t.id,
+                    "crashed": Some(t.id) == self.stop.thread,
+                    "pc": t.pc.map(|p| format!("{p:#x}")),
+                    "sp": t.sp.map(|s| format!("{s:#x}")),
+                    "registers": registers,
+                })
+            })
+            .collect();
+        let memory: Vec<Value> = self
+            .memory
+            .iter()
+            .map(|m| {
+                json!({
+                    "address": format!("{:#x}", m.address),
+                    "length": m.data.len(),
+                    "file": m.file_name(),
+                })
+            })
+            .collect();
+
+        json!({
+            "stop": {
+                "signal": self.stop.signal,
+                "thread": self.stop.thread,
+                "reason": self.stop.reason,
+                "description": self.stop.description,
+                "exception_type": self.stop.exception_type,
+                "exception_data": self
+                    .stop
+                    .exception_data
+                    .iter()
+                    .map(|d| format!("{d:#x}"))
+                    .collect::<Vec<_>>(),
+            },
+            "threads": threads,
+            "memory": memory,
+        })
+    }
+
+    /// Writes ``report.json`` and a ``.bin`` file per memory dump into a directory
+    pub async fn write_to(&self, dir: impl AsRef<Path>) -> Result<(), IdeviceError> {
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(
+            dir.join("report.json"),
+            serde_json::to_vec_pretty(&self.to_json())?,
+        )
+        .await?;
+        for dump in &self.memory {
+            tokio::fs::write(dir.join(dump.file_name()), &dump.data).await?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryDump {
+    fn file_name(&self) -> String {
+        format!("memory-{:x}.bin", self.address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    use super::*;
+    use crate::debug_proxy::{
+        tests::{expect_ack, expect_packet, packet},
+        ProcessEvent,
+    };
+
+    async fn answer(server: &mut DuplexStream, request: &str, response: &str) {
+        expect_packet(server, request).await;
+        server.write_all(packet(response).as_bytes()).await.unwrap();
+        expect_ack(server).await;
+    }
+
+    #[tokio::test]
+    async fn crashes_are_captured() {
+        let registers: String = [0x1122u64, 0x1_6fdf_f000, 0x1_0000_4000]
+            .iter()
+            .flat_map(|r| r.to_le_bytes())
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        let (socket, mut server) = tokio::io::duplex(4096);
+        let debugserver = tokio::spawn(async move {
+            expect_packet(&mut server, "c").await;
+            let stop =
+                "T0bthread:303;reason:exception;description:4241443f;metype:1;medata:1;medata:10;";
+            server.write_all(packet(stop).as_bytes()).await.unwrap();
+            expect_ack(&mut server).await;
+
+            answer(
+                &mut server,
+                "qRegisterInfo0",
+                "name:x0;bitsize:64;offset:0;",
+            )
+            .await;
+            answer(
+                &mut server,
+                "qRegisterInfo1",
+                "name:sp;bitsize:64;offset:8;generic:sp;",
+            )
+            .await;
+            answer(
+                &mut server,
+                "qRegisterInfo2",
+                "name:pc;bitsize:64;offset:16;generic:pc;",
+            )
+            .await;
+            answer(
+                &mut server,
+                "qRegisterInfo3",
+                "name:v0;bitsize:128;offset:24;",
+            )
+            .await;
+            answer(&mut server, "qRegisterInfo4", "E45").await;
+            answer(&mut server, "qfThreadInfo", "m303").await;
+            answer(&mut server, "qsThreadInfo", "l").await;
+            answer(&mut server, "Hg303", "OK").await;
+            answer(&mut server, "g", &registers).await;
+            answer(&mut server, "m16fdff000,8", "0102030405060708").await;
+        });
+
+        let mut client = DebugProxyClient::new(socket);
+        client.continue_process().await.unwrap();
+        let ProcessEvent::Stopped(stop) = client.next_event().await.unwrap() else {
+            panic!("expected a stop");
+        };
+        assert!(stop.is_exception());
+        assert_eq!(stop.thread, Some(0x303));
+        assert_eq!(stop.description.as_deref(), Some("BAD?"));
+        assert_eq!(stop.exception_data, [1, 0x10]);
+
+        let options = SnapshotOptions {
+            stack_bytes: 8,
+            regions: Vec::new(),
+        };
+        let snapshot = client.capture_snapshot(&stop, &options).await.unwrap();
+        debugserver.await.unwrap();
+
+        let thread = &snapshot.threads[0];
+        assert_eq!(thread.pc, Some(0x1_0000_4000));
+        // The vector register is too wide to keep
+        assert_eq!(thread.registers.len(), 3);
+        assert_eq!(snapshot.memory[0].data, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let report = snapshot.to_json();
+        assert_eq!(report["threads"][0]["crashed"], true);
+        assert_eq!(
+            report["threads"][0]["registers"]["x0"],
+            "0x0000000000001122"
+        );
+        assert_eq!(report["memory"][0]["file"], "memory-16fdff000.bin");
+    }
+}
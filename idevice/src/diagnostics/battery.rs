@@ -0,0 +1,163 @@
+//! Battery health, merged from the sources diagnostics_relay has.
+//!
+//! None of them is complete on its own. The ``AppleSmartBattery`` IORegistry entry has the
+//! most, but newer versions report ``MaxCapacity`` as a percentage and move the raw values
+//! around. The GasGauge domain has capacities and cycles but no temperature, and
+//! MobileGestalt, which has the serial number, stopped answering on iOS 17.
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::{DiagnosticsAction, DiagnosticsClient, DiagnosticsDomain};
+use crate::IdeviceError;
+
+/// What's known about the battery. Fields no source reported are ``None``.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatteryHealth {
+    /// What the battery held when new, in mAh
+    pub design_capacity: Option<u64>,
+    /// What the battery holds now when fully charged, in mAh
+    pub max_capacity: Option<u64>,
+    pub cycle_count: Option<u64>,
+    /// In degrees Celsius
+    pub temperature: Option<f64>,
+    pub serial: Option<String>,
+}
+
+impl BatteryHealth {
+    /// The maximum capacity as a percentage of the design capacity, like Settings shows
+    pub fn health_percent(&self) -> Option<f64> {
+        match (self.max_capacity, self.design_capacity) {
+            (Some(max), Some(design)) if design > 0 => Some(max as f64 * 100.0 / design as f64),
+            _ => None,
+        }
+    }
+
+    /// Merges the sources, preferring IORegistry over GasGauge over MobileGestalt
+    pub fn from_sources(
+        io_registry: Option<&plist::Dictionary>,
+        gas_gauge: Option<&plist::Dictionary>,
+        gestalt: Option<&plist::Dictionary>,
+    ) -> Self {
+        let battery_data = io_registry
+            .and_then(|r| r.get("BatteryData"))
+            .and_then(|d| d.as_dictionary());
+        let sources = [io_registry, battery_data, gas_gauge, gestalt];
+        let first_int = |keys: &[&str]| {
+            sources.iter().flatten().find_map(|source| {
+                keys.iter()
+                    .find_map(|key| source.get(key).and_then(|v| v.as_unsigned_integer()))
+            })
+        };
+
+        Self {
+            design_capacity: first_int(&["DesignCapacity"]),
+            // MaxCapacity is a percentage on iOS 10 and later
+            max_capacity: first_int(&[
+                "AppleRawMaxCapacity",
+                "NominalChargeCapacity",
+                "FullChargeCapacity",
+            ]),
+            cycle_count: first_int(&["CycleCount"]),
+            // Reported in hundredths of a degree
+            temperature: io_registry
+                .and_then(|r| r.get("Temperature"))
+                .and_then(|t| t.as_signed_integer())
+                .map(|t| t as f64 / 100.0),
+            serial: sources.iter().flatten().find_map(|source| {
+                ["Serial", "BatterySerialNumber"].iter().find_map(|key| {
+                    source
+                        .get(key)
+                        .and_then(|v| v.as_string())
+                        .map(|s| s.to_string())
+                })
+            }),
+        }
+    }
+}
+
+/// Reads every source and merges them. Sources the device refuses are skipped.
+pub async fn health_report(client: &mut DiagnosticsClient) -> Result<BatteryHealth, IdeviceError> {
+    let io_registry = match client.query_io_registry_class("AppleSmartBattery").await {
+        Ok(r) => Some(r),
+        Err(e) => {
+            debug!("No AppleSmartBattery entry: {e:?}");
+            None
+        }
+    };
+    let gas_gauge = match client
+        .request_diagnostics(DiagnosticsAction::Domain(DiagnosticsDomain::GasGauge))
+        .await
+    {
+        Ok(plist::Value::Dictionary(g)) => g
+            .get("GasGauge")
+            .and_then(|g| g.as_dictionary())
+            .cloned()
+            .or(Some(g)),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("No GasGauge diagnostics: {e:?}");
+            None
+        }
+    };
+    let gestalt = match client.query_mobile_gestalt(&["BatterySerialNumber"]).await {
+        Ok(g) => Some(g),
+        Err(e) => {
+            debug!("MobileGestalt didn't answer: {e:?}");
+            None
+        }
+    };
+
+    if io_registry.is_none() && gas_gauge.is_none() && gestalt.is_none() {
+        return Err(IdeviceError::DiagnosticsError(
+            "no battery information available".to_string(),
+        ));
+    }
+    Ok(BatteryHealth::from_sources(
+        io_registry.as_ref(),
+        gas_gauge.as_ref(),
+        gestalt.as_ref(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_are_merged() {
+        let mut io_registry = plist::Dictionary::new();
+        io_registry.insert("MaxCapacity".into(), 100u64.into());
+        io_registry.insert("AppleRawMaxCapacity".into(), 2700u64.into());
+        io_registry.insert("Temperature".into(), 2980i64.into());
+        let mut battery_data = plist::Dictionary::new();
+        battery_data.insert("DesignCapacity".into(), 3000u64.into());
+        io_registry.insert("BatteryData".into(), battery_data.into());
+
+        let mut gas_gauge = plist::Dictionary::new();
+        gas_gauge.insert("CycleCount".into(), 412u64.into());
+        gas_gauge.insert("DesignCapacity".into(), 2900u64.into());
+
+        let mut gestalt = plist::Dictionary::new();
+        gestalt.insert("BatterySerialNumber".into(), "F5D1234".into());
+
+        let health =
+            BatteryHealth::from_sources(Some(&io_registry), Some(&gas_gauge), Some(&gestalt));
+        assert_eq!(
+            health,
+            BatteryHealth {
+                design_capacity: Some(3000),
+                max_capacity: Some(2700),
+                cycle_count: Some(412),
+                temperature: Some(29.8),
+                serial: Some("F5D1234".to_string()),
+            }
+        );
+        assert_eq!(health.health_percent(), Some(90.0));
+
+        let health = BatteryHealth::from_sources(None, Some(&gas_gauge), None);
+        assert_eq!(health.design_capacity, Some(2900));
+        assert_eq!(health.max_capacity, None);
+        assert_eq!(health.health_percent(), None);
+    }
+}
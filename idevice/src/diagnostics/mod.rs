@@ -2,10 +2,13 @@
 //! 
 //! This module provides functionality to retrieve diagnostic information from iOS devices.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
+use crate::{provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
+use log::warn;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::collections::HashMap;
 
+pub mod battery;
+
 const DIAGNOSTICS_SERVICE_NAME: &str = "com.apple.mobile.diagnostics_relay";
 
 /// Diagnostics action types
@@ -51,19 +54,45 @@ impl DiagnosticsDomain {
     }
 }
 
+/// Wi-Fi connection status as reported by the Wi-Fi driver
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WifiStatus {
+    /// BSD interface name, usually ``en0``
+    pub interface: Option<String>,
+    pub ssid: Option<String>,
+    pub bssid: Option<String>,
+    /// Signal strength in dBm
+    pub rssi: Option<i64>,
+    /// Noise floor in dBm
+    pub noise: Option<i64>,
+    pub channel: Option<u64>,
+    pub mac_address: Option<String>,
+    pub ip_addresses: Vec<std::net::IpAddr>,
+}
+
 /// Diagnostics client for retrieving diagnostic information from iOS devices
 pub struct DiagnosticsClient {
-    socket: tokio::net::TcpStream,
+    socket: Box<dyn ReadWrite>,
+}
+
+impl IdeviceService for DiagnosticsClient {
+    fn service_name() -> &'static str {
+        DIAGNOSTICS_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl DiagnosticsClient {
-    /// Connect to the Diagnostics service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(DIAGNOSTICS_SERVICE_NAME).await?;
-        
-        Ok(Self {
-            socket: service.socket,
-        })
+    /// Create a client over an existing connection to diagnostics_relay
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        Self { socket }
     }
 
     /// Request diagnostics information
@@ -116,7 +145,7 @@ impl DiagnosticsClient {
         }
         
         // If no diagnostics data, return the whole response
-        Ok(response)
+        Ok(plist::Value::Dictionary(response))
     }
 
     /// Get device information
@@ -147,6 +176,89 @@ impl DiagnosticsClient {
         self.request_diagnostics(DiagnosticsAction::NetworkInterfaces).await
     }
 
+    /// Query MobileGestalt keys
+    pub async fn query_mobile_gestalt(&mut self, keys: &[&str]) -> Result<plist::Dictionary, IdeviceError> {
+        let mut dict = plist::Dictionary::new();
+        dict.insert("Request".into(), "MobileGestalt".into());
+        dict.insert(
+            "MobileGestaltKeys".into(),
+            plist::Value::Array(keys.iter().map(|k| (*k).into()).collect()),
+        );
+
+        let response = self.send_request(dict).await?;
+        match response
+            .get("MobileGestalt")
+            .and_then(|m| m.as_dictionary())
+        {
+            Some(m) => Ok(m.clone()),
+            None => Err(IdeviceError::DiagnosticsError("MobileGestalt response missing".to_string())),
+        }
+    }
+
+    /// Query an I/O Registry entry by class name
+    pub async fn query_io_registry_class(&mut self, entry_class: &str) -> Result<plist::Dictionary, IdeviceError> {
+        let mut dict = plist::Dictionary::new();
+        dict.insert("Request".into(), "IORegistry".into());
+        dict.insert("EntryClass".into(), entry_class.into());
+
+        let response = self.send_request(dict).await?;
+        match response
+            .get("IORegistry")
+            .and_then(|m| m.as_dictionary())
+        {
+            Some(m) => Ok(m.clone()),
+            None => Err(IdeviceError::DiagnosticsError("IORegistry response missing".to_string())),
+        }
+    }
+
+    /// Get the current Wi-Fi connection status
+    ///
+    /// Fields the device doesn't report (for example while disassociated) are left as ``None``.
+    pub async fn get_wifi_status(&mut self) -> Result<WifiStatus, IdeviceError> {
+        let interface = self.query_io_registry_class("IO80211Interface").await?;
+        
+        let mut status = WifiStatus {
+            interface: interface.get("BSD Name").and_then(|v| v.as_string()).map(|s| s.to_string()),
+            ssid: interface.get("IO80211SSID").and_then(plist_string),
+            bssid: interface.get("IO80211BSSID").and_then(plist_mac),
+            rssi: interface.get("RSSI").and_then(|v| v.as_signed_integer()),
+            noise: interface.get("NOISE").and_then(|v| v.as_signed_integer()),
+            channel: interface.get("IO80211Channel").and_then(|v| v.as_unsigned_integer()),
+            mac_address: interface.get("IOMACAddress").and_then(plist_mac),
+            ip_addresses: Vec::new(),
+        };
+        
+        // MobileGestalt is refused from iOS 17.4, so the address is left out when it fails
+        if status.mac_address.is_none() {
+            match self.query_mobile_gestalt(&["WifiAddress"]).await {
+                Ok(values) => {
+                    status.mac_address = values
+                        .get("WifiAddress")
+                        .and_then(|v| v.as_string())
+                        .map(|s| s.to_string());
+                }
+                Err(e) => warn!("Couldn't read the Wi-Fi address from MobileGestalt: {e:?}"),
+            }
+        }
+        
+        if let Some(name) = &status.interface {
+            let interfaces = self.get_network_interfaces().await?;
+            if let Some(addresses) = interfaces
+                .as_dictionary()
+                .and_then(|d| d.get(name))
+                .and_then(|a| a.as_array())
+            {
+                status.ip_addresses = addresses
+                    .iter()
+                    .filter_map(|a| a.as_string())
+                    .filter_map(|a| a.parse().ok())
+                    .collect();
+            }
+        }
+        
+        Ok(status)
+    }
+
     /// Restart the device
     pub async fn restart(&mut self) -> Result<(), IdeviceError> {
         self.request_diagnostics(DiagnosticsAction::Restart).await?;
@@ -166,9 +278,29 @@ impl DiagnosticsClient {
     }
 
     // Helper methods
+    async fn send_request(&mut self, dict: plist::Dictionary) -> Result<plist::Dictionary, IdeviceError> {
+        self.send_plist(&dict).await?;
+        let response = self.read_plist().await?;
+        
+        match response.get("Status").and_then(|s| s.as_string()) {
+            Some("Success") => {}
+            _ => {
+                let error_msg = response.get("Error")
+                    .and_then(|e| e.as_string())
+                    .unwrap_or("Unknown error");
+                return Err(IdeviceError::DiagnosticsError(error_msg.to_string()));
+            }
+        }
+        
+        match response.get("Diagnostics").and_then(|d| d.as_dictionary()) {
+            Some(d) => Ok(d.clone()),
+            None => Ok(response),
+        }
+    }
+
     async fn send_plist(&mut self, dict: &plist::Dictionary) -> Result<(), IdeviceError> {
-        let xml = plist::to_format_xml(dict)?;
-        let xml_bytes = xml.into_bytes();
+        let mut xml_bytes = Vec::new();
+        plist::to_writer_xml(&mut xml_bytes, dict)?;
         
         // Send the length as a 32-bit big-endian integer
         let len = (xml_bytes.len() as u32).to_be_bytes();
@@ -187,7 +319,7 @@ impl DiagnosticsClient {
         let len = u32::from_be_bytes(len_buf) as usize;
         
         // Read the XML data
-        let mut data = vec![0u8; len];
+        let mut data = vec![0u8; crate::util::check_message_size(len)?];
         self.socket.read_exact(&mut data).await?;
         
         // Parse the XML data
@@ -195,4 +327,84 @@ impl DiagnosticsClient {
         
         Ok(dict)
     }
-}
\ No newline at end of file
+}
+
+/// The driver reports some strings as raw data
+fn plist_string(value: &plist::Value) -> Option<String> {
+    match value {
+        plist::Value::String(s) => Some(s.clone()),
+        plist::Value::Data(d) => Some(String::from_utf8_lossy(d).trim_end_matches('\0').to_string()),
+        _ => None,
+    }
+}
+
+/// Formats a MAC address reported either as a string or as 6 raw bytes
+fn plist_mac(value: &plist::Value) -> Option<String> {
+    match value {
+        plist::Value::String(s) => Some(s.clone()),
+        plist::Value::Data(d) if d.len() == 6 => Some(
+            d.iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<Vec<_>>()
+                .join(":"),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, ScriptedResponder};
+
+    fn dict(entries: &[(&str, plist::Value)]) -> plist::Dictionary {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn wifi_status_without_mobile_gestalt() {
+        let responder = ScriptedResponder::new(vec![
+            dict(&[
+                ("Status", "Success".into()),
+                (
+                    "Diagnostics",
+                    plist::Value::Dictionary(dict(&[(
+                        "IORegistry",
+                        plist::Value::Dictionary(dict(&[
+                            ("BSD Name", "en0".into()),
+                            ("RSSI", (-52i64).into()),
+                        ])),
+                    )])),
+                ),
+            ]),
+            // What iOS 17.4 and later answer
+            dict(&[
+                ("Status", "MobileGestaltDeprecated".into()),
+                ("Error", "MobileGestaltDeprecated".into()),
+            ]),
+            dict(&[
+                ("Status", "Success".into()),
+                (
+                    "Diagnostics",
+                    plist::Value::Dictionary(dict(&[(
+                        "en0",
+                        plist::Value::Array(vec!["192.168.1.20".into()]),
+                    )])),
+                ),
+            ]),
+        ]);
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(DIAGNOSTICS_SERVICE_NAME, responder);
+
+        let mut client = DiagnosticsClient::connect(&provider).await.unwrap();
+        let status = client.get_wifi_status().await.unwrap();
+        assert_eq!(status.interface.as_deref(), Some("en0"));
+        assert_eq!(status.rssi, Some(-52));
+        assert_eq!(status.mac_address, None);
+        assert_eq!(status.ip_addresses, ["192.168.1.20".parse::<std::net::IpAddr>().unwrap()]);
+    }
+}
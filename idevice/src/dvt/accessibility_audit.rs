@@ -0,0 +1,224 @@
+// Jackson Coxson
+// Client for the accessibility audit daemon, which speaks the same DTX protocol as Instruments.
+// Arguments and results are wrapped in {ObjectType, Value} dictionaries.
+
+use log::{debug, warn};
+use plist::{Dictionary, Value};
+
+use crate::{
+    dvt::message::AuxValue, lockdownd::LockdowndClient, nskeyed, IdeviceError, IdeviceService,
+    ReadWrite,
+};
+
+use super::remote_server::RemoteServerClient;
+
+pub const SERVICE_NAME: &str = "com.apple.accessibility.axAuditDaemon.remoteserver";
+
+const AUDIT_COMPLETE: &str = "hostDeviceDidCompleteAuditCategoriesWithAuditIssues:";
+const ELEMENT_CHANGED: &str = "hostInspectorCurrentElementChanged:";
+
+/// Direction used when moving the inspector focus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveDirection {
+    Previous = 3,
+    Next = 4,
+    First = 5,
+    Last = 6,
+}
+
+pub struct AccessibilityAuditClient<R: ReadWrite> {
+    client: RemoteServerClient<R>,
+}
+
+impl AccessibilityAuditClient<Box<dyn ReadWrite>> {
+    pub async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+
+        let (port, ssl) = lockdown.start_service(SERVICE_NAME).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Ok(Self::new(RemoteServerClient::new(idevice.into_inner()?)))
+    }
+}
+
+impl<R: ReadWrite> AccessibilityAuditClient<R> {
+    pub fn new(client: RemoteServerClient<R>) -> Self {
+        Self { client }
+    }
+
+    pub fn into_inner(self) -> RemoteServerClient<R> {
+        self.client
+    }
+
+    pub async fn api_version(&mut self) -> Result<u64, IdeviceError> {
+        match self.invoke("deviceApiVersion", Vec::new()).await? {
+            Value::Integer(i) => i.as_unsigned().ok_or(IdeviceError::UnexpectedResponse),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn capabilities(&mut self) -> Result<Vec<String>, IdeviceError> {
+        string_array(self.invoke("deviceCapabilities", Vec::new()).await?)
+    }
+
+    /// Lists the audit case IDs that ``run_audit`` accepts
+    pub async fn audit_types(&mut self) -> Result<Vec<String>, IdeviceError> {
+        string_array(self.invoke("deviceAllAuditCaseIDs", Vec::new()).await?)
+    }
+
+    pub async fn settings(&mut self) -> Result<Value, IdeviceError> {
+        self.invoke("deviceAccessibilitySettings", Vec::new()).await
+    }
+
+    /// Runs the given audit types against the foreground app and waits for the issues
+    pub async fn run_audit(
+        &mut self,
+        audit_types: Vec<String>,
+    ) -> Result<Vec<Value>, IdeviceError> {
+        let audit_types = audit_types.into_iter().map(Value::String).collect();
+        self.invoke(
+            "deviceBeginAuditTypes:",
+            vec![passthrough(Value::Array(audit_types))],
+        )
+        .await?;
+
+        match self.wait_for(AUDIT_COMPLETE).await? {
+            Value::Array(a) => Ok(a),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Moves the inspector focus and returns the newly focused element
+    pub async fn move_focus(&mut self, direction: MoveDirection) -> Result<Value, IdeviceError> {
+        let mut options = Dictionary::new();
+        options.insert("allowNonAX".into(), passthrough(false.into()));
+        options.insert("direction".into(), passthrough((direction as u64).into()));
+        options.insert("includeContainers".into(), passthrough(true.into()));
+        let options = typed("AXAuditInspectorMoveOptions_v1", Value::Dictionary(options));
+
+        self.invoke("deviceInspectorMoveWithOptions:", vec![options])
+            .await?;
+        self.wait_for(ELEMENT_CHANGED).await
+    }
+
+    /// Walks the accessibility elements of the foreground app in focus order.
+    /// Stops when the focus wraps around or ``limit`` elements were collected.
+    pub async fn fetch_element_tree(&mut self, limit: usize) -> Result<Vec<Value>, IdeviceError> {
+        self.invoke(
+            "deviceSetAppMonitoringEnabled:",
+            vec![passthrough(true.into())],
+        )
+        .await?;
+        self.invoke(
+            "deviceInspectorSetMonitoredEventType:",
+            vec![passthrough(0u64.into())],
+        )
+        .await?;
+
+        let mut elements: Vec<Value> = Vec::new();
+        let mut direction = MoveDirection::First;
+        while elements.len() < limit {
+            let element = self.move_focus(direction).await?;
+            if elements.contains(&element) {
+                break;
+            }
+            elements.push(element);
+            direction = MoveDirection::Next;
+        }
+
+        Ok(elements)
+    }
+
+    /// Calls a method on the root channel and returns the unwrapped reply
+    async fn invoke(&mut self, selector: &str, args: Vec<Value>) -> Result<Value, IdeviceError> {
+        let args = args.into_iter().map(AuxValue::archived_value).collect();
+        self.client
+            .call_method(0, Some(selector), Some(args), true)
+            .await?;
+
+        let res = self.client.read_message(0).await?;
+        Ok(res
+            .data
+            .map(unwrap_object)
+            .unwrap_or(Value::Dictionary(Dictionary::new())))
+    }
+
+    /// Reads messages until the daemon calls back with the selector, returning its argument
+    async fn wait_for(&mut self, selector: &str) -> Result<Value, IdeviceError> {
+        loop {
+            let msg = self.client.read_message(0).await?;
+            match &msg.data {
+                Some(Value::String(s)) if s == selector => {}
+                d => {
+                    debug!("Skipping accessibility message: {d:?}");
+                    continue;
+                }
+            }
+            return match msg.aux.as_ref().and_then(|a| a.values.first()) {
+                Some(AuxValue::Array(a)) => Ok(unwrap_object(nskeyed::decode(a)?.into_plist())),
+                _ => {
+                    warn!("{selector} did not contain an archived argument");
+                    Err(IdeviceError::UnexpectedResponse)
+                }
+            };
+        }
+    }
+}
+
+fn typed(object_type: &str, value: Value) -> Value {
+    let mut d = Dictionary::new();
+    d.insert("ObjectType".into(), object_type.into());
+    d.insert("Value".into(), value);
+    Value::Dictionary(d)
+}
+
+fn passthrough(value: Value) -> Value {
+    typed("passthrough", value)
+}
+
+/// Strips the {ObjectType, Value} wrappers. Typed objects keep their type under ``ObjectType``.
+fn unwrap_object(value: Value) -> Value {
+    match value {
+        Value::Dictionary(mut d) => {
+            let object_type = d
+                .get("ObjectType")
+                .and_then(|t| t.as_string())
+                .map(|t| t.to_string());
+            match (object_type, d.remove("Value")) {
+                (Some(t), Some(inner)) => match unwrap_object(inner) {
+                    Value::Dictionary(mut inner) if t != "passthrough" => {
+                        inner.insert("ObjectType".into(), t.into());
+                        Value::Dictionary(inner)
+                    }
+                    inner => inner,
+                },
+                (_, inner) => {
+                    if let Some(inner) = inner {
+                        d.insert("Value".into(), inner);
+                    }
+                    Value::Dictionary(d.into_iter().map(|(k, v)| (k, unwrap_object(v))).collect())
+                }
+            }
+        }
+        Value::Array(a) => Value::Array(a.into_iter().map(unwrap_object).collect()),
+        v => v,
+    }
+}
+
+fn string_array(value: Value) -> Result<Vec<String>, IdeviceError> {
+    match value {
+        Value::Array(a) => Ok(a.into_iter().filter_map(|v| v.into_string()).collect()),
+        _ => Err(IdeviceError::UnexpectedResponse),
+    }
+}
@@ -0,0 +1,329 @@
+// Jackson Coxson
+// Per-process memory use from Instruments' system monitor tap.
+// Allocation tracking down to each malloc needs the app launched with malloc stack logging,
+// so this follows the kernel's counters instead, which is enough to spot a process that
+// keeps growing while the same workload runs over and over.
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, warn};
+use plist::Value;
+
+use crate::{dvt::message::AuxValue, IdeviceError, ReadWrite};
+
+use super::remote_server::{Channel, RemoteServerClient};
+
+const IDENTIFIER: &str = "com.apple.instruments.server.services.sysmontap";
+
+/// Requested for each process, values come back in this order
+const PROCESS_ATTRIBUTES: [&str; 6] = [
+    "pid",
+    "name",
+    "physFootprint",
+    "memAnon",
+    "memResidentSize",
+    "faults",
+];
+
+/// Memory use of a process at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemorySample {
+    pub pid: u64,
+    pub name: Option<String>,
+    /// What the system charges the process for, the number Jetsam limits apply to
+    pub phys_footprint: u64,
+    /// Anonymous memory, which is where the heap lives
+    pub heap_size: u64,
+    pub resident_size: u64,
+    /// Page faults since the process started
+    pub faults: u64,
+    /// Host clock when the sample was received
+    pub received: SystemTime,
+}
+
+pub struct AllocationsClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+    pids: Option<HashSet<u64>>,
+    pending: VecDeque<MemorySample>,
+}
+
+impl<'a, R: ReadWrite> AllocationsClient<'a, R> {
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let channel = client.make_channel(IDENTIFIER).await?;
+
+        Ok(Self {
+            channel,
+            pids: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Only yield samples for these processes. Every process is sampled by default.
+    pub fn filter_pids(&mut self, pids: impl IntoIterator<Item = u64>) {
+        self.pids = Some(pids.into_iter().collect());
+    }
+
+    /// Starts sampling every process each interval
+    pub async fn start(&mut self, interval: Duration) -> Result<(), IdeviceError> {
+        let mut config = plist::Dictionary::new();
+        config.insert("ur".into(), (interval.as_millis() as u64).into());
+        config.insert("sampleInterval".into(), (interval.as_nanos() as u64).into());
+        config.insert("bm".into(), 0u64.into());
+        config.insert("cpuUsage".into(), false.into());
+        config.insert("physFootprint".into(), true.into());
+        config.insert(
+            "procAttrs".into(),
+            Value::Array(PROCESS_ATTRIBUTES.iter().map(|a| (*a).into()).collect()),
+        );
+        config.insert("sysAttrs".into(), Value::Array(Vec::new()));
+
+        self.channel
+            .call_method(
+                Some("setConfig:"),
+                Some(vec![AuxValue::archived_value(config)]),
+                false,
+            )
+            .await?;
+        self.channel.call_method(Some("start"), None, false).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), IdeviceError> {
+        self.channel.call_method(Some("stop"), None, false).await
+    }
+
+    /// Waits for the next process sample.
+    /// Each update from the device holds a sample of every process, returned one at a time.
+    pub async fn next_sample(&mut self) -> Result<MemorySample, IdeviceError> {
+        loop {
+            if let Some(sample) = self.pending.pop_front() {
+                return Ok(sample);
+            }
+
+            let msg = self.channel.read_message().await?;
+            let data = match msg.data {
+                Some(d) => d,
+                None => continue,
+            };
+            let received = SystemTime::now();
+            for sample in parse_update(&data, received)? {
+                if let Some(pids) = &self.pids {
+                    if !pids.contains(&sample.pid) {
+                        continue;
+                    }
+                }
+                self.pending.push_back(sample);
+            }
+        }
+    }
+
+    /// Samples for the given number of updates and summarizes each process
+    pub async fn collect(&mut self, updates: usize) -> Result<HighWaterMarks, IdeviceError> {
+        let mut marks = HighWaterMarks::new();
+        let mut seen = 0;
+        let mut last = None;
+        while seen < updates {
+            let sample = self.next_sample().await?;
+            // Samples from one update share the time they were received
+            if last != Some(sample.received) {
+                last = Some(sample.received);
+                seen += 1;
+            }
+            marks.add(&sample);
+        }
+        Ok(marks)
+    }
+}
+
+/// Pulls the process samples out of a sysmontap update, which is a list of tables keyed by pid
+fn parse_update(data: &Value, received: SystemTime) -> Result<Vec<MemorySample>, IdeviceError> {
+    // Updates come wrapped in a tap message
+    let data = match data
+        .as_dictionary()
+        .and_then(|d| d.get("DTTapMessagePlist"))
+    {
+        Some(d) => d,
+        None => data,
+    };
+    let tables = match data {
+        Value::Array(a) => a.as_slice(),
+        Value::Dictionary(_) => std::slice::from_ref(data),
+        d => {
+            debug!("Skipping sysmontap message: {d:?}");
+            return Ok(Vec::new());
+        }
+    };
+
+    let mut samples = Vec::new();
+    for table in tables {
+        let processes = match table
+            .as_dictionary()
+            .and_then(|t| t.get("Processes"))
+            .and_then(|p| p.as_dictionary())
+        {
+            Some(p) => p,
+            None => continue,
+        };
+        for (pid, values) in processes {
+            let values = match values.as_array() {
+                Some(v) => v,
+                None => {
+                    warn!("Attributes of process {pid} were not an array");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            let unsigned = |i: usize| {
+                values
+                    .get(i)
+                    .and_then(|v| v.as_unsigned_integer())
+                    .unwrap_or_default()
+            };
+            let pid = match pid.parse() {
+                Ok(p) => p,
+                Err(_) => unsigned(0),
+            };
+            samples.push(MemorySample {
+                pid,
+                name: values
+                    .get(1)
+                    .and_then(|n| n.as_string())
+                    .map(|n| n.to_string()),
+                phys_footprint: unsigned(2),
+                heap_size: unsigned(3),
+                resident_size: unsigned(4),
+                faults: unsigned(5),
+                received,
+            });
+        }
+    }
+    Ok(samples)
+}
+
+/// How a process's memory use went over a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemorySummary {
+    pub pid: u64,
+    pub name: Option<String>,
+    pub samples: u64,
+    pub first_footprint: u64,
+    pub last_footprint: u64,
+    pub peak_footprint: u64,
+    pub peak_heap_size: u64,
+}
+
+impl MemorySummary {
+    /// Bytes the footprint grew by from the first sample to the last, negative if it shrank
+    pub fn growth(&self) -> i64 {
+        self.last_footprint as i64 - self.first_footprint as i64
+    }
+}
+
+/// The most memory each process used, built up from samples
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighWaterMarks {
+    processes: BTreeMap<u64, MemorySummary>,
+}
+
+impl HighWaterMarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, sample: &MemorySample) {
+        let summary = self
+            .processes
+            .entry(sample.pid)
+            .or_insert_with(|| MemorySummary {
+                pid: sample.pid,
+                name: None,
+                samples: 0,
+                first_footprint: sample.phys_footprint,
+                last_footprint: 0,
+                peak_footprint: 0,
+                peak_heap_size: 0,
+            });
+        if sample.name.is_some() {
+            summary.name.clone_from(&sample.name);
+        }
+        summary.samples += 1;
+        summary.last_footprint = sample.phys_footprint;
+        summary.peak_footprint = summary.peak_footprint.max(sample.phys_footprint);
+        summary.peak_heap_size = summary.peak_heap_size.max(sample.heap_size);
+    }
+
+    pub fn get(&self, pid: u64) -> Option<&MemorySummary> {
+        self.processes.get(&pid)
+    }
+
+    /// Every process seen, by pid
+    pub fn iter(&self) -> impl Iterator<Item = &MemorySummary> {
+        self.processes.values()
+    }
+
+    /// Processes whose footprint went over the limit at any point
+    pub fn over(&self, limit: u64) -> impl Iterator<Item = &MemorySummary> {
+        self.iter().filter(move |s| s.peak_footprint > limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(processes: &[(u64, &str, u64, u64)]) -> Value {
+        let mut table = plist::Dictionary::new();
+        for (pid, name, footprint, heap) in processes {
+            let values = vec![
+                Value::from(*pid),
+                Value::from(*name),
+                Value::from(*footprint),
+                Value::from(*heap),
+                Value::from(*footprint * 2),
+                Value::from(10u64),
+            ];
+            table.insert(pid.to_string(), Value::Array(values));
+        }
+        let mut processes = plist::Dictionary::new();
+        processes.insert("Processes".into(), Value::Dictionary(table));
+        let mut message = plist::Dictionary::new();
+        message.insert(
+            "DTTapMessagePlist".into(),
+            Value::Array(vec![Value::Dictionary(processes)]),
+        );
+        Value::Dictionary(message)
+    }
+
+    #[test]
+    fn peaks_are_tracked() {
+        let now = SystemTime::now();
+        let mut marks = HighWaterMarks::new();
+        for data in [
+            update(&[(1, "launchd", 500, 100), (42, "Demo", 1000, 400)]),
+            update(&[(42, "Demo", 3000, 900)]),
+            update(&[(42, "Demo", 2000, 700)]),
+        ] {
+            for sample in parse_update(&data, now).unwrap() {
+                marks.add(&sample);
+            }
+        }
+
+        let demo = marks.get(42).unwrap();
+        assert_eq!(demo.name.as_deref(), Some("Demo"));
+        assert_eq!(demo.samples, 3);
+        assert_eq!(demo.peak_footprint, 3000);
+        assert_eq!(demo.peak_heap_size, 900);
+        assert_eq!(demo.growth(), 1000);
+        assert_eq!(marks.get(1).unwrap().growth(), 0);
+
+        let over: Vec<_> = marks.over(1000).map(|s| s.pid).collect();
+        assert_eq!(over, [42]);
+    }
+
+    #[test]
+    fn other_messages_are_skipped() {
+        let samples = parse_update(&Value::from("ok"), SystemTime::now()).unwrap();
+        assert!(samples.is_empty());
+    }
+}
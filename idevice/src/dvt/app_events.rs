@@ -0,0 +1,140 @@
+// Jackson Coxson
+// Application lifecycle notifications from Instruments
+
+use std::time::SystemTime;
+
+use log::{debug, warn};
+use plist::Value;
+
+use crate::{dvt::message::AuxValue, nskeyed, IdeviceError, ReadWrite};
+
+use super::remote_server::{Channel, RemoteServerClient};
+
+const IDENTIFIER: &str = "com.apple.instruments.server.services.mobilenotifications";
+const STATE_NOTIFICATION: &str = "applicationStateNotification:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppState {
+    Launched,
+    Foregrounded,
+    Backgrounded,
+    Suspended,
+    Terminated,
+    /// A state description this library doesn't know about
+    Other(String),
+}
+
+impl AppState {
+    fn from_description(description: &str) -> Self {
+        match description {
+            "Launching" | "Launched" => Self::Launched,
+            "Foreground Running" | "Foreground" => Self::Foregrounded,
+            "Background Running" | "Background" | "Background Task Running" => Self::Backgrounded,
+            "Background Task Suspended" | "Suspended" => Self::Suspended,
+            "Terminated" | "Not running" => Self::Terminated,
+            d => Self::Other(d.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppEvent {
+    pub bundle_id: String,
+    pub pid: Option<u64>,
+    pub state: AppState,
+    /// Device clock at the time of the transition, in mach absolute time units
+    pub mach_absolute_time: Option<u64>,
+    /// Host clock when the event was received
+    pub received: SystemTime,
+}
+
+pub struct AppEventsClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+    bundle_id: Option<String>,
+}
+
+impl<'a, R: ReadWrite> AppEventsClient<'a, R> {
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let channel = client.make_channel(IDENTIFIER).await?;
+
+        Ok(Self {
+            channel,
+            bundle_id: None,
+        })
+    }
+
+    /// Opens the channel, enables state notifications and only yields events for the bundle ID
+    pub async fn app_events(
+        client: &'a mut RemoteServerClient<R>,
+        bundle_id: impl Into<String>,
+    ) -> Result<Self, IdeviceError> {
+        let mut res = Self::new(client).await?;
+        res.bundle_id = Some(bundle_id.into());
+        res.set_enabled(true).await?;
+        Ok(res)
+    }
+
+    pub async fn set_enabled(&mut self, enabled: bool) -> Result<(), IdeviceError> {
+        self.channel
+            .call_method(
+                Some("setApplicationStateNotificationsEnabled:"),
+                Some(vec![AuxValue::archived_value(enabled)]),
+                false,
+            )
+            .await
+    }
+
+    /// Waits for the next lifecycle event, skipping apps that don't match the filter
+    pub async fn next_event(&mut self) -> Result<AppEvent, IdeviceError> {
+        loop {
+            let msg = self.channel.read_message().await?;
+            match &msg.data {
+                Some(Value::String(s)) if s == STATE_NOTIFICATION => {}
+                d => {
+                    debug!("Skipping mobilenotifications message: {d:?}");
+                    continue;
+                }
+            }
+
+            let info = match msg.aux.as_ref().and_then(|a| a.values.first()) {
+                Some(AuxValue::Array(a)) => nskeyed::decode(a)?.into_plist(),
+                _ => {
+                    warn!("State notification did not contain an archived argument");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            let info = match info.into_dictionary() {
+                Some(i) => i,
+                None => return Err(IdeviceError::UnexpectedResponse),
+            };
+
+            let bundle_id = match info.get("displayID").and_then(|d| d.as_string()) {
+                Some(b) => b.to_string(),
+                None => {
+                    warn!("State notification did not contain a displayID");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            if let Some(filter) = &self.bundle_id {
+                if filter != &bundle_id {
+                    continue;
+                }
+            }
+
+            let state = match info.get("state_description").and_then(|s| s.as_string()) {
+                Some(s) => AppState::from_description(s),
+                None => return Err(IdeviceError::UnexpectedResponse),
+            };
+
+            return Ok(AppEvent {
+                bundle_id,
+                pid: info.get("pid").and_then(|p| p.as_unsigned_integer()),
+                state,
+                mach_absolute_time: info
+                    .get("mach_absolute_time")
+                    .and_then(|t| t.as_unsigned_integer()),
+                received: SystemTime::now(),
+            });
+        }
+    }
+}
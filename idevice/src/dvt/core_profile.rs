@@ -0,0 +1,382 @@
+// Jackson Coxson
+// Stack sampling from Instruments' core profile session tap, the data behind the Time Profiler.
+// The device streams kdebug trace buffers; kperf's thread info and callstack events in them
+// are put back together into one sample per thread per timer fire.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    time::Duration,
+};
+
+use log::debug;
+use plist::Value;
+
+use crate::{dvt::message::AuxValue, nskeyed::NsObject, IdeviceError, ReadWrite};
+
+use super::{
+    kdebug::{DebugId, KdebugEvent, KdebugStream, DBG_PERF, IDENTIFIER},
+    remote_server::{Channel, RemoteServerClient},
+    symbolicator::Symbolicator,
+};
+
+const PERF_THREADINFO: u8 = 1;
+const PERF_CALLSTACK: u8 = 2;
+const PERF_TI_DATA: DebugId = DebugId::new(DBG_PERF, PERF_THREADINFO, 1);
+const PERF_CS_UDATA: DebugId = DebugId::new(DBG_PERF, PERF_CALLSTACK, 4);
+const PERF_CS_UHDR: DebugId = DebugId::new(DBG_PERF, PERF_CALLSTACK, 6);
+
+/// The user space stack of a thread at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackSample {
+    /// Device clock in mach absolute time units
+    pub timestamp: u64,
+    pub pid: u32,
+    pub thread_id: u64,
+    /// Return addresses, innermost frame first
+    pub frames: Vec<u64>,
+}
+
+pub struct CoreProfileClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+    parser: SampleParser,
+    pid: Option<u32>,
+    pending: VecDeque<StackSample>,
+}
+
+impl<'a, R: ReadWrite> CoreProfileClient<'a, R> {
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let channel = client.make_channel(IDENTIFIER).await?;
+
+        Ok(Self {
+            channel,
+            parser: SampleParser::default(),
+            pid: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Starts sampling every thread on the device each interval.
+    /// Only samples from threads of the pid are returned by ``next_sample``.
+    pub async fn start_sampling(
+        &mut self,
+        pid: u32,
+        interval: Duration,
+    ) -> Result<(), IdeviceError> {
+        self.pid = Some(pid);
+        self.parser = SampleParser::default();
+        self.pending.clear();
+
+        // Keys as Instruments' Time Profiler sends them: a timer trigger ("tk") firing every
+        // "si" nanoseconds, sampling thread info and user callstacks up to "csd" frames deep,
+        // with the trace filtered ("kdf2") down to kperf's events
+        let trigger = NsObject::Dictionary(vec![
+            ("tk".into(), 1u64.into()),
+            ("si".into(), (interval.as_nanos() as u64).into()),
+            (
+                "ta".into(),
+                NsObject::Array(vec![NsObject::Array(vec![3u64.into()])]),
+            ),
+            ("csd".into(), 128u64.into()),
+            (
+                "kdf2".into(),
+                NsObject::Set(vec![
+                    u64::from(DebugId::new(DBG_PERF, PERF_THREADINFO, 0).0).into(),
+                    u64::from(DebugId::new(DBG_PERF, PERF_CALLSTACK, 0).0).into(),
+                ]),
+            ),
+            (
+                "uuid".into(),
+                uuid::Uuid::new_v4().to_string().to_uppercase().into(),
+            ),
+        ]);
+        let config = NsObject::Dictionary(vec![
+            ("rp".into(), 100u64.into()),
+            ("bm".into(), 0u64.into()),
+            ("tc".into(), NsObject::Array(vec![trigger])),
+        ]);
+
+        self.channel
+            .call_method(
+                Some("setConfig:"),
+                Some(vec![AuxValue::archived_object(&config)]),
+                false,
+            )
+            .await?;
+        self.channel.call_method(Some("start"), None, false).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), IdeviceError> {
+        self.pid = None;
+        self.channel.call_method(Some("stop"), None, false).await
+    }
+
+    /// Waits for the next sample of the process being sampled
+    pub async fn next_sample(&mut self) -> Result<StackSample, IdeviceError> {
+        loop {
+            if let Some(sample) = self.pending.pop_front() {
+                return Ok(sample);
+            }
+
+            let msg = self.channel.read_message().await?;
+            let data = match msg.data {
+                Some(Value::Data(d)) => d,
+                d => {
+                    debug!("Skipping core profile message: {d:?}");
+                    continue;
+                }
+            };
+            for sample in self.parser.feed(&data)? {
+                if Some(sample.pid) == self.pid {
+                    self.pending.push_back(sample);
+                }
+            }
+        }
+    }
+
+    /// Collects the given number of samples into collapsed stacks
+    pub async fn collect(
+        &mut self,
+        samples: usize,
+        symbolicator: &dyn Symbolicator,
+    ) -> Result<CollapsedStacks, IdeviceError> {
+        let mut stacks = CollapsedStacks::new();
+        for _ in 0..samples {
+            let sample = self.next_sample().await?;
+            stacks.add(&sample, symbolicator);
+        }
+        Ok(stacks)
+    }
+}
+
+/// A callstack whose frames are still arriving
+#[derive(Debug)]
+struct PartialStack {
+    timestamp: u64,
+    remaining: usize,
+    frames: Vec<u64>,
+}
+
+/// Puts samples back together from kperf's events in a kdebug trace stream
+#[derive(Debug, Default)]
+struct SampleParser {
+    stream: KdebugStream,
+    /// Thread ID to pid, for threads started after the stream's thread map was sent
+    threads: HashMap<u64, u32>,
+    stacks: HashMap<u64, PartialStack>,
+}
+
+impl SampleParser {
+    fn feed(&mut self, data: &[u8]) -> Result<Vec<StackSample>, IdeviceError> {
+        let events = self.stream.feed(data)?;
+        Ok(events.iter().filter_map(|e| self.event(e)).collect())
+    }
+
+    fn event(&mut self, event: &KdebugEvent) -> Option<StackSample> {
+        let thread_id = event.thread_id;
+        match event.debug_id.event_id() {
+            PERF_TI_DATA => {
+                self.threads.insert(event.args[1], event.args[0] as u32);
+                None
+            }
+            PERF_CS_UHDR => {
+                let remaining = event.args[1] as usize;
+                let stack = PartialStack {
+                    timestamp: event.timestamp,
+                    remaining,
+                    frames: Vec::with_capacity(remaining.min(256)),
+                };
+                if remaining == 0 {
+                    return self.finish(thread_id, stack);
+                }
+                self.stacks.insert(thread_id, stack);
+                None
+            }
+            PERF_CS_UDATA => {
+                let mut stack = self.stacks.remove(&thread_id)?;
+                for frame in event.args.into_iter().take(stack.remaining) {
+                    stack.frames.push(frame);
+                    stack.remaining -= 1;
+                }
+                if stack.remaining == 0 {
+                    return self.finish(thread_id, stack);
+                }
+                self.stacks.insert(thread_id, stack);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn finish(&self, thread_id: u64, stack: PartialStack) -> Option<StackSample> {
+        let pid = match self.threads.get(&thread_id) {
+            Some(p) => *p,
+            None => match self.stream.thread(thread_id) {
+                Some(t) => t.pid,
+                None => {
+                    debug!("Dropping a sample from unknown thread {thread_id}");
+                    return None;
+                }
+            },
+        };
+        Some(StackSample {
+            timestamp: stack.timestamp,
+            pid,
+            thread_id,
+            frames: stack.frames,
+        })
+    }
+}
+
+/// Samples folded into one line per distinct stack, the input format of flamegraph tools.
+/// Each line is the frames from outermost to innermost separated by ``;``, then the count.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollapsedStacks {
+    counts: BTreeMap<String, u64>,
+}
+
+impl CollapsedStacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sample, naming frames the symbolicator can't resolve by their address
+    pub fn add(&mut self, sample: &StackSample, symbolicator: &dyn Symbolicator) {
+        if sample.frames.is_empty() {
+            return;
+        }
+        let stack = sample
+            .frames
+            .iter()
+            .rev()
+            .map(|a| match symbolicator.symbolicate(*a) {
+                // The separators can't appear in a frame
+                Some(s) => s.replace([';', ' '], "_"),
+                None => format!("{a:#x}"),
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        *self.counts.entry(stack).or_default() += 1;
+    }
+
+    /// Each distinct stack with how many samples it was seen in
+    pub fn stacks(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.counts.iter().map(|(s, c)| (s.as_str(), *c))
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+impl fmt::Display for CollapsedStacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stack, count) in &self.counts {
+            writeln!(f, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dvt::kdebug::tests::{event, header};
+
+    struct Names;
+
+    impl Symbolicator for Names {
+        fn symbolicate(&self, address: u64) -> Option<String> {
+            match address {
+                0x1000 => Some("main".into()),
+                0x2000 => Some("work".into()),
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn samples_come_through_fragmented_buffers() {
+        use crate::{
+            codec::read_frame,
+            dvt::{
+                message::{Message, MessageHeader, PayloadHeader},
+                remote_server::tests::tap_fragments,
+            },
+            testing::MockTransport,
+        };
+
+        let mut stream = header(&[(7, 100)]);
+        for _ in 0..2000 {
+            stream.extend(event(PERF_CS_UHDR.0, 7, [0, 2, 0, 0]));
+            stream.extend(event(PERF_CS_UDATA.0, 7, [0x2000, 0x1000, 0, 0]));
+        }
+
+        let (host, mut device) = MockTransport::pair();
+        let device = tokio::spawn(async move {
+            // Accept the channel, then send one large buffer the way the tap does
+            let request: Message = read_frame(&mut device).await.unwrap();
+            let reply = Message::new(
+                MessageHeader::new(0, 1, request.message_header.identifier(), 1, 0, false),
+                PayloadHeader::new(),
+                None,
+                None,
+            );
+            device.write_frame(&reply).await.unwrap();
+            for fragment in tap_fragments(100, 1, &stream, 65536) {
+                device.write_frame(&fragment).await.unwrap();
+            }
+            device
+        });
+
+        let mut client = RemoteServerClient::new(host);
+        let mut profile = CoreProfileClient::new(&mut client).await.unwrap();
+        profile
+            .start_sampling(100, Duration::from_millis(1))
+            .await
+            .unwrap();
+        let _device = device.await.unwrap();
+        for _ in 0..2000 {
+            let sample = profile.next_sample().await.unwrap();
+            assert_eq!(sample.thread_id, 7);
+            assert_eq!(sample.frames, [0x2000, 0x1000]);
+        }
+    }
+
+    #[test]
+    fn samples_are_collapsed() {
+        let mut stream = header(&[(7, 100)]);
+        // Thread 8 is only known from its thread info event
+        stream.extend(event(PERF_TI_DATA.0, 8, [100, 8, 0, 0]));
+        stream.extend(event(PERF_CS_UHDR.0, 7, [0, 2, 0, 0]));
+        stream.extend(event(PERF_CS_UDATA.0, 7, [0x2000, 0x1000, 0, 0]));
+        stream.extend(event(PERF_CS_UHDR.0 | 1, 8, [0, 5, 0, 0]));
+        stream.extend(event(PERF_CS_UDATA.0, 8, [0x3000, 0x2000, 0x2000, 0x2000]));
+        stream.extend(event(PERF_CS_UDATA.0, 8, [0x1000, 0, 0, 0]));
+        stream.extend(event(PERF_CS_UHDR.0, 9, [0, 1, 0, 0]));
+        stream.extend(event(PERF_CS_UDATA.0, 9, [0x1000, 0, 0, 0]));
+        stream.extend(event(PERF_CS_UHDR.0, 7, [0, 2, 0, 0]));
+        stream.extend(event(PERF_CS_UDATA.0, 7, [0x2000, 0x1000, 0, 0]));
+
+        // Buffers split events anywhere
+        let mut parser = SampleParser::default();
+        let mut samples = Vec::new();
+        for chunk in stream.chunks(50) {
+            samples.extend(parser.feed(chunk).unwrap());
+        }
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[1].thread_id, 8);
+        assert_eq!(samples[1].frames, [0x3000, 0x2000, 0x2000, 0x2000, 0x1000]);
+
+        let mut stacks = CollapsedStacks::new();
+        for sample in &samples {
+            stacks.add(sample, &Names);
+        }
+        assert_eq!(stacks.total(), 3);
+        assert_eq!(
+            stacks.to_string(),
+            "main;work 2\nmain;work;work;work;0x3000 1\n"
+        );
+    }
+}
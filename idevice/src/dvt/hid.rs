@@ -0,0 +1,239 @@
+// Jackson Coxson
+// Synthesized touch, text and hardware button input through testmanagerd.
+// testmanagerd expects XCTest's own classes as arguments, which can't be expressed as
+// plain plist values, so the records are archived as objects of those classes.
+
+use std::collections::BTreeMap;
+
+use log::warn;
+
+use crate::{
+    dvt::message::AuxValue,
+    lockdownd::LockdowndClient,
+    nskeyed::{ArchivedObject, NsObject},
+    IdeviceError, IdeviceService, ReadWrite,
+};
+
+use super::remote_server::{Channel, RemoteServerClient};
+
+pub const SERVICE_NAME: &str = "com.apple.testmanagerd.lockdown.secure";
+
+const IDENTIFIER: &str =
+    "dtxproxy:XCTestManager_IDEInterface:XCTestManager_DaemonConnectionInterface";
+const PROTOCOL_VERSION: u64 = 36;
+
+// XCPointerEvent event types
+const POINTER_DOWN: u64 = 1;
+const POINTER_MOVE: u64 = 2;
+const POINTER_UP: u64 = 3;
+
+/// Hardware buttons, as HID consumer page usages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HardwareButton {
+    Home,
+    Lock,
+    VolumeUp,
+    VolumeDown,
+}
+
+impl HardwareButton {
+    fn usage(&self) -> (u64, u64) {
+        // (usage page, usage)
+        match self {
+            HardwareButton::Home => (0x0C, 0x40),
+            HardwareButton::Lock => (0x0C, 0x30),
+            HardwareButton::VolumeUp => (0x0C, 0xE9),
+            HardwareButton::VolumeDown => (0x0C, 0xEA),
+        }
+    }
+}
+
+/// Connects to testmanagerd and returns a remote server client for ``HidClient::new``
+pub async fn connect(
+    provider: &dyn crate::provider::IdeviceProvider,
+) -> Result<RemoteServerClient<Box<dyn ReadWrite>>, IdeviceError> {
+    let mut lockdown = LockdowndClient::connect(provider).await?;
+    lockdown
+        .start_session(&provider.get_pairing_file().await?)
+        .await?;
+
+    let (port, ssl) = lockdown.start_service(SERVICE_NAME).await?;
+
+    let mut idevice = provider.connect(port).await?;
+    if ssl {
+        idevice
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+    }
+
+    Ok(RemoteServerClient::new(idevice.into_inner()?))
+}
+
+pub struct HidClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+}
+
+impl<'a, R: ReadWrite> HidClient<'a, R> {
+    /// Opens the daemon connection channel and starts a control session
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let channel = client.make_channel(IDENTIFIER).await?;
+        let mut res = Self { channel };
+
+        res.channel
+            .call_method(
+                Some("_IDE_initiateControlSessionWithProtocolVersion:"),
+                Some(vec![AuxValue::archived_value(PROTOCOL_VERSION)]),
+                true,
+            )
+            .await?;
+        res.channel.read_message().await?;
+
+        Ok(res)
+    }
+
+    /// Taps at the point, in screen points
+    pub async fn tap(&mut self, x: f64, y: f64) -> Result<(), IdeviceError> {
+        self.synthesize(
+            "tap",
+            vec![
+                PointerEvent::new(POINTER_DOWN, x, y, 0.0),
+                PointerEvent::new(POINTER_UP, x, y, 0.05),
+            ],
+        )
+        .await
+    }
+
+    /// Presses at the point for ``duration`` seconds
+    pub async fn long_press(&mut self, x: f64, y: f64, duration: f64) -> Result<(), IdeviceError> {
+        self.synthesize(
+            "long press",
+            vec![
+                PointerEvent::new(POINTER_DOWN, x, y, 0.0),
+                PointerEvent::new(POINTER_UP, x, y, duration),
+            ],
+        )
+        .await
+    }
+
+    /// Drags from one point to another over ``duration`` seconds
+    pub async fn swipe(
+        &mut self,
+        from: (f64, f64),
+        to: (f64, f64),
+        duration: f64,
+    ) -> Result<(), IdeviceError> {
+        self.synthesize(
+            "swipe",
+            vec![
+                PointerEvent::new(POINTER_DOWN, from.0, from.1, 0.0),
+                PointerEvent::new(POINTER_MOVE, to.0, to.1, duration),
+                PointerEvent::new(POINTER_UP, to.0, to.1, duration),
+            ],
+        )
+        .await
+    }
+
+    pub async fn press_button(&mut self, button: HardwareButton) -> Result<(), IdeviceError> {
+        let (usage_page, usage) = button.usage();
+
+        let mut event = BTreeMap::new();
+        event.insert("usagePage".into(), usage_page.into());
+        event.insert("usage".into(), usage.into());
+        event.insert("duration".into(), 0.1.into());
+        let event = ArchivedObject::new("XCDeviceEvent", event);
+
+        self.invoke("_XCT_performDeviceEvent:completion:", event.into())
+            .await
+    }
+
+    /// Types the text into the focused element
+    pub async fn send_string(&mut self, text: impl Into<String>) -> Result<(), IdeviceError> {
+        self.channel
+            .call_method(
+                Some("_XCT_sendString:maximumFrequency:completion:"),
+                Some(vec![
+                    AuxValue::archived_value(text.into()),
+                    AuxValue::archived_value(60u64),
+                ]),
+                true,
+            )
+            .await?;
+        self.check_reply().await
+    }
+
+    async fn synthesize(
+        &mut self,
+        name: &str,
+        events: Vec<PointerEvent>,
+    ) -> Result<(), IdeviceError> {
+        let pointer_events = events.iter().map(|e| e.to_object()).collect();
+        let mut path = BTreeMap::new();
+        path.insert("pointerEvents".into(), NsObject::Array(pointer_events));
+        let path = ArchivedObject::new("XCPointerEventPath", path);
+
+        let mut record = BTreeMap::new();
+        record.insert("name".into(), name.into());
+        record.insert("interfaceOrientation".into(), 1u64.into());
+        record.insert("eventPaths".into(), NsObject::Array(vec![path.into()]));
+        let record = ArchivedObject::new("XCSynthesizedEventRecord", record);
+
+        self.invoke("_XCT_synthesizeEvent:completion:", record.into())
+            .await
+    }
+
+    async fn invoke(&mut self, method: &str, argument: NsObject) -> Result<(), IdeviceError> {
+        self.channel
+            .call_method(
+                Some(method),
+                Some(vec![AuxValue::archived_object(&argument)]),
+                true,
+            )
+            .await?;
+        self.check_reply().await
+    }
+
+    /// Replies are empty on success and contain an NSError otherwise
+    async fn check_reply(&mut self) -> Result<(), IdeviceError> {
+        let res = self.channel.read_message().await?;
+        match res.data {
+            None => Ok(()),
+            Some(e) => {
+                warn!("testmanagerd rejected the event: {e:?}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+}
+
+struct PointerEvent {
+    event_type: u64,
+    x: f64,
+    y: f64,
+    offset: f64,
+}
+
+impl PointerEvent {
+    fn new(event_type: u64, x: f64, y: f64, offset: f64) -> Self {
+        Self {
+            event_type,
+            x,
+            y,
+            offset,
+        }
+    }
+
+    fn to_object(&self) -> NsObject {
+        let mut event = BTreeMap::new();
+        event.insert("eventType".into(), 1u64.into());
+        event.insert("pointerEventType".into(), self.event_type.into());
+        event.insert(
+            "coordinate".into(),
+            format!("{{{}, {}}}", self.x, self.y).into(),
+        );
+        event.insert("offset".into(), self.offset.into());
+        event.insert("pressure".into(), 1.0.into());
+        event.insert("buttonType".into(), 0u64.into());
+        event.insert("clickCount".into(), 1u64.into());
+        ArchivedObject::new("XCPointerEvent", event).into()
+    }
+}
@@ -0,0 +1,553 @@
+// Jackson Coxson
+// Raw kdebug tracing through Instruments' core profile session tap.
+// The device streams the kernel's trace buffers as is: a v3 header with a map of the threads
+// alive when tracing started, then fixed size events for as long as the session runs.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use log::{debug, warn};
+use plist::Value;
+
+use crate::{dvt::message::AuxValue, nskeyed::NsObject, IdeviceError, ReadWrite};
+
+use super::remote_server::{Channel, RemoteServerClient};
+
+pub(super) const IDENTIFIER: &str = "com.apple.instruments.server.services.coreprofilesessiontap";
+
+// kdebug v3 chunk tags
+const RAW_VERSION3: u32 = 0x1000;
+const V3_THREAD_MAP: u32 = 0x1d00;
+const V3_RAW_EVENTS: u32 = 0x1e00;
+
+/// Size of a 64 bit ``kd_buf``
+const EVENT_SIZE: usize = 64;
+/// Size of a ``kd_threadmap`` entry
+const THREAD_MAP_ENTRY_SIZE: usize = 32;
+
+pub const DBG_MACH: u8 = 1;
+pub const DBG_NETWORK: u8 = 2;
+pub const DBG_FSYSTEM: u8 = 3;
+pub const DBG_BSD: u8 = 4;
+pub const DBG_IOKIT: u8 = 5;
+pub const DBG_DRIVERS: u8 = 6;
+pub const DBG_TRACE: u8 = 7;
+pub const DBG_DLIL: u8 = 8;
+pub const DBG_PTHREAD: u8 = 9;
+pub const DBG_MISC: u8 = 20;
+pub const DBG_DYLD: u8 = 31;
+pub const DBG_APPS: u8 = 33;
+pub const DBG_PERF: u8 = 37;
+pub const DBG_DISPATCH: u8 = 46;
+
+/// Mach traps, or Unix system calls under ``DBG_BSD``
+pub const DBG_EXCP_SC: u8 = 0x0c;
+pub const DBG_MACH_SCHED: u8 = 0x40;
+pub const MACH_SCHED: u16 = 0x0;
+pub const MACH_MAKERUNNABLE: u16 = 0x6;
+
+/// What kind of event a ``kd_buf`` is: a class, subclass and code, with the low two bits
+/// marking the start or end of an interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DebugId(pub u32);
+
+impl DebugId {
+    pub const fn new(class: u8, subclass: u8, code: u16) -> Self {
+        Self((class as u32) << 24 | (subclass as u32) << 16 | (code as u32 & 0x3fff) << 2)
+    }
+
+    pub fn class(&self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub fn subclass(&self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn code(&self) -> u16 {
+        ((self.0 >> 2) & 0x3fff) as u16
+    }
+
+    pub fn function(&self) -> EventFunction {
+        match self.0 & 0x3 {
+            1 => EventFunction::Start,
+            2 => EventFunction::End,
+            _ => EventFunction::None,
+        }
+    }
+
+    /// The ID without the start and end bits
+    pub fn event_id(&self) -> Self {
+        Self(self.0 & !0x3)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFunction {
+    None,
+    Start,
+    End,
+}
+
+/// A single trace event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdebugEvent {
+    /// Device clock in mach absolute time units
+    pub timestamp: u64,
+    pub args: [u64; 4],
+    pub thread_id: u64,
+    pub debug_id: DebugId,
+    pub cpu: u32,
+}
+
+/// The events this library knows how to read, the rest are left as ``Other``
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// The scheduler switched the CPU from the event's thread to another
+    ContextSwitch {
+        reason: u64,
+        to_thread: u64,
+        from_priority: u64,
+        to_priority: u64,
+    },
+    /// A thread was made runnable
+    MakeRunnable {
+        thread: u64,
+        priority: u64,
+    },
+    /// A Unix system call was entered, with its first four arguments
+    SyscallEnter {
+        number: u16,
+        args: [u64; 4],
+    },
+    /// A Unix system call returned
+    SyscallExit {
+        number: u16,
+        errno: u64,
+        value: u64,
+    },
+    /// A Mach trap was entered, with its first four arguments
+    MachTrapEnter {
+        number: u16,
+        args: [u64; 4],
+    },
+    /// A Mach trap returned
+    MachTrapExit {
+        number: u16,
+        value: u64,
+    },
+    Other,
+}
+
+impl KdebugEvent {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            timestamp: read_u64(bytes, 0),
+            args: [
+                read_u64(bytes, 8),
+                read_u64(bytes, 16),
+                read_u64(bytes, 24),
+                read_u64(bytes, 32),
+            ],
+            thread_id: read_u64(bytes, 40),
+            debug_id: DebugId(read_u32(bytes, 48)),
+            cpu: read_u32(bytes, 52),
+        }
+    }
+
+    pub fn kind(&self) -> EventKind {
+        let id = self.debug_id;
+        let [a1, a2, a3, a4] = self.args;
+        match (id.class(), id.subclass(), id.function()) {
+            (DBG_MACH, DBG_MACH_SCHED, _) if id.code() == MACH_SCHED => EventKind::ContextSwitch {
+                reason: a1,
+                to_thread: a2,
+                from_priority: a3,
+                to_priority: a4,
+            },
+            (DBG_MACH, DBG_MACH_SCHED, _) if id.code() == MACH_MAKERUNNABLE => {
+                EventKind::MakeRunnable {
+                    thread: a1,
+                    priority: a2,
+                }
+            }
+            (DBG_BSD, DBG_EXCP_SC, EventFunction::Start) => EventKind::SyscallEnter {
+                number: id.code(),
+                args: self.args,
+            },
+            (DBG_BSD, DBG_EXCP_SC, EventFunction::End) => EventKind::SyscallExit {
+                number: id.code(),
+                errno: a1,
+                value: a2,
+            },
+            (DBG_MACH, DBG_EXCP_SC, EventFunction::Start) => EventKind::MachTrapEnter {
+                number: id.code(),
+                args: self.args,
+            },
+            (DBG_MACH, DBG_EXCP_SC, EventFunction::End) => EventKind::MachTrapExit {
+                number: id.code(),
+                value: a1,
+            },
+            _ => EventKind::Other,
+        }
+    }
+}
+
+/// Which events to trace, by class or by class and subclass.
+/// An empty filter traces everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KdebugFilter {
+    classes: HashSet<u8>,
+    subclasses: HashSet<(u8, u8)>,
+}
+
+impl KdebugFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_class(mut self, class: u8) -> Self {
+        self.classes.insert(class);
+        self
+    }
+
+    pub fn with_subclass(mut self, class: u8, subclass: u8) -> Self {
+        self.subclasses.insert((class, subclass));
+        self
+    }
+
+    pub fn matches(&self, id: DebugId) -> bool {
+        (self.classes.is_empty() && self.subclasses.is_empty())
+            || self.classes.contains(&id.class())
+            || self.subclasses.contains(&(id.class(), id.subclass()))
+    }
+
+    /// The filter as the device takes it, one entry per class and subclass pair
+    fn to_object(&self) -> NsObject {
+        let mut pairs: Vec<(u8, u8)> = self.subclasses.iter().copied().collect();
+        for class in &self.classes {
+            pairs.extend((0..=u8::MAX).map(|s| (*class, s)));
+        }
+        pairs.sort();
+        pairs.dedup();
+        NsObject::Set(
+            pairs
+                .into_iter()
+                .map(|(c, s)| u64::from(DebugId::new(c, s, 0).0).into())
+                .collect(),
+        )
+    }
+}
+
+/// A thread from the map sent when tracing starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub pid: u32,
+    pub command: String,
+}
+
+/// Splits a kdebug trace stream into events.
+/// Trace buffers don't line up with event boundaries, so leftovers are kept for the next one.
+#[derive(Debug, Default)]
+pub struct KdebugStream {
+    started: bool,
+    buffer: Vec<u8>,
+    threads: HashMap<u64, ThreadInfo>,
+}
+
+impl KdebugStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next piece of the stream, returning the events completed by it
+    pub fn feed(&mut self, data: &[u8]) -> Result<Vec<KdebugEvent>, IdeviceError> {
+        self.buffer.extend_from_slice(data);
+        if !self.started {
+            if self.buffer.len() < 4 {
+                return Ok(Vec::new());
+            }
+            if read_u32(&self.buffer, 0) == RAW_VERSION3 {
+                match self.read_header()? {
+                    Some(events_start) => {
+                        self.buffer.drain(..events_start);
+                    }
+                    // Wait for the rest of the header
+                    None => return Ok(Vec::new()),
+                }
+            }
+            self.started = true;
+        }
+
+        let usable = self.buffer.len() - self.buffer.len() % EVENT_SIZE;
+        let events = self
+            .buffer
+            .drain(..usable)
+            .collect::<Vec<_>>()
+            .chunks_exact(EVENT_SIZE)
+            .map(KdebugEvent::parse)
+            .collect();
+        Ok(events)
+    }
+
+    /// Threads from the map in the stream's header
+    pub fn thread(&self, thread_id: u64) -> Option<&ThreadInfo> {
+        self.threads.get(&thread_id)
+    }
+
+    /// Walks the v3 header chunks, returning where the events start
+    fn read_header(&mut self) -> Result<Option<usize>, IdeviceError> {
+        let mut offset = 0;
+        loop {
+            if self.buffer.len() < offset + 16 {
+                return Ok(None);
+            }
+            let tag = read_u32(&self.buffer, offset);
+            let length = read_u64(&self.buffer, offset + 8) as usize;
+            offset += 16;
+            if tag == V3_RAW_EVENTS {
+                return Ok(Some(offset));
+            }
+            let end = match offset.checked_add(length) {
+                Some(e) => e,
+                None => {
+                    warn!("kdebug chunk {tag:#x} has an impossible length");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            if self.buffer.len() < end {
+                return Ok(None);
+            }
+            if tag == V3_THREAD_MAP {
+                for entry in self.buffer[offset..end].chunks_exact(THREAD_MAP_ENTRY_SIZE) {
+                    let command = &entry[12..];
+                    let command = command.split(|b| *b == 0).next().unwrap_or_default();
+                    self.threads.insert(
+                        read_u64(entry, 0),
+                        ThreadInfo {
+                            pid: read_u32(entry, 8),
+                            command: String::from_utf8_lossy(command).into_owned(),
+                        },
+                    );
+                }
+            }
+            offset = end;
+        }
+    }
+}
+
+pub struct KdebugClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+    stream: KdebugStream,
+    filter: KdebugFilter,
+    pending: VecDeque<KdebugEvent>,
+}
+
+impl<'a, R: ReadWrite> KdebugClient<'a, R> {
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let channel = client.make_channel(IDENTIFIER).await?;
+
+        Ok(Self {
+            channel,
+            stream: KdebugStream::new(),
+            filter: KdebugFilter::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Starts tracing the events the filter matches
+    pub async fn start(&mut self, filter: KdebugFilter) -> Result<(), IdeviceError> {
+        self.stream = KdebugStream::new();
+        self.pending.clear();
+
+        // A kdebug trigger ("tk"), with the filter ("kdf2") as pairs of class and subclass
+        let mut trigger = vec![
+            ("tk".into(), 3u64.into()),
+            (
+                "ta".into(),
+                NsObject::Array(vec![
+                    NsObject::Array(vec![3u64.into()]),
+                    NsObject::Array(vec![0u64.into()]),
+                    NsObject::Array(vec![2u64.into()]),
+                    NsObject::Array(vec![1u64.into(), 1u64.into(), 0u64.into()]),
+                ]),
+            ),
+            ("csd".into(), 128u64.into()),
+            (
+                "uuid".into(),
+                uuid::Uuid::new_v4().to_string().to_uppercase().into(),
+            ),
+        ];
+        if filter != KdebugFilter::default() {
+            trigger.push(("kdf2".into(), filter.to_object()));
+        }
+        let config = NsObject::Dictionary(vec![
+            ("rp".into(), 100u64.into()),
+            ("bm".into(), 0u64.into()),
+            (
+                "tc".into(),
+                NsObject::Array(vec![NsObject::Dictionary(trigger)]),
+            ),
+        ]);
+        self.filter = filter;
+
+        self.channel
+            .call_method(
+                Some("setConfig:"),
+                Some(vec![AuxValue::archived_object(&config)]),
+                false,
+            )
+            .await?;
+        self.channel.call_method(Some("start"), None, false).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), IdeviceError> {
+        self.channel.call_method(Some("stop"), None, false).await
+    }
+
+    /// Waits for the next event that matches the filter
+    pub async fn next_event(&mut self) -> Result<KdebugEvent, IdeviceError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            let msg = self.channel.read_message().await?;
+            let data = match msg.data {
+                Some(Value::Data(d)) => d,
+                d => {
+                    debug!("Skipping kdebug message: {d:?}");
+                    continue;
+                }
+            };
+            // The device filters too, but not always as finely
+            let events = self.stream.feed(&data)?;
+            self.pending.extend(
+                events
+                    .into_iter()
+                    .filter(|e| self.filter.matches(e.debug_id)),
+            );
+        }
+    }
+
+    /// The process and command of a thread that was alive when tracing started
+    pub fn thread(&self, thread_id: u64) -> Option<&ThreadInfo> {
+        self.stream.thread(thread_id)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub fn event(debug_id: u32, thread_id: u64, args: [u64; 4]) -> Vec<u8> {
+        let mut event = Vec::with_capacity(EVENT_SIZE);
+        event.extend_from_slice(&42u64.to_le_bytes());
+        for arg in args {
+            event.extend_from_slice(&arg.to_le_bytes());
+        }
+        event.extend_from_slice(&thread_id.to_le_bytes());
+        event.extend_from_slice(&debug_id.to_le_bytes());
+        event.extend_from_slice(&3u32.to_le_bytes());
+        event.extend_from_slice(&0u64.to_le_bytes());
+        event
+    }
+
+    pub fn header(threads: &[(u64, u32)]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&RAW_VERSION3.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+
+        header.extend_from_slice(&V3_THREAD_MAP.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&((threads.len() * THREAD_MAP_ENTRY_SIZE) as u64).to_le_bytes());
+        for (thread_id, pid) in threads {
+            header.extend_from_slice(&thread_id.to_le_bytes());
+            header.extend_from_slice(&pid.to_le_bytes());
+            let mut command = [0; 20];
+            command[..4].copy_from_slice(b"demo");
+            header.extend_from_slice(&command);
+        }
+
+        header.extend_from_slice(&V3_RAW_EVENTS.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn events_are_typed() {
+        let read = DebugId::new(DBG_BSD, DBG_EXCP_SC, 3);
+        let mut stream = header(&[(7, 100)]);
+        stream.extend(event(read.0 | 1, 7, [4, 0x1000, 64, 0]));
+        stream.extend(event(read.0 | 2, 7, [0, 64, 0, 0]));
+        stream.extend(event(
+            DebugId::new(DBG_MACH, DBG_MACH_SCHED, MACH_SCHED).0,
+            7,
+            [1, 8, 31, 47],
+        ));
+        stream.extend(event(DebugId::new(DBG_DYLD, 1, 5).0, 7, [0; 4]));
+
+        let mut parser = KdebugStream::new();
+        let mut events = Vec::new();
+        for chunk in stream.chunks(50) {
+            events.extend(parser.feed(chunk).unwrap());
+        }
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].cpu, 3);
+        assert_eq!(
+            parser.thread(7),
+            Some(&ThreadInfo {
+                pid: 100,
+                command: "demo".into()
+            })
+        );
+
+        let kinds: Vec<_> = events.iter().map(|e| e.kind()).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::SyscallEnter {
+                    number: 3,
+                    args: [4, 0x1000, 64, 0]
+                },
+                EventKind::SyscallExit {
+                    number: 3,
+                    errno: 0,
+                    value: 64
+                },
+                EventKind::ContextSwitch {
+                    reason: 1,
+                    to_thread: 8,
+                    from_priority: 31,
+                    to_priority: 47
+                },
+                EventKind::Other,
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_match_classes_and_subclasses() {
+        let filter = KdebugFilter::new()
+            .with_class(DBG_BSD)
+            .with_subclass(DBG_MACH, DBG_MACH_SCHED);
+        assert!(filter.matches(DebugId::new(DBG_BSD, DBG_EXCP_SC, 3)));
+        assert!(filter.matches(DebugId::new(DBG_MACH, DBG_MACH_SCHED, MACH_SCHED)));
+        assert!(!filter.matches(DebugId::new(DBG_MACH, DBG_EXCP_SC, 31)));
+        assert!(KdebugFilter::new().matches(DebugId::new(DBG_DYLD, 1, 5)));
+
+        let NsObject::Set(entries) = filter.to_object() else {
+            panic!("Expected a set");
+        };
+        assert_eq!(entries.len(), 257);
+    }
+}
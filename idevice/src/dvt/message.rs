@@ -7,10 +7,16 @@
 //   - Aux data
 // - Payload (NSKeyedArchive)
 
+use bytes::Bytes;
+use log::warn;
 use plist::Value;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::AsyncRead;
 
-use crate::IdeviceError;
+use crate::{
+    codec::Codec,
+    nskeyed::{self, NsObject},
+    IdeviceError,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct MessageHeader {
@@ -182,7 +188,12 @@ impl Aux {
 impl AuxValue {
     // Returns an array AuxType
     pub fn archived_value(v: impl Into<plist::Value>) -> Self {
-        Self::Array(ns_keyed_archive::encode::encode_to_bytes(v.into()).expect("Failed to encode"))
+        Self::archived_object(&NsObject::from(v.into()))
+    }
+
+    /// Returns an array AuxType for an object that isn't a plain plist value
+    pub fn archived_object(object: &NsObject) -> Self {
+        Self::Array(nskeyed::encode(object).expect("Failed to encode"))
     }
 }
 
@@ -209,6 +220,39 @@ impl MessageHeader {
         }
     }
 
+    fn from_bytes(buf: &[u8]) -> Self {
+        Self {
+            magic: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            header_len: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            fragment_id: u16::from_le_bytes([buf[8], buf[9]]),
+            fragment_count: u16::from_le_bytes([buf[10], buf[11]]),
+            length: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
+            identifier: u32::from_le_bytes([buf[16], buf[17], buf[18], buf[19]]),
+            conversation_index: u32::from_le_bytes([buf[20], buf[21], buf[22], buf[23]]),
+            channel: u32::from_le_bytes([buf[24], buf[25], buf[26], buf[27]]),
+            expects_reply: u32::from_le_bytes([buf[28], buf[29], buf[30], buf[31]]) == 1,
+        }
+    }
+
+    /// The message this frame belongs to, shared by all of its fragments
+    pub fn identifier(&self) -> u32 {
+        self.identifier
+    }
+
+    pub fn fragment_id(&self) -> u16 {
+        self.fragment_id
+    }
+
+    pub fn fragment_count(&self) -> u16 {
+        self.fragment_count
+    }
+
+    /// Whether the frame is the first of a message split into fragments, which carries
+    /// only this header and no payload
+    fn is_first_fragment(&self) -> bool {
+        self.fragment_count > 1 && self.fragment_id == 0
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut res = Vec::new();
         res.extend_from_slice(&self.magic.to_le_bytes());
@@ -253,24 +297,35 @@ impl PayloadHeader {
 
 impl Message {
     pub async fn from_reader<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self, IdeviceError> {
-        let mut buf = [0u8; 32];
-        reader.read_exact(&mut buf).await?;
+        crate::codec::read_frame(reader).await
+    }
 
-        let mheader = MessageHeader {
-            magic: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
-            header_len: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
-            fragment_id: u16::from_le_bytes([buf[8], buf[9]]),
-            fragment_count: u16::from_le_bytes([buf[10], buf[11]]),
-            length: u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]),
-            identifier: u32::from_le_bytes([buf[16], buf[17], buf[18], buf[19]]),
-            conversation_index: u32::from_le_bytes([buf[20], buf[21], buf[22], buf[23]]),
-            channel: u32::from_le_bytes([buf[24], buf[25], buf[26], buf[27]]),
-            expects_reply: u32::from_le_bytes([buf[28], buf[29], buf[30], buf[31]]) == 1,
-        };
+    pub fn new(
+        message_header: MessageHeader,
+        payload_header: PayloadHeader,
+        aux: Option<Aux>,
+        data: Option<Value>,
+    ) -> Self {
+        Self {
+            message_header,
+            payload_header,
+            aux,
+            data,
+        }
+    }
 
-        let mut buf = [0u8; 16];
-        reader.read_exact(&mut buf).await?;
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode().expect("Failed to encode value").into()
+    }
 
+    /// Parses a message from its header and everything after it: the payload header, the
+    /// auxiliary values and the data. For a message that came in fragments, that's the
+    /// pieces after the first one put together.
+    fn from_parts(mheader: MessageHeader, body: &[u8]) -> Result<Self, IdeviceError> {
+        if body.len() < 16 {
+            return Err(IdeviceError::NotEnoughBytes(body.len() + 32, 48));
+        }
+        let buf = &body[..16];
         let pheader = PayloadHeader {
             flags: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
             aux_length: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
@@ -279,21 +334,33 @@ impl Message {
             ]),
         };
 
-        let aux = if pheader.aux_length > 0 {
-            let mut buf = vec![0u8; pheader.aux_length as usize];
-            reader.read_exact(&mut buf).await?;
-            Some(Aux::from_bytes(buf)?)
+        // The payload header's lengths have to agree with the message header's
+        let payload = &body[16..];
+        let aux_length = pheader.aux_length as usize;
+        if pheader.total_length > payload.len() as u64 || aux_length as u64 > pheader.total_length {
+            return Err(IdeviceError::NotEnoughBytes(
+                payload.len(),
+                pheader.total_length as usize,
+            ));
+        }
+
+        let aux = if aux_length > 0 {
+            Some(Aux::from_bytes(payload[..aux_length].to_vec())?)
         } else {
             None
         };
 
-        let mut buf = vec![0u8; (pheader.total_length - pheader.aux_length as u64) as usize];
-        reader.read_exact(&mut buf).await?;
-
-        let data = if buf.is_empty() {
+        let data = &payload[aux_length..pheader.total_length as usize];
+        let data = if data.is_empty() {
             None
+        } else if !data.starts_with(b"bplist") {
+            // Taps send their trace data as is instead of archiving it
+            Some(Value::Data(data.to_vec()))
         } else {
-            Some(ns_keyed_archive::decode::from_bytes(&buf)?)
+            match nskeyed::decode(data)? {
+                NsObject::Null => None,
+                o => Some(o.into_plist()),
+            }
         };
 
         Ok(Message {
@@ -303,29 +370,35 @@ impl Message {
             data,
         })
     }
+}
 
-    pub fn new(
-        message_header: MessageHeader,
-        payload_header: PayloadHeader,
-        aux: Option<Aux>,
-        data: Option<Value>,
-    ) -> Self {
-        Self {
-            message_header,
-            payload_header,
-            aux,
-            data,
+impl Codec for Message {
+    const HEADER_LEN: usize = 32;
+
+    fn frame_length(header: &[u8]) -> Result<usize, IdeviceError> {
+        Fragment::frame_length(header)
+    }
+
+    fn decode_frame(frame: &[u8]) -> Result<Self, IdeviceError> {
+        let mheader = MessageHeader::from_bytes(&frame[..32]);
+        if mheader.fragment_count > 1 {
+            // Only whole messages can be read this way, ``Fragment`` reads the pieces
+            warn!(
+                "DTX message {} is split into {} fragments",
+                mheader.identifier, mheader.fragment_count
+            );
+            return Err(IdeviceError::UnexpectedResponse);
         }
+        Self::from_parts(mheader, &frame[32..])
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
+    fn encode(&self) -> Result<Bytes, IdeviceError> {
         let aux = match &self.aux {
             Some(a) => a.serialize(),
             None => Vec::new(),
         };
         let data = match &self.data {
-            Some(d) => ns_keyed_archive::encode::encode_to_bytes(d.to_owned())
-                .expect("Failed to encode value"),
+            Some(d) => nskeyed::encode(&NsObject::from(d.to_owned()))?,
             None => Vec::new(),
         };
 
@@ -345,7 +418,58 @@ impl Message {
         res.extend_from_slice(&aux);
         res.extend_from_slice(&data);
 
-        res
+        Ok(res.into())
+    }
+}
+
+/// One DTX frame as it is on the wire. Large messages are split into several: the first
+/// carries only the message header, and the ones after it each carry a piece of the payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub header: MessageHeader,
+    pub body: Vec<u8>,
+}
+
+impl Fragment {
+    /// Puts a message back together from the first fragment's header and the pieces after
+    /// it, in order
+    pub fn reassemble(mut header: MessageHeader, body: &[u8]) -> Result<Message, IdeviceError> {
+        header.fragment_id = 0;
+        header.fragment_count = 1;
+        header.length = body.len() as u32;
+        Message::from_parts(header, body)
+    }
+}
+
+impl Codec for Fragment {
+    const HEADER_LEN: usize = 32;
+
+    fn frame_length(header: &[u8]) -> Result<usize, IdeviceError> {
+        let header = MessageHeader::from_bytes(header);
+        // The first fragment's length is the whole message's, but nothing follows it
+        if header.is_first_fragment() {
+            return Ok(32);
+        }
+        (header.length as usize)
+            .checked_add(32)
+            .ok_or(IdeviceError::MessageTooLarge(usize::MAX))
+    }
+
+    fn decode_frame(frame: &[u8]) -> Result<Self, IdeviceError> {
+        Ok(Self {
+            header: MessageHeader::from_bytes(&frame[..32]),
+            body: frame[32..].to_vec(),
+        })
+    }
+
+    fn encode(&self) -> Result<Bytes, IdeviceError> {
+        let mut header = self.header.clone();
+        if !header.is_first_fragment() {
+            header.length = self.body.len() as u32;
+        }
+        let mut res = header.serialize();
+        res.extend_from_slice(&self.body);
+        Ok(res.into())
     }
 }
 
@@ -1,7 +1,29 @@
 // Jackson Coxson
 
+pub mod accessibility_audit;
+pub mod allocations;
+pub mod app_events;
+pub mod core_profile;
+pub mod hid;
+pub mod kdebug;
 pub mod message;
+pub mod network_monitor;
 pub mod process_control;
 pub mod remote_server;
+pub mod symbolicator;
+pub mod thermal;
 
 pub const SERVICE_NAME: &str = "com.apple.instruments.dtservicehub";
+
+/// Connects to the Instruments remote server the way the device's version offers it,
+/// through lockdown before iOS 17 and through a software CoreDevice tunnel from then on
+pub async fn connect(
+    provider: &dyn crate::provider::IdeviceProvider,
+) -> Result<remote_server::RemoteServerClient<Box<dyn crate::ReadWrite>>, crate::IdeviceError> {
+    let socket = crate::capabilities::connect_developer_service(
+        provider,
+        crate::capabilities::Capabilities::instruments_service,
+    )
+    .await?;
+    Ok(remote_server::RemoteServerClient::new(socket))
+}
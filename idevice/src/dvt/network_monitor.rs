@@ -0,0 +1,197 @@
+// Jackson Coxson
+// Live network connection events from Instruments
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use log::warn;
+use plist::Value;
+
+use crate::{IdeviceError, ReadWrite};
+
+use super::remote_server::{Channel, RemoteServerClient};
+
+const IDENTIFIER: &str = "com.apple.instruments.server.services.networking";
+
+const INTERFACE_DETECTION: u64 = 0;
+const CONNECTION_DETECTION: u64 = 1;
+const CONNECTION_UPDATE: u64 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkEvent {
+    Interface(InterfaceDetection),
+    Connection(ConnectionDetection),
+    Update(ConnectionUpdate),
+}
+
+/// A network interface the device started tracking
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDetection {
+    pub interface_index: u64,
+    pub name: String,
+}
+
+/// A new connection opened by a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionDetection {
+    pub local_address: Option<SocketAddr>,
+    pub remote_address: Option<SocketAddr>,
+    pub interface_index: u64,
+    pub pid: u64,
+    pub recv_buffer_size: u64,
+    pub recv_buffer_used: u64,
+    /// Identifies the connection in later ``ConnectionUpdate`` events
+    pub serial_number: u64,
+    pub kind: u64,
+}
+
+/// Traffic counters for a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionUpdate {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub rx_dups: u64,
+    pub rx_out_of_order: u64,
+    pub tx_retransmits: u64,
+    pub min_rtt: u64,
+    pub avg_rtt: u64,
+    pub connection_serial: u64,
+}
+
+pub struct NetworkMonitorClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+}
+
+impl<'a, R: ReadWrite> NetworkMonitorClient<'a, R> {
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let channel = client.make_channel(IDENTIFIER).await?;
+
+        Ok(Self { channel })
+    }
+
+    pub async fn start_monitoring(&mut self) -> Result<(), IdeviceError> {
+        self.channel
+            .call_method(Some("startMonitoring"), None, false)
+            .await
+    }
+
+    pub async fn stop_monitoring(&mut self) -> Result<(), IdeviceError> {
+        self.channel
+            .call_method(Some("stopMonitoring"), None, false)
+            .await
+    }
+
+    /// Waits for the next event from the device.
+    /// Messages of an unknown type are skipped.
+    pub async fn next_event(&mut self) -> Result<NetworkEvent, IdeviceError> {
+        loop {
+            let msg = self.channel.read_message().await?;
+            let values = match msg.data {
+                Some(Value::Array(a)) => a,
+                _ => {
+                    warn!("Network event was not an array");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            let (event_type, data) = match (values.first(), values.get(1)) {
+                (Some(Value::Integer(t)), Some(Value::Array(d))) => match t.as_unsigned() {
+                    Some(t) => (t, d),
+                    None => return Err(IdeviceError::UnexpectedResponse),
+                },
+                _ => {
+                    warn!("Network event did not contain a type and data");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+
+            match event_type {
+                INTERFACE_DETECTION => {
+                    return Ok(NetworkEvent::Interface(InterfaceDetection {
+                        interface_index: unsigned_at(data, 0)?,
+                        name: match data.get(1) {
+                            Some(Value::String(s)) => s.to_owned(),
+                            _ => return Err(IdeviceError::UnexpectedResponse),
+                        },
+                    }))
+                }
+                CONNECTION_DETECTION => {
+                    return Ok(NetworkEvent::Connection(ConnectionDetection {
+                        local_address: sockaddr_at(data, 0),
+                        remote_address: sockaddr_at(data, 1),
+                        interface_index: unsigned_at(data, 2)?,
+                        pid: unsigned_at(data, 3)?,
+                        recv_buffer_size: unsigned_at(data, 4)?,
+                        recv_buffer_used: unsigned_at(data, 5)?,
+                        serial_number: unsigned_at(data, 6)?,
+                        kind: unsigned_at(data, 7)?,
+                    }))
+                }
+                CONNECTION_UPDATE => {
+                    return Ok(NetworkEvent::Update(ConnectionUpdate {
+                        rx_packets: unsigned_at(data, 0)?,
+                        rx_bytes: unsigned_at(data, 1)?,
+                        tx_packets: unsigned_at(data, 2)?,
+                        tx_bytes: unsigned_at(data, 3)?,
+                        rx_dups: unsigned_at(data, 4)?,
+                        rx_out_of_order: unsigned_at(data, 5)?,
+                        tx_retransmits: unsigned_at(data, 6)?,
+                        min_rtt: unsigned_at(data, 7)?,
+                        avg_rtt: unsigned_at(data, 8)?,
+                        connection_serial: unsigned_at(data, 9)?,
+                    }))
+                }
+                t => {
+                    warn!("Unknown network event type {t}");
+                    continue;
+                }
+            }
+        }
+    }
+}
+
+fn unsigned_at(data: &[Value], index: usize) -> Result<u64, IdeviceError> {
+    match data.get(index) {
+        Some(Value::Integer(i)) => match i.as_unsigned() {
+            Some(i) => Ok(i),
+            None => Ok(i.as_signed().unwrap_or_default() as u64),
+        },
+        _ => {
+            warn!("Network event field {index} was not an integer");
+            Err(IdeviceError::UnexpectedResponse)
+        }
+    }
+}
+
+fn sockaddr_at(data: &[Value], index: usize) -> Option<SocketAddr> {
+    match data.get(index) {
+        Some(Value::Data(d)) => parse_sockaddr(d),
+        _ => None,
+    }
+}
+
+/// Parses a raw ``sockaddr_in`` or ``sockaddr_in6`` as sent by the device
+fn parse_sockaddr(bytes: &[u8]) -> Option<SocketAddr> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let family = bytes[1];
+    let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+    match family {
+        // AF_INET
+        2 => {
+            let addr = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+            Some(SocketAddr::new(IpAddr::V4(addr), port))
+        }
+        // AF_INET6 on Darwin
+        30 => {
+            if bytes.len() < 24 {
+                return None;
+            }
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(&bytes[8..24]);
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(addr)), port))
+        }
+        _ => None,
+    }
+}
@@ -6,7 +6,9 @@ use log::{debug, warn};
 use tokio::io::AsyncWriteExt;
 
 use crate::{
-    dvt::message::{Aux, Message, MessageHeader, PayloadHeader},
+    codec::read_frame,
+    dvt::message::{Aux, Fragment, Message, MessageHeader, PayloadHeader},
+    util::check_message_size,
     IdeviceError, ReadWrite,
 };
 
@@ -14,11 +16,16 @@ use super::message::AuxValue;
 
 pub const INSTRUMENTS_MESSAGE_TYPE: u32 = 2;
 
+/// Dropping the client closes the connection without cancelling its channels, so
+/// instruments that were streaming keep running until the device notices it's gone.
+/// ``close`` cancels them first.
 pub struct RemoteServerClient<R: ReadWrite> {
     idevice: R,
     current_message: u32,
     new_channel: u32,
     channels: HashMap<u32, VecDeque<Message>>,
+    /// Messages still coming in fragments, by identifier, with the pieces so far
+    fragments: HashMap<u32, (MessageHeader, Vec<u8>)>,
 }
 
 pub struct Channel<'a, R: ReadWrite> {
@@ -35,6 +42,7 @@ impl<R: ReadWrite> RemoteServerClient<R> {
             current_message: 0,
             new_channel: 1,
             channels,
+            fragments: HashMap::new(),
         }
     }
 
@@ -58,10 +66,7 @@ impl<R: ReadWrite> RemoteServerClient<R> {
 
         let args = vec![
             AuxValue::U32(code),
-            AuxValue::Array(
-                ns_keyed_archive::encode::encode_to_bytes(plist::Value::String(identifier.into()))
-                    .expect("Failed to encode"),
-            ),
+            AuxValue::archived_value(identifier.into()),
         ];
 
         let mut root = self.root_channel();
@@ -82,6 +87,37 @@ impl<R: ReadWrite> RemoteServerClient<R> {
         self.build_channel(code)
     }
 
+    /// Tells the device a channel isn't needed anymore, which stops the instrument behind it.
+    /// Messages still cached for the channel are dropped.
+    pub async fn cancel_channel(&mut self, channel: u32) -> Result<(), IdeviceError> {
+        if channel == 0 || self.channels.remove(&channel).is_none() {
+            return Err(IdeviceError::UnknownChannel(channel));
+        }
+        self.call_method(
+            0,
+            Some("_channelCanceled:"),
+            Some(vec![AuxValue::U32(channel)]),
+            false,
+        )
+        .await
+    }
+
+    /// Cancels every channel that's still open and shuts the connection down
+    pub async fn close(mut self) -> Result<(), IdeviceError> {
+        let mut channels = self
+            .channels
+            .keys()
+            .copied()
+            .filter(|c| *c != 0)
+            .collect::<Vec<u32>>();
+        channels.sort();
+        for channel in channels {
+            self.cancel_channel(channel).await?;
+        }
+        self.idevice.shutdown().await?;
+        Ok(())
+    }
+
     fn build_channel(&mut self, code: u32) -> Result<Channel<R>, IdeviceError> {
         Ok(Channel {
             client: self,
@@ -122,7 +158,7 @@ impl<R: ReadWrite> RemoteServerClient<R> {
         }
 
         loop {
-            let msg = Message::from_reader(&mut self.idevice).await?;
+            let msg = self.read_whole_message().await?;
             debug!("Read message: {msg:#?}");
 
             if msg.message_header.channel == channel {
@@ -137,9 +173,50 @@ impl<R: ReadWrite> RemoteServerClient<R> {
             }
         }
     }
+
+    /// Reads frames until a message is complete. Large messages, like tap buffers, come in
+    /// fragments that can be interleaved with other messages, so the pieces are kept by the
+    /// message's identifier until the last one arrives.
+    async fn read_whole_message(&mut self) -> Result<Message, IdeviceError> {
+        loop {
+            let fragment: Fragment = read_frame(&mut self.idevice).await?;
+            let header = fragment.header;
+            if header.fragment_count() <= 1 {
+                return Fragment::reassemble(header, &fragment.body);
+            }
+
+            let identifier = header.identifier();
+            if header.fragment_id() == 0 {
+                self.fragments.insert(identifier, (header, Vec::new()));
+                continue;
+            }
+            let (_, body) = match self.fragments.get_mut(&identifier) {
+                Some(f) => f,
+                None => {
+                    warn!("Received a fragment of message {identifier} without its first fragment");
+                    continue;
+                }
+            };
+            if let Err(e) = check_message_size(body.len() + fragment.body.len()) {
+                self.fragments.remove(&identifier);
+                return Err(e);
+            }
+            body.extend_from_slice(&fragment.body);
+
+            if header.fragment_id() + 1 == header.fragment_count() {
+                let (first, body) = self.fragments.remove(&identifier).unwrap();
+                return Fragment::reassemble(first, &body);
+            }
+        }
+    }
 }
 
 impl<R: ReadWrite> Channel<'_, R> {
+    /// Cancels the channel on the device. The root channel can't be cancelled.
+    pub async fn close(self) -> Result<(), IdeviceError> {
+        self.client.cancel_channel(self.channel).await
+    }
+
     pub async fn read_message(&mut self) -> Result<Message, IdeviceError> {
         self.client.read_message(self.channel).await
     }
@@ -155,3 +232,69 @@ impl<R: ReadWrite> Channel<'_, R> {
             .await
     }
 }
+
+#[cfg(all(test, feature = "testing"))]
+pub(super) mod tests {
+    use super::*;
+    use crate::testing::MockTransport;
+
+    /// A tap buffer on the channel, sent as is after a payload header without auxiliary
+    /// values, split into fragments of at most ``piece_len`` bytes
+    pub fn tap_fragments(
+        identifier: u32,
+        channel: u32,
+        data: &[u8],
+        piece_len: usize,
+    ) -> Vec<Fragment> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        body.extend_from_slice(data);
+
+        let pieces: Vec<&[u8]> = body.chunks(piece_len).collect();
+        let count = pieces.len() as u16 + 1;
+        let mut fragments = vec![Fragment {
+            header: MessageHeader::new(0, count, identifier, 0, channel, false),
+            body: Vec::new(),
+        }];
+        for (i, piece) in pieces.into_iter().enumerate() {
+            fragments.push(Fragment {
+                header: MessageHeader::new(i as u16 + 1, count, identifier, 0, channel, false),
+                body: piece.to_vec(),
+            });
+        }
+        fragments
+    }
+
+    #[tokio::test]
+    async fn fragmented_messages_are_put_back_together() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let fragments = tap_fragments(7, 1, &data, 40_000);
+        assert_eq!(fragments.len(), 4);
+
+        let (host, mut device) = MockTransport::pair();
+        let mut client = RemoteServerClient::new(host);
+        client.channels.insert(1, VecDeque::new());
+
+        for (i, fragment) in fragments.iter().enumerate() {
+            device.write_frame(fragment).await.unwrap();
+            if i == 1 {
+                // Other messages can come in between the pieces
+                let reply = Message::new(
+                    MessageHeader::new(0, 1, 8, 0, 0, false),
+                    PayloadHeader::new(),
+                    None,
+                    Some("ok".into()),
+                );
+                device.write_frame(&reply).await.unwrap();
+            }
+        }
+
+        let msg = client.read_message(1).await.unwrap();
+        assert_eq!(msg.data, Some(plist::Value::Data(data)));
+        assert!(client.fragments.is_empty());
+        let msg = client.read_message(0).await.unwrap();
+        assert_eq!(msg.data, Some("ok".into()));
+    }
+}
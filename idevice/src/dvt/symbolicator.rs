@@ -0,0 +1,327 @@
+// Jackson Coxson
+// Turning sampled addresses back into function names.
+// The device only sends addresses, so names come from symbol files on the host.
+
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::IdeviceError;
+
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_SYMTAB: u32 = 0x2;
+const LC_UUID: u32 = 0x1b;
+
+const N_STAB: u8 = 0xe0;
+const N_TYPE: u8 = 0x0e;
+const N_SECT: u8 = 0x0e;
+
+pub trait Symbolicator {
+    /// The name of the function containing the address, if known
+    fn symbolicate(&self, address: u64) -> Option<String>;
+}
+
+/// Leaves every frame as an address
+pub struct NoSymbols;
+
+impl Symbolicator for NoSymbols {
+    fn symbolicate(&self, _address: u64) -> Option<String> {
+        None
+    }
+}
+
+/// Tries each symbolicator in order, for processes with several images
+impl<S: Symbolicator> Symbolicator for [S] {
+    fn symbolicate(&self, address: u64) -> Option<String> {
+        self.iter().find_map(|s| s.symbolicate(address))
+    }
+}
+
+impl<S: Symbolicator> Symbolicator for Vec<S> {
+    fn symbolicate(&self, address: u64) -> Option<String> {
+        self.as_slice().symbolicate(address)
+    }
+}
+
+/// Symbols of one image from its dSYM, or any unstripped 64 bit Mach-O
+#[derive(Debug, Clone)]
+pub struct DsymSymbolicator {
+    uuid: Option<[u8; 16]>,
+    /// Where the image's ``__TEXT`` segment was loaded on the device
+    load_address: u64,
+    text_vmaddr: u64,
+    text_size: u64,
+    /// Sorted by address
+    symbols: Vec<(u64, String)>,
+}
+
+impl DsymSymbolicator {
+    /// Reads a dSYM bundle, or the Mach-O file inside one.
+    /// The load address is where the image's ``__TEXT`` segment is in the sampled process.
+    pub fn open(path: impl AsRef<Path>, load_address: u64) -> Result<Self, IdeviceError> {
+        let path = path.as_ref();
+        let path = if path.is_dir() {
+            dwarf_file(path)?
+        } else {
+            path.to_path_buf()
+        };
+        let bytes = std::fs::read(&path)?;
+        Self::parse(&bytes, load_address)
+    }
+
+    pub fn parse(bytes: &[u8], load_address: u64) -> Result<Self, IdeviceError> {
+        let image = match read_u32(bytes, 0) {
+            Some(MH_MAGIC_64) => bytes,
+            // Universal headers are big endian
+            Some(m) if m.swap_bytes() == FAT_MAGIC => arm64_slice(bytes)?,
+            _ => return Err(invalid("not a 64 bit Mach-O file")),
+        };
+
+        let command_count = read_u32(image, 16).ok_or_else(|| invalid("truncated header"))?;
+        let mut uuid = None;
+        let mut text = None;
+        let mut symtab = None;
+        let mut offset = 32;
+        for _ in 0..command_count {
+            let (command, size) = match (read_u32(image, offset), read_u32(image, offset + 4)) {
+                (Some(c), Some(s)) if s >= 8 => (c, s as usize),
+                _ => return Err(invalid("truncated load command")),
+            };
+            match command {
+                LC_UUID => {
+                    uuid = image
+                        .get(offset + 8..offset + 24)
+                        .and_then(|u| u.try_into().ok());
+                }
+                LC_SEGMENT_64 => {
+                    let name = image
+                        .get(offset + 8..offset + 24)
+                        .ok_or_else(|| invalid("truncated segment"))?;
+                    if name.starts_with(b"__TEXT\0") {
+                        text = read_u64(image, offset + 24).zip(read_u64(image, offset + 32));
+                    }
+                }
+                LC_SYMTAB => {
+                    symtab = (|| {
+                        Some((
+                            read_u32(image, offset + 8)? as usize,
+                            read_u32(image, offset + 12)? as usize,
+                            read_u32(image, offset + 16)? as usize,
+                            read_u32(image, offset + 20)? as usize,
+                        ))
+                    })();
+                }
+                _ => {}
+            }
+            offset += size;
+        }
+
+        let (text_vmaddr, text_size) = text.ok_or_else(|| invalid("no __TEXT segment"))?;
+        let (symbol_offset, symbol_count, strings_offset, strings_size) =
+            symtab.ok_or_else(|| invalid("no symbol table"))?;
+        let strings = image
+            .get(strings_offset..strings_offset.saturating_add(strings_size))
+            .ok_or_else(|| invalid("string table out of bounds"))?;
+
+        let mut symbols = Vec::new();
+        for i in 0..symbol_count {
+            // nlist_64
+            let entry = symbol_offset + i * 16;
+            let (name, kind, value) = match (
+                read_u32(image, entry),
+                image.get(entry + 4),
+                read_u64(image, entry + 8),
+            ) {
+                (Some(n), Some(k), Some(v)) => (n as usize, *k, v),
+                _ => return Err(invalid("symbol table out of bounds")),
+            };
+            // Debugger entries and undefined symbols have no code of their own
+            if kind & N_STAB != 0 || kind & N_TYPE != N_SECT {
+                continue;
+            }
+            let name = match strings.get(name..) {
+                Some(n) => n.split(|b| *b == 0).next().unwrap_or_default(),
+                None => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let name = String::from_utf8_lossy(name);
+            // C symbols get a leading underscore
+            let name = name.strip_prefix('_').unwrap_or(&name).to_string();
+            symbols.push((value, name));
+        }
+        symbols.sort();
+        symbols.dedup_by_key(|(a, _)| *a);
+        debug!("Loaded {} symbols", symbols.len());
+
+        Ok(Self {
+            uuid,
+            load_address,
+            text_vmaddr,
+            text_size,
+            symbols,
+        })
+    }
+
+    /// The image's UUID, to match it with the images loaded in the process
+    pub fn uuid(&self) -> Option<[u8; 16]> {
+        self.uuid
+    }
+}
+
+impl Symbolicator for DsymSymbolicator {
+    fn symbolicate(&self, address: u64) -> Option<String> {
+        let offset = address.checked_sub(self.load_address)?;
+        if offset >= self.text_size {
+            return None;
+        }
+        let address = self.text_vmaddr + offset;
+        let i = self.symbols.partition_point(|(a, _)| *a <= address);
+        let (_, name) = self.symbols.get(i.checked_sub(1)?)?;
+        Some(name.clone())
+    }
+}
+
+/// The Mach-O file in a dSYM bundle
+fn dwarf_file(bundle: &Path) -> Result<PathBuf, IdeviceError> {
+    let dir = bundle.join("Contents").join("Resources").join("DWARF");
+    match std::fs::read_dir(&dir)?.next() {
+        Some(entry) => Ok(entry?.path()),
+        None => Err(invalid("dSYM bundle has no DWARF file")),
+    }
+}
+
+fn arm64_slice(bytes: &[u8]) -> Result<&[u8], IdeviceError> {
+    let count = read_u32(bytes, 4).map(u32::swap_bytes).unwrap_or_default();
+    for i in 0..count as usize {
+        // fat_arch
+        let entry = 8 + i * 20;
+        let field = |o| read_u32(bytes, entry + o).map(u32::swap_bytes);
+        if field(0) != Some(CPU_TYPE_ARM64) {
+            continue;
+        }
+        let (offset, size) = match (field(8), field(12)) {
+            (Some(o), Some(s)) => (o as usize, s as usize),
+            _ => break,
+        };
+        return bytes
+            .get(offset..offset.saturating_add(size))
+            .ok_or_else(|| invalid("arm64 slice out of bounds"));
+    }
+    Err(invalid("no arm64 slice"))
+}
+
+fn invalid(reason: &str) -> IdeviceError {
+    IdeviceError::InvalidSymbolFile(reason.to_string())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Mach-O with a __TEXT segment at 0x100000000 and three functions
+    fn macho() -> Vec<u8> {
+        let strings = b"\0_main\0_work\0helper\0";
+        let symbols: [(u32, u8, u64); 4] = [
+            (1, N_SECT | 0x01, 0x1_0000_1000),
+            (7, N_SECT | 0x01, 0x1_0000_1100),
+            (13, N_SECT, 0x1_0000_1200),
+            // A debugger entry that has to be skipped
+            (13, N_STAB, 0x1_0000_1180),
+        ];
+
+        let commands_size = 72 + 24 + 24;
+        let symbols_offset = 32 + commands_size;
+        let strings_offset = symbols_offset + symbols.len() * 16;
+
+        let mut bytes = Vec::new();
+        for field in [
+            MH_MAGIC_64,
+            CPU_TYPE_ARM64,
+            0,
+            2,
+            3,
+            commands_size as u32,
+            0,
+            0,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&LC_SEGMENT_64.to_le_bytes());
+        bytes.extend_from_slice(&72u32.to_le_bytes());
+        bytes.extend_from_slice(b"__TEXT\0\0\0\0\0\0\0\0\0\0");
+        bytes.extend_from_slice(&0x1_0000_0000u64.to_le_bytes());
+        bytes.extend_from_slice(&0x4000u64.to_le_bytes());
+        bytes.extend_from_slice(&[0; 32]);
+
+        bytes.extend_from_slice(&LC_UUID.to_le_bytes());
+        bytes.extend_from_slice(&24u32.to_le_bytes());
+        bytes.extend_from_slice(&[0xab; 16]);
+
+        for field in [
+            LC_SYMTAB,
+            24,
+            symbols_offset as u32,
+            symbols.len() as u32,
+            strings_offset as u32,
+            strings.len() as u32,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+
+        for (name, kind, value) in symbols {
+            bytes.extend_from_slice(&name.to_le_bytes());
+            bytes.extend_from_slice(&[kind, 1, 0, 0]);
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(strings);
+        bytes
+    }
+
+    #[test]
+    fn addresses_are_resolved_with_the_slide() {
+        let symbols = DsymSymbolicator::parse(&macho(), 0x1_0420_0000).unwrap();
+        assert_eq!(symbols.uuid(), Some([0xab; 16]));
+
+        let resolve = |a| symbols.symbolicate(a);
+        assert_eq!(resolve(0x1_0420_0f00), None);
+        assert_eq!(resolve(0x1_0420_1000).as_deref(), Some("main"));
+        assert_eq!(resolve(0x1_0420_1190).as_deref(), Some("work"));
+        assert_eq!(resolve(0x1_0420_1300).as_deref(), Some("helper"));
+        // Past the end of __TEXT
+        assert_eq!(resolve(0x1_0420_4000), None);
+
+        let both = vec![
+            symbols,
+            DsymSymbolicator::parse(&macho(), 0x2_0000_0000).unwrap(),
+        ];
+        assert_eq!(both.symbolicate(0x2_0000_1000).as_deref(), Some("main"));
+        assert_eq!(NoSymbols.symbolicate(0x2_0000_1000), None);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(matches!(
+            DsymSymbolicator::parse(b"not a binary", 0),
+            Err(IdeviceError::InvalidSymbolFile(_))
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+// Jackson Coxson
+// Thermal pressure from Instruments' system monitor tap.
+// The device's thermal pressure level is one of the system attributes sysmontap can sample.
+// Its name differs between versions, so it's looked up in the list deviceinfo returns.
+// Changes come back as a stream, and a ThermalTimeline turns them into periods that test
+// results can be checked against.
+
+use std::time::{Duration, SystemTime};
+
+use log::{debug, warn};
+use plist::Value;
+
+use crate::{dvt::message::AuxValue, IdeviceError, ReadWrite};
+
+use super::remote_server::{Channel, RemoteServerClient};
+
+const IDENTIFIER: &str = "com.apple.instruments.server.services.sysmontap";
+const DEVICE_INFO_IDENTIFIER: &str = "com.apple.instruments.server.services.deviceinfo";
+
+/// The states ``ProcessInfo.thermalState`` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ThermalState {
+    Nominal,
+    Fair,
+    /// The system is reducing performance
+    Serious,
+    Critical,
+}
+
+impl ThermalState {
+    /// Maps a kernel thermal pressure level. Trapping and sleeping are both critical.
+    pub fn from_pressure_level(level: u64) -> Self {
+        match level {
+            0 => Self::Nominal,
+            1 => Self::Fair,
+            2 => Self::Serious,
+            _ => Self::Critical,
+        }
+    }
+
+    /// Whether results measured in this state are likely to be throttled
+    pub fn is_throttling(&self) -> bool {
+        *self >= Self::Serious
+    }
+}
+
+/// The thermal state changing, or the first state read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThermalChange {
+    pub state: ThermalState,
+    pub previous: Option<ThermalState>,
+    /// Host clock when the sample was received
+    pub received: SystemTime,
+}
+
+pub struct ThermalMonitorClient<'a, R: ReadWrite> {
+    channel: Channel<'a, R>,
+    attribute: String,
+    last: Option<ThermalState>,
+}
+
+impl<'a, R: ReadWrite> ThermalMonitorClient<'a, R> {
+    /// Fails with ``ThermalStateUnavailable`` if the device can't sample its thermal state
+    pub async fn new(client: &'a mut RemoteServerClient<R>) -> Result<Self, IdeviceError> {
+        let attribute = {
+            let mut device_info = client.make_channel(DEVICE_INFO_IDENTIFIER).await?;
+            device_info
+                .call_method(Some("sysmonSystemAttributes"), None, true)
+                .await?;
+            let attributes = match device_info.read_message().await?.data {
+                Some(Value::Array(a)) => a,
+                _ => {
+                    warn!("System attributes were not an array");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            match find_thermal_attribute(&attributes) {
+                Some(a) => a,
+                None => {
+                    debug!("System attributes: {attributes:?}");
+                    return Err(IdeviceError::ThermalStateUnavailable);
+                }
+            }
+        };
+        let channel = client.make_channel(IDENTIFIER).await?;
+
+        Ok(Self {
+            channel,
+            attribute,
+            last: None,
+        })
+    }
+
+    /// Starts sampling the thermal state each interval
+    pub async fn start(&mut self, interval: Duration) -> Result<(), IdeviceError> {
+        let mut config = plist::Dictionary::new();
+        config.insert("ur".into(), (interval.as_millis() as u64).into());
+        config.insert("sampleInterval".into(), (interval.as_nanos() as u64).into());
+        config.insert("bm".into(), 0u64.into());
+        config.insert("cpuUsage".into(), false.into());
+        config.insert("procAttrs".into(), Value::Array(Vec::new()));
+        config.insert(
+            "sysAttrs".into(),
+            Value::Array(vec![self.attribute.as_str().into()]),
+        );
+
+        self.channel
+            .call_method(
+                Some("setConfig:"),
+                Some(vec![AuxValue::archived_value(config)]),
+                false,
+            )
+            .await?;
+        self.channel.call_method(Some("start"), None, false).await
+    }
+
+    pub async fn stop(&mut self) -> Result<(), IdeviceError> {
+        self.channel.call_method(Some("stop"), None, false).await
+    }
+
+    /// Waits until the thermal state changes. The first call returns the current state.
+    pub async fn next_change(&mut self) -> Result<ThermalChange, IdeviceError> {
+        loop {
+            let msg = self.channel.read_message().await?;
+            let state = match msg.data.as_ref().and_then(parse_update) {
+                Some(s) => s,
+                None => continue,
+            };
+            if self.last == Some(state) {
+                continue;
+            }
+            let previous = self.last.replace(state);
+            return Ok(ThermalChange {
+                state,
+                previous,
+                received: SystemTime::now(),
+            });
+        }
+    }
+}
+
+fn find_thermal_attribute(attributes: &[Value]) -> Option<String> {
+    attributes
+        .iter()
+        .filter_map(|a| a.as_string())
+        .find(|a| a.to_lowercase().contains("thermal"))
+        .map(|a| a.to_string())
+}
+
+/// Pulls the sampled level out of a sysmontap update. Only one attribute is requested, so
+/// it's the first system value.
+fn parse_update(data: &Value) -> Option<ThermalState> {
+    // Updates come wrapped in a tap message
+    let data = match data
+        .as_dictionary()
+        .and_then(|d| d.get("DTTapMessagePlist"))
+    {
+        Some(d) => d,
+        None => data,
+    };
+    let tables = match data {
+        Value::Array(a) => a.as_slice(),
+        Value::Dictionary(_) => std::slice::from_ref(data),
+        _ => return None,
+    };
+    tables.iter().rev().find_map(|table| {
+        table
+            .as_dictionary()
+            .and_then(|t| t.get("System"))
+            .and_then(|s| s.as_array())
+            .and_then(|s| s.first())
+            .and_then(|l| l.as_unsigned_integer())
+            .map(ThermalState::from_pressure_level)
+    })
+}
+
+/// A stretch of time spent in one state. ``end`` is ``None`` while it's still going.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThermalPeriod {
+    pub state: ThermalState,
+    pub start: SystemTime,
+    pub end: Option<SystemTime>,
+}
+
+impl ThermalPeriod {
+    pub fn contains(&self, time: SystemTime) -> bool {
+        time >= self.start && self.end.map(|e| time < e).unwrap_or(true)
+    }
+}
+
+/// The changes seen so far, as periods
+#[derive(Debug, Clone, Default)]
+pub struct ThermalTimeline {
+    periods: Vec<ThermalPeriod>,
+}
+
+impl ThermalTimeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, change: &ThermalChange) {
+        if let Some(last) = self.periods.last_mut() {
+            last.end = Some(change.received);
+        }
+        self.periods.push(ThermalPeriod {
+            state: change.state,
+            start: change.received,
+            end: None,
+        });
+    }
+
+    pub fn periods(&self) -> &[ThermalPeriod] {
+        &self.periods
+    }
+
+    /// The state at a point in time, if it was being monitored then
+    pub fn state_at(&self, time: SystemTime) -> Option<ThermalState> {
+        self.periods
+            .iter()
+            .find(|p| p.contains(time))
+            .map(|p| p.state)
+    }
+
+    /// Periods a measurement taken in them was likely throttled
+    pub fn throttled(&self) -> impl Iterator<Item = &ThermalPeriod> {
+        self.periods.iter().filter(|p| p.state.is_throttling())
+    }
+
+    /// Whether any throttling overlapped the given span, such as a benchmark run
+    pub fn throttled_between(&self, start: SystemTime, end: SystemTime) -> bool {
+        self.throttled()
+            .any(|p| p.start < end && p.end.map(|e| e > start).unwrap_or(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(level: u64) -> Value {
+        let mut table = plist::Dictionary::new();
+        table.insert("System".into(), Value::Array(vec![level.into()]));
+        let mut message = plist::Dictionary::new();
+        message.insert(
+            "DTTapMessagePlist".into(),
+            Value::Array(vec![Value::Dictionary(table)]),
+        );
+        Value::Dictionary(message)
+    }
+
+    #[test]
+    fn states_become_periods() {
+        assert_eq!(
+            find_thermal_attribute(&["vmFreeCount".into(), "thermalPressure".into()]),
+            Some("thermalPressure".to_string())
+        );
+        assert_eq!(parse_update(&update(2)), Some(ThermalState::Serious));
+        assert_eq!(parse_update(&update(4)), Some(ThermalState::Critical));
+        assert_eq!(parse_update(&Value::from("x")), None);
+
+        let start = SystemTime::UNIX_EPOCH;
+        let at = |s: u64| start + Duration::from_secs(s);
+        let mut timeline = ThermalTimeline::new();
+        for (state, s) in [
+            (ThermalState::Nominal, 0),
+            (ThermalState::Serious, 10),
+            (ThermalState::Fair, 20),
+        ] {
+            timeline.push(&ThermalChange {
+                state,
+                previous: None,
+                received: at(s),
+            });
+        }
+
+        assert_eq!(timeline.periods().len(), 3);
+        assert_eq!(timeline.state_at(at(15)), Some(ThermalState::Serious));
+        assert_eq!(timeline.state_at(at(100)), Some(ThermalState::Fair));
+        assert!(timeline.throttled_between(at(5), at(12)));
+        assert!(!timeline.throttled_between(at(0), at(10)));
+        assert!(!timeline.throttled_between(at(20), at(30)));
+    }
+}
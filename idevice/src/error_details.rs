@@ -0,0 +1,351 @@
+// Jackson Coxson
+// Error codes and details that don't depend on the English messages.
+// Apps show their own, localized messages by matching on the code, and log the details.
+// Codes are part of the API: once released, a code isn't renamed or reused.
+
+use std::collections::BTreeMap;
+
+use crate::IdeviceError;
+
+/// What's known about an error, in a form to match on rather than show
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDetails {
+    /// The stable code, the same as ``IdeviceError::code``
+    pub code: &'static str,
+    /// The service the error came from, when only one service returns it
+    pub service: Option<&'static str>,
+    /// What was being done, when the error only comes from one operation
+    pub operation: Option<&'static str>,
+    /// The ``Error`` key the device answered with, such as ``PasswordProtected``
+    pub response: Option<String>,
+    /// Free text from the device or the crate. It isn't localized and can change.
+    pub message: Option<String>,
+}
+
+impl ErrorDetails {
+    /// The details as string pairs, leaving out the ones that aren't known
+    pub fn to_map(&self) -> BTreeMap<&'static str, String> {
+        let mut map = BTreeMap::new();
+        map.insert("code", self.code.to_string());
+        if let Some(service) = self.service {
+            map.insert("service", service.to_string());
+        }
+        if let Some(operation) = self.operation {
+            map.insert("operation", operation.to_string());
+        }
+        if let Some(response) = &self.response {
+            map.insert("response", response.clone());
+        }
+        if let Some(message) = &self.message {
+            map.insert("message", message.clone());
+        }
+        map
+    }
+
+    /// For the errors lockdown answers with, which keep the name of its error key
+    fn lockdown(&mut self, response: &str) {
+        self.service = Some(LOCKDOWN);
+        self.response = Some(response.to_string());
+    }
+}
+
+const LOCKDOWN: &str = "com.apple.mobile.lockdown";
+#[cfg(feature = "dvt")]
+const DVT: &str = "com.apple.instruments.dtservicehub";
+
+impl IdeviceError {
+    /// A code for the kind of error, stable across releases unlike the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Socket(_) => "socket_io",
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Ssl(_) => "ssl_io",
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::SslSetup(_) => "ssl_setup",
+            Self::Plist(_) => "plist",
+            Self::Utf8(_) => "utf8",
+            Self::UnexpectedResponse => "unexpected_response",
+            Self::GetProhibited => "get_prohibited",
+            Self::SessionInactive => "session_inactive",
+            Self::InvalidHostID => "invalid_host_id",
+            Self::NoEstablishedConnection => "no_established_connection",
+            Self::HeartbeatSleepyTime => "heartbeat_sleepy_time",
+            Self::HeartbeatTimeout => "heartbeat_timeout",
+            Self::NotFound => "not_found",
+            Self::CdtunnelPacketTooShort => "cdtunnel_packet_too_short",
+            Self::CdtunnelPacketInvalidMagic => "cdtunnel_packet_invalid_magic",
+            Self::PacketSizeMismatch => "packet_size_mismatch",
+            #[cfg(any(
+                feature = "core_device_proxy",
+                feature = "debug_proxy",
+                feature = "remote_pairing",
+                feature = "report",
+                feature = "tunnel_supervisor",
+                feature = "web_inspector"
+            ))]
+            Self::Json(_) => "json",
+            Self::DeviceNotFound => "device_not_found",
+            Self::DeviceLocked => "device_locked",
+            Self::UsbConnectionRefused => "usb_connection_refused",
+            Self::UsbBadCommand => "usb_bad_command",
+            Self::UsbBadDevice => "usb_bad_device",
+            Self::UsbBadVersion => "usb_bad_version",
+            Self::BadBuildManifest => "bad_build_manifest",
+            Self::ImageNotMounted => "image_not_mounted",
+            Self::DeveloperImageNotMounted => "developer_image_not_mounted",
+            #[cfg(any(feature = "tss", feature = "tunneld", feature = "webhook_sink"))]
+            Self::Reqwest(_) => "http",
+            Self::InternalError(_) => "internal_error",
+            Self::FeatureDisabled(_) => "feature_disabled",
+            #[cfg(feature = "xpc")]
+            Self::Xpc(_) => "xpc",
+            #[cfg(feature = "nskeyed")]
+            Self::NsKeyedArchiveError(_) => "nskeyed_archive",
+            #[cfg(feature = "dvt")]
+            Self::UnknownAuxValueType(_) => "unknown_aux_value_type",
+            #[cfg(feature = "dvt")]
+            Self::UnknownChannel(_) => "unknown_channel",
+            Self::AddrParseError(_) => "addr_parse",
+            #[cfg(feature = "dvt")]
+            Self::DisableMemoryLimitFailed => "disable_memory_limit_failed",
+            #[cfg(feature = "dvt")]
+            Self::InvalidSymbolFile(_) => "invalid_symbol_file",
+            #[cfg(feature = "dvt")]
+            Self::ThermalStateUnavailable => "thermal_state_unavailable",
+            Self::NotEnoughBytes(_, _) => "not_enough_bytes",
+            Self::Utf8Error => "invalid_utf8",
+            Self::InvalidArgument => "invalid_argument",
+            #[cfg(feature = "debug_proxy")]
+            Self::DebugserverError(_) => "debugserver",
+            Self::UnknownErrorType(_) => "unknown_device_error",
+            Self::InvalidPairingOffer => "invalid_pairing_offer",
+            Self::PairingOfferMismatch => "pairing_offer_mismatch",
+            Self::Timeout => "timeout",
+            Self::InvalidPairingFile(_) => "invalid_pairing_file",
+            Self::PairingRecordNotFound(_) => "pairing_record_not_found",
+            Self::PairingDialogResponsePending => "pairing_dialog_response_pending",
+            Self::UserDeniedPairing => "user_denied_pairing",
+            Self::InvalidPairRecord => "invalid_pair_record",
+            Self::PasswordProtected => "password_protected",
+            Self::MessageTooLarge(_) => "message_too_large",
+            #[cfg(feature = "ipa")]
+            Self::Zip(_) => "zip",
+            #[cfg(feature = "ipa")]
+            Self::InvalidIpa(_) => "invalid_ipa",
+            #[cfg(feature = "atc")]
+            Self::SyncFailed(_) => "sync_failed",
+            #[cfg(feature = "mcinstall")]
+            Self::ProfileRequestFailed(_) => "profile_request_failed",
+            #[cfg(feature = "diagnostics")]
+            Self::DiagnosticsError(_) => "diagnostics",
+            #[cfg(feature = "afc")]
+            Self::AfcError(_) => "afc",
+            #[cfg(feature = "afc")]
+            Self::HashMismatch { .. } => "hash_mismatch",
+            #[cfg(feature = "file_relay")]
+            Self::FileRelayError(_) => "file_relay",
+            #[cfg(feature = "house_arrest")]
+            Self::HouseArrestError(_) => "house_arrest",
+            #[cfg(feature = "mobile_backup")]
+            Self::MobileBackupError(_) => "mobile_backup",
+            #[cfg(feature = "notification_proxy")]
+            Self::NotificationProxyError(_) => "notification_proxy",
+            #[cfg(feature = "sync_lock")]
+            Self::SyncInProgress => "sync_in_progress",
+            #[cfg(feature = "screenshot")]
+            Self::ScreenshotError(_) => "screenshot",
+            #[cfg(feature = "web_inspector")]
+            Self::InspectorCommandFailed(_) => "inspector_command_failed",
+            #[cfg(feature = "web_inspector")]
+            Self::JavaScriptException(_) => "javascript_exception",
+            #[cfg(all(feature = "afc", not(target_arch = "wasm32")))]
+            Self::InvalidAfcPath(_) => "invalid_afc_path",
+            #[cfg(feature = "recovery")]
+            Self::Rusb(_) => "usb",
+            #[cfg(feature = "amfi")]
+            Self::AmfiError(_) => "amfi",
+            #[cfg(feature = "remote_pairing")]
+            Self::RemotePairingFailed(_) => "remote_pairing_failed",
+            #[cfg(feature = "quic_tunnel")]
+            Self::QuicTunnelFailed(_) => "quic_tunnel_failed",
+        }
+    }
+
+    /// The code along with whatever the error knows about where it came from
+    pub fn details(&self) -> ErrorDetails {
+        let mut details = ErrorDetails {
+            code: self.code(),
+            service: None,
+            operation: None,
+            response: None,
+            message: None,
+        };
+
+        match self {
+            Self::GetProhibited => details.lockdown("GetProhibited"),
+            Self::SessionInactive => details.lockdown("SessionInactive"),
+            Self::InvalidHostID => details.lockdown("InvalidHostID"),
+            Self::PairingDialogResponsePending => details.lockdown("PairingDialogResponsePending"),
+            Self::UserDeniedPairing => details.lockdown("UserDeniedPairing"),
+            Self::InvalidPairRecord => details.lockdown("InvalidPairRecord"),
+            Self::PasswordProtected => details.lockdown("PasswordProtected"),
+            Self::DeviceLocked => details.response = Some("DeviceLocked".to_string()),
+            Self::UnknownErrorType(e) => details.response = Some(e.clone()),
+            Self::HeartbeatSleepyTime | Self::HeartbeatTimeout => {
+                details.service = Some("com.apple.mobile.heartbeat");
+            }
+            Self::ImageNotMounted | Self::BadBuildManifest => {
+                details.service = Some("com.apple.mobile.mobile_image_mounter");
+                details.operation = Some("mount");
+            }
+            Self::InternalError(m) => details.message = Some(m.clone()),
+            Self::FeatureDisabled(f) => details.message = Some(f.to_string()),
+            Self::InvalidPairingFile(m) | Self::PairingRecordNotFound(m) => {
+                details.message = Some(m.clone())
+            }
+            #[cfg(feature = "dvt")]
+            Self::UnknownAuxValueType(_) | Self::UnknownChannel(_) => details.service = Some(DVT),
+            #[cfg(feature = "dvt")]
+            Self::DisableMemoryLimitFailed => {
+                details.service = Some(DVT);
+                details.operation = Some("disable_memory_limit");
+            }
+            #[cfg(feature = "dvt")]
+            Self::InvalidSymbolFile(m) => {
+                details.service = Some(DVT);
+                details.operation = Some("symbolicate");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "dvt")]
+            Self::ThermalStateUnavailable => {
+                details.service = Some(DVT);
+                details.operation = Some("thermal_state");
+            }
+            #[cfg(feature = "debug_proxy")]
+            Self::DebugserverError(m) => {
+                details.service = Some("com.apple.internal.dt.remote.debugproxy");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "ipa")]
+            Self::InvalidIpa(m) => {
+                details.operation = Some("inspect_ipa");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "atc")]
+            Self::SyncFailed(m) => {
+                details.service = Some("com.apple.atc");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "mcinstall")]
+            Self::ProfileRequestFailed(m) => {
+                details.service = Some("com.apple.mobile.MCInstall");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "diagnostics")]
+            Self::DiagnosticsError(m) => {
+                details.service = Some("com.apple.mobile.diagnostics_relay");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "afc")]
+            Self::AfcError(m) => {
+                details.service = Some("com.apple.afc");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "afc")]
+            Self::HashMismatch { path, .. } => {
+                details.service = Some("com.apple.afc");
+                details.operation = Some("verify_file");
+                details.message = Some(path.clone());
+            }
+            #[cfg(all(feature = "afc", not(target_arch = "wasm32")))]
+            Self::InvalidAfcPath(e) => {
+                details.service = Some("com.apple.afc");
+                details.message = Some(e.to_string());
+            }
+            #[cfg(feature = "file_relay")]
+            Self::FileRelayError(m) => {
+                details.service = Some("com.apple.mobile.file_relay");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "house_arrest")]
+            Self::HouseArrestError(m) => {
+                details.service = Some("com.apple.mobile.house_arrest");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "mobile_backup")]
+            Self::MobileBackupError(m) => {
+                details.service = Some("com.apple.mobile.backup");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "notification_proxy")]
+            Self::NotificationProxyError(m) => {
+                details.service = Some("com.apple.mobile.notification_proxy");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "sync_lock")]
+            Self::SyncInProgress => {
+                details.service = Some("com.apple.afc");
+                details.operation = Some("sync_lock");
+            }
+            #[cfg(feature = "screenshot")]
+            Self::ScreenshotError(m) => {
+                details.service = Some("com.apple.screenshotr");
+                details.operation = Some("take_screenshot");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "web_inspector")]
+            Self::InspectorCommandFailed(m) | Self::JavaScriptException(m) => {
+                details.service = Some("com.apple.webinspector");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "amfi")]
+            Self::AmfiError(m) => {
+                details.service = Some("com.apple.amfi.lockdown");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "remote_pairing")]
+            Self::RemotePairingFailed(m) => {
+                details.operation = Some("remote_pairing");
+                details.message = Some(m.clone());
+            }
+            #[cfg(feature = "quic_tunnel")]
+            Self::QuicTunnelFailed(m) => {
+                details.operation = Some("quic_tunnel");
+                details.message = Some(m.clone());
+            }
+            // The rest come from the host, and their source error is in the message
+            _ => details.message = std::error::Error::source(self).map(|s| s.to_string()),
+        }
+        details
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn details_are_machine_readable() {
+        let e = IdeviceError::PasswordProtected;
+        assert_eq!(e.code(), "password_protected");
+        let details = e.details();
+        assert_eq!(details.service, Some(LOCKDOWN));
+        assert_eq!(details.response.as_deref(), Some("PasswordProtected"));
+
+        let e = IdeviceError::UnknownErrorType("MissingValue".to_string());
+        let map = e.details().to_map();
+        assert_eq!(
+            map.get("code").map(|c| c.as_str()),
+            Some("unknown_device_error")
+        );
+        assert_eq!(
+            map.get("response").map(|c| c.as_str()),
+            Some("MissingValue")
+        );
+        assert!(!map.contains_key("service"));
+
+        let e = IdeviceError::Socket(std::io::ErrorKind::BrokenPipe.into());
+        assert_eq!(e.code(), "socket_io");
+        assert_eq!(e.details().message.as_deref(), Some("broken pipe"));
+    }
+}
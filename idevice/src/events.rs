@@ -0,0 +1,261 @@
+// Jackson Coxson
+// One stream of everything happening on a device.
+// Monitoring tools otherwise need a usbmuxd listener, a notification_proxy client and an
+// os_trace_relay stream, each in its own task. ``subscribe`` runs those tasks and fans them in.
+
+use std::{sync::Arc, time::SystemTime};
+
+use log::debug;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::{
+    os_trace_relay::{LogEntry, LogLevel, OsTraceRelayClient},
+    provider::IdeviceProvider,
+    runtime::Task,
+    usbmuxd::{UsbmuxdAddr, UsbmuxdEvent},
+    IdeviceError, IdeviceService,
+};
+
+#[cfg(feature = "notification_proxy")]
+use crate::notification_proxy::{NotificationProxyClient, NotificationType};
+
+/// Events waiting for the subscriber before the sources stop reading
+const EVENT_BUFFER: usize = 256;
+
+/// A source of events to subscribe to
+#[derive(Debug, Clone)]
+pub enum Subscription {
+    /// Devices attaching to and detaching from usbmuxd.
+    /// This covers every device, not just the provider's.
+    Usbmuxd(UsbmuxdAddr),
+    /// The given notifications posted on the device
+    #[cfg(feature = "notification_proxy")]
+    Notifications(Vec<NotificationType>),
+    /// Log messages at or above a level
+    Logs {
+        min_level: LogLevel,
+        /// Only messages from this process
+        pid: Option<u32>,
+    },
+}
+
+impl Subscription {
+    /// Errors and faults from every process
+    pub fn errors() -> Self {
+        Self::Logs {
+            min_level: LogLevel::Error,
+            pid: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    Usbmuxd(UsbmuxdEvent),
+    #[cfg(feature = "notification_proxy")]
+    Notification(NotificationType),
+    Log(LogEntry),
+}
+
+/// An event and when it was received
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub time: SystemTime,
+    pub event: DeviceEvent,
+}
+
+/// Events from all subscribed sources, in the order they were received.
+/// Dropping the stream stops the sources.
+#[derive(Debug)]
+pub struct EventStream {
+    rx: mpsc::Receiver<Result<Event, IdeviceError>>,
+    tasks: Vec<Task<()>>,
+}
+
+impl EventStream {
+    /// Waits for the next event.
+    /// A source that fails yields its error once and stops, while the others keep going.
+    /// Returns ``None`` once every source has stopped.
+    pub async fn next(&mut self) -> Option<Result<Event, IdeviceError>> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        for task in &mut self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Timestamps events and sends them on.
+/// Sending under a lock keeps the timestamps in the same order as the stream.
+#[derive(Clone)]
+struct Sink {
+    tx: mpsc::Sender<Result<Event, IdeviceError>>,
+    lock: Arc<Mutex<()>>,
+}
+
+impl Sink {
+    /// Returns false once the subscriber is gone
+    async fn send(&self, event: Result<DeviceEvent, IdeviceError>) -> bool {
+        let _guard = self.lock.lock().await;
+        let event = event.map(|event| Event {
+            time: SystemTime::now(),
+            event,
+        });
+        self.tx.send(event).await.is_ok()
+    }
+}
+
+/// Connects to every subscribed source and merges their events into one stream.
+/// Connecting is done up front, so a source that can't be reached fails the whole call.
+pub async fn subscribe(
+    provider: &dyn IdeviceProvider,
+    subscriptions: &[Subscription],
+) -> Result<EventStream, IdeviceError> {
+    let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+    let sink = Sink {
+        tx,
+        lock: Arc::new(Mutex::new(())),
+    };
+
+    let mut stream = EventStream {
+        rx,
+        tasks: Vec::new(),
+    };
+    for subscription in subscriptions {
+        // Tasks already started are aborted by the stream's drop if this fails
+        let task = match subscription {
+            Subscription::Usbmuxd(addr) => {
+                let mut conn = addr.connect(0).await?;
+                conn.listen().await?;
+                let sink = sink.clone();
+                crate::runtime::spawn(async move {
+                    loop {
+                        let event = conn.next_event().await;
+                        let failed = event.is_err();
+                        if !sink.send(event.map(DeviceEvent::Usbmuxd)).await || failed {
+                            break;
+                        }
+                    }
+                })
+            }
+            #[cfg(feature = "notification_proxy")]
+            Subscription::Notifications(notifications) => {
+                let mut client = NotificationProxyClient::connect(provider).await?;
+                client.observe_notifications(notifications).await?;
+                let mut notifications = client.start_listening().await?;
+                let sink = sink.clone();
+                crate::runtime::spawn(async move {
+                    // The client stops listening when dropped
+                    let _client = client;
+                    while let Some(n) = notifications.recv().await {
+                        if !sink.send(Ok(DeviceEvent::Notification(n))).await {
+                            return;
+                        }
+                    }
+                    sink.send(Err(IdeviceError::NotificationProxyError(
+                        "Notification stream closed".to_string(),
+                    )))
+                    .await;
+                })
+            }
+            Subscription::Logs { min_level, pid } => {
+                let mut client = OsTraceRelayClient::connect(provider).await?;
+                client.start_trace(*pid).await?;
+                let min_level = *min_level;
+                let sink = sink.clone();
+                crate::runtime::spawn(async move {
+                    loop {
+                        let entry = match client.next_log().await {
+                            Ok(e) if e.level < min_level => continue,
+                            e => e,
+                        };
+                        let failed = entry.is_err();
+                        if !sink.send(entry.map(DeviceEvent::Log)).await || failed {
+                            break;
+                        }
+                    }
+                })
+            }
+        };
+        stream.tasks.push(task);
+    }
+    debug!("Subscribed to {} event sources", stream.tasks.len());
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn logs_are_filtered_and_errors_end_the_source() {
+        use std::{future::Future, pin::Pin};
+        use tokio::io::AsyncWriteExt;
+
+        use crate::testing::{MockProvider, MockTransport, Responder};
+
+        struct TraceResponder;
+        impl Responder for TraceResponder {
+            fn serve(
+                &self,
+                mut transport: MockTransport,
+            ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+                Box::pin(async move {
+                    let req = transport.read_plist().await?;
+                    assert_eq!(
+                        req.get("Request").and_then(|r| r.as_string()),
+                        Some("StartActivity")
+                    );
+
+                    let mut res = plist::Dictionary::new();
+                    res.insert("Status".into(), "RequestSuccessful".into());
+                    let mut res_bytes = Vec::new();
+                    plist::Value::Dictionary(res).to_writer_xml(&mut res_bytes)?;
+                    transport.write_all(&4_u32.to_le_bytes()).await?;
+                    transport
+                        .write_all(&(res_bytes.len() as u32).to_le_bytes())
+                        .await?;
+                    transport.write_all(&res_bytes).await?;
+
+                    for (pid, level) in [(1_u32, 0x01), (2, 0x10)] {
+                        let mut entry = vec![0; 130];
+                        entry[9..13].copy_from_slice(&pid.to_le_bytes());
+                        entry[68] = level;
+                        transport.write_all(&[0x02]).await?;
+                        transport
+                            .write_all(&(entry.len() as u32).to_le_bytes())
+                            .await?;
+                        transport.write_all(&entry).await?;
+                    }
+                    Ok(())
+                })
+            }
+        }
+
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(OsTraceRelayClient::service_name(), TraceResponder);
+        let mut events = subscribe(&provider, &[Subscription::errors()])
+            .await
+            .unwrap();
+
+        match events.next().await {
+            Some(Ok(Event {
+                event: DeviceEvent::Log(entry),
+                ..
+            })) => {
+                assert_eq!(entry.pid, 2);
+                assert_eq!(entry.level, LogLevel::Error);
+            }
+            e => panic!("Expected the error log, got {e:?}"),
+        }
+        assert!(matches!(events.next().await, Some(Err(_))));
+        assert!(events.next().await.is_none());
+    }
+}
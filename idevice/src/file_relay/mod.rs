@@ -2,7 +2,7 @@
 //! 
 //! This module provides functionality to retrieve various files and logs from iOS devices.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
+use crate::{provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::collections::HashSet;
 
@@ -52,17 +52,27 @@ impl FileRelaySource {
 
 /// File Relay client for retrieving files and logs from iOS devices
 pub struct FileRelayClient {
-    socket: tokio::net::TcpStream,
+    socket: Box<dyn ReadWrite>,
+}
+
+impl IdeviceService for FileRelayClient {
+    fn service_name() -> &'static str {
+        FILE_RELAY_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl FileRelayClient {
-    /// Connect to the File Relay service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(FILE_RELAY_SERVICE_NAME).await?;
-        
-        Ok(Self {
-            socket: service.socket,
-        })
+    /// Create a client over an existing connection to the File Relay service
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        Self { socket }
     }
 
     /// Request files from the device
@@ -102,7 +112,7 @@ impl FileRelayClient {
         self.socket.read_exact(&mut length_buf).await?;
         let length = u32::from_be_bytes(length_buf) as usize;
         
-        let mut data = vec![0u8; length];
+        let mut data = vec![0u8; crate::util::check_message_size(length)?];
         self.socket.read_exact(&mut data).await?;
         
         Ok(data)
@@ -110,8 +120,8 @@ impl FileRelayClient {
 
     // Helper methods
     async fn send_plist(&mut self, dict: &plist::Dictionary) -> Result<(), IdeviceError> {
-        let xml = plist::to_format_xml(dict)?;
-        let xml_bytes = xml.into_bytes();
+        let mut xml_bytes = Vec::new();
+        plist::to_writer_xml(&mut xml_bytes, dict)?;
         
         // Send the length as a 32-bit big-endian integer
         let len = (xml_bytes.len() as u32).to_be_bytes();
@@ -130,7 +140,7 @@ impl FileRelayClient {
         let len = u32::from_be_bytes(len_buf) as usize;
         
         // Read the XML data
-        let mut data = vec![0u8; len];
+        let mut data = vec![0u8; crate::util::check_message_size(len)?];
         self.socket.read_exact(&mut data).await?;
         
         // Parse the XML data
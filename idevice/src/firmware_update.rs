@@ -0,0 +1,165 @@
+// Jackson Coxson
+// Checks a device's build against Apple's OTA catalog.
+// HTTP is left to the caller through HttpFetcher, so this doesn't pull in a client.
+
+use std::{future::Future, pin::Pin};
+
+use log::warn;
+
+use crate::{lockdownd::LockdowndClient, util::compare_versions, IdeviceError};
+
+/// The public software update catalog
+pub const OTA_CATALOG_URL: &str = "https://mesu.apple.com/assets/com_apple_MobileAsset_SoftwareUpdate/com_apple_MobileAsset_SoftwareUpdate.xml";
+
+/// Fetches a URL and returns the body.
+/// This is an ugly trait until async traits are stabilized
+pub trait HttpFetcher: Send + Sync {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, IdeviceError>> + Send + 'a>>;
+}
+
+/// The build the device is running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Such as ``iPhone15,2``
+    pub product_type: String,
+    /// Such as ``17.4.1``
+    pub product_version: String,
+    /// Such as ``21E237``
+    pub build_version: String,
+}
+
+impl BuildInfo {
+    pub async fn from_lockdown(lockdown: &mut LockdowndClient) -> Result<Self, IdeviceError> {
+        Ok(Self {
+            product_type: string_value(lockdown, "ProductType").await?,
+            product_version: string_value(lockdown, "ProductVersion").await?,
+            build_version: string_value(lockdown, "BuildVersion").await?,
+        })
+    }
+}
+
+async fn string_value(lockdown: &mut LockdowndClient, key: &str) -> Result<String, IdeviceError> {
+    match lockdown.get_value(key).await?.into_string() {
+        Some(v) => Ok(v),
+        None => {
+            warn!("{key} was not a string");
+            Err(IdeviceError::UnexpectedResponse)
+        }
+    }
+}
+
+/// A full update in the OTA catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtaAsset {
+    pub version: String,
+    pub build: String,
+    pub supported_devices: Vec<String>,
+    /// Download URL of the update
+    pub url: Option<String>,
+    pub download_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateStatus {
+    UpToDate,
+    UpdateAvailable(OtaAsset),
+    /// The catalog has no full updates for the device
+    NotInCatalog,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateCheck {
+    pub current: BuildInfo,
+    pub status: UpdateStatus,
+}
+
+impl UpdateCheck {
+    pub fn is_update_available(&self) -> bool {
+        matches!(self.status, UpdateStatus::UpdateAvailable(_))
+    }
+}
+
+/// Compares the device's build against the OTA catalog
+pub async fn check_for_update(
+    lockdown: &mut LockdowndClient,
+    http: &dyn HttpFetcher,
+) -> Result<UpdateCheck, IdeviceError> {
+    let current = BuildInfo::from_lockdown(lockdown).await?;
+    let catalog = fetch_catalog(http, OTA_CATALOG_URL).await?;
+
+    let status = match latest_for(&catalog, &current.product_type) {
+        Some(latest) => {
+            if compare_versions(&latest.version, &current.product_version)
+                == std::cmp::Ordering::Greater
+            {
+                UpdateStatus::UpdateAvailable(latest.clone())
+            } else {
+                UpdateStatus::UpToDate
+            }
+        }
+        None => UpdateStatus::NotInCatalog,
+    };
+
+    Ok(UpdateCheck { current, status })
+}
+
+/// Downloads and parses an OTA catalog.
+/// Delta updates and betas are left out, since they depend on what's installed.
+pub async fn fetch_catalog(
+    http: &dyn HttpFetcher,
+    url: &str,
+) -> Result<Vec<OtaAsset>, IdeviceError> {
+    let body = http.get(url).await?;
+    let catalog: plist::Dictionary = plist::from_bytes(&body)?;
+
+    let assets = match catalog.get("Assets").and_then(|a| a.as_array()) {
+        Some(a) => a,
+        None => {
+            warn!("OTA catalog has no assets");
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+    };
+
+    Ok(assets
+        .iter()
+        .filter_map(|a| a.as_dictionary())
+        .filter(|a| !a.contains_key("PrerequisiteBuild"))
+        .filter(|a| a.get("ReleaseType").and_then(|r| r.as_string()) != Some("Beta"))
+        .filter_map(|a| {
+            let string = |key: &str| a.get(key).and_then(|v| v.as_string());
+            let url = match (string("__BaseURL"), string("__RelativePath")) {
+                (Some(base), Some(path)) => Some(format!("{base}{path}")),
+                _ => None,
+            };
+
+            // Some entries are versioned as 9.9.<version>
+            Some(OtaAsset {
+                version: string("OSVersion")?.trim_start_matches("9.9.").to_string(),
+                build: string("Build")?.to_string(),
+                supported_devices: a
+                    .get("SupportedDevices")
+                    .and_then(|d| d.as_array())
+                    .map(|d| {
+                        d.iter()
+                            .filter_map(|d| d.as_string())
+                            .map(|d| d.to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                url,
+                download_size: a.get("_DownloadSize").and_then(|s| s.as_unsigned_integer()),
+            })
+        })
+        .collect())
+}
+
+/// The newest asset that supports the product type
+pub fn latest_for<'a>(catalog: &'a [OtaAsset], product_type: &str) -> Option<&'a OtaAsset> {
+    catalog
+        .iter()
+        .filter(|a| a.supported_devices.iter().any(|d| d == product_type))
+        .max_by(|a, b| compare_versions(&a.version, &b.version))
+}
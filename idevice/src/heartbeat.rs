@@ -1,7 +1,9 @@
 // Jackson Coxson
 // Abstractions for the heartbeat service on iOS
 
-use crate::{lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+use std::{future::Future, time::Duration};
+
+use crate::{codec::WireFormat, lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
 
 pub struct HeartbeatClient {
     pub idevice: Idevice,
@@ -29,12 +31,17 @@ impl IdeviceService for HeartbeatClient {
                 .await?;
         }
 
-        Ok(Self { idevice })
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice))
     }
 }
 
 impl HeartbeatClient {
-    pub fn new(idevice: Idevice) -> Self {
+    pub fn new(mut idevice: Idevice) -> Self {
+        idevice.default_wire_format(WireFormat::for_service(Self::service_name()));
         Self { idevice }
     }
 
@@ -42,7 +49,9 @@ impl HeartbeatClient {
         // Get a plist or wait for the interval
         let rec = tokio::select! {
             rec = self.idevice.read_plist() => rec?,
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs(interval)) => {
+            _ = crate::runtime::sleep(Duration::from_secs(interval)) => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::heartbeat_missed();
                 return Err(IdeviceError::HeartbeatTimeout)
             }
         };
@@ -75,4 +84,63 @@ impl HeartbeatClient {
             .await?;
         Ok(())
     }
+
+    /// Answers heartbeats for as long as the device sends them, so it only returns once
+    /// they stop: with ``HeartbeatSleepyTime`` when the device goes to sleep,
+    /// ``HeartbeatTimeout`` when one is missed, or the connection's error. It never
+    /// returns ``Ok``, and is meant to be raced against other work or cancelled.
+    pub async fn keep_alive(&mut self) -> Result<(), IdeviceError> {
+        let mut interval = 15;
+        loop {
+            // Give the device some slack past the interval it asked for
+            interval = self.get_marco(interval + 5).await?;
+            self.send_polo().await?;
+        }
+    }
+}
+
+/// Runs the operation while answering heartbeats on a separate connection.
+/// Long-running jobs such as backups or large transfers get dropped by the device if
+/// nothing answers the heartbeat service.
+/// # Returns
+/// The operation's result, or the heartbeat error if the heartbeat failed first
+pub async fn with_keepalive<F, T>(
+    provider: &dyn crate::provider::IdeviceProvider,
+    op: F,
+) -> Result<T, IdeviceError>
+where
+    F: Future<Output = Result<T, IdeviceError>>,
+{
+    let mut heartbeat = HeartbeatClient::connect(provider).await?;
+
+    tokio::select! {
+        res = op => res,
+        res = heartbeat.keep_alive() => match res {
+            Ok(()) => Err(IdeviceError::UnexpectedResponse),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// Same as ``with_keepalive``, but holds the sync lock around the operation, so the device
+/// shows a sync and Finder or iTunes keep off it until the operation is done.
+/// The lock is given back even if the operation fails, and the operation's result is returned
+/// either way. Failing to give it back is only logged, since the work itself is already done
+/// or failed for its own reason.
+#[cfg(feature = "sync_lock")]
+pub async fn with_sync_keepalive<F, T>(
+    provider: &dyn crate::provider::IdeviceProvider,
+    op: F,
+) -> Result<T, IdeviceError>
+where
+    F: Future<Output = Result<T, IdeviceError>>,
+{
+    let lock = crate::sync_lock::sync_lock(provider).await?;
+
+    let res = with_keepalive(provider, op).await;
+
+    if let Err(e) = lock.release().await {
+        log::warn!("Failed to release the sync lock: {e:?}");
+    }
+    res
 }
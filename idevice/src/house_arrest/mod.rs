@@ -2,7 +2,7 @@
 //! 
 //! This module provides functionality to access app containers on iOS devices.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
+use crate::{afc::AfcClient, provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::collections::HashMap;
 
@@ -10,41 +10,47 @@ const HOUSE_ARREST_SERVICE_NAME: &str = "com.apple.mobile.house_arrest";
 
 /// House Arrest client for accessing app containers
 pub struct HouseArrestClient {
-    socket: tokio::net::TcpStream,
+    socket: Box<dyn ReadWrite>,
+}
+
+impl IdeviceService for HouseArrestClient {
+    fn service_name() -> &'static str {
+        HOUSE_ARREST_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl HouseArrestClient {
-    /// Connect to the House Arrest service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(HOUSE_ARREST_SERVICE_NAME).await?;
-        
-        Ok(Self {
-            socket: service.socket,
-        })
+    /// Create a client over an existing connection to the House Arrest service
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        Self { socket }
     }
 
-    /// Get an AFC client for accessing the app's Documents directory
-    pub async fn documents(&mut self, bundle_id: &str) -> Result<crate::afc::AfcClient, IdeviceError> {
+    /// Get an AFC client for accessing the app's Documents directory.
+    /// The connection switches over to AFC, so this client is used up.
+    pub async fn documents(mut self, bundle_id: &str) -> Result<AfcClient, IdeviceError> {
         self.send_command("VendDocuments", bundle_id).await?;
         self.check_result().await?;
         
         // The service has now switched to AFC protocol
-        Ok(crate::afc::AfcClient {
-            socket: std::mem::replace(&mut self.socket, tokio::net::TcpStream::connect("0.0.0.0:0").await.unwrap()),
-            packet_num: 0,
-        })
+        Ok(AfcClient::new(self.socket))
     }
 
-    /// Get an AFC client for accessing the app's Container directory
-    pub async fn container(&mut self, bundle_id: &str) -> Result<crate::afc::AfcClient, IdeviceError> {
+    /// Get an AFC client for accessing the app's Container directory.
+    /// The connection switches over to AFC, so this client is used up.
+    pub async fn container(mut self, bundle_id: &str) -> Result<AfcClient, IdeviceError> {
         self.send_command("VendContainer", bundle_id).await?;
         self.check_result().await?;
         
         // The service has now switched to AFC protocol
-        Ok(crate::afc::AfcClient {
-            socket: std::mem::replace(&mut self.socket, tokio::net::TcpStream::connect("0.0.0.0:0").await.unwrap()),
-            packet_num: 0,
-        })
+        Ok(AfcClient::new(self.socket))
     }
 
     /// List installed applications
@@ -78,7 +84,7 @@ impl HouseArrestClient {
         
         if let Some(info) = result.get("LookupResult") {
             if let Some(info_dict) = info.as_dictionary() {
-                return Ok(info_dict.clone());
+                return Ok(info_dict.clone().into_iter().collect());
             }
         }
         
@@ -91,8 +97,8 @@ impl HouseArrestClient {
         dict.insert("Command".into(), command.into());
         dict.insert("Identifier".into(), bundle_id.into());
         
-        let xml = plist::to_format_xml(&dict)?;
-        let xml_bytes = xml.into_bytes();
+        let mut xml_bytes = Vec::new();
+        plist::to_writer_xml(&mut xml_bytes, &dict)?;
         
         // Send the length as a 32-bit big-endian integer
         let len = (xml_bytes.len() as u32).to_be_bytes();
@@ -111,7 +117,7 @@ impl HouseArrestClient {
         let len = u32::from_be_bytes(len_buf) as usize;
         
         // Read the XML data
-        let mut data = vec![0u8; len];
+        let mut data = vec![0u8; crate::util::check_message_size(len)?];
         self.socket.read_exact(&mut data).await?;
         
         // Parse the XML data
@@ -139,4 +145,133 @@ impl HouseArrestClient {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Sandbox storage used by an app, in bytes
+#[derive(Debug, Clone, Default)]
+pub struct AppStorageReport {
+    pub bundle_id: String,
+    pub documents: u64,
+    /// Library, not counting Library/Caches. None if only Documents could be vended.
+    pub library: Option<u64>,
+    pub caches: Option<u64>,
+    pub tmp: Option<u64>,
+}
+
+impl AppStorageReport {
+    /// Total of every directory that could be measured
+    pub fn total(&self) -> u64 {
+        self.documents
+            + self.library.unwrap_or(0)
+            + self.caches.unwrap_or(0)
+            + self.tmp.unwrap_or(0)
+    }
+}
+
+/// Measure an app's sandbox.
+///
+/// The whole container can only be vended for development-signed apps. For other
+/// file sharing apps this falls back to Documents, and the other sizes are None.
+pub async fn app_storage_report(
+    provider: &dyn IdeviceProvider,
+    bundle_id: &str,
+) -> Result<AppStorageReport, IdeviceError> {
+    // Vending hands the connection over to AFC, so each attempt needs its own connection
+    let house_arrest = HouseArrestClient::connect(provider).await?;
+    
+    match house_arrest.container(bundle_id).await {
+        Ok(mut afc) => {
+            let library = afc.path_contents_size("/Library").await?;
+            let caches = afc.path_contents_size("/Library/Caches").await?;
+            
+            Ok(AppStorageReport {
+                bundle_id: bundle_id.to_string(),
+                documents: afc.path_contents_size("/Documents").await?,
+                library: Some(library.saturating_sub(caches)),
+                caches: Some(caches),
+                tmp: Some(afc.path_contents_size("/tmp").await?),
+            })
+        }
+        Err(IdeviceError::HouseArrestError(_)) => {
+            let house_arrest = HouseArrestClient::connect(provider).await?;
+            let mut afc = house_arrest.documents(bundle_id).await?;
+            
+            // VendDocuments roots the connection at Documents itself
+            Ok(AppStorageReport {
+                bundle_id: bundle_id.to_string(),
+                documents: afc.path_contents_size("/").await?,
+                ..Default::default()
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Measure every app that has file sharing enabled
+pub async fn file_sharing_storage_reports(
+    provider: &dyn IdeviceProvider,
+) -> Result<Vec<AppStorageReport>, IdeviceError> {
+    let mut house_arrest = HouseArrestClient::connect(provider).await?;
+    
+    let mut file_sharing_apps = Vec::new();
+    for bundle_id in house_arrest.list_installed_applications().await? {
+        let info = house_arrest.get_application_info(&bundle_id).await?;
+        if let Some(true) = info.get("UIFileSharingEnabled").and_then(|v| v.as_boolean()) {
+            file_sharing_apps.push(bundle_id);
+        }
+    }
+    
+    let mut reports = Vec::with_capacity(file_sharing_apps.len());
+    for bundle_id in file_sharing_apps {
+        reports.push(app_storage_report(provider, &bundle_id).await?);
+    }
+    
+    Ok(reports)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{AfcResponder, MockProvider, MockTransport, Responder};
+    use std::{future::Future, pin::Pin};
+
+    /// Only vends Documents, like the device does for apps that aren't development-signed
+    struct DocumentsOnly(AfcResponder);
+
+    impl Responder for DocumentsOnly {
+        fn serve(
+            &self,
+            mut transport: MockTransport,
+        ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+            let documents = self.0.clone();
+            Box::pin(async move {
+                let req = transport.read_plist().await?;
+                let mut res = plist::Dictionary::new();
+                if req.get("Command").and_then(|c| c.as_string()) != Some("VendDocuments") {
+                    res.insert("Error".into(), "InstallationLookupFailed".into());
+                    return transport.send_plist(res).await;
+                }
+                res.insert("Status".into(), "Complete".into());
+                transport.send_plist(res).await?;
+                documents.serve(transport).await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn storage_report_falls_back_to_documents() {
+        let documents = AfcResponder::new()
+            .with_file("/notes.txt", vec![0; 10])
+            .with_file("/Inbox/photo.jpg", vec![0; 32]);
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(HOUSE_ARREST_SERVICE_NAME, DocumentsOnly(documents));
+
+        let report = app_storage_report(&provider, "com.example.notes")
+            .await
+            .unwrap();
+        assert_eq!(report.documents, 42);
+        assert_eq!(report.library, None);
+        assert_eq!(report.total(), 42);
+    }
+}
@@ -0,0 +1,151 @@
+// Jackson Coxson
+// Inspects, signs, stages and installs an .ipa.
+// Signing is left to a Signer so apps can bring their own zsign or ldid setup.
+
+use std::{future::Future, io::Cursor, pin::Pin};
+
+use log::warn;
+
+use crate::{
+    afc::AfcClient, installation_proxy::InstallationProxyClient, ipa::IpaInfo, IdeviceError,
+};
+
+/// AFC directory installd picks packages up from
+pub const STAGING_DIRECTORY: &str = "PublicStaging";
+
+/// Re-signs a package before it's uploaded.
+/// This is an ugly trait until async traits are stabilized
+pub trait Signer: Send + Sync {
+    /// Takes the inspected package and its bytes, and returns the signed package
+    fn sign<'a>(
+        &'a self,
+        info: &'a IpaInfo,
+        ipa: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, IdeviceError>> + Send + 'a>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallStage {
+    Inspecting,
+    Signing,
+    Uploading { sent: usize, total: usize },
+    Verifying,
+    Installing { percent: u64 },
+    Complete,
+}
+
+pub struct InstallPipeline {
+    pub afc: AfcClient,
+    pub instproxy: InstallationProxyClient,
+    signer: Option<Box<dyn Signer>>,
+}
+
+impl InstallPipeline {
+    pub fn new(afc: AfcClient, instproxy: InstallationProxyClient) -> Self {
+        Self {
+            afc,
+            instproxy,
+            signer: None,
+        }
+    }
+
+    /// Sets the signer to run between inspection and upload.
+    /// Without one, packages are installed as they are.
+    pub fn with_signer(mut self, signer: impl Signer + 'static) -> Self {
+        self.signer = Some(Box::new(signer));
+        self
+    }
+
+    /// Installs the package, calling the callback with (stage, state) as it progresses
+    /// # Arguments
+    /// `ipa` - The contents of the .ipa
+    /// `options` - Extra client options for the install request
+    /// # Returns
+    /// The metadata of the package that was installed, after signing
+    pub async fn install<Fut, S>(
+        &mut self,
+        ipa: Vec<u8>,
+        options: Option<plist::Dictionary>,
+        callback: impl Fn((InstallStage, S)) -> Fut,
+        state: S,
+    ) -> Result<IpaInfo, IdeviceError>
+    where
+        Fut: Future<Output = ()>,
+        S: Clone,
+    {
+        // The archive is already in memory, so this doesn't block on IO
+        callback((InstallStage::Inspecting, state.clone())).await;
+        let mut info = IpaInfo::from_reader(Cursor::new(&ipa))?;
+
+        let ipa = match &self.signer {
+            Some(signer) => {
+                callback((InstallStage::Signing, state.clone())).await;
+                let signed = signer.sign(&info, ipa).await?;
+                // The signer may have changed the bundle ID or profile
+                info = IpaInfo::from_reader(Cursor::new(&signed))?;
+                signed
+            }
+            None => ipa,
+        };
+
+        let bundle_id = match info.bundle_id() {
+            Some(b) => b.to_string(),
+            None => {
+                return Err(IdeviceError::InvalidIpa(
+                    "Info.plist has no CFBundleIdentifier".to_string(),
+                ))
+            }
+        };
+        let staged_path = format!("{STAGING_DIRECTORY}/{bundle_id}.ipa");
+
+        self.afc.make_directory(STAGING_DIRECTORY).await?;
+        // A package corrupted on the way gets one more upload before giving up
+        let mut retried = false;
+        loop {
+            self.afc
+                .write_file_with_progress(
+                    &staged_path,
+                    &ipa,
+                    |((sent, total), state)| {
+                        callback((InstallStage::Uploading { sent, total }, state))
+                    },
+                    state.clone(),
+                )
+                .await?;
+
+            callback((InstallStage::Verifying, state.clone())).await;
+            match self.afc.verify_file(&staged_path, &ipa).await {
+                Ok(()) => break,
+                Err(IdeviceError::HashMismatch { .. }) if !retried => {
+                    warn!("Staged package {staged_path} was corrupted, uploading it again");
+                    retried = true;
+                }
+                Err(e) => {
+                    if let Err(e) = self.afc.remove_path(&staged_path).await {
+                        warn!("Failed to remove staged package {staged_path}: {e:?}");
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let res = self
+            .instproxy
+            .install_with_callback(
+                staged_path.as_str(),
+                options,
+                |(percent, state)| callback((InstallStage::Installing { percent }, state)),
+                state.clone(),
+            )
+            .await;
+
+        // installd usually removes the package itself, but not when the install fails
+        if let Err(e) = self.afc.remove_path(&staged_path).await {
+            warn!("Failed to remove staged package {staged_path}: {e:?}");
+        }
+        res?;
+
+        callback((InstallStage::Complete, state)).await;
+        Ok(info)
+    }
+}
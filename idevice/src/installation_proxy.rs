@@ -2,8 +2,17 @@
 // Incomplete implementation for installation_proxy
 
 use std::collections::HashMap;
+#[cfg(feature = "notification_proxy")]
+use std::collections::VecDeque;
 
-use crate::{lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+#[cfg(feature = "notification_proxy")]
+use crate::notification_proxy::{NotificationProxyClient, NotificationType};
+use crate::{
+    lockdownd::LockdowndClient,
+    progress::{ProgressEvent, ProgressObserver},
+    util::compare_versions,
+    Idevice, IdeviceError, IdeviceService,
+};
 
 pub struct InstallationProxyClient {
     pub idevice: Idevice,
@@ -30,6 +39,10 @@ impl IdeviceService for InstallationProxyClient {
                 .await?;
         }
 
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
         Ok(Self::new(idevice))
     }
 }
@@ -58,7 +71,39 @@ impl InstallationProxyClient {
             options.insert("BundleIDs".into(), ids.into()).unwrap();
         }
         options.insert("ApplicationType".into(), application_type.into());
+        self.lookup(options).await
+    }
+
+    /// Gets only the given attributes of installed apps, which is much faster than
+    /// ``get_apps`` on devices with many apps
+    /// # Arguments
+    /// `application_type` - The application type to filter by
+    /// `attributes` - The keys to return, such as ``StaticDiskUsage``
+    pub async fn get_app_attributes(
+        &mut self,
+        application_type: Option<String>,
+        attributes: &[&str],
+    ) -> Result<HashMap<String, plist::Value>, IdeviceError> {
+        let mut options = plist::Dictionary::new();
+        options.insert(
+            "ApplicationType".into(),
+            application_type.unwrap_or("Any".to_string()).into(),
+        );
+        options.insert(
+            "ReturnAttributes".into(),
+            attributes
+                .iter()
+                .map(|a| plist::Value::from(*a))
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        self.lookup(options).await
+    }
 
+    async fn lookup(
+        &mut self,
+        options: plist::Dictionary,
+    ) -> Result<HashMap<String, plist::Value>, IdeviceError> {
         let mut req = plist::Dictionary::new();
         req.insert("Command".into(), "Lookup".into());
         req.insert("ClientOptions".into(), plist::Value::Dictionary(options));
@@ -74,4 +119,296 @@ impl InstallationProxyClient {
             _ => Err(IdeviceError::UnexpectedResponse),
         }
     }
+
+    /// Installs a package that has already been uploaded to the device
+    /// # Arguments
+    /// `package_path` - The path of the package, relative to the AFC root, such as ``PublicStaging/app.ipa``
+    /// `options` - Extra client options to send with the request
+    pub async fn install(
+        &mut self,
+        package_path: impl Into<String>,
+        options: Option<plist::Dictionary>,
+    ) -> Result<(), IdeviceError> {
+        self.install_with_callback(package_path, options, |_| async {}, ())
+            .await
+    }
+
+    /// Installs a package, calling the callback with (percent complete, state) as the device reports it
+    pub async fn install_with_callback<Fut, S>(
+        &mut self,
+        package_path: impl Into<String>,
+        options: Option<plist::Dictionary>,
+        callback: impl Fn((u64, S)) -> Fut,
+        state: S,
+    ) -> Result<(), IdeviceError>
+    where
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let mut req = plist::Dictionary::new();
+        req.insert("Command".into(), "Install".into());
+        req.insert("PackagePath".into(), package_path.into().into());
+        req.insert(
+            "ClientOptions".into(),
+            plist::Value::Dictionary(options.unwrap_or_default()),
+        );
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+
+        loop {
+            // Errors are raised by read_plist
+            let res = self.idevice.read_plist().await?;
+            if let Some(percent) = res
+                .get("PercentComplete")
+                .and_then(|p| p.as_unsigned_integer())
+            {
+                callback((percent, state.clone())).await;
+            }
+            if let Some("Complete") = res.get("Status").and_then(|s| s.as_string()) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// ``install`` reporting to the observer, with progress out of 100
+    pub async fn install_with_observer(
+        &mut self,
+        package_path: impl Into<String>,
+        options: Option<plist::Dictionary>,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        let res = self
+            .install_with_callback(
+                package_path,
+                options,
+                |(percent, observer): (u64, ProgressObserver)| async move {
+                    observer.emit(ProgressEvent::Progress {
+                        done: percent,
+                        total: 100,
+                    });
+                },
+                observer.clone(),
+            )
+            .await;
+        observer.complete(res)
+    }
+
+    /// Asks the device whether it has all the capabilities
+    /// # Arguments
+    /// `capabilities` - UIRequiredDeviceCapabilities style names, such as ``arm64`` or ``metal``
+    /// `options` - Extra client options to send with the request
+    pub async fn check_capabilities_match(
+        &mut self,
+        capabilities: Vec<String>,
+        options: Option<plist::Dictionary>,
+    ) -> Result<bool, IdeviceError> {
+        let capabilities = capabilities
+            .into_iter()
+            .map(plist::Value::String)
+            .collect::<Vec<plist::Value>>();
+
+        let mut req = plist::Dictionary::new();
+        req.insert("Command".into(), "CheckCapabilitiesMatch".into());
+        req.insert("Capabilities".into(), capabilities.into());
+        req.insert(
+            "ClientOptions".into(),
+            plist::Value::Dictionary(options.unwrap_or_default()),
+        );
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+
+        let res = self.idevice.read_plist().await?;
+        match res.get("LookupResult") {
+            Some(plist::Value::Boolean(b)) => Ok(*b),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    /// Checks an app's Info.plist against the device before anything is uploaded
+    /// # Arguments
+    /// `info_plist` - The app's Info.plist
+    /// `device_version` - The device's ``ProductVersion`` from lockdown
+    pub async fn check_app_compatibility(
+        &mut self,
+        info_plist: &plist::Dictionary,
+        device_version: &str,
+    ) -> Result<CompatibilityReport, IdeviceError> {
+        let mut issues = Vec::new();
+
+        if let Some(required) = info_plist
+            .get("MinimumOSVersion")
+            .and_then(|v| v.as_string())
+        {
+            if compare_versions(device_version, required) == std::cmp::Ordering::Less {
+                issues.push(Incompatibility::MinimumOsVersion {
+                    required: required.to_string(),
+                    device: device_version.to_string(),
+                });
+            }
+        }
+
+        // Either a list of required capabilities, or a map of capability to required/forbidden
+        let (required, forbidden) = match info_plist.get("UIRequiredDeviceCapabilities") {
+            Some(plist::Value::Array(a)) => (
+                a.iter()
+                    .filter_map(|c| c.as_string())
+                    .map(|c| c.to_string())
+                    .collect(),
+                Vec::new(),
+            ),
+            Some(plist::Value::Dictionary(d)) => {
+                let mut required = Vec::new();
+                let mut forbidden = Vec::new();
+                for (capability, value) in d {
+                    match value.as_boolean() {
+                        Some(true) => required.push(capability.clone()),
+                        Some(false) => forbidden.push(capability.clone()),
+                        None => continue,
+                    }
+                }
+                (required, forbidden)
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+
+        if !required.is_empty()
+            && !self
+                .check_capabilities_match(required.clone(), None)
+                .await?
+        {
+            // Narrow down which ones are missing
+            for capability in required {
+                if !self
+                    .check_capabilities_match(vec![capability.clone()], None)
+                    .await?
+                {
+                    issues.push(Incompatibility::MissingCapability(capability));
+                }
+            }
+        }
+        for capability in forbidden {
+            if self
+                .check_capabilities_match(vec![capability.clone()], None)
+                .await?
+            {
+                issues.push(Incompatibility::ForbiddenCapability(capability));
+            }
+        }
+
+        Ok(CompatibilityReport { issues })
+    }
+}
+
+/// Reasons an app can't be installed on a device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    MinimumOsVersion {
+        required: String,
+        device: String,
+    },
+    /// The app requires a capability the device doesn't have
+    MissingCapability(String),
+    /// The app can't run on devices with this capability
+    ForbiddenCapability(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompatibilityReport {
+    pub issues: Vec<Incompatibility>,
+}
+
+impl CompatibilityReport {
+    pub fn is_compatible(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[cfg(feature = "notification_proxy")]
+#[derive(Debug, Clone)]
+pub enum AppInstallEvent {
+    /// An app was installed or updated, with its Lookup info
+    AppInstalled {
+        bundle_id: String,
+        info: plist::Value,
+    },
+    /// An app was removed, with the last Lookup info seen for it
+    AppRemoved {
+        bundle_id: String,
+        info: plist::Value,
+    },
+}
+
+/// Turns install notifications into typed events by diffing Lookup results
+#[cfg(feature = "notification_proxy")]
+pub struct AppInstallWatcher {
+    instproxy: InstallationProxyClient,
+    notifications: tokio::sync::mpsc::Receiver<NotificationType>,
+    // Kept so the listener isn't torn down
+    _notification_client: NotificationProxyClient,
+    apps: HashMap<String, plist::Value>,
+    pending: VecDeque<AppInstallEvent>,
+}
+
+/// Starts watching for installs and removals.
+/// The current app list is taken as the baseline, so only later changes are reported.
+#[cfg(feature = "notification_proxy")]
+pub async fn watch_events(
+    mut instproxy: InstallationProxyClient,
+    mut notification_client: NotificationProxyClient,
+) -> Result<AppInstallWatcher, IdeviceError> {
+    notification_client
+        .observe_notifications(&[
+            NotificationType::AppInstalled,
+            NotificationType::AppUninstalled,
+        ])
+        .await?;
+    let notifications = notification_client.start_listening().await?;
+    let apps = instproxy.get_apps(None, None).await?;
+
+    Ok(AppInstallWatcher {
+        instproxy,
+        notifications,
+        _notification_client: notification_client,
+        apps,
+        pending: VecDeque::new(),
+    })
+}
+
+#[cfg(feature = "notification_proxy")]
+impl AppInstallWatcher {
+    /// Waits for the next install or removal
+    pub async fn next_event(&mut self) -> Result<AppInstallEvent, IdeviceError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            match self.notifications.recv().await {
+                Some(NotificationType::AppInstalled | NotificationType::AppUninstalled) => {}
+                Some(_) => continue,
+                None => return Err(IdeviceError::NoEstablishedConnection),
+            }
+
+            let apps = self.instproxy.get_apps(None, None).await?;
+            for (bundle_id, info) in apps.iter() {
+                // Updates replace the existing entry, so compare the whole record
+                if self.apps.get(bundle_id) != Some(info) {
+                    self.pending.push_back(AppInstallEvent::AppInstalled {
+                        bundle_id: bundle_id.clone(),
+                        info: info.clone(),
+                    });
+                }
+            }
+            for (bundle_id, info) in self.apps.drain() {
+                if !apps.contains_key(&bundle_id) {
+                    self.pending
+                        .push_back(AppInstallEvent::AppRemoved { bundle_id, info });
+                }
+            }
+            self.apps = apps;
+        }
+    }
 }
@@ -0,0 +1,240 @@
+// Jackson Coxson
+// Reads the metadata install flows need out of an .ipa without extracting it.
+// This does blocking file IO, so call it from spawn_blocking in async contexts.
+
+use std::{
+    io::{Read, Seek},
+    path::Path,
+};
+
+use log::warn;
+use openssl::{
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    stack::Stack,
+    x509::store::X509StoreBuilder,
+};
+
+use crate::IdeviceError;
+
+#[derive(Debug, Clone)]
+pub struct IpaInfo {
+    /// Path of the app bundle inside the archive, such as ``Payload/Example.app``
+    pub app_path: String,
+    pub info_plist: plist::Dictionary,
+    /// The decoded ``embedded.mobileprovision``, if the app has one
+    pub provisioning_profile: Option<plist::Dictionary>,
+}
+
+impl IpaInfo {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, IdeviceError> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Result<Self, IdeviceError> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+
+        // Payload/<name>.app/Info.plist, not any nested bundle's Info.plist
+        let app_path = match archive.file_names().find_map(|name| {
+            let rest = name.strip_prefix("Payload/")?;
+            let (app, file) = rest.split_once('/')?;
+            if app.ends_with(".app") && file == "Info.plist" {
+                Some(format!("Payload/{app}"))
+            } else {
+                None
+            }
+        }) {
+            Some(p) => p,
+            None => {
+                return Err(IdeviceError::InvalidIpa(
+                    "no app bundle in Payload".to_string(),
+                ))
+            }
+        };
+
+        let info_plist = read_entry(&mut archive, &format!("{app_path}/Info.plist"))?;
+        let info_plist = match plist::from_bytes::<plist::Dictionary>(&info_plist) {
+            Ok(i) => i,
+            Err(e) => {
+                warn!("Failed to parse Info.plist: {e:?}");
+                return Err(IdeviceError::InvalidIpa(
+                    "Info.plist is invalid".to_string(),
+                ));
+            }
+        };
+
+        let profile_path = format!("{app_path}/embedded.mobileprovision");
+        let provisioning_profile = if archive.index_for_name(&profile_path).is_some() {
+            Some(decode_profile(&read_entry(&mut archive, &profile_path)?)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            app_path,
+            info_plist,
+            provisioning_profile,
+        })
+    }
+
+    pub fn bundle_id(&self) -> Option<&str> {
+        self.info_string("CFBundleIdentifier")
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.info_string("CFBundleDisplayName")
+            .or_else(|| self.info_string("CFBundleName"))
+    }
+
+    /// The marketing version, ``CFBundleShortVersionString``
+    pub fn version(&self) -> Option<&str> {
+        self.info_string("CFBundleShortVersionString")
+    }
+
+    /// The build number, ``CFBundleVersion``
+    pub fn build(&self) -> Option<&str> {
+        self.info_string("CFBundleVersion")
+    }
+
+    pub fn minimum_os_version(&self) -> Option<&str> {
+        self.info_string("MinimumOSVersion")
+    }
+
+    /// The entitlements granted by the provisioning profile
+    pub fn entitlements(&self) -> Option<&plist::Dictionary> {
+        self.provisioning_profile
+            .as_ref()?
+            .get("Entitlements")?
+            .as_dictionary()
+    }
+
+    pub fn team_id(&self) -> Option<&str> {
+        let profile = self.provisioning_profile.as_ref()?;
+        match profile
+            .get("TeamIdentifier")
+            .and_then(|t| t.as_array())
+            .and_then(|t| t.first())
+            .and_then(|t| t.as_string())
+        {
+            Some(t) => Some(t),
+            None => self
+                .entitlements()?
+                .get("com.apple.developer.team-identifier")?
+                .as_string(),
+        }
+    }
+
+    /// Device UDIDs the profile is limited to. ``None`` for enterprise and App Store profiles.
+    pub fn provisioned_devices(&self) -> Option<Vec<&str>> {
+        Some(
+            self.provisioning_profile
+                .as_ref()?
+                .get("ProvisionedDevices")?
+                .as_array()?
+                .iter()
+                .filter_map(|d| d.as_string())
+                .collect(),
+        )
+    }
+
+    /// Whether the existing signature allows installing on the device as-is.
+    /// If not, the app has to be re-signed first.
+    pub fn is_provisioned_for(&self, udid: &str) -> bool {
+        let profile = match &self.provisioning_profile {
+            Some(p) => p,
+            None => return false,
+        };
+        if let Some(true) = profile
+            .get("ProvisionsAllDevices")
+            .and_then(|p| p.as_boolean())
+        {
+            return true;
+        }
+        match self.provisioned_devices() {
+            Some(devices) => devices.iter().any(|d| d.eq_ignore_ascii_case(udid)),
+            None => false,
+        }
+    }
+
+    fn info_string(&self, key: &str) -> Option<&str> {
+        self.info_plist.get(key).and_then(|v| v.as_string())
+    }
+}
+
+/// Largest entry read into memory. Only plists and profiles are read, which are far smaller.
+const MAX_ENTRY_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Reads an entry, without trusting the size in its header for more than a hint
+fn read_entry<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>, IdeviceError> {
+    let file = archive.by_name(name)?;
+    let mut buf = Vec::with_capacity(file.size().min(MAX_ENTRY_SIZE) as usize);
+    file.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > MAX_ENTRY_SIZE {
+        return Err(IdeviceError::InvalidIpa(format!("{name} is too large")));
+    }
+    Ok(buf)
+}
+
+/// Provisioning profiles are a plist wrapped in a CMS signature.
+/// The signature isn't checked, only the content is extracted.
+fn decode_profile(bytes: &[u8]) -> Result<plist::Dictionary, IdeviceError> {
+    let pkcs7 = Pkcs7::from_der(bytes)?;
+    let certs = Stack::new()?;
+    let store = X509StoreBuilder::new()?.build();
+
+    let mut content = Vec::new();
+    pkcs7.verify(
+        &certs,
+        &store,
+        None,
+        Some(&mut content),
+        Pkcs7Flags::NOVERIFY | Pkcs7Flags::NOSIGS,
+    )?;
+
+    match plist::from_bytes(&content) {
+        Ok(p) => Ok(p),
+        Err(e) => {
+            warn!("Failed to parse provisioning profile: {e:?}");
+            Err(IdeviceError::InvalidIpa(
+                "embedded.mobileprovision is invalid".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    fn ipa(info_plist: &[u8]) -> Cursor<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("Payload/Example.app/Info.plist", options)
+            .unwrap();
+        zip.write_all(info_plist).unwrap();
+        let mut reader = zip.finish().unwrap();
+        reader.set_position(0);
+        reader
+    }
+
+    #[test]
+    fn oversized_entries_are_refused() {
+        let mut info = plist::Dictionary::new();
+        info.insert("CFBundleIdentifier".into(), "com.example.app".into());
+        let mut xml = Vec::new();
+        plist::to_writer_xml(&mut xml, &info).unwrap();
+        let ipa_info = IpaInfo::from_reader(ipa(&xml)).unwrap();
+        assert_eq!(ipa_info.bundle_id(), Some("com.example.app"));
+
+        // Compresses to almost nothing, so the archive itself stays small
+        let huge = vec![b' '; MAX_ENTRY_SIZE as usize + 1];
+        assert!(matches!(
+            IpaInfo::from_reader(ipa(&huge)),
+            Err(IdeviceError::InvalidIpa(_))
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+// Jackson Coxson
+// Where pairing material is kept between runs.
+// Pairing records hold the host's private keys, so instead of reading and writing plists
+// directly, hosts can hand the crate a KeyStore backed by a keychain, secret service or
+// HSM. FsKeyStore is the default and keeps the plists in a directory like usbmuxd does.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{pairing_file::PairingFile, runtime::BoxFuture, IdeviceError};
+
+/// What a stored record is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordKind {
+    /// A lockdown ``PairingFile``, stored by the device's UDID
+    Lockdown,
+    /// A RemotePairing record, stored by the device's pairing identifier
+    RemotePairing,
+}
+
+/// Keeps serialized pairing records, private keys included.
+/// Implementations decide how the bytes are protected at rest.
+pub trait KeyStore: Send + Sync + fmt::Debug {
+    /// Returns ``None`` if nothing is stored under ``id``
+    fn load<'a>(
+        &'a self,
+        kind: RecordKind,
+        id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, IdeviceError>>;
+
+    fn save<'a>(
+        &'a self,
+        kind: RecordKind,
+        id: &'a str,
+        record: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), IdeviceError>>;
+
+    /// Removing a record that isn't stored is not an error
+    fn delete<'a>(
+        &'a self,
+        kind: RecordKind,
+        id: &'a str,
+    ) -> BoxFuture<'a, Result<(), IdeviceError>>;
+}
+
+impl dyn KeyStore + '_ {
+    pub async fn load_pairing_file(&self, udid: &str) -> Result<PairingFile, IdeviceError> {
+        match self.load(RecordKind::Lockdown, udid).await? {
+            Some(record) => PairingFile::from_bytes(&record),
+            None => Err(IdeviceError::PairingRecordNotFound(udid.to_string())),
+        }
+    }
+
+    pub async fn save_pairing_file(
+        &self,
+        udid: &str,
+        pairing_file: &PairingFile,
+    ) -> Result<(), IdeviceError> {
+        let record = pairing_file.clone().serialize()?;
+        self.save(RecordKind::Lockdown, udid, record).await
+    }
+
+    #[cfg(feature = "remote_pairing")]
+    pub async fn load_remote_pairing_file(
+        &self,
+        device_identifier: &str,
+    ) -> Result<crate::remote_pairing::RemotePairingFile, IdeviceError> {
+        match self
+            .load(RecordKind::RemotePairing, device_identifier)
+            .await?
+        {
+            Some(record) => crate::remote_pairing::RemotePairingFile::from_bytes(&record),
+            None => Err(IdeviceError::PairingRecordNotFound(
+                device_identifier.to_string(),
+            )),
+        }
+    }
+
+    /// Stores the record under the identifier of the device it pairs with
+    #[cfg(feature = "remote_pairing")]
+    pub async fn save_remote_pairing_file(
+        &self,
+        pairing_file: &crate::remote_pairing::RemotePairingFile,
+    ) -> Result<(), IdeviceError> {
+        let record = pairing_file.serialize()?;
+        self.save(
+            RecordKind::RemotePairing,
+            &pairing_file.device_identifier,
+            record,
+        )
+        .await
+    }
+}
+
+/// Stores records as plists in a directory, ``<udid>.plist`` for lockdown like
+/// ``/var/lib/lockdown`` and ``<identifier>.remote.plist`` for RemotePairing.
+/// Files are only readable by the owner on unix.
+#[derive(Debug, Clone)]
+pub struct FsKeyStore {
+    dir: PathBuf,
+}
+
+impl FsKeyStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, kind: RecordKind, id: &str) -> Result<PathBuf, IdeviceError> {
+        // The ID ends up in a file name
+        if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+            return Err(IdeviceError::InvalidPairingFile(format!(
+                "{id:?} can't be used as a record name"
+            )));
+        }
+        Ok(self.dir.join(match kind {
+            RecordKind::Lockdown => format!("{id}.plist"),
+            RecordKind::RemotePairing => format!("{id}.remote.plist"),
+        }))
+    }
+}
+
+impl KeyStore for FsKeyStore {
+    fn load<'a>(
+        &'a self,
+        kind: RecordKind,
+        id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, IdeviceError>> {
+        Box::pin(async move {
+            match std::fs::read(self.path(kind, id)?) {
+                Ok(record) => Ok(Some(record)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn save<'a>(
+        &'a self,
+        kind: RecordKind,
+        id: &'a str,
+        record: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), IdeviceError>> {
+        Box::pin(async move {
+            let path = self.path(kind, id)?;
+            std::fs::create_dir_all(&self.dir)?;
+
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+                options.mode(0o600);
+                // The mode only applies to new files
+                if path.exists() {
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+                }
+            }
+            std::io::Write::write_all(&mut options.open(&path)?, &record)?;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(
+        &'a self,
+        kind: RecordKind,
+        id: &'a str,
+    ) -> BoxFuture<'a, Result<(), IdeviceError>> {
+        Box::pin(async move {
+            match std::fs::remove_file(self.path(kind, id)?) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
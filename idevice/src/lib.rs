@@ -1,17 +1,42 @@
 // Jackson Coxson
 
+#[cfg(feature = "atc")]
+pub mod atc;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod capabilities;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client_builder;
+pub mod codec;
+#[cfg(all(feature = "usbmuxd", not(target_arch = "wasm32")))]
+pub mod connectivity;
 #[cfg(feature = "core_device_proxy")]
 pub mod core_device_proxy;
 #[cfg(feature = "debug_proxy")]
 pub mod debug_proxy;
 #[cfg(feature = "dvt")]
 pub mod dvt;
+mod error_details;
+#[cfg(feature = "events")]
+pub mod events;
+#[cfg(feature = "firmware_update")]
+pub mod firmware_update;
 #[cfg(feature = "heartbeat")]
 pub mod heartbeat;
 #[cfg(feature = "xpc")]
 pub mod http2;
+#[cfg(feature = "install_pipeline")]
+pub mod install_pipeline;
 #[cfg(feature = "installation_proxy")]
 pub mod installation_proxy;
+#[cfg(feature = "ipa")]
+pub mod ipa;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod key_store;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lock_state;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod lockdownd;
 #[cfg(feature = "amfi")]
 pub mod amfi;
@@ -19,56 +44,198 @@ pub mod amfi;
 #[cfg(feature = "companion_proxy")]
 pub mod companion_proxy;
 
+#[cfg(feature = "mcinstall")]
+pub mod mcinstall;
+#[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+pub mod metrics;
 #[cfg(feature = "misagent")]
 pub mod misagent;
 #[cfg(feature = "mounter")]
 pub mod mounter;
+#[cfg(feature = "nskeyed")]
+pub mod nskeyed;
+#[cfg(feature = "os_trace_relay")]
+pub mod os_trace_relay;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod pairing_file;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pairing_offer;
+pub mod plist_util;
+pub mod pretty;
+pub mod progress;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod provider;
+#[cfg(feature = "provisioning")]
+pub mod provisioning;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod re_pairing;
+#[cfg(feature = "recovery")]
+pub mod recovery;
+#[cfg(feature = "remote_pairing")]
+pub mod remote_pairing;
+#[cfg(feature = "report")]
+pub mod report;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod runtime;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shared;
+#[cfg(all(feature = "sinks", not(target_arch = "wasm32")))]
+pub mod sinks;
+#[cfg(feature = "springboardservices")]
+pub mod springboardservices;
+#[cfg(feature = "storage")]
+pub mod storage;
+#[cfg(all(feature = "sync_lock", not(target_arch = "wasm32")))]
+pub mod sync_lock;
 #[cfg(feature = "tunnel_tcp_stack")]
 pub mod tcp;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod throttle;
+#[cfg(feature = "time_sync")]
+pub mod time_sync;
 #[cfg(feature = "tss")]
 pub mod tss;
 #[cfg(feature = "tunneld")]
 pub mod tunneld;
-#[cfg(feature = "usbmuxd")]
+#[cfg(all(feature = "tunnel_supervisor", not(target_arch = "wasm32")))]
+pub mod tunnel_supervisor;
+#[cfg(all(feature = "usbmuxd", not(target_arch = "wasm32")))]
 pub mod usbmuxd;
+// Without sockets only the packet framing is built, for hosts that bring their own transport
+#[cfg(all(feature = "usbmuxd", target_arch = "wasm32"))]
+pub mod usbmuxd {
+    pub mod raw_packet;
+}
 mod util;
 #[cfg(feature = "xpc")]
 pub mod xpc;
 
-use log::{debug, error, trace};
+#[cfg(not(target_arch = "wasm32"))]
+use log::{debug, error, trace, warn};
+#[cfg(not(target_arch = "wasm32"))]
 use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+#[cfg(not(target_arch = "wasm32"))]
 use provider::IdeviceProvider;
-use std::io::{self, BufWriter};
+use std::io;
 use thiserror::Error;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-pub use util::{pretty_print_dictionary, pretty_print_plist};
+pub use error_details::ErrorDetails;
+pub use pretty::Pretty;
+pub use util::{
+    max_message_size, pretty_print_dictionary, pretty_print_plist, set_max_message_size,
+    DEFAULT_MAX_MESSAGE_SIZE,
+};
 
 pub trait ReadWrite: AsyncRead + AsyncWrite + Unpin + Send + Sync + std::fmt::Debug {}
 impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync + std::fmt::Debug> ReadWrite for T {}
 
+#[cfg(not(target_arch = "wasm32"))]
 pub trait IdeviceService: Sized {
     fn service_name() -> &'static str;
     fn connect(
         provider: &dyn IdeviceProvider,
     ) -> impl std::future::Future<Output = Result<Self, IdeviceError>> + Send;
+    /// Wraps a connection to the service once lockdown has started it
+    fn from_idevice(
+        idevice: Idevice,
+    ) -> impl std::future::Future<Output = Result<Self, IdeviceError>> + Send;
+
+    /// Connects with options other than the defaults
+    fn builder<'a>() -> client_builder::ClientBuilder<'a, Self> {
+        client_builder::ClientBuilder::new()
+    }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub type IdeviceSocket = Box<dyn ReadWrite>;
 
+/// A connection to a service on the device.
+///
+/// Dropping it closes the socket right away. Clients that keep state on the device
+/// have a ``close`` that tidies up first, which saves the service waiting for a timeout.
+#[cfg(not(target_arch = "wasm32"))]
 pub struct Idevice {
     socket: Option<Box<dyn ReadWrite>>, // in a box for now to use the ReadWrite trait for further uses
     label: String,
+    /// ``None`` until a client or the caller picks one, then XML
+    wire_format: Option<codec::WireFormat>,
+    /// Set once a reply has been parsed or the format has been switched
+    wire_format_settled: bool,
+    /// The first request, kept to send again in the other format if the reply won't parse
+    unanswered: Option<plist::Value>,
+    #[cfg(feature = "proxy")]
+    tap: Option<proxy::Tap>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Idevice {
     pub fn new(socket: Box<dyn ReadWrite>, label: impl Into<String>) -> Self {
         Self {
             socket: Some(socket),
             label: label.into(),
+            wire_format: None,
+            wire_format_settled: false,
+            unanswered: None,
+            #[cfg(feature = "proxy")]
+            tap: None,
+        }
+    }
+
+    /// The format plists are sent in. Replies are read in either format.
+    pub fn wire_format(&self) -> codec::WireFormat {
+        self.wire_format.unwrap_or_default()
+    }
+
+    /// Sends plists in ``format`` from now on, in place of the service's default.
+    /// A format picked here is kept even if the service can't parse it.
+    pub fn set_wire_format(&mut self, format: codec::WireFormat) {
+        self.wire_format = Some(format);
+        self.wire_format_settled = true;
+        self.unanswered = None;
+    }
+
+    /// Like ``set_wire_format``, for picking the format before handing the connection
+    /// to a client
+    pub fn with_wire_format(mut self, format: codec::WireFormat) -> Self {
+        self.set_wire_format(format);
+        self
+    }
+
+    /// Used by clients for their service's default, unless a format was already picked
+    pub(crate) fn default_wire_format(&mut self, format: codec::WireFormat) {
+        self.wire_format.get_or_insert(format);
+    }
+
+    #[cfg(feature = "proxy")]
+    fn set_tap(&mut self, tap: proxy::Tap) {
+        if let proxy::Tap::Record(connection) = &tap {
+            if let Some(socket) = self.socket.take() {
+                self.socket = Some(Box::new(connection.wrap(socket)));
+            }
         }
+        self.tap = Some(tap);
+    }
+
+    /// Consumes the connection and returns the underlying socket, for services
+    /// that speak their own protocol after the lockdown handshake.
+    /// The socket is only gone if setting up TLS failed part way.
+    pub fn into_inner(self) -> Result<Box<dyn ReadWrite>, IdeviceError> {
+        self.socket.ok_or(IdeviceError::NoEstablishedConnection)
+    }
+
+    /// Shuts the socket down, so the service sees the connection end cleanly
+    pub async fn close(mut self) -> Result<(), IdeviceError> {
+        if let Some(mut socket) = self.socket.take() {
+            socket.shutdown().await?;
+        }
+        Ok(())
     }
 
     pub async fn get_type(&mut self) -> Result<String, IdeviceError> {
@@ -115,17 +282,17 @@ impl Idevice {
 
     /// Sends a plist to the socket
     async fn send_plist(&mut self, message: plist::Value) -> Result<(), IdeviceError> {
+        let format = self.wire_format();
         if let Some(socket) = &mut self.socket {
             debug!("Sending plist: {}", pretty_print_plist(&message));
 
-            let buf = Vec::new();
-            let mut writer = BufWriter::new(buf);
-            message.to_writer_xml(&mut writer)?;
-            let message = writer.into_inner().unwrap();
-            let message = String::from_utf8(message)?;
-            let len = message.len() as u32;
-            socket.write_all(&len.to_be_bytes()).await?;
-            socket.write_all(message.as_bytes()).await?;
+            let encoded = codec::encode_plist(&message, format)?;
+            socket.write_all(&encoded).await?;
+            #[cfg(feature = "metrics")]
+            crate::metrics::sent(encoded.len());
+            if !self.wire_format_settled {
+                self.unanswered = Some(message);
+            }
             Ok(())
         } else {
             Err(IdeviceError::NoEstablishedConnection)
@@ -154,7 +321,46 @@ impl Idevice {
             for (i, part) in message_parts.enumerate() {
                 trace!("Writing {i}/{part_len}");
                 socket.write_all(part).await?;
+                #[cfg(feature = "metrics")]
+                crate::metrics::sent(part.len());
+                callback(((i, part_len), state.clone())).await;
+            }
+            Ok(())
+        } else {
+            Err(IdeviceError::NoEstablishedConnection)
+        }
+    }
+
+    /// Streams exactly `len` bytes from a reader to the socket without buffering the whole payload
+    async fn send_reader_with_progress<R, Fut, S>(
+        &mut self,
+        reader: &mut R,
+        len: u64,
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<(), IdeviceError>
+    where
+        R: AsyncRead + Unpin,
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        const CHUNK_SIZE: u64 = 1024 * 64;
+        if let Some(socket) = &mut self.socket {
+            let part_len = len.div_ceil(CHUNK_SIZE).saturating_sub(1) as usize;
+            let mut buf = vec![0; CHUNK_SIZE as usize];
+            let mut remaining = len;
+            let mut i = 0;
+
+            while remaining > 0 {
+                let to_read = remaining.min(CHUNK_SIZE) as usize;
+                reader.read_exact(&mut buf[..to_read]).await?;
+                trace!("Writing {i}/{part_len}");
+                socket.write_all(&buf[..to_read]).await?;
+                #[cfg(feature = "metrics")]
+                crate::metrics::sent(to_read);
                 callback(((i, part_len), state.clone())).await;
+                remaining -= to_read as u64;
+                i += 1;
             }
             Ok(())
         } else {
@@ -165,8 +371,10 @@ impl Idevice {
     /// Reads raw bytes from the socket
     async fn read_raw(&mut self, len: usize) -> Result<Vec<u8>, IdeviceError> {
         if let Some(socket) = &mut self.socket {
-            let mut buf = vec![0; len];
+            let mut buf = vec![0; util::check_message_size(len)?];
             socket.read_exact(&mut buf).await?;
+            #[cfg(feature = "metrics")]
+            crate::metrics::received(buf.len());
             Ok(buf)
         } else {
             Err(IdeviceError::NoEstablishedConnection)
@@ -178,6 +386,8 @@ impl Idevice {
         if let Some(socket) = &mut self.socket {
             let mut buf = vec![0; max_size as usize];
             let len = socket.read(&mut buf).await?;
+            #[cfg(feature = "metrics")]
+            crate::metrics::received(len);
             Ok(buf[..len].to_vec())
         } else {
             Err(IdeviceError::NoEstablishedConnection)
@@ -186,27 +396,69 @@ impl Idevice {
 
     /// Read a plist from the socket
     async fn read_plist(&mut self) -> Result<plist::Dictionary, IdeviceError> {
-        if let Some(socket) = &mut self.socket {
-            debug!("Reading response size");
-            let mut buf = [0u8; 4];
-            socket.read_exact(&mut buf).await?;
-            let len = u32::from_be_bytes(buf);
-            let mut buf = vec![0; len as usize];
-            socket.read_exact(&mut buf).await?;
-            let res: plist::Dictionary = plist::from_bytes(&buf)?;
-            debug!("Received plist: {}", pretty_print_dictionary(&res));
+        debug!("Reading plist");
+        let res = match self.read_plist_frame().await? {
+            plist::Value::Dictionary(res) => res,
+            _ => {
+                warn!("Received a plist that isn't a dictionary");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        debug!("Received plist: {}", pretty_print_dictionary(&res));
+
+        if let Some(e) = res.get("Error") {
+            let e: String = plist::from_value(e)?;
+            if let Some(e) = IdeviceError::from_device_error_type(e.as_str(), &res) {
+                return Err(e);
+            } else {
+                return Err(IdeviceError::UnknownErrorType(e));
+            }
+        }
+        Ok(res)
+    }
 
-            if let Some(e) = res.get("Error") {
-                let e: String = plist::from_value(e)?;
-                if let Some(e) = IdeviceError::from_device_error_type(e.as_str(), &res) {
-                    return Err(e);
-                } else {
-                    return Err(IdeviceError::UnknownErrorType(e));
+    /// Read a plist that doesn't have to be a dictionary
+    async fn read_plist_value(&mut self) -> Result<plist::Value, IdeviceError> {
+        debug!("Reading plist value");
+        let res = self.read_plist_frame().await?;
+        if let Some(e) = res.as_dictionary().and_then(|d| d.get("Error")) {
+            let e: String = plist::from_value(e)?;
+            return Err(IdeviceError::UnknownErrorType(e));
+        }
+        Ok(res)
+    }
+
+    /// Reads one plist frame.
+    /// A service that can't parse the format it was sent can answer with something that
+    /// isn't a plist at all. If that happens to the first request, it's sent once more in
+    /// the other format, which is then kept for the rest of the connection.
+    async fn read_plist_frame(&mut self) -> Result<plist::Value, IdeviceError> {
+        loop {
+            let socket = match &mut self.socket {
+                Some(socket) => socket,
+                None => return Err(IdeviceError::NoEstablishedConnection),
+            };
+            match codec::read_frame::<plist::Value, _>(socket).await {
+                Ok(res) => {
+                    self.wire_format_settled = true;
+                    self.unanswered = None;
+                    return Ok(res);
+                }
+                Err(IdeviceError::Plist(e)) => {
+                    let message = match self.unanswered.take() {
+                        Some(m) => m,
+                        None => return Err(IdeviceError::Plist(e)),
+                    };
+                    let format = self.wire_format().other();
+                    warn!(
+                        "Couldn't parse the reply ({e}), sending the request again as {format:?}"
+                    );
+                    self.wire_format = Some(format);
+                    self.wire_format_settled = true;
+                    self.send_plist(message).await?;
                 }
+                Err(e) => return Err(e),
             }
-            Ok(res)
-        } else {
-            Err(IdeviceError::NoEstablishedConnection)
         }
     }
 
@@ -215,6 +467,13 @@ impl Idevice {
         &mut self,
         pairing_file: &pairing_file::PairingFile,
     ) -> Result<(), IdeviceError> {
+        #[cfg(feature = "proxy")]
+        match &self.tap {
+            Some(proxy::Tap::Replay) => return Ok(()),
+            Some(proxy::Tap::Record(connection)) => connection.pause(),
+            None => {}
+        }
+
         let connector = SslConnector::builder(SslMethod::tls()).unwrap();
 
         let mut connector = connector
@@ -232,6 +491,12 @@ impl Idevice {
 
         let mut ssl_stream = tokio_openssl::SslStream::new(connector, socket)?;
         std::pin::Pin::new(&mut ssl_stream).connect().await?;
+
+        #[cfg(feature = "proxy")]
+        if let Some(proxy::Tap::Record(connection)) = &self.tap {
+            self.socket = Some(Box::new(connection.wrap(Box::new(ssl_stream))));
+            return Ok(());
+        }
         self.socket = Some(Box::new(ssl_stream));
 
         Ok(())
@@ -243,8 +508,10 @@ impl Idevice {
 pub enum IdeviceError {
     #[error("device socket io failed")]
     Socket(#[from] io::Error),
+    #[cfg(not(target_arch = "wasm32"))]
     #[error("ssl io failed")]
     Ssl(#[from] openssl::ssl::Error),
+    #[cfg(not(target_arch = "wasm32"))]
     #[error("ssl failed to setup")]
     SslSetup(#[from] openssl::error::ErrorStack),
     #[error("io on plist")]
@@ -274,7 +541,14 @@ pub enum IdeviceError {
     #[error("Proclaimed packet size does not match actual size")]
     PacketSizeMismatch,
 
-    #[cfg(feature = "core_device_proxy")]
+    #[cfg(any(
+        feature = "core_device_proxy",
+        feature = "debug_proxy",
+        feature = "remote_pairing",
+        feature = "report",
+        feature = "tunnel_supervisor",
+        feature = "web_inspector"
+    ))]
     #[error("JSON serialization failed")]
     Json(#[from] serde_json::Error),
 
@@ -297,21 +571,26 @@ pub enum IdeviceError {
     BadBuildManifest,
     #[error("image not mounted")]
     ImageNotMounted,
+    #[error("developer disk image is not mounted, mount one to use developer services")]
+    DeveloperImageNotMounted,
 
-    #[cfg(any(feature = "tss", feature = "tunneld"))]
+    #[cfg(any(feature = "tss", feature = "tunneld", feature = "webhook_sink"))]
     #[error("http reqwest error")]
     Reqwest(#[from] reqwest::Error),
 
     #[error("internal error")]
     InternalError(String),
 
+    #[error("this needs the {0} feature")]
+    FeatureDisabled(&'static str),
+
     #[cfg(feature = "xpc")]
     #[error("xpc message failed")]
     Xpc(#[from] xpc::error::XPCError),
 
-    #[cfg(feature = "dvt")]
+    #[cfg(feature = "nskeyed")]
     #[error("NSKeyedArchive error")]
-    NsKeyedArchiveError(#[from] ns_keyed_archive::ConverterError),
+    NsKeyedArchiveError(#[from] nskeyed::NsKeyedError),
 
     #[cfg(feature = "dvt")]
     #[error("Unknown aux value type")]
@@ -328,27 +607,151 @@ pub enum IdeviceError {
     #[error("disable memory limit failed")]
     DisableMemoryLimitFailed,
 
+    #[cfg(feature = "dvt")]
+    #[error("invalid symbol file: {0}")]
+    InvalidSymbolFile(String),
+
+    #[cfg(feature = "dvt")]
+    #[error("the device doesn't report its thermal state")]
+    ThermalStateUnavailable,
+
     #[error("not enough bytes, expected {1}, got {0}")]
     NotEnoughBytes(usize, usize),
 
     #[error("failed to parse bytes as valid utf8")]
     Utf8Error,
 
-    #[cfg(feature = "debug_proxy")]
     #[error("invalid argument passed")]
     InvalidArgument,
 
+    #[cfg(feature = "debug_proxy")]
+    #[error("debugserver returned an error: {0}")]
+    DebugserverError(String),
+
     #[error("unknown error `{0}` returned from device")]
     UnknownErrorType(String),
+
+    #[error("pairing offer is malformed")]
+    InvalidPairingOffer,
+    #[error("pairing record does not match the pairing offer")]
+    PairingOfferMismatch,
+
+    #[error("operation timed out")]
+    Timeout,
+
+    #[error("pairing file is invalid: {0}")]
+    InvalidPairingFile(String),
+
+    #[error("no pairing record stored for {0}")]
+    PairingRecordNotFound(String),
+
+    #[error("waiting for the user to trust this computer")]
+    PairingDialogResponsePending,
+    #[error("the user didn't trust this computer")]
+    UserDeniedPairing,
+    #[error("device rejected the pairing record")]
+    InvalidPairRecord,
+    #[error("device must be unlocked to pair")]
+    PasswordProtected,
+
+    #[error("message of {0} bytes is over the size limit")]
+    MessageTooLarge(usize),
+
+    #[cfg(feature = "ipa")]
+    #[error("zip archive error")]
+    Zip(#[from] zip::result::ZipError),
+    #[cfg(feature = "ipa")]
+    #[error("invalid ipa: {0}")]
+    InvalidIpa(String),
+
+    #[cfg(feature = "atc")]
+    #[error("sync failed: {0}")]
+    SyncFailed(String),
+
+    #[cfg(feature = "mcinstall")]
+    #[error("profile request failed: {0}")]
+    ProfileRequestFailed(String),
+
+    #[cfg(feature = "diagnostics")]
+    #[error("diagnostics request failed: {0}")]
+    DiagnosticsError(String),
+
+    #[cfg(feature = "afc")]
+    #[error("afc request failed: {0}")]
+    AfcError(String),
+
+    #[cfg(feature = "afc")]
+    #[error("{path} was corrupted in transit, its SHA-1 doesn't match what was sent")]
+    HashMismatch {
+        path: String,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+
+    #[cfg(feature = "file_relay")]
+    #[error("file relay request failed: {0}")]
+    FileRelayError(String),
+
+    #[cfg(feature = "house_arrest")]
+    #[error("house arrest request failed: {0}")]
+    HouseArrestError(String),
+
+    #[cfg(feature = "mobile_backup")]
+    #[error("backup request failed: {0}")]
+    MobileBackupError(String),
+
+    #[cfg(feature = "notification_proxy")]
+    #[error("notification proxy error: {0}")]
+    NotificationProxyError(String),
+
+    #[cfg(feature = "sync_lock")]
+    #[error("another host is syncing with the device")]
+    SyncInProgress,
+
+    #[cfg(feature = "screenshot")]
+    #[error("screenshot failed: {0}")]
+    ScreenshotError(String),
+
+    #[cfg(feature = "web_inspector")]
+    #[error("inspector command failed: {0}")]
+    InspectorCommandFailed(String),
+    #[cfg(feature = "web_inspector")]
+    #[error("javascript exception: {0}")]
+    JavaScriptException(String),
+
+    #[cfg(all(feature = "afc", not(target_arch = "wasm32")))]
+    #[error("invalid afc path: {0}")]
+    InvalidAfcPath(#[from] afc::AfcPathError),
+
+    #[cfg(feature = "recovery")]
+    #[error("usb error")]
+    Rusb(#[from] rusb::Error),
+
+    #[cfg(feature = "amfi")]
+    #[error("AMFI refused: {0}")]
+    AmfiError(String),
+
+    #[cfg(feature = "remote_pairing")]
+    #[error("remote pairing failed: {0}")]
+    RemotePairingFailed(String),
+
+    #[cfg(feature = "quic_tunnel")]
+    #[error("QUIC tunnel failed: {0}")]
+    QuicTunnelFailed(String),
 }
 
 impl IdeviceError {
+    #[cfg(not(target_arch = "wasm32"))]
     fn from_device_error_type(e: &str, context: &plist::Dictionary) -> Option<Self> {
         match e {
             "GetProhibited" => Some(Self::GetProhibited),
             "InvalidHostID" => Some(Self::InvalidHostID),
             "SessionInactive" => Some(Self::SessionInactive),
             "DeviceLocked" => Some(Self::DeviceLocked),
+            "PairingDialogResponsePending" => Some(Self::PairingDialogResponsePending),
+            "UserDeniedPairing" => Some(Self::UserDeniedPairing),
+            "InvalidPairRecord" => Some(Self::InvalidPairRecord),
+            "PasswordProtected" => Some(Self::PasswordProtected),
             "InternalError" => {
                 let detailed_error = context
                     .get("DetailedError")
@@ -373,8 +776,13 @@ pub mod file_relay;
 pub mod house_arrest;
 #[cfg(feature = "screenshot")]
 pub mod screenshot;
-#[cfg(feature = "afc")]
+#[cfg(all(feature = "afc", not(target_arch = "wasm32")))]
 pub mod afc;
+#[cfg(all(feature = "afc", target_arch = "wasm32"))]
+pub mod afc {
+    mod packet;
+    pub use packet::{AfcPacket, AFC_MAGIC};
+}
 
 #[cfg(feature = "notification_proxy")]
 pub mod notification_proxy;
@@ -382,3 +790,9 @@ pub mod notification_proxy;
 pub mod diagnostics;
 #[cfg(feature = "mobile_backup")]
 pub mod mobile_backup;
+#[cfg(feature = "sysdiagnose")]
+pub mod sysdiagnose;
+#[cfg(feature = "media")]
+pub mod media;
+#[cfg(feature = "web_inspector")]
+pub mod web_inspector;
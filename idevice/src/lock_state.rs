@@ -0,0 +1,414 @@
+// Jackson Coxson
+// Waiting out a locked screen.
+// Services that touch protected data won't start while a device with a passcode is locked,
+// lockdown answers PasswordProtected or DeviceLocked instead. RetryOnLock tells the caller
+// the device is locked and starts the service again once it's been unlocked.
+// There's no request that reports whether the screen is locked, so lock_state starts one of
+// those services and sees if it's refused. SpringBoard and keybagd post notifications when
+// the state changes, which wake the stream up early.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::{lockdownd::LockdowndClient, provider::IdeviceProvider, IdeviceError, IdeviceService};
+
+#[cfg(feature = "notification_proxy")]
+use crate::notification_proxy::{NotificationProxyClient, NotificationType};
+
+/// Refused while the device is locked, when it has a passcode
+const PROBE_SERVICE: &str = "com.apple.mobilebackup2";
+
+/// Whether an error means the device has to be unlocked first
+pub fn is_locked_error(e: &IdeviceError) -> bool {
+    matches!(
+        e,
+        IdeviceError::PasswordProtected | IdeviceError::DeviceLocked
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockEvent {
+    /// The service was refused because the device is locked. Tools should ask the user
+    /// to unlock it.
+    DeviceLocked { service: String },
+    /// Starting the service again, counting from 1
+    Retrying { service: String, attempt: u32 },
+}
+
+type EventCallback = Arc<dyn Fn(LockEvent) + Send + Sync>;
+
+#[cfg(feature = "notification_proxy")]
+type Listener = (
+    NotificationProxyClient,
+    tokio::sync::mpsc::Receiver<NotificationType>,
+);
+
+/// How to retry a service the device refused while locked
+#[derive(Clone)]
+pub struct RetryOnLock {
+    poll_interval: Duration,
+    timeout: Option<Duration>,
+    #[cfg(feature = "notification_proxy")]
+    wait_for_notification: bool,
+    on_event: Option<EventCallback>,
+}
+
+impl Default for RetryOnLock {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            timeout: None,
+            #[cfg(feature = "notification_proxy")]
+            wait_for_notification: true,
+            on_event: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for RetryOnLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryOnLock")
+            .field("poll_interval", &self.poll_interval)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl RetryOnLock {
+    /// Retries every 2 seconds until the device is unlocked
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long to wait between attempts when no unlock notification arrives
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Gives up after this long, returning the error the device last answered with
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Whether to retry as soon as the device posts a lock status notification instead of
+    /// only polling. On by default.
+    #[cfg(feature = "notification_proxy")]
+    pub fn with_notification(mut self, wait_for_notification: bool) -> Self {
+        self.wait_for_notification = wait_for_notification;
+        self
+    }
+
+    /// Called with each lock event, such as to prompt the user to unlock the device
+    pub fn with_events(mut self, on_event: impl Fn(LockEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+
+    fn emit(&self, event: LockEvent) {
+        debug!("{event:?}");
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Connects to a service, waiting for the device to be unlocked if it's refused
+    pub async fn connect<S: IdeviceService>(
+        &self,
+        provider: &dyn IdeviceProvider,
+    ) -> Result<S, IdeviceError> {
+        self.run(provider, S::service_name(), || S::connect(provider))
+            .await
+    }
+
+    /// Runs something that starts a service, such as a client's own connect, until it
+    /// isn't refused for the device being locked
+    pub async fn run<T, F, Fut>(
+        &self,
+        provider: &dyn IdeviceProvider,
+        service: &str,
+        mut op: F,
+    ) -> Result<T, IdeviceError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, IdeviceError>>,
+    {
+        let started = Instant::now();
+        let mut waiter = UnlockWaiter {
+            #[cfg(feature = "notification_proxy")]
+            listener: None,
+            #[cfg(feature = "notification_proxy")]
+            polling_only: !self.wait_for_notification,
+        };
+        let mut attempt = 0;
+        loop {
+            let e = match op().await {
+                Err(e) if is_locked_error(&e) => e,
+                r => return r,
+            };
+            if attempt == 0 {
+                self.emit(LockEvent::DeviceLocked {
+                    service: service.to_string(),
+                });
+            }
+
+            let mut wait = self.poll_interval;
+            if let Some(timeout) = self.timeout {
+                let left = timeout.saturating_sub(started.elapsed());
+                if left.is_zero() {
+                    return Err(e);
+                }
+                wait = wait.min(left);
+            }
+            waiter.wait(provider, wait).await;
+
+            attempt += 1;
+            self.emit(LockEvent::Retrying {
+                service: service.to_string(),
+                attempt,
+            });
+        }
+    }
+}
+
+/// Sleeps between attempts, waking up early on a lock status notification
+#[derive(Default)]
+struct UnlockWaiter {
+    #[cfg(feature = "notification_proxy")]
+    listener: Option<Listener>,
+    /// Set when notifications are turned off or couldn't be listened for
+    #[cfg(feature = "notification_proxy")]
+    polling_only: bool,
+}
+
+impl UnlockWaiter {
+    #[cfg(feature = "notification_proxy")]
+    async fn wait(&mut self, provider: &dyn IdeviceProvider, wait: Duration) {
+        if self.listener.is_none() && !self.polling_only {
+            match listen(provider).await {
+                Ok(l) => self.listener = Some(l),
+                Err(e) => {
+                    // Polling still works without it
+                    debug!("Unable to listen for lock status notifications: {e:?}");
+                    self.polling_only = true;
+                }
+            }
+        }
+
+        let (_, notifications) = match &mut self.listener {
+            Some(l) => l,
+            None => return crate::runtime::sleep(wait).await,
+        };
+        let closed = tokio::select! {
+            n = notifications.recv() => n.is_none(),
+            _ = crate::runtime::sleep(wait) => false,
+        };
+        if closed {
+            self.listener = None;
+        }
+    }
+
+    #[cfg(not(feature = "notification_proxy"))]
+    async fn wait(&mut self, _: &dyn IdeviceProvider, wait: Duration) {
+        crate::runtime::sleep(wait).await
+    }
+}
+
+#[cfg(feature = "notification_proxy")]
+async fn listen(provider: &dyn IdeviceProvider) -> Result<Listener, IdeviceError> {
+    let mut client = NotificationProxyClient::connect(provider).await?;
+    client
+        .observe_notifications(&[
+            NotificationType::LockStatusChanged,
+            NotificationType::SpringBoardLockStateChanged,
+        ])
+        .await?;
+    let notifications = client.start_listening().await?;
+    Ok((client, notifications))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Locked,
+    Unlocked,
+}
+
+/// Whether the device is locked right now.
+/// A device without a passcode is always unlocked as far as services are concerned.
+pub async fn lock_state(provider: &dyn IdeviceProvider) -> Result<LockState, IdeviceError> {
+    let mut lockdown = LockdowndClient::connect(provider).await?;
+    lockdown
+        .start_session(&provider.get_pairing_file().await?)
+        .await?;
+    match lockdown.get_value("PasswordProtected").await {
+        Ok(plist::Value::Boolean(false)) => return Ok(LockState::Unlocked),
+        Ok(_) => {}
+        Err(e) => debug!("Unable to read PasswordProtected, probing anyway: {e:?}"),
+    }
+    match lockdown.start_service(PROBE_SERVICE).await {
+        Ok(_) => Ok(LockState::Unlocked),
+        Err(e) if is_locked_error(&e) => Ok(LockState::Locked),
+        Err(e) => Err(e),
+    }
+}
+
+/// The lock state changing, or the first state read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockStateChange {
+    pub state: LockState,
+    pub previous: Option<LockState>,
+}
+
+/// Lock state changes, from [`lock_state_stream`]
+pub struct LockStateStream<'a> {
+    provider: &'a dyn IdeviceProvider,
+    poll_interval: Duration,
+    last: Option<LockState>,
+    waiter: UnlockWaiter,
+}
+
+/// Follows the device being locked and unlocked, such as to wait for the user before a
+/// backup. The state is checked every 2 seconds, and when the device posts a lock
+/// notification.
+pub fn lock_state_stream(provider: &dyn IdeviceProvider) -> LockStateStream<'_> {
+    LockStateStream {
+        provider,
+        poll_interval: Duration::from_secs(2),
+        last: None,
+        waiter: UnlockWaiter::default(),
+    }
+}
+
+impl LockStateStream<'_> {
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Waits until the device is locked or unlocked. The first call returns the current state.
+    pub async fn next(&mut self) -> Result<LockStateChange, IdeviceError> {
+        loop {
+            if self.last.is_some() {
+                self.waiter.wait(self.provider, self.poll_interval).await;
+            }
+            let state = lock_state(self.provider).await?;
+            if self.last == Some(state) {
+                continue;
+            }
+            let previous = self.last.replace(state);
+            return Ok(LockStateChange { state, previous });
+        }
+    }
+
+    /// Waits for the device to be unlocked, returning straight away if it already is
+    pub async fn wait_for_unlock(&mut self) -> Result<(), IdeviceError> {
+        if self.last == Some(LockState::Unlocked) {
+            return Ok(());
+        }
+        while self.next().await?.state != LockState::Unlocked {}
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::testing::{MockProvider, ScriptedResponder};
+
+    #[tokio::test]
+    async fn retries_until_unlocked() {
+        let provider = Arc::new(
+            MockProvider::new("test")
+                .unwrap()
+                .with_service("com.example.protected", ScriptedResponder::new(vec![])),
+        );
+        provider.set_locked(true);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let retry = {
+            let events = events.clone();
+            let provider = provider.clone();
+            RetryOnLock::new()
+                .with_poll_interval(Duration::from_millis(10))
+                .with_events(move |event| {
+                    if event
+                        == (LockEvent::Retrying {
+                            service: "com.example.protected".to_string(),
+                            attempt: 2,
+                        })
+                    {
+                        provider.set_locked(false);
+                    }
+                    events.lock().unwrap().push(event);
+                })
+        };
+
+        let start_service = || async {
+            let mut lockdown = LockdowndClient::connect(provider.as_ref()).await?;
+            lockdown.start_service("com.example.protected").await
+        };
+        let (port, _) = retry
+            .run(provider.as_ref(), "com.example.protected", start_service)
+            .await
+            .unwrap();
+        assert_ne!(port, 0);
+
+        {
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), 3);
+            assert_eq!(
+                events[0],
+                LockEvent::DeviceLocked {
+                    service: "com.example.protected".to_string()
+                }
+            );
+        }
+
+        provider.set_locked(true);
+        let res = RetryOnLock::new()
+            .with_poll_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_millis(30))
+            .run(provider.as_ref(), "com.example.protected", start_service)
+            .await;
+        assert!(matches!(res, Err(IdeviceError::PasswordProtected)));
+    }
+
+    #[tokio::test]
+    async fn follows_lock_state() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(PROBE_SERVICE, ScriptedResponder::new(vec![]))
+            .with_value("PasswordProtected", false);
+        provider.set_locked(true);
+        assert_eq!(lock_state(&provider).await.unwrap(), LockState::Unlocked);
+
+        let provider = provider.with_value("PasswordProtected", true);
+        provider.set_locked(true);
+        let mut stream = lock_state_stream(&provider).with_poll_interval(Duration::from_millis(10));
+        assert_eq!(
+            stream.next().await.unwrap(),
+            LockStateChange {
+                state: LockState::Locked,
+                previous: None
+            }
+        );
+
+        provider.set_locked(false);
+        assert_eq!(
+            stream.next().await.unwrap(),
+            LockStateChange {
+                state: LockState::Unlocked,
+                previous: Some(LockState::Locked)
+            }
+        );
+        stream.wait_for_unlock().await.unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+// Jackson Coxson
+// Typed values for the lockdown domains worth inspecting.
+// Every struct also keeps the whole domain in ``values``, since the keys in a domain
+// change between iOS versions.
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::IdeviceError;
+
+pub const BATTERY_DOMAIN: &str = "com.apple.mobile.battery";
+pub const ITUNES_DOMAIN: &str = "com.apple.mobile.iTunes";
+pub const DISK_USAGE_DOMAIN: &str = "com.apple.disk_usage";
+pub const DEVELOPER_DOMAIN: &str = "com.apple.xcode.developerdomain";
+
+const KNOWN_DOMAINS: &[&str] = &[
+    BATTERY_DOMAIN,
+    ITUNES_DOMAIN,
+    DISK_USAGE_DOMAIN,
+    DEVELOPER_DOMAIN,
+    super::ACCESSIBILITY_DOMAIN,
+    "com.apple.disk_usage.factory",
+    "com.apple.fairplay",
+    super::FMIP_DOMAIN,
+    "com.apple.iTunes",
+    super::INTERNATIONAL_DOMAIN,
+    "com.apple.iqagent",
+    "com.apple.mobile.backup",
+    "com.apple.mobile.chaperone",
+    "com.apple.mobile.data_sync",
+    "com.apple.mobile.debug",
+    "com.apple.mobile.internal",
+    "com.apple.mobile.lockdown_cache",
+    "com.apple.mobile.mobile_application_usage",
+    "com.apple.mobile.restriction",
+    "com.apple.mobile.software_behavior",
+    "com.apple.mobile.sync_data_class",
+    "com.apple.mobile.third_party_termination",
+    "com.apple.mobile.tethered_sync",
+    "com.apple.mobile.user_preferences",
+    super::WIRELESS_LOCKDOWN_DOMAIN,
+    "com.apple.purplebuddy",
+    "com.apple.PurpleBuddy",
+];
+
+/// The domains known to lockdown, typed ones first.
+/// Lockdown can't list its domains, so this is what's been seen on devices.
+pub fn list_domains() -> &'static [&'static str] {
+    KNOWN_DOMAINS
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BatteryInfo {
+    /// Percent charged
+    pub battery_current_capacity: Option<u64>,
+    pub battery_is_charging: Option<bool>,
+    pub external_charge_capable: Option<bool>,
+    pub external_connected: Option<bool>,
+    pub fully_charged: Option<bool>,
+    pub gas_gauge_capability: Option<bool>,
+    pub has_battery: Option<bool>,
+    /// Everything in the domain as lockdown returned it, including the fields above
+    #[serde(skip)]
+    pub values: plist::Dictionary,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ITunesInfo {
+    #[serde(rename = "MinITunesVersion")]
+    pub min_itunes_version: Option<String>,
+    #[serde(rename = "MinMacOSVersion")]
+    pub min_macos_version: Option<String>,
+    #[serde(rename = "DBVersion")]
+    pub db_version: Option<u64>,
+    #[serde(rename = "FairPlayDeviceType")]
+    pub fairplay_device_type: Option<u64>,
+    /// Everything in the domain as lockdown returned it, including the fields above
+    #[serde(skip)]
+    pub values: plist::Dictionary,
+}
+
+/// Sizes are in bytes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DiskUsage {
+    pub total_disk_capacity: Option<u64>,
+    pub total_data_capacity: Option<u64>,
+    pub total_data_available: Option<u64>,
+    pub total_system_capacity: Option<u64>,
+    pub total_system_available: Option<u64>,
+    pub amount_data_available: Option<u64>,
+    pub amount_data_reserved: Option<u64>,
+    /// Everything in the domain as lockdown returned it, including the fields above
+    #[serde(skip)]
+    pub values: plist::Dictionary,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeveloperInfo {
+    /// ``Development`` once a developer disk image has been mounted
+    pub developer_status: Option<String>,
+    /// Everything in the domain as lockdown returned it, including the fields above
+    #[serde(skip)]
+    pub values: plist::Dictionary,
+}
+
+/// The contents of a domain, typed when the domain is one of the known ones
+#[derive(Debug, Clone)]
+pub enum DomainValues {
+    Battery(BatteryInfo),
+    ITunes(ITunesInfo),
+    DiskUsage(DiskUsage),
+    Developer(DeveloperInfo),
+    Other(plist::Dictionary),
+}
+
+impl DomainValues {
+    pub fn parse(domain: &str, values: plist::Dictionary) -> Result<Self, IdeviceError> {
+        Ok(match domain {
+            BATTERY_DOMAIN => Self::Battery(BatteryInfo {
+                values: values.clone(),
+                ..typed(values)?
+            }),
+            ITUNES_DOMAIN => Self::ITunes(ITunesInfo {
+                values: values.clone(),
+                ..typed(values)?
+            }),
+            DISK_USAGE_DOMAIN => Self::DiskUsage(DiskUsage {
+                values: values.clone(),
+                ..typed(values)?
+            }),
+            DEVELOPER_DOMAIN => Self::Developer(DeveloperInfo {
+                values: values.clone(),
+                ..typed(values)?
+            }),
+            _ => Self::Other(values),
+        })
+    }
+}
+
+fn typed<T: DeserializeOwned>(values: plist::Dictionary) -> Result<T, IdeviceError> {
+    Ok(plist::from_value(&plist::Value::Dictionary(values))?)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{lockdownd::LockdowndClient, testing::MockProvider, IdeviceService};
+
+    #[tokio::test]
+    async fn domains_are_typed() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_domain_value(BATTERY_DOMAIN, "BatteryCurrentCapacity", 87)
+            .with_domain_value(BATTERY_DOMAIN, "BatteryIsCharging", true)
+            .with_domain_value(BATTERY_DOMAIN, "NewKey", "kept")
+            .with_domain_value(DISK_USAGE_DOMAIN, "TotalDiskCapacity", 128_000_000_000_u64)
+            .with_domain_value("com.apple.international", "Locale", "ko_KR");
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+
+        match lockdown.get_domain(BATTERY_DOMAIN).await.unwrap() {
+            DomainValues::Battery(battery) => {
+                assert_eq!(battery.battery_current_capacity, Some(87));
+                assert_eq!(battery.battery_is_charging, Some(true));
+                assert_eq!(battery.has_battery, None);
+                assert_eq!(
+                    battery.values.get("NewKey").and_then(|v| v.as_string()),
+                    Some("kept")
+                );
+            }
+            v => panic!("Expected battery info, got {v:?}"),
+        }
+        match lockdown.get_domain(DISK_USAGE_DOMAIN).await.unwrap() {
+            DomainValues::DiskUsage(usage) => {
+                assert_eq!(usage.total_disk_capacity, Some(128_000_000_000));
+            }
+            v => panic!("Expected disk usage, got {v:?}"),
+        }
+        match lockdown
+            .get_domain("com.apple.international")
+            .await
+            .unwrap()
+        {
+            DomainValues::Other(values) => {
+                assert_eq!(
+                    values.get("Locale").and_then(|v| v.as_string()),
+                    Some("ko_KR")
+                );
+            }
+            v => panic!("Expected raw values, got {v:?}"),
+        }
+        // Nothing set in the domain still parses
+        assert!(matches!(
+            lockdown.get_domain(DEVELOPER_DOMAIN).await.unwrap(),
+            DomainValues::Developer(DeveloperInfo {
+                developer_status: None,
+                ..
+            })
+        ));
+
+        assert!(list_domains().contains(&"com.apple.international"));
+    }
+}
@@ -7,6 +7,47 @@ use serde::{Deserialize, Serialize};
 
 use crate::{pairing_file, Idevice, IdeviceError, IdeviceService};
 
+mod domains;
+pub use domains::{
+    list_domains, BatteryInfo, DeveloperInfo, DiskUsage, DomainValues, ITunesInfo, BATTERY_DOMAIN,
+    DEVELOPER_DOMAIN, DISK_USAGE_DOMAIN, ITUNES_DOMAIN,
+};
+
+mod pairing;
+
+mod security;
+pub use security::{ActivationState, SecurityStatus, FMIP_DOMAIN};
+
+mod settings;
+mod snapshot;
+pub use settings::{AccessibilityFeature, ACCESSIBILITY_DOMAIN, INTERNATIONAL_DOMAIN};
+pub use snapshot::DeviceSnapshot;
+
+mod telephony;
+pub use telephony::{CarrierBundle, SimStatus, TelephonyInfo};
+
+mod wireless;
+pub use wireless::WIRELESS_LOCKDOWN_DOMAIN;
+
+/// Services that only exist while a developer disk image is mounted
+const DEVELOPER_SERVICE_PREFIXES: [&str; 5] = [
+    "com.apple.instruments.",
+    "com.apple.debugserver",
+    "com.apple.dt.",
+    "com.apple.internal.dt.",
+    "com.apple.mobile.screenshotr",
+];
+
+/// Whether a service is provided by the developer disk image, so that it's missing when the
+/// image isn't mounted rather than missing altogether
+pub fn needs_developer_image(service: &str) -> bool {
+    DEVELOPER_SERVICE_PREFIXES
+        .iter()
+        .any(|p| service.starts_with(p))
+}
+
+/// Dropping the client hangs up without a ``Goodbye``, so lockdownd only notices the
+/// session is over when the socket closes. Use ``close`` to end it properly.
 pub struct LockdowndClient {
     pub idevice: crate::Idevice,
 }
@@ -22,12 +63,18 @@ impl IdeviceService for LockdowndClient {
         let idevice = provider.connect(Self::LOCKDOWND_PORT).await?;
         Ok(Self::new(idevice))
     }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct LockdowndRequest {
     label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
     key: Option<String>,
     request: String,
 }
@@ -41,6 +88,7 @@ impl LockdowndClient {
     pub async fn get_value(&mut self, value: impl Into<String>) -> Result<Value, IdeviceError> {
         let req = LockdowndRequest {
             label: self.idevice.label.clone(),
+            domain: None,
             key: Some(value.into()),
             request: "GetValue".to_string(),
         };
@@ -54,8 +102,30 @@ impl LockdowndClient {
     }
 
     pub async fn get_all_values(&mut self) -> Result<plist::Dictionary, IdeviceError> {
+        self.get_values(None).await
+    }
+
+    /// Gets every key and value in a domain, such as ``com.apple.mobile.battery``
+    pub async fn get_domain_values(
+        &mut self,
+        domain: impl Into<String>,
+    ) -> Result<plist::Dictionary, IdeviceError> {
+        self.get_values(Some(domain.into())).await
+    }
+
+    /// Gets a domain, parsed into its struct for the domains in ``list_domains``
+    pub async fn get_domain(&mut self, domain: &str) -> Result<DomainValues, IdeviceError> {
+        let values = self.get_domain_values(domain).await?;
+        DomainValues::parse(domain, values)
+    }
+
+    async fn get_values(
+        &mut self,
+        domain: Option<String>,
+    ) -> Result<plist::Dictionary, IdeviceError> {
         let req = LockdowndRequest {
             label: self.idevice.label.clone(),
+            domain,
             key: None,
             request: "GetValue".to_string(),
         };
@@ -68,6 +138,19 @@ impl LockdowndClient {
         }
     }
 
+    /// Says goodbye to lockdownd and closes the connection
+    pub async fn close(mut self) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), "Goodbye".into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await?;
+        // lockdownd hangs up after answering, so there's nothing left to shut down
+        Ok(())
+    }
+
     /// Starts a TLS session with the client
     pub async fn start_session(
         &mut self,
@@ -126,13 +209,30 @@ impl LockdowndClient {
         identifier: impl Into<String>,
     ) -> Result<(u16, bool), IdeviceError> {
         let identifier = identifier.into();
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let res = self.start_service_inner(&identifier).await;
+        #[cfg(feature = "metrics")]
+        crate::metrics::service_started(&identifier, started.elapsed(), res.is_ok());
+        res
+    }
+
+    async fn start_service_inner(&mut self, identifier: &str) -> Result<(u16, bool), IdeviceError> {
         let mut req = plist::Dictionary::new();
         req.insert("Request".into(), "StartService".into());
         req.insert("Service".into(), identifier.into());
         self.idevice
             .send_plist(plist::Value::Dictionary(req))
             .await?;
-        let response = self.idevice.read_plist().await?;
+        let response = match self.idevice.read_plist().await {
+            Err(IdeviceError::UnknownErrorType(e))
+                if e == "InvalidService" && needs_developer_image(identifier) =>
+            {
+                error!("{identifier} isn't available without the developer disk image");
+                return Err(IdeviceError::DeveloperImageNotMounted);
+            }
+            r => r?,
+        };
 
         let ssl = match response.get("EnableServiceSSL") {
             Some(plist::Value::Boolean(ssl)) => ssl.to_owned(),
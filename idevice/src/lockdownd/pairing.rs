@@ -0,0 +1,168 @@
+// Jackson Coxson
+// Pairing with lockdown, the "Trust This Computer?" prompt.
+// The host makes up a root certificate, signs a host certificate and a certificate for the
+// device's key with it, and hands the certificates to the device. Once the user trusts the
+// host, the device keeps them and answers with its escrow bag.
+
+use log::warn;
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{HasPublic, PKey, PKeyRef, Private},
+    rsa::Rsa,
+    x509::{
+        extension::{BasicConstraints, KeyUsage, SubjectKeyIdentifier},
+        X509Builder, X509NameBuilder, X509,
+    },
+};
+
+use crate::{pairing_file::PairingFile, IdeviceError};
+
+use super::LockdowndClient;
+
+/// How long the certificates are valid, like iTunes
+const CERTIFICATE_DAYS: u32 = 365 * 10;
+
+impl LockdowndClient {
+    /// Sets a value, in a domain or at the top level. Most keys need a session.
+    pub async fn set_value(
+        &mut self,
+        domain: Option<&str>,
+        key: impl Into<String>,
+        value: plist::Value,
+    ) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("Request".into(), "SetValue".into());
+        if let Some(domain) = domain {
+            req.insert("Domain".into(), domain.into());
+        }
+        req.insert("Key".into(), key.into().into());
+        req.insert("Value".into(), value);
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await?;
+        Ok(())
+    }
+
+    /// Pairs with the device, which shows the trust prompt if it's unlocked. Until the user
+    /// answers, this fails with ``PairingDialogResponsePending`` and can be called again.
+    /// # Arguments
+    /// `host_id` - Identifies this host to the device, usually an uppercase UUID
+    /// `system_buid` - Identifies the host's usbmuxd, from ``ReadBUID``
+    /// # Returns
+    /// The new pairing file, which has to be kept to talk to the device again
+    pub async fn pair(
+        &mut self,
+        host_id: impl Into<String>,
+        system_buid: impl Into<String>,
+    ) -> Result<PairingFile, IdeviceError> {
+        let device_public_key = match self.get_value("DevicePublicKey").await?.as_data() {
+            Some(k) => device_public_key(k)?,
+            None => {
+                warn!("DevicePublicKey is not data");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        let wifi_mac_address = self.get_value("WiFiAddress").await.ok();
+        let udid = self.get_value("UniqueDeviceID").await.ok();
+
+        let root_key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let root_certificate = certificate(&root_key, &root_key, true)?;
+        let host_key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let host_certificate = certificate(&host_key, &root_key, false)?;
+        let device_certificate = certificate(&device_public_key, &root_key, false)?;
+
+        let mut record = plist::Dictionary::new();
+        record.insert(
+            "DeviceCertificate".into(),
+            plist::Value::Data(device_certificate.to_pem()?),
+        );
+        record.insert(
+            "HostCertificate".into(),
+            plist::Value::Data(host_certificate.to_pem()?),
+        );
+        record.insert(
+            "RootCertificate".into(),
+            plist::Value::Data(root_certificate.to_pem()?),
+        );
+        record.insert("HostID".into(), host_id.into().into());
+        record.insert("SystemBUID".into(), system_buid.into().into());
+
+        let mut options = plist::Dictionary::new();
+        options.insert("ExtendedPairingErrors".into(), true.into());
+        let mut req = plist::Dictionary::new();
+        req.insert("Label".into(), self.idevice.label.clone().into());
+        req.insert("ProtocolVersion".into(), "2".into());
+        req.insert("Request".into(), "Pair".into());
+        req.insert(
+            "PairRecord".into(),
+            plist::Value::Dictionary(record.clone()),
+        );
+        req.insert("PairingOptions".into(), plist::Value::Dictionary(options));
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        let res = self.idevice.read_plist().await?;
+
+        if let Some(escrow_bag) = res.get("EscrowBag") {
+            record.insert("EscrowBag".into(), escrow_bag.clone());
+        }
+        if let Some(wifi_mac_address) = wifi_mac_address {
+            record.insert("WiFiMACAddress".into(), wifi_mac_address);
+        }
+        if let Some(udid) = udid {
+            record.insert("UDID".into(), udid);
+        }
+        PairingFile::from_lockdown_record(&record, host_key, root_key)
+    }
+}
+
+/// Devices send a PKCS#1 key, but take a SubjectPublicKeyInfo one too
+fn device_public_key(pem: &[u8]) -> Result<PKey<openssl::pkey::Public>, IdeviceError> {
+    match Rsa::public_key_from_pem_pkcs1(pem) {
+        Ok(k) => Ok(PKey::from_rsa(k)?),
+        Err(_) => Ok(PKey::public_key_from_pem(pem)?),
+    }
+}
+
+/// A certificate for ``key`` issued by the root key. Pairing certificates have no names.
+fn certificate<T: HasPublic>(
+    key: &PKeyRef<T>,
+    root_key: &PKey<Private>,
+    ca: bool,
+) -> Result<X509, IdeviceError> {
+    let name = X509NameBuilder::new()?.build();
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = BigNum::from_u32(0)?.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(CERTIFICATE_DAYS)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    if ca {
+        builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    } else {
+        builder.append_extension(BasicConstraints::new().critical().build()?)?;
+        builder.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+    }
+    let subject_key_identifier =
+        SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(subject_key_identifier)?;
+
+    builder.sign(root_key, MessageDigest::sha256())?;
+    Ok(builder.build())
+}
@@ -0,0 +1,128 @@
+// Jackson Coxson
+// Activation and Find My status, for screening a device before buying or wiping it.
+// MobileGestalt stopped answering these keys for third parties in iOS 17, so everything
+// comes from lockdown.
+
+use log::warn;
+
+use crate::IdeviceError;
+
+use super::LockdowndClient;
+
+/// Holds ``IsAssociated``, which is whether Find My is on
+pub const FMIP_DOMAIN: &str = "com.apple.fmip";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActivationState {
+    Activated,
+    /// Activated at the factory, but not by a user yet
+    FactoryActivated,
+    SoftActivation,
+    Unactivated,
+    MismatchedImei,
+    MismatchedIccid,
+    MissingSim,
+    Unknown(String),
+}
+
+impl From<&str> for ActivationState {
+    fn from(state: &str) -> Self {
+        match state {
+            "Activated" => Self::Activated,
+            "FactoryActivated" => Self::FactoryActivated,
+            "SoftActivation" => Self::SoftActivation,
+            "Unactivated" => Self::Unactivated,
+            "MismatchedIMEI" => Self::MismatchedImei,
+            "MismatchedICCID" => Self::MismatchedIccid,
+            "MissingSIM" => Self::MissingSim,
+            s => Self::Unknown(s.to_string()),
+        }
+    }
+}
+
+impl ActivationState {
+    pub fn is_activated(&self) -> bool {
+        matches!(self, Self::Activated | Self::FactoryActivated)
+    }
+}
+
+/// What lockdown reports about activation and Find My.
+/// Values are ``None`` when the device doesn't report them, such as before pairing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityStatus {
+    pub activation_state: Option<ActivationState>,
+    /// Whether Find My is on, which ties the device to an Apple ID through activation lock
+    pub find_my_enabled: Option<bool>,
+    /// Whether a passcode is set
+    pub passcode_protected: Option<bool>,
+    /// Set when the device refuses to be used until it's activated
+    pub brick_state: Option<bool>,
+}
+
+impl SecurityStatus {
+    /// Whether erasing the device would leave it asking for the owner's Apple ID.
+    /// ``None`` when Find My's status couldn't be read.
+    pub fn activation_locked(&self) -> Option<bool> {
+        self.find_my_enabled
+    }
+}
+
+impl LockdowndClient {
+    /// Reads the activation state and Find My status.
+    /// Most of these values are only reported in a session started with ``start_session``.
+    pub async fn security_status(&mut self) -> Result<SecurityStatus, IdeviceError> {
+        let values = self.get_all_values().await?;
+        // Some devices refuse the domain, which shouldn't hide the other values
+        let fmip = match self.get_domain_values(FMIP_DOMAIN).await {
+            Ok(fmip) => fmip,
+            Err(e) => {
+                warn!("Couldn't read {FMIP_DOMAIN}: {e:?}");
+                plist::Dictionary::new()
+            }
+        };
+        let flag =
+            |values: &plist::Dictionary, key: &str| values.get(key).and_then(|v| v.as_boolean());
+
+        Ok(SecurityStatus {
+            activation_state: values
+                .get("ActivationState")
+                .and_then(|v| v.as_string())
+                .map(ActivationState::from),
+            find_my_enabled: flag(&fmip, "IsAssociated"),
+            passcode_protected: flag(&values, "PasswordProtected"),
+            brick_state: flag(&values, "BrickState"),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{testing::MockProvider, IdeviceService};
+
+    #[tokio::test]
+    async fn security_status_is_read() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_value("ActivationState", "Activated")
+            .with_value("PasswordProtected", true)
+            .with_domain_value(FMIP_DOMAIN, "IsAssociated", true);
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+
+        let status = lockdown.security_status().await.unwrap();
+        assert_eq!(
+            status,
+            SecurityStatus {
+                activation_state: Some(ActivationState::Activated),
+                find_my_enabled: Some(true),
+                passcode_protected: Some(true),
+                brick_state: None,
+            }
+        );
+        assert_eq!(status.activation_locked(), Some(true));
+        assert_eq!(
+            ActivationState::from("Tethered"),
+            ActivationState::Unknown("Tethered".into())
+        );
+    }
+}
@@ -0,0 +1,198 @@
+// Jackson Coxson
+// The device's name, language, region and accessibility features, for setting devices up
+// while provisioning them. All of these need a session. A new name shows up right away,
+// but apps only pick up a new language or locale once the device restarts.
+
+use crate::IdeviceError;
+
+use super::LockdowndClient;
+
+/// Holds ``Language``, ``Locale`` and the lists of what the device supports
+pub const INTERNATIONAL_DOMAIN: &str = "com.apple.international";
+/// Holds the accessibility features a host can turn on
+pub const ACCESSIBILITY_DOMAIN: &str = "com.apple.Accessibility";
+
+/// Accessibility features that can be toggled over lockdown, like Finder and iTunes do.
+/// Guided Access isn't one of them, see ``mcinstall::KioskProfile`` for locking to an app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessibilityFeature {
+    VoiceOver,
+    Zoom,
+    InvertColors,
+    MonoAudio,
+    ClosedCaptioning,
+    SpeakAutoCorrections,
+}
+
+impl AccessibilityFeature {
+    fn key(&self) -> &'static str {
+        match self {
+            Self::VoiceOver => "VoiceOverTouchEnabledByiTunes",
+            Self::Zoom => "ZoomTouchEnabledByiTunes",
+            Self::InvertColors => "InvertDisplayEnabledByiTunes",
+            Self::MonoAudio => "MonoAudioEnabledByiTunes",
+            Self::ClosedCaptioning => "ClosedCaptioningEnabledByiTunes",
+            Self::SpeakAutoCorrections => "SpeakAutoCorrectionsEnabledByiTunes",
+        }
+    }
+}
+
+impl LockdowndClient {
+    pub async fn device_name(&mut self) -> Result<String, IdeviceError> {
+        match self.get_value("DeviceName").await? {
+            plist::Value::String(name) => Ok(name),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn set_device_name(&mut self, name: &str) -> Result<(), IdeviceError> {
+        self.set_value(None, "DeviceName", name.into()).await
+    }
+
+    /// The preferred language, such as ``en`` or ``pt-BR``
+    pub async fn language(&mut self) -> Result<String, IdeviceError> {
+        self.international_string("Language").await
+    }
+
+    /// The region format, such as ``en_US``
+    pub async fn locale(&mut self) -> Result<String, IdeviceError> {
+        self.international_string("Locale").await
+    }
+
+    pub async fn supported_languages(&mut self) -> Result<Vec<String>, IdeviceError> {
+        self.international_list("SupportedLanguages").await
+    }
+
+    pub async fn supported_locales(&mut self) -> Result<Vec<String>, IdeviceError> {
+        self.international_list("SupportedLocales").await
+    }
+
+    /// Sets the preferred language. Returns whether it changed, in which case the device
+    /// needs a restart for it to apply everywhere.
+    pub async fn set_language(&mut self, language: &str) -> Result<bool, IdeviceError> {
+        self.set_international("Language", language).await
+    }
+
+    /// Sets the region format. Returns whether it changed, in which case the device needs
+    /// a restart for it to apply everywhere.
+    pub async fn set_locale(&mut self, locale: &str) -> Result<bool, IdeviceError> {
+        self.set_international("Locale", locale).await
+    }
+
+    pub async fn accessibility(
+        &mut self,
+        feature: AccessibilityFeature,
+    ) -> Result<bool, IdeviceError> {
+        let values = self.get_domain_values(ACCESSIBILITY_DOMAIN).await?;
+        Ok(values
+            .get(feature.key())
+            .and_then(|v| v.as_boolean())
+            .unwrap_or(false))
+    }
+
+    /// Turns an accessibility feature on or off, which applies right away
+    pub async fn set_accessibility(
+        &mut self,
+        feature: AccessibilityFeature,
+        enabled: bool,
+    ) -> Result<(), IdeviceError> {
+        self.set_value(Some(ACCESSIBILITY_DOMAIN), feature.key(), enabled.into())
+            .await
+    }
+
+    async fn international_string(&mut self, key: &str) -> Result<String, IdeviceError> {
+        let values = self.get_domain_values(INTERNATIONAL_DOMAIN).await?;
+        match values.get(key).and_then(|v| v.as_string()) {
+            Some(v) => Ok(v.to_string()),
+            None => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    async fn international_list(&mut self, key: &str) -> Result<Vec<String>, IdeviceError> {
+        let values = self.get_domain_values(INTERNATIONAL_DOMAIN).await?;
+        Ok(values
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_string().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn set_international(&mut self, key: &str, value: &str) -> Result<bool, IdeviceError> {
+        let values = self.get_domain_values(INTERNATIONAL_DOMAIN).await?;
+        if values.get(key).and_then(|v| v.as_string()) == Some(value) {
+            return Ok(false);
+        }
+        if let Some(supported) = values
+            .get(if key == "Language" {
+                "SupportedLanguages"
+            } else {
+                "SupportedLocales"
+            })
+            .and_then(|v| v.as_array())
+        {
+            if !supported.iter().any(|s| s.as_string() == Some(value)) {
+                log::warn!("{value} is not one of the device's supported values for {key}");
+                return Err(IdeviceError::InvalidArgument);
+            }
+        }
+        self.set_value(Some(INTERNATIONAL_DOMAIN), key, value.into())
+            .await?;
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{testing::MockProvider, IdeviceService};
+
+    #[tokio::test]
+    async fn names_and_languages() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_domain_value(INTERNATIONAL_DOMAIN, "Language", "en")
+            .with_domain_value(INTERNATIONAL_DOMAIN, "Locale", "en_US")
+            .with_domain_value(
+                INTERNATIONAL_DOMAIN,
+                "SupportedLanguages",
+                vec![plist::Value::from("en"), plist::Value::from("ko")],
+            );
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+
+        lockdown.set_device_name("Kiosk 4").await.unwrap();
+        assert_eq!(lockdown.device_name().await.unwrap(), "Kiosk 4");
+
+        assert!(!lockdown.set_language("en").await.unwrap());
+        assert!(lockdown.set_language("ko").await.unwrap());
+        assert_eq!(lockdown.language().await.unwrap(), "ko");
+        assert!(matches!(
+            lockdown.set_language("xx").await,
+            Err(IdeviceError::InvalidArgument)
+        ));
+        assert_eq!(
+            lockdown.supported_languages().await.unwrap(),
+            vec!["en".to_string(), "ko".to_string()]
+        );
+
+        // Nothing to check against without SupportedLocales
+        assert!(lockdown.set_locale("ko_KR").await.unwrap());
+        assert_eq!(lockdown.locale().await.unwrap(), "ko_KR");
+
+        assert!(!lockdown
+            .accessibility(AccessibilityFeature::Zoom)
+            .await
+            .unwrap());
+        lockdown
+            .set_accessibility(AccessibilityFeature::Zoom, true)
+            .await
+            .unwrap();
+        assert!(lockdown
+            .accessibility(AccessibilityFeature::Zoom)
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,115 @@
+// Jackson Coxson
+// The values lockdown reports, kept to compare against later.
+// Provisioning tools save a snapshot of a device that's set up the way they want and check
+// each new device against it. Snapshots serialize as plists so they can be kept as files.
+
+use std::collections::BTreeMap;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    plist_util::{self, PlistDiff},
+    IdeviceError,
+};
+
+use super::LockdowndClient;
+
+/// Lockdown's top level values and the domains asked for
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSnapshot {
+    pub values: plist::Dictionary,
+    pub domains: BTreeMap<String, plist::Dictionary>,
+}
+
+impl DeviceSnapshot {
+    /// Everything in the snapshot as one dictionary, with each domain under its name in
+    /// ``Domains``
+    pub fn to_value(&self) -> plist::Value {
+        let mut dict = plist::Dictionary::new();
+        dict.insert(
+            "Values".into(),
+            plist::Value::Dictionary(self.values.clone()),
+        );
+        let domains = self
+            .domains
+            .iter()
+            .map(|(name, values)| (name.clone(), plist::Value::Dictionary(values.clone())))
+            .collect();
+        dict.insert("Domains".into(), plist::Value::Dictionary(domains));
+        plist::Value::Dictionary(dict)
+    }
+
+    /// What changed from ``self`` to ``other``
+    pub fn diff(&self, other: &DeviceSnapshot) -> PlistDiff {
+        plist_util::diff(&self.to_value(), &other.to_value())
+    }
+
+    /// Checks this snapshot against a golden one that only holds the keys that matter.
+    /// Returns the keys that are missing here or have another value, ignoring the ones
+    /// only this snapshot has.
+    pub fn verify(&self, golden: &DeviceSnapshot) -> PlistDiff {
+        PlistDiff {
+            changes: golden.diff(self).missing_or_changed().cloned().collect(),
+        }
+    }
+}
+
+impl LockdowndClient {
+    /// Reads the top level values and the given domains. Most values need a session.
+    /// A domain the device doesn't have is left out rather than failing the snapshot.
+    pub async fn snapshot(&mut self, domains: &[&str]) -> Result<DeviceSnapshot, IdeviceError> {
+        let mut snapshot = DeviceSnapshot {
+            values: self.get_all_values().await?,
+            domains: BTreeMap::new(),
+        };
+        for domain in domains {
+            match self.get_domain_values(*domain).await {
+                Ok(values) => {
+                    snapshot.domains.insert(domain.to_string(), values);
+                }
+                Err(e) => debug!("Leaving {domain} out of the snapshot: {e:?}"),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{lockdownd::INTERNATIONAL_DOMAIN, testing::MockProvider, IdeviceService};
+
+    #[tokio::test]
+    async fn snapshots_are_verified() {
+        let provider = MockProvider::new("test").unwrap().with_domain_value(
+            INTERNATIONAL_DOMAIN,
+            "Language",
+            "en",
+        );
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+
+        let mut golden = DeviceSnapshot::default();
+        golden
+            .values
+            .insert("DeviceName".into(), "Mock Device".into());
+        golden.domains.insert(INTERNATIONAL_DOMAIN.into(), {
+            let mut values = plist::Dictionary::new();
+            values.insert("Language".into(), "en".into());
+            values
+        });
+
+        let snapshot = lockdown.snapshot(&[INTERNATIONAL_DOMAIN]).await.unwrap();
+        assert!(snapshot.verify(&golden).is_empty());
+        assert!(!golden.diff(&snapshot).is_empty());
+
+        lockdown.set_language("ko").await.unwrap();
+        let snapshot = lockdown.snapshot(&[INTERNATIONAL_DOMAIN]).await.unwrap();
+        let deviations = snapshot.verify(&golden);
+        assert_eq!(deviations.changes.len(), 1);
+        assert_eq!(
+            deviations.changes[0].to_string(),
+            "~ /Domains/com.apple.international/Language: \"en\" -> \"ko\""
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Jackson Coxson
+// Carrier, baseband and SIM details from lockdown, for grading and resale reports.
+// Devices without a cellular modem leave most of these out.
+
+use crate::IdeviceError;
+
+use super::LockdowndClient;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimStatus {
+    Ready,
+    NotInserted,
+    /// Waiting for the SIM's PIN
+    PinLocked,
+    /// Waiting for the PUK after too many wrong PINs
+    PukLocked,
+    Unknown(String),
+}
+
+impl From<&str> for SimStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "kCTSIMSupportSIMStatusReady" => Self::Ready,
+            "kCTSIMSupportSIMStatusNotInserted" => Self::NotInserted,
+            "kCTSIMSupportSIMStatusPINLocked" => Self::PinLocked,
+            "kCTSIMSupportSIMStatusPUKLocked" => Self::PukLocked,
+            s => Self::Unknown(s.to_string()),
+        }
+    }
+}
+
+/// The carrier settings installed for one SIM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarrierBundle {
+    /// Such as ``com.apple.Verizon_US``
+    pub identifier: Option<String>,
+    pub version: Option<String>,
+    pub iccid: Option<String>,
+    /// Mobile country code
+    pub mcc: Option<String>,
+    /// Mobile network code
+    pub mnc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelephonyInfo {
+    pub imei: Option<String>,
+    /// The second IMEI on dual SIM devices
+    pub imei2: Option<String>,
+    /// Only on CDMA capable devices
+    pub meid: Option<String>,
+    pub iccid: Option<String>,
+    pub phone_number: Option<String>,
+    pub baseband_version: Option<String>,
+    pub baseband_status: Option<String>,
+    pub sim_status: Option<SimStatus>,
+    pub carrier_bundles: Vec<CarrierBundle>,
+}
+
+impl LockdowndClient {
+    /// Reads the identifiers and firmware of the cellular modem and SIM.
+    /// Lockdown only reports these in a session started with ``start_session``.
+    pub async fn telephony_info(&mut self) -> Result<TelephonyInfo, IdeviceError> {
+        let values = self.get_all_values().await?;
+        let string = |values: &plist::Dictionary, key: &str| {
+            values
+                .get(key)
+                .and_then(|v| v.as_string())
+                .map(|s| s.to_string())
+        };
+
+        let carrier_bundles = values
+            .get("CarrierBundleInfoArray")
+            .and_then(|v| v.as_array())
+            .map(|bundles| {
+                bundles
+                    .iter()
+                    .filter_map(|b| b.as_dictionary())
+                    .map(|b| CarrierBundle {
+                        identifier: string(b, "CFBundleIdentifier"),
+                        version: string(b, "CFBundleVersion"),
+                        iccid: string(b, "IntegratedCircuitCardIdentity"),
+                        mcc: string(b, "MCC"),
+                        mnc: string(b, "MNC"),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(TelephonyInfo {
+            imei: string(&values, "InternationalMobileEquipmentIdentity"),
+            imei2: string(&values, "InternationalMobileEquipmentIdentity2"),
+            meid: string(&values, "MobileEquipmentIdentifier"),
+            iccid: string(&values, "IntegratedCircuitCardIdentity"),
+            phone_number: string(&values, "PhoneNumber"),
+            baseband_version: string(&values, "BasebandVersion"),
+            baseband_status: string(&values, "BasebandStatus"),
+            sim_status: values
+                .get("SIMStatus")
+                .and_then(|v| v.as_string())
+                .map(SimStatus::from),
+            carrier_bundles,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{testing::MockProvider, IdeviceService};
+
+    #[tokio::test]
+    async fn telephony_info_is_read() {
+        let mut bundle = plist::Dictionary::new();
+        bundle.insert("CFBundleIdentifier".into(), "com.apple.Verizon_US".into());
+        bundle.insert("CFBundleVersion".into(), "58.0".into());
+        bundle.insert("MCC".into(), "311".into());
+        bundle.insert("MNC".into(), "480".into());
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_value("InternationalMobileEquipmentIdentity", "356789012345678")
+            .with_value("BasebandVersion", "3.50.03")
+            .with_value("SIMStatus", "kCTSIMSupportSIMStatusReady")
+            .with_value(
+                "CarrierBundleInfoArray",
+                vec![plist::Value::Dictionary(bundle)],
+            );
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+
+        let info = lockdown.telephony_info().await.unwrap();
+        assert_eq!(info.imei.as_deref(), Some("356789012345678"));
+        assert_eq!(info.imei2, None);
+        assert_eq!(info.baseband_version.as_deref(), Some("3.50.03"));
+        assert_eq!(info.sim_status, Some(SimStatus::Ready));
+        assert_eq!(
+            info.carrier_bundles,
+            [CarrierBundle {
+                identifier: Some("com.apple.Verizon_US".into()),
+                version: Some("58.0".into()),
+                iccid: None,
+                mcc: Some("311".into()),
+                mnc: Some("480".into()),
+            }]
+        );
+    }
+}
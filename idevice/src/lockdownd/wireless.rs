@@ -0,0 +1,58 @@
+// Jackson Coxson
+// Wi-Fi sync, which lets usbmuxd reach the device over the network.
+// The device only advertises itself over Bonjour once a host has turned this on, and the
+// switch can only be flipped by a paired host, usually over USB.
+
+use crate::IdeviceError;
+
+use super::LockdowndClient;
+
+/// Holds whether the device can be reached over Wi-Fi
+pub const WIRELESS_LOCKDOWN_DOMAIN: &str = "com.apple.mobile.wireless_lockdown";
+
+impl LockdowndClient {
+    /// Whether Wi-Fi sync is on. Needs a session.
+    pub async fn wifi_sync_enabled(&mut self) -> Result<bool, IdeviceError> {
+        let values = self.get_domain_values(WIRELESS_LOCKDOWN_DOMAIN).await?;
+        Ok(values
+            .get("EnableWifiConnections")
+            .and_then(|v| v.as_boolean())
+            .unwrap_or(false))
+    }
+
+    /// Turns Wi-Fi sync on or off. Needs a session. The device starts or stops advertising
+    /// itself right away.
+    pub async fn set_wifi_sync_enabled(&mut self, enabled: bool) -> Result<(), IdeviceError> {
+        self.set_value(
+            Some(WIRELESS_LOCKDOWN_DOMAIN),
+            "EnableWifiConnections",
+            enabled.into(),
+        )
+        .await
+    }
+
+    /// The MAC address of the Wi-Fi interface, which network connections come from
+    pub async fn wifi_address(&mut self) -> Result<String, IdeviceError> {
+        match self.get_value("WiFiAddress").await? {
+            plist::Value::String(address) => Ok(address),
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{testing::MockProvider, IdeviceService};
+
+    #[tokio::test]
+    async fn wifi_sync_toggles() {
+        let provider = MockProvider::new("test").unwrap();
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+
+        assert!(!lockdown.wifi_sync_enabled().await.unwrap());
+        lockdown.set_wifi_sync_enabled(true).await.unwrap();
+        assert!(lockdown.wifi_sync_enabled().await.unwrap());
+        assert_eq!(lockdown.wifi_address().await.unwrap(), "00:00:00:00:00:00");
+    }
+}
@@ -0,0 +1,702 @@
+// Jackson Coxson
+// Abstractions for com.apple.mobile.MCInstall, the configuration profile service.
+// Reads whether a device is supervised or enrolled in an organization's MDM, and installs
+// the logging profile that stops os_log from redacting private data and the kiosk profile
+// that locks supervised devices to an app.
+
+use log::warn;
+use plist::Dictionary;
+
+use crate::{lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+
+pub struct McInstallClient {
+    pub idevice: Idevice,
+}
+
+impl IdeviceService for McInstallClient {
+    fn service_name() -> &'static str {
+        "com.apple.mobile.MCInstall"
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice))
+    }
+}
+
+/// How the device is managed, from its cloud configuration and installed profiles
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManagementStatus {
+    pub supervised: bool,
+    /// Enrolled through Automated Device Enrollment (DEP) during setup
+    pub dep_enrolled: bool,
+    /// Setup couldn't be finished without enrolling
+    pub enrollment_mandatory: bool,
+    /// The user can't remove the MDM profile
+    pub mdm_unremovable: bool,
+    pub organization_name: Option<String>,
+    /// Identifiers of the installed configuration profiles
+    pub profiles: Vec<String>,
+    /// The cloud configuration as the device returned it
+    pub cloud_configuration: Dictionary,
+}
+
+impl McInstallClient {
+    pub fn new(idevice: Idevice) -> Self {
+        Self { idevice }
+    }
+
+    /// Gets the configuration the device was given by Apple's enrollment servers during setup.
+    /// Devices that were never enrolled return an empty or near empty dictionary.
+    pub async fn get_cloud_configuration(&mut self) -> Result<Dictionary, IdeviceError> {
+        let res = self.request("GetCloudConfiguration").await?;
+        match res.get("CloudConfiguration") {
+            Some(plist::Value::Dictionary(c)) => Ok(c.to_owned()),
+            None => Ok(Dictionary::new()),
+            _ => {
+                warn!("CloudConfiguration wasn't a dictionary");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Gets the identifiers of the installed profiles, in install order
+    pub async fn get_profile_list(&mut self) -> Result<Vec<String>, IdeviceError> {
+        let res = self.request("GetProfileList").await?;
+        match res.get("OrderedIdentifiers") {
+            Some(plist::Value::Array(ids)) => Ok(ids
+                .iter()
+                .filter_map(|id| id.as_string().map(|s| s.to_string()))
+                .collect()),
+            None => Ok(Vec::new()),
+            _ => {
+                warn!("OrderedIdentifiers wasn't an array");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Installs a configuration profile, given as the bytes of its plist
+    pub async fn install_profile(&mut self, profile: &[u8]) -> Result<(), IdeviceError> {
+        let mut req = Dictionary::new();
+        req.insert("Payload".into(), plist::Value::Data(profile.to_vec()));
+        self.send_request("InstallProfile", req).await?;
+        Ok(())
+    }
+
+    /// Removes an installed profile by its identifier
+    pub async fn remove_profile(&mut self, identifier: &str) -> Result<(), IdeviceError> {
+        // The device wants the profile's UUID and version along with the identifier
+        let res = self.request("GetProfileList").await?;
+        let metadata = match res
+            .get("ProfileMetadata")
+            .and_then(|m| m.as_dictionary())
+            .and_then(|m| m.get(identifier))
+            .and_then(|m| m.as_dictionary())
+        {
+            Some(m) => m,
+            None => {
+                return Err(IdeviceError::ProfileRequestFailed(format!(
+                    "{identifier} is not installed"
+                )))
+            }
+        };
+
+        let mut profile = Dictionary::new();
+        profile.insert("PayloadIdentifier".into(), identifier.into());
+        profile.insert("PayloadType".into(), "Configuration".into());
+        for key in ["PayloadUUID", "PayloadVersion"] {
+            if let Some(v) = metadata.get(key) {
+                profile.insert(key.into(), v.clone());
+            }
+        }
+        let mut bytes = Vec::new();
+        plist::to_writer_xml(&mut bytes, &profile)?;
+
+        let mut req = Dictionary::new();
+        req.insert("ProfileIdentifier".into(), plist::Value::Data(bytes));
+        self.send_request("RemoveProfile", req).await?;
+        Ok(())
+    }
+
+    async fn request(&mut self, request_type: &str) -> Result<Dictionary, IdeviceError> {
+        self.send_request(request_type, Dictionary::new()).await
+    }
+
+    async fn send_request(
+        &mut self,
+        request_type: &str,
+        mut req: Dictionary,
+    ) -> Result<Dictionary, IdeviceError> {
+        req.insert("RequestType".into(), request_type.into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+
+        let res = self.idevice.read_plist().await?;
+        match res.get("Status").and_then(|s| s.as_string()) {
+            Some("Acknowledged") => Ok(res),
+            _ => {
+                let reason = res
+                    .get("ErrorChain")
+                    .and_then(|c| c.as_array())
+                    .and_then(|c| c.first())
+                    .and_then(|e| e.as_dictionary())
+                    .and_then(|e| e.get("LocalizedDescription"))
+                    .and_then(|d| d.as_string())
+                    .unwrap_or("No error given");
+                Err(IdeviceError::ProfileRequestFailed(reason.to_string()))
+            }
+        }
+    }
+}
+
+/// Reads whether the device is supervised and how it was enrolled
+pub async fn management_status(
+    provider: &dyn crate::provider::IdeviceProvider,
+) -> Result<ManagementStatus, IdeviceError> {
+    let mut client = McInstallClient::connect(provider).await?;
+    let cloud_configuration = client.get_cloud_configuration().await?;
+    let profiles = client.get_profile_list().await?;
+
+    let flag = |key: &str| {
+        cloud_configuration
+            .get(key)
+            .and_then(|v| v.as_boolean())
+            .unwrap_or(false)
+    };
+    Ok(ManagementStatus {
+        supervised: flag("IsSupervised"),
+        dep_enrolled: flag("ConfigurationWasApplied"),
+        enrollment_mandatory: flag("IsMandatory"),
+        mdm_unremovable: flag("IsMDMUnremovable"),
+        organization_name: cloud_configuration
+            .get("OrganizationName")
+            .and_then(|v| v.as_string())
+            .map(|s| s.to_string()),
+        profiles,
+        cloud_configuration,
+    })
+}
+
+/// Wraps payloads in a profile ready for ``McInstallClient::install_profile``
+fn configuration_profile(
+    identifier: &str,
+    uuid: &str,
+    display_name: &str,
+    payloads: Vec<Dictionary>,
+) -> Result<Vec<u8>, IdeviceError> {
+    let mut profile = Dictionary::new();
+    profile.insert("PayloadType".into(), "Configuration".into());
+    profile.insert("PayloadIdentifier".into(), identifier.into());
+    profile.insert("PayloadUUID".into(), uuid.into());
+    profile.insert("PayloadVersion".into(), 1.into());
+    profile.insert("PayloadDisplayName".into(), display_name.into());
+    profile.insert("PayloadRemovalDisallowed".into(), false.into());
+    profile.insert(
+        "PayloadContent".into(),
+        payloads
+            .into_iter()
+            .map(plist::Value::Dictionary)
+            .collect::<Vec<_>>()
+            .into(),
+    );
+
+    let mut bytes = Vec::new();
+    plist::to_writer_xml(&mut bytes, &profile)?;
+    Ok(bytes)
+}
+
+/// Identifier of the profile installed by ``install_logging_profile``
+pub const LOGGING_PROFILE_IDENTIFIER: &str = "com.jkcoxson.idevice.logging";
+const LOGGING_PROFILE_UUID: &str = "6B1B8E55-0F9A-4C5D-9E2B-2C8F7A3D4E61";
+const LOGGING_PAYLOAD_UUID: &str = "0D3C2A71-5E4B-4F86-A1D9-7B6E5C4F3A28";
+
+/// Shown to the user before the logging profile is installed
+pub const PRIVATE_DATA_WARNING: &str = "This installs a configuration profile that stops the \
+device from redacting private data in its logs. Anything apps log, including personal \
+information, will be readable by whoever reads the logs until the profile is removed.";
+
+/// How much a subsystem logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoggingLevel {
+    Off,
+    Default,
+    Info,
+    Debug,
+}
+
+impl LoggingLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Default => "Default",
+            Self::Info => "Info",
+            Self::Debug => "Debug",
+        }
+    }
+}
+
+/// The ``com.apple.system.logging`` profile, which turns on private data in os_log and can
+/// raise the level of individual subsystems
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggingProfile {
+    pub private_data: bool,
+    /// Subsystems, such as ``com.apple.network``, and the level to log and keep them at
+    pub subsystem_levels: Vec<(String, LoggingLevel)>,
+}
+
+impl Default for LoggingProfile {
+    fn default() -> Self {
+        Self {
+            private_data: true,
+            subsystem_levels: Vec::new(),
+        }
+    }
+}
+
+impl LoggingProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_subsystem_level(
+        mut self,
+        subsystem: impl Into<String>,
+        level: LoggingLevel,
+    ) -> Self {
+        self.subsystem_levels.push((subsystem.into(), level));
+        self
+    }
+
+    /// The profile as a plist, ready for ``McInstallClient::install_profile``
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IdeviceError> {
+        let mut system = Dictionary::new();
+        system.insert("Enable-Private-Data".into(), self.private_data.into());
+
+        let mut subsystems = Dictionary::new();
+        for (subsystem, level) in &self.subsystem_levels {
+            let mut levels = Dictionary::new();
+            levels.insert("Enable".into(), level.as_str().into());
+            levels.insert("Persist".into(), level.as_str().into());
+            let mut options = Dictionary::new();
+            options.insert("Level".into(), levels.into());
+            let mut settings = Dictionary::new();
+            settings.insert("DEFAULT-OPTIONS".into(), options.into());
+            subsystems.insert(subsystem.clone(), settings.into());
+        }
+
+        let mut payload = Dictionary::new();
+        payload.insert("PayloadType".into(), "com.apple.system.logging".into());
+        payload.insert(
+            "PayloadIdentifier".into(),
+            format!("{LOGGING_PROFILE_IDENTIFIER}.payload").into(),
+        );
+        payload.insert("PayloadUUID".into(), LOGGING_PAYLOAD_UUID.into());
+        payload.insert("PayloadVersion".into(), 1.into());
+        payload.insert("System".into(), system.into());
+        if !subsystems.is_empty() {
+            payload.insert("Subsystems".into(), subsystems.into());
+        }
+
+        configuration_profile(
+            LOGGING_PROFILE_IDENTIFIER,
+            LOGGING_PROFILE_UUID,
+            "idevice logging",
+            vec![payload],
+        )
+    }
+}
+
+/// Installs the logging profile once ``confirm`` agrees to ``PRIVATE_DATA_WARNING``.
+/// Returns whether it was installed. Remove it with ``remove_logging_profile`` when done.
+pub async fn install_logging_profile(
+    provider: &dyn crate::provider::IdeviceProvider,
+    profile: &LoggingProfile,
+    confirm: impl FnOnce(&str) -> bool,
+) -> Result<bool, IdeviceError> {
+    if profile.private_data && !confirm(PRIVATE_DATA_WARNING) {
+        return Ok(false);
+    }
+    let mut client = McInstallClient::connect(provider).await?;
+    client.install_profile(&profile.to_bytes()?).await?;
+    Ok(true)
+}
+
+pub async fn remove_logging_profile(
+    provider: &dyn crate::provider::IdeviceProvider,
+) -> Result<(), IdeviceError> {
+    let mut client = McInstallClient::connect(provider).await?;
+    client.remove_profile(LOGGING_PROFILE_IDENTIFIER).await
+}
+
+/// Identifier of the profile installed by ``install_kiosk_profile``
+pub const KIOSK_PROFILE_IDENTIFIER: &str = "com.jkcoxson.idevice.kiosk";
+const KIOSK_PROFILE_UUID: &str = "A4E7C2D9-3B61-4F0E-8D25-96C1B7E4F053";
+const APP_LOCK_PAYLOAD_UUID: &str = "5F2D8B3E-C714-4A96-B0E1-7D3A9C62E48F";
+const RESTRICTIONS_PAYLOAD_UUID: &str = "E91C4A07-6D28-4B5F-A3E6-0B8F2D7C1A94";
+
+/// Changes Single App Mode makes to the device while it's locked to the app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppLockOption {
+    DisableTouch,
+    DisableDeviceRotation,
+    DisableVolumeButtons,
+    DisableRingerSwitch,
+    DisableSleepWakeButton,
+    DisableAutoLock,
+    EnableVoiceOver,
+    EnableZoom,
+    EnableInvertColors,
+    EnableAssistiveTouch,
+    EnableSpeakSelection,
+    EnableMonoAudio,
+}
+
+impl AppLockOption {
+    fn key(&self) -> &'static str {
+        match self {
+            Self::DisableTouch => "DisableTouch",
+            Self::DisableDeviceRotation => "DisableDeviceRotation",
+            Self::DisableVolumeButtons => "DisableVolumeButtons",
+            Self::DisableRingerSwitch => "DisableRingerSwitch",
+            Self::DisableSleepWakeButton => "DisableSleepWakeButton",
+            Self::DisableAutoLock => "DisableAutoLock",
+            Self::EnableVoiceOver => "EnableVoiceOver",
+            Self::EnableZoom => "EnableZoom",
+            Self::EnableInvertColors => "EnableInvertColors",
+            Self::EnableAssistiveTouch => "EnableAssistiveTouch",
+            Self::EnableSpeakSelection => "EnableSpeakSelection",
+            Self::EnableMonoAudio => "EnableMonoAudio",
+        }
+    }
+}
+
+/// Accessibility features the user can still turn on while locked to the app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAccessibilityOption {
+    VoiceOver,
+    Zoom,
+    InvertColors,
+    AssistiveTouch,
+}
+
+impl UserAccessibilityOption {
+    fn key(&self) -> &'static str {
+        match self {
+            Self::VoiceOver => "VoiceOver",
+            Self::Zoom => "Zoom",
+            Self::InvertColors => "InvertColors",
+            Self::AssistiveTouch => "AssistiveTouch",
+        }
+    }
+}
+
+/// Locks a supervised device to one app (Single App Mode), and lets apps start Guided
+/// Access sessions on their own (Autonomous Single App Mode). Guided Access itself can't be
+/// turned on remotely, the user has to enable it in Settings > Accessibility.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KioskProfile {
+    /// Bundle ID of the app to lock the device to
+    pub app: Option<String>,
+    pub options: Vec<AppLockOption>,
+    pub user_options: Vec<UserAccessibilityOption>,
+    /// Bundle IDs of apps allowed to lock the device to themselves
+    pub guided_access_apps: Vec<String>,
+}
+
+impl KioskProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A profile that locks the device to ``bundle_id``
+    pub fn single_app(bundle_id: impl Into<String>) -> Self {
+        Self {
+            app: Some(bundle_id.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_option(mut self, option: AppLockOption) -> Self {
+        self.options.push(option);
+        self
+    }
+
+    pub fn with_user_option(mut self, option: UserAccessibilityOption) -> Self {
+        self.user_options.push(option);
+        self
+    }
+
+    pub fn with_guided_access_app(mut self, bundle_id: impl Into<String>) -> Self {
+        self.guided_access_apps.push(bundle_id.into());
+        self
+    }
+
+    /// The profile as a plist, ready for ``McInstallClient::install_profile``
+    pub fn to_bytes(&self) -> Result<Vec<u8>, IdeviceError> {
+        let mut payloads = Vec::new();
+
+        if let Some(app) = &self.app {
+            let mut options = Dictionary::new();
+            for option in &self.options {
+                options.insert(option.key().into(), true.into());
+            }
+            let mut user_options = Dictionary::new();
+            for option in &self.user_options {
+                user_options.insert(option.key().into(), true.into());
+            }
+            let mut lock = Dictionary::new();
+            lock.insert("Identifier".into(), app.as_str().into());
+            lock.insert("Options".into(), options.into());
+            lock.insert("UserEnabledOptions".into(), user_options.into());
+
+            let mut payload = Dictionary::new();
+            payload.insert("PayloadType".into(), "com.apple.app.lock".into());
+            payload.insert(
+                "PayloadIdentifier".into(),
+                format!("{KIOSK_PROFILE_IDENTIFIER}.applock").into(),
+            );
+            payload.insert("PayloadUUID".into(), APP_LOCK_PAYLOAD_UUID.into());
+            payload.insert("PayloadVersion".into(), 1.into());
+            payload.insert("App".into(), lock.into());
+            payloads.push(payload);
+        }
+
+        if !self.guided_access_apps.is_empty() {
+            let mut payload = Dictionary::new();
+            payload.insert("PayloadType".into(), "com.apple.applicationaccess".into());
+            payload.insert(
+                "PayloadIdentifier".into(),
+                format!("{KIOSK_PROFILE_IDENTIFIER}.restrictions").into(),
+            );
+            payload.insert("PayloadUUID".into(), RESTRICTIONS_PAYLOAD_UUID.into());
+            payload.insert("PayloadVersion".into(), 1.into());
+            payload.insert(
+                "autonomousSingleAppModePermittedAppIDs".into(),
+                self.guided_access_apps
+                    .iter()
+                    .map(|a| plist::Value::from(a.as_str()))
+                    .collect::<Vec<_>>()
+                    .into(),
+            );
+            payloads.push(payload);
+        }
+
+        configuration_profile(
+            KIOSK_PROFILE_IDENTIFIER,
+            KIOSK_PROFILE_UUID,
+            "idevice kiosk",
+            payloads,
+        )
+    }
+}
+
+/// Installs a kiosk profile, replacing any installed before.
+/// Devices ignore these payloads unless they're supervised, so unsupervised devices fail.
+pub async fn install_kiosk_profile(
+    provider: &dyn crate::provider::IdeviceProvider,
+    profile: &KioskProfile,
+) -> Result<(), IdeviceError> {
+    if !management_status(provider).await?.supervised {
+        warn!("Not installing a kiosk profile on an unsupervised device");
+        return Err(IdeviceError::ProfileRequestFailed(
+            "Single App Mode needs a supervised device".into(),
+        ));
+    }
+    let mut client = McInstallClient::connect(provider).await?;
+    client.install_profile(&profile.to_bytes()?).await
+}
+
+/// Removes the kiosk profile, unlocking the device from its app
+pub async fn remove_kiosk_profile(
+    provider: &dyn crate::provider::IdeviceProvider,
+) -> Result<(), IdeviceError> {
+    let mut client = McInstallClient::connect(provider).await?;
+    client.remove_profile(KIOSK_PROFILE_IDENTIFIER).await
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::{future::Future, pin::Pin};
+
+    use super::*;
+    use crate::testing::{MockProvider, MockTransport, Responder};
+
+    struct McInstallResponder;
+
+    impl Responder for McInstallResponder {
+        fn serve(
+            &self,
+            mut transport: MockTransport,
+        ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+            Box::pin(async move {
+                loop {
+                    let req = transport.read_plist().await?;
+                    let mut res = Dictionary::new();
+                    res.insert("Status".into(), "Acknowledged".into());
+                    match req.get("RequestType").and_then(|r| r.as_string()) {
+                        Some("GetCloudConfiguration") => {
+                            let mut config = Dictionary::new();
+                            config.insert("IsSupervised".into(), true.into());
+                            config.insert("ConfigurationWasApplied".into(), true.into());
+                            config.insert("OrganizationName".into(), "Example Corp".into());
+                            res.insert("CloudConfiguration".into(), config.into());
+                        }
+                        Some("GetProfileList") => {
+                            res.insert(
+                                "OrderedIdentifiers".into(),
+                                vec![plist::Value::from("com.example.mdm")].into(),
+                            );
+                            let mut profiles = Dictionary::new();
+                            for (identifier, uuid) in [
+                                (LOGGING_PROFILE_IDENTIFIER, LOGGING_PROFILE_UUID),
+                                (KIOSK_PROFILE_IDENTIFIER, KIOSK_PROFILE_UUID),
+                            ] {
+                                let mut metadata = Dictionary::new();
+                                metadata.insert("PayloadUUID".into(), uuid.into());
+                                metadata.insert("PayloadVersion".into(), 1.into());
+                                profiles.insert(identifier.into(), metadata.into());
+                            }
+                            res.insert("ProfileMetadata".into(), profiles.into());
+                        }
+                        Some("InstallProfile") => {
+                            let payload = req["Payload"].as_data().unwrap();
+                            let profile: Dictionary = plist::from_bytes(payload).unwrap();
+                            let content = profile["PayloadContent"].as_array().unwrap();
+                            if profile["PayloadIdentifier"] == KIOSK_PROFILE_IDENTIFIER.into() {
+                                let lock = content[0].as_dictionary().unwrap()["App"]
+                                    .as_dictionary()
+                                    .unwrap();
+                                assert_eq!(lock["Identifier"], "com.example.kiosk".into());
+                            } else {
+                                let system = content[0].as_dictionary().unwrap()["System"]
+                                    .as_dictionary()
+                                    .unwrap();
+                                assert_eq!(system["Enable-Private-Data"], true.into());
+                            }
+                        }
+                        Some("RemoveProfile") => {
+                            let profile = req["ProfileIdentifier"].as_data().unwrap();
+                            let profile: Dictionary = plist::from_bytes(profile).unwrap();
+                            let uuid = match profile["PayloadIdentifier"].as_string() {
+                                Some(LOGGING_PROFILE_IDENTIFIER) => LOGGING_PROFILE_UUID,
+                                Some(KIOSK_PROFILE_IDENTIFIER) => KIOSK_PROFILE_UUID,
+                                id => panic!("Unexpected profile {id:?}"),
+                            };
+                            assert_eq!(profile["PayloadUUID"], uuid.into());
+                        }
+                        _ => {
+                            let mut error = Dictionary::new();
+                            error.insert("LocalizedDescription".into(), "Unknown request".into());
+                            res.insert("Status".into(), "Error".into());
+                            res.insert(
+                                "ErrorChain".into(),
+                                vec![plist::Value::Dictionary(error)].into(),
+                            );
+                        }
+                    }
+                    transport.send_plist(res).await?;
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn management_status_is_read() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(McInstallClient::service_name(), McInstallResponder);
+
+        let status = management_status(&provider).await.unwrap();
+        assert!(status.supervised);
+        assert!(status.dep_enrolled);
+        assert!(!status.enrollment_mandatory);
+        assert_eq!(status.organization_name.as_deref(), Some("Example Corp"));
+        assert_eq!(status.profiles, ["com.example.mdm"]);
+
+        let mut client = McInstallClient::connect(&provider).await.unwrap();
+        match client.request("Flush").await {
+            Err(IdeviceError::ProfileRequestFailed(reason)) => {
+                assert_eq!(reason, "Unknown request")
+            }
+            r => panic!("Expected the request to fail, got {r:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn logging_profile_needs_confirmation() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(McInstallClient::service_name(), McInstallResponder);
+        let profile =
+            LoggingProfile::new().with_subsystem_level("com.example", LoggingLevel::Debug);
+
+        let installed = install_logging_profile(&provider, &profile, |_| false)
+            .await
+            .unwrap();
+        assert!(!installed);
+
+        let installed = install_logging_profile(&provider, &profile, |w| {
+            assert_eq!(w, PRIVATE_DATA_WARNING);
+            true
+        })
+        .await
+        .unwrap();
+        assert!(installed);
+        remove_logging_profile(&provider).await.unwrap();
+
+        let mut client = McInstallClient::connect(&provider).await.unwrap();
+        assert!(matches!(
+            client.remove_profile("com.example.missing").await,
+            Err(IdeviceError::ProfileRequestFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn kiosk_profile() {
+        let profile = KioskProfile::single_app("com.example.kiosk")
+            .with_option(AppLockOption::DisableAutoLock)
+            .with_user_option(UserAccessibilityOption::Zoom)
+            .with_guided_access_app("com.example.exam");
+        let bytes = profile.to_bytes().unwrap();
+        let parsed: Dictionary = plist::from_bytes(&bytes).unwrap();
+        let content = parsed["PayloadContent"].as_array().unwrap();
+        assert_eq!(content.len(), 2);
+        let lock = content[0].as_dictionary().unwrap()["App"]
+            .as_dictionary()
+            .unwrap();
+        assert_eq!(
+            lock["Options"].as_dictionary().unwrap()["DisableAutoLock"],
+            true.into()
+        );
+        assert_eq!(
+            content[1].as_dictionary().unwrap()["autonomousSingleAppModePermittedAppIDs"],
+            vec![plist::Value::from("com.example.exam")].into()
+        );
+
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(McInstallClient::service_name(), McInstallResponder);
+        install_kiosk_profile(&provider, &profile).await.unwrap();
+        remove_kiosk_profile(&provider).await.unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+//! Media library helpers
+//!
+//! This module lists, exports and imports the photos and videos in the device's
+//! DCIM folder over AFC, like mounting the media partition with ifuse would, and reads
+//! their embedded thumbnails for galleries.
+
+use crate::afc::AfcClient;
+use crate::notification_proxy::NotificationProxyClient;
+use crate::{provider::IdeviceProvider, sync_lock, IdeviceError, IdeviceService};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod thumbnail;
+pub use thumbnail::{thumbnail, Thumbnail, ThumbnailFormat};
+
+/// Camera roll root on the media partition
+pub const DCIM_DIRECTORY: &str = "/DCIM";
+
+/// Directory new imports are written to when the device has none of its own yet
+const DEFAULT_IMPORT_DIRECTORY: &str = "/DCIM/100APPLE";
+
+/// How much of each image is read to find its EXIF data
+const EXIF_PREFIX_LEN: u64 = 64 * 1024;
+
+/// Extensions that can carry EXIF data
+const EXIF_EXTENSIONS: &[&str] = &["jpg", "jpeg", "heic", "tif", "tiff", "dng"];
+
+/// A photo or video in the camera roll
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Photo {
+    /// AFC path, e.g. ``/DCIM/100APPLE/IMG_0001.HEIC``
+    pub path: String,
+    pub size: u64,
+    /// When the photo was taken. This is the EXIF ``DateTimeOriginal`` when there is one,
+    /// read as UTC since EXIF has no time zone, and the file's modification time otherwise.
+    pub taken: Option<SystemTime>,
+}
+
+impl Photo {
+    /// File name without the directory
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Client for the camera roll
+pub struct MediaClient {
+    afc: AfcClient,
+    notifications: NotificationProxyClient,
+}
+
+impl MediaClient {
+    /// Connect to AFC and the notification proxy
+    pub async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        let afc = AfcClient::connect(provider).await?;
+        let notifications = NotificationProxyClient::connect(provider).await?;
+
+        Ok(Self::new(afc, notifications))
+    }
+
+    /// Create a media client from existing connections
+    pub fn new(afc: AfcClient, notifications: NotificationProxyClient) -> Self {
+        Self { afc, notifications }
+    }
+
+    /// List the camera roll, oldest first.
+    /// Items taken before `since` are skipped. Items without any date are always included.
+    pub async fn list_photos(
+        &mut self,
+        since: Option<SystemTime>,
+    ) -> Result<Vec<Photo>, IdeviceError> {
+        let mut photos = Vec::new();
+        let mut pending = vec![DCIM_DIRECTORY.to_string()];
+
+        while let Some(dir) = pending.pop() {
+            for entry in self.afc.read_directory(&dir).await? {
+                // Skips . and .. as well as the Photos app's hidden metadata
+                if entry.starts_with('.') {
+                    continue;
+                }
+
+                let path = format!("{}/{}", dir, entry);
+                let info = self.afc.get_file_info(&path).await?;
+
+                match info.get("st_ifmt").map(|s| s.as_str()) {
+                    Some("S_IFDIR") => {
+                        pending.push(path);
+                        continue;
+                    }
+                    Some("S_IFREG") => {}
+                    _ => continue,
+                }
+
+                let size = info
+                    .get("st_size")
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .unwrap_or(0);
+                let modified = info
+                    .get("st_mtime")
+                    .and_then(|s| s.parse::<u64>().ok())
+                    .map(|ns| UNIX_EPOCH + Duration::from_nanos(ns));
+
+                let extension = entry.rsplit('.').next().unwrap_or("").to_lowercase();
+                let taken = if EXIF_EXTENSIONS.contains(&extension.as_str()) {
+                    let prefix = self.afc.read_file_prefix(&path, EXIF_PREFIX_LEN).await?;
+                    exif_date_taken(&prefix).or(modified)
+                } else {
+                    modified
+                };
+
+                if let (Some(since), Some(taken)) = (since, taken) {
+                    if taken < since {
+                        continue;
+                    }
+                }
+
+                photos.push(Photo { path, size, taken });
+            }
+        }
+
+        photos.sort_by(|a, b| a.taken.cmp(&b.taken).then_with(|| a.path.cmp(&b.path)));
+        Ok(photos)
+    }
+
+    /// Copy a photo into `dest`, keeping its file name.
+    /// Returns the path that was written.
+    pub async fn export(
+        &mut self,
+        photo: &Photo,
+        dest: impl AsRef<Path>,
+    ) -> Result<PathBuf, IdeviceError> {
+        let data = self.afc.read_file(&photo.path).await?;
+
+        let path = dest.as_ref().join(photo.file_name());
+        tokio::fs::write(&path, data).await?;
+
+        Ok(path)
+    }
+
+    /// Read the small preview embedded in a photo instead of the whole file, see ``thumbnail``
+    pub async fn thumbnail(&mut self, photo: &Photo) -> Result<Option<Thumbnail>, IdeviceError> {
+        thumbnail(&mut self.afc, &photo.path).await
+    }
+
+    /// Upload a photo into the camera roll.
+    ///
+    /// The upload is done holding the sync lock, and giving it back is what makes the device
+    /// scan DCIM and add the new file to the Photos library.
+    pub async fn import_photo(&mut self, path: impl AsRef<Path>) -> Result<Photo, IdeviceError> {
+        let path = path.as_ref();
+        let file_name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => return Err(IdeviceError::AfcError("Invalid file name".to_string())),
+        };
+        let data = tokio::fs::read(path).await?;
+
+        let dir = self.import_directory().await?;
+        let dest = format!("{}/{}", dir, file_name);
+        if !self.afc.get_file_info(&dest).await?.is_empty() {
+            return Err(IdeviceError::AfcError(format!("{} already exists", dest)));
+        }
+
+        let handle = sync_lock::lock(&mut self.afc, &mut self.notifications).await?;
+        let res = self.afc.write_file(&dest, &data).await;
+        // Always give the lock back, or the device keeps showing the sync indicator
+        sync_lock::unlock(&mut self.afc, &mut self.notifications, handle).await?;
+        res?;
+
+        Ok(Photo {
+            path: dest,
+            size: data.len() as u64,
+            taken: exif_date_taken(&data),
+        })
+    }
+
+    /// The last NNNAPPLE directory, where the camera is writing
+    async fn import_directory(&mut self) -> Result<String, IdeviceError> {
+        let mut dirs = self
+            .afc
+            .read_directory(DCIM_DIRECTORY)
+            .await?
+            .into_iter()
+            .filter(|d| {
+                d.len() == 8 && d.ends_with("APPLE") && d[..3].chars().all(|c| c.is_ascii_digit())
+            })
+            .collect::<Vec<String>>();
+        dirs.sort();
+
+        match dirs.pop() {
+            Some(dir) => Ok(format!("{}/{}", DCIM_DIRECTORY, dir)),
+            None => {
+                self.afc.make_directory(DEFAULT_IMPORT_DIRECTORY).await?;
+                Ok(DEFAULT_IMPORT_DIRECTORY.to_string())
+            }
+        }
+    }
+}
+
+/// Find the EXIF block in a JPEG or HEIC file and read when the photo was taken
+fn exif_date_taken(data: &[u8]) -> Option<SystemTime> {
+    let start = data.windows(6).position(|w| w == b"Exif\0\0")? + 6;
+    let tiff = Tiff::new(&data[start..])?;
+
+    let ifd0 = tiff.u32(4)? as usize;
+    // DateTimeOriginal lives in the EXIF sub-IFD, DateTime in IFD0 is the fallback
+    let original = tiff
+        .find_tag(ifd0, 0x8769)
+        .and_then(|exif_ifd| tiff.find_tag(exif_ifd as usize, 0x9003))
+        .and_then(|offset| tiff.date(offset as usize));
+
+    original.or_else(|| {
+        tiff.find_tag(ifd0, 0x0132)
+            .and_then(|offset| tiff.date(offset as usize))
+    })
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    /// Value field of the tag's entry. For the tags used here that's always an offset.
+    fn find_tag(&self, ifd: usize, tag: u16) -> Option<u32> {
+        let count = self.u16(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|entry| self.u16(*entry) == Some(tag))
+            .and_then(|entry| self.u32(entry + 8))
+    }
+
+    /// Parses an EXIF ``YYYY:MM:DD HH:MM:SS`` string
+    fn date(&self, offset: usize) -> Option<SystemTime> {
+        let s = std::str::from_utf8(self.data.get(offset..offset + 19)?).ok()?;
+        let num = |range: std::ops::Range<usize>| s.get(range)?.parse::<u64>().ok();
+
+        let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+        let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+        if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+
+        let secs = days_since_epoch(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
+/// Days from 1970-01-01 to the date, for dates after 1970
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Count years from March so the leap day is at the end
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,441 @@
+//! Thumbnails read straight out of photos
+//!
+//! JPEGs carry a small JPEG in their EXIF data, and HEIC files have a thumbnail item that
+//! the ``meta`` box points to. Both sit near known offsets, so only the start of the file
+//! and the thumbnail itself are read instead of the whole photo.
+
+use super::{Tiff, EXIF_PREFIX_LEN};
+use crate::afc::AfcClient;
+use crate::IdeviceError;
+
+/// The most read for the ``meta`` box or a thumbnail. Real ones are a small fraction of
+/// this, so anything bigger comes from a damaged file.
+const MAX_READ_LEN: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    /// A bare HEVC image, which needs ``decoder_config`` to decode
+    Hevc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub format: ThumbnailFormat,
+    pub data: Vec<u8>,
+    /// The ``hvcC`` box of a HEVC thumbnail
+    pub decoder_config: Option<Vec<u8>>,
+    /// From the HEIC ``ispe`` property, JPEG thumbnails have it in their own header
+    pub dimensions: Option<(u32, u32)>,
+}
+
+/// Where a thumbnail is in the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Located {
+    format: ThumbnailFormat,
+    /// Offset and length of each piece, in order
+    extents: Vec<(u64, u64)>,
+    decoder_config: Option<Vec<u8>>,
+    dimensions: Option<(u32, u32)>,
+}
+
+/// Reads the thumbnail embedded in a photo.
+/// Returns ``None`` for files without one, such as screenshots and videos.
+pub async fn thumbnail(afc: &mut AfcClient, path: &str) -> Result<Option<Thumbnail>, IdeviceError> {
+    let mut prefix = afc.read_file_prefix(path, EXIF_PREFIX_LEN).await?;
+
+    let located = if prefix.starts_with(&[0xff, 0xd8]) {
+        locate_exif_thumbnail(&prefix)
+    } else {
+        // The meta box can outgrow the prefix when the image is split into many tiles
+        if let Some((offset, len)) = find_box(&prefix, b"meta") {
+            let end = match offset.checked_add(len) {
+                Some(end) if len <= MAX_READ_LEN => end,
+                _ => return Ok(None),
+            };
+            if end > prefix.len() as u64 {
+                let rest = afc
+                    .read_file_range(path, prefix.len() as u64, end - prefix.len() as u64)
+                    .await?;
+                prefix.extend_from_slice(&rest);
+            }
+        }
+        locate_heic_thumbnail(&prefix)
+    };
+    let located = match located {
+        Some(l) => l,
+        None => return Ok(None),
+    };
+
+    let mut data = Vec::new();
+    for &(offset, len) in &located.extents {
+        let end = match offset.checked_add(len) {
+            Some(end) if len <= MAX_READ_LEN - data.len() as u64 => end,
+            _ => return Ok(None),
+        };
+        match prefix.get(offset as usize..end as usize) {
+            Some(d) => data.extend_from_slice(d),
+            None => data.extend_from_slice(&afc.read_file_range(path, offset, len).await?),
+        }
+    }
+
+    Ok(Some(Thumbnail {
+        format: located.format,
+        data,
+        decoder_config: located.decoder_config,
+        dimensions: located.dimensions,
+    }))
+}
+
+/// The thumbnail pointed to by IFD1 of the EXIF data
+fn locate_exif_thumbnail(data: &[u8]) -> Option<Located> {
+    let start = data.windows(6).position(|w| w == b"Exif\0\0")? + 6;
+    let tiff = Tiff::new(&data[start..])?;
+
+    let ifd0 = tiff.u32(4)? as usize;
+    let entries = tiff.u16(ifd0)? as usize;
+    let ifd1 = tiff.u32(ifd0 + 2 + entries * 12)? as usize;
+    if ifd1 == 0 {
+        return None;
+    }
+    // JPEGInterchangeFormat and JPEGInterchangeFormatLength
+    let offset = tiff.find_tag(ifd1, 0x0201)? as u64;
+    let len = tiff.find_tag(ifd1, 0x0202)? as u64;
+    if len == 0 {
+        return None;
+    }
+
+    Some(Located {
+        format: ThumbnailFormat::Jpeg,
+        extents: vec![(start as u64 + offset, len)],
+        decoder_config: None,
+        dimensions: None,
+    })
+}
+
+/// The item the ``meta`` box marks as a thumbnail (``thmb``) of another item
+fn locate_heic_thumbnail(data: &[u8]) -> Option<Located> {
+    let (meta_offset, meta_len) = find_box(data, b"meta")?;
+    let meta = data.get(meta_offset as usize..meta_offset.checked_add(meta_len)? as usize)?;
+    // meta is a full box, skip its header, version and flags
+    let header = box_header_len(meta)?;
+    let children = meta.get(header + 4..)?;
+
+    let iref = box_contents(children, b"iref")?;
+    let item = thumbnail_item(iref)?;
+    let extents = item_extents(box_contents(children, b"iloc")?, item)?;
+
+    let mut decoder_config = None;
+    let mut dimensions = None;
+    if let Some(iprp) = box_contents(children, b"iprp") {
+        let ipco = box_contents(iprp, b"ipco")?;
+        let properties = boxes(ipco);
+        for index in item_properties(box_contents(iprp, b"ipma")?, item)? {
+            let (kind, property) = match properties.get(index.checked_sub(1)?) {
+                Some(p) => *p,
+                None => continue,
+            };
+            match kind {
+                b"hvcC" => decoder_config = Some(property.to_vec()),
+                b"ispe" => {
+                    let header = box_header_len(property)?;
+                    let u32_at = |o: usize| {
+                        Some(u32::from_be_bytes(property.get(o..o + 4)?.try_into().ok()?))
+                    };
+                    dimensions = Some((u32_at(header + 4)?, u32_at(header + 8)?));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Some(Located {
+        format: ThumbnailFormat::Hevc,
+        extents,
+        decoder_config,
+        dimensions,
+    })
+}
+
+/// Length of a box header, 8 bytes or 16 with a 64 bit size
+fn box_header_len(data: &[u8]) -> Option<usize> {
+    let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+    Some(if size == 1 { 16 } else { 8 })
+}
+
+/// Each box in `data` as its type and the whole box, header included
+fn boxes(data: &[u8]) -> Vec<(&[u8; 4], &[u8])> {
+    let mut found = Vec::new();
+    let mut offset = 0usize;
+    while let Some(size) = data.get(offset..offset + 4) {
+        let kind: &[u8; 4] = match data
+            .get(offset + 4..offset + 8)
+            .and_then(|k| k.try_into().ok())
+        {
+            Some(k) => k,
+            None => break,
+        };
+        let size = match u32::from_be_bytes(size.try_into().unwrap()) {
+            0 => (data.len() - offset) as u64,
+            1 => match data.get(offset + 8..offset + 16) {
+                Some(s) => u64::from_be_bytes(s.try_into().unwrap()),
+                None => break,
+            },
+            s => s as u64,
+        };
+        if size < 8 {
+            break;
+        }
+        let end = (offset as u64).saturating_add(size).min(data.len() as u64) as usize;
+        found.push((kind, &data[offset..end]));
+        offset = end;
+        if end == data.len() {
+            break;
+        }
+    }
+    found
+}
+
+/// Offset and full length of the first top level box of a type. The length may run past
+/// the end of `data` when only the start of the file was read.
+fn find_box(data: &[u8], kind: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut offset = 0u64;
+    loop {
+        let start = usize::try_from(offset).ok()?;
+        let header = data.get(start..start.checked_add(8)?)?;
+        let size = match u32::from_be_bytes(header[..4].try_into().ok()?) {
+            0 => data.len() as u64 - offset,
+            1 => u64::from_be_bytes(
+                data.get(start + 8..start.checked_add(16)?)?
+                    .try_into()
+                    .ok()?,
+            ),
+            s => s as u64,
+        };
+        if &header[4..8] == kind {
+            return Some((offset, size));
+        }
+        if size < 8 {
+            return None;
+        }
+        offset = offset.checked_add(size)?;
+    }
+}
+
+/// The contents of the first box of a type in `data`, after its header
+fn box_contents<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .into_iter()
+        .find(|(k, _)| *k == kind)
+        .and_then(|(_, b)| b.get(box_header_len(b)?..))
+}
+
+/// Reads big endian numbers of varying sizes out of a box
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn uint(&mut self, size: usize) -> Option<u64> {
+        let bytes = self.data.get(self.offset..self.offset + size)?;
+        self.offset += size;
+        Some(bytes.iter().fold(0u64, |n, b| n << 8 | *b as u64))
+    }
+}
+
+/// The first item with a ``thmb`` reference in ``iref``
+fn thumbnail_item(iref: &[u8]) -> Option<u32> {
+    let version = *iref.first()?;
+    let id_size = if version == 0 { 2 } else { 4 };
+    boxes(iref.get(4..)?)
+        .into_iter()
+        .find(|(kind, _)| *kind == b"thmb")
+        .and_then(|(_, reference)| {
+            let mut reader = Reader::new(reference.get(box_header_len(reference)?..)?);
+            reader.uint(id_size).map(|id| id as u32)
+        })
+}
+
+/// Where an item's data is, from ``iloc``
+fn item_extents(iloc: &[u8], item: u32) -> Option<Vec<(u64, u64)>> {
+    let mut reader = Reader::new(iloc);
+    let version = reader.uint(1)?;
+    reader.uint(3)?;
+    let sizes = reader.uint(2)?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = (sizes >> 8 & 0xf) as usize;
+    let base_offset_size = (sizes >> 4 & 0xf) as usize;
+    let index_size = if version == 1 || version == 2 {
+        (sizes & 0xf) as usize
+    } else {
+        0
+    };
+    let id_size = if version < 2 { 2 } else { 4 };
+
+    let items = reader.uint(id_size)?;
+    for _ in 0..items {
+        let id = reader.uint(id_size)?;
+        let construction_method = if version == 1 || version == 2 {
+            reader.uint(2)? & 0xf
+        } else {
+            0
+        };
+        reader.uint(2)?;
+        let base_offset = reader.uint(base_offset_size)?;
+        let extent_count = reader.uint(2)?;
+
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            reader.uint(index_size)?;
+            let offset = reader.uint(offset_size)?;
+            let length = reader.uint(length_size)?;
+            extents.push((base_offset.checked_add(offset)?, length));
+        }
+        if id == item as u64 {
+            // Data kept inside the meta box (idat) isn't used for thumbnails
+            return (construction_method == 0).then_some(extents);
+        }
+    }
+    None
+}
+
+/// The 1 based ``ipco`` indexes of an item's properties, from ``ipma``
+fn item_properties(ipma: &[u8], item: u32) -> Option<Vec<usize>> {
+    let mut reader = Reader::new(ipma);
+    let version = reader.uint(1)?;
+    let flags = reader.uint(3)?;
+    let id_size = if version < 1 { 2 } else { 4 };
+    let index_size = if flags & 1 == 1 { 2 } else { 1 };
+    // The top bit of each association marks the property as essential
+    let index_mask = if index_size == 2 { 0x7fff } else { 0x7f };
+
+    let entries = reader.uint(4)?;
+    for _ in 0..entries {
+        let id = reader.uint(id_size)?;
+        let count = reader.uint(1)?;
+        let mut indexes = Vec::new();
+        for _ in 0..count {
+            indexes.push((reader.uint(index_size)? & index_mask) as usize);
+        }
+        if id == item as u64 {
+            return Some(indexes);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+        let mut contents = vec![version];
+        contents.extend_from_slice(&flags.to_be_bytes()[1..]);
+        contents.extend_from_slice(body);
+        plain_box(kind, &contents)
+    }
+
+    fn plain_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut b = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(body);
+        b
+    }
+
+    #[test]
+    fn exif_thumbnail_is_found() {
+        // Little endian TIFF with an empty IFD0 pointing to an IFD1 with the thumbnail tags
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&0u16.to_le_bytes());
+        tiff.extend_from_slice(&14u32.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        for (tag, value) in [(0x0201u16, 100u32), (0x0202, 2000)] {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&4u16.to_le_bytes());
+            tiff.extend_from_slice(&1u32.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1, 0, 0];
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+
+        let located = locate_exif_thumbnail(&jpeg).unwrap();
+        assert_eq!(located.format, ThumbnailFormat::Jpeg);
+        assert_eq!(located.extents, [(12 + 100, 2000)]);
+    }
+
+    #[test]
+    fn heic_thumbnail_is_found() {
+        // Item 2 is a thumbnail of item 1
+        let mut thmb = 2u16.to_be_bytes().to_vec();
+        thmb.extend_from_slice(&1u16.to_be_bytes());
+        thmb.extend_from_slice(&1u16.to_be_bytes());
+        let iref = full_box(b"iref", 0, 0, &plain_box(b"thmb", &thmb));
+
+        // Version 1, 4 byte offsets and lengths, no base offset
+        let mut iloc = vec![0x44, 0x00];
+        iloc.extend_from_slice(&2u16.to_be_bytes());
+        for (id, offset, len) in [(1u16, 5000u32, 90000u32), (2, 95000, 3000)] {
+            iloc.extend_from_slice(&id.to_be_bytes());
+            iloc.extend_from_slice(&0u16.to_be_bytes());
+            iloc.extend_from_slice(&0u16.to_be_bytes());
+            iloc.extend_from_slice(&1u16.to_be_bytes());
+            iloc.extend_from_slice(&offset.to_be_bytes());
+            iloc.extend_from_slice(&len.to_be_bytes());
+        }
+        let iloc = full_box(b"iloc", 1, 0, &iloc);
+
+        let mut ispe = 320u32.to_be_bytes().to_vec();
+        ispe.extend_from_slice(&240u32.to_be_bytes());
+        let mut ipco = plain_box(b"hvcC", &[1, 2, 3]);
+        ipco.extend_from_slice(&full_box(b"ispe", 0, 0, &ispe));
+        let mut ipma = 1u32.to_be_bytes().to_vec();
+        ipma.extend_from_slice(&2u16.to_be_bytes());
+        ipma.extend_from_slice(&[2, 0x81, 0x02]);
+        let mut iprp = plain_box(b"ipco", &ipco);
+        iprp.extend_from_slice(&full_box(b"ipma", 0, 0, &ipma));
+
+        let mut meta_body = iref;
+        meta_body.extend_from_slice(&iloc);
+        meta_body.extend_from_slice(&plain_box(b"iprp", &iprp));
+        let mut heic = plain_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        heic.extend_from_slice(&full_box(b"meta", 0, 0, &meta_body));
+
+        let located = locate_heic_thumbnail(&heic).unwrap();
+        assert_eq!(located.format, ThumbnailFormat::Hevc);
+        assert_eq!(located.extents, [(95000, 3000)]);
+        assert_eq!(located.decoder_config, Some(plain_box(b"hvcC", &[1, 2, 3])));
+        assert_eq!(located.dimensions, Some((320, 240)));
+
+        assert!(find_box(&heic, b"meta").is_some());
+        assert!(find_box(&heic, b"mdat").is_none());
+    }
+
+    #[test]
+    fn oversized_boxes_are_ignored() {
+        // A meta box with a 64 bit size that runs past the end of the address space
+        let mut heic = plain_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        heic.extend_from_slice(&1u32.to_be_bytes());
+        heic.extend_from_slice(b"meta");
+        heic.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        assert_eq!(find_box(&heic, b"meta"), Some((24, u64::MAX - 8)));
+        assert_eq!(locate_heic_thumbnail(&heic), None);
+
+        // An extent whose offset overflows once the base offset is added
+        let mut iloc = vec![0x88, 0x80];
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&0u16.to_be_bytes());
+        iloc.extend_from_slice(&u64::MAX.to_be_bytes());
+        iloc.extend_from_slice(&1u16.to_be_bytes());
+        iloc.extend_from_slice(&1u64.to_be_bytes());
+        iloc.extend_from_slice(&16u64.to_be_bytes());
+        assert_eq!(item_extents(&full_box(b"iloc", 0, 0, &iloc)[8..], 1), None);
+    }
+}
@@ -0,0 +1,66 @@
+// Jackson Coxson
+// Metrics for daemons that keep devices connected for a long time.
+// Everything goes through the ``metrics`` facade, so nothing is collected until the embedding
+// program installs a recorder, like metrics-exporter-prometheus for a ``/metrics`` endpoint.
+
+use std::time::Duration;
+
+use ::metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+
+/// Bytes written to device connections
+pub const BYTES_SENT: &str = "idevice_bytes_sent_total";
+/// Bytes read from device connections
+pub const BYTES_RECEIVED: &str = "idevice_bytes_received_total";
+/// How long lockdown took to start a service, labeled by ``service`` and ``result``
+pub const SERVICE_START_SECONDS: &str = "idevice_service_start_seconds";
+/// Tunnels the supervisor opened again after they dropped, labeled by ``udid``
+pub const TUNNEL_RECONNECTS: &str = "idevice_tunnel_reconnects_total";
+/// Heartbeats the device didn't send in time
+pub const HEARTBEAT_MISSES: &str = "idevice_heartbeat_misses_total";
+
+/// Gives the recorder units and help text for the metrics above.
+/// Call it once after installing the recorder.
+pub fn describe() {
+    describe_counter!(
+        BYTES_SENT,
+        Unit::Bytes,
+        "Bytes written to device connections"
+    );
+    describe_counter!(
+        BYTES_RECEIVED,
+        Unit::Bytes,
+        "Bytes read from device connections"
+    );
+    describe_histogram!(
+        SERVICE_START_SECONDS,
+        Unit::Seconds,
+        "Time taken by lockdown to start a service"
+    );
+    describe_counter!(TUNNEL_RECONNECTS, "Tunnels opened again after they dropped");
+    describe_counter!(HEARTBEAT_MISSES, "Heartbeats not received in time");
+}
+
+pub(crate) fn sent(bytes: usize) {
+    counter!(BYTES_SENT).increment(bytes as u64);
+}
+
+pub(crate) fn received(bytes: usize) {
+    counter!(BYTES_RECEIVED).increment(bytes as u64);
+}
+
+pub(crate) fn service_started(service: &str, elapsed: Duration, ok: bool) {
+    histogram!(
+        SERVICE_START_SECONDS,
+        "service" => service.to_string(),
+        "result" => if ok { "ok" } else { "error" }
+    )
+    .record(elapsed.as_secs_f64());
+}
+
+pub(crate) fn tunnel_reconnected(udid: &str) {
+    counter!(TUNNEL_RECONNECTS, "udid" => udid.to_string()).increment(1);
+}
+
+pub(crate) fn heartbeat_missed() {
+    counter!(HEARTBEAT_MISSES).increment(1);
+}
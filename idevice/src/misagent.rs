@@ -31,6 +31,10 @@ impl IdeviceService for MisagentClient {
                 .await?;
         }
 
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
         Ok(Self::new(idevice))
     }
 }
@@ -2,7 +2,9 @@
 //! 
 //! This module provides functionality for device backup and restore operations.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
+use crate::progress::{ProgressEvent, ProgressObserver};
+use crate::throttle::RateLimiter;
+use crate::{provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::path::Path;
 
@@ -19,16 +21,37 @@ pub enum BackupType {
 
 /// Mobile Backup client for iOS device backup/restore operations
 pub struct MobileBackupClient {
-    socket: tokio::net::TcpStream,
+    socket: Box<dyn ReadWrite>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl IdeviceService for MobileBackupClient {
+    fn service_name() -> &'static str {
+        MOBILE_BACKUP_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl MobileBackupClient {
-    /// Connect to the Mobile Backup service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(MOBILE_BACKUP_SERVICE_NAME).await?;
-        Ok(Self {
-            socket: service.socket,
-        })
+    /// Create a client over an existing connection to the Mobile Backup service
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        Self {
+            socket,
+            rate_limiter: None,
+        }
+    }
+
+    /// Limit how fast backup data is sent and received, or ``None`` to go as fast as the link allows.
+    /// Sharing a limiter between clients limits their combined rate.
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<RateLimiter>) {
+        self.rate_limiter = rate_limiter;
     }
 
     /// Start a backup operation
@@ -54,6 +77,21 @@ impl MobileBackupClient {
         self.read_confirmation().await
     }
 
+    /// Start a backup operation, reporting to the observer.
+    /// The service doesn't report how far along it is, so only the start and end are emitted.
+    pub async fn start_backup_with_observer(
+        &mut self,
+        backup_type: BackupType,
+        target_dir: &Path,
+        encryption_key: Option<&str>,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        observer.phase("Backing up");
+        let res = self.start_backup(backup_type, target_dir, encryption_key).await;
+        observer.complete(res)
+    }
+
     /// Start a restore operation
     pub async fn start_restore(
         &mut self,
@@ -75,7 +113,7 @@ impl MobileBackupClient {
     /// Get backup information
     pub async fn get_backup_info(&mut self) -> Result<plist::Value, IdeviceError> {
         let dict = plist::Dictionary::from_iter(vec![
-            ("MessageName".into(), "GetBackupInfo".into())
+            ("MessageName".to_string(), plist::Value::from("GetBackupInfo"))
         ]);
         
         self.send_plist(&dict).await?;
@@ -84,9 +122,12 @@ impl MobileBackupClient {
 
     // Helper methods
     async fn send_plist(&mut self, dict: &plist::Dictionary) -> Result<(), IdeviceError> {
-        let xml = plist::to_format_xml(dict)?;
-        let xml_bytes = xml.into_bytes();
+        let mut xml_bytes = Vec::new();
+        plist::to_writer_xml(&mut xml_bytes, dict)?;
         
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(4 + xml_bytes.len()).await;
+        }
         let len = (xml_bytes.len() as u32).to_be_bytes();
         self.socket.write_all(&len).await?;
         self.socket.write_all(&xml_bytes).await?;
@@ -98,8 +139,11 @@ impl MobileBackupClient {
         self.socket.read_exact(&mut len_buf).await?;
         let len = u32::from_be_bytes(len_buf) as usize;
         
-        let mut data = vec![0u8; len];
+        let mut data = vec![0u8; crate::util::check_message_size(len)?];
         self.socket.read_exact(&mut data).await?;
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(4 + len).await;
+        }
         plist::from_bytes(&data).map_err(Into::into)
     }
 
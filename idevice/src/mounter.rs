@@ -1,9 +1,17 @@
 // Jackson Coxson
 
+use std::path::PathBuf;
+
 use log::debug;
 use openssl::sha::Sha384;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt};
 
-use crate::{lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+use crate::{
+    capabilities::{Capabilities, DeveloperImageKind, IosVersion},
+    lockdownd::LockdowndClient,
+    progress::{self, ProgressEvent, ProgressObserver},
+    Idevice, IdeviceError, IdeviceService,
+};
 
 #[cfg(feature = "tss")]
 use crate::tss::TSSRequest;
@@ -37,10 +45,64 @@ impl IdeviceService for ImageMounter {
                 .await?;
         }
 
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
         Ok(Self { idevice })
     }
 }
 
+/// An image reported by ``CopyDevices``
+#[derive(Debug, Clone)]
+pub struct MountedImage {
+    /// ``Developer`` for pre-iOS 17 images, ``Personalized`` and friends afterwards
+    pub image_type: Option<String>,
+    pub signature: Option<Vec<u8>>,
+    pub mount_path: Option<String>,
+    /// The raw entry returned by the device
+    pub info: plist::Dictionary,
+}
+
+impl MountedImage {
+    pub const DEVELOPER_MOUNT_PATH: &'static str = "/Developer";
+    pub const PERSONALIZED_MOUNT_PATH: &'static str = "/System/Developer";
+
+    pub fn from_dictionary(info: plist::Dictionary) -> Self {
+        let image_type = info
+            .get("DiskImageType")
+            .or_else(|| info.get("ImageType"))
+            .and_then(|t| t.as_string())
+            .map(|t| t.to_string());
+        let signature = info
+            .get("ImageSignature")
+            .and_then(|s| s.as_data())
+            .map(|s| s.to_vec());
+        let mount_path = info
+            .get("MountPath")
+            .and_then(|p| p.as_string())
+            .map(|p| p.to_string());
+
+        Self {
+            image_type,
+            signature,
+            mount_path,
+            info,
+        }
+    }
+
+    /// Whether this is a developer disk image, either the legacy or personalized variant
+    pub fn is_developer_image(&self) -> bool {
+        match self.mount_path.as_deref() {
+            Some(Self::DEVELOPER_MOUNT_PATH) | Some(Self::PERSONALIZED_MOUNT_PATH) => true,
+            _ => matches!(
+                self.image_type.as_deref(),
+                Some("Developer") | Some("Personalized")
+            ),
+        }
+    }
+}
+
 impl ImageMounter {
     pub fn new(idevice: Idevice) -> Self {
         Self { idevice }
@@ -60,6 +122,31 @@ impl ImageMounter {
         }
     }
 
+    /// Same as ``copy_devices``, but parsed into typed entries
+    pub async fn copy_mounted_images(&mut self) -> Result<Vec<MountedImage>, IdeviceError> {
+        let entries = self.copy_devices().await?;
+        let mut images = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match entry {
+                plist::Value::Dictionary(d) => images.push(MountedImage::from_dictionary(d)),
+                _ => {
+                    log::warn!("Mounted image entry wasn't a dictionary");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            }
+        }
+        Ok(images)
+    }
+
+    /// Returns true if a developer disk image (legacy or personalized) is mounted
+    pub async fn is_developer_image_mounted(&mut self) -> Result<bool, IdeviceError> {
+        Ok(self
+            .copy_mounted_images()
+            .await?
+            .iter()
+            .any(|i| i.is_developer_image()))
+    }
+
     /// Looks up an image and returns the signature
     pub async fn lookup_image(
         &mut self,
@@ -80,6 +167,11 @@ impl ImageMounter {
         }
     }
 
+    /// Uploads an image from memory. A memory-mapped file derefs to a slice and can be
+    /// passed as is, which sends it straight from the mapping.
+    ///
+    /// The image isn't reachable over AFC to hash, but the device checks it against the
+    /// signature when mounting, so an image corrupted on the way fails to mount.
     pub async fn upload_image(
         &mut self,
         image_type: impl Into<String>,
@@ -102,7 +194,6 @@ impl ImageMounter {
         Fut: std::future::Future<Output = ()>,
         S: Clone,
     {
-        let image_type = image_type.into();
         let image_size = match u64::try_from(image.len()) {
             Ok(i) => i,
             Err(e) => {
@@ -111,6 +202,74 @@ impl ImageMounter {
             }
         };
 
+        self.request_receive_bytes(image_type, image_size, signature)
+            .await?;
+
+        debug!("Sending image bytes");
+        self.idevice
+            .send_raw_with_progress(image, callback, state)
+            .await?;
+
+        self.finish_upload().await
+    }
+
+    /// Uploads an image by streaming it from disk in chunks.
+    /// Personalized images can be hundreds of megabytes, so this avoids reading them into memory.
+    pub async fn upload_image_from_path<Fut, S>(
+        &mut self,
+        image_type: impl Into<String>,
+        path: impl AsRef<std::path::Path>,
+        signature: Vec<u8>,
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<(), IdeviceError>
+    where
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let mut file = tokio::fs::File::open(path).await?;
+        self.upload_image_from_reader(image_type, &mut file, signature, callback, state)
+            .await
+    }
+
+    /// Uploads an image by streaming it from a reader in chunks, from its current position
+    /// to the end
+    pub async fn upload_image_from_reader<R, Fut, S>(
+        &mut self,
+        image_type: impl Into<String>,
+        reader: &mut R,
+        signature: Vec<u8>,
+        callback: impl Fn(((usize, usize), S)) -> Fut,
+        state: S,
+    ) -> Result<(), IdeviceError>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        let start = reader.stream_position().await?;
+        let image_size = reader.seek(std::io::SeekFrom::End(0)).await? - start;
+        reader.seek(std::io::SeekFrom::Start(start)).await?;
+
+        self.request_receive_bytes(image_type, image_size, signature)
+            .await?;
+
+        debug!("Streaming {image_size} image bytes");
+        self.idevice
+            .send_reader_with_progress(reader, image_size, callback, state)
+            .await?;
+
+        self.finish_upload().await
+    }
+
+    async fn request_receive_bytes(
+        &mut self,
+        image_type: impl Into<String>,
+        image_size: u64,
+        signature: Vec<u8>,
+    ) -> Result<(), IdeviceError> {
+        let image_type = image_type.into();
+
         let mut req = plist::Dictionary::new();
         req.insert("Command".into(), "ReceiveBytes".into());
         req.insert("ImageType".into(), image_type.into());
@@ -131,11 +290,10 @@ impl ImageMounter {
             _ => return Err(IdeviceError::UnexpectedResponse),
         }
 
-        debug!("Sending image bytes");
-        self.idevice
-            .send_raw_with_progress(image, callback, state)
-            .await?;
+        Ok(())
+    }
 
+    async fn finish_upload(&mut self) -> Result<(), IdeviceError> {
         let res = self.idevice.read_plist().await?;
         match res.get("Status") {
             Some(plist::Value::String(s)) => {
@@ -210,6 +368,52 @@ impl ImageMounter {
         }
     }
 
+    /// Unmounts whichever developer disk image is mounted, resolving its mount path from ``CopyDevices``.
+    /// Returns ``ImageNotMounted`` if no developer image is mounted.
+    pub async fn unmount_developer(&mut self) -> Result<(), IdeviceError> {
+        let image = match self
+            .copy_mounted_images()
+            .await?
+            .into_iter()
+            .find(|i| i.is_developer_image())
+        {
+            Some(i) => i,
+            None => return Err(IdeviceError::ImageNotMounted),
+        };
+
+        let mount_path = match image.mount_path {
+            Some(p) => p,
+            None => match image.image_type.as_deref() {
+                Some("Developer") => MountedImage::DEVELOPER_MOUNT_PATH.to_string(),
+                _ => MountedImage::PERSONALIZED_MOUNT_PATH.to_string(),
+            },
+        };
+        debug!("Unmounting developer image at {mount_path}");
+        self.unmount_image(mount_path).await
+    }
+
+    /// Unmounts the developer image if one is mounted, then uploads and mounts the given image again.
+    /// Useful when the device still reports a mount that went stale after a reboot.
+    pub async fn remount(
+        &mut self,
+        image_type: impl Into<String>,
+        image: &[u8],
+        signature: Vec<u8>,
+        trust_cache: Option<Vec<u8>>,
+        info_plist: Option<plist::Value>,
+    ) -> Result<(), IdeviceError> {
+        let image_type = image_type.into();
+        match self.unmount_developer().await {
+            Ok(()) | Err(IdeviceError::ImageNotMounted) => {}
+            Err(e) => return Err(e),
+        }
+
+        self.upload_image(image_type.as_str(), image, signature.clone())
+            .await?;
+        self.mount_image(image_type, signature, trust_cache, info_plist)
+            .await
+    }
+
     /// Queries the personalization manifest from the device.
     /// On failure, the socket must be closed and reestablished.
     pub async fn query_personalization_manifest(
@@ -321,6 +525,34 @@ impl ImageMounter {
         Ok(())
     }
 
+    /// Uploads and mounts a developer image, reporting to the observer
+    pub async fn mount_developer_with_observer(
+        &mut self,
+        image: &[u8],
+        signature: Vec<u8>,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        observer.phase("Uploading");
+        let res = match self
+            .upload_image_with_progress(
+                "Developer",
+                image,
+                signature.clone(),
+                progress::forward,
+                observer.clone(),
+            )
+            .await
+        {
+            Ok(()) => {
+                observer.phase("Mounting");
+                self.mount_image("Developer", signature, None, None).await
+            }
+            Err(e) => Err(e),
+        };
+        observer.complete(res)
+    }
+
     #[cfg(feature = "tss")]
     pub async fn mount_personalized(
         &mut self,
@@ -396,6 +628,35 @@ impl ImageMounter {
         Ok(())
     }
 
+    #[cfg(feature = "tss")]
+    /// ``mount_personalized`` reporting to the observer
+    #[allow(clippy::too_many_arguments)]
+    pub async fn mount_personalized_with_observer(
+        &mut self,
+        provider: &dyn crate::provider::IdeviceProvider,
+        image: Vec<u8>,
+        trust_cache: Vec<u8>,
+        build_manifest: &[u8],
+        info_plist: Option<plist::Value>,
+        unique_chip_id: u64,
+        observer: ProgressObserver,
+    ) -> Result<(), IdeviceError> {
+        observer.emit(ProgressEvent::Started);
+        let res = self
+            .mount_personalized_with_callback(
+                provider,
+                image,
+                trust_cache,
+                build_manifest,
+                info_plist,
+                unique_chip_id,
+                progress::forward,
+                observer.clone(),
+            )
+            .await;
+        observer.complete(res)
+    }
+
     #[cfg(feature = "tss")]
     pub async fn get_manifest_from_tss(
         &mut self,
@@ -589,3 +850,106 @@ impl ImageMounter {
         }
     }
 }
+
+/// A local copy of developer disk images to mount from when one is needed, laid out like
+/// https://github.com/doronz88/DeveloperDiskImage
+#[derive(Debug, Clone)]
+pub struct DdiRepository {
+    root: PathBuf,
+}
+
+impl DdiRepository {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The image and signature for an iOS version before 17, using the closest older
+    /// version of the same major release when there isn't an exact match
+    pub fn developer_image(&self, version: IosVersion) -> Option<(PathBuf, PathBuf)> {
+        let images = self.root.join("DeveloperDiskImages");
+        (0..=version.minor).rev().find_map(|minor| {
+            let dir = images.join(format!("{}.{minor}", version.major));
+            let image = dir.join("DeveloperDiskImage.dmg");
+            let signature = dir.join("DeveloperDiskImage.dmg.signature");
+            (image.is_file() && signature.is_file()).then_some((image, signature))
+        })
+    }
+
+    /// The image, trust cache and build manifest of the personalized image for iOS 17 and up
+    pub fn personalized_image(&self) -> Option<(PathBuf, PathBuf, PathBuf)> {
+        let dir = self
+            .root
+            .join("PersonalizedImages")
+            .join("Xcode_iOS_DDI_Personalized");
+        let paths = (
+            dir.join("Image.dmg"),
+            dir.join("Image.dmg.trustcache"),
+            dir.join("BuildManifest.plist"),
+        );
+        (paths.0.is_file() && paths.1.is_file() && paths.2.is_file()).then_some(paths)
+    }
+}
+
+/// Checks that a developer disk image is mounted before using developer services, which
+/// otherwise fail with errors that don't say why. If it isn't and a repository is given,
+/// the right image for the device is mounted from it.
+pub async fn ensure_developer_image(
+    provider: &dyn crate::provider::IdeviceProvider,
+    repository: Option<&DdiRepository>,
+) -> Result<(), IdeviceError> {
+    let mut mounter = ImageMounter::connect(provider).await?;
+    if mounter.is_developer_image_mounted().await? {
+        return Ok(());
+    }
+    let repository = match repository {
+        Some(r) => r,
+        None => return Err(IdeviceError::DeveloperImageNotMounted),
+    };
+
+    // Lockdown has to be queried after the mounter is connected, see ImageMounter
+    let mut lockdown = LockdowndClient::connect(provider).await?;
+    lockdown
+        .start_session(&provider.get_pairing_file().await?)
+        .await?;
+    let capabilities = Capabilities::from_lockdown(&mut lockdown).await?;
+    let version = capabilities.version();
+    debug!("Mounting a developer disk image for iOS {version}");
+
+    if capabilities.developer_image() == DeveloperImageKind::Developer {
+        let (image, signature) = repository.developer_image(version).ok_or_else(|| {
+            log::warn!("The repository has no developer disk image for iOS {version}");
+            IdeviceError::DeveloperImageNotMounted
+        })?;
+        let image = tokio::fs::read(image).await?;
+        let signature = tokio::fs::read(signature).await?;
+        return mounter.mount_developer(&image, signature).await;
+    }
+
+    #[cfg(feature = "tss")]
+    {
+        let (image, trust_cache, manifest) = repository.personalized_image().ok_or_else(|| {
+            log::warn!("The repository has no personalized developer disk image");
+            IdeviceError::DeveloperImageNotMounted
+        })?;
+        let unique_chip_id = lockdown
+            .get_value("UniqueChipID")
+            .await?
+            .as_unsigned_integer()
+            .ok_or(IdeviceError::UnexpectedResponse)?;
+        mounter
+            .mount_personalized(
+                provider,
+                tokio::fs::read(image).await?,
+                tokio::fs::read(trust_cache).await?,
+                &tokio::fs::read(manifest).await?,
+                None,
+                unique_chip_id,
+            )
+            .await
+    }
+    #[cfg(not(feature = "tss"))]
+    {
+        log::warn!("Mounting personalized images needs the tss feature");
+        Err(IdeviceError::DeveloperImageNotMounted)
+    }
+}
@@ -2,10 +2,9 @@
 //! 
 //! This module provides functionality to send and receive notifications to/from iOS devices.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc;
-use std::collections::HashSet;
+use crate::{provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
 
 const NOTIFICATION_PROXY_SERVICE_NAME: &str = "com.apple.mobile.notification_proxy";
 
@@ -26,22 +25,106 @@ pub enum NotificationType {
     RestoreDidFinish,
     /// Notification sent when an app has been installed
     AppInstalled,
+    /// Notification sent when an app has been uninstalled
+    AppUninstalled,
     /// Notification sent when a pairing has succeeded
     PairingSucceeded,
     /// Notification sent when iTunes is starting a sync
     ITunesSyncWillStart,
     /// Notification sent when iTunes has finished a sync
     ITunesSyncDidFinish,
+    /// Notification sent when a host is about to take the sync lock
+    ITunesSyncLockRequest,
+    /// Notification sent when a host has taken the sync lock
+    ITunesSyncDidStart,
     /// Notification sent when a download is about to start
     DownloadWillStart,
     /// Notification sent when a download has finished
     DownloadDidFinish,
-    /// Custom notification type
+    /// Notification sent when a data sync domain has changed
+    DataSyncDomainChanged,
+    /// Notification sent when a backup domain has changed
+    BackupDomainChanged,
+    /// Notification sent when the developer disk image has been mounted
+    DeveloperImageMounted,
+    /// Notification sent when the phone number has changed
+    PhoneNumberChanged,
+    /// Notification sent when the device name has changed
+    DeviceNameChanged,
+    /// Notification sent when the time zone has changed
+    TimezoneChanged,
+    /// Notification sent when the device language has changed
+    LanguageChanged,
+    /// Notification sent when a trusted host has attached
+    TrustedHostAttached,
+    /// Notification sent when a host has attached
+    HostAttached,
+    /// Notification sent when a host has detached
+    HostDetached,
+    /// Notification sent when the activation state has changed
+    ActivationStateChanged,
+    /// Notification sent when the brick state has changed
+    BrickStateChanged,
+    /// Notification sent when device registration has failed
+    RegistrationFailed,
+    /// Notification sent when disk usage has changed
+    DiskUsageChanged,
+    /// Notification sent when the address book preferences have changed
+    AddressBookPreferenceChanged,
+    /// Notification sent when SpringBoard attempts activation
+    AttemptActivation,
+    /// Notification sent when the device is locked or unlocked
+    LockStatusChanged,
+    /// Notification sent by SpringBoard when the screen is locked or unlocked
+    SpringBoardLockStateChanged,
+    /// Custom notification type, for names this library doesn't know about or
+    /// for an app's own namespace (e.g. ``com.example.myapp.refresh``)
     Custom(String),
 }
 
 impl NotificationType {
-    fn as_str(&self) -> &str {
+    /// Every predefined notification type, excluding ``Custom``
+    pub fn all_known() -> impl Iterator<Item = NotificationType> {
+        [
+            NotificationType::SyncWillStart,
+            NotificationType::SyncDidFinish,
+            NotificationType::BackupWillStart,
+            NotificationType::BackupDidFinish,
+            NotificationType::RestoreWillStart,
+            NotificationType::RestoreDidFinish,
+            NotificationType::AppInstalled,
+            NotificationType::AppUninstalled,
+            NotificationType::PairingSucceeded,
+            NotificationType::ITunesSyncWillStart,
+            NotificationType::ITunesSyncDidFinish,
+            NotificationType::ITunesSyncLockRequest,
+            NotificationType::ITunesSyncDidStart,
+            NotificationType::DownloadWillStart,
+            NotificationType::DownloadDidFinish,
+            NotificationType::DataSyncDomainChanged,
+            NotificationType::BackupDomainChanged,
+            NotificationType::DeveloperImageMounted,
+            NotificationType::PhoneNumberChanged,
+            NotificationType::DeviceNameChanged,
+            NotificationType::TimezoneChanged,
+            NotificationType::LanguageChanged,
+            NotificationType::TrustedHostAttached,
+            NotificationType::HostAttached,
+            NotificationType::HostDetached,
+            NotificationType::ActivationStateChanged,
+            NotificationType::BrickStateChanged,
+            NotificationType::RegistrationFailed,
+            NotificationType::DiskUsageChanged,
+            NotificationType::AddressBookPreferenceChanged,
+            NotificationType::AttemptActivation,
+            NotificationType::LockStatusChanged,
+            NotificationType::SpringBoardLockStateChanged,
+        ]
+        .into_iter()
+    }
+
+    /// The notification name as posted on the device
+    pub fn as_str(&self) -> &str {
         match self {
             NotificationType::SyncWillStart => "com.apple.itunes-client.syncWillStart",
             NotificationType::SyncDidFinish => "com.apple.itunes-client.syncDidFinish",
@@ -50,51 +133,99 @@ impl NotificationType {
             NotificationType::RestoreWillStart => "com.apple.itunes-client.restoreWillStart",
             NotificationType::RestoreDidFinish => "com.apple.itunes-client.restoreDidFinish",
             NotificationType::AppInstalled => "com.apple.mobile.application_installed",
+            NotificationType::AppUninstalled => "com.apple.mobile.application_uninstalled",
             NotificationType::PairingSucceeded => "com.apple.mobile.paired",
             NotificationType::ITunesSyncWillStart => "com.apple.itunes-mobdev.syncWillStart",
             NotificationType::ITunesSyncDidFinish => "com.apple.itunes-mobdev.syncDidFinish",
+            NotificationType::ITunesSyncLockRequest => "com.apple.itunes-mobdev.syncLockRequest",
+            NotificationType::ITunesSyncDidStart => "com.apple.itunes-mobdev.syncDidStart",
             NotificationType::DownloadWillStart => "com.apple.mobile.data_sync.willStart",
             NotificationType::DownloadDidFinish => "com.apple.mobile.data_sync.didFinish",
+            NotificationType::DataSyncDomainChanged => "com.apple.mobile.data_sync.domain_changed",
+            NotificationType::BackupDomainChanged => "com.apple.mobile.backup.domain_changed",
+            NotificationType::DeveloperImageMounted => "com.apple.mobile.developer_image_mounted",
+            NotificationType::PhoneNumberChanged => "com.apple.mobile.lockdown.phone_number_changed",
+            NotificationType::DeviceNameChanged => "com.apple.mobile.lockdown.device_name_changed",
+            NotificationType::TimezoneChanged => "com.apple.mobile.lockdown.timezone_changed",
+            NotificationType::LanguageChanged => "com.apple.language.changed",
+            NotificationType::TrustedHostAttached => "com.apple.mobile.lockdown.trusted_host_attached",
+            NotificationType::HostAttached => "com.apple.mobile.lockdown.host_attached",
+            NotificationType::HostDetached => "com.apple.mobile.lockdown.host_detached",
+            NotificationType::ActivationStateChanged => "com.apple.mobile.lockdown.activation_state",
+            NotificationType::BrickStateChanged => "com.apple.mobile.lockdown.brick_state",
+            NotificationType::RegistrationFailed => "com.apple.mobile.lockdown.registration_failed",
+            NotificationType::DiskUsageChanged => "com.apple.mobile.lockdown.disk_usage_changed",
+            NotificationType::AddressBookPreferenceChanged => "com.apple.AddressBook.PreferenceChanged",
+            NotificationType::AttemptActivation => "com.apple.springboard.attemptactivation",
+            NotificationType::LockStatusChanged => "com.apple.mobile.keybagd.lock_status",
+            NotificationType::SpringBoardLockStateChanged => "com.apple.springboard.lockstate",
             NotificationType::Custom(s) => s,
         }
     }
 
-    fn from_str(s: &str) -> Self {
-        match s {
-            "com.apple.itunes-client.syncWillStart" => NotificationType::SyncWillStart,
-            "com.apple.itunes-client.syncDidFinish" => NotificationType::SyncDidFinish,
-            "com.apple.itunes-client.backupWillStart" => NotificationType::BackupWillStart,
-            "com.apple.itunes-client.backupDidFinish" => NotificationType::BackupDidFinish,
-            "com.apple.itunes-client.restoreWillStart" => NotificationType::RestoreWillStart,
-            "com.apple.itunes-client.restoreDidFinish" => NotificationType::RestoreDidFinish,
-            "com.apple.mobile.application_installed" => NotificationType::AppInstalled,
-            "com.apple.mobile.paired" => NotificationType::PairingSucceeded,
-            "com.apple.itunes-mobdev.syncWillStart" => NotificationType::ITunesSyncWillStart,
-            "com.apple.itunes-mobdev.syncDidFinish" => NotificationType::ITunesSyncDidFinish,
-            "com.apple.mobile.data_sync.willStart" => NotificationType::DownloadWillStart,
-            "com.apple.mobile.data_sync.didFinish" => NotificationType::DownloadDidFinish,
-            _ => NotificationType::Custom(s.to_string()),
+    /// Whether the notification name is under the given namespace, e.g. ``com.apple.mobile.lockdown``
+    pub fn in_namespace(&self, namespace: &str) -> bool {
+        self.as_str()
+            .strip_prefix(namespace)
+            .is_some_and(|rest| rest.starts_with('.'))
+    }
+}
+
+impl std::fmt::Display for NotificationType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<String> for NotificationType {
+    fn from(s: String) -> Self {
+        match Self::all_known().find(|n| n.as_str() == s) {
+            Some(n) => n,
+            None => NotificationType::Custom(s),
         }
     }
 }
 
+impl std::str::FromStr for NotificationType {
+    type Err = std::convert::Infallible;
+
+    /// Unknown names parse as ``Custom``
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(s.to_string()))
+    }
+}
+
 /// Notification Proxy client for sending and receiving notifications
 pub struct NotificationProxyClient {
-    socket: tokio::net::TcpStream,
-    notification_rx: Option<mpsc::Receiver<NotificationType>>,
-    notification_tx: Option<mpsc::Sender<NotificationType>>,
+    socket: WriteHalf<Box<dyn ReadWrite>>,
+    /// Handed to the listening task once ``start_listening`` is called
+    reader: Option<ReadHalf<Box<dyn ReadWrite>>>,
+    stop_listening: Option<oneshot::Sender<()>>,
+}
+
+impl IdeviceService for NotificationProxyClient {
+    fn service_name() -> &'static str {
+        NOTIFICATION_PROXY_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl NotificationProxyClient {
-    /// Connect to the Notification Proxy service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(NOTIFICATION_PROXY_SERVICE_NAME).await?;
-        
-        Ok(Self {
-            socket: service.socket,
-            notification_rx: None,
-            notification_tx: None,
-        })
+    /// Create a client over an existing connection to the Notification Proxy service
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        let (reader, writer) = tokio::io::split(socket);
+        Self {
+            socket: writer,
+            reader: Some(reader),
+            stop_listening: None,
+        }
     }
 
     /// Observe a notification type
@@ -141,64 +272,47 @@ impl NotificationProxyClient {
         Ok(())
     }
 
-    /// Start listening for notifications
+    /// Start listening for notifications. Posting and observing still work while listening,
+    /// but the connection can only be listened to once.
     pub async fn start_listening(&mut self) -> Result<mpsc::Receiver<NotificationType>, IdeviceError> {
-        if self.notification_rx.is_some() {
-            return Err(IdeviceError::NotificationProxyError("Already listening for notifications".to_string()));
-        }
+        let mut reader = match self.reader.take() {
+            Some(r) => r,
+            None => {
+                return Err(IdeviceError::NotificationProxyError(
+                    "Already listening for notifications".to_string(),
+                ))
+            }
+        };
         
         let (tx, rx) = mpsc::channel(100);
-        self.notification_tx = Some(tx.clone());
-        self.notification_rx = Some(rx.clone());
-        
-        let mut socket = self.socket.try_clone().map_err(|e| {
-            IdeviceError::NotificationProxyError(format!("Failed to clone socket: {}", e))
-        })?;
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        self.stop_listening = Some(stop_tx);
         
-        // Spawn a task to listen for notifications
-        tokio::spawn(async move {
+        // Listen in the background until stopped, the client is dropped or the receiver is
+        crate::runtime::runtime().spawn(Box::pin(async move {
             loop {
-                // Read the command
-                let mut command = [0u8; 2];
-                if let Err(_) = socket.read_exact(&mut command).await {
-                    break;
-                }
-                
-                // Check if it's a notification
-                if command[0] == 'N' as u8 && command[1] == 'P' as u8 {
-                    // Read the notification length
-                    let mut len_buf = [0u8; 4];
-                    if let Err(_) = socket.read_exact(&mut len_buf).await {
-                        break;
-                    }
-                    let len = u32::from_be_bytes(len_buf) as usize;
-                    
-                    // Read the notification string
-                    let mut notification_bytes = vec![0u8; len];
-                    if let Err(_) = socket.read_exact(&mut notification_bytes).await {
-                        break;
-                    }
-                    
-                    // Convert to string
-                    if let Ok(notification_str) = String::from_utf8(notification_bytes) {
-                        let notification = NotificationType::from_str(&notification_str);
-                        
-                        // Send the notification to the channel
-                        if let Err(_) = tx.send(notification).await {
+                let notification = tokio::select! {
+                    n = read_notification(&mut reader) => n,
+                    _ = &mut stop_rx => break,
+                };
+                match notification {
+                    Ok(Some(notification)) => {
+                        if tx.send(notification).await.is_err() {
                             break;
                         }
                     }
+                    Ok(None) => continue,
+                    Err(_) => break,
                 }
             }
-        });
+        }));
         
         Ok(rx)
     }
 
     /// Stop listening for notifications
     pub fn stop_listening(&mut self) {
-        self.notification_rx = None;
-        self.notification_tx = None;
+        self.stop_listening = None;
     }
 
     /// Observe multiple notification types
@@ -209,4 +323,26 @@ impl NotificationProxyClient {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Reads the next packet, which is ``None`` if it wasn't a notification
+async fn read_notification(
+    reader: &mut ReadHalf<Box<dyn ReadWrite>>,
+) -> Result<Option<NotificationType>, IdeviceError> {
+    // Read the command
+    let mut command = [0u8; 2];
+    reader.read_exact(&mut command).await?;
+    if command != *b"NP" {
+        return Ok(None);
+    }
+    
+    // Read the notification length
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf).await?;
+    let len = crate::util::check_message_size(u32::from_be_bytes(len_buf) as usize)?;
+    
+    // Read the notification string
+    let mut notification_bytes = vec![0u8; len];
+    reader.read_exact(&mut notification_bytes).await?;
+    Ok(String::from_utf8(notification_bytes).ok().map(NotificationType::from))
+}
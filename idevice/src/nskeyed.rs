@@ -0,0 +1,742 @@
+// Jackson Coxson
+// NSKeyedArchiver archives, the object graphs Foundation based services exchange.
+// Instruments sends every method argument and reply this way. Archives decode into ``NsObject``,
+// and classes without a variant of their own are kept as ``NsObject::Object`` with their fields,
+// so encoding a decoded archive gives back the same objects.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use plist::{Dictionary, Uid, Value};
+
+const ARCHIVER: &str = "NSKeyedArchiver";
+const ARCHIVER_VERSION: u64 = 100000;
+
+/// Seconds from the unix epoch to 2001-01-01, which NSDate counts from
+const NSDATE_EPOCH: u64 = 978_307_200;
+
+/// How deep objects can nest before an archive is rejected, as archives can contain cycles
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NsKeyedError {
+    #[error("plist error")]
+    Plist(#[from] plist::Error),
+    #[error("not an NSKeyedArchiver archive")]
+    NotAnArchive,
+    #[error("object reference {0} is out of range")]
+    InvalidReference(u64),
+    #[error("objects nest deeper than {MAX_DEPTH}")]
+    TooDeep,
+    #[error("malformed {0}")]
+    Malformed(String),
+}
+
+/// An archived object
+#[derive(Debug, Clone, PartialEq)]
+pub enum NsObject {
+    Null,
+    Bool(bool),
+    Integer(plist::Integer),
+    Real(f64),
+    String(String),
+    Data(Vec<u8>),
+    Date(SystemTime),
+    Uuid([u8; 16]),
+    Array(Vec<NsObject>),
+    Set(Vec<NsObject>),
+    /// Keys in archive order. Keys are usually strings, but don't have to be.
+    Dictionary(Vec<(NsObject, NsObject)>),
+    Error(NsError),
+    TapMessage(TapMessage),
+    /// Any other class
+    Object(ArchivedObject),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NsError {
+    pub domain: String,
+    pub code: i64,
+    pub user_info: Vec<(NsObject, NsObject)>,
+}
+
+/// The messages Instruments taps, like sysmontap, stream their samples in.
+/// The payload is a dictionary whose layout depends on the tap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapMessage {
+    /// Such as ``DTSysmonTapMessage`` or ``DTKTraceTapMessage``
+    pub class: String,
+    pub plist: Box<NsObject>,
+}
+
+/// An object of a class this module doesn't know about
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedObject {
+    /// The class name followed by its superclasses
+    pub classes: Vec<String>,
+    pub fields: BTreeMap<String, NsObject>,
+}
+
+impl NsObject {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_unsigned_integer(&self) -> Option<u64> {
+        match self {
+            Self::Integer(i) => i.as_unsigned(),
+            _ => None,
+        }
+    }
+
+    pub fn as_signed_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(i) => i.as_signed(),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[NsObject]> {
+        match self {
+            Self::Array(a) | Self::Set(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Looks up a string key in a dictionary, or a field of an object
+    pub fn get(&self, key: &str) -> Option<&NsObject> {
+        match self {
+            Self::Dictionary(d) => d
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v),
+            Self::Object(o) => o.fields.get(key),
+            _ => None,
+        }
+    }
+
+    /// Converts to a plist value, for code that works with plists.
+    /// Null becomes the string ``$null``, the way it's stored in archives. Sets become arrays,
+    /// UUIDs data, and errors and other objects dictionaries of their fields. Integer
+    /// dictionary keys are written out in decimal, and entries with other keys are left out.
+    pub fn into_plist(self) -> Value {
+        match self {
+            Self::Null => Value::String("$null".into()),
+            Self::Bool(b) => Value::Boolean(b),
+            Self::Integer(i) => Value::Integer(i),
+            Self::Real(r) => Value::Real(r),
+            Self::String(s) => Value::String(s),
+            Self::Data(d) => Value::Data(d),
+            Self::Date(d) => Value::Date(d.into()),
+            Self::Uuid(u) => Value::Data(u.to_vec()),
+            Self::Array(a) | Self::Set(a) => {
+                Value::Array(a.into_iter().map(|o| o.into_plist()).collect())
+            }
+            Self::Dictionary(d) => Value::Dictionary(
+                d.into_iter()
+                    .filter_map(|(k, v)| match k {
+                        Self::String(k) => Some((k, v.into_plist())),
+                        Self::Integer(k) => Some((k.to_string(), v.into_plist())),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            Self::Error(e) => {
+                let mut d = Dictionary::new();
+                d.insert("NSDomain".into(), e.domain.into());
+                d.insert("NSCode".into(), e.code.into());
+                d.insert(
+                    "NSUserInfo".into(),
+                    Self::Dictionary(e.user_info).into_plist(),
+                );
+                Value::Dictionary(d)
+            }
+            Self::TapMessage(t) => {
+                let mut d = Dictionary::new();
+                d.insert("DTTapMessagePlist".into(), t.plist.into_plist());
+                Value::Dictionary(d)
+            }
+            Self::Object(o) => Value::Dictionary(
+                o.fields
+                    .into_iter()
+                    .map(|(k, v)| (k, v.into_plist()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl NsError {
+    /// The ``NSLocalizedDescription`` from the user info
+    pub fn description(&self) -> Option<&str> {
+        self.user_info
+            .iter()
+            .find(|(k, _)| k.as_str() == Some("NSLocalizedDescription"))
+            .and_then(|(_, v)| v.as_str())
+    }
+}
+
+impl ArchivedObject {
+    /// An object of a class that directly inherits from NSObject
+    pub fn new(class: impl Into<String>, fields: BTreeMap<String, NsObject>) -> Self {
+        Self {
+            classes: vec![class.into(), "NSObject".into()],
+            fields,
+        }
+    }
+
+    pub fn class(&self) -> Option<&str> {
+        self.classes.first().map(|c| c.as_str())
+    }
+}
+
+impl From<Value> for NsObject {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Boolean(b) => Self::Bool(b),
+            Value::Integer(i) => Self::Integer(i),
+            Value::Real(r) => Self::Real(r),
+            Value::String(s) => Self::String(s),
+            Value::Data(d) => Self::Data(d),
+            Value::Date(d) => Self::Date(d.into()),
+            Value::Array(a) => Self::Array(a.into_iter().map(Self::from).collect()),
+            Value::Dictionary(d) => Self::Dictionary(
+                d.into_iter()
+                    .map(|(k, v)| (Self::String(k), Self::from(v)))
+                    .collect(),
+            ),
+            Value::Uid(u) => Self::Integer(u.get().into()),
+            _ => Self::Null,
+        }
+    }
+}
+
+impl From<&str> for NsObject {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for NsObject {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl From<u64> for NsObject {
+    fn from(i: u64) -> Self {
+        Self::Integer(i.into())
+    }
+}
+
+impl From<i64> for NsObject {
+    fn from(i: i64) -> Self {
+        Self::Integer(i.into())
+    }
+}
+
+impl From<f64> for NsObject {
+    fn from(r: f64) -> Self {
+        Self::Real(r)
+    }
+}
+
+impl From<bool> for NsObject {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<ArchivedObject> for NsObject {
+    fn from(o: ArchivedObject) -> Self {
+        Self::Object(o)
+    }
+}
+
+/// Archives an object into a binary plist
+pub fn encode(object: &NsObject) -> Result<Vec<u8>, NsKeyedError> {
+    let mut buf = Vec::new();
+    plist::to_writer_binary(&mut buf, &to_archive(object))?;
+    Ok(buf)
+}
+
+/// Unarchives an object from an archive in any plist format
+pub fn decode(bytes: &[u8]) -> Result<NsObject, NsKeyedError> {
+    from_archive(&plist::from_bytes(bytes)?)
+}
+
+/// Archives an object into the archive's plist, without serializing it
+pub fn to_archive(object: &NsObject) -> Value {
+    let mut encoder = Encoder {
+        objects: vec![Value::String("$null".into())],
+        classes: HashMap::new(),
+    };
+    let root = encoder.add(object);
+
+    let mut top = Dictionary::new();
+    top.insert("root".into(), Value::Uid(root));
+
+    let mut archive = Dictionary::new();
+    archive.insert("$version".into(), ARCHIVER_VERSION.into());
+    archive.insert("$archiver".into(), ARCHIVER.into());
+    archive.insert("$top".into(), Value::Dictionary(top));
+    archive.insert("$objects".into(), Value::Array(encoder.objects));
+    Value::Dictionary(archive)
+}
+
+/// Unarchives an object from an archive's plist
+pub fn from_archive(archive: &Value) -> Result<NsObject, NsKeyedError> {
+    let archive = archive.as_dictionary().ok_or(NsKeyedError::NotAnArchive)?;
+    if archive.get("$archiver").and_then(|a| a.as_string()) != Some(ARCHIVER) {
+        return Err(NsKeyedError::NotAnArchive);
+    }
+    let objects = archive
+        .get("$objects")
+        .and_then(|o| o.as_array())
+        .ok_or(NsKeyedError::NotAnArchive)?;
+    let top = archive
+        .get("$top")
+        .and_then(|t| t.as_dictionary())
+        .ok_or(NsKeyedError::NotAnArchive)?;
+    // The root is usually under ``root``, but some archivers pick their own key
+    let root = top
+        .get("root")
+        .or_else(|| top.values().next())
+        .ok_or(NsKeyedError::NotAnArchive)?;
+
+    Decoder { objects }.value(root, 0)
+}
+
+struct Encoder {
+    objects: Vec<Value>,
+    /// Class dictionaries already added, by class hierarchy
+    classes: HashMap<Vec<String>, Uid>,
+}
+
+impl Encoder {
+    /// Adds an object, returning its reference
+    fn add(&mut self, object: &NsObject) -> Uid {
+        let raw = match object {
+            NsObject::Null => return Uid::new(0),
+            NsObject::Bool(b) => Value::Boolean(*b),
+            NsObject::Integer(i) => Value::Integer(*i),
+            NsObject::Real(r) => Value::Real(*r),
+            NsObject::String(s) => Value::String(s.clone()),
+            NsObject::Data(d) => Value::Data(d.clone()),
+            _ => return self.add_object(object),
+        };
+        self.push(raw)
+    }
+
+    /// Adds an object of a class. The object comes before its contents, like Foundation does it.
+    fn add_object(&mut self, object: &NsObject) -> Uid {
+        let uid = self.push(Value::String("$null".into()));
+
+        let (classes, mut fields): (&[&str], Dictionary) = match object {
+            NsObject::Date(d) => {
+                let mut fields = Dictionary::new();
+                fields.insert("NS.time".into(), nsdate_seconds(*d).into());
+                (&["NSDate", "NSObject"], fields)
+            }
+            NsObject::Uuid(u) => {
+                let mut fields = Dictionary::new();
+                fields.insert("NS.uuidbytes".into(), Value::Data(u.to_vec()));
+                (&["NSUUID", "NSObject"], fields)
+            }
+            NsObject::Array(a) => (&["NSArray", "NSObject"], self.objects_field(a)),
+            NsObject::Set(s) => (&["NSSet", "NSObject"], self.objects_field(s)),
+            NsObject::Dictionary(d) => (&["NSDictionary", "NSObject"], self.dictionary_fields(d)),
+            NsObject::Error(e) => {
+                let mut fields = Dictionary::new();
+                fields.insert(
+                    "NSDomain".into(),
+                    Value::Uid(self.add(&NsObject::String(e.domain.clone()))),
+                );
+                fields.insert("NSCode".into(), e.code.into());
+                let user_info = NsObject::Dictionary(e.user_info.clone());
+                fields.insert("NSUserInfo".into(), Value::Uid(self.add(&user_info)));
+                (&["NSError", "NSObject"], fields)
+            }
+            NsObject::TapMessage(t) => {
+                let mut fields = Dictionary::new();
+                fields.insert("DTTapMessagePlist".into(), Value::Uid(self.add(&t.plist)));
+                let classes = [t.class.as_str(), "DTTapMessage", "NSObject"];
+                fields.insert("$class".into(), Value::Uid(self.add_class(&classes)));
+                self.objects[uid.get() as usize] = Value::Dictionary(fields);
+                return uid;
+            }
+            NsObject::Object(o) => {
+                let mut fields = Dictionary::new();
+                for (k, v) in &o.fields {
+                    let v = self.field(v);
+                    fields.insert(k.clone(), v);
+                }
+                let classes: Vec<&str> = o.classes.iter().map(|c| c.as_str()).collect();
+                fields.insert("$class".into(), Value::Uid(self.add_class(&classes)));
+                self.objects[uid.get() as usize] = Value::Dictionary(fields);
+                return uid;
+            }
+            _ => unreachable!("primitives aren't objects"),
+        };
+
+        fields.insert("$class".into(), Value::Uid(self.add_class(classes)));
+        self.objects[uid.get() as usize] = Value::Dictionary(fields);
+        uid
+    }
+
+    /// A field of an object. Numbers are stored in the object, anything else is referenced.
+    fn field(&mut self, object: &NsObject) -> Value {
+        match object {
+            NsObject::Bool(b) => Value::Boolean(*b),
+            NsObject::Integer(i) => Value::Integer(*i),
+            NsObject::Real(r) => Value::Real(*r),
+            o => Value::Uid(self.add(o)),
+        }
+    }
+
+    fn objects_field(&mut self, objects: &[NsObject]) -> Dictionary {
+        let objects = objects.iter().map(|o| Value::Uid(self.add(o))).collect();
+        let mut fields = Dictionary::new();
+        fields.insert("NS.objects".into(), Value::Array(objects));
+        fields
+    }
+
+    fn dictionary_fields(&mut self, entries: &[(NsObject, NsObject)]) -> Dictionary {
+        let keys = entries
+            .iter()
+            .map(|(k, _)| Value::Uid(self.add(k)))
+            .collect();
+        let objects = entries
+            .iter()
+            .map(|(_, v)| Value::Uid(self.add(v)))
+            .collect();
+        let mut fields = Dictionary::new();
+        fields.insert("NS.keys".into(), Value::Array(keys));
+        fields.insert("NS.objects".into(), Value::Array(objects));
+        fields
+    }
+
+    fn add_class(&mut self, classes: &[&str]) -> Uid {
+        let classes: Vec<String> = classes.iter().map(|c| c.to_string()).collect();
+        if let Some(uid) = self.classes.get(&classes) {
+            return *uid;
+        }
+
+        let mut class = Dictionary::new();
+        class.insert(
+            "$classname".into(),
+            classes.first().cloned().unwrap_or_default().into(),
+        );
+        class.insert(
+            "$classes".into(),
+            Value::Array(classes.iter().map(|c| c.clone().into()).collect()),
+        );
+        let uid = self.push(Value::Dictionary(class));
+        self.classes.insert(classes, uid);
+        uid
+    }
+
+    fn push(&mut self, value: Value) -> Uid {
+        self.objects.push(value);
+        Uid::new(self.objects.len() as u64 - 1)
+    }
+}
+
+struct Decoder<'a> {
+    objects: &'a [Value],
+}
+
+impl Decoder<'_> {
+    /// Decodes a reference, or a value stored in place
+    fn value(&self, value: &Value, depth: usize) -> Result<NsObject, NsKeyedError> {
+        if depth > MAX_DEPTH {
+            return Err(NsKeyedError::TooDeep);
+        }
+        match value {
+            Value::Uid(uid) => self.object(uid.get(), depth),
+            Value::Array(a) => Ok(NsObject::Array(
+                a.iter()
+                    .map(|v| self.value(v, depth + 1))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::Dictionary(_) => Err(NsKeyedError::Malformed(
+                "dictionary outside of the object list".into(),
+            )),
+            v => Ok(NsObject::from(v.clone())),
+        }
+    }
+
+    fn object(&self, uid: u64, depth: usize) -> Result<NsObject, NsKeyedError> {
+        let object = self
+            .objects
+            .get(uid as usize)
+            .ok_or(NsKeyedError::InvalidReference(uid))?;
+        let fields = match object {
+            Value::String(s) if uid == 0 || s == "$null" => return Ok(NsObject::Null),
+            Value::Dictionary(d) => d,
+            v => return Ok(NsObject::from(v.clone())),
+        };
+
+        let classes = self.classes(fields)?;
+        let class = classes.first().map(|c| c.as_str()).unwrap_or_default();
+        let field = |key: &str| -> Result<NsObject, NsKeyedError> {
+            match fields.get(key) {
+                Some(v) => self.value(v, depth + 1),
+                None => Ok(NsObject::Null),
+            }
+        };
+        let references = |key: &str| -> Result<Vec<NsObject>, NsKeyedError> {
+            match fields.get(key) {
+                Some(Value::Array(a)) => a.iter().map(|v| self.value(v, depth + 1)).collect(),
+                None => Ok(Vec::new()),
+                _ => Err(NsKeyedError::Malformed(format!("{key} of {class}"))),
+            }
+        };
+
+        Ok(match class {
+            "NSArray" | "NSMutableArray" => NsObject::Array(references("NS.objects")?),
+            "NSSet" | "NSMutableSet" => NsObject::Set(references("NS.objects")?),
+            "NSDictionary" | "NSMutableDictionary" => {
+                let keys = references("NS.keys")?;
+                let objects = references("NS.objects")?;
+                if keys.len() != objects.len() {
+                    return Err(NsKeyedError::Malformed(class.into()));
+                }
+                NsObject::Dictionary(keys.into_iter().zip(objects).collect())
+            }
+            "NSString" | "NSMutableString" => field("NS.string")?,
+            "NSData" | "NSMutableData" => field("NS.data")?,
+            "NSNull" => NsObject::Null,
+            "NSDate" => match fields
+                .get("NS.time")
+                .and_then(|t| t.as_real())
+                .and_then(nsdate)
+            {
+                Some(d) => NsObject::Date(d),
+                None => return Err(NsKeyedError::Malformed(class.into())),
+            },
+            "NSUUID" => match fields.get("NS.uuidbytes").and_then(|u| u.as_data()) {
+                Some(u) => NsObject::Uuid(
+                    u.try_into()
+                        .map_err(|_| NsKeyedError::Malformed(class.into()))?,
+                ),
+                None => return Err(NsKeyedError::Malformed(class.into())),
+            },
+            "NSError" => NsObject::Error(NsError {
+                domain: field("NSDomain")?.as_str().unwrap_or_default().to_string(),
+                code: field("NSCode")?.as_signed_integer().unwrap_or_default(),
+                user_info: match field("NSUserInfo")? {
+                    NsObject::Dictionary(d) => d,
+                    _ => Vec::new(),
+                },
+            }),
+            _ if classes.iter().any(|c| c == "DTTapMessage") => NsObject::TapMessage(TapMessage {
+                class: class.to_string(),
+                plist: Box::new(field("DTTapMessagePlist")?),
+            }),
+            _ => {
+                let mut object_fields = BTreeMap::new();
+                for (k, v) in fields {
+                    if k != "$class" {
+                        object_fields.insert(k.clone(), self.value(v, depth + 1)?);
+                    }
+                }
+                NsObject::Object(ArchivedObject {
+                    classes,
+                    fields: object_fields,
+                })
+            }
+        })
+    }
+
+    /// The class hierarchy of an object, from the class dictionary it references
+    fn classes(&self, fields: &Dictionary) -> Result<Vec<String>, NsKeyedError> {
+        let class = match fields.get("$class") {
+            Some(Value::Uid(uid)) => self
+                .objects
+                .get(uid.get() as usize)
+                .and_then(|c| c.as_dictionary())
+                .ok_or(NsKeyedError::InvalidReference(uid.get()))?,
+            _ => return Err(NsKeyedError::Malformed("object without a class".into())),
+        };
+        match class.get("$classes").and_then(|c| c.as_array()) {
+            Some(classes) => Ok(classes
+                .iter()
+                .filter_map(|c| c.as_string().map(|c| c.to_string()))
+                .collect()),
+            None => match class.get("$classname").and_then(|c| c.as_string()) {
+                Some(name) => Ok(vec![name.to_string()]),
+                None => Err(NsKeyedError::Malformed("class without a name".into())),
+            },
+        }
+    }
+}
+
+/// The time an NS.time refers to, or ``None`` if it's out of range
+fn nsdate(seconds: f64) -> Option<SystemTime> {
+    let since_unix = seconds + NSDATE_EPOCH as f64;
+    if since_unix >= 0.0 {
+        UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(since_unix).ok()?)
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::try_from_secs_f64(-since_unix).ok()?)
+    }
+}
+
+fn nsdate_seconds(date: SystemTime) -> f64 {
+    match date.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64() - NSDATE_EPOCH as f64,
+        Err(e) => -e.duration().as_secs_f64() - NSDATE_EPOCH as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_round_trip() {
+        let date = UNIX_EPOCH + Duration::from_secs(NSDATE_EPOCH + 86400);
+        let tap_fields = vec![(NsObject::from("CPUCount"), NsObject::from(6u64))];
+        let mut fields = BTreeMap::new();
+        fields.insert("name".into(), NsObject::from("tap"));
+        fields.insert("offset".into(), NsObject::from(0.5));
+        fields.insert("missing".into(), NsObject::Null);
+        let object = NsObject::Array(vec![
+            NsObject::Dictionary(vec![
+                (NsObject::from("pid"), NsObject::from(42u64)),
+                (NsObject::from("when"), NsObject::Date(date)),
+                (NsObject::from(7u64), NsObject::from(true)),
+            ]),
+            NsObject::Set(vec![NsObject::Data(vec![1, 2, 3])]),
+            NsObject::Uuid([7; 16]),
+            NsObject::Error(NsError {
+                domain: "DTXMessage".into(),
+                code: -1,
+                user_info: vec![(
+                    NsObject::from("NSLocalizedDescription"),
+                    NsObject::from("Channel canceled"),
+                )],
+            }),
+            NsObject::TapMessage(TapMessage {
+                class: "DTSysmonTapMessage".into(),
+                plist: Box::new(NsObject::Dictionary(tap_fields)),
+            }),
+            NsObject::Object(ArchivedObject::new("XCPointerEvent", fields)),
+            NsObject::Null,
+        ]);
+
+        let decoded = decode(&encode(&object).unwrap()).unwrap();
+        assert_eq!(decoded, object);
+
+        let archive = to_archive(&object);
+        let objects = archive.as_dictionary().unwrap()["$objects"]
+            .as_array()
+            .unwrap();
+        // The root comes first, and each class is only stored once
+        assert_eq!(
+            objects[1].as_dictionary().unwrap()["NS.objects"]
+                .as_array()
+                .unwrap()
+                .len(),
+            7
+        );
+        let class_count = objects
+            .iter()
+            .filter(|o| {
+                o.as_dictionary()
+                    .and_then(|d| d.get("$classname"))
+                    .and_then(|c| c.as_string())
+                    == Some("NSDictionary")
+            })
+            .count();
+        assert_eq!(class_count, 1);
+
+        let NsObject::Error(e) = &decoded.as_array().unwrap()[3] else {
+            panic!("Expected an error");
+        };
+        assert_eq!(e.description(), Some("Channel canceled"));
+        assert_eq!(
+            decoded.as_array().unwrap()[0]
+                .get("pid")
+                .unwrap()
+                .as_unsigned_integer(),
+            Some(42)
+        );
+
+        let plist = decoded.into_plist();
+        let dictionary = plist.as_array().unwrap()[0].as_dictionary().unwrap();
+        assert_eq!(dictionary["7"], Value::Boolean(true));
+    }
+
+    #[test]
+    fn foundation_archives_decode() {
+        // An NSMutableDictionary holding an NSMutableString, as Foundation archives it
+        let mut archive = Dictionary::new();
+        archive.insert("$archiver".into(), ARCHIVER.into());
+        archive.insert("$version".into(), ARCHIVER_VERSION.into());
+        let mut top = Dictionary::new();
+        top.insert("root".into(), Value::Uid(Uid::new(1)));
+        archive.insert("$top".into(), top.into());
+
+        let mut dictionary = Dictionary::new();
+        dictionary.insert("NS.keys".into(), vec![Value::Uid(Uid::new(2))].into());
+        dictionary.insert("NS.objects".into(), vec![Value::Uid(Uid::new(3))].into());
+        dictionary.insert("$class".into(), Value::Uid(Uid::new(5)));
+        let mut string = Dictionary::new();
+        string.insert("NS.string".into(), "com.apple.Preferences".into());
+        string.insert("$class".into(), Value::Uid(Uid::new(4)));
+        let class = |classes: &[&str]| {
+            let mut class = Dictionary::new();
+            class.insert("$classname".into(), classes[0].into());
+            class.insert(
+                "$classes".into(),
+                Value::Array(classes.iter().map(|c| (*c).into()).collect()),
+            );
+            Value::Dictionary(class)
+        };
+        archive.insert(
+            "$objects".into(),
+            vec![
+                "$null".into(),
+                dictionary.into(),
+                "bundleIdentifier".into(),
+                string.into(),
+                class(&["NSMutableString", "NSString", "NSObject"]),
+                class(&["NSMutableDictionary", "NSDictionary", "NSObject"]),
+            ]
+            .into(),
+        );
+
+        let object = from_archive(&Value::Dictionary(archive)).unwrap();
+        assert_eq!(
+            object.get("bundleIdentifier").and_then(|b| b.as_str()),
+            Some("com.apple.Preferences")
+        );
+        let mut expected = Dictionary::new();
+        expected.insert("bundleIdentifier".into(), "com.apple.Preferences".into());
+        assert_eq!(object.into_plist(), Value::Dictionary(expected));
+
+        // An object that contains itself
+        let mut archive = to_archive(&NsObject::Object(ArchivedObject::new(
+            "Loop",
+            BTreeMap::new(),
+        )));
+        let objects = archive.as_dictionary_mut().unwrap()["$objects"]
+            .as_array_mut()
+            .unwrap();
+        objects[1]
+            .as_dictionary_mut()
+            .unwrap()
+            .insert("next".into(), Value::Uid(Uid::new(1)));
+        assert!(matches!(from_archive(&archive), Err(NsKeyedError::TooDeep)));
+        assert!(matches!(
+            decode(b"not a plist"),
+            Err(NsKeyedError::Plist(_))
+        ));
+    }
+}
@@ -0,0 +1,542 @@
+// Jackson Coxson
+// Abstractions for os_trace_relay, the service behind ``log collect`` and ``syslog``
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+#[cfg(feature = "afc")]
+use crate::afc::{AfcClient, AfcFile, AfcFopenMode};
+use crate::{lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+
+pub struct OsTraceRelayClient {
+    pub idevice: Idevice,
+}
+
+impl IdeviceService for OsTraceRelayClient {
+    fn service_name() -> &'static str {
+        "com.apple.os_trace_relay"
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+
+        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self { idevice })
+    }
+}
+
+/// Limits applied by the device when building the archive
+#[derive(Debug, Default, Clone)]
+pub struct ArchiveOptions {
+    /// Maximum size of the archive in bytes
+    pub size_limit: Option<u64>,
+    /// Maximum age of the included entries in seconds
+    pub age_limit: Option<u64>,
+    /// Only include entries newer than this unix timestamp
+    pub start_time: Option<u64>,
+}
+
+/// Log levels, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Notice,
+    UserAction,
+    Error,
+    Fault,
+}
+
+impl LogLevel {
+    fn from_byte(b: u8) -> Option<Self> {
+        Some(match b {
+            0x00 => Self::Notice,
+            0x01 => Self::Info,
+            0x02 => Self::Debug,
+            0x03 => Self::UserAction,
+            0x10 => Self::Error,
+            0x11 => Self::Fault,
+            _ => return None,
+        })
+    }
+}
+
+/// A single message from the device's unified log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub pid: u32,
+    pub timestamp: SystemTime,
+    pub level: LogLevel,
+    /// Path of the binary that logged the message
+    pub filename: String,
+    /// Name of the image, such as a framework, the message came from
+    pub image_name: String,
+    pub message: String,
+    pub subsystem: Option<String>,
+    pub category: Option<String>,
+}
+
+impl LogEntry {
+    /// Offset of the filename, the end of the fixed size part of an entry
+    const HEADER_LEN: usize = 129;
+
+    /// Parses an entry from the trace stream.
+    /// Most of the header is undocumented, so only the known fields are read.
+    pub fn parse(data: &[u8]) -> Result<Self, IdeviceError> {
+        if data.len() < Self::HEADER_LEN {
+            warn!("Log entry is too short: {}", data.len());
+            return Err(IdeviceError::NotEnoughBytes(data.len(), Self::HEADER_LEN));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+        let pid = u32_at(9);
+        let timestamp = UNIX_EPOCH
+            + Duration::from_secs(u32_at(55) as u64)
+            + Duration::from_micros(u32_at(63) as u64);
+        let level = match LogLevel::from_byte(data[68]) {
+            Some(l) => l,
+            None => {
+                warn!("Unknown log level {:02X}", data[68]);
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        let image_name_size = u16_at(107);
+        let message_size = u16_at(109);
+        let subsystem_size = u32_at(117) as usize;
+        let category_size = u32_at(121) as usize;
+
+        let mut rest = &data[Self::HEADER_LEN..];
+        let filename_len = match rest.iter().position(|b| *b == 0) {
+            Some(l) => l,
+            None => {
+                warn!("Log entry filename isn't terminated");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        let filename = String::from_utf8_lossy(&rest[..filename_len]).into_owned();
+        rest = &rest[filename_len + 1..];
+
+        let mut take = |len: usize| -> Result<String, IdeviceError> {
+            if rest.len() < len {
+                warn!("Log entry is truncated");
+                return Err(IdeviceError::NotEnoughBytes(rest.len(), len));
+            }
+            let (field, r) = rest.split_at(len);
+            rest = r;
+            Ok(String::from_utf8_lossy(field)
+                .trim_end_matches('\0')
+                .to_string())
+        };
+        let image_name = take(image_name_size)?;
+        let message = take(message_size)?;
+        let (subsystem, category) = if subsystem_size > 0 {
+            (Some(take(subsystem_size)?), Some(take(category_size)?))
+        } else {
+            (None, None)
+        };
+
+        Ok(Self {
+            pid,
+            timestamp,
+            level,
+            filename,
+            image_name,
+            message,
+            subsystem,
+            category,
+        })
+    }
+}
+
+impl OsTraceRelayClient {
+    const ARCHIVE_HEADER_MAGIC: u8 = 0x01;
+    const ARCHIVE_CHUNK_MAGIC: u8 = 0x03;
+    const LOG_ENTRY_MAGIC: u8 = 0x02;
+
+    pub fn new(idevice: Idevice) -> Self {
+        Self { idevice }
+    }
+
+    /// Requests a ``.logarchive`` from the device and streams it into the writer.
+    /// The device sends the archive as a tar stream, which is written as-is.
+    /// This consumes the connection, as the device closes it once the archive is sent.
+    /// # Returns
+    /// The number of bytes written
+    pub async fn create_archive<W: AsyncWrite + Unpin>(
+        self,
+        out: &mut W,
+        options: ArchiveOptions,
+    ) -> Result<u64, IdeviceError> {
+        self.create_archive_with_progress(out, options, |_| async {}, ())
+            .await
+    }
+
+    /// Same as ``create_archive``, but calls the callback with the total bytes received after each chunk
+    pub async fn create_archive_with_progress<W, Fut, S>(
+        mut self,
+        out: &mut W,
+        options: ArchiveOptions,
+        callback: impl Fn((u64, S)) -> Fut,
+        state: S,
+    ) -> Result<u64, IdeviceError>
+    where
+        W: AsyncWrite + Unpin,
+        Fut: std::future::Future<Output = ()>,
+        S: Clone,
+    {
+        self.request_archive(&options).await?;
+
+        let mut written = 0;
+        while let Some(chunk) = self.next_archive_chunk().await? {
+            out.write_all(&chunk).await?;
+
+            written += chunk.len() as u64;
+            callback((written, state.clone())).await;
+        }
+        out.flush().await?;
+
+        Ok(written)
+    }
+
+    /// Streams a ``.logarchive`` tarball to a file on disk
+    pub async fn create_archive_to_path(
+        self,
+        path: impl AsRef<Path>,
+        options: ArchiveOptions,
+    ) -> Result<u64, IdeviceError> {
+        let mut file = tokio::fs::File::create(path).await?;
+        self.create_archive(&mut file, options).await
+    }
+
+    /// Streams a ``.logarchive`` tarball into a file over AFC, a chunk at a time as it arrives,
+    /// such as into an app's container through house_arrest.
+    /// # Returns
+    /// The number of bytes written
+    #[cfg(feature = "afc")]
+    pub async fn create_archive_to_afc(
+        mut self,
+        afc: &mut AfcClient,
+        path: &str,
+        options: ArchiveOptions,
+    ) -> Result<u64, IdeviceError> {
+        self.request_archive(&options).await?;
+
+        let mut file = AfcFile::open(afc, path, AfcFopenMode::WrOnly).await?;
+        let res = async {
+            let mut written = 0;
+            while let Some(chunk) = self.next_archive_chunk().await? {
+                file.write(&chunk).await?;
+                written += chunk.len() as u64;
+            }
+            Ok(written)
+        }
+        .await;
+        file.close_after(res).await
+    }
+
+    async fn request_archive(&mut self, options: &ArchiveOptions) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Request".into(), "CreateArchive".into());
+        if let Some(size_limit) = options.size_limit {
+            req.insert("SizeLimit".into(), size_limit.into());
+        }
+        if let Some(age_limit) = options.age_limit {
+            req.insert("AgeLimit".into(), age_limit.into());
+        }
+        if let Some(start_time) = options.start_time {
+            req.insert("StartTime".into(), start_time.into());
+        }
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+
+        let magic = self.idevice.read_raw(1).await?;
+        if magic[0] != Self::ARCHIVE_HEADER_MAGIC {
+            warn!("Archive response had bad magic: {:02X}", magic[0]);
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+        let res = self.idevice.read_plist().await?;
+        match res.get("Status").and_then(|s| s.as_string()) {
+            Some("RequestSuccessful") => Ok(()),
+            s => {
+                warn!("Archive request was not successful: {s:?}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Reads the next chunk of the archive, or ``None`` once the device has closed the stream
+    async fn next_archive_chunk(&mut self) -> Result<Option<Vec<u8>>, IdeviceError> {
+        let magic = match self.idevice.read_raw(1).await {
+            Ok(m) => m,
+            Err(IdeviceError::Socket(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                debug!("Device closed the archive stream");
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        if magic[0] != Self::ARCHIVE_CHUNK_MAGIC {
+            warn!("Archive chunk had bad magic: {:02X}", magic[0]);
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+
+        let len = self.idevice.read_raw(4).await?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+        Ok(Some(self.idevice.read_raw(len as usize).await?))
+    }
+
+    /// Starts streaming the device's log, read it with ``next_log``.
+    /// The connection can't be used for other requests afterwards.
+    /// # Arguments
+    /// `pid` - Only stream messages from this process, or everything if ``None``
+    pub async fn start_trace(&mut self, pid: Option<u32>) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("Request".into(), "StartActivity".into());
+        req.insert("MessageFilter".into(), 65535.into());
+        req.insert("Pid".into(), pid.map(|p| p as i64).unwrap_or(-1).into());
+        req.insert("StreamFlags".into(), 60.into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+
+        // The response is prefixed by the size of its length, then the length, both little endian
+        let len_len = self.idevice.read_raw(4).await?;
+        let len_len = u32::from_le_bytes([len_len[0], len_len[1], len_len[2], len_len[3]]);
+        if len_len == 0 || len_len > 8 {
+            warn!("Trace response length has a bad size: {len_len}");
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+        let len = self
+            .idevice
+            .read_raw(len_len as usize)
+            .await?
+            .iter()
+            .rev()
+            .fold(0_u64, |acc, b| (acc << 8) | *b as u64);
+        let res = self.idevice.read_raw(len as usize).await?;
+        let res: plist::Dictionary = plist::from_bytes(&res)?;
+        match res.get("Status").and_then(|s| s.as_string()) {
+            Some("RequestSuccessful") => Ok(()),
+            s => {
+                warn!("Trace request was not successful: {s:?}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Reads the next message from a stream started with ``start_trace``
+    pub async fn next_log(&mut self) -> Result<LogEntry, IdeviceError> {
+        let magic = self.idevice.read_raw(1).await?;
+        if magic[0] != Self::LOG_ENTRY_MAGIC {
+            warn!("Log entry had bad magic: {:02X}", magic[0]);
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+        let len = self.idevice.read_raw(4).await?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+        let entry = self.idevice.read_raw(len as usize).await?;
+        LogEntry::parse(&entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_entry() -> Vec<u8> {
+        let mut entry = vec![0; LogEntry::HEADER_LEN];
+        entry[9..13].copy_from_slice(&42_u32.to_le_bytes());
+        entry[55..59].copy_from_slice(&1_700_000_000_u32.to_le_bytes());
+        entry[63..67].copy_from_slice(&500_u32.to_le_bytes());
+        entry[68] = 0x10;
+        entry[107..109].copy_from_slice(&6_u16.to_le_bytes());
+        entry[109..111].copy_from_slice(&6_u16.to_le_bytes());
+        entry[117..121].copy_from_slice(&13_u32.to_le_bytes());
+        entry[121..125].copy_from_slice(&3_u32.to_le_bytes());
+        entry.extend_from_slice(b"/usr/libexec/lockdownd\0");
+        entry.extend_from_slice(b"libxpc");
+        entry.extend_from_slice(b"oh no\0");
+        entry.extend_from_slice(b"com.apple.xpc");
+        entry.extend_from_slice(b"ipc");
+        entry
+    }
+
+    /// Answers an archive request the way the device does: a status plist, the archive in
+    /// chunks, then closing the connection
+    #[cfg(feature = "testing")]
+    fn relay(chunks: &[&[u8]]) -> OsTraceRelayClient {
+        use crate::testing::MockTransport;
+
+        let chunks: Vec<Vec<u8>> = chunks.iter().map(|c| c.to_vec()).collect();
+        let (host, mut device) = MockTransport::pair();
+        tokio::spawn(async move {
+            let req = device.read_plist().await.unwrap();
+            assert_eq!(
+                req.get("Request").and_then(|r| r.as_string()),
+                Some("CreateArchive")
+            );
+            assert_eq!(
+                req.get("SizeLimit").and_then(|r| r.as_unsigned_integer()),
+                Some(1024)
+            );
+
+            device
+                .write_all(&[OsTraceRelayClient::ARCHIVE_HEADER_MAGIC])
+                .await
+                .unwrap();
+            let mut status = plist::Dictionary::new();
+            status.insert("Status".into(), "RequestSuccessful".into());
+            device.send_plist(status).await.unwrap();
+            for chunk in chunks {
+                device
+                    .write_all(&[OsTraceRelayClient::ARCHIVE_CHUNK_MAGIC])
+                    .await
+                    .unwrap();
+                device
+                    .write_all(&(chunk.len() as u32).to_le_bytes())
+                    .await
+                    .unwrap();
+                device.write_all(&chunk).await.unwrap();
+            }
+        });
+        OsTraceRelayClient::new(Idevice::new(Box::new(host), "os_trace_relay"))
+    }
+
+    #[cfg(feature = "testing")]
+    fn options() -> ArchiveOptions {
+        ArchiveOptions {
+            size_limit: Some(1024),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn archive_chunks_are_written_until_the_device_closes() {
+        let mut out = Vec::new();
+        let progress = std::sync::Mutex::new(Vec::new());
+        let written = relay(&[b"log", b"", b"archive"])
+            .create_archive_with_progress(
+                &mut out,
+                options(),
+                |(n, _)| {
+                    progress.lock().unwrap().push(n);
+                    async {}
+                },
+                (),
+            )
+            .await
+            .unwrap();
+        assert_eq!(written, 10);
+        assert_eq!(out, b"logarchive");
+        assert_eq!(*progress.lock().unwrap(), [3, 3, 10]);
+    }
+
+    #[cfg(all(feature = "testing", feature = "afc"))]
+    #[tokio::test]
+    async fn archive_streams_over_afc() {
+        use crate::afc::tests::client;
+        use crate::testing::AfcResponder;
+
+        let responder = AfcResponder::new().with_directory("/Downloads");
+        let mut afc = client(&responder);
+
+        let written = relay(&[b"log", b"archive"])
+            .create_archive_to_afc(&mut afc, "/Downloads/system.logarchive.tar", options())
+            .await
+            .unwrap();
+        assert_eq!(written, 10);
+        assert_eq!(
+            responder
+                .file("/Downloads/system.logarchive.tar")
+                .as_deref(),
+            Some(&b"logarchive"[..])
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn trace_entries_are_read_from_the_stream() {
+        use crate::testing::MockTransport;
+
+        let (host, mut device) = MockTransport::pair();
+        tokio::spawn(async move {
+            let req = device.read_plist().await.unwrap();
+            assert_eq!(
+                req.get("Request").and_then(|r| r.as_string()),
+                Some("StartActivity")
+            );
+            assert_eq!(req.get("Pid").and_then(|p| p.as_signed_integer()), Some(42));
+
+            let mut status = plist::Dictionary::new();
+            status.insert("Status".into(), "RequestSuccessful".into());
+            let mut res = Vec::new();
+            plist::to_writer_binary(&mut res, &status).unwrap();
+            // The length is sent in as many bytes as the size before it says
+            device.write_all(&2_u32.to_le_bytes()).await.unwrap();
+            device
+                .write_all(&(res.len() as u16).to_le_bytes())
+                .await
+                .unwrap();
+            device.write_all(&res).await.unwrap();
+
+            let entry = log_entry();
+            device
+                .write_all(&[OsTraceRelayClient::LOG_ENTRY_MAGIC])
+                .await
+                .unwrap();
+            device
+                .write_all(&(entry.len() as u32).to_le_bytes())
+                .await
+                .unwrap();
+            device.write_all(&entry).await.unwrap();
+        });
+
+        let mut client = OsTraceRelayClient::new(Idevice::new(Box::new(host), "os_trace_relay"));
+        client.start_trace(Some(42)).await.unwrap();
+        let entry = client.next_log().await.unwrap();
+        assert_eq!(entry.pid, 42);
+        assert_eq!(entry.message, "oh no");
+    }
+
+    #[test]
+    fn parse_log_entry() {
+        let entry = LogEntry::parse(&log_entry()).unwrap();
+        assert_eq!(entry.pid, 42);
+        assert_eq!(
+            entry.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_micros(500)
+        );
+        assert_eq!(entry.level, LogLevel::Error);
+        assert_eq!(entry.filename, "/usr/libexec/lockdownd");
+        assert_eq!(entry.image_name, "libxpc");
+        assert_eq!(entry.message, "oh no");
+        assert_eq!(entry.subsystem.as_deref(), Some("com.apple.xpc"));
+        assert_eq!(entry.category.as_deref(), Some("ipc"));
+
+        assert!(LogEntry::parse(&[0; 10]).is_err());
+    }
+}
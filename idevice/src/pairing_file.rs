@@ -1,16 +1,23 @@
 // Jackson Coxson
 
-use std::path::Path;
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use log::warn;
 use openssl::{
+    asn1::{Asn1Time, Asn1TimeRef},
     pkey::{PKey, Private},
     x509::X509,
 };
 use plist::Data;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+use crate::pretty::{format_unix_time, Pretty, REDACTED};
+
+#[derive(Clone)]
 pub struct PairingFile {
     pub device_certificate: X509,
     pub host_private_key: PKey<Private>,
@@ -43,6 +50,58 @@ struct RawPairingFile {
     udid: Option<String>,
 }
 
+/// Keys every pairing file has, and whether they're data or a string
+const REQUIRED_KEYS: [(&str, bool); 9] = [
+    ("DeviceCertificate", true),
+    ("HostPrivateKey", true),
+    ("HostCertificate", true),
+    ("RootPrivateKey", true),
+    ("RootCertificate", true),
+    ("SystemBUID", false),
+    ("HostID", false),
+    ("EscrowBag", true),
+    ("WiFiMACAddress", false),
+];
+
+/// What's wrong with a pairing file, for telling the user how to fix it.
+/// Keys are named the way they appear in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairingFileProblem {
+    /// The file couldn't be read at all
+    Unreadable(String),
+    /// The file isn't a plist dictionary
+    MalformedPlist(String),
+    MissingKey(&'static str),
+    /// The key is there but holds the wrong kind of value
+    WrongType(&'static str),
+    /// A certificate isn't valid PEM
+    BadCertificate(&'static str),
+    /// A private key isn't valid PEM
+    BadKey(&'static str),
+    /// A certificate doesn't belong to the private key stored with it
+    KeyMismatch(&'static str),
+    /// A certificate wasn't issued by the root certificate, so the file mixes two pairings
+    NotSigned(&'static str),
+    /// A certificate has expired and the device has to be paired again
+    Expired(&'static str),
+}
+
+impl fmt::Display for PairingFileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(e) => write!(f, "unable to read the file: {e}"),
+            Self::MalformedPlist(e) => write!(f, "not a valid plist: {e}"),
+            Self::MissingKey(k) => write!(f, "{k} is missing"),
+            Self::WrongType(k) => write!(f, "{k} has the wrong type"),
+            Self::BadCertificate(k) => write!(f, "{k} is not a valid certificate"),
+            Self::BadKey(k) => write!(f, "{k} is not a valid private key"),
+            Self::KeyMismatch(k) => write!(f, "{k} does not match its private key"),
+            Self::NotSigned(k) => write!(f, "{k} was not signed by the root certificate"),
+            Self::Expired(k) => write!(f, "{k} has expired"),
+        }
+    }
+}
+
 impl PairingFile {
     pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, crate::IdeviceError> {
         let f = std::fs::read(path)?;
@@ -73,6 +132,77 @@ impl PairingFile {
         Ok(p)
     }
 
+    /// Reads a pairing file, saying exactly what's wrong if it can't be used.
+    /// Unlike ``read_from_file``, this also runs ``validate``.
+    pub fn check_file(path: impl AsRef<Path>) -> Result<Self, PairingFileProblem> {
+        let f = std::fs::read(path).map_err(|e| PairingFileProblem::Unreadable(e.to_string()))?;
+        Self::check_bytes(&f)
+    }
+
+    /// Parses a pairing file, saying exactly what's wrong if it can't be used.
+    /// Unlike ``from_bytes``, this also runs ``validate``.
+    pub fn check_bytes(bytes: &[u8]) -> Result<Self, PairingFileProblem> {
+        let record = match plist::from_bytes::<plist::Value>(bytes) {
+            Ok(plist::Value::Dictionary(d)) => d,
+            Ok(_) => {
+                return Err(PairingFileProblem::MalformedPlist(
+                    "not a dictionary".to_string(),
+                ))
+            }
+            Err(e) => return Err(PairingFileProblem::MalformedPlist(e.to_string())),
+        };
+        for (key, is_data) in REQUIRED_KEYS {
+            match record.get(key) {
+                None => return Err(PairingFileProblem::MissingKey(key)),
+                Some(plist::Value::Data(_)) if is_data => {}
+                Some(plist::Value::String(_)) if !is_data => {}
+                Some(_) => return Err(PairingFileProblem::WrongType(key)),
+            }
+        }
+        let data = |key| {
+            record
+                .get(key)
+                .and_then(|v| v.as_data())
+                .unwrap_or_default()
+        };
+        let string = |key| {
+            record
+                .get(key)
+                .and_then(|v| v.as_string())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let certificate = |key: &'static str| {
+            X509::from_pem(data(key)).map_err(|_| PairingFileProblem::BadCertificate(key))
+        };
+        let private_key = |key: &'static str| {
+            PKey::private_key_from_pem(data(key)).map_err(|_| PairingFileProblem::BadKey(key))
+        };
+
+        let pairing_file = Self {
+            device_certificate: certificate("DeviceCertificate")?,
+            host_private_key: private_key("HostPrivateKey")?,
+            host_certificate: certificate("HostCertificate")?,
+            root_private_key: private_key("RootPrivateKey")?,
+            root_certificate: certificate("RootCertificate")?,
+            system_buid: string("SystemBUID"),
+            host_id: string("HostID"),
+            escrow_bag: data("EscrowBag").to_vec(),
+            wifi_mac_address: string("WiFiMACAddress"),
+            udid: match record.get("UDID") {
+                None => None,
+                Some(plist::Value::String(u)) => Some(u.clone()),
+                Some(_) => return Err(PairingFileProblem::WrongType("UDID")),
+            },
+        };
+        match pairing_file.find_problem() {
+            Ok(None) => Ok(pairing_file),
+            Ok(Some(problem)) => Err(problem),
+            // OpenSSL failing to compare keys or read a date means the data is off
+            Err(e) => Err(PairingFileProblem::MalformedPlist(e.to_string())),
+        }
+    }
+
     pub fn serialize(self) -> Result<Vec<u8>, crate::IdeviceError> {
         let raw = RawPairingFile::try_from(self)?;
 
@@ -80,6 +210,226 @@ impl PairingFile {
         plist::to_writer_xml(&mut buf, &raw)?;
         Ok(buf)
     }
+
+    pub fn host_id(&self) -> &str {
+        &self.host_id
+    }
+
+    pub fn system_buid(&self) -> &str {
+        &self.system_buid
+    }
+
+    pub fn wifi_mac_address(&self) -> &str {
+        &self.wifi_mac_address
+    }
+
+    pub fn device_certificate_expiry(&self) -> Result<SystemTime, crate::IdeviceError> {
+        asn1_to_system_time(self.device_certificate.not_after())
+    }
+
+    pub fn host_certificate_expiry(&self) -> Result<SystemTime, crate::IdeviceError> {
+        asn1_to_system_time(self.host_certificate.not_after())
+    }
+
+    pub fn root_certificate_expiry(&self) -> Result<SystemTime, crate::IdeviceError> {
+        asn1_to_system_time(self.root_certificate.not_after())
+    }
+
+    /// The soonest expiry of the three certificates. Once this passes, the record must be re-paired.
+    pub fn expiry(&self) -> Result<SystemTime, crate::IdeviceError> {
+        Ok(self
+            .device_certificate_expiry()?
+            .min(self.host_certificate_expiry()?)
+            .min(self.root_certificate_expiry()?))
+    }
+
+    /// Checks that the keys belong to their certificates, that the host and device
+    /// certificates were issued by the root certificate, and that none have expired.
+    pub fn validate(&self) -> Result<(), crate::IdeviceError> {
+        match self.find_problem()? {
+            Some(problem) => Err(crate::IdeviceError::InvalidPairingFile(problem.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn find_problem(&self) -> Result<Option<PairingFileProblem>, crate::IdeviceError> {
+        if !self
+            .host_certificate
+            .public_key()?
+            .public_eq(&self.host_private_key)
+        {
+            return Ok(Some(PairingFileProblem::KeyMismatch("HostCertificate")));
+        }
+        if !self
+            .root_certificate
+            .public_key()?
+            .public_eq(&self.root_private_key)
+        {
+            return Ok(Some(PairingFileProblem::KeyMismatch("RootCertificate")));
+        }
+        if !self.host_certificate.verify(&self.root_private_key)? {
+            return Ok(Some(PairingFileProblem::NotSigned("HostCertificate")));
+        }
+        if !self.device_certificate.verify(&self.root_private_key)? {
+            return Ok(Some(PairingFileProblem::NotSigned("DeviceCertificate")));
+        }
+        let now = SystemTime::now();
+        for (key, expiry) in [
+            ("DeviceCertificate", self.device_certificate_expiry()?),
+            ("HostCertificate", self.host_certificate_expiry()?),
+            ("RootCertificate", self.root_certificate_expiry()?),
+        ] {
+            if expiry < now {
+                return Ok(Some(PairingFileProblem::Expired(key)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Serializes to the layout usbmuxd stores with ``SavePairRecord``
+    pub fn to_usbmuxd_record(&self) -> Result<Vec<u8>, crate::IdeviceError> {
+        self.clone().serialize()
+    }
+
+    /// Parses the ``PairRecordData`` usbmuxd returns from ``ReadPairRecord``
+    pub fn from_usbmuxd_record(bytes: &[u8]) -> Result<Self, crate::IdeviceError> {
+        Self::from_bytes(bytes)
+    }
+
+    /// The record lockdownd expects in a ``Pair`` request. It doesn't contain any private keys.
+    pub fn to_lockdown_record(&self) -> Result<plist::Dictionary, crate::IdeviceError> {
+        let mut record = plist::Dictionary::new();
+        record.insert(
+            "DeviceCertificate".into(),
+            plist::Value::Data(self.device_certificate.to_pem()?),
+        );
+        record.insert(
+            "HostCertificate".into(),
+            plist::Value::Data(self.host_certificate.to_pem()?),
+        );
+        record.insert(
+            "RootCertificate".into(),
+            plist::Value::Data(self.root_certificate.to_pem()?),
+        );
+        record.insert("HostID".into(), self.host_id.clone().into());
+        record.insert("SystemBUID".into(), self.system_buid.clone().into());
+        Ok(record)
+    }
+
+    /// Builds a pairing file from a lockdown ``Pair`` record and the private keys that were
+    /// used to create it. ``EscrowBag``, ``WiFiMACAddress`` and ``UDID`` are read from the
+    /// record when present, so the ``Pair`` response can be merged in first.
+    pub fn from_lockdown_record(
+        record: &plist::Dictionary,
+        host_private_key: PKey<Private>,
+        root_private_key: PKey<Private>,
+    ) -> Result<Self, crate::IdeviceError> {
+        fn data<'a>(
+            record: &'a plist::Dictionary,
+            key: &str,
+        ) -> Result<&'a [u8], crate::IdeviceError> {
+            match record.get(key).and_then(|v| v.as_data()) {
+                Some(d) => Ok(d),
+                None => Err(crate::IdeviceError::InvalidPairingFile(format!(
+                    "lockdown record is missing {key}"
+                ))),
+            }
+        }
+        fn string(record: &plist::Dictionary, key: &str) -> Option<String> {
+            record
+                .get(key)
+                .and_then(|v| v.as_string())
+                .map(|s| s.to_string())
+        }
+
+        Ok(Self {
+            device_certificate: X509::from_pem(data(record, "DeviceCertificate")?)?,
+            host_private_key,
+            host_certificate: X509::from_pem(data(record, "HostCertificate")?)?,
+            root_private_key,
+            root_certificate: X509::from_pem(data(record, "RootCertificate")?)?,
+            system_buid: match string(record, "SystemBUID") {
+                Some(s) => s,
+                None => {
+                    return Err(crate::IdeviceError::InvalidPairingFile(
+                        "lockdown record is missing SystemBUID".into(),
+                    ))
+                }
+            },
+            host_id: match string(record, "HostID") {
+                Some(s) => s,
+                None => {
+                    return Err(crate::IdeviceError::InvalidPairingFile(
+                        "lockdown record is missing HostID".into(),
+                    ))
+                }
+            },
+            escrow_bag: record
+                .get("EscrowBag")
+                .and_then(|v| v.as_data())
+                .map(|d| d.to_vec())
+                .unwrap_or_default(),
+            wifi_mac_address: string(record, "WiFiMACAddress").unwrap_or_default(),
+            udid: string(record, "UDID"),
+        })
+    }
+}
+
+/// Leaves out the private keys and escrow bag
+impl fmt::Debug for PairingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PairingFile")
+            .field("device_certificate", &self.device_certificate)
+            .field("host_private_key", &REDACTED)
+            .field("host_certificate", &self.host_certificate)
+            .field("root_private_key", &REDACTED)
+            .field("root_certificate", &self.root_certificate)
+            .field("system_buid", &self.system_buid)
+            .field("host_id", &self.host_id)
+            .field("escrow_bag", &REDACTED)
+            .field("wifi_mac_address", &self.wifi_mac_address)
+            .field("udid", &self.udid)
+            .finish()
+    }
+}
+
+/// ``pairing record for 00008030-001A2D3E0C01802E, host ID 2A1F...``
+impl fmt::Display for PairingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.udid {
+            Some(udid) => write!(f, "pairing record for {udid}")?,
+            None => write!(f, "pairing record")?,
+        }
+        write!(f, ", host ID {}", self.host_id)
+    }
+}
+
+impl Pretty for PairingFile {
+    fn pretty(&self) -> String {
+        let expiry = match self.expiry() {
+            Ok(t) => format_unix_time(t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()),
+            Err(_) => "unknown".to_string(),
+        };
+        format!(
+            "UDID: {}\nHost ID: {}\nSystem BUID: {}\nWiFi MAC address: {}\n\
+             Escrow bag: {REDACTED} ({} bytes)\nExpires: {expiry}",
+            self.udid.as_deref().unwrap_or("unknown"),
+            self.host_id,
+            self.system_buid,
+            self.wifi_mac_address,
+            self.escrow_bag.len(),
+        )
+    }
+}
+
+fn asn1_to_system_time(time: &Asn1TimeRef) -> Result<SystemTime, crate::IdeviceError> {
+    let epoch = Asn1Time::from_unix(0)?;
+    let diff = epoch.diff(time)?;
+    let secs = diff.days as i64 * 86400 + diff.secs as i64;
+    if secs < 0 {
+        return Ok(UNIX_EPOCH);
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(secs as u64))
 }
 
 impl TryFrom<RawPairingFile> for PairingFile {
@@ -136,3 +486,61 @@ fn f1() {
 
     assert_eq!(f[..output.len()], output);
 }
+
+#[cfg(all(test, feature = "testing"))]
+#[test]
+fn problems_are_named() {
+    let pairing_file = crate::testing::MockProvider::new("test")
+        .unwrap()
+        .pairing_file()
+        .clone();
+    let bytes = pairing_file.clone().serialize().unwrap();
+    assert!(PairingFile::check_bytes(&bytes).is_ok());
+
+    let mut record: plist::Dictionary = plist::from_bytes(&bytes).unwrap();
+    let edited = |record: &plist::Dictionary| {
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, record).unwrap();
+        PairingFile::check_bytes(&buf).unwrap_err()
+    };
+
+    assert!(matches!(
+        PairingFile::check_bytes(b"not a plist"),
+        Err(PairingFileProblem::MalformedPlist(_))
+    ));
+    let host_id = record.remove("HostID").unwrap();
+    assert_eq!(edited(&record), PairingFileProblem::MissingKey("HostID"));
+    record.insert("HostID".into(), 1u64.into());
+    assert_eq!(edited(&record), PairingFileProblem::WrongType("HostID"));
+    record.insert("HostID".into(), host_id);
+
+    let host_key = record.get("HostPrivateKey").unwrap().clone();
+    record.insert(
+        "HostPrivateKey".into(),
+        plist::Value::Data(b"junk".to_vec()),
+    );
+    assert_eq!(
+        edited(&record),
+        PairingFileProblem::BadKey("HostPrivateKey")
+    );
+    let root_key = record.get("RootPrivateKey").unwrap().clone();
+    record.insert("HostPrivateKey".into(), root_key);
+    assert_eq!(
+        edited(&record),
+        PairingFileProblem::KeyMismatch("HostCertificate")
+    );
+    record.insert("HostPrivateKey".into(), host_key);
+
+    let other = crate::testing::MockProvider::new("other")
+        .unwrap()
+        .pairing_file()
+        .clone();
+    record.insert(
+        "DeviceCertificate".into(),
+        plist::Value::Data(other.device_certificate.to_pem().unwrap()),
+    );
+    assert_eq!(
+        edited(&record),
+        PairingFileProblem::NotSigned("DeviceCertificate")
+    );
+}
@@ -0,0 +1,242 @@
+// Jackson Coxson
+// Out-of-band data for "scan to trust this computer" flows.
+// The host renders a PairingOffer (usually as a QR code), and whoever scans it
+// uses a PairingAcceptor to check that the pairing record presented over the
+// network belongs to the same host.
+
+use std::fmt::Write;
+
+use log::warn;
+use openssl::{hash::MessageDigest, x509::X509};
+
+use crate::{pairing_file::PairingFile, IdeviceError};
+
+pub const OFFER_SCHEME: &str = "idevice-pair";
+pub const OFFER_VERSION: &str = "v1";
+
+/// Identifies a host and the certificates it will present when pairing over the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingOffer {
+    pub host_id: String,
+    pub system_buid: String,
+    /// SHA-256 of the DER encoded host certificate
+    pub host_certificate_fingerprint: [u8; 32],
+    /// SHA-256 of the DER encoded root certificate
+    pub root_certificate_fingerprint: [u8; 32],
+    pub udid: Option<String>,
+}
+
+impl PairingOffer {
+    pub fn from_pairing_file(pairing_file: &PairingFile) -> Result<Self, IdeviceError> {
+        Ok(Self {
+            host_id: pairing_file.host_id.clone(),
+            system_buid: pairing_file.system_buid.clone(),
+            host_certificate_fingerprint: fingerprint(&pairing_file.host_certificate)?,
+            root_certificate_fingerprint: fingerprint(&pairing_file.root_certificate)?,
+            udid: pairing_file.udid.clone(),
+        })
+    }
+
+    /// Encodes the offer as a URI suitable for embedding in a QR code.
+    /// ``idevice-pair://v1?host=...&buid=...&hfp=...&rfp=...[&udid=...]``
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!(
+            "{OFFER_SCHEME}://{OFFER_VERSION}?host={}&buid={}&hfp={}&rfp={}",
+            self.host_id,
+            self.system_buid,
+            hex_encode(&self.host_certificate_fingerprint),
+            hex_encode(&self.root_certificate_fingerprint),
+        );
+        if let Some(udid) = &self.udid {
+            uri.push_str("&udid=");
+            uri.push_str(udid);
+        }
+        uri
+    }
+
+    pub fn from_uri(uri: &str) -> Result<Self, IdeviceError> {
+        let query = match uri
+            .strip_prefix(OFFER_SCHEME)
+            .and_then(|u| u.strip_prefix("://"))
+            .and_then(|u| u.strip_prefix(OFFER_VERSION))
+            .and_then(|u| u.strip_prefix('?'))
+        {
+            Some(q) => q,
+            None => {
+                warn!("Pairing offer has an unknown scheme or version");
+                return Err(IdeviceError::InvalidPairingOffer);
+            }
+        };
+
+        let mut host_id = None;
+        let mut system_buid = None;
+        let mut host_fp = None;
+        let mut root_fp = None;
+        let mut udid = None;
+        for pair in query.split('&') {
+            let (key, value) = match pair.split_once('=') {
+                Some(p) => p,
+                None => return Err(IdeviceError::InvalidPairingOffer),
+            };
+            if !is_identifier(value) {
+                warn!("Pairing offer value for {key} contains invalid characters");
+                return Err(IdeviceError::InvalidPairingOffer);
+            }
+            match key {
+                "host" => host_id = Some(value.to_string()),
+                "buid" => system_buid = Some(value.to_string()),
+                "hfp" => host_fp = Some(hex_decode_fingerprint(value)?),
+                "rfp" => root_fp = Some(hex_decode_fingerprint(value)?),
+                "udid" => udid = Some(value.to_string()),
+                _ => {
+                    // Unknown keys are allowed so newer hosts can add fields
+                    continue;
+                }
+            }
+        }
+
+        match (host_id, system_buid, host_fp, root_fp) {
+            (Some(host_id), Some(system_buid), Some(host_fp), Some(root_fp)) => Ok(Self {
+                host_id,
+                system_buid,
+                host_certificate_fingerprint: host_fp,
+                root_certificate_fingerprint: root_fp,
+                udid,
+            }),
+            _ => {
+                warn!("Pairing offer is missing required fields");
+                Err(IdeviceError::InvalidPairingOffer)
+            }
+        }
+    }
+}
+
+/// Verifies pairing records against a scanned offer
+#[derive(Debug, Clone)]
+pub struct PairingAcceptor {
+    offer: PairingOffer,
+}
+
+impl PairingAcceptor {
+    pub fn new(offer: PairingOffer) -> Self {
+        Self { offer }
+    }
+
+    pub fn from_uri(uri: &str) -> Result<Self, IdeviceError> {
+        Ok(Self::new(PairingOffer::from_uri(uri)?))
+    }
+
+    pub fn offer(&self) -> &PairingOffer {
+        &self.offer
+    }
+
+    /// Checks that the pairing record was issued by the host that created the offer.
+    /// Returns ``PairingOfferMismatch`` if any identifier or fingerprint differs.
+    pub fn verify(&self, pairing_file: &PairingFile) -> Result<(), IdeviceError> {
+        let presented = PairingOffer::from_pairing_file(pairing_file)?;
+        if presented.host_id != self.offer.host_id
+            || presented.system_buid != self.offer.system_buid
+            || presented.host_certificate_fingerprint != self.offer.host_certificate_fingerprint
+            || presented.root_certificate_fingerprint != self.offer.root_certificate_fingerprint
+        {
+            warn!("Pairing record does not match the scanned offer");
+            return Err(IdeviceError::PairingOfferMismatch);
+        }
+        // A record without a UDID can't be shown to be for the device the offer named
+        if let Some(expected) = &self.offer.udid {
+            match &presented.udid {
+                Some(udid) if udid == expected => {}
+                Some(udid) => {
+                    warn!("Pairing record is for {udid}, offer was for {expected}");
+                    return Err(IdeviceError::PairingOfferMismatch);
+                }
+                None => {
+                    warn!("Pairing record has no UDID, offer was for {expected}");
+                    return Err(IdeviceError::PairingOfferMismatch);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fingerprint(cert: &X509) -> Result<[u8; 32], IdeviceError> {
+    let digest = cert.digest(MessageDigest::sha256())?;
+    let mut res = [0u8; 32];
+    res.copy_from_slice(&digest);
+    Ok(res)
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02X}");
+        output
+    })
+}
+
+fn hex_decode_fingerprint(s: &str) -> Result<[u8; 32], IdeviceError> {
+    if s.len() != 64 {
+        return Err(IdeviceError::InvalidPairingOffer);
+    }
+    let mut res = [0u8; 32];
+    for (i, b) in res.iter_mut().enumerate() {
+        *b = match u8::from_str_radix(&s[i * 2..i * 2 + 2], 16) {
+            Ok(b) => b,
+            Err(_) => return Err(IdeviceError::InvalidPairingOffer),
+        };
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_round_trip() {
+        let offer = PairingOffer {
+            host_id: "5E6A0C9B-2F4D-4C3B-9A1E-7D8F6B5A4C3D".to_string(),
+            system_buid: "A1B2C3D4-E5F6-4A7B-8C9D-0E1F2A3B4C5D".to_string(),
+            host_certificate_fingerprint: [0xAB; 32],
+            root_certificate_fingerprint: [0x01; 32],
+            udid: Some("00008030-001A2B3C4D5E6F70".to_string()),
+        };
+        let uri = offer.to_uri();
+        assert_eq!(PairingOffer::from_uri(&uri).unwrap(), offer);
+    }
+
+    #[test]
+    fn rejects_bad_offers() {
+        assert!(PairingOffer::from_uri("https://example.com").is_err());
+        assert!(PairingOffer::from_uri("idevice-pair://v1?host=a&buid=b&hfp=00&rfp=00").is_err());
+        assert!(PairingOffer::from_uri("idevice-pair://v1?host=a%20b&buid=b").is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn records_must_name_the_offered_device() {
+        use crate::{provider::IdeviceProvider, testing::MockProvider};
+
+        let provider = MockProvider::new("test").unwrap();
+        let pairing_file = provider.get_pairing_file().await.unwrap();
+        let acceptor =
+            PairingAcceptor::new(PairingOffer::from_pairing_file(&pairing_file).unwrap());
+        acceptor.verify(&pairing_file).unwrap();
+
+        let mut other = pairing_file.clone();
+        other.udid = Some("00008030-001A2B3C4D5E6F70".to_string());
+        assert!(matches!(
+            acceptor.verify(&other),
+            Err(IdeviceError::PairingOfferMismatch)
+        ));
+        other.udid = None;
+        assert!(matches!(
+            acceptor.verify(&other),
+            Err(IdeviceError::PairingOfferMismatch)
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+// Jackson Coxson
+// Comparing plists key by key.
+// Device state comes back as nested dictionaries, and a tool checking a device against
+// what it should look like needs to say which key differs, not just that something does.
+// Paths are written like JSON pointers, ``/com.apple.mobile.battery/BatteryIsCharging``,
+// since lockdown keys and domains have dots in them.
+
+use std::fmt;
+
+use crate::pretty::{is_secret, Pretty, REDACTED};
+
+/// One step into a plist
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Where a value sits in a plist, from the root
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PlistPath(pub Vec<PathSegment>);
+
+impl PlistPath {
+    fn child(&self, segment: PathSegment) -> Self {
+        let mut path = self.0.clone();
+        path.push(segment);
+        Self(path)
+    }
+
+    /// The key the value is stored under, if its parent is a dictionary
+    pub fn key(&self) -> Option<&str> {
+        match self.0.last() {
+            Some(PathSegment::Key(k)) => Some(k),
+            _ => None,
+        }
+    }
+}
+
+/// ``/`` for the root, otherwise each key or index after a ``/``, with ``~`` and ``/`` in
+/// keys escaped as ``~0`` and ``~1``
+impl fmt::Display for PlistPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("/");
+        }
+        for segment in &self.0 {
+            match segment {
+                PathSegment::Key(k) => write!(f, "/{}", k.replace('~', "~0").replace('/', "~1"))?,
+                PathSegment::Index(i) => write!(f, "/{i}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlistChange {
+    /// Only in the second plist
+    Added {
+        path: PlistPath,
+        value: plist::Value,
+    },
+    /// Only in the first plist
+    Removed {
+        path: PlistPath,
+        value: plist::Value,
+    },
+    /// In both, but different. Dictionaries and arrays are compared inside instead.
+    Changed {
+        path: PlistPath,
+        from: plist::Value,
+        to: plist::Value,
+    },
+}
+
+impl PlistChange {
+    pub fn path(&self) -> &PlistPath {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Changed { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+/// ``+ /path: value``, ``- /path: value`` or ``~ /path: from -> to``.
+/// Values under secret keys are redacted.
+impl fmt::Display for PlistChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |v: &plist::Value| match self.path().key() {
+            Some(k) if is_secret(k) => REDACTED.to_string(),
+            _ => summary(v),
+        };
+        match self {
+            Self::Added { path, value: v } => write!(f, "+ {path}: {}", value(v)),
+            Self::Removed { path, value: v } => write!(f, "- {path}: {}", value(v)),
+            Self::Changed { path, from, to } => {
+                write!(f, "~ {path}: {} -> {}", value(from), value(to))
+            }
+        }
+    }
+}
+
+/// Every difference between two plists, in the order they appear
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlistDiff {
+    pub changes: Vec<PlistChange>,
+}
+
+impl PlistDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes other than additions, for checking a plist against one that only has
+    /// the keys that matter
+    pub fn missing_or_changed(&self) -> impl Iterator<Item = &PlistChange> {
+        self.changes
+            .iter()
+            .filter(|c| !matches!(c, PlistChange::Added { .. }))
+    }
+}
+
+impl Pretty for PlistDiff {
+    fn pretty(&self) -> String {
+        if self.is_empty() {
+            return "No differences".to_string();
+        }
+        self.changes
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Compares ``b`` against ``a``. Dictionaries are compared key by key and arrays index by
+/// index, so an item inserted into an array shows up as a change to everything after it.
+pub fn diff(a: &plist::Value, b: &plist::Value) -> PlistDiff {
+    let mut changes = Vec::new();
+    diff_into(&PlistPath::default(), a, b, &mut changes);
+    PlistDiff { changes }
+}
+
+fn diff_into(path: &PlistPath, a: &plist::Value, b: &plist::Value, out: &mut Vec<PlistChange>) {
+    match (a, b) {
+        (plist::Value::Dictionary(a), plist::Value::Dictionary(b)) => {
+            for (key, a_value) in a {
+                let path = path.child(PathSegment::Key(key.clone()));
+                match b.get(key) {
+                    Some(b_value) => diff_into(&path, a_value, b_value, out),
+                    None => out.push(PlistChange::Removed {
+                        path,
+                        value: a_value.clone(),
+                    }),
+                }
+            }
+            for (key, b_value) in b {
+                if !a.contains_key(key) {
+                    out.push(PlistChange::Added {
+                        path: path.child(PathSegment::Key(key.clone())),
+                        value: b_value.clone(),
+                    });
+                }
+            }
+        }
+        (plist::Value::Array(a), plist::Value::Array(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                let path = path.child(PathSegment::Index(i));
+                match (a.get(i), b.get(i)) {
+                    (Some(a), Some(b)) => diff_into(&path, a, b, out),
+                    (Some(a), None) => out.push(PlistChange::Removed {
+                        path,
+                        value: a.clone(),
+                    }),
+                    (None, Some(b)) => out.push(PlistChange::Added {
+                        path,
+                        value: b.clone(),
+                    }),
+                    (None, None) => {}
+                }
+            }
+        }
+        (a, b) if a != b => out.push(PlistChange::Changed {
+            path: path.clone(),
+            from: a.clone(),
+            to: b.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// A value on one line, with containers and data summarized
+fn summary(value: &plist::Value) -> String {
+    match value {
+        plist::Value::Dictionary(d) => format!("{{{} keys}}", d.len()),
+        plist::Value::Array(a) => format!("[{} items]", a.len()),
+        plist::Value::Data(d) => format!("<{} bytes>", d.len()),
+        plist::Value::String(s) => format!("{s:?}"),
+        plist::Value::Boolean(b) => b.to_string(),
+        plist::Value::Integer(i) => i.to_string(),
+        plist::Value::Real(r) => r.to_string(),
+        plist::Value::Date(d) => d.to_xml_format(),
+        plist::Value::Uid(u) => format!("uid {}", u.get()),
+        _ => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_nested_values() {
+        let a = plist::Value::from_reader_xml(
+            &br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+    <key>DeviceName</key><string>Kiosk 1</string>
+    <key>com.apple.mobile.battery</key><dict>
+        <key>BatteryIsCharging</key><true/>
+    </dict>
+    <key>Apps</key><array><string>a</string><string>b</string></array>
+    <key>EscrowBag</key><data>AQID</data>
+    <key>a/b</key><integer>1</integer>
+</dict></plist>"#[..],
+        )
+        .unwrap();
+        let b = plist::Value::from_reader_xml(
+            &br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+    <key>DeviceName</key><string>Kiosk 2</string>
+    <key>com.apple.mobile.battery</key><dict>
+        <key>BatteryIsCharging</key><true/>
+        <key>BatteryCurrentCapacity</key><integer>80</integer>
+    </dict>
+    <key>Apps</key><array><string>a</string></array>
+    <key>EscrowBag</key><data>BAUG</data>
+</dict></plist>"#[..],
+        )
+        .unwrap();
+
+        assert!(diff(&a, &a).is_empty());
+        let changes = diff(&a, &b)
+            .changes
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                "~ /DeviceName: \"Kiosk 1\" -> \"Kiosk 2\"",
+                "+ /com.apple.mobile.battery/BatteryCurrentCapacity: 80",
+                "- /Apps/1: \"b\"",
+                "~ /EscrowBag: <redacted> -> <redacted>",
+                "- /a~1b: 1",
+            ]
+        );
+        assert_eq!(diff(&a, &b).missing_or_changed().count(), 4);
+    }
+}
@@ -0,0 +1,104 @@
+// Jackson Coxson
+// Readable output for protocol types.
+// ``Display`` gives a one line summary for logs, ``pretty`` the whole thing for people.
+// Neither prints private keys, escrow bags or other secrets.
+
+use std::error::Error;
+
+use crate::IdeviceError;
+
+/// Values under these keys are replaced when a plist is printed
+pub const SECRET_KEYS: &[&str] = &[
+    "EscrowBag",
+    "HostPrivateKey",
+    "PairRecordData",
+    "Password",
+    "RootPrivateKey",
+];
+
+/// What's printed in place of a secret
+pub const REDACTED: &str = "<redacted>";
+
+/// A multi-line, human readable rendering
+pub trait Pretty {
+    fn pretty(&self) -> String;
+}
+
+impl Pretty for plist::Value {
+    fn pretty(&self) -> String {
+        crate::pretty_print_plist(self)
+    }
+}
+
+impl Pretty for plist::Dictionary {
+    fn pretty(&self) -> String {
+        crate::pretty_print_dictionary(self)
+    }
+}
+
+/// The error followed by whatever caused it
+impl Pretty for IdeviceError {
+    fn pretty(&self) -> String {
+        let mut out = self.to_string();
+        let mut source = self.source();
+        while let Some(e) = source {
+            out.push_str(&format!("\n  caused by: {e}"));
+            source = e.source();
+        }
+        out
+    }
+}
+
+pub fn is_secret(key: &str) -> bool {
+    SECRET_KEYS.contains(&key)
+}
+
+/// Formats seconds since the epoch as ``2024-03-09 17:04:05 UTC``
+pub fn format_unix_time(secs: u64) -> String {
+    let days = secs / 86400;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// Days since the epoch to a proleptic Gregorian date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted() {
+        let mut dict = plist::Dictionary::new();
+        dict.insert("HostID".into(), "ABCD".into());
+        dict.insert("EscrowBag".into(), plist::Value::Data(vec![1, 2, 3]));
+        let printed = dict.pretty();
+        assert!(printed.contains("\"ABCD\""));
+        assert!(printed.contains(REDACTED));
+        assert!(!printed.contains("01 02 03"));
+    }
+
+    #[test]
+    fn times_are_formatted() {
+        assert_eq!(format_unix_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_unix_time(1709996645), "2024-03-09 15:04:05 UTC");
+        assert_eq!(format_unix_time(951782400), "2000-02-29 00:00:00 UTC");
+    }
+}
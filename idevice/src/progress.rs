@@ -0,0 +1,97 @@
+// Jackson Coxson
+// A single progress model for long operations.
+// Frontends subscribe to one channel instead of handling each module's callback shape.
+
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::IdeviceError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    Started,
+    Progress {
+        done: u64,
+        total: u64,
+    },
+    /// The operation moved on to a new step, such as ``Uploading`` or ``Mounting``
+    Phase(String),
+    Finished,
+    Failed(String),
+}
+
+/// The sending half of a progress channel, passed into ``*_with_observer`` functions
+#[derive(Debug, Clone)]
+pub struct ProgressObserver {
+    sender: mpsc::UnboundedSender<ProgressEvent>,
+}
+
+impl ProgressObserver {
+    /// Creates an observer and the receiver its events are delivered to
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<ProgressEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
+
+    /// Sends an event. A dropped receiver doesn't fail the operation being observed.
+    pub fn emit(&self, event: ProgressEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn phase(&self, phase: impl Into<String>) {
+        self.emit(ProgressEvent::Phase(phase.into()));
+    }
+
+    /// Emits ``Finished`` or ``Failed`` for the result and passes it through
+    pub fn complete<T>(&self, res: Result<T, IdeviceError>) -> Result<T, IdeviceError> {
+        match &res {
+            Ok(_) => self.emit(ProgressEvent::Finished),
+            Err(e) => self.emit(ProgressEvent::Failed(e.to_string())),
+        }
+        res
+    }
+}
+
+/// How a client's transfers have gone, for reporting throughput to a user or comparing runs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransferStats {
+    /// File contents moved in either direction, not counting protocol overhead
+    pub bytes: u64,
+    /// Time spent waiting on the device for those bytes
+    pub elapsed: Duration,
+    /// Requests sent again because the device was busy
+    pub retries: u64,
+}
+
+impl TransferStats {
+    /// Megabytes (10^6 bytes) per second, or zero before anything was transferred
+    pub fn mb_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.bytes as f64 / secs / 1_000_000.0
+    }
+}
+
+impl std::fmt::Display for TransferStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes in {:.2}s ({:.2} MB/s), {} retries",
+            self.bytes,
+            self.elapsed.as_secs_f64(),
+            self.mb_per_sec(),
+            self.retries
+        )
+    }
+}
+
+/// Adapts the ``((done, total), state)`` progress callbacks, with the observer passed as the state
+pub async fn forward(((done, total), observer): ((usize, usize), ProgressObserver)) {
+    observer.emit(ProgressEvent::Progress {
+        done: done as u64,
+        total: total as u64,
+    });
+}
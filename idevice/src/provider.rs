@@ -4,12 +4,13 @@ use std::{
     future::Future,
     net::{IpAddr, SocketAddr},
     pin::Pin,
+    sync::Arc,
 };
 
-#[cfg(feature = "tcp")]
-use tokio::net::TcpStream;
-
-use crate::{pairing_file::PairingFile, Idevice, IdeviceError};
+use crate::{
+    key_store::KeyStore, lockdownd::LockdowndClient, pairing_file::PairingFile, Idevice,
+    IdeviceError, IdeviceService, ReadWrite,
+};
 
 #[cfg(feature = "usbmuxd")]
 use crate::usbmuxd::UsbmuxdAddr;
@@ -29,6 +30,70 @@ pub trait IdeviceProvider: Unpin + Send + Sync + std::fmt::Debug {
     ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>>;
 }
 
+impl dyn IdeviceProvider + '_ {
+    /// Starts any lockdown service by name and returns its connection, with SSL set up if
+    /// the service asks for it. For services this crate doesn't have a client for.
+    pub async fn start_service_raw(&self, name: &str) -> Result<Box<dyn ReadWrite>, IdeviceError> {
+        let pairing_file = self.get_pairing_file().await?;
+        let mut lockdown = LockdowndClient::connect(self).await?;
+        lockdown.start_session(&pairing_file).await?;
+        let (port, ssl) = lockdown.start_service(name).await?;
+
+        let mut idevice = self.connect(port).await?;
+        if ssl {
+            idevice.start_session(&pairing_file).await?;
+        }
+        idevice.into_inner()
+    }
+
+    /// Restarts the device through diagnostics_relay, waiting until it disconnects.
+    /// Needed for language and Developer Mode changes to apply.
+    pub async fn restart(&self) -> Result<(), IdeviceError> {
+        let mut diagnostics = Idevice::new(
+            self.start_service_raw("com.apple.mobile.diagnostics_relay")
+                .await?,
+            self.label(),
+        );
+        let mut req = plist::Dictionary::new();
+        req.insert("Request".into(), "Restart".into());
+        req.insert("WaitForDisconnect".into(), true.into());
+        diagnostics
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        diagnostics.read_plist().await?;
+        Ok(())
+    }
+}
+
+/// Connects through another provider, but reads the pairing file from a key store
+#[derive(Debug)]
+pub struct KeyStoreProvider<P> {
+    pub provider: P,
+    pub store: Arc<dyn KeyStore>,
+    pub udid: String,
+}
+
+impl<P: IdeviceProvider> IdeviceProvider for KeyStoreProvider<P> {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        self.provider.connect(port)
+    }
+
+    fn label(&self) -> &str {
+        self.provider.label()
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        let store = self.store.clone();
+        let udid = self.udid.clone();
+        Box::pin(async move { store.load_pairing_file(&udid).await })
+    }
+}
+
 #[cfg(feature = "tcp")]
 #[derive(Debug)]
 pub struct TcpProvider {
@@ -47,8 +112,8 @@ impl IdeviceProvider for TcpProvider {
         let label = self.label.clone();
         Box::pin(async move {
             let socket_addr = SocketAddr::new(addr, port);
-            let stream = TcpStream::connect(socket_addr).await?;
-            Ok(Idevice::new(Box::new(stream), label))
+            let stream = crate::runtime::connect_tcp(socket_addr).await?;
+            Ok(Idevice::new(stream, label))
         })
     }
 
@@ -0,0 +1,307 @@
+// Jackson Coxson
+// Onboards a device by running a list of steps: pairing, Developer Mode, profiles, apps,
+// the device name and language, and the developer disk image. Steps that are already done
+// are skipped, and how far a device got is kept in a ProvisioningState, so a run that
+// failed or had to wait for a restart picks up where it stopped.
+
+use std::{path::PathBuf, sync::Arc};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    afc::AfcClient,
+    amfi::{developer_mode_status, AmfiClient},
+    install_pipeline::{InstallPipeline, InstallStage},
+    installation_proxy::InstallationProxyClient,
+    key_store::KeyStore,
+    lockdownd::LockdowndClient,
+    mcinstall::McInstallClient,
+    mounter::{ensure_developer_image, DdiRepository},
+    provider::IdeviceProvider,
+    IdeviceError, IdeviceService,
+};
+
+/// One thing to do to a device. Plans can be written as plists or JSON, such as
+/// ``{"step": "install_app", "path": "Lab.ipa"}``.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    /// Pairs if the provider's pairing file isn't trusted, saving the record to the key store
+    Pair,
+    /// Arms Developer Mode and restarts the device, then accepts it on the next run
+    EnableDeveloperMode,
+    InstallProfile {
+        path: PathBuf,
+    },
+    InstallApp {
+        path: PathBuf,
+    },
+    SetDeviceName {
+        name: String,
+    },
+    /// Restarts the device if the language or region changed. The next run skips this step.
+    SetLanguage {
+        language: String,
+        locale: Option<String>,
+    },
+    /// Mounts the right image from a ``DdiRepository``
+    MountDeveloperImage {
+        repository: PathBuf,
+    },
+}
+
+/// How far a device got through a plan. Keep it between runs to resume.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvisioningState {
+    /// The index of the first step that hasn't finished
+    pub next_step: usize,
+    /// Developer Mode was armed and the device restarted, so it has to be accepted
+    pub developer_mode_armed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProvisioningEvent {
+    Started {
+        step: usize,
+        total: usize,
+    },
+    Skipped {
+        step: usize,
+        reason: &'static str,
+    },
+    /// Bytes uploaded for the step
+    Progress {
+        step: usize,
+        done: u64,
+        total: u64,
+    },
+    Finished {
+        step: usize,
+    },
+    Failed {
+        step: usize,
+        error: String,
+    },
+    /// The device is restarting, run the plan again once it's back
+    Restarting {
+        step: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvisioningOutcome {
+    Complete,
+    /// The device is restarting partway through
+    Restarting,
+}
+
+enum StepOutcome {
+    Done,
+    Skipped(&'static str),
+    Restarting,
+}
+
+pub struct Provisioner {
+    pub plan: Vec<Step>,
+    key_store: Option<Arc<dyn KeyStore>>,
+    events: Option<Box<dyn Fn(ProvisioningEvent) + Send + Sync>>,
+}
+
+impl Provisioner {
+    pub fn new(plan: Vec<Step>) -> Self {
+        Self {
+            plan,
+            key_store: None,
+            events: None,
+        }
+    }
+
+    /// Where ``Step::Pair`` saves new pairing records. Give the provider a
+    /// ``KeyStoreProvider`` over the same store so later steps use the new record.
+    pub fn with_key_store(mut self, key_store: Arc<dyn KeyStore>) -> Self {
+        self.key_store = Some(key_store);
+        self
+    }
+
+    pub fn with_events(
+        mut self,
+        events: impl Fn(ProvisioningEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.events = Some(Box::new(events));
+        self
+    }
+
+    fn emit(&self, event: ProvisioningEvent) {
+        if let Some(events) = &self.events {
+            events(event);
+        }
+    }
+
+    /// Runs the plan from ``state.next_step``, advancing the state as steps finish.
+    /// On an error the state points at the step that failed, so running again retries it.
+    pub async fn run(
+        &self,
+        provider: &dyn IdeviceProvider,
+        state: &mut ProvisioningState,
+    ) -> Result<ProvisioningOutcome, IdeviceError> {
+        let total = self.plan.len();
+        while let Some(step) = self.plan.get(state.next_step) {
+            let index = state.next_step;
+            self.emit(ProvisioningEvent::Started { step: index, total });
+            debug!("Provisioning step {}/{total}: {step:?}", index + 1);
+
+            match self.run_step(index, step, provider, state).await {
+                Ok(StepOutcome::Done) => self.emit(ProvisioningEvent::Finished { step: index }),
+                Ok(StepOutcome::Skipped(reason)) => self.emit(ProvisioningEvent::Skipped {
+                    step: index,
+                    reason,
+                }),
+                Ok(StepOutcome::Restarting) => {
+                    self.emit(ProvisioningEvent::Restarting { step: index });
+                    return Ok(ProvisioningOutcome::Restarting);
+                }
+                Err(e) => {
+                    warn!("Provisioning step {step:?} failed: {e:?}");
+                    self.emit(ProvisioningEvent::Failed {
+                        step: index,
+                        error: e.to_string(),
+                    });
+                    return Err(e);
+                }
+            }
+            state.next_step += 1;
+        }
+        Ok(ProvisioningOutcome::Complete)
+    }
+
+    async fn run_step(
+        &self,
+        index: usize,
+        step: &Step,
+        provider: &dyn IdeviceProvider,
+        state: &mut ProvisioningState,
+    ) -> Result<StepOutcome, IdeviceError> {
+        match step {
+            Step::Pair => self.pair(provider).await,
+            Step::EnableDeveloperMode => enable_developer_mode(provider, state).await,
+            Step::InstallProfile { path } => {
+                let profile = tokio::fs::read(path).await?;
+                let mut mcinstall = McInstallClient::connect(provider).await?;
+                mcinstall.install_profile(&profile).await?;
+                Ok(StepOutcome::Done)
+            }
+            Step::InstallApp { path } => {
+                let ipa = tokio::fs::read(path).await?;
+                let afc = AfcClient::new(provider.start_service_raw("com.apple.afc").await?);
+                let instproxy = InstallationProxyClient::connect(provider).await?;
+                InstallPipeline::new(afc, instproxy)
+                    .install(
+                        ipa,
+                        None,
+                        |(stage, step): (InstallStage, usize)| {
+                            if let InstallStage::Uploading { sent, total } = stage {
+                                self.emit(ProvisioningEvent::Progress {
+                                    step,
+                                    done: sent as u64,
+                                    total: total as u64,
+                                });
+                            }
+                            async {}
+                        },
+                        index,
+                    )
+                    .await?;
+                Ok(StepOutcome::Done)
+            }
+            Step::SetDeviceName { name } => {
+                let mut lockdown = session(provider).await?;
+                if lockdown.device_name().await? == *name {
+                    return Ok(StepOutcome::Skipped("the device already has this name"));
+                }
+                lockdown.set_device_name(name).await?;
+                Ok(StepOutcome::Done)
+            }
+            Step::SetLanguage { language, locale } => {
+                let mut lockdown = session(provider).await?;
+                let mut changed = lockdown.set_language(language).await?;
+                if let Some(locale) = locale {
+                    changed |= lockdown.set_locale(locale).await?;
+                }
+                if !changed {
+                    return Ok(StepOutcome::Skipped(
+                        "the device already uses this language",
+                    ));
+                }
+                provider.restart().await?;
+                Ok(StepOutcome::Restarting)
+            }
+            Step::MountDeveloperImage { repository } => {
+                ensure_developer_image(provider, Some(&DdiRepository::new(repository))).await?;
+                Ok(StepOutcome::Done)
+            }
+        }
+    }
+
+    async fn pair(&self, provider: &dyn IdeviceProvider) -> Result<StepOutcome, IdeviceError> {
+        if let Ok(pairing_file) = provider.get_pairing_file().await {
+            let mut lockdown = LockdowndClient::connect(provider).await?;
+            if lockdown.start_session(&pairing_file).await.is_ok() {
+                return Ok(StepOutcome::Skipped("the device already trusts this host"));
+            }
+        }
+
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        let udid = match lockdown.get_value("UniqueDeviceID").await?.as_string() {
+            Some(u) => u.to_string(),
+            None => return Err(IdeviceError::UnexpectedResponse),
+        };
+        let key_store = match &self.key_store {
+            Some(k) => k,
+            None => {
+                warn!("Can't pair {udid} without a key store to save the record to");
+                return Err(IdeviceError::PairingRecordNotFound(udid));
+            }
+        };
+        // A fresh identity, since the provider's usbmuxd may not be around to ask for its BUID
+        let pairing_file = lockdown
+            .pair(
+                uuid::Uuid::new_v4().to_string().to_uppercase(),
+                uuid::Uuid::new_v4().to_string().to_uppercase(),
+            )
+            .await?;
+        key_store.save_pairing_file(&udid, &pairing_file).await?;
+        Ok(StepOutcome::Done)
+    }
+}
+
+async fn session(provider: &dyn IdeviceProvider) -> Result<LockdowndClient, IdeviceError> {
+    let mut lockdown = LockdowndClient::connect(provider).await?;
+    lockdown
+        .start_session(&provider.get_pairing_file().await?)
+        .await?;
+    Ok(lockdown)
+}
+
+async fn enable_developer_mode(
+    provider: &dyn IdeviceProvider,
+    state: &mut ProvisioningState,
+) -> Result<StepOutcome, IdeviceError> {
+    if developer_mode_status(&mut session(provider).await?).await? {
+        state.developer_mode_armed = false;
+        return Ok(StepOutcome::Skipped("Developer Mode is already on"));
+    }
+
+    let mut amfi = AmfiClient::connect(provider).await?;
+    if state.developer_mode_armed {
+        amfi.accept_developer_mode().await?;
+        state.developer_mode_armed = false;
+        return Ok(StepOutcome::Done);
+    }
+
+    amfi.arm_developer_mode().await?;
+    state.developer_mode_armed = true;
+
+    provider.restart().await?;
+    Ok(StepOutcome::Restarting)
+}
@@ -0,0 +1,586 @@
+// Jackson Coxson
+// Records device sessions to a file and serves them back.
+// RecordingProvider wraps another provider and tees every connection into a Recorder,
+// logging each message as it goes. ReplayProvider answers with a recording instead of a device.
+// Recordings are taken above TLS, so they hold each service's plaintext.
+
+pub mod pcapng;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    io::Write,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use log::{debug, log_enabled, warn, Level};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{
+    codec::Codec, pairing_file::PairingFile, pretty_print_plist, provider::IdeviceProvider,
+    Idevice, IdeviceError, ReadWrite,
+};
+
+/// The first bytes of a recording file
+pub const RECORDING_MAGIC: &[u8; 8] = b"IDVREC01";
+
+/// How much unparsed traffic is kept for logging before it's given up on
+const LOG_BUFFER_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Host to device
+    Sent,
+    /// Device to host
+    Received,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => "Sent",
+            Direction::Received => "Received",
+        }
+    }
+}
+
+/// A chunk of traffic on one connection, as it was read or written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedPacket {
+    /// Numbered in the order the connections were made
+    pub connection: u64,
+    pub port: u16,
+    pub direction: Direction,
+    /// Time since the recording started
+    pub time: Duration,
+    pub data: Vec<u8>,
+}
+
+impl RecordedPacket {
+    fn to_plist(&self) -> plist::Value {
+        let mut p = plist::Dictionary::new();
+        p.insert("Connection".into(), self.connection.into());
+        p.insert("Port".into(), (self.port as u64).into());
+        p.insert("Direction".into(), self.direction.as_str().into());
+        p.insert("Time".into(), (self.time.as_nanos() as u64).into());
+        p.insert("Data".into(), plist::Value::Data(self.data.clone()));
+        plist::Value::Dictionary(p)
+    }
+
+    fn from_plist(p: &plist::Value) -> Option<Self> {
+        let p = p.as_dictionary()?;
+        Some(Self {
+            connection: p.get("Connection")?.as_unsigned_integer()?,
+            port: p.get("Port")?.as_unsigned_integer()? as u16,
+            direction: match p.get("Direction")?.as_string()? {
+                "Sent" => Direction::Sent,
+                "Received" => Direction::Received,
+                _ => return None,
+            },
+            time: Duration::from_nanos(p.get("Time")?.as_unsigned_integer()?),
+            data: p.get("Data")?.as_data()?.to_vec(),
+        })
+    }
+}
+
+/// Parses a recording file.
+/// A partial packet at the end, from a recorder that didn't exit cleanly, is dropped.
+pub fn read_recording(data: &[u8]) -> Result<Vec<RecordedPacket>, IdeviceError> {
+    if !data.starts_with(RECORDING_MAGIC) {
+        warn!("Recording doesn't start with the recording magic");
+        return Err(IdeviceError::UnexpectedResponse);
+    }
+
+    let mut buf = BytesMut::from(&data[RECORDING_MAGIC.len()..]);
+    let mut res = Vec::new();
+    while let Some(p) = plist::Value::decode(&mut buf)? {
+        match RecordedPacket::from_plist(&p) {
+            Some(p) => res.push(p),
+            None => {
+                warn!("Invalid packet in recording: {}", pretty_print_plist(&p));
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        }
+    }
+    if !buf.is_empty() {
+        warn!("Dropping {} bytes of a truncated packet", buf.len());
+    }
+    Ok(res)
+}
+
+/// Writes traffic to a recording. Clones write to the same recording.
+#[derive(Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    start: Instant,
+    next_connection: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+impl Recorder {
+    pub fn new(mut writer: impl Write + Send + 'static) -> Result<Self, IdeviceError> {
+        writer.write_all(RECORDING_MAGIC)?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            start: Instant::now(),
+            next_connection: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Starts a recording at a file path, replacing anything there
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, IdeviceError> {
+        Self::new(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    fn connection(&self, port: u16) -> RecordedConnection {
+        RecordedConnection {
+            recorder: self.clone(),
+            id: self.next_connection.fetch_add(1, Ordering::Relaxed),
+            port,
+            active_layer: Arc::new(AtomicUsize::new(0)),
+            next_layer: Arc::new(AtomicUsize::new(0)),
+            sent: Arc::new(Mutex::new(BytesMut::new())),
+            received: Arc::new(Mutex::new(BytesMut::new())),
+        }
+    }
+
+    /// Failing to record doesn't fail the connection being recorded
+    fn record(&self, packet: &RecordedPacket) {
+        let frame = match packet.to_plist().encode() {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("Unable to encode recorded packet: {e:?}");
+                return;
+            }
+        };
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writer.write_all(&frame).and_then(|_| writer.flush()) {
+            warn!("Unable to write to recording: {e:?}");
+        }
+    }
+}
+
+/// How a connection is tapped by the proxy
+#[derive(Debug, Clone)]
+pub(crate) enum Tap {
+    Record(RecordedConnection),
+    /// The peer is a recording, so there's no TLS handshake to do
+    Replay,
+}
+
+/// One recorded connection.
+/// Starting TLS wraps the socket again, and only the newest layer is recorded so the
+/// handshake and ciphertext stay out of the recording.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedConnection {
+    recorder: Recorder,
+    id: u64,
+    port: u16,
+    active_layer: Arc<AtomicUsize>,
+    next_layer: Arc<AtomicUsize>,
+    // Partial messages, kept for logging
+    sent: Arc<Mutex<BytesMut>>,
+    received: Arc<Mutex<BytesMut>>,
+}
+
+impl RecordedConnection {
+    pub(crate) fn wrap(&self, inner: Box<dyn ReadWrite>) -> RecordingStream {
+        let layer = self.next_layer.fetch_add(1, Ordering::Relaxed) + 1;
+        self.active_layer.store(layer, Ordering::Relaxed);
+        RecordingStream {
+            inner,
+            connection: self.clone(),
+            layer,
+        }
+    }
+
+    /// Stops recording until the next ``wrap``
+    pub(crate) fn pause(&self) {
+        self.active_layer.store(0, Ordering::Relaxed);
+    }
+
+    fn record(&self, layer: usize, direction: Direction, data: &[u8]) {
+        if data.is_empty() || self.active_layer.load(Ordering::Relaxed) != layer {
+            return;
+        }
+        if log_enabled!(Level::Debug) {
+            let buf = match direction {
+                Direction::Sent => &self.sent,
+                Direction::Received => &self.received,
+            };
+            log_messages(self.port, direction, &mut buf.lock().unwrap(), data);
+        }
+        self.recorder.record(&RecordedPacket {
+            connection: self.id,
+            port: self.port,
+            direction,
+            time: self.recorder.start.elapsed(),
+            data: data.to_vec(),
+        });
+    }
+}
+
+/// Logs each whole message in the buffer, guessing the framing from the first bytes
+fn log_messages(port: u16, direction: Direction, buf: &mut BytesMut, data: &[u8]) {
+    buf.extend_from_slice(data);
+    loop {
+        #[cfg(feature = "afc")]
+        if buf.starts_with(crate::afc::AFC_MAGIC) {
+            match crate::afc::AfcPacket::decode(buf) {
+                Ok(Some(p)) => {
+                    debug!(
+                        "[{port}] {} AFC {} with {} bytes",
+                        direction.as_str(),
+                        p.operation_name(),
+                        p.data.len()
+                    );
+                    continue;
+                }
+                Ok(None) => return,
+                Err(_) => {}
+            }
+        }
+
+        #[cfg(feature = "dvt")]
+        if buf.starts_with(&0x1F3D5B79_u32.to_le_bytes()) {
+            match crate::dvt::message::Fragment::decode(buf) {
+                Ok(Some(f)) if f.header.fragment_count() > 1 => {
+                    debug!(
+                        "[{port}] {} DTX fragment {}/{} of message {} with {} bytes",
+                        direction.as_str(),
+                        f.header.fragment_id() + 1,
+                        f.header.fragment_count(),
+                        f.header.identifier(),
+                        f.body.len()
+                    );
+                    continue;
+                }
+                Ok(Some(f)) => {
+                    match crate::dvt::message::Fragment::reassemble(f.header, &f.body) {
+                        Ok(m) => debug!("[{port}] {} DTX {m:#?}", direction.as_str()),
+                        Err(e) => debug!(
+                            "[{port}] {} DTX message that didn't parse: {e:?}",
+                            direction.as_str()
+                        ),
+                    }
+                    continue;
+                }
+                Ok(None) => return,
+                Err(_) => {}
+            }
+        }
+
+        match plist::Value::decode(buf) {
+            Ok(Some(p)) => {
+                debug!(
+                    "[{port}] {} plist: {}",
+                    direction.as_str(),
+                    pretty_print_plist(&p)
+                );
+            }
+            Ok(None) if buf.len() <= LOG_BUFFER_LIMIT => return,
+            _ => {
+                debug!(
+                    "[{port}] {} {} bytes without known framing",
+                    direction.as_str(),
+                    buf.len()
+                );
+                buf.clear();
+                return;
+            }
+        }
+    }
+}
+
+/// A socket that copies everything through it into a recording
+#[derive(Debug)]
+pub(crate) struct RecordingStream {
+    inner: Box<dyn ReadWrite>,
+    connection: RecordedConnection,
+    layer: usize,
+}
+
+impl AsyncRead for RecordingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            self.connection
+                .record(self.layer, Direction::Received, &buf.filled()[before..]);
+        }
+        res
+    }
+}
+
+impl AsyncWrite for RecordingStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.connection
+                .record(self.layer, Direction::Sent, &buf[..n]);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Records every connection made through another provider
+#[derive(Debug)]
+pub struct RecordingProvider<P: IdeviceProvider> {
+    pub inner: P,
+    pub recorder: Recorder,
+}
+
+impl<P: IdeviceProvider> RecordingProvider<P> {
+    pub fn new(inner: P, recorder: Recorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl<P: IdeviceProvider> IdeviceProvider for RecordingProvider<P> {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let connect = self.inner.connect(port);
+        let recorder = self.recorder.clone();
+        Box::pin(async move {
+            let mut idevice = connect.await?;
+            idevice.set_tap(Tap::Record(recorder.connection(port)));
+            Ok(idevice)
+        })
+    }
+
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        self.inner.get_pairing_file()
+    }
+}
+
+/// Serves a recording in place of a device.
+/// Each connection to a port gets the next recorded connection to that port. What the client
+/// sends is checked against the recording, and differences are logged rather than fatal.
+#[derive(Debug)]
+pub struct ReplayProvider {
+    label: String,
+    /// The pairing file the recording was made with, so session requests match it
+    pairing_file: PairingFile,
+    connections: Mutex<HashMap<u16, VecDeque<Vec<RecordedPacket>>>>,
+}
+
+impl ReplayProvider {
+    pub fn new(
+        recording: Vec<RecordedPacket>,
+        pairing_file: PairingFile,
+        label: impl Into<String>,
+    ) -> Self {
+        let mut by_id: Vec<(u64, u16, Vec<RecordedPacket>)> = Vec::new();
+        for packet in recording {
+            match by_id.iter_mut().find(|(id, _, _)| *id == packet.connection) {
+                Some((_, _, packets)) => packets.push(packet),
+                None => by_id.push((packet.connection, packet.port, vec![packet])),
+            }
+        }
+        by_id.sort_by_key(|(id, _, _)| *id);
+
+        let mut connections: HashMap<u16, VecDeque<Vec<RecordedPacket>>> = HashMap::new();
+        for (_, port, packets) in by_id {
+            connections.entry(port).or_default().push_back(packets);
+        }
+
+        Self {
+            label: label.into(),
+            pairing_file,
+            connections: Mutex::new(connections),
+        }
+    }
+
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        pairing_file: PairingFile,
+        label: impl Into<String>,
+    ) -> Result<Self, IdeviceError> {
+        let recording = read_recording(&std::fs::read(path)?)?;
+        Ok(Self::new(recording, pairing_file, label))
+    }
+}
+
+impl IdeviceProvider for ReplayProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let packets = self
+            .connections
+            .lock()
+            .unwrap()
+            .get_mut(&port)
+            .and_then(|c| c.pop_front());
+        let label = self.label.clone();
+
+        Box::pin(async move {
+            let packets = match packets {
+                Some(p) => p,
+                None => {
+                    warn!("No recorded connections left for port {port}");
+                    return Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into());
+                }
+            };
+
+            let (host, mut device) = tokio::io::duplex(1024 * 1024);
+            crate::runtime::runtime().spawn(Box::pin(async move {
+                if let Err(e) = replay(&mut device, port, packets).await {
+                    warn!("Replay of port {port} stopped: {e:?}");
+                }
+            }));
+
+            let mut idevice = Idevice::new(Box::new(host), label);
+            idevice.set_tap(Tap::Replay);
+            Ok(idevice)
+        })
+    }
+
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        let pairing_file = self.pairing_file.clone();
+        Box::pin(async move { Ok(pairing_file) })
+    }
+}
+
+async fn replay(
+    device: &mut tokio::io::DuplexStream,
+    port: u16,
+    packets: Vec<RecordedPacket>,
+) -> Result<(), IdeviceError> {
+    for packet in packets {
+        match packet.direction {
+            Direction::Sent => {
+                let mut buf = vec![0; packet.data.len()];
+                device.read_exact(&mut buf).await?;
+                if buf != packet.data {
+                    warn!(
+                        "[{port}] Client sent different bytes than the recording at {:?}",
+                        packet.time
+                    );
+                }
+            }
+            Direction::Received => device.write_all(&packet.data).await?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_round_trip() {
+        let packets = vec![
+            RecordedPacket {
+                connection: 0,
+                port: 62078,
+                direction: Direction::Sent,
+                time: Duration::from_millis(1),
+                data: vec![0, 0, 0, 1, b'a'],
+            },
+            RecordedPacket {
+                connection: 0,
+                port: 62078,
+                direction: Direction::Received,
+                time: Duration::from_millis(2),
+                data: vec![],
+            },
+        ];
+
+        let mut file = RECORDING_MAGIC.to_vec();
+        for p in &packets {
+            file.extend_from_slice(&p.to_plist().encode().unwrap());
+        }
+        // A partial packet from an interrupted recording
+        file.extend_from_slice(&[0, 0, 1]);
+
+        assert_eq!(read_recording(&file).unwrap(), packets);
+        assert!(read_recording(b"not a recording").is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn record_then_replay() {
+        use crate::{lockdownd::LockdowndClient, testing::MockProvider, IdeviceService};
+
+        #[derive(Clone)]
+        struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mock = MockProvider::new("test").unwrap();
+        let pairing_file = mock.pairing_file().clone();
+        let file = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+        let provider = RecordingProvider::new(mock, Recorder::new(file.clone()).unwrap());
+
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+        lockdown.start_session(&pairing_file).await.unwrap();
+        let recorded = lockdown.get_value("ProductVersion").await.unwrap();
+        drop(lockdown);
+
+        let recording = read_recording(&file.0.lock().unwrap()).unwrap();
+        assert!(recording.iter().all(|p| p.port == 62078));
+        let provider = ReplayProvider::new(recording, pairing_file.clone(), "replay");
+
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+        lockdown.start_session(&pairing_file).await.unwrap();
+        assert_eq!(
+            lockdown.get_value("ProductVersion").await.unwrap(),
+            recorded
+        );
+        assert!(LockdowndClient::connect(&provider).await.is_err());
+    }
+}
@@ -0,0 +1,390 @@
+// Jackson Coxson
+// Exports recordings as pcapng so they can be opened in Wireshark next to USB captures.
+// Each connection is written as a TCP stream between a made up host and device address,
+// so Follow TCP Stream and Decode As work on it. Every service gets its own interface,
+// named from the StartService requests found in the lockdown traffic.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::Write,
+    net::Ipv4Addr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BytesMut;
+use log::warn;
+
+use super::{Direction, RecordedPacket};
+use crate::{codec::Codec, lockdownd::LockdowndClient, IdeviceError};
+
+/// Address used for the host side of every connection
+pub const HOST_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+/// Address used for the device side of every connection
+pub const DEVICE_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+const LINKTYPE_IPV4: u16 = 228;
+const MAX_SEGMENT: usize = u16::MAX as usize - 40;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Writes a recording as a pcapng capture.
+/// Recordings only keep time relative to their start, so ``start`` is when the recording began.
+pub fn write_pcapng(
+    packets: &[RecordedPacket],
+    start: SystemTime,
+    writer: &mut impl Write,
+) -> Result<(), IdeviceError> {
+    let start = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let names = service_names(packets);
+
+    let mut shb = Vec::new();
+    shb.extend_from_slice(&0x1A2B3C4D_u32.to_le_bytes());
+    shb.extend_from_slice(&1_u16.to_le_bytes());
+    shb.extend_from_slice(&0_u16.to_le_bytes());
+    shb.extend_from_slice(&(-1_i64).to_le_bytes());
+    push_option(&mut shb, 4, b"idevice"); // shb_userappl
+    push_option(&mut shb, 0, &[]);
+    write_block(writer, 0x0A0D0D0A, &shb)?;
+
+    let mut interfaces: HashMap<u16, u32> = HashMap::new();
+    let mut connections: HashMap<u64, Connection> = HashMap::new();
+    for packet in packets {
+        let interface = match interfaces.get(&packet.port) {
+            Some(i) => *i,
+            None => {
+                let i = interfaces.len() as u32;
+                let name = names.get(&packet.port).map(String::as_str);
+                write_interface(writer, packet.port, name)?;
+                interfaces.insert(packet.port, i);
+                i
+            }
+        };
+        let time = start + packet.time;
+
+        let connection = match connections.entry(packet.connection) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let connection = e.insert(Connection {
+                    host_port: 49152 + (packet.connection % 16384) as u16,
+                    device_port: packet.port,
+                    host_seq: 0,
+                    device_seq: 0,
+                });
+                connection.handshake(writer, interface, time)?;
+                connection
+            }
+        };
+
+        for chunk in packet.data.chunks(MAX_SEGMENT) {
+            let segment = connection.segment(packet.direction, TCP_PSH | TCP_ACK, chunk);
+            write_packet(writer, interface, time, packet.direction, &segment)?;
+            match packet.direction {
+                Direction::Sent => {
+                    connection.host_seq = connection.host_seq.wrapping_add(chunk.len() as u32)
+                }
+                Direction::Received => {
+                    connection.device_seq = connection.device_seq.wrapping_add(chunk.len() as u32)
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// One recorded connection, as a TCP stream
+struct Connection {
+    host_port: u16,
+    device_port: u16,
+    host_seq: u32,
+    device_seq: u32,
+}
+
+impl Connection {
+    /// Writes a three way handshake, since Wireshark only labels streams it saw open
+    fn handshake(
+        &mut self,
+        writer: &mut impl Write,
+        interface: u32,
+        time: Duration,
+    ) -> Result<(), IdeviceError> {
+        let syn = self.segment(Direction::Sent, TCP_SYN, &[]);
+        write_packet(writer, interface, time, Direction::Sent, &syn)?;
+        self.host_seq = 1;
+
+        let syn_ack = self.segment(Direction::Received, TCP_SYN | TCP_ACK, &[]);
+        write_packet(writer, interface, time, Direction::Received, &syn_ack)?;
+        self.device_seq = 1;
+
+        let ack = self.segment(Direction::Sent, TCP_ACK, &[]);
+        write_packet(writer, interface, time, Direction::Sent, &ack)
+    }
+
+    /// Builds an IPv4 packet holding one TCP segment
+    fn segment(&self, direction: Direction, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (source, destination, source_port, destination_port, seq, ack) = match direction {
+            Direction::Sent => (
+                HOST_ADDR,
+                DEVICE_ADDR,
+                self.host_port,
+                self.device_port,
+                self.host_seq,
+                self.device_seq,
+            ),
+            Direction::Received => (
+                DEVICE_ADDR,
+                HOST_ADDR,
+                self.device_port,
+                self.host_port,
+                self.device_seq,
+                self.host_seq,
+            ),
+        };
+        let ack = if flags & TCP_ACK == 0 { 0 } else { ack };
+
+        let mut tcp = Vec::with_capacity(20 + payload.len());
+        tcp.extend_from_slice(&source_port.to_be_bytes());
+        tcp.extend_from_slice(&destination_port.to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.push(5 << 4); // header length in words
+        tcp.push(flags);
+        tcp.extend_from_slice(&u16::MAX.to_be_bytes()); // window
+        tcp.extend_from_slice(&[0, 0, 0, 0]); // checksum and urgent pointer
+        tcp.extend_from_slice(payload);
+
+        let mut pseudo_header = Vec::with_capacity(12);
+        pseudo_header.extend_from_slice(&source.octets());
+        pseudo_header.extend_from_slice(&destination.octets());
+        pseudo_header.extend_from_slice(&[0, 6]);
+        pseudo_header.extend_from_slice(&(tcp.len() as u16).to_be_bytes());
+        let tcp_checksum = checksum(&[&pseudo_header, &tcp]);
+        tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+
+        let mut ip = Vec::with_capacity(20 + tcp.len());
+        ip.push(0x45); // version 4, 5 words of header
+        ip.push(0);
+        ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0x40, 0]); // id, don't fragment
+        ip.push(64); // ttl
+        ip.push(6); // tcp
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(&source.octets());
+        ip.extend_from_slice(&destination.octets());
+        let ip_checksum = checksum(&[&ip]);
+        ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+        ip.extend_from_slice(&tcp);
+        ip
+    }
+}
+
+/// The internet checksum. Only the last part may have an odd length.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0_u32;
+    for part in parts {
+        for word in part.chunks(2) {
+            sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+        }
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Finds which service was started on each port from the lockdown traffic
+fn service_names(packets: &[RecordedPacket]) -> HashMap<u16, String> {
+    let mut names = HashMap::new();
+    names.insert(LockdowndClient::LOCKDOWND_PORT, "lockdownd".to_string());
+
+    let mut buffers: HashMap<(u64, Direction), BytesMut> = HashMap::new();
+    let mut requested: HashMap<u64, String> = HashMap::new();
+    for packet in packets
+        .iter()
+        .filter(|p| p.port == LockdowndClient::LOCKDOWND_PORT)
+    {
+        let buf = buffers
+            .entry((packet.connection, packet.direction))
+            .or_default();
+        buf.extend_from_slice(&packet.data);
+
+        loop {
+            let message = match plist::Value::decode(buf) {
+                Ok(Some(m)) => m,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Unable to parse lockdown traffic for service names: {e:?}");
+                    buf.clear();
+                    break;
+                }
+            };
+            let Some(message) = message.as_dictionary() else {
+                continue;
+            };
+            match packet.direction {
+                Direction::Sent => {
+                    if message.get("Request").and_then(|r| r.as_string()) == Some("StartService") {
+                        if let Some(service) = message.get("Service").and_then(|s| s.as_string()) {
+                            requested.insert(packet.connection, service.to_string());
+                        }
+                    }
+                }
+                Direction::Received => {
+                    let Some(port) = message.get("Port").and_then(|p| p.as_unsigned_integer())
+                    else {
+                        continue;
+                    };
+                    if let Some(service) = requested.remove(&packet.connection) {
+                        names.insert(port as u16, service);
+                    }
+                }
+            }
+        }
+    }
+    names
+}
+
+fn write_interface(
+    writer: &mut impl Write,
+    port: u16,
+    service: Option<&str>,
+) -> Result<(), IdeviceError> {
+    let name = match service {
+        Some(s) => format!("{s}:{port}"),
+        None => format!("port {port}"),
+    };
+
+    let mut idb = Vec::new();
+    idb.extend_from_slice(&LINKTYPE_IPV4.to_le_bytes());
+    idb.extend_from_slice(&0_u16.to_le_bytes());
+    idb.extend_from_slice(&0_u32.to_le_bytes()); // no snap length
+    push_option(&mut idb, 2, name.as_bytes()); // if_name
+    push_option(&mut idb, 9, &[9]); // if_tsresol, nanoseconds
+    push_option(&mut idb, 0, &[]);
+    write_block(writer, 1, &idb)
+}
+
+fn write_packet(
+    writer: &mut impl Write,
+    interface: u32,
+    time: Duration,
+    direction: Direction,
+    data: &[u8],
+) -> Result<(), IdeviceError> {
+    let time = time.as_nanos() as u64;
+    let flags: u32 = match direction {
+        Direction::Received => 1, // inbound
+        Direction::Sent => 2,     // outbound
+    };
+
+    let mut epb = Vec::with_capacity(20 + data.len() + 16);
+    epb.extend_from_slice(&interface.to_le_bytes());
+    epb.extend_from_slice(&((time >> 32) as u32).to_le_bytes());
+    epb.extend_from_slice(&(time as u32).to_le_bytes());
+    epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    epb.extend_from_slice(data);
+    pad(&mut epb);
+    push_option(&mut epb, 2, &flags.to_le_bytes()); // epb_flags
+    push_option(&mut epb, 0, &[]);
+    write_block(writer, 6, &epb)
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> Result<(), IdeviceError> {
+    let len = (12 + body.len()) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_le_bytes())?;
+    Ok(())
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plist_frame(dict: &[(&str, plist::Value)]) -> Vec<u8> {
+        let dict: plist::Dictionary = dict
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+        plist::Value::Dictionary(dict).encode().unwrap().to_vec()
+    }
+
+    fn blocks(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut res = Vec::new();
+        while !data.is_empty() {
+            let block_type = u32::from_le_bytes(data[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(data[len - 4..len], data[4..8]);
+            res.push((block_type, &data[8..len - 4]));
+            data = &data[len..];
+        }
+        res
+    }
+
+    #[test]
+    fn pcapng_export() {
+        let packet = |connection, port, direction, data| RecordedPacket {
+            connection,
+            port,
+            direction,
+            time: Duration::from_millis(connection * 10),
+            data,
+        };
+        let packets = vec![
+            packet(
+                0,
+                LockdowndClient::LOCKDOWND_PORT,
+                Direction::Sent,
+                plist_frame(&[
+                    ("Request", "StartService".into()),
+                    ("Service", "com.apple.afc".into()),
+                ]),
+            ),
+            packet(
+                0,
+                LockdowndClient::LOCKDOWND_PORT,
+                Direction::Received,
+                plist_frame(&[("Port", 49200_u64.into())]),
+            ),
+            packet(1, 49200, Direction::Sent, b"CFA6LPAA".to_vec()),
+            packet(1, 49200, Direction::Received, vec![1; 3]),
+        ];
+
+        let mut out = Vec::new();
+        write_pcapng(&packets, UNIX_EPOCH, &mut out).unwrap();
+        let blocks = blocks(&out);
+
+        // Section, then an interface per service followed by its handshake and messages
+        let types: Vec<u32> = blocks.iter().map(|b| b.0).collect();
+        assert_eq!(types, [0x0A0D0D0A, 1, 6, 6, 6, 6, 6, 1, 6, 6, 6, 6, 6]);
+        assert!(blocks[7].1.windows(19).any(|w| w == b"com.apple.afc:49200"));
+
+        // The last packet is the device's 3 bytes, after the host's 8
+        let epb = blocks[12].1;
+        let ip = &epb[20..20 + 43];
+        assert_eq!(checksum(&[&ip[..20]]), 0);
+        assert_eq!(&ip[12..16], &DEVICE_ADDR.octets());
+        let tcp = &ip[20..];
+        assert_eq!(u16::from_be_bytes([tcp[0], tcp[1]]), 49200);
+        assert_eq!(u32::from_be_bytes(tcp[4..8].try_into().unwrap()), 1);
+        assert_eq!(u32::from_be_bytes(tcp[8..12].try_into().unwrap()), 9);
+        assert_eq!(&tcp[20..], &[1, 1, 1]);
+    }
+}
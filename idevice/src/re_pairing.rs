@@ -0,0 +1,269 @@
+// Jackson Coxson
+// Recovering when a device forgets this host.
+// Tapping "Forget this computer", resetting Location & Privacy or restoring the device throws
+// its copy of the pairing record away, and from then on lockdown answers InvalidHostID. A
+// daemon that's left running would fail every request until someone pairs by hand.
+// RePairPolicy pairs again under the same host identity and saves the new record, or just
+// reports that the device has to be paired. Every service, notification_proxy included, needs
+// a trusted session, so nothing is posted when this happens and waiting for the user to trust
+// the host again has to poll.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    key_store::KeyStore, lockdownd::LockdowndClient, pairing_file::PairingFile,
+    provider::IdeviceProvider, IdeviceError, IdeviceService,
+};
+
+/// Whether an error means the device no longer accepts the pairing record
+pub fn is_pairing_rejected(e: &IdeviceError) -> bool {
+    matches!(
+        e,
+        IdeviceError::InvalidHostID | IdeviceError::InvalidPairRecord
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RePairEvent {
+    /// The device with this UDID rejected the pairing record. Without a key store to pair
+    /// into, tools should ask the user to pair again.
+    RepairRequired(String),
+    /// The trust prompt is up, or the device has to be unlocked to show it
+    WaitingForTrust(String),
+    /// A new record was saved to the key store
+    Repaired(String),
+}
+
+type EventCallback = Arc<dyn Fn(RePairEvent) + Send + Sync>;
+
+/// What to do when a device rejects the pairing record
+#[derive(Clone)]
+pub struct RePairPolicy {
+    key_store: Option<Arc<dyn KeyStore>>,
+    poll_interval: Duration,
+    timeout: Option<Duration>,
+    on_event: Option<EventCallback>,
+}
+
+impl Default for RePairPolicy {
+    fn default() -> Self {
+        Self {
+            key_store: None,
+            poll_interval: Duration::from_secs(2),
+            timeout: None,
+            on_event: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for RePairPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RePairPolicy")
+            .field("key_store", &self.key_store)
+            .field("poll_interval", &self.poll_interval)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl RePairPolicy {
+    /// Only reports ``RepairRequired``, returning the error
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pairs again and saves the record here. Give the provider a ``KeyStoreProvider`` over
+    /// the same store so the new record is used.
+    pub fn with_key_store(mut self, key_store: Arc<dyn KeyStore>) -> Self {
+        self.key_store = Some(key_store);
+        self
+    }
+
+    /// How often to ask again while the trust prompt is waiting for the user
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Gives up waiting for the user to trust the host after this long
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Called with each event, such as to tell the user to pair or to answer the prompt
+    pub fn with_events(mut self, on_event: impl Fn(RePairEvent) + Send + Sync + 'static) -> Self {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+
+    fn emit(&self, event: RePairEvent) {
+        debug!("{event:?}");
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Connects to a service, pairing again first if the record was rejected
+    pub async fn connect<S: IdeviceService>(
+        &self,
+        provider: &dyn IdeviceProvider,
+    ) -> Result<S, IdeviceError> {
+        self.run(provider, || S::connect(provider)).await
+    }
+
+    /// Runs something that starts a session, such as a client's own connect. If the device
+    /// rejects the pairing record, it's paired again and the operation is run once more.
+    pub async fn run<T, F, Fut>(
+        &self,
+        provider: &dyn IdeviceProvider,
+        mut op: F,
+    ) -> Result<T, IdeviceError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, IdeviceError>>,
+    {
+        let e = match op().await {
+            Err(e) if is_pairing_rejected(&e) => e,
+            r => return r,
+        };
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        let udid = match lockdown.get_value("UniqueDeviceID").await?.as_string() {
+            Some(u) => u.to_string(),
+            None => return Err(IdeviceError::UnexpectedResponse),
+        };
+        warn!("{udid} rejected the pairing record: {e:?}");
+        self.emit(RePairEvent::RepairRequired(udid.clone()));
+
+        let key_store = match &self.key_store {
+            Some(k) => k,
+            None => return Err(e),
+        };
+        let previous = provider.get_pairing_file().await?;
+        let pairing_file = self.pair(provider, lockdown, &udid, &previous).await?;
+        key_store.save_pairing_file(&udid, &pairing_file).await?;
+        self.emit(RePairEvent::Repaired(udid));
+
+        op().await
+    }
+
+    /// Pairs under the rejected record's host ID and BUID, so the device sees the same host
+    async fn pair(
+        &self,
+        provider: &dyn IdeviceProvider,
+        mut lockdown: LockdowndClient,
+        udid: &str,
+        previous: &PairingFile,
+    ) -> Result<PairingFile, IdeviceError> {
+        let started = Instant::now();
+        let mut waiting = false;
+        loop {
+            let e = match lockdown
+                .pair(previous.host_id(), previous.system_buid())
+                .await
+            {
+                Err(
+                    e @ (IdeviceError::PairingDialogResponsePending
+                    | IdeviceError::PasswordProtected),
+                ) => e,
+                r => return r,
+            };
+            if !waiting {
+                waiting = true;
+                self.emit(RePairEvent::WaitingForTrust(udid.to_string()));
+            }
+
+            let mut wait = self.poll_interval;
+            if let Some(timeout) = self.timeout {
+                let left = timeout.saturating_sub(started.elapsed());
+                if left.is_zero() {
+                    return Err(e);
+                }
+                wait = wait.min(left);
+            }
+            crate::runtime::sleep(wait).await;
+            lockdown = LockdowndClient::connect(provider).await?;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        key_store::{FsKeyStore, RecordKind},
+        provider::KeyStoreProvider,
+        testing::{MockProvider, MOCK_UDID},
+    };
+
+    #[tokio::test]
+    async fn pairs_again_when_forgotten() {
+        let dir = std::env::temp_dir().join(format!("idevice-re-pair-{}", std::process::id()));
+        let store: Arc<dyn KeyStore> = Arc::new(FsKeyStore::new(&dir));
+        let mock = MockProvider::new("test").unwrap();
+        let previous = mock.pairing_file().clone();
+        store.save_pairing_file(MOCK_UDID, &previous).await.unwrap();
+        mock.set_trusted(false);
+        let provider = KeyStoreProvider {
+            provider: mock,
+            store: store.clone(),
+            udid: MOCK_UDID.into(),
+        };
+
+        let session = || async {
+            let mut lockdown = LockdowndClient::connect(&provider).await?;
+            lockdown
+                .start_session(&provider.get_pairing_file().await?)
+                .await
+        };
+        assert!(matches!(session().await, Err(IdeviceError::InvalidHostID)));
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let notify = {
+            let events = events.clone();
+            RePairPolicy::new().with_events(move |event| events.lock().unwrap().push(event))
+        };
+        assert!(matches!(
+            notify.run(&provider, session).await,
+            Err(IdeviceError::InvalidHostID)
+        ));
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            [RePairEvent::RepairRequired(MOCK_UDID.to_string())]
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let policy = {
+            let events = events.clone();
+            RePairPolicy::new()
+                .with_key_store(store.clone())
+                .with_events(move |event| events.lock().unwrap().push(event))
+        };
+        policy.run(&provider, session).await.unwrap();
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            [
+                RePairEvent::RepairRequired(MOCK_UDID.to_string()),
+                RePairEvent::Repaired(MOCK_UDID.to_string()),
+            ]
+        );
+
+        let saved = store.load_pairing_file(MOCK_UDID).await.unwrap();
+        assert_eq!(saved.host_id(), previous.host_id());
+        assert_ne!(
+            saved.host_certificate.to_pem().unwrap(),
+            previous.host_certificate.to_pem().unwrap()
+        );
+
+        store.delete(RecordKind::Lockdown, MOCK_UDID).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+// Jackson Coxson
+// Finds devices in recovery or DFU mode and sends them back to a normal boot.
+// These devices aren't handled by usbmuxd, so this talks to iBoot over USB directly.
+// rusb is blocking, so call these from spawn_blocking in async contexts.
+
+use std::time::Duration;
+
+use log::{debug, warn};
+use rusb::UsbContext;
+
+use crate::{usbmuxd::DeviceMode, IdeviceError};
+
+pub const APPLE_VENDOR_ID: u16 = 0x05ac;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct RecoveryDevice {
+    pub mode: DeviceMode,
+    pub ecid: Option<u64>,
+    /// iBoot's serial string, such as ``CPID:8101 CPRV:11 ... ECID:001A2B3C4D5E6F7A ...``
+    pub serial: String,
+    bus_number: u8,
+    address: u8,
+}
+
+/// Lists Apple devices attached in recovery or DFU mode
+pub fn list_devices() -> Result<Vec<RecoveryDevice>, IdeviceError> {
+    let mut res = Vec::new();
+    for device in rusb::devices()?.iter() {
+        let desc = device.device_descriptor()?;
+        if desc.vendor_id() != APPLE_VENDOR_ID {
+            continue;
+        }
+        let mode = DeviceMode::from_product_id(desc.product_id());
+        if !matches!(mode, DeviceMode::Recovery | DeviceMode::Dfu) {
+            continue;
+        }
+
+        let serial = match device
+            .open()
+            .and_then(|h| h.read_serial_number_string_ascii(&desc))
+        {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Unable to read the serial of a {mode:?} device: {e:?}");
+                String::new()
+            }
+        };
+
+        res.push(RecoveryDevice {
+            mode,
+            ecid: parse_ecid(&serial),
+            serial,
+            bus_number: device.bus_number(),
+            address: device.address(),
+        });
+    }
+    Ok(res)
+}
+
+/// Sets iBoot to auto-boot and reboots, which leaves the restore screen.
+/// DFU mode can only be left with a restore, so DFU devices are rejected.
+pub fn exit_recovery(device: &RecoveryDevice) -> Result<(), IdeviceError> {
+    if device.mode != DeviceMode::Recovery {
+        warn!("Only devices in recovery mode can be rebooted out of it");
+        return Err(IdeviceError::InvalidArgument);
+    }
+
+    let usb_device = match rusb::devices()?
+        .iter()
+        .find(|d| d.bus_number() == device.bus_number && d.address() == device.address)
+    {
+        Some(d) => d,
+        None => return Err(IdeviceError::DeviceNotFound),
+    };
+    let handle = usb_device.open()?;
+    // Not supported on every platform, in which case there's nothing to detach
+    let _ = handle.set_auto_detach_kernel_driver(true);
+    handle.claim_interface(0)?;
+
+    send_command(&handle, "setenv auto-boot true")?;
+    send_command(&handle, "saveenv")?;
+    // The device drops off the bus before acknowledging
+    if let Err(e) = send_command(&handle, "reboot") {
+        debug!("reboot wasn't acknowledged: {e:?}");
+    }
+    Ok(())
+}
+
+fn send_command<T: UsbContext>(
+    handle: &rusb::DeviceHandle<T>,
+    command: &str,
+) -> Result<(), IdeviceError> {
+    debug!("Sending iBoot command {command}");
+    let mut buf = command.as_bytes().to_vec();
+    buf.push(0);
+    handle.write_control(0x40, 0, 0, 0, &buf, CONTROL_TIMEOUT)?;
+    Ok(())
+}
+
+fn parse_ecid(serial: &str) -> Option<u64> {
+    serial
+        .split(' ')
+        .find_map(|part| part.strip_prefix("ECID:"))
+        .and_then(|ecid| u64::from_str_radix(ecid, 16).ok())
+}
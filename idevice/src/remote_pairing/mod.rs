@@ -0,0 +1,636 @@
+// Jackson Coxson
+// RemotePairing, which CoreDevice uses on iOS 17 and later to pair with devices and open
+// tunnels to them without lockdown, over the network included.
+// Messages are JSON in ``RPPairing`` frames. Pairing is HomeKit style: pair setup proves
+// the PIN the device shows with SRP and swaps long term Ed25519 keys, pair verify later
+// uses those keys to agree on a session key that encrypts everything after it.
+
+use std::net::SocketAddr;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{BufMut, Bytes, BytesMut};
+use log::{debug, warn};
+use openssl::{
+    derive::Deriver,
+    md::Md,
+    pkey::{Id, PKey},
+    pkey_ctx::PkeyCtx,
+    sign::{Signer, Verifier},
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    codec::{read_frame, Codec},
+    IdeviceError, ReadWrite,
+};
+
+pub mod pairing_file;
+#[cfg(feature = "quic_tunnel")]
+pub mod quic;
+mod srp;
+pub mod tlv;
+
+pub use pairing_file::RemotePairingFile;
+use tlv::Tlv8;
+
+/// The port devices listen for RemotePairing on, advertised as ``_remotepairing._tcp``
+pub const DEFAULT_PORT: u16 = 49152;
+
+const MAGIC: &[u8; 9] = b"RPPairing";
+const WIRE_PROTOCOL_VERSION: u64 = 19;
+
+/// A JSON message in RPPairing framing, a magic and a big endian u16 length
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpPairingPacket(pub Value);
+
+impl Codec for RpPairingPacket {
+    const HEADER_LEN: usize = MAGIC.len() + 2;
+
+    fn frame_length(header: &[u8]) -> Result<usize, IdeviceError> {
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(IdeviceError::RemotePairingFailed(
+                "invalid RPPairing magic".into(),
+            ));
+        }
+        let len = u16::from_be_bytes([header[MAGIC.len()], header[MAGIC.len() + 1]]);
+        Ok(Self::HEADER_LEN + len as usize)
+    }
+
+    fn decode_frame(frame: &[u8]) -> Result<Self, IdeviceError> {
+        Ok(Self(serde_json::from_slice(&frame[Self::HEADER_LEN..])?))
+    }
+
+    fn encode(&self) -> Result<Bytes, IdeviceError> {
+        let body = serde_json::to_vec(&self.0)?;
+        let len =
+            u16::try_from(body.len()).map_err(|_| IdeviceError::MessageTooLarge(body.len()))?;
+        let mut packet = BytesMut::with_capacity(Self::HEADER_LEN + body.len());
+        packet.put_slice(MAGIC);
+        packet.put_u16(len);
+        packet.put_slice(&body);
+        Ok(packet.freeze())
+    }
+}
+
+/// Keys agreed on by pair verify
+struct Session {
+    shared_key: Vec<u8>,
+    client_key: Vec<u8>,
+    server_key: Vec<u8>,
+    sent: u64,
+    received: u64,
+}
+
+pub struct RemotePairingClient<R: ReadWrite> {
+    socket: R,
+    /// Shown on the device as the host's name
+    host_name: String,
+    sequence_number: u64,
+    session: Option<Session>,
+}
+
+/// Connects to a device's RemotePairing port
+pub async fn connect(
+    addr: SocketAddr,
+    host_name: impl Into<String>,
+) -> Result<RemotePairingClient<Box<dyn ReadWrite>>, IdeviceError> {
+    let socket = crate::runtime::connect_tcp(addr).await?;
+    let mut client = RemotePairingClient::new(socket, host_name);
+    client.handshake().await?;
+    Ok(client)
+}
+
+impl<R: ReadWrite> RemotePairingClient<R> {
+    pub fn new(socket: R, host_name: impl Into<String>) -> Self {
+        Self {
+            socket,
+            host_name: host_name.into(),
+            sequence_number: 0,
+            session: None,
+        }
+    }
+
+    async fn send_message(&mut self, message: Value) -> Result<(), IdeviceError> {
+        let packet = RpPairingPacket(json!({
+            "message": message,
+            "originatedBy": "host",
+            "sequenceNumber": self.sequence_number,
+        }));
+        self.sequence_number += 1;
+        self.socket.write_all(&packet.encode()?).await?;
+        Ok(())
+    }
+
+    async fn read_message(&mut self) -> Result<Value, IdeviceError> {
+        let RpPairingPacket(mut packet) = read_frame(&mut self.socket).await?;
+        Ok(packet["message"].take())
+    }
+
+    async fn send_plain(&mut self, body: Value) -> Result<(), IdeviceError> {
+        self.send_message(json!({ "plain": { "_0": body } })).await
+    }
+
+    async fn read_plain(&mut self) -> Result<Value, IdeviceError> {
+        let mut message = self.read_message().await?;
+        match message["plain"]["_0"].take() {
+            Value::Null => {
+                warn!("Expected a plain message, got {message}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+            body => Ok(body),
+        }
+    }
+
+    /// Has to come first. Returns what the device says about itself.
+    pub async fn handshake(&mut self) -> Result<Value, IdeviceError> {
+        self.send_plain(json!({
+            "request": { "_0": { "handshake": { "_0": {
+                "hostOptions": { "attemptPairVerify": true },
+                "wireProtocolVersion": WIRE_PROTOCOL_VERSION,
+            }}}}
+        }))
+        .await?;
+        let mut res = self.read_plain().await?;
+        match res["response"]["_1"]["handshake"]["_0"].take() {
+            Value::Null => {
+                warn!("Unexpected handshake response {res}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+            info => Ok(info["peerDeviceInfo"].clone()),
+        }
+    }
+
+    async fn send_pairing_data(
+        &mut self,
+        data: Tlv8,
+        kind: &str,
+        start_new_session: bool,
+    ) -> Result<(), IdeviceError> {
+        let mut pairing_data = json!({
+            "data": STANDARD.encode(data.encode()),
+            "kind": kind,
+            "startNewSession": start_new_session,
+        });
+        if kind == "setupManualPairing" {
+            pairing_data["sendingHost"] = self.host_name.clone().into();
+        }
+        self.send_plain(json!({ "event": { "_0": { "pairingData": { "_0": pairing_data } } } }))
+            .await
+    }
+
+    async fn read_pairing_data(&mut self) -> Result<Tlv8, IdeviceError> {
+        let res = self.read_plain().await?;
+        let event = &res["event"]["_0"];
+        if let Some(data) = event["pairingData"]["_0"]["data"].as_str() {
+            let data = STANDARD.decode(data).map_err(|e| {
+                IdeviceError::RemotePairingFailed(format!("pairing data isn't base64: {e}"))
+            })?;
+            let tlv = Tlv8::decode(&data)?;
+            if let Some(code) = tlv.get(tlv::ERROR) {
+                return Err(IdeviceError::RemotePairingFailed(format!(
+                    "device returned pairing error {code:?}"
+                )));
+            }
+            return Ok(tlv);
+        }
+        if let Some(rejected) = event.get("pairingRejectedWithError") {
+            let description = rejected["wrappedError"]["userInfo"]["NSLocalizedDescription"]
+                .as_str()
+                .unwrap_or("no description");
+            return Err(IdeviceError::RemotePairingFailed(format!(
+                "device rejected pairing: {description}"
+            )));
+        }
+        warn!("Unexpected pairing response {res}");
+        Err(IdeviceError::UnexpectedResponse)
+    }
+
+    /// Runs pair setup. The device shows a PIN once this starts, which ``get_pin`` gives back.
+    /// The returned file should be kept, it's needed for ``verify`` from then on.
+    pub async fn pair<F, Fut>(&mut self, get_pin: F) -> Result<RemotePairingFile, IdeviceError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = String>,
+    {
+        // M1 and M2, the device's SRP salt and public key
+        let m1 = Tlv8::new().with(tlv::METHOD, [0]).with(tlv::STATE, [1]);
+        self.send_pairing_data(m1, "setupManualPairing", true)
+            .await?;
+        let m2 = self.read_pairing_data().await?;
+        let salt = m2.require(tlv::SALT)?.to_vec();
+        let server_public = m2.require(tlv::PUBLIC_KEY)?.to_vec();
+
+        // M3 and M4, proving the PIN
+        let pin = get_pin().await;
+        let srp = srp::SrpClient::new(pin.trim(), &salt, &server_public)?;
+        let m3 = Tlv8::new()
+            .with(tlv::STATE, [3])
+            .with(tlv::PUBLIC_KEY, srp.public_key.clone())
+            .with(tlv::PROOF, srp.proof.clone());
+        self.send_pairing_data(m3, "setupManualPairing", false)
+            .await?;
+        let m4 = self.read_pairing_data().await?;
+        let session_key = srp.verify_server(m4.require(tlv::PROOF)?)?.to_vec();
+        debug!("SRP verified, exchanging long term keys");
+
+        // M5 and M6, swapping long term keys under the SRP session key
+        let identifier = uuid::Uuid::new_v4().to_string().to_uppercase();
+        let private_key = PKey::generate_ed25519()?;
+        let public_key = private_key.raw_public_key()?;
+        let encrypt_key = hkdf(
+            &session_key,
+            b"Pair-Setup-Encrypt-Salt",
+            b"Pair-Setup-Encrypt-Info",
+        )?;
+
+        let mut signed = hkdf(
+            &session_key,
+            b"Pair-Setup-Controller-Sign-Salt",
+            b"Pair-Setup-Controller-Sign-Info",
+        )?;
+        signed.extend_from_slice(identifier.as_bytes());
+        signed.extend_from_slice(&public_key);
+        let signature = Signer::new_without_digest(&private_key)?.sign_oneshot_to_vec(&signed)?;
+
+        let inner = Tlv8::new()
+            .with(tlv::IDENTIFIER, identifier.as_bytes())
+            .with(tlv::PUBLIC_KEY, public_key)
+            .with(tlv::SIGNATURE, signature)
+            .with(tlv::INFO, self.host_info(&identifier)?);
+        let m5 = Tlv8::new().with(tlv::STATE, [5]).with(
+            tlv::ENCRYPTED_DATA,
+            seal(&encrypt_key, &label_nonce(b"PS-Msg05"), &inner.encode())?,
+        );
+        self.send_pairing_data(m5, "setupManualPairing", false)
+            .await?;
+
+        let m6 = self.read_pairing_data().await?;
+        let device = Tlv8::decode(&open(
+            &encrypt_key,
+            &label_nonce(b"PS-Msg06"),
+            m6.require(tlv::ENCRYPTED_DATA)?,
+        )?)?;
+        let device_identifier = String::from_utf8(device.require(tlv::IDENTIFIER)?.to_vec())?;
+        let device_public_key = device.require(tlv::PUBLIC_KEY)?.to_vec();
+
+        let mut signed = hkdf(
+            &session_key,
+            b"Pair-Setup-Accessory-Sign-Salt",
+            b"Pair-Setup-Accessory-Sign-Info",
+        )?;
+        signed.extend_from_slice(device_identifier.as_bytes());
+        signed.extend_from_slice(&device_public_key);
+        verify_signature(&device_public_key, &signed, device.require(tlv::SIGNATURE)?)?;
+
+        Ok(RemotePairingFile {
+            identifier,
+            private_key,
+            device_identifier,
+            device_public_key,
+        })
+    }
+
+    /// What the device lists the host as, in OPACK
+    fn host_info(&self, identifier: &str) -> Result<Vec<u8>, IdeviceError> {
+        let mut alt_irk = [0; 16];
+        openssl::rand::rand_bytes(&mut alt_irk)?;
+        Ok(opack_dictionary(&[
+            ("accountID", OpackValue::String(identifier)),
+            ("altIRK", OpackValue::Data(&alt_irk)),
+            ("btAddr", OpackValue::String("11:22:33:44:55:66")),
+            (
+                "mac",
+                OpackValue::Data(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+            ),
+            ("model", OpackValue::String("computer-model")),
+            ("name", OpackValue::String(&self.host_name)),
+            (
+                "remotepairing_serial_number",
+                OpackValue::String("AAAAAAAAAAAA"),
+            ),
+        ]))
+    }
+
+    /// Proves an existing pairing and sets up encryption for the rest of the connection.
+    /// Fails if the device has forgotten the pairing, which then has to be redone.
+    pub async fn verify(&mut self, pairing_file: &RemotePairingFile) -> Result<(), IdeviceError> {
+        let ephemeral = PKey::generate_x25519()?;
+        let ephemeral_public = ephemeral.raw_public_key()?;
+
+        let m1 = Tlv8::new()
+            .with(tlv::STATE, [1])
+            .with(tlv::PUBLIC_KEY, ephemeral_public.clone());
+        self.send_pairing_data(m1, "verifyManualPairing", true)
+            .await?;
+        let m2 = self.read_pairing_data().await?;
+        let device_public = m2.require(tlv::PUBLIC_KEY)?.to_vec();
+
+        let peer = PKey::public_key_from_raw_bytes(&device_public, Id::X25519)?;
+        let mut deriver = Deriver::new(&ephemeral)?;
+        deriver.set_peer(&peer)?;
+        let shared_key = deriver.derive_to_vec()?;
+        let encrypt_key = hkdf(
+            &shared_key,
+            b"Pair-Verify-Encrypt-Salt",
+            b"Pair-Verify-Encrypt-Info",
+        )?;
+
+        let mut signed = ephemeral_public;
+        signed.extend_from_slice(pairing_file.identifier.as_bytes());
+        signed.extend_from_slice(&device_public);
+        let signature =
+            Signer::new_without_digest(&pairing_file.private_key)?.sign_oneshot_to_vec(&signed)?;
+        let inner = Tlv8::new()
+            .with(tlv::IDENTIFIER, pairing_file.identifier.as_bytes())
+            .with(tlv::SIGNATURE, signature);
+        let m3 = Tlv8::new().with(tlv::STATE, [3]).with(
+            tlv::ENCRYPTED_DATA,
+            seal(&encrypt_key, &label_nonce(b"PV-Msg03"), &inner.encode())?,
+        );
+        self.send_pairing_data(m3, "verifyManualPairing", false)
+            .await?;
+        self.read_pairing_data().await?;
+
+        self.session = Some(Session {
+            client_key: hkdf(&shared_key, b"", b"ClientEncrypt-main")?,
+            server_key: hkdf(&shared_key, b"", b"ServerEncrypt-main")?,
+            shared_key,
+            sent: 0,
+            received: 0,
+        });
+        Ok(())
+    }
+
+    fn session(&mut self) -> Result<&mut Session, IdeviceError> {
+        self.session.as_mut().ok_or(IdeviceError::SessionInactive)
+    }
+
+    async fn send_encrypted(&mut self, request: Value) -> Result<(), IdeviceError> {
+        let session = self.session()?;
+        let nonce = counter_nonce(session.sent);
+        session.sent += 1;
+        let sealed = seal(&session.client_key, &nonce, &serde_json::to_vec(&request)?)?;
+        self.send_message(json!({ "streamEncrypted": { "_0": STANDARD.encode(sealed) } }))
+            .await
+    }
+
+    async fn read_encrypted(&mut self) -> Result<Value, IdeviceError> {
+        let message = self.read_message().await?;
+        let Some(data) = message["streamEncrypted"]["_0"].as_str() else {
+            warn!("Expected an encrypted message, got {message}");
+            return Err(IdeviceError::UnexpectedResponse);
+        };
+        let data = STANDARD.decode(data).map_err(|e| {
+            IdeviceError::RemotePairingFailed(format!("encrypted message isn't base64: {e}"))
+        })?;
+        let session = self.session()?;
+        let nonce = counter_nonce(session.received);
+        session.received += 1;
+        Ok(serde_json::from_slice(&open(
+            &session.server_key,
+            &nonce,
+            &data,
+        )?)?)
+    }
+
+    /// Asks the device to listen for a tunnel connection, secured with the session key.
+    /// Returns the port it listens on.
+    pub async fn create_tcp_listener(&mut self) -> Result<u16, IdeviceError> {
+        let key = STANDARD.encode(&self.session()?.shared_key);
+        self.send_encrypted(json!({
+            "request": { "_0": { "createListener": {
+                "key": key,
+                "peerConnectionsInfo": [{
+                    "owningPID": std::process::id(),
+                    "owningProcessName": "CoreDeviceService",
+                }],
+                "transportProtocolType": "tcp",
+            }}}
+        }))
+        .await?;
+        let res = self.read_encrypted().await?;
+        match res["response"]["_1"]["createListener"]["port"].as_u64() {
+            Some(port) => Ok(port as u16),
+            None => {
+                warn!("Unexpected createListener response {res}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Opens the same CoreDevice tunnel ``CoreDeviceProxy`` gives over USB, to the device at
+    /// ``addr``. Needs ``verify`` first.
+    #[cfg(feature = "core_device_proxy")]
+    pub async fn connect_tunnel(
+        &mut self,
+        addr: std::net::IpAddr,
+    ) -> Result<crate::core_device_proxy::CoreDeviceProxy, IdeviceError> {
+        let key = self.session()?.shared_key.clone();
+        let port = self.create_tcp_listener().await?;
+        let socket = crate::runtime::connect_tcp(SocketAddr::new(addr, port)).await?;
+        let socket = psk_connect(socket, key).await?;
+        crate::core_device_proxy::CoreDeviceProxy::new(crate::Idevice::new(
+            Box::new(socket),
+            self.host_name.clone(),
+        ))
+        .await
+    }
+
+    pub fn into_inner(self) -> R {
+        self.socket
+    }
+}
+
+/// TLS with the pair verify key as a pre-shared key, how tunnel connections are secured
+#[cfg(feature = "core_device_proxy")]
+async fn psk_connect(
+    socket: Box<dyn ReadWrite>,
+    key: Vec<u8>,
+) -> Result<tokio_openssl::SslStream<Box<dyn ReadWrite>>, IdeviceError> {
+    use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode, SslVersion};
+
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    builder.set_max_proto_version(Some(SslVersion::TLS1_2))?;
+    builder.set_cipher_list("PSK")?;
+    builder.set_verify(SslVerifyMode::NONE);
+    builder.set_psk_client_callback(move |_, _, identity, psk| {
+        // An empty identity
+        identity[0] = 0;
+        psk[..key.len()].copy_from_slice(&key);
+        Ok(key.len())
+    });
+    let ssl = builder
+        .build()
+        .configure()?
+        .verify_hostname(false)
+        .into_ssl("")?;
+    let mut stream = tokio_openssl::SslStream::new(ssl, socket)?;
+    std::pin::Pin::new(&mut stream).connect().await?;
+    Ok(stream)
+}
+
+fn hkdf(key: &[u8], salt: &[u8], info: &[u8]) -> Result<Vec<u8>, IdeviceError> {
+    let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+    ctx.derive_init()?;
+    ctx.set_hkdf_md(Md::sha512())?;
+    ctx.set_hkdf_key(key)?;
+    if !salt.is_empty() {
+        ctx.set_hkdf_salt(salt)?;
+    }
+    ctx.add_hkdf_info(info)?;
+    let mut out = vec![0; 32];
+    ctx.derive(Some(&mut out))?;
+    Ok(out)
+}
+
+/// Pairing messages use their name as the nonce, after four zeros
+fn label_nonce(label: &[u8; 8]) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(label);
+    nonce
+}
+
+/// Stream messages count up from 0, little endian at the start of the nonce
+fn counter_nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// ChaCha20-Poly1305, with the tag appended
+fn seal(key: &[u8], nonce: &[u8; 12], data: &[u8]) -> Result<Vec<u8>, IdeviceError> {
+    let mut tag = [0; 16];
+    let mut out = encrypt_aead(
+        Cipher::chacha20_poly1305(),
+        key,
+        Some(nonce),
+        &[],
+        data,
+        &mut tag,
+    )?;
+    out.extend_from_slice(&tag);
+    Ok(out)
+}
+
+fn open(key: &[u8], nonce: &[u8; 12], data: &[u8]) -> Result<Vec<u8>, IdeviceError> {
+    if data.len() < 16 {
+        return Err(IdeviceError::NotEnoughBytes(data.len(), 16));
+    }
+    let (data, tag) = data.split_at(data.len() - 16);
+    decrypt_aead(
+        Cipher::chacha20_poly1305(),
+        key,
+        Some(nonce),
+        &[],
+        data,
+        tag,
+    )
+    .map_err(|_| IdeviceError::RemotePairingFailed("couldn't decrypt message".into()))
+}
+
+fn verify_signature(public_key: &[u8], data: &[u8], signature: &[u8]) -> Result<(), IdeviceError> {
+    let key = PKey::public_key_from_raw_bytes(public_key, Id::ED25519)?;
+    if Verifier::new_without_digest(&key)?.verify_oneshot(signature, data)? {
+        Ok(())
+    } else {
+        Err(IdeviceError::RemotePairingFailed(
+            "device's signature doesn't match its key".into(),
+        ))
+    }
+}
+
+enum OpackValue<'a> {
+    String(&'a str),
+    Data(&'a [u8]),
+}
+
+/// Just enough OPACK, Apple's compact serialization, for a small dictionary
+fn opack_dictionary(entries: &[(&str, OpackValue)]) -> Vec<u8> {
+    fn item(out: &mut Vec<u8>, short: u8, long: u8, bytes: &[u8]) {
+        if bytes.len() <= 0x20 {
+            out.push(short + bytes.len() as u8);
+        } else {
+            out.push(long);
+            out.push(bytes.len() as u8);
+        }
+        out.extend_from_slice(bytes);
+    }
+
+    let mut out = vec![0xe0 + entries.len() as u8];
+    for (key, value) in entries {
+        item(&mut out, 0x40, 0x61, key.as_bytes());
+        match value {
+            OpackValue::String(s) => item(&mut out, 0x40, 0x61, s.as_bytes()),
+            OpackValue::Data(d) => item(&mut out, 0x70, 0x91, d),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+
+    #[test]
+    fn packets_are_framed() {
+        let packet = RpPairingPacket(json!({ "message": { "plain": { "_0": {} } } }));
+        let encoded = packet.encode().unwrap();
+        assert_eq!(&encoded[..9], b"RPPairing");
+        assert_eq!(
+            u16::from_be_bytes([encoded[9], encoded[10]]) as usize,
+            encoded.len() - 11
+        );
+
+        let mut buf = BytesMut::from(&encoded[..]);
+        assert_eq!(RpPairingPacket::decode(&mut buf).unwrap(), Some(packet));
+
+        let mut bad = BytesMut::from(&b"CDTunnel\x00\x00\x00"[..]);
+        assert!(RpPairingPacket::decode(&mut bad).is_err());
+    }
+
+    #[test]
+    fn sealed_messages_open() {
+        let key = hkdf(
+            b"shared",
+            b"Pair-Verify-Encrypt-Salt",
+            b"Pair-Verify-Encrypt-Info",
+        )
+        .unwrap();
+        let sealed = seal(&key, &label_nonce(b"PV-Msg03"), b"hello").unwrap();
+        assert_eq!(sealed.len(), 5 + 16);
+        assert_eq!(
+            open(&key, &label_nonce(b"PV-Msg03"), &sealed).unwrap(),
+            b"hello"
+        );
+        assert!(open(&key, &label_nonce(b"PV-Msg04"), &sealed).is_err());
+
+        let counted = seal(&key, &counter_nonce(1), b"hello").unwrap();
+        assert_ne!(counted, sealed);
+        assert_eq!(open(&key, &counter_nonce(1), &counted).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn host_info_is_opack() {
+        let info = opack_dictionary(&[
+            ("name", OpackValue::String("host")),
+            ("mac", OpackValue::Data(&[1, 2])),
+        ]);
+        assert_eq!(
+            info,
+            [
+                &[0xe2, 0x44][..],
+                b"name",
+                &[0x44],
+                b"host",
+                &[0x43],
+                b"mac",
+                &[0x72, 1, 2],
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// Jackson Coxson
+// The keys a RemotePairing pair setup leaves behind, needed to verify the pairing and
+// open a tunnel later.
+
+use std::{fmt, path::Path};
+
+use log::warn;
+use openssl::pkey::{Id, PKey, Private};
+use plist::Data;
+use serde::{Deserialize, Serialize};
+
+use crate::{pretty::REDACTED, IdeviceError};
+
+#[derive(Clone)]
+pub struct RemotePairingFile {
+    /// The host's pairing identifier, a UUID
+    pub identifier: String,
+    /// The host's long term Ed25519 key
+    pub private_key: PKey<Private>,
+    /// The identifier the device paired as
+    pub device_identifier: String,
+    /// The device's long term Ed25519 public key
+    pub device_public_key: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct RawRemotePairingFile {
+    identifier: String,
+    private_key: Data,
+    device_identifier: String,
+    device_public_key: Data,
+}
+
+impl RemotePairingFile {
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, IdeviceError> {
+        let f = std::fs::read(path)?;
+        Self::from_bytes(&f)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IdeviceError> {
+        let raw: RawRemotePairingFile = plist::from_bytes(bytes)?;
+        let private_key =
+            match PKey::private_key_from_raw_bytes(raw.private_key.as_ref(), Id::ED25519) {
+                Ok(k) => k,
+                Err(e) => {
+                    warn!("Remote pairing file has an invalid private key: {e:?}");
+                    return Err(IdeviceError::InvalidPairingFile(
+                        "private key is not an Ed25519 key".into(),
+                    ));
+                }
+            };
+        Ok(Self {
+            identifier: raw.identifier,
+            private_key,
+            device_identifier: raw.device_identifier,
+            device_public_key: raw.device_public_key.into(),
+        })
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, IdeviceError> {
+        let raw = RawRemotePairingFile {
+            identifier: self.identifier.clone(),
+            private_key: self.private_key.raw_private_key()?.into(),
+            device_identifier: self.device_identifier.clone(),
+            device_public_key: self.device_public_key.clone().into(),
+        };
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &raw)?;
+        Ok(buf)
+    }
+
+    /// The host's long term public key, as the device knows it
+    pub fn public_key(&self) -> Result<Vec<u8>, IdeviceError> {
+        Ok(self.private_key.raw_public_key()?)
+    }
+}
+
+/// Leaves out the private key
+impl fmt::Debug for RemotePairingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemotePairingFile")
+            .field("identifier", &self.identifier)
+            .field("private_key", &REDACTED)
+            .field("device_identifier", &self.device_identifier)
+            .field("device_public_key", &self.device_public_key)
+            .finish()
+    }
+}
+
+impl fmt::Display for RemotePairingFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "remote pairing record for {}, host {}",
+            self.device_identifier, self.identifier
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_round_trip() {
+        let file = RemotePairingFile {
+            identifier: "5C1B8A4E-2F0D-4E5B-9D3A-7A1E2C3B4D5F".into(),
+            private_key: PKey::generate_ed25519().unwrap(),
+            device_identifier: "00008120-000A1B2C3D4E5F60".into(),
+            device_public_key: vec![9; 32],
+        };
+        let read = RemotePairingFile::from_bytes(&file.serialize().unwrap()).unwrap();
+        assert_eq!(read.identifier, file.identifier);
+        assert_eq!(read.public_key().unwrap(), file.public_key().unwrap());
+        assert_eq!(read.device_public_key, file.device_public_key);
+        assert!(format!("{read:?}").contains(REDACTED));
+    }
+}
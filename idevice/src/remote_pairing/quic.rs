@@ -0,0 +1,312 @@
+// Jackson Coxson
+// The QUIC variant of the CoreDevice tunnel. The handshake is the same CDTunnel exchange as
+// over TCP, on the first stream, but IP packets then travel as QUIC datagrams, which keeps a
+// lost packet from holding up every connection behind it. Much snappier over Wi-Fi.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use log::{debug, warn};
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    x509::{X509NameBuilder, X509},
+};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+use serde_json::json;
+
+use super::RemotePairingClient;
+use crate::{
+    core_device_proxy::{CDTunnelPacket, CoreDeviceProxy, HandshakeResponse},
+    IdeviceError, ReadWrite,
+};
+
+const ALPN: &[u8] = b"RemotePairingTunnelProtocol";
+
+/// The largest IP packet a datagram carries, what CoreDevice asks for
+pub const MAX_DATAGRAM: u32 = 14000;
+
+/// How long to give QUIC before settling for TCP, for ``connect_any_tunnel``
+pub const DEFAULT_QUIC_TIMEOUT: Duration = Duration::from_secs(3);
+
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+fn quic_err(e: impl std::fmt::Display) -> IdeviceError {
+    IdeviceError::QuicTunnelFailed(e.to_string())
+}
+
+/// A CoreDevice tunnel over QUIC
+pub struct QuicTunnel {
+    pub handshake: HandshakeResponse,
+    connection: quinn::Connection,
+    // The device tears the tunnel down with the handshake stream
+    _stream: (quinn::SendStream, quinn::RecvStream),
+    _endpoint: quinn::Endpoint,
+}
+
+impl QuicTunnel {
+    /// Sends one IP packet
+    pub fn send(&self, packet: &[u8]) -> Result<(), IdeviceError> {
+        self.connection
+            .send_datagram(Bytes::copy_from_slice(packet))
+            .map_err(quic_err)
+    }
+
+    /// Waits for the next IP packet
+    pub async fn recv(&self) -> Result<Vec<u8>, IdeviceError> {
+        let datagram = self.connection.read_datagram().await.map_err(quic_err)?;
+        Ok(datagram.to_vec())
+    }
+
+    /// The connection's current round trip time estimate
+    pub fn rtt(&self) -> Duration {
+        self.connection.rtt()
+    }
+}
+
+/// A CoreDevice tunnel over whichever transport could be set up
+pub enum CoreDeviceTunnel {
+    Quic(QuicTunnel),
+    Tcp(CoreDeviceProxy),
+}
+
+impl CoreDeviceTunnel {
+    pub fn handshake(&self) -> &HandshakeResponse {
+        match self {
+            Self::Quic(t) => &t.handshake,
+            Self::Tcp(t) => &t.handshake,
+        }
+    }
+
+    pub async fn send(&mut self, packet: &[u8]) -> Result<(), IdeviceError> {
+        match self {
+            Self::Quic(t) => t.send(packet),
+            Self::Tcp(t) => t.send(packet).await,
+        }
+    }
+
+    pub async fn recv(&mut self) -> Result<Vec<u8>, IdeviceError> {
+        match self {
+            Self::Quic(t) => t.recv().await,
+            Self::Tcp(t) => t.recv().await,
+        }
+    }
+}
+
+impl<R: ReadWrite> RemotePairingClient<R> {
+    /// Asks the device to listen for a QUIC tunnel from the holder of ``key``.
+    /// Returns the port it listens on.
+    pub async fn create_quic_listener(&mut self, key: &PKey<Private>) -> Result<u16, IdeviceError> {
+        let key = STANDARD.encode(key.public_key_to_der()?);
+        self.send_encrypted(json!({
+            "request": { "_0": { "createListener": {
+                "key": key,
+                "peerConnectionsInfo": [{
+                    "owningPID": std::process::id(),
+                    "owningProcessName": "CoreDeviceService",
+                }],
+                "transportProtocolType": "quic",
+            }}}
+        }))
+        .await?;
+        let res = self.read_encrypted().await?;
+        match res["response"]["_1"]["createListener"]["port"].as_u64() {
+            Some(port) => Ok(port as u16),
+            None => {
+                warn!("Unexpected createListener response {res}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Opens a CoreDevice tunnel over QUIC to the device at ``addr``. Needs ``verify`` first.
+    pub async fn connect_quic_tunnel(&mut self, addr: IpAddr) -> Result<QuicTunnel, IdeviceError> {
+        let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let port = self.create_quic_listener(&key).await?;
+        quic_connect(SocketAddr::new(addr, port), &key).await
+    }
+
+    /// Opens a CoreDevice tunnel, over QUIC if the device answers within ``quic_timeout``
+    /// and over TCP otherwise. Needs ``verify`` first.
+    pub async fn connect_any_tunnel(
+        &mut self,
+        addr: IpAddr,
+        quic_timeout: Duration,
+    ) -> Result<CoreDeviceTunnel, IdeviceError> {
+        // Only the QUIC side is raced, the pairing channel can't be left mid request
+        let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let quic = match self.create_quic_listener(&key).await {
+            Ok(port) => tokio::select! {
+                res = quic_connect(SocketAddr::new(addr, port), &key) => res,
+                _ = crate::runtime::sleep(quic_timeout) => Err(IdeviceError::Timeout),
+            },
+            Err(e) => Err(e),
+        };
+        match quic {
+            Ok(t) => {
+                debug!("Tunneling over QUIC, RTT {:?}", t.rtt());
+                Ok(CoreDeviceTunnel::Quic(t))
+            }
+            Err(e) => {
+                warn!("QUIC tunnel failed, falling back to TCP: {e:?}");
+                Ok(CoreDeviceTunnel::Tcp(self.connect_tunnel(addr).await?))
+            }
+        }
+    }
+}
+
+async fn quic_connect(addr: SocketAddr, key: &PKey<Private>) -> Result<QuicTunnel, IdeviceError> {
+    let bind = if addr.is_ipv6() {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+    } else {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+    };
+    let mut endpoint = quinn::Endpoint::client(bind)?;
+    endpoint.set_default_client_config(client_config(key)?);
+
+    let connection = endpoint
+        .connect(addr, &addr.ip().to_string())
+        .map_err(quic_err)?
+        .await
+        .map_err(quic_err)?;
+    debug!("QUIC connection to {addr} is up");
+
+    let (mut send, mut recv) = connection.open_bi().await.map_err(quic_err)?;
+    let request = CDTunnelPacket::new(serde_json::to_vec(&json!({
+        "type": "clientHandshakeRequest",
+        "mtu": MAX_DATAGRAM,
+    }))?);
+    send.write_all(&request.serialize()?)
+        .await
+        .map_err(quic_err)?;
+
+    let mut header = [0; CDTunnelPacket::MAGIC.len() + 2];
+    recv.read_exact(&mut header).await.map_err(quic_err)?;
+    let len = u16::from_be_bytes([header[header.len() - 2], header[header.len() - 1]]);
+    let mut body = vec![0; len as usize];
+    recv.read_exact(&mut body).await.map_err(quic_err)?;
+    let packet = CDTunnelPacket::parse(&[&header[..], &body].concat())?;
+    let handshake: HandshakeResponse = serde_json::from_slice(packet.body())?;
+
+    Ok(QuicTunnel {
+        handshake,
+        connection,
+        _stream: (send, recv),
+        _endpoint: endpoint,
+    })
+}
+
+/// TLS 1.3 with a throwaway client certificate for ``key``, which the device pins from
+/// ``createListener``. The device's own certificate is self signed.
+fn client_config(key: &PKey<Private>) -> Result<quinn::ClientConfig, IdeviceError> {
+    let cert = self_signed(key)?;
+    let provider = Arc::new(ring::default_provider());
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(quic_err)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyServerCert(provider)))
+        .with_client_auth_cert(
+            vec![CertificateDer::from(cert.to_der()?)],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.private_key_to_pkcs8()?)),
+        )
+        .map_err(quic_err)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+
+    let tls = quinn::crypto::rustls::QuicClientConfig::try_from(tls).map_err(quic_err)?;
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    transport.datagram_receive_buffer_size(Some(MAX_DATAGRAM as usize * 64));
+    let mut config = quinn::ClientConfig::new(Arc::new(tls));
+    config.transport_config(Arc::new(transport));
+    Ok(config)
+}
+
+fn self_signed(key: &PKey<Private>) -> Result<X509, IdeviceError> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", &uuid::Uuid::new_v4().to_string())?;
+    let name = name.build();
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    cert.set_serial_number(BigNum::from_u32(1)?.to_asn1_integer()?.as_ref())?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(key)?;
+    cert.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    cert.set_not_after(Asn1Time::days_from_now(3650)?.as_ref())?;
+    cert.sign(key, MessageDigest::sha256())?;
+    Ok(cert.build())
+}
+
+/// Takes whatever certificate the device presents, but still checks it signed the handshake
+#[derive(Debug)]
+struct AnyServerCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls12NotOffered,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_config_builds() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let cert = self_signed(&key).unwrap();
+        assert!(cert.verify(&key).unwrap());
+        client_config(&key).unwrap();
+    }
+}
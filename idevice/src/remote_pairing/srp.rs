@@ -0,0 +1,197 @@
+// Jackson Coxson
+// The client side of SRP-6a, as pair setup uses it: the 3072 bit group from RFC 5054
+// with SHA-512. Numbers are hashed without leading zeros except where the protocol pads
+// them, which is what the device checks against.
+
+use openssl::{
+    bn::{BigNum, BigNumContext, BigNumRef},
+    error::ErrorStack,
+    sha::Sha512,
+};
+
+use crate::IdeviceError;
+
+const N_3072: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A0879\
+    8E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B\
+    0BFF5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA4836\
+    1C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804\
+    F1746C08CA18217C32905E462E36CE3BE39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF6\
+    955817183995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33A85521ABDF1CBA64\
+    ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7ABF5AE8CDB0933D71E8C94E04A25619DCEE3D226\
+    1AD2EE6BF12FFA06D98A0864D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+const G: u32 = 5;
+
+/// The username pair setup always uses
+pub const USERNAME: &str = "Pair-Setup";
+
+fn hash(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finish()
+}
+
+fn strip(bytes: &[u8]) -> &[u8] {
+    let zeros = bytes.iter().take_while(|b| **b == 0).count();
+    &bytes[zeros..]
+}
+
+/// Left pads to the length of N
+fn pad(n: &BigNumRef, len: usize) -> Result<Vec<u8>, ErrorStack> {
+    n.to_vec_padded(len as i32)
+}
+
+/// The client's half of the exchange, ready to be sent
+#[derive(Debug)]
+pub struct SrpClient {
+    /// ``A``
+    pub public_key: Vec<u8>,
+    /// ``M1``
+    pub proof: Vec<u8>,
+    session_key: Vec<u8>,
+    server_proof: Vec<u8>,
+}
+
+impl SrpClient {
+    pub fn new(password: &str, salt: &[u8], server_public: &[u8]) -> Result<Self, IdeviceError> {
+        let mut a = BigNum::new()?;
+        a.rand(256, openssl::bn::MsbOption::MAYBE_ZERO, false)?;
+        Self::with_private(password, salt, server_public, &a)
+    }
+
+    fn with_private(
+        password: &str,
+        salt: &[u8],
+        server_public: &[u8],
+        a: &BigNumRef,
+    ) -> Result<Self, IdeviceError> {
+        let mut ctx = BigNumContext::new()?;
+        let n = BigNum::from_hex_str(N_3072)?;
+        let g = BigNum::from_u32(G)?;
+        let len = n.num_bytes() as usize;
+
+        let b = BigNum::from_slice(server_public)?;
+        let mut b_mod_n = BigNum::new()?;
+        b_mod_n.nnmod(&b, &n, &mut ctx)?;
+        if b_mod_n.num_bits() == 0 {
+            return Err(IdeviceError::RemotePairingFailed(
+                "device sent an invalid SRP public key".into(),
+            ));
+        }
+
+        let mut big_a = BigNum::new()?;
+        big_a.mod_exp(&g, a, &n, &mut ctx)?;
+
+        let k = BigNum::from_slice(&hash(&[&n.to_vec(), &pad(&g, len)?]))?;
+        let u = BigNum::from_slice(&hash(&[&pad(&big_a, len)?, &pad(&b, len)?]))?;
+
+        let identity = hash(&[format!("{USERNAME}:{password}").as_bytes()]);
+        let x = BigNum::from_slice(&hash(&[strip(salt), strip(&identity)]))?;
+
+        // S = (B - k * g^x) ^ (a + u * x) mod N
+        let mut gx = BigNum::new()?;
+        gx.mod_exp(&g, &x, &n, &mut ctx)?;
+        let mut kgx = BigNum::new()?;
+        kgx.mod_mul(&k, &gx, &n, &mut ctx)?;
+        let mut base = BigNum::new()?;
+        base.mod_sub(&b, &kgx, &n, &mut ctx)?;
+        let mut ux = BigNum::new()?;
+        ux.checked_mul(&u, &x, &mut ctx)?;
+        let mut exponent = BigNum::new()?;
+        exponent.checked_add(a, &ux)?;
+        let mut s = BigNum::new()?;
+        s.mod_exp(&base, &exponent, &n, &mut ctx)?;
+
+        let session_key = hash(&[&s.to_vec()]).to_vec();
+
+        let hn = hash(&[&n.to_vec()]);
+        let hg = hash(&[&g.to_vec()]);
+        let xor: Vec<u8> = hn.iter().zip(hg.iter()).map(|(n, g)| n ^ g).collect();
+        let proof = hash(&[
+            strip(&xor),
+            &hash(&[USERNAME.as_bytes()]),
+            strip(salt),
+            &big_a.to_vec(),
+            &b.to_vec(),
+            &session_key,
+        ])
+        .to_vec();
+        let server_proof = hash(&[&big_a.to_vec(), &proof, &session_key]).to_vec();
+
+        Ok(Self {
+            public_key: big_a.to_vec(),
+            proof,
+            session_key,
+            server_proof,
+        })
+    }
+
+    /// Checks ``M2`` and gives back the shared session key
+    pub fn verify_server(&self, server_proof: &[u8]) -> Result<&[u8], IdeviceError> {
+        if server_proof != self.server_proof {
+            return Err(IdeviceError::RemotePairingFailed(
+                "device's SRP proof doesn't match, the PIN is likely wrong".into(),
+            ));
+        }
+        Ok(&self.session_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_agrees_with_a_server() {
+        let mut ctx = BigNumContext::new().unwrap();
+        let n = BigNum::from_hex_str(N_3072).unwrap();
+        assert_eq!(n.num_bits(), 3072);
+        assert!(n.is_prime(20, &mut ctx).unwrap());
+
+        let g = BigNum::from_u32(G).unwrap();
+        let len = n.num_bytes() as usize;
+        let salt = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+        let identity = hash(&[b"Pair-Setup:123456"]);
+        let x = BigNum::from_slice(&hash(&[strip(&salt), strip(&identity)])).unwrap();
+        let mut v = BigNum::new().unwrap();
+        v.mod_exp(&g, &x, &n, &mut ctx).unwrap();
+
+        // B = k * v + g^b
+        let server_private = BigNum::from_u32(0x1234_5678).unwrap();
+        let k = BigNum::from_slice(&hash(&[&n.to_vec(), &pad(&g, len).unwrap()])).unwrap();
+        let mut kv = BigNum::new().unwrap();
+        kv.mod_mul(&k, &v, &n, &mut ctx).unwrap();
+        let mut gb = BigNum::new().unwrap();
+        gb.mod_exp(&g, &server_private, &n, &mut ctx).unwrap();
+        let mut b = BigNum::new().unwrap();
+        b.mod_add(&kv, &gb, &n, &mut ctx).unwrap();
+
+        let a = BigNum::from_u32(0x9abc_def0).unwrap();
+        let client = SrpClient::with_private("123456", &salt, &b.to_vec(), &a).unwrap();
+
+        // S = (A * v^u) ^ b
+        let big_a = BigNum::from_slice(&client.public_key).unwrap();
+        let u = BigNum::from_slice(&hash(&[&pad(&big_a, len).unwrap(), &pad(&b, len).unwrap()]))
+            .unwrap();
+        let mut vu = BigNum::new().unwrap();
+        vu.mod_exp(&v, &u, &n, &mut ctx).unwrap();
+        let mut avu = BigNum::new().unwrap();
+        avu.mod_mul(&big_a, &vu, &n, &mut ctx).unwrap();
+        let mut s = BigNum::new().unwrap();
+        s.mod_exp(&avu, &server_private, &n, &mut ctx).unwrap();
+        let session_key = hash(&[&s.to_vec()]);
+
+        let server_proof = hash(&[&client.public_key, &client.proof, &session_key]);
+        assert_eq!(
+            client.verify_server(&server_proof).unwrap(),
+            &session_key[..]
+        );
+        assert!(client.verify_server(&[0; 64]).is_err());
+
+        let wrong_pin = SrpClient::with_private("654321", &salt, &b.to_vec(), &a).unwrap();
+        assert!(wrong_pin.verify_server(&server_proof).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// Jackson Coxson
+// TLV8, the type-length-value encoding pairing messages are written in.
+// Values over 255 bytes are split into consecutive items of the same type.
+
+use crate::IdeviceError;
+
+pub const METHOD: u8 = 0x00;
+pub const IDENTIFIER: u8 = 0x01;
+pub const SALT: u8 = 0x02;
+pub const PUBLIC_KEY: u8 = 0x03;
+pub const PROOF: u8 = 0x04;
+pub const ENCRYPTED_DATA: u8 = 0x05;
+pub const STATE: u8 = 0x06;
+pub const ERROR: u8 = 0x07;
+pub const SIGNATURE: u8 = 0x0a;
+pub const INFO: u8 = 0x11;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tlv8 {
+    items: Vec<(u8, Vec<u8>)>,
+}
+
+impl Tlv8 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, kind: u8, value: impl Into<Vec<u8>>) -> Self {
+        self.items.push((kind, value.into()));
+        self
+    }
+
+    pub fn get(&self, kind: u8) -> Option<&[u8]> {
+        self.items
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Like ``get``, but a missing item is an error
+    pub fn require(&self, kind: u8) -> Result<&[u8], IdeviceError> {
+        self.get(kind).ok_or_else(|| {
+            IdeviceError::RemotePairingFailed(format!("response is missing TLV item {kind:#04x}"))
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (kind, value) in &self.items {
+            if value.is_empty() {
+                out.extend_from_slice(&[*kind, 0]);
+            }
+            for chunk in value.chunks(255) {
+                out.push(*kind);
+                out.push(chunk.len() as u8);
+                out.extend_from_slice(chunk);
+            }
+        }
+        out
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self, IdeviceError> {
+        let mut items: Vec<(u8, Vec<u8>)> = Vec::new();
+        // Only a full item can be continued
+        let mut continues = false;
+        while !data.is_empty() {
+            if data.len() < 2 {
+                return Err(IdeviceError::NotEnoughBytes(data.len(), 2));
+            }
+            let (kind, len) = (data[0], data[1] as usize);
+            let value = data
+                .get(2..2 + len)
+                .ok_or(IdeviceError::NotEnoughBytes(data.len() - 2, len))?;
+            match items.last_mut() {
+                Some((last, v)) if continues && *last == kind => v.extend_from_slice(value),
+                _ => items.push((kind, value.to_vec())),
+            }
+            continues = len == 255;
+            data = &data[2 + len..];
+        }
+        Ok(Self { items })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_values_are_fragmented() {
+        let key = vec![7; 384];
+        let tlv = Tlv8::new()
+            .with(STATE, [3])
+            .with(PUBLIC_KEY, key.clone())
+            .with(PROOF, []);
+        let encoded = tlv.encode();
+        assert_eq!(encoded.len(), 3 + 2 + 255 + 2 + 129 + 2);
+        assert_eq!(&encoded[3..5], &[PUBLIC_KEY, 255]);
+
+        let decoded = Tlv8::decode(&encoded).unwrap();
+        assert_eq!(decoded, tlv);
+        assert_eq!(decoded.get(PUBLIC_KEY), Some(&key[..]));
+        assert!(decoded.require(SALT).is_err());
+        assert!(Tlv8::decode(&[STATE, 2, 1]).is_err());
+    }
+}
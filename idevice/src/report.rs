@@ -0,0 +1,287 @@
+// Jackson Coxson
+// Reports on many devices at once, as JSON or CSV.
+// Each device gets a row made of named sections, such as its battery health or storage use.
+// JSON keeps the sections nested. CSV flattens them into ``section.field`` columns, and
+// devices that lack a column leave the cell empty, so one sheet can hold a whole fleet.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{lockdownd::DeviceSnapshot, pretty::is_secret, pretty::REDACTED, IdeviceError};
+
+/// What's known about one device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceReport {
+    pub udid: String,
+    /// Each section in the order it was added
+    pub sections: Vec<(String, Value)>,
+}
+
+impl DeviceReport {
+    pub fn new(udid: impl Into<String>) -> Self {
+        Self {
+            udid: udid.into(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Adds anything serde can serialize as a section, replacing one with the same name
+    pub fn section(
+        mut self,
+        name: impl Into<String>,
+        value: &impl Serialize,
+    ) -> Result<Self, IdeviceError> {
+        self.insert(name.into(), serde_json::to_value(value)?);
+        Ok(self)
+    }
+
+    /// Adds a snapshot as the ``snapshot`` section, with secrets redacted and data as hex
+    pub fn snapshot(mut self, snapshot: &DeviceSnapshot) -> Self {
+        let domains = snapshot
+            .domains
+            .iter()
+            .map(|(name, values)| (name.clone(), dictionary_to_json(values)))
+            .collect();
+        let mut section = Map::new();
+        section.insert("values".into(), dictionary_to_json(&snapshot.values));
+        section.insert("domains".into(), Value::Object(domains));
+        self.insert("snapshot".into(), Value::Object(section));
+        self
+    }
+
+    /// Adds battery health as the ``battery`` section, along with the health percentage
+    #[cfg(feature = "diagnostics")]
+    pub fn battery(mut self, health: &crate::diagnostics::battery::BatteryHealth) -> Self {
+        let mut section = Map::new();
+        section.insert("design_capacity".into(), health.design_capacity.into());
+        section.insert("max_capacity".into(), health.max_capacity.into());
+        section.insert("health_percent".into(), health.health_percent().into());
+        section.insert("cycle_count".into(), health.cycle_count.into());
+        section.insert("temperature".into(), health.temperature.into());
+        section.insert("serial".into(), health.serial.clone().into());
+        self.insert("battery".into(), Value::Object(section));
+        self
+    }
+
+    /// Adds storage use as the ``storage`` section, along with the space used
+    #[cfg(feature = "storage")]
+    pub fn storage(mut self, usage: &crate::storage::StorageUsage) -> Self {
+        let mut section = Map::new();
+        section.insert("capacity".into(), usage.capacity.into());
+        section.insert("available".into(), usage.available.into());
+        section.insert("used".into(), usage.used().into());
+        section.insert("system".into(), usage.system.into());
+        section.insert("apps".into(), usage.apps.into());
+        section.insert("media".into(), usage.media.into());
+        section.insert("other".into(), usage.other.into());
+        section.insert("app_count".into(), usage.app_usage.len().into());
+        self.insert("storage".into(), Value::Object(section));
+        self
+    }
+
+    fn insert(&mut self, name: String, value: Value) {
+        match self.sections.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.sections.push((name, value)),
+        }
+    }
+
+    /// The report as one JSON object, with the UDID under ``udid``
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("udid".into(), self.udid.clone().into());
+        for (name, value) in &self.sections {
+            object.insert(name.clone(), value.clone());
+        }
+        Value::Object(object)
+    }
+
+    /// Every cell of the report as a column name and its text
+    pub fn columns(&self) -> Vec<(String, String)> {
+        let mut out = vec![("udid".to_string(), self.udid.clone())];
+        for (name, value) in &self.sections {
+            flatten(name, value, &mut out);
+        }
+        out
+    }
+}
+
+/// Reports on several devices, rendered as one document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FleetReport {
+    pub devices: Vec<DeviceReport>,
+}
+
+impl FleetReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, device: DeviceReport) {
+        self.devices.push(device);
+    }
+
+    /// A JSON array with an object for each device
+    pub fn to_json(&self) -> String {
+        let devices = self.devices.iter().map(|d| d.to_json()).collect();
+        format!("{:#}", Value::Array(devices))
+    }
+
+    /// A CSV document with a header row and a row for each device.
+    /// The columns are every column any device has, in the order they were first seen.
+    /// Arrays are written as JSON in a single cell.
+    pub fn to_csv(&self) -> String {
+        let rows = self.devices.iter().map(|d| d.columns()).collect::<Vec<_>>();
+        let mut header: Vec<&str> = Vec::new();
+        for row in &rows {
+            for (column, _) in row {
+                if !header.contains(&column.as_str()) {
+                    header.push(column);
+                }
+            }
+        }
+
+        let mut out = csv_line(header.iter().copied());
+        for row in &rows {
+            out.push_str(&csv_line(header.iter().map(|column| {
+                row.iter()
+                    .find(|(c, _)| c == column)
+                    .map(|(_, v)| v.as_str())
+                    .unwrap_or("")
+            })));
+        }
+        out
+    }
+}
+
+impl FromIterator<DeviceReport> for FleetReport {
+    fn from_iter<T: IntoIterator<Item = DeviceReport>>(iter: T) -> Self {
+        Self {
+            devices: iter.into_iter().collect(),
+        }
+    }
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                flatten(&format!("{prefix}.{key}"), value, out);
+            }
+        }
+        Value::Null => out.push((prefix.to_string(), String::new())),
+        Value::String(s) => out.push((prefix.to_string(), s.clone())),
+        // Bools, numbers and arrays
+        v => out.push((prefix.to_string(), v.to_string())),
+    }
+}
+
+/// One CSV record, quoting the cells that need it
+fn csv_line<'a>(cells: impl Iterator<Item = &'a str>) -> String {
+    let mut line = cells
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+fn dictionary_to_json(dict: &plist::Dictionary) -> Value {
+    Value::Object(
+        dict.iter()
+            .map(|(key, value)| {
+                let value = if is_secret(key) {
+                    REDACTED.into()
+                } else {
+                    plist_to_json(value)
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+    )
+}
+
+fn plist_to_json(value: &plist::Value) -> Value {
+    match value {
+        plist::Value::Dictionary(d) => dictionary_to_json(d),
+        plist::Value::Array(a) => Value::Array(a.iter().map(plist_to_json).collect()),
+        plist::Value::Boolean(b) => (*b).into(),
+        plist::Value::Integer(i) => match (i.as_signed(), i.as_unsigned()) {
+            (Some(i), _) => i.into(),
+            (None, Some(u)) => u.into(),
+            _ => Value::Null,
+        },
+        plist::Value::Real(r) => (*r).into(),
+        plist::Value::String(s) => s.clone().into(),
+        plist::Value::Data(d) => d
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+            .into(),
+        plist::Value::Date(d) => d.to_xml_format().into(),
+        plist::Value::Uid(u) => u.get().into(),
+        _ => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Enrollment {
+        group: &'static str,
+        tags: Vec<&'static str>,
+    }
+
+    #[test]
+    fn devices_are_aggregated() {
+        let mut snapshot = DeviceSnapshot::default();
+        snapshot
+            .values
+            .insert("DeviceName".into(), "Kiosk, lobby".into());
+        snapshot
+            .values
+            .insert("EscrowBag".into(), plist::Value::Data(vec![1, 2, 3]));
+
+        let report: FleetReport = [
+            DeviceReport::new("00008030-0001")
+                .snapshot(&snapshot)
+                .section(
+                    "enrollment",
+                    &Enrollment {
+                        group: "lobby",
+                        tags: vec!["kiosk", "floor 1"],
+                    },
+                )
+                .unwrap(),
+            DeviceReport::new("00008030-0002"),
+        ]
+        .into_iter()
+        .collect();
+
+        let csv = report.to_csv();
+        let lines = csv.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "udid,snapshot.values.DeviceName,snapshot.values.EscrowBag,enrollment.group,enrollment.tags",
+                "00008030-0001,\"Kiosk, lobby\",<redacted>,lobby,\"[\"\"kiosk\"\",\"\"floor 1\"\"]\"",
+                "00008030-0002,,,,",
+                "",
+            ]
+        );
+
+        let json: Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json[0]["udid"], "00008030-0001");
+        assert_eq!(json[0]["snapshot"]["values"]["EscrowBag"], REDACTED);
+        assert_eq!(json[0]["enrollment"]["tags"][1], "floor 1");
+        assert_eq!(json[1], serde_json::json!({ "udid": "00008030-0002" }));
+    }
+}
@@ -0,0 +1,325 @@
+// Jackson Coxson
+// The async runtime the crate runs on.
+// Protocol code only needs tokio's IO traits, which work on any executor. Sockets, timers
+// and background tasks come from a runtime instead, tokio unless another one is set.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::path::Path;
+
+use log::warn;
+use tokio::sync::oneshot;
+
+use crate::ReadWrite;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What the crate needs from an executor
+pub trait Runtime: Send + Sync + 'static {
+    /// Runs a task in the background, without waiting for it
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+
+    fn connect_tcp(&self, addr: SocketAddr) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>>;
+
+    #[cfg(unix)]
+    fn connect_unix(&self, path: &Path) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>>;
+}
+
+static RUNTIME: OnceLock<Box<dyn Runtime>> = OnceLock::new();
+
+/// Sets the runtime for the rest of the process. This has to happen before anything
+/// connects, the runtime can't be changed once it's been used.
+/// Gives the runtime back if one was already set.
+pub fn set_runtime(runtime: Box<dyn Runtime>) -> Result<(), Box<dyn Runtime>> {
+    RUNTIME.set(runtime)
+}
+
+pub(crate) fn runtime() -> &'static dyn Runtime {
+    RUNTIME.get_or_init(|| Box::new(TokioRuntime)).as_ref()
+}
+
+pub(crate) async fn sleep(duration: Duration) {
+    runtime().sleep(duration).await
+}
+
+/// Waits for the future, failing with ``Timeout`` if it takes longer than the duration
+#[cfg(any(test, feature = "sysdiagnose"))]
+pub(crate) async fn timeout<F: Future>(
+    duration: Duration,
+    future: F,
+) -> Result<F::Output, crate::IdeviceError> {
+    tokio::select! {
+        res = future => Ok(res),
+        _ = sleep(duration) => Err(crate::IdeviceError::Timeout),
+    }
+}
+
+/// Runs a task in the background on the runtime, handing back its output
+#[cfg(any(test, feature = "debug_proxy", feature = "events"))]
+pub(crate) fn spawn<T: Send + 'static>(task: impl Future<Output = T> + Send + 'static) -> Task<T> {
+    let (output_tx, output) = oneshot::channel();
+    let (abort, aborted) = oneshot::channel();
+    runtime().spawn(Box::pin(async move {
+        tokio::select! {
+            res = task => {
+                let _ = output_tx.send(res);
+            }
+            // Only ``abort`` stops the task, not dropping its handle
+            Ok(()) = aborted => {}
+        }
+    }));
+    Task {
+        output,
+        abort: Some(abort),
+    }
+}
+
+/// A task started by the crate, like a ``JoinHandle`` on any runtime.
+/// Awaiting it gives the task's output, or ``None`` if it was aborted or panicked.
+/// Dropping it leaves the task running.
+#[derive(Debug)]
+pub struct Task<T> {
+    output: oneshot::Receiver<T>,
+    abort: Option<oneshot::Sender<()>>,
+}
+
+impl<T> Task<T> {
+    /// Stops the task the next time it waits on something
+    pub fn abort(&mut self) {
+        if let Some(abort) = self.abort.take() {
+            let _ = abort.send(());
+        }
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.output).poll(cx).map(Result::ok)
+    }
+}
+
+pub(crate) async fn connect_tcp(addr: SocketAddr) -> io::Result<Box<dyn ReadWrite>> {
+    runtime().connect_tcp(addr).await
+}
+
+#[cfg(unix)]
+pub(crate) async fn connect_unix(path: &Path) -> io::Result<Box<dyn ReadWrite>> {
+    runtime().connect_unix(path).await
+}
+
+/// The default, which needs to be called from inside a tokio runtime
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        // tokio::spawn panics outside a runtime, which can happen from a drop
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(task);
+            }
+            Err(_) => warn!("No tokio runtime to run a background task on, it won't run"),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn connect_tcp(&self, addr: SocketAddr) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        Box::pin(async move {
+            let stream = tokio::net::TcpStream::connect(addr).await?;
+            Ok(Box::new(stream) as Box<dyn ReadWrite>)
+        })
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(&self, path: &Path) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            Ok(Box::new(stream) as Box<dyn ReadWrite>)
+        })
+    }
+}
+
+#[cfg(feature = "smol")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "smol")]
+impl Runtime for SmolRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        smol::spawn(task).detach();
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            smol::Timer::after(duration).await;
+        })
+    }
+
+    fn connect_tcp(&self, addr: SocketAddr) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        Box::pin(async move {
+            let stream = smol::net::TcpStream::connect(addr).await?;
+            Ok(Box::new(FuturesIo::new(stream)) as Box<dyn ReadWrite>)
+        })
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(&self, path: &Path) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let stream = smol::net::unix::UnixStream::connect(path).await?;
+            Ok(Box::new(FuturesIo::new(stream)) as Box<dyn ReadWrite>)
+        })
+    }
+}
+
+#[cfg(feature = "async_std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async_std")]
+impl Runtime for AsyncStdRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        // Dropping the handle leaves the task running
+        async_std::task::spawn(task);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async_std::task::sleep(duration))
+    }
+
+    fn connect_tcp(&self, addr: SocketAddr) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        Box::pin(async move {
+            let stream = async_std::net::TcpStream::connect(addr).await?;
+            Ok(Box::new(FuturesIo::new(stream)) as Box<dyn ReadWrite>)
+        })
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(&self, path: &Path) -> BoxFuture<'static, io::Result<Box<dyn ReadWrite>>> {
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let stream = async_std::os::unix::net::UnixStream::connect(path).await?;
+            Ok(Box::new(FuturesIo::new(stream)) as Box<dyn ReadWrite>)
+        })
+    }
+}
+
+/// Implements tokio's IO traits for a socket written against the ``futures`` ones, so that
+/// it can be used as a ``ReadWrite``
+#[cfg(feature = "futures_io")]
+#[derive(Debug)]
+pub struct FuturesIo<T>(T);
+
+#[cfg(feature = "futures_io")]
+impl<T> FuturesIo<T> {
+    pub fn new(inner: T) -> Self {
+        Self(inner)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[cfg(feature = "futures_io")]
+impl<T: futures_io::AsyncRead + Unpin> tokio::io::AsyncRead for FuturesIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        let read = Pin::new(&mut self.0).poll_read(cx, buf.initialize_unfilled());
+        let n = std::task::ready!(read)?;
+        buf.advance(n);
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures_io")]
+impl<T: futures_io::AsyncWrite + Unpin> tokio::io::AsyncWrite for FuturesIo<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn tokio_is_the_default() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (sent, done) = tokio::sync::oneshot::channel();
+        runtime().spawn(Box::pin(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"hi").await.unwrap();
+            sent.send(()).unwrap();
+        }));
+
+        let mut stream = connect_tcp(addr).await.unwrap();
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hi");
+        done.await.unwrap();
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    #[tokio::test]
+    async fn tasks_give_their_output_until_aborted() {
+        assert_eq!(spawn(async { 1 }).await, Some(1));
+        assert!(
+            timeout(Duration::from_millis(1), std::future::pending::<()>())
+                .await
+                .is_err()
+        );
+
+        let (tx, rx) = oneshot::channel::<()>();
+        let mut task = spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await
+        });
+        task.abort();
+        assert_eq!(task.await, None);
+        // The task was dropped, and with it the sender
+        assert!(rx.await.is_err());
+    }
+}
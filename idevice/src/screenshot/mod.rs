@@ -2,24 +2,34 @@
 //!
 //! This module provides functionality to capture screenshots from iOS devices.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
+use crate::{provider::IdeviceProvider, Idevice, IdeviceError, IdeviceService, ReadWrite};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 const SCREENSHOTR_SERVICE_NAME: &str = "com.apple.screenshotr";
 
 /// Screenshot client for capturing device screens
 pub struct ScreenshotClient {
-    socket: tokio::net::TcpStream,
+    socket: Box<dyn ReadWrite>,
+}
+
+impl IdeviceService for ScreenshotClient {
+    fn service_name() -> &'static str {
+        SCREENSHOTR_SERVICE_NAME
+    }
+
+    async fn connect(provider: &dyn IdeviceProvider) -> Result<Self, IdeviceError> {
+        Ok(Self::new(provider.start_service_raw(Self::service_name()).await?))
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice.into_inner()?))
+    }
 }
 
 impl ScreenshotClient {
-    /// Connect to the screenshot service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(SCREENSHOTR_SERVICE_NAME).await?;
-        
-        Ok(Self {
-            socket: service.socket,
-        })
+    /// Create a screenshot client over an existing connection to screenshotr
+    pub fn new(socket: Box<dyn ReadWrite>) -> Self {
+        Self { socket }
     }
 
     /// Take a screenshot from the device
@@ -71,8 +81,8 @@ impl ScreenshotClient {
 
     // Helper methods
     async fn send_plist(&mut self, dict: &plist::Dictionary) -> Result<(), IdeviceError> {
-        let xml = plist::to_format_xml(dict)?;
-        let xml_bytes = xml.into_bytes();
+        let mut xml_bytes = Vec::new();
+        plist::to_writer_xml(&mut xml_bytes, dict)?;
         
         // Send the length as a 32-bit big-endian integer
         let len = (xml_bytes.len() as u32).to_be_bytes();
@@ -91,7 +101,7 @@ impl ScreenshotClient {
         let len = u32::from_be_bytes(len_buf) as usize;
         
         // Read the XML data
-        let mut data = vec![0u8; len];
+        let mut data = vec![0u8; crate::util::check_message_size(len)?];
         self.socket.read_exact(&mut data).await?;
         
         // Parse the XML data
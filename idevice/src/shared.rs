@@ -0,0 +1,220 @@
+// Jackson Coxson
+// Sharing one connection between tasks.
+// Services answer one request at a time, so a server juggling many callers would otherwise
+// open a connection for each. Here requests queue for the connection in the order they're made.
+
+use std::sync::Arc;
+
+use tokio::sync::{oneshot, Mutex, MutexGuard};
+
+use crate::{runtime::BoxFuture, IdeviceError};
+
+#[cfg(feature = "afc")]
+use crate::afc::AfcClient;
+use crate::lockdownd::{DomainValues, LockdowndClient};
+
+/// An AFC connection any number of tasks can use at once
+#[cfg(feature = "afc")]
+pub type SharedAfcClient = Shared<AfcClient>;
+
+/// A lockdown connection any number of tasks can use at once
+pub type SharedLockdown = Shared<LockdowndClient>;
+
+/// A client that clones can use from different tasks.
+///
+/// Requests wait for the ones before them and then run one at a time, first come first
+/// served. Once a request has its turn it's sent from its own task, so a caller that gives
+/// up waiting for the answer can't leave the connection half way through a request.
+pub struct Shared<C> {
+    client: Arc<Mutex<C>>,
+}
+
+impl<C> Clone for Shared<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<C> std::fmt::Debug for Shared<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("clones", &Arc::strong_count(&self.client))
+            .finish()
+    }
+}
+
+impl<C: Send + 'static> Shared<C> {
+    pub fn new(client: C) -> Self {
+        Self {
+            client: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    /// Waits for its turn and then has the client to itself until the guard is dropped, for
+    /// a sequence of requests nothing else should come between.
+    /// Unlike the other methods, a request cancelled through the guard isn't finished.
+    pub async fn lock(&self) -> MutexGuard<'_, C> {
+        self.client.lock().await
+    }
+
+    /// Takes the client back, or returns the wrapper if other clones are still around
+    pub fn into_inner(self) -> Result<C, Self> {
+        match Arc::try_unwrap(self.client) {
+            Ok(client) => Ok(client.into_inner()),
+            Err(client) => Err(Self { client }),
+        }
+    }
+
+    /// Queues a request and waits for its answer
+    pub async fn run<T, F>(&self, request: F) -> Result<T, IdeviceError>
+    where
+        T: Send + 'static,
+        F: for<'c> FnOnce(&'c mut C) -> BoxFuture<'c, Result<T, IdeviceError>> + Send + 'static,
+    {
+        let mut client = self.client.clone().lock_owned().await;
+        let (sender, receiver) = oneshot::channel();
+        crate::runtime::runtime().spawn(Box::pin(async move {
+            let _ = sender.send(request(&mut client).await);
+        }));
+        match receiver.await {
+            Ok(res) => res,
+            // The task never finished, so the connection is in an unknown state
+            Err(_) => Err(IdeviceError::NoEstablishedConnection),
+        }
+    }
+}
+
+#[cfg(feature = "afc")]
+impl Shared<AfcClient> {
+    pub async fn read_directory(&self, path: &str) -> Result<Vec<String>, IdeviceError> {
+        let path = path.to_string();
+        self.run(move |c| Box::pin(async move { c.read_directory(&path).await }))
+            .await
+    }
+
+    pub async fn get_file_info(
+        &self,
+        path: &str,
+    ) -> Result<std::collections::HashMap<String, String>, IdeviceError> {
+        let path = path.to_string();
+        self.run(move |c| Box::pin(async move { c.get_file_info(&path).await }))
+            .await
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>, IdeviceError> {
+        let path = path.to_string();
+        self.run(move |c| Box::pin(async move { c.read_file(&path).await }))
+            .await
+    }
+
+    pub async fn write_file(&self, path: &str, data: Vec<u8>) -> Result<(), IdeviceError> {
+        let path = path.to_string();
+        self.run(move |c| Box::pin(async move { c.write_file(&path, &data).await }))
+            .await
+    }
+
+    pub async fn make_directory(&self, path: &str) -> Result<(), IdeviceError> {
+        let path = path.to_string();
+        self.run(move |c| Box::pin(async move { c.make_directory(&path).await }))
+            .await
+    }
+
+    pub async fn remove_path(&self, path: &str) -> Result<(), IdeviceError> {
+        let path = path.to_string();
+        self.run(move |c| Box::pin(async move { c.remove_path(&path).await }))
+            .await
+    }
+
+    pub async fn rename_path(&self, from_path: &str, to_path: &str) -> Result<(), IdeviceError> {
+        let from_path = from_path.to_string();
+        let to_path = to_path.to_string();
+        self.run(move |c| Box::pin(async move { c.rename_path(&from_path, &to_path).await }))
+            .await
+    }
+}
+
+impl Shared<LockdowndClient> {
+    pub async fn get_value(&self, value: impl Into<String>) -> Result<plist::Value, IdeviceError> {
+        let value = value.into();
+        self.run(move |c| Box::pin(async move { c.get_value(value).await }))
+            .await
+    }
+
+    pub async fn get_all_values(&self) -> Result<plist::Dictionary, IdeviceError> {
+        self.run(move |c| Box::pin(async move { c.get_all_values().await }))
+            .await
+    }
+
+    pub async fn get_domain_values(
+        &self,
+        domain: impl Into<String>,
+    ) -> Result<plist::Dictionary, IdeviceError> {
+        let domain = domain.into();
+        self.run(move |c| Box::pin(async move { c.get_domain_values(domain).await }))
+            .await
+    }
+
+    pub async fn get_domain(&self, domain: &str) -> Result<DomainValues, IdeviceError> {
+        let domain = domain.to_string();
+        self.run(move |c| Box::pin(async move { c.get_domain(&domain).await }))
+            .await
+    }
+
+    pub async fn start_service(
+        &self,
+        identifier: impl Into<String>,
+    ) -> Result<(u16, bool), IdeviceError> {
+        let identifier = identifier.into();
+        self.run(move |c| Box::pin(async move { c.start_service(identifier).await }))
+            .await
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "afc"))]
+mod tests {
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::{AfcResponder, MockProvider};
+    use crate::IdeviceService;
+
+    #[tokio::test]
+    async fn shared_clients_serve_concurrent_tasks() {
+        let responder = AfcResponder::new().with_directory("/Downloads");
+        let afc = SharedAfcClient::new(client(&responder));
+
+        let mut tasks = Vec::new();
+        for i in 0..16 {
+            let afc = afc.clone();
+            tasks.push(tokio::spawn(async move {
+                let path = format!("/Downloads/{i}.txt");
+                afc.write_file(&path, vec![i; 100]).await.unwrap();
+                afc.read_file(&path).await.unwrap()
+            }));
+        }
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.await.unwrap(), vec![i as u8; 100]);
+        }
+
+        // Abandoning a request part way leaves the connection usable for the next one
+        tokio::select! {
+            biased;
+            _ = afc.write_file("/Downloads/late.txt", b"late".to_vec()) => {}
+            _ = async {} => {}
+        }
+        assert_eq!(afc.read_file("/Downloads/late.txt").await.unwrap(), b"late");
+        assert_eq!(afc.read_directory("/Downloads").await.unwrap().len(), 19);
+        assert!(afc.into_inner().is_ok());
+
+        let provider = MockProvider::new("test").unwrap();
+        let lockdown = SharedLockdown::new(LockdowndClient::connect(&provider).await.unwrap());
+        let other = lockdown.clone();
+        let (a, b) = tokio::join!(
+            lockdown.get_value("ProductVersion"),
+            other.get_value("ProductVersion")
+        );
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert!(lockdown.into_inner().is_err());
+    }
+}
@@ -0,0 +1,352 @@
+// Jackson Coxson
+// Sending device events to monitoring systems.
+// A daemon watching a fleet finds out about devices coming and going, re-pairing, batteries
+// running low and new crash reports from different places. Sinks give those one shape and
+// one place to go, so an integration is a single ``EventSink`` instead of glue for each.
+// The webhook sink is behind its own feature since it pulls in an HTTP client.
+
+use std::sync::Arc;
+#[cfg(feature = "webhook_sink")]
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    lockdownd::{LockdowndClient, BATTERY_DOMAIN},
+    provider::IdeviceProvider,
+    re_pairing::RePairEvent,
+    runtime::BoxFuture,
+    IdeviceError, IdeviceService,
+};
+
+/// Something worth telling a monitoring system about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SinkEvent {
+    /// The device showed up in usbmuxd, over ``USB`` or a network address
+    Attached {
+        udid: String,
+        connection: String,
+    },
+    Detached {
+        udid: String,
+    },
+    /// The device was paired with this host, or paired again after forgetting it
+    Paired {
+        udid: String,
+    },
+    /// The device forgot this host and has to be paired again by hand
+    RepairRequired {
+        udid: String,
+    },
+    LowBattery {
+        udid: String,
+        percent: u64,
+    },
+    /// A crash report that wasn't there the last time the device was checked
+    CrashReport {
+        udid: String,
+        name: String,
+    },
+}
+
+impl SinkEvent {
+    pub fn udid(&self) -> &str {
+        match self {
+            Self::Attached { udid, .. }
+            | Self::Detached { udid }
+            | Self::Paired { udid }
+            | Self::RepairRequired { udid }
+            | Self::LowBattery { udid, .. }
+            | Self::CrashReport { udid, .. } => udid,
+        }
+    }
+}
+
+/// Somewhere events are sent.
+/// This is an ugly trait until async traits are usable as trait objects
+pub trait EventSink: Send + Sync {
+    fn publish<'a>(&'a self, event: &'a SinkEvent) -> BoxFuture<'a, Result<(), IdeviceError>>;
+}
+
+/// The registered sinks. Clones share them.
+#[derive(Clone, Default)]
+pub struct Sinks {
+    sinks: Vec<Arc<dyn EventSink>>,
+}
+
+impl Sinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.register(sink);
+        self
+    }
+
+    pub fn register(&mut self, sink: impl EventSink + 'static) {
+        self.sinks.push(Arc::new(sink));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Sends the event to every sink in turn. A sink that fails is logged and skipped, so
+    /// one unreachable endpoint doesn't hold back the rest.
+    /// Returns how many sinks took the event.
+    pub async fn publish(&self, event: SinkEvent) -> usize {
+        let mut delivered = 0;
+        for sink in &self.sinks {
+            match sink.publish(&event).await {
+                Ok(()) => delivered += 1,
+                Err(e) => warn!("A sink failed to take {event:?}: {e:?}"),
+            }
+        }
+        delivered
+    }
+
+    /// A callback for ``RePairPolicy::with_events`` that publishes in the background
+    pub fn re_pair_events(&self) -> impl Fn(RePairEvent) + Send + Sync + 'static {
+        let sinks = self.clone();
+        move |event| {
+            let event = match event {
+                RePairEvent::Repaired(udid) => SinkEvent::Paired { udid },
+                RePairEvent::RepairRequired(udid) => SinkEvent::RepairRequired { udid },
+                RePairEvent::WaitingForTrust(_) => return,
+            };
+            let sinks = sinks.clone();
+            crate::runtime::runtime().spawn(Box::pin(async move {
+                sinks.publish(event).await;
+            }));
+        }
+    }
+
+    /// Reads the battery level and publishes ``LowBattery`` if it's under ``threshold``
+    /// percent. Returns the level.
+    pub async fn check_battery(
+        &self,
+        provider: &dyn IdeviceProvider,
+        threshold: u64,
+    ) -> Result<u64, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        let udid = device_udid(&mut lockdown).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+        let percent = lockdown
+            .get_domain_values(BATTERY_DOMAIN)
+            .await?
+            .get("BatteryCurrentCapacity")
+            .and_then(|c| c.as_unsigned_integer())
+            .ok_or(IdeviceError::UnexpectedResponse)?;
+        if percent < threshold {
+            self.publish(SinkEvent::LowBattery { udid, percent }).await;
+        }
+        Ok(percent)
+    }
+
+    /// Lists the device's crash reports and publishes the ones not in ``seen``, adding them
+    /// to it. Start with an empty set to report everything already on the device, or with
+    /// the first listing to only report what comes after.
+    #[cfg(feature = "afc")]
+    pub async fn check_crash_reports(
+        &self,
+        provider: &dyn IdeviceProvider,
+        seen: &mut std::collections::HashSet<String>,
+    ) -> Result<usize, IdeviceError> {
+        let udid = device_udid(&mut LockdowndClient::connect(provider).await?).await?;
+        let mut afc =
+            crate::afc::AfcClient::connect_to_service(provider, "com.apple.crashreportcopymobile")
+                .await?;
+        let mut new = 0;
+        for name in afc.read_directory("/").await? {
+            if name == "." || name == ".." || !seen.insert(name.clone()) {
+                continue;
+            }
+            new += 1;
+            self.publish(SinkEvent::CrashReport {
+                udid: udid.clone(),
+                name,
+            })
+            .await;
+        }
+        Ok(new)
+    }
+
+    /// Publishes attach, detach and pairing events from usbmuxd until the stream ends.
+    /// Other events in the stream are skipped.
+    #[cfg(feature = "events")]
+    pub async fn forward(&self, mut stream: crate::events::EventStream) {
+        use crate::{events::DeviceEvent, usbmuxd::UsbmuxdEvent};
+
+        // usbmuxd only gives the device ID after the device is gone
+        let mut udids = std::collections::HashMap::new();
+        while let Some(event) = stream.next().await {
+            let event = match event {
+                Ok(e) => e.event,
+                Err(e) => {
+                    debug!("An event source stopped: {e:?}");
+                    continue;
+                }
+            };
+            let event = match event {
+                DeviceEvent::Usbmuxd(UsbmuxdEvent::Attached(device)) => {
+                    udids.insert(device.device_id, device.udid.clone());
+                    SinkEvent::Attached {
+                        udid: device.udid,
+                        connection: device.connection_type.to_string(),
+                    }
+                }
+                DeviceEvent::Usbmuxd(UsbmuxdEvent::Detached(id)) => match udids.remove(&id) {
+                    Some(udid) => SinkEvent::Detached { udid },
+                    None => continue,
+                },
+                DeviceEvent::Usbmuxd(UsbmuxdEvent::Paired(id)) => match udids.get(&id) {
+                    Some(udid) => SinkEvent::Paired { udid: udid.clone() },
+                    None => continue,
+                },
+                _ => continue,
+            };
+            self.publish(event).await;
+        }
+    }
+}
+
+async fn device_udid(lockdown: &mut LockdowndClient) -> Result<String, IdeviceError> {
+    match lockdown.get_value("UniqueDeviceID").await? {
+        plist::Value::String(udid) => Ok(udid),
+        _ => Err(IdeviceError::UnexpectedResponse),
+    }
+}
+
+/// How long a webhook request can take before it's given up on, unless changed with
+/// ``WebhookSink::with_timeout``
+#[cfg(feature = "webhook_sink")]
+pub const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts each event as JSON, with the event's name under ``event``, its fields beside it
+/// and the seconds since the epoch under ``time``
+#[cfg(feature = "webhook_sink")]
+#[derive(Debug, Clone)]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+#[cfg(feature = "webhook_sink")]
+impl WebhookSink {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            headers: Vec::new(),
+            timeout: DEFAULT_WEBHOOK_TIMEOUT,
+        }
+    }
+
+    /// Gives up on a request after this long, so an endpoint that stops answering doesn't
+    /// hold up the events behind it
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a header to every request, such as ``Authorization``
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+#[cfg(feature = "webhook_sink")]
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    time: u64,
+    #[serde(flatten)]
+    event: &'a SinkEvent,
+}
+
+#[cfg(feature = "webhook_sink")]
+impl EventSink for WebhookSink {
+    fn publish<'a>(&'a self, event: &'a SinkEvent) -> BoxFuture<'a, Result<(), IdeviceError>> {
+        Box::pin(async move {
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let mut request = self
+                .client
+                .post(&self.url)
+                .timeout(self.timeout)
+                .json(&WebhookBody { time, event });
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::testing::{MockProvider, MOCK_UDID};
+
+    #[derive(Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<SinkEvent>>>);
+
+    impl EventSink for Recorder {
+        fn publish<'a>(&'a self, event: &'a SinkEvent) -> BoxFuture<'a, Result<(), IdeviceError>> {
+            self.0.lock().unwrap().push(event.clone());
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    struct Unreachable;
+
+    impl EventSink for Unreachable {
+        fn publish<'a>(&'a self, _: &'a SinkEvent) -> BoxFuture<'a, Result<(), IdeviceError>> {
+            Box::pin(async { Err(IdeviceError::Timeout) })
+        }
+    }
+
+    #[tokio::test]
+    async fn events_reach_every_sink() {
+        let recorder = Recorder::default();
+        let sinks = Sinks::new()
+            .with_sink(Unreachable)
+            .with_sink(recorder.clone());
+
+        let event = SinkEvent::Detached {
+            udid: MOCK_UDID.to_string(),
+        };
+        assert_eq!(sinks.publish(event.clone()).await, 1);
+
+        let provider = MockProvider::new("test").unwrap().with_domain_value(
+            BATTERY_DOMAIN,
+            "BatteryCurrentCapacity",
+            15,
+        );
+        assert_eq!(sinks.check_battery(&provider, 10).await.unwrap(), 15);
+        assert_eq!(sinks.check_battery(&provider, 20).await.unwrap(), 15);
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                event,
+                SinkEvent::LowBattery {
+                    udid: MOCK_UDID.to_string(),
+                    percent: 15
+                }
+            ]
+        );
+    }
+}
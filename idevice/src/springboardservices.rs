@@ -0,0 +1,204 @@
+// Jackson Coxson
+// SpringBoard services, for the home screen layout, app icons and wallpapers.
+// The service can only read wallpapers. Nothing over lockdown sets one, supervised devices
+// have to be sent an MDM Settings command instead.
+
+use log::warn;
+
+use crate::{codec::WireFormat, lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+
+/// Which wallpaper to preview
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wallpaper {
+    HomeScreen,
+    LockScreen,
+}
+
+impl Wallpaper {
+    fn name(&self) -> &'static str {
+        match self {
+            Wallpaper::HomeScreen => "homescreen",
+            Wallpaper::LockScreen => "lockscreen",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterfaceOrientation {
+    Unknown,
+    Portrait,
+    PortraitUpsideDown,
+    LandscapeRight,
+    LandscapeLeft,
+}
+
+pub struct SpringBoardServicesClient {
+    pub idevice: Idevice,
+}
+
+impl IdeviceService for SpringBoardServicesClient {
+    fn service_name() -> &'static str {
+        "com.apple.springboardservices"
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(idevice))
+    }
+}
+
+impl SpringBoardServicesClient {
+    pub fn new(mut idevice: Idevice) -> Self {
+        idevice.default_wire_format(WireFormat::for_service(Self::service_name()));
+        Self { idevice }
+    }
+
+    async fn request(
+        &mut self,
+        command: &str,
+        args: plist::Dictionary,
+    ) -> Result<plist::Dictionary, IdeviceError> {
+        let mut req = args;
+        req.insert("command".into(), command.into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        self.idevice.read_plist().await
+    }
+
+    async fn png(
+        &mut self,
+        command: &str,
+        args: plist::Dictionary,
+    ) -> Result<Vec<u8>, IdeviceError> {
+        let res = self.request(command, args).await?;
+        match res.get("pngData").and_then(|d| d.as_data()) {
+            Some(png) => Ok(png.to_vec()),
+            None => {
+                warn!("{command} didn't return pngData: {res:?}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// The home screen layout, one array per page with the dock first.
+    /// Pass it back to ``set_icon_state`` after rearranging it.
+    pub async fn icon_state(&mut self) -> Result<Vec<plist::Value>, IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("command".into(), "getIconState".into());
+        req.insert("formatVersion".into(), "2".into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(req))
+            .await?;
+        match self.idevice.read_plist_value().await? {
+            plist::Value::Array(state) => Ok(state),
+            res => {
+                warn!("Icon state is not an array: {res:?}");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Rearranges the home screen. SpringBoard doesn't answer, and ignores layouts it
+    /// doesn't like.
+    pub async fn set_icon_state(&mut self, state: Vec<plist::Value>) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("command".into(), "setIconState".into());
+        req.insert("iconState".into(), plist::Value::Array(state));
+        self.idevice.send_plist(plist::Value::Dictionary(req)).await
+    }
+
+    /// An app's icon as a PNG
+    pub async fn icon_png(&mut self, bundle_id: &str) -> Result<Vec<u8>, IdeviceError> {
+        let mut args = plist::Dictionary::new();
+        args.insert("bundleId".into(), bundle_id.into());
+        self.png("getIconPNGData", args).await
+    }
+
+    /// The home screen wallpaper as a PNG
+    pub async fn home_screen_wallpaper(&mut self) -> Result<Vec<u8>, IdeviceError> {
+        self.png("getHomeScreenWallpaperPNGData", plist::Dictionary::new())
+            .await
+    }
+
+    /// A preview of a wallpaper as a PNG, as shown in Settings
+    pub async fn wallpaper_preview(
+        &mut self,
+        wallpaper: Wallpaper,
+    ) -> Result<Vec<u8>, IdeviceError> {
+        let mut args = plist::Dictionary::new();
+        args.insert("wallpaperName".into(), wallpaper.name().into());
+        self.png("getWallpaperPreviewImage", args).await
+    }
+
+    pub async fn interface_orientation(&mut self) -> Result<InterfaceOrientation, IdeviceError> {
+        let res = self
+            .request("getInterfaceOrientation", plist::Dictionary::new())
+            .await?;
+        Ok(
+            match res
+                .get("interfaceOrientation")
+                .and_then(|o| o.as_unsigned_integer())
+            {
+                Some(1) => InterfaceOrientation::Portrait,
+                Some(2) => InterfaceOrientation::PortraitUpsideDown,
+                Some(3) => InterfaceOrientation::LandscapeRight,
+                Some(4) => InterfaceOrientation::LandscapeLeft,
+                _ => InterfaceOrientation::Unknown,
+            },
+        )
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{MockProvider, ScriptedResponder};
+
+    #[tokio::test]
+    async fn wallpapers_and_orientation() {
+        let mut png = plist::Dictionary::new();
+        png.insert("pngData".into(), plist::Value::Data(vec![0x89, b'P']));
+        let mut orientation = plist::Dictionary::new();
+        orientation.insert("interfaceOrientation".into(), 3u64.into());
+        let provider = MockProvider::new("test").unwrap().with_service(
+            SpringBoardServicesClient::service_name(),
+            ScriptedResponder::new(vec![png.clone(), png, orientation]),
+        );
+
+        let mut client = SpringBoardServicesClient::connect(&provider).await.unwrap();
+        assert_eq!(
+            client.home_screen_wallpaper().await.unwrap(),
+            vec![0x89, b'P']
+        );
+        assert_eq!(
+            client
+                .wallpaper_preview(Wallpaper::LockScreen)
+                .await
+                .unwrap(),
+            vec![0x89, b'P']
+        );
+        assert_eq!(
+            client.interface_orientation().await.unwrap(),
+            InterfaceOrientation::LandscapeRight
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// Jackson Coxson
+// Where a device's storage went, split up the way Finder shows it.
+// Totals come from lockdown's com.apple.disk_usage domain and app sizes from
+// installation_proxy. Older devices also report photo and media usage in the domain, newer
+// ones leave it out and it ends up counted as other.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    installation_proxy::InstallationProxyClient,
+    lockdownd::{LockdowndClient, DISK_USAGE_DOMAIN},
+    provider::IdeviceProvider,
+    IdeviceError, IdeviceService,
+};
+
+/// Keys in the disk usage domain counted as media
+const MEDIA_USAGE_KEYS: [&str; 4] = [
+    "CameraUsage",
+    "PhotoUsage",
+    "MediaCacheUsage",
+    "VoicemailUsage",
+];
+
+/// The space one app takes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppUsage {
+    pub bundle_id: String,
+    pub name: Option<String>,
+    /// The app itself, in bytes
+    pub app_size: u64,
+    /// Documents, caches and other data the app stored, in bytes
+    pub data_size: u64,
+}
+
+impl AppUsage {
+    pub fn total(&self) -> u64 {
+        self.app_size + self.data_size
+    }
+}
+
+/// Storage use in bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageUsage {
+    pub capacity: u64,
+    pub available: u64,
+    /// The system volume, which the user can't free up
+    pub system: u64,
+    /// Apps and their data
+    pub apps: u64,
+    /// Photos and media, on devices that still report them
+    pub media: u64,
+    /// Used space that isn't any of the above
+    pub other: u64,
+    /// Every app, largest first
+    pub app_usage: Vec<AppUsage>,
+}
+
+impl StorageUsage {
+    /// Splits up the disk usage domain and the sizes of the apps
+    pub fn from_parts(disk_usage: &plist::Dictionary, mut app_usage: Vec<AppUsage>) -> Self {
+        let value = |key: &str| disk_usage.get(key).and_then(|v| v.as_unsigned_integer());
+
+        let capacity = value("TotalDiskCapacity").unwrap_or_default();
+        let data_capacity = value("TotalDataCapacity");
+        let available = value("TotalDataAvailable")
+            .or_else(|| value("AmountDataAvailable"))
+            .unwrap_or_default();
+        let system = match data_capacity {
+            Some(data) if capacity > data => capacity - data,
+            _ => value("TotalSystemCapacity").unwrap_or_default(),
+        };
+
+        app_usage.sort_by_key(|a| std::cmp::Reverse(a.total()));
+        let apps = app_usage.iter().map(|a| a.total()).sum();
+        let media = MEDIA_USAGE_KEYS.iter().filter_map(|k| value(k)).sum();
+        let used = data_capacity
+            .unwrap_or(capacity.saturating_sub(system))
+            .saturating_sub(available);
+
+        Self {
+            capacity,
+            available,
+            system,
+            apps,
+            media,
+            other: used.saturating_sub(apps).saturating_sub(media),
+            app_usage,
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.capacity.saturating_sub(self.available)
+    }
+}
+
+/// Reads the device's storage use
+pub async fn usage(provider: &dyn IdeviceProvider) -> Result<StorageUsage, IdeviceError> {
+    let mut lockdown = LockdowndClient::connect(provider).await?;
+    lockdown
+        .start_session(&provider.get_pairing_file().await?)
+        .await?;
+    let disk_usage = lockdown.get_domain_values(DISK_USAGE_DOMAIN).await?;
+
+    let mut instproxy = InstallationProxyClient::connect(provider).await?;
+    let apps = instproxy
+        .get_app_attributes(
+            None,
+            &[
+                "CFBundleIdentifier",
+                "CFBundleDisplayName",
+                "StaticDiskUsage",
+                "DynamicDiskUsage",
+            ],
+        )
+        .await?;
+
+    let mut app_usage = Vec::new();
+    for (bundle_id, app) in apps {
+        let app = match app.as_dictionary() {
+            Some(a) => a,
+            None => {
+                warn!("Attributes of {bundle_id} were not a dictionary");
+                continue;
+            }
+        };
+        let size = |key: &str| {
+            app.get(key)
+                .and_then(|v| v.as_unsigned_integer())
+                .unwrap_or_default()
+        };
+        app_usage.push(AppUsage {
+            name: app
+                .get("CFBundleDisplayName")
+                .and_then(|n| n.as_string())
+                .map(|n| n.to_string()),
+            app_size: size("StaticDiskUsage"),
+            data_size: size("DynamicDiskUsage"),
+            bundle_id,
+        });
+    }
+    Ok(StorageUsage::from_parts(&disk_usage, app_usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1_000_000_000;
+
+    #[test]
+    fn usage_is_split_up() {
+        let mut disk_usage = plist::Dictionary::new();
+        disk_usage.insert("TotalDiskCapacity".into(), (128 * GB).into());
+        disk_usage.insert("TotalDataCapacity".into(), (118 * GB).into());
+        disk_usage.insert("TotalDataAvailable".into(), (40 * GB).into());
+        disk_usage.insert("PhotoUsage".into(), (20 * GB).into());
+
+        let app = |bundle_id: &str, app_size, data_size| AppUsage {
+            bundle_id: bundle_id.to_string(),
+            name: None,
+            app_size,
+            data_size,
+        };
+        let usage = StorageUsage::from_parts(
+            &disk_usage,
+            vec![
+                app("com.example.small", GB, 0),
+                app("com.example.big", 5 * GB, 10 * GB),
+            ],
+        );
+
+        assert_eq!(usage.capacity, 128 * GB);
+        assert_eq!(usage.available, 40 * GB);
+        assert_eq!(usage.system, 10 * GB);
+        assert_eq!(usage.apps, 16 * GB);
+        assert_eq!(usage.media, 20 * GB);
+        assert_eq!(usage.other, 42 * GB);
+        assert_eq!(usage.used(), 88 * GB);
+        assert_eq!(usage.app_usage[0].bundle_id, "com.example.big");
+    }
+}
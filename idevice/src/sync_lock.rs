@@ -0,0 +1,199 @@
+// Jackson Coxson
+// The sync lock, which keeps this crate and Finder or iTunes from working on a device at once.
+// A host that syncs holds an exclusive lock on a file over AFC, and posts notifications around
+// taking and giving it back so the device and other hosts know a sync is going on.
+
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::{
+    afc::{AfcClient, AfcFile, AfcFopenMode, AfcLock},
+    notification_proxy::{NotificationProxyClient, NotificationType},
+    provider::IdeviceProvider,
+    IdeviceError, IdeviceService,
+};
+
+/// The file hosts lock while they sync
+pub const SYNC_LOCK_PATH: &str = "/com.apple.itunes.lock_sync";
+
+/// How many times the lock is tried before giving up, the same as iTunes
+const LOCK_ATTEMPTS: u32 = 50;
+/// Waited between tries
+const LOCK_WAIT: Duration = Duration::from_millis(200);
+
+/// Held while syncing, from [`sync_lock`]
+///
+/// ``release`` gives the lock back and tells the device the sync finished. Dropping the guard
+/// closes its connections, which frees the lock, but the device isn't told and can keep
+/// showing a sync in progress.
+pub struct SyncLock {
+    afc: AfcClient,
+    notifications: NotificationProxyClient,
+    handle: u64,
+}
+
+/// Connects to AFC and the notification proxy and takes the sync lock.
+/// Fails with ``SyncInProgress`` if another host doesn't give it up within about 10 seconds.
+pub async fn sync_lock(provider: &dyn IdeviceProvider) -> Result<SyncLock, IdeviceError> {
+    let afc = AfcClient::connect(provider).await?;
+    let notifications = NotificationProxyClient::connect(provider).await?;
+    SyncLock::acquire(afc, notifications).await
+}
+
+impl SyncLock {
+    /// Takes the sync lock over existing connections
+    pub async fn acquire(
+        mut afc: AfcClient,
+        mut notifications: NotificationProxyClient,
+    ) -> Result<Self, IdeviceError> {
+        let handle = lock(&mut afc, &mut notifications).await?;
+        Ok(Self {
+            afc,
+            notifications,
+            handle,
+        })
+    }
+
+    /// The AFC connection holding the lock, for the work done under it
+    pub fn afc(&mut self) -> &mut AfcClient {
+        &mut self.afc
+    }
+
+    /// Gives the lock back and tells the device the sync finished
+    pub async fn release(mut self) -> Result<(), IdeviceError> {
+        unlock(&mut self.afc, &mut self.notifications, self.handle).await
+    }
+}
+
+/// Takes the sync lock over connections the caller keeps, returning the handle of the
+/// locked file for ``unlock``. Posts the host's sync notifications the same as [`SyncLock`].
+pub(crate) async fn lock(
+    afc: &mut AfcClient,
+    notifications: &mut NotificationProxyClient,
+) -> Result<u64, IdeviceError> {
+    notifications
+        .post_notification(NotificationType::ITunesSyncWillStart)
+        .await?;
+    match take(afc, notifications).await {
+        Ok(handle) => Ok(handle),
+        Err(e) => {
+            // Tell the device the sync won't happen after all
+            if let Err(e) = notifications
+                .post_notification(NotificationType::ITunesSyncDidFinish)
+                .await
+            {
+                warn!("Failed to end the sync: {e:?}");
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Gives back a lock from ``lock`` and tells the device the sync finished
+pub(crate) async fn unlock(
+    afc: &mut AfcClient,
+    notifications: &mut NotificationProxyClient,
+    handle: u64,
+) -> Result<(), IdeviceError> {
+    let mut file = AfcFile::from_handle(afc, handle);
+    let res = match file.unlock().await {
+        Ok(()) => file.close().await,
+        Err(e) => Err(e),
+    };
+    // Always end the sync, or the device keeps showing the sync indicator
+    notifications
+        .post_notification(NotificationType::ITunesSyncDidFinish)
+        .await?;
+    res
+}
+
+/// Opens and locks the lock file, leaving it open
+async fn take(
+    afc: &mut AfcClient,
+    notifications: &mut NotificationProxyClient,
+) -> Result<u64, IdeviceError> {
+    let mut file = AfcFile::open(afc, SYNC_LOCK_PATH, AfcFopenMode::Rw).await?;
+    notifications
+        .post_notification(NotificationType::ITunesSyncLockRequest)
+        .await?;
+
+    for attempt in 1..=LOCK_ATTEMPTS {
+        if file.try_lock(AfcLock::Exclusive).await? {
+            notifications
+                .post_notification(NotificationType::ITunesSyncDidStart)
+                .await?;
+            return Ok(file.into_handle());
+        }
+        debug!("The sync lock is held by another host, try {attempt}/{LOCK_ATTEMPTS}");
+        crate::runtime::sleep(LOCK_WAIT).await;
+    }
+
+    file.close().await?;
+    Err(IdeviceError::SyncInProgress)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::afc::tests::client;
+    use crate::testing::{AfcResponder, MockTransport};
+
+    /// A notification proxy that keeps the names posted to it
+    fn notification_proxy() -> (NotificationProxyClient, Arc<Mutex<Vec<String>>>) {
+        let (host, mut device) = MockTransport::pair();
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let record = posted.clone();
+        tokio::spawn(async move {
+            let mut command = [0; 2];
+            while device.read_exact(&mut command).await.is_ok() {
+                let len = device.read_u32().await.unwrap();
+                let mut name = vec![0; len as usize];
+                device.read_exact(&mut name).await.unwrap();
+                record
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(name).unwrap());
+            }
+        });
+        (NotificationProxyClient::new(Box::new(host)), posted)
+    }
+
+    #[tokio::test]
+    async fn sync_lock_keeps_other_hosts_out() {
+        let responder = AfcResponder::new().with_file(SYNC_LOCK_PATH, b"");
+        let (notifications, posted) = notification_proxy();
+        let mut lock = SyncLock::acquire(client(&responder), notifications)
+            .await
+            .unwrap();
+        lock.afc().make_directory("/Books").await.unwrap();
+
+        // Finder, on another connection
+        let mut other = client(&responder);
+        let mut file = AfcFile::open(&mut other, SYNC_LOCK_PATH, AfcFopenMode::Rw)
+            .await
+            .unwrap();
+        assert!(!file.try_lock(AfcLock::Exclusive).await.unwrap());
+
+        lock.release().await.unwrap();
+        assert!(file.try_lock(AfcLock::Exclusive).await.unwrap());
+        file.close().await.unwrap();
+
+        // The last notification can still be on its way
+        crate::runtime::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            *posted.lock().unwrap(),
+            [
+                NotificationType::ITunesSyncWillStart,
+                NotificationType::ITunesSyncLockRequest,
+                NotificationType::ITunesSyncDidStart,
+                NotificationType::ITunesSyncDidFinish,
+            ]
+            .map(|n| n.to_string())
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Sysdiagnose capture workflow
+//!
+//! This module triggers a sysdiagnose on the device, waits for it to finish and
+//! pulls the resulting archive through the crash report services.
+
+use crate::afc::AfcClient;
+use crate::notification_proxy::{NotificationProxyClient, NotificationType};
+use crate::{provider::IdeviceProvider, IdeviceError, IdeviceService};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+const CRASH_REPORT_MOVER_SERVICE_NAME: &str = "com.apple.crashreportmover";
+const CRASH_REPORT_COPY_SERVICE_NAME: &str = "com.apple.crashreportcopymobile";
+
+/// Directory inside the crash report container where sysdiagnose archives are written
+pub const SYSDIAGNOSE_DIRECTORY: &str = "DiagnosticLogs/sysdiagnose";
+
+/// Posted to ask the device to start a sysdiagnose
+pub const SYSDIAGNOSE_TRIGGER_NOTIFICATION: &str = "com.apple.sysdiagnose.sysdiagnoseRequested";
+/// Posted by the device when sysdiagnose starts collecting
+pub const SYSDIAGNOSE_STARTED_NOTIFICATION: &str = "com.apple.sysdiagnose.sysdiagnoseStarted";
+/// Posted by the device when sysdiagnose has finished collecting
+pub const SYSDIAGNOSE_STOPPED_NOTIFICATION: &str = "com.apple.sysdiagnose.sysdiagnoseStopped";
+
+/// Progress events emitted while capturing a sysdiagnose
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SysdiagnoseEvent {
+    /// The trigger notification was posted
+    Triggered,
+    /// The device reported that collection started
+    Started,
+    /// The device reported that collection finished
+    Stopped,
+    /// Waiting for the archive to show up in the crash report directory
+    WaitingForArchive,
+    /// The archive was found and is being downloaded
+    Downloading { name: String },
+    /// Download progress of the archive
+    Progress { received: usize, total: usize },
+    /// The archive was written to disk
+    Finished { path: PathBuf },
+}
+
+/// Options for ``capture``
+#[derive(Debug, Clone)]
+pub struct SysdiagnoseOptions {
+    /// Post the trigger notification. Older devices ignore it, in which case
+    /// collection has to be started on the device with the button chord.
+    pub trigger: bool,
+    /// How long to wait for collection and compression to finish
+    pub timeout: Duration,
+    /// How often to check the crash report directory for the archive
+    pub poll_interval: Duration,
+    /// Remove the archive from the device after it was downloaded
+    pub remove_after_download: bool,
+}
+
+impl Default for SysdiagnoseOptions {
+    fn default() -> Self {
+        Self {
+            trigger: true,
+            timeout: Duration::from_secs(600),
+            poll_interval: Duration::from_secs(2),
+            remove_after_download: false,
+        }
+    }
+}
+
+/// Asks ``crashreportmover`` to move pending reports into the copy directory.
+/// The service replies with ``ping`` once it is done.
+pub async fn flush_crash_reports(provider: &dyn IdeviceProvider) -> Result<(), IdeviceError> {
+    let mut socket = provider
+        .start_service_raw(CRASH_REPORT_MOVER_SERVICE_NAME)
+        .await?;
+
+    let mut buf = [0u8; 4];
+    socket.read_exact(&mut buf).await?;
+    if &buf != b"ping" {
+        return Err(IdeviceError::UnexpectedResponse);
+    }
+
+    Ok(())
+}
+
+/// Triggers a sysdiagnose, waits for it to finish and writes the archive into ``out_dir``.
+/// The callback is called with each ``SysdiagnoseEvent``.
+/// # Returns
+/// The path of the downloaded archive
+pub async fn capture<Fut, S>(
+    provider: &dyn IdeviceProvider,
+    out_dir: impl AsRef<Path>,
+    options: SysdiagnoseOptions,
+    callback: impl Fn((SysdiagnoseEvent, S)) -> Fut,
+    state: S,
+) -> Result<PathBuf, IdeviceError>
+where
+    Fut: std::future::Future<Output = ()>,
+    S: Clone,
+{
+    // Remember what's already there so an old archive isn't mistaken for ours
+    let mut afc = AfcClient::connect_to_service(provider, CRASH_REPORT_COPY_SERVICE_NAME).await?;
+    let existing: HashSet<String> = list_archives(&mut afc).await?.into_iter().collect();
+
+    let mut notifications = NotificationProxyClient::connect(provider).await?;
+    notifications
+        .observe_notifications(&[
+            NotificationType::Custom(SYSDIAGNOSE_STARTED_NOTIFICATION.to_string()),
+            NotificationType::Custom(SYSDIAGNOSE_STOPPED_NOTIFICATION.to_string()),
+        ])
+        .await?;
+    let mut rx = notifications.start_listening().await?;
+
+    if options.trigger {
+        notifications
+            .post_notification(NotificationType::Custom(
+                SYSDIAGNOSE_TRIGGER_NOTIFICATION.to_string(),
+            ))
+            .await?;
+        callback((SysdiagnoseEvent::Triggered, state.clone())).await;
+    }
+
+    let deadline = Instant::now() + options.timeout;
+
+    // Wait for the device to report that collection is done
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let notification = match crate::runtime::timeout(remaining, rx.recv()).await? {
+            Some(n) => n,
+            None => {
+                return Err(IdeviceError::NotificationProxyError(
+                    "Notification stream closed before sysdiagnose finished".to_string(),
+                ))
+            }
+        };
+        match notification {
+            NotificationType::Custom(n) if n == SYSDIAGNOSE_STARTED_NOTIFICATION => {
+                callback((SysdiagnoseEvent::Started, state.clone())).await;
+            }
+            NotificationType::Custom(n) if n == SYSDIAGNOSE_STOPPED_NOTIFICATION => {
+                callback((SysdiagnoseEvent::Stopped, state.clone())).await;
+                break;
+            }
+            _ => continue,
+        }
+    }
+    notifications.stop_listening();
+
+    // The archive is compressed after collection stops, so poll until it appears
+    callback((SysdiagnoseEvent::WaitingForArchive, state.clone())).await;
+    let name = loop {
+        flush_crash_reports(provider).await?;
+        if let Some(name) = list_archives(&mut afc)
+            .await?
+            .into_iter()
+            .find(|a| !existing.contains(a))
+        {
+            break name;
+        }
+        if Instant::now() + options.poll_interval > deadline {
+            return Err(IdeviceError::Timeout);
+        }
+        crate::runtime::sleep(options.poll_interval).await;
+    };
+
+    callback((
+        SysdiagnoseEvent::Downloading { name: name.clone() },
+        state.clone(),
+    ))
+    .await;
+    let remote_path = format!("{SYSDIAGNOSE_DIRECTORY}/{name}");
+    // Archives run to hundreds of megabytes, so they go straight to disk
+    let path = out_dir.as_ref().join(&name);
+    let mut file = tokio::fs::File::create(&path).await?;
+    let res = afc
+        .read_file_to_writer(
+            &remote_path,
+            &mut file,
+            |((received, total), (callback, state))| {
+                callback((SysdiagnoseEvent::Progress { received, total }, state))
+            },
+            (&callback, state.clone()),
+        )
+        .await;
+    drop(file);
+    if let Err(e) = res {
+        // Don't leave a truncated archive that looks like a finished one
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(e);
+    }
+
+    if options.remove_after_download {
+        afc.remove_path(&remote_path).await?;
+    }
+
+    callback((SysdiagnoseEvent::Finished { path: path.clone() }, state)).await;
+    Ok(path)
+}
+
+/// Lists finished sysdiagnose archives in the crash report directory
+async fn list_archives(afc: &mut AfcClient) -> Result<Vec<String>, IdeviceError> {
+    Ok(afc
+        .read_directory(SYSDIAGNOSE_DIRECTORY)
+        .await?
+        .into_iter()
+        .filter(|e| e.starts_with("sysdiagnose_") && e.ends_with(".tar.gz"))
+        .collect())
+}
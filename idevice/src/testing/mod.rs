@@ -0,0 +1,845 @@
+// Jackson Coxson
+// An in-memory device for integration tests.
+// MockProvider hands out connections to scripted responders instead of a real device,
+// so flows can be tested from lockdown onwards without hardware.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use log::{debug, warn};
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    ssl::{Ssl, SslAcceptor, SslMethod},
+    x509::{X509Builder, X509NameBuilder, X509},
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{
+    codec::{read_frame, Codec},
+    lockdownd::LockdowndClient,
+    pairing_file::PairingFile,
+    provider::IdeviceProvider,
+    Idevice, IdeviceError, ReadWrite,
+};
+
+#[cfg(feature = "afc")]
+mod afc;
+#[cfg(feature = "afc")]
+pub use afc::AfcResponder;
+
+const MOCK_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The first port handed out to services added with ``with_service``
+const FIRST_SERVICE_PORT: u16 = 49152;
+
+/// One end of an in-memory connection.
+/// Responders get the device's end, and the host's end goes into an ``Idevice``.
+#[derive(Debug)]
+pub struct MockTransport {
+    inner: Box<dyn ReadWrite>,
+}
+
+impl MockTransport {
+    /// Creates a connected pair of transports
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = tokio::io::duplex(MOCK_BUFFER_SIZE);
+        (Self { inner: Box::new(a) }, Self { inner: Box::new(b) })
+    }
+
+    /// Reads one message in the given framing
+    pub async fn read_frame<T: Codec>(&mut self) -> Result<T, IdeviceError> {
+        read_frame(&mut self.inner).await
+    }
+
+    pub async fn write_frame<T: Codec>(&mut self, message: &T) -> Result<(), IdeviceError> {
+        self.inner.write_all(&message.encode()?).await?;
+        Ok(())
+    }
+
+    /// Reads a plist in lockdown's framing
+    pub async fn read_plist(&mut self) -> Result<plist::Dictionary, IdeviceError> {
+        match self.read_frame::<plist::Value>().await? {
+            plist::Value::Dictionary(d) => Ok(d),
+            _ => {
+                warn!("Mock received a plist that isn't a dictionary");
+                Err(IdeviceError::UnexpectedResponse)
+            }
+        }
+    }
+
+    /// Sends a plist in lockdown's framing
+    pub async fn send_plist(&mut self, message: plist::Dictionary) -> Result<(), IdeviceError> {
+        self.write_frame(&plist::Value::Dictionary(message)).await
+    }
+
+    /// Answers a TLS handshake as the device
+    async fn accept_tls(
+        self,
+        device_certificate: &X509,
+        device_key: &PKey<Private>,
+    ) -> Result<Self, IdeviceError> {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        acceptor.set_certificate(device_certificate)?;
+        acceptor.set_private_key(device_key)?;
+        let ssl = Ssl::new(acceptor.build().context())?;
+
+        let mut stream = tokio_openssl::SslStream::new(ssl, self.inner)?;
+        Pin::new(&mut stream).accept().await?;
+        Ok(Self {
+            inner: Box::new(stream),
+        })
+    }
+}
+
+impl AsyncRead for MockTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MockTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Plays the device's side of a service connection.
+/// This is an ugly trait until async traits are stabilized
+pub trait Responder: Send + Sync {
+    fn serve(
+        &self,
+        transport: MockTransport,
+    ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>>;
+}
+
+/// A device made of responders.
+/// Lockdown is built in, and answers ``StartService`` with the ports of the added services.
+pub struct MockProvider {
+    label: String,
+    pairing_file: PairingFile,
+    device_key: PKey<Private>,
+    values: Arc<Mutex<plist::Dictionary>>,
+    domains: Arc<Mutex<HashMap<String, plist::Dictionary>>>,
+    locked: Arc<AtomicBool>,
+    untrusted: Arc<AtomicBool>,
+    services: HashMap<String, u16>,
+    responders: HashMap<u16, Arc<dyn Responder>>,
+}
+
+impl MockProvider {
+    /// Creates a device with a freshly generated pairing record
+    pub fn new(label: impl Into<String>) -> Result<Self, IdeviceError> {
+        let (pairing_file, device_key) = generate_pairing_file()?;
+        let mut values = plist::Dictionary::new();
+        values.insert("DeviceName".into(), "Mock Device".into());
+        values.insert("ProductType".into(), "iPhone15,2".into());
+        values.insert("DeviceClass".into(), "iPhone".into());
+        values.insert("ProductVersion".into(), "17.4.1".into());
+        values.insert("BuildVersion".into(), "21E237".into());
+        values.insert("UniqueDeviceID".into(), MOCK_UDID.into());
+        values.insert("WiFiAddress".into(), "00:00:00:00:00:00".into());
+        values.insert(
+            "DevicePublicKey".into(),
+            plist::Value::Data(device_key.rsa()?.public_key_to_pem_pkcs1()?),
+        );
+
+        Ok(Self {
+            label: label.into(),
+            pairing_file,
+            device_key,
+            values: Arc::new(Mutex::new(values)),
+            domains: Arc::default(),
+            locked: Arc::default(),
+            untrusted: Arc::default(),
+            services: HashMap::new(),
+            responders: HashMap::new(),
+        })
+    }
+
+    /// Sets a value lockdown returns for ``GetValue`` without a domain
+    pub fn with_value(self, key: impl Into<String>, value: impl Into<plist::Value>) -> Self {
+        self.values.lock().unwrap().insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets a value lockdown returns for ``GetValue`` in a domain
+    pub fn with_domain_value(
+        self,
+        domain: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<plist::Value>,
+    ) -> Self {
+        self.domains
+            .lock()
+            .unwrap()
+            .entry(domain.into())
+            .or_default()
+            .insert(key.into(), value.into());
+        self
+    }
+
+    /// Adds a service that lockdown will start
+    pub fn with_service(
+        mut self,
+        service_name: impl Into<String>,
+        responder: impl Responder + 'static,
+    ) -> Self {
+        let port = FIRST_SERVICE_PORT + self.responders.len() as u16;
+        self.services.insert(service_name.into(), port);
+        self.responders.insert(port, Arc::new(responder));
+        self
+    }
+
+    /// Locks or unlocks the screen. While locked, lockdown refuses to start services
+    /// with ``PasswordProtected``, like a device with a passcode does for protected ones.
+    pub fn set_locked(&self, locked: bool) {
+        self.locked.store(locked, Ordering::SeqCst);
+    }
+
+    /// Forgets or trusts the host. An untrusted device refuses sessions with
+    /// ``InvalidHostID`` until it's paired again.
+    pub fn set_trusted(&self, trusted: bool) {
+        self.untrusted.store(!trusted, Ordering::SeqCst);
+    }
+
+    pub fn pairing_file(&self) -> &PairingFile {
+        &self.pairing_file
+    }
+}
+
+impl std::fmt::Debug for MockProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockProvider")
+            .field("label", &self.label)
+            .field("services", &self.services)
+            .finish()
+    }
+}
+
+impl IdeviceProvider for MockProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        let responder: Option<Arc<dyn Responder>> = if port == LockdowndClient::LOCKDOWND_PORT {
+            Some(Arc::new(LockdownResponder {
+                values: self.values.clone(),
+                domains: self.domains.clone(),
+                locked: self.locked.clone(),
+                untrusted: self.untrusted.clone(),
+                services: self.services.clone(),
+                device_certificate: self.pairing_file.device_certificate.clone(),
+                device_key: self.device_key.clone(),
+            }))
+        } else {
+            self.responders.get(&port).cloned()
+        };
+        let label = self.label.clone();
+
+        Box::pin(async move {
+            let responder = match responder {
+                Some(r) => r,
+                None => {
+                    return Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into())
+                }
+            };
+
+            let (host, device) = MockTransport::pair();
+            tokio::spawn(async move {
+                if let Err(e) = responder.serve(device).await {
+                    warn!("Mock responder on port {port} failed: {e:?}");
+                }
+            });
+            Ok(Idevice::new(Box::new(host), label))
+        })
+    }
+
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        let pairing_file = self.pairing_file.clone();
+        Box::pin(async move { Ok(pairing_file) })
+    }
+}
+
+/// Whether a read failed because the other end hung up, which ends a responder normally
+pub(crate) fn is_closed(e: &IdeviceError) -> bool {
+    matches!(e, IdeviceError::Socket(e) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+struct LockdownResponder {
+    values: Arc<Mutex<plist::Dictionary>>,
+    domains: Arc<Mutex<HashMap<String, plist::Dictionary>>>,
+    locked: Arc<AtomicBool>,
+    untrusted: Arc<AtomicBool>,
+    services: HashMap<String, u16>,
+    device_certificate: X509,
+    device_key: PKey<Private>,
+}
+
+impl Responder for LockdownResponder {
+    fn serve(
+        &self,
+        mut transport: MockTransport,
+    ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+        let values = self.values.clone();
+        let domains = self.domains.clone();
+        let locked = self.locked.clone();
+        let untrusted = self.untrusted.clone();
+        let services = self.services.clone();
+        let device_certificate = self.device_certificate.clone();
+        let device_key = self.device_key.clone();
+
+        Box::pin(async move {
+            loop {
+                let req = match transport.read_plist().await {
+                    Ok(r) => r,
+                    Err(e) if is_closed(&e) => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+                let request = req
+                    .get("Request")
+                    .and_then(|r| r.as_string())
+                    .unwrap_or_default()
+                    .to_string();
+                debug!("Mock lockdown received {request}");
+
+                let mut res = plist::Dictionary::new();
+                res.insert("Request".into(), request.clone().into());
+                match request.as_str() {
+                    "QueryType" => {
+                        res.insert("Type".into(), "com.apple.mobile.lockdown".into());
+                    }
+                    "GetValue" => {
+                        let values = match req.get("Domain").and_then(|d| d.as_string()) {
+                            Some(domain) => domains
+                                .lock()
+                                .unwrap()
+                                .get(domain)
+                                .cloned()
+                                .unwrap_or_default(),
+                            None => values.lock().unwrap().clone(),
+                        };
+                        match req.get("Key").and_then(|k| k.as_string()) {
+                            Some(key) => match values.get(key) {
+                                Some(v) => {
+                                    res.insert("Value".into(), v.clone());
+                                }
+                                None => {
+                                    res.insert("Error".into(), "MissingValue".into());
+                                }
+                            },
+                            None => {
+                                res.insert("Value".into(), plist::Value::Dictionary(values));
+                            }
+                        }
+                    }
+                    "SetValue" => {
+                        match (req.get("Key").and_then(|k| k.as_string()), req.get("Value")) {
+                            (Some(key), Some(value)) => {
+                                let key = key.to_string();
+                                match req.get("Domain").and_then(|d| d.as_string()) {
+                                    Some(domain) => domains
+                                        .lock()
+                                        .unwrap()
+                                        .entry(domain.to_string())
+                                        .or_default()
+                                        .insert(key, value.clone()),
+                                    None => values.lock().unwrap().insert(key, value.clone()),
+                                };
+                            }
+                            _ => {
+                                res.insert("Error".into(), "MissingValue".into());
+                            }
+                        }
+                    }
+                    "Pair" => {
+                        untrusted.store(false, Ordering::SeqCst);
+                        res.insert("EscrowBag".into(), plist::Value::Data(vec![0xe5; 16]));
+                    }
+                    "StartSession" if untrusted.load(Ordering::SeqCst) => {
+                        res.insert("Error".into(), "InvalidHostID".into());
+                    }
+                    "StartSession" => {
+                        res.insert("SessionID".into(), MOCK_SESSION_ID.into());
+                        res.insert("EnableSessionSSL".into(), true.into());
+                        transport.send_plist(res).await?;
+                        transport = transport
+                            .accept_tls(&device_certificate, &device_key)
+                            .await?;
+                        continue;
+                    }
+                    "Goodbye" => {
+                        res.insert("Result".into(), "Success".into());
+                        transport.send_plist(res).await?;
+                        return Ok(());
+                    }
+                    "StartService" => {
+                        let service = req
+                            .get("Service")
+                            .and_then(|s| s.as_string())
+                            .unwrap_or_default();
+                        match services.get(service) {
+                            Some(_) if locked.load(Ordering::SeqCst) => {
+                                res.insert("Error".into(), "PasswordProtected".into());
+                            }
+                            Some(port) => {
+                                res.insert("Service".into(), service.into());
+                                res.insert("Port".into(), (*port as u64).into());
+                                res.insert("EnableServiceSSL".into(), false.into());
+                            }
+                            None => {
+                                res.insert("Error".into(), "InvalidService".into());
+                            }
+                        }
+                    }
+                    _ => {
+                        res.insert("Error".into(), "UnsupportedRequest".into());
+                    }
+                }
+                transport.send_plist(res).await?;
+            }
+        })
+    }
+}
+
+/// Answers each plist request with the next response in a list, then hangs up
+pub struct ScriptedResponder {
+    responses: Vec<plist::Dictionary>,
+}
+
+impl ScriptedResponder {
+    pub fn new(responses: Vec<plist::Dictionary>) -> Self {
+        Self { responses }
+    }
+}
+
+impl Responder for ScriptedResponder {
+    fn serve(
+        &self,
+        mut transport: MockTransport,
+    ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+        let responses = self.responses.clone();
+        Box::pin(async move {
+            for res in responses {
+                match transport.read_plist().await {
+                    Ok(req) => debug!("Scripted responder received {req:?}"),
+                    Err(e) if is_closed(&e) => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+                transport.send_plist(res).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// screenshotr, returning the same image for every request
+pub struct ScreenshotrResponder {
+    image: Vec<u8>,
+}
+
+impl ScreenshotrResponder {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self { image }
+    }
+}
+
+impl Responder for ScreenshotrResponder {
+    fn serve(
+        &self,
+        mut transport: MockTransport,
+    ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+        let image = self.image.clone();
+        Box::pin(async move {
+            loop {
+                match transport.read_plist().await {
+                    Ok(_) => {}
+                    Err(e) if is_closed(&e) => return Ok(()),
+                    Err(e) => return Err(e),
+                }
+                let mut res = plist::Dictionary::new();
+                res.insert("Status".into(), "Success".into());
+                res.insert("ImageData".into(), plist::Value::Data(image.clone()));
+                transport.send_plist(res).await?;
+            }
+        })
+    }
+}
+
+/// The UDID lockdown reports
+pub const MOCK_UDID: &str = "00008120-000A1B2C3D4E5F60";
+const MOCK_SESSION_ID: &str = "00000000-0000-0000-0000-000000000001";
+
+/// Generates a root, host and device certificate the way a pairing would.
+/// Returns the device's private key too, since the mock plays the device in TLS.
+fn generate_pairing_file() -> Result<(PairingFile, PKey<Private>), IdeviceError> {
+    let root_key = PKey::from_rsa(Rsa::generate(2048)?)?;
+    let root_certificate = certificate("Root", &root_key, None)?;
+    let host_key = PKey::from_rsa(Rsa::generate(2048)?)?;
+    let host_certificate = certificate("Host", &host_key, Some((&root_certificate, &root_key)))?;
+    let device_key = PKey::from_rsa(Rsa::generate(2048)?)?;
+    let device_certificate =
+        certificate("Device", &device_key, Some((&root_certificate, &root_key)))?;
+
+    let pairing_file = PairingFile {
+        device_certificate,
+        host_private_key: host_key,
+        host_certificate,
+        root_private_key: root_key,
+        root_certificate,
+        system_buid: "00000000-0000-0000-0000-0000000000B1".to_string(),
+        host_id: "00000000-0000-0000-0000-0000000000A1".to_string(),
+        escrow_bag: Vec::new(),
+        wifi_mac_address: "00:00:00:00:00:00".to_string(),
+        udid: Some(MOCK_UDID.to_string()),
+    };
+    Ok((pairing_file, device_key))
+}
+
+fn certificate(
+    common_name: &str,
+    key: &PKey<Private>,
+    issuer: Option<(&X509, &PKey<Private>)>,
+) -> Result<X509, IdeviceError> {
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", common_name)?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    let serial = BigNum::from_u32(1)?.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    match issuer {
+        Some((issuer, _)) => builder.set_issuer_name(issuer.subject_name())?,
+        None => builder.set_issuer_name(&name)?,
+    }
+    builder.set_pubkey(key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(365)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    builder.sign(
+        issuer.map(|(_, k)| k).unwrap_or(key),
+        MessageDigest::sha256(),
+    )?;
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codec::WireFormat, IdeviceService};
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn pairing_files_print_without_secrets() {
+        let (mut pairing_file, _) = generate_pairing_file().unwrap();
+        pairing_file.escrow_bag = b"escrow secret".to_vec();
+
+        let debug = format!("{pairing_file:?}");
+        let pretty = crate::Pretty::pretty(&pairing_file);
+        for printed in [&debug, &pretty] {
+            assert!(printed.contains(crate::pretty::REDACTED));
+            assert!(!printed.contains("escrow secret"));
+            assert!(!printed.contains("101, 115, 99"));
+        }
+        assert!(pretty.contains("Escrow bag: <redacted> (13 bytes)"));
+        assert_eq!(
+            pairing_file.to_string(),
+            format!("pairing record for {MOCK_UDID}, host ID 00000000-0000-0000-0000-0000000000A1")
+        );
+    }
+
+    #[tokio::test]
+    async fn lockdown_session_and_services() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_domain_value("com.apple.mobile.battery", "BatteryCurrentCapacity", 87)
+            .with_service(
+                "com.apple.screenshotr",
+                ScreenshotrResponder::new(vec![1, 2, 3]),
+            );
+
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+        assert_eq!(
+            lockdown.get_value("ProductVersion").await.unwrap(),
+            plist::Value::from("17.4.1")
+        );
+        assert!(lockdown.get_value("NotAKey").await.is_err());
+
+        lockdown
+            .start_session(provider.pairing_file())
+            .await
+            .unwrap();
+        let (port, ssl) = lockdown
+            .start_service("com.apple.screenshotr")
+            .await
+            .unwrap();
+        assert_eq!(port, FIRST_SERVICE_PORT);
+        assert!(!ssl);
+        assert!(lockdown.start_service("com.apple.afc").await.is_err());
+        assert!(matches!(
+            lockdown
+                .start_service("com.apple.instruments.remoteserver")
+                .await,
+            Err(IdeviceError::DeveloperImageNotMounted)
+        ));
+        lockdown.close().await.unwrap();
+
+        let mut screenshotr = provider.connect(port).await.unwrap();
+        screenshotr
+            .send_plist(plist::Value::Dictionary(plist::Dictionary::new()))
+            .await
+            .unwrap();
+        let res = screenshotr.read_plist().await.unwrap();
+        assert_eq!(
+            res.get("ImageData").and_then(|d| d.as_data()),
+            Some(&[1, 2, 3][..])
+        );
+    }
+
+    #[tokio::test]
+    async fn raw_services() {
+        let provider = MockProvider::new("test").unwrap().with_service(
+            "com.apple.screenshotr",
+            ScreenshotrResponder::new(vec![4, 5]),
+        );
+        let provider: &dyn IdeviceProvider = &provider;
+
+        let mut socket = provider
+            .start_service_raw("com.apple.screenshotr")
+            .await
+            .unwrap();
+        let request = plist::Value::Dictionary(plist::Dictionary::new());
+        socket.write_all(&request.encode().unwrap()).await.unwrap();
+        let res: plist::Value = read_frame(&mut socket).await.unwrap();
+        assert_eq!(
+            res.as_dictionary()
+                .and_then(|d| d.get("ImageData"))
+                .and_then(|d| d.as_data()),
+            Some(&[4, 5][..])
+        );
+
+        assert!(provider.start_service_raw("com.apple.afc").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn wire_format_falls_back() {
+        let (host, mut device) = MockTransport::pair();
+        // Like a service that only parses binary, and answers XML with garbage
+        tokio::spawn(async move {
+            loop {
+                let len = device.inner.read_u32().await.unwrap();
+                let mut request = vec![0; len as usize];
+                device.inner.read_exact(&mut request).await.unwrap();
+                if !request.starts_with(b"bplist00") {
+                    device.inner.write_all(b"\0\0\0\x03???").await.unwrap();
+                    continue;
+                }
+                let mut res = plist::Dictionary::new();
+                res.insert("Type".into(), "com.apple.mobile.lockdown".into());
+                device.send_plist(res).await.unwrap();
+            }
+        });
+
+        let mut idevice = Idevice::new(Box::new(host), "test");
+        assert_eq!(idevice.wire_format(), WireFormat::Xml);
+        assert_eq!(
+            idevice.get_type().await.unwrap(),
+            "com.apple.mobile.lockdown"
+        );
+        assert_eq!(idevice.wire_format(), WireFormat::Binary);
+
+        // Once a format has worked, an unreadable reply is an error
+        idevice.set_wire_format(WireFormat::Xml);
+        assert!(matches!(
+            idevice.get_type().await,
+            Err(IdeviceError::Plist(_))
+        ));
+    }
+
+    #[cfg(feature = "screenshot")]
+    #[tokio::test]
+    async fn socket_clients_are_services() {
+        use crate::screenshot::ScreenshotClient;
+
+        let provider = MockProvider::new("test").unwrap().with_service(
+            ScreenshotClient::service_name(),
+            ScreenshotrResponder::new(vec![6, 7]),
+        );
+
+        let mut client = ScreenshotClient::connect(&provider).await.unwrap();
+        assert_eq!(client.take_screenshot().await.unwrap(), vec![6, 7]);
+
+        // The same client, from a connection started some other way
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+        let (port, _) = lockdown
+            .start_service(ScreenshotClient::service_name())
+            .await
+            .unwrap();
+        let idevice = provider.connect(port).await.unwrap();
+        let mut client = ScreenshotClient::from_idevice(idevice).await.unwrap();
+        assert_eq!(client.take_screenshot().await.unwrap(), vec![6, 7]);
+    }
+
+    #[tokio::test]
+    async fn lockdown_pairing() {
+        let provider = MockProvider::new("test").unwrap();
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+        let pairing_file = lockdown.pair("HOST-ID", "SYSTEM-BUID").await.unwrap();
+
+        pairing_file.validate().unwrap();
+        assert!(pairing_file
+            .device_certificate
+            .public_key()
+            .unwrap()
+            .public_eq(&provider.device_key));
+        assert_eq!(pairing_file.host_id(), "HOST-ID");
+        assert_eq!(pairing_file.escrow_bag, vec![0xe5; 16]);
+        assert_eq!(pairing_file.udid.as_deref(), Some(MOCK_UDID));
+    }
+
+    #[cfg(feature = "provisioning")]
+    #[tokio::test]
+    async fn provisioning_resumes() {
+        use crate::provisioning::{
+            Provisioner, ProvisioningEvent, ProvisioningOutcome, ProvisioningState, Step,
+        };
+
+        let provider = MockProvider::new("test").unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let provisioner = Provisioner::new(vec![
+            Step::Pair,
+            Step::SetDeviceName {
+                name: "Lab 12".into(),
+            },
+            Step::InstallProfile {
+                path: "/nonexistent/profile.mobileconfig".into(),
+            },
+        ])
+        .with_events(move |e| recorded.lock().unwrap().push(e));
+
+        let mut state = ProvisioningState::default();
+        assert!(provisioner.run(&provider, &mut state).await.is_err());
+        assert_eq!(state.next_step, 2);
+        assert!(matches!(
+            events.lock().unwrap().as_slice(),
+            [
+                ProvisioningEvent::Started { step: 0, total: 3 },
+                ProvisioningEvent::Skipped { step: 0, .. },
+                ProvisioningEvent::Started { step: 1, .. },
+                ProvisioningEvent::Finished { step: 1 },
+                ProvisioningEvent::Started { step: 2, .. },
+                ProvisioningEvent::Failed { step: 2, .. },
+            ]
+        ));
+
+        let mut lockdown = LockdowndClient::connect(&provider).await.unwrap();
+        assert_eq!(
+            lockdown.get_value("DeviceName").await.unwrap(),
+            plist::Value::from("Lab 12")
+        );
+
+        // Picks up after the failed step once the plan is fixed
+        let mut provisioner = provisioner;
+        provisioner.plan[2] = Step::SetDeviceName {
+            name: "Lab 12".into(),
+        };
+        events.lock().unwrap().clear();
+        assert_eq!(
+            provisioner.run(&provider, &mut state).await.unwrap(),
+            ProvisioningOutcome::Complete
+        );
+        assert_eq!(state.next_step, 3);
+        assert!(matches!(
+            events.lock().unwrap().as_slice(),
+            [
+                ProvisioningEvent::Started { step: 2, .. },
+                ProvisioningEvent::Skipped { step: 2, .. },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn key_store_sessions() {
+        use crate::{
+            key_store::{FsKeyStore, KeyStore, RecordKind},
+            provider::KeyStoreProvider,
+        };
+
+        let dir = std::env::temp_dir().join(format!("idevice-keys-{}", std::process::id()));
+        let store: Arc<dyn KeyStore> = Arc::new(FsKeyStore::new(&dir));
+        let mock = MockProvider::new("test").unwrap();
+        assert!(matches!(
+            store.load_pairing_file(MOCK_UDID).await,
+            Err(IdeviceError::PairingRecordNotFound(_))
+        ));
+        assert!(store.load(RecordKind::Lockdown, "../etc").await.is_err());
+
+        store
+            .save_pairing_file(MOCK_UDID, mock.pairing_file())
+            .await
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = dir.join(format!("{MOCK_UDID}.plist"));
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let provider = KeyStoreProvider {
+            provider: mock,
+            store: store.clone(),
+            udid: MOCK_UDID.into(),
+        };
+        let provider: &dyn IdeviceProvider = &provider;
+        let pairing_file = provider.get_pairing_file().await.unwrap();
+        let mut lockdown = LockdowndClient::connect(provider).await.unwrap();
+        lockdown.start_session(&pairing_file).await.unwrap();
+
+        store.delete(RecordKind::Lockdown, MOCK_UDID).await.unwrap();
+        store.delete(RecordKind::Lockdown, MOCK_UDID).await.unwrap();
+        assert!(store
+            .load(RecordKind::Lockdown, MOCK_UDID)
+            .await
+            .unwrap()
+            .is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+// Jackson Coxson
+// Rate limiting for bulk transfers.
+// A background sync on a shared host can otherwise saturate the link and starve
+// anything else talking to the same device. Clones share the same limit.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A token bucket limiting transfers to a number of bytes per second.
+/// Up to a second's worth of bytes can be sent in a burst after being idle.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    bytes_per_sec: u64,
+    /// Goes negative when a transfer is larger than what's available, which makes the
+    /// next transfer wait for it to be paid back
+    available: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    /// Takes bytes from the bucket, returning how long to wait before sending them
+    fn take(&mut self, now: Instant, bytes: usize) -> Duration {
+        if self.bytes_per_sec == 0 {
+            return Duration::ZERO;
+        }
+        let rate = self.bytes_per_sec as f64;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.available = (self.available + elapsed * rate).min(rate);
+        self.last = now;
+
+        self.available -= bytes as f64;
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / rate)
+        }
+    }
+}
+
+impl RateLimiter {
+    /// A limit of zero doesn't limit anything
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                bytes_per_sec,
+                available: bytes_per_sec as f64,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    /// Changes the limit for every clone, such as when an interactive session starts
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.bytes_per_sec = bytes_per_sec;
+        bucket.available = bucket.available.min(bytes_per_sec as f64);
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().bytes_per_sec
+    }
+
+    /// Waits until the bytes can be transferred without going over the limit
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.bucket.lock().unwrap().take(Instant::now(), bytes);
+        if !wait.is_zero() {
+            crate::runtime::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_waits_for_debt() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            bytes_per_sec: 1000,
+            available: 1000.0,
+            last: start,
+        };
+
+        // The burst allowance goes through right away
+        assert_eq!(bucket.take(start, 1000), Duration::ZERO);
+        // Then a full second is owed for the next thousand
+        assert_eq!(bucket.take(start, 1000), Duration::from_secs(1));
+        // Half a second later, half of that has been paid back
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.take(later, 0), Duration::from_millis(500));
+        // Idling doesn't build up more than a second's burst
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(bucket.take(much_later, 1000), Duration::ZERO);
+        assert_eq!(bucket.take(much_later, 1), Duration::from_millis(1));
+
+        bucket.bytes_per_sec = 0;
+        assert_eq!(bucket.take(much_later, 1 << 30), Duration::ZERO);
+    }
+}
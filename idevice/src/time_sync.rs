@@ -0,0 +1,146 @@
+// Jackson Coxson
+// Reads the device clock over lockdown to measure skew against the host
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+use crate::{lockdownd::LockdowndClient, IdeviceError, IdeviceService};
+
+pub struct TimeSyncClient {
+    pub lockdown: LockdowndClient,
+}
+
+impl IdeviceService for TimeSyncClient {
+    fn service_name() -> &'static str {
+        LockdowndClient::service_name()
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        Ok(Self::new(LockdowndClient::connect(provider).await?))
+    }
+
+    async fn from_idevice(idevice: crate::Idevice) -> Result<Self, IdeviceError> {
+        Ok(Self::new(LockdowndClient::new(idevice)))
+    }
+}
+
+/// The device clock and its locale settings
+#[derive(Debug, Clone)]
+pub struct DeviceClock {
+    pub time: SystemTime,
+    /// Olson name, such as ``America/Denver``
+    pub timezone: Option<String>,
+    /// Offset from UTC in seconds
+    pub timezone_offset: Option<f64>,
+    pub uses_24_hour_clock: Option<bool>,
+}
+
+/// Difference between the device and host clocks
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOffset {
+    /// Device time minus host time, in seconds. Positive when the device is ahead.
+    pub offset: f64,
+    /// How long the request took. The offset is accurate to about half of this.
+    pub round_trip: Duration,
+}
+
+impl TimeSyncClient {
+    pub fn new(lockdown: LockdowndClient) -> Self {
+        Self { lockdown }
+    }
+
+    pub async fn get_clock(&mut self) -> Result<DeviceClock, IdeviceError> {
+        let time = self.get_time().await?;
+        let timezone = self
+            .lockdown
+            .get_value("TimeZone")
+            .await
+            .ok()
+            .and_then(|v| v.into_string());
+        let timezone_offset = self
+            .lockdown
+            .get_value("TimeZoneOffsetFromUTC")
+            .await
+            .ok()
+            .and_then(|v| v.as_real());
+        let uses_24_hour_clock = self
+            .lockdown
+            .get_value("Uses24HourClock")
+            .await
+            .ok()
+            .and_then(|v| v.as_boolean());
+
+        Ok(DeviceClock {
+            time,
+            timezone,
+            timezone_offset,
+            uses_24_hour_clock,
+        })
+    }
+
+    /// Measures the device clock against the host clock.
+    /// The host time is taken as the midpoint of the request to cancel out latency.
+    pub async fn time_offset(&mut self) -> Result<TimeOffset, IdeviceError> {
+        let host_before = SystemTime::now();
+        let started = Instant::now();
+        let device = self.get_time().await?;
+        let round_trip = started.elapsed();
+        let host = host_before + round_trip / 2;
+
+        Ok(TimeOffset {
+            offset: unix_seconds(device) - unix_seconds(host),
+            round_trip,
+        })
+    }
+
+    /// Measures the offset every ``interval`` and passes it to the callback.
+    /// Stops when the callback resolves to false.
+    pub async fn watch_drift<Fut, S>(
+        &mut self,
+        interval: Duration,
+        callback: impl Fn((TimeOffset, S)) -> Fut,
+        state: S,
+    ) -> Result<(), IdeviceError>
+    where
+        Fut: std::future::Future<Output = bool>,
+        S: Clone,
+    {
+        loop {
+            let offset = self.time_offset().await?;
+            if !callback((offset, state.clone())).await {
+                return Ok(());
+            }
+            crate::runtime::sleep(interval).await;
+        }
+    }
+
+    async fn get_time(&mut self) -> Result<SystemTime, IdeviceError> {
+        let value = self.lockdown.get_value("TimeIntervalSince1970").await?;
+        let secs = match value {
+            plist::Value::Real(r) => r,
+            plist::Value::Integer(i) => match i.as_signed() {
+                Some(i) => i as f64,
+                None => return Err(IdeviceError::UnexpectedResponse),
+            },
+            _ => {
+                warn!("TimeIntervalSince1970 was not a number");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        if secs < 0.0 {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+
+        Ok(UNIX_EPOCH + Duration::from_secs_f64(secs))
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> f64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    }
+}
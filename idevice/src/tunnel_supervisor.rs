@@ -0,0 +1,334 @@
+// Jackson Coxson
+// Keeps CoreDevice tunnels to devices up on behalf of other processes, like pymobiledevice3's
+// tunneld. Exposing a tunnel to the system takes a TUN device, so opening one is left to a
+// ``TunnelOpener`` from the embedder. The supervisor reopens tunnels that drop and serves their
+// addresses over HTTP in tunneld's format, so ``tunneld::get_tunneld_devices`` works against it.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{oneshot, Mutex},
+};
+
+use crate::{runtime::BoxFuture, IdeviceError};
+
+/// How long to wait before reopening a tunnel that dropped or failed to open
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(3);
+
+/// Requests to the control API are a single HTTP request line and headers
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// Where a device's tunnel can be reached, as tunneld lists it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelInfo {
+    /// The TUN interface, such as ``utun4``
+    pub interface: String,
+    /// The device's address inside the tunnel
+    #[serde(rename = "tunnel-address")]
+    pub address: String,
+    /// RemoteServiceDiscovery's port at that address
+    #[serde(rename = "tunnel-port")]
+    pub rsd_port: u16,
+}
+
+/// A tunnel that's up
+pub struct OpenTunnel {
+    pub info: TunnelInfo,
+    /// Forwards the tunnel's traffic, finishing when the tunnel drops
+    pub run: BoxFuture<'static, Result<(), IdeviceError>>,
+}
+
+/// Opens tunnels for the supervisor
+pub trait TunnelOpener: Send + Sync + 'static {
+    fn open(&self, udid: String) -> BoxFuture<'static, Result<OpenTunnel, IdeviceError>>;
+}
+
+struct Supervised {
+    /// ``None`` while the tunnel is being (re)opened
+    info: Option<TunnelInfo>,
+    /// Dropping this stops the device's task
+    _stop: oneshot::Sender<()>,
+}
+
+struct Inner {
+    opener: Box<dyn TunnelOpener>,
+    tunnels: Mutex<HashMap<String, Supervised>>,
+    retry_delay_ms: AtomicU64,
+}
+
+/// Owns the tunnels of any number of devices. Clones share the same tunnels.
+#[derive(Clone)]
+pub struct TunnelSupervisor {
+    inner: Arc<Inner>,
+}
+
+impl TunnelSupervisor {
+    pub fn new(opener: impl TunnelOpener) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                opener: Box::new(opener),
+                tunnels: Mutex::new(HashMap::new()),
+                retry_delay_ms: AtomicU64::new(DEFAULT_RETRY_DELAY.as_millis() as u64),
+            }),
+        }
+    }
+
+    pub fn set_retry_delay(&self, delay: Duration) {
+        self.inner
+            .retry_delay_ms
+            .store(delay.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Starts keeping a tunnel to the device up, until it's removed.
+    /// Does nothing if the device is already supervised.
+    pub async fn add(&self, udid: impl Into<String>) {
+        let udid = udid.into();
+        let mut tunnels = self.inner.tunnels.lock().await;
+        if tunnels.contains_key(&udid) {
+            return;
+        }
+        let (stop, stopped) = oneshot::channel();
+        tunnels.insert(
+            udid.clone(),
+            Supervised {
+                info: None,
+                _stop: stop,
+            },
+        );
+
+        let inner = self.inner.clone();
+        crate::runtime::runtime().spawn(Box::pin(async move {
+            tokio::select! {
+                _ = stopped => {}
+                _ = supervise(inner, udid.clone()) => {}
+            }
+            debug!("Stopped supervising {udid}");
+        }));
+    }
+
+    /// Closes the device's tunnel and stops reopening it
+    pub async fn remove(&self, udid: &str) {
+        self.inner.tunnels.lock().await.remove(udid);
+    }
+
+    /// The tunnels that are currently up, by UDID
+    pub async fn tunnels(&self) -> HashMap<String, TunnelInfo> {
+        self.inner
+            .tunnels
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(udid, t)| Some((udid.clone(), t.info.clone()?)))
+            .collect()
+    }
+
+    /// Supervises every device usbmuxd has attached over USB, as they come and go.
+    /// Runs until the usbmuxd connection fails.
+    #[cfg(feature = "usbmuxd")]
+    pub async fn watch_usbmuxd(
+        &self,
+        addr: crate::usbmuxd::UsbmuxdAddr,
+    ) -> Result<(), IdeviceError> {
+        use crate::usbmuxd::{Connection, UsbmuxdEvent};
+
+        let mut muxer = addr.connect(0).await?;
+        muxer.listen().await?;
+        let mut attached = HashMap::new();
+        loop {
+            match muxer.next_event().await? {
+                UsbmuxdEvent::Attached(device) => {
+                    if matches!(device.connection_type, Connection::Usb) {
+                        self.add(device.udid.clone()).await;
+                        attached.insert(device.device_id, device.udid);
+                    }
+                }
+                UsbmuxdEvent::Detached(id) => {
+                    if let Some(udid) = attached.remove(&id) {
+                        self.remove(&udid).await;
+                    }
+                }
+                UsbmuxdEvent::Paired(_) => {}
+            }
+        }
+    }
+
+    /// Answers control requests on a listener, forever
+    pub async fn serve_tcp(&self, listener: tokio::net::TcpListener) -> Result<(), IdeviceError> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let supervisor = self.clone();
+            crate::runtime::runtime().spawn(Box::pin(async move {
+                if let Err(e) = supervisor.serve_connection(stream).await {
+                    debug!("Control connection failed: {e:?}");
+                }
+            }));
+        }
+    }
+
+    #[cfg(unix)]
+    pub async fn serve_unix(&self, listener: tokio::net::UnixListener) -> Result<(), IdeviceError> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let supervisor = self.clone();
+            crate::runtime::runtime().spawn(Box::pin(async move {
+                if let Err(e) = supervisor.serve_connection(stream).await {
+                    debug!("Control connection failed: {e:?}");
+                }
+            }));
+        }
+    }
+
+    /// Answers one HTTP request with the tunnels as JSON, ``{udid: [TunnelInfo]}`` like tunneld.
+    /// Any path gets the same answer.
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: S,
+    ) -> Result<(), IdeviceError> {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            request.extend_from_slice(&buf[..n]);
+            if request.len() > MAX_REQUEST_LEN {
+                return Err(IdeviceError::MessageTooLarge(request.len()));
+            }
+        }
+
+        let tunnels: HashMap<String, Vec<TunnelInfo>> = self
+            .tunnels()
+            .await
+            .into_iter()
+            .map(|(udid, info)| (udid, vec![info]))
+            .collect();
+        let body = serde_json::to_vec(&tunnels)?;
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(&body).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Opens the device's tunnel and reopens it whenever it drops
+async fn supervise(inner: Arc<Inner>, udid: String) {
+    #[cfg(feature = "metrics")]
+    let mut opened = false;
+    loop {
+        match inner.opener.open(udid.clone()).await {
+            Ok(tunnel) => {
+                #[cfg(feature = "metrics")]
+                if std::mem::replace(&mut opened, true) {
+                    crate::metrics::tunnel_reconnected(&udid);
+                }
+                info!(
+                    "Tunnel to {udid} is up at [{}]:{}",
+                    tunnel.info.address, tunnel.info.rsd_port
+                );
+                set_info(&inner, &udid, Some(tunnel.info)).await;
+                match tunnel.run.await {
+                    Ok(()) => info!("Tunnel to {udid} closed"),
+                    Err(e) => warn!("Tunnel to {udid} dropped: {e:?}"),
+                }
+                set_info(&inner, &udid, None).await;
+            }
+            Err(e) => warn!("Failed to open a tunnel to {udid}: {e:?}"),
+        }
+        let delay = inner.retry_delay_ms.load(Ordering::Relaxed);
+        crate::runtime::sleep(Duration::from_millis(delay)).await;
+    }
+}
+
+async fn set_info(inner: &Inner, udid: &str, info: Option<TunnelInfo>) {
+    if let Some(t) = inner.tunnels.lock().await.get_mut(udid) {
+        t.info = info;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    /// The first tunnel drops right away, later ones stay up
+    struct FlakyOpener {
+        opened: Arc<AtomicUsize>,
+    }
+
+    impl TunnelOpener for FlakyOpener {
+        fn open(&self, udid: String) -> BoxFuture<'static, Result<OpenTunnel, IdeviceError>> {
+            let attempt = self.opened.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                let run: BoxFuture<'static, Result<(), IdeviceError>> = if attempt == 0 {
+                    Box::pin(async { Err(IdeviceError::NoEstablishedConnection) })
+                } else {
+                    Box::pin(std::future::pending())
+                };
+                Ok(OpenTunnel {
+                    info: TunnelInfo {
+                        interface: format!("utun{attempt}"),
+                        address: format!("fd00::{udid}"),
+                        rsd_port: 58783,
+                    },
+                    run,
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_tunnels_are_reopened_and_served() {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let supervisor = TunnelSupervisor::new(FlakyOpener {
+            opened: opened.clone(),
+        });
+        supervisor.set_retry_delay(Duration::from_millis(1));
+        supervisor.add("1").await;
+
+        let mut tunnels = supervisor.tunnels().await;
+        for _ in 0..100 {
+            if opened.load(Ordering::SeqCst) >= 2 && !tunnels.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            tunnels = supervisor.tunnels().await;
+        }
+        assert_eq!(tunnels["1"].interface, "utun1");
+
+        let (client, server) = tokio::io::duplex(4096);
+        let serving = supervisor.clone();
+        let serving = tokio::spawn(async move { serving.serve_connection(server).await });
+        let (mut read, mut write) = tokio::io::split(client);
+        write
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        read.read_to_string(&mut response).await.unwrap();
+        serving.await.unwrap().unwrap();
+
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        let listed: HashMap<String, Vec<TunnelInfo>> = serde_json::from_str(body).unwrap();
+        assert_eq!(listed["1"][0].address, "fd00::1");
+        assert_eq!(listed["1"][0].rsd_port, 58783);
+
+        supervisor.remove("1").await;
+        assert!(supervisor.tunnels().await.is_empty());
+    }
+}
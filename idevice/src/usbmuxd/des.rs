@@ -24,4 +24,6 @@ pub struct DevicePropertiesResponse {
     pub network_address: Option<plist::Data>,
     #[serde(rename = "SerialNumber")]
     pub serial_number: String,
+    #[serde(rename = "ProductID")]
+    pub product_id: Option<u16>,
 }
@@ -1,6 +1,7 @@
 // Jackson Coxson
 
 use std::{
+    fmt,
     net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
 };
@@ -9,14 +10,18 @@ use std::{
 use std::net::SocketAddrV4;
 
 use log::{debug, warn};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 
 use crate::{
-    pairing_file::PairingFile, provider::UsbmuxdProvider, Idevice, IdeviceError, ReadWrite,
+    codec::{read_frame, Codec},
+    pairing_file::PairingFile,
+    pretty::Pretty,
+    provider::UsbmuxdProvider,
+    runtime, Idevice, IdeviceError, ReadWrite,
 };
 
 mod des;
-mod raw_packet;
+pub mod raw_packet;
 
 #[derive(Debug, Clone)]
 pub enum Connection {
@@ -25,11 +30,85 @@ pub enum Connection {
     Unknown(String),
 }
 
+/// The state a device was attached in, from its USB product ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceMode {
+    Normal,
+    Recovery,
+    Dfu,
+    Unknown,
+}
+
+impl DeviceMode {
+    pub fn from_product_id(product_id: u16) -> Self {
+        match product_id {
+            0x1227 => Self::Dfu,
+            0x1280..=0x1283 => Self::Recovery,
+            0x1290..=0x12af => Self::Normal,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UsbmuxdDevice {
     pub connection_type: Connection,
     pub udid: String,
     pub device_id: u32,
+    pub mode: DeviceMode,
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usb => write!(f, "USB"),
+            Self::Network(addr) => write!(f, "network {addr}"),
+            Self::Unknown(kind) => write!(f, "unknown connection {kind}"),
+        }
+    }
+}
+
+impl fmt::Display for DeviceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Normal => "normal",
+            Self::Recovery => "recovery",
+            Self::Dfu => "DFU",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// ``00008030-001A2D3E0C01802E (USB, id 3)``, with the mode if it isn't normal
+impl fmt::Display for UsbmuxdDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, id {}",
+            self.udid, self.connection_type, self.device_id
+        )?;
+        if self.mode != DeviceMode::Normal {
+            write!(f, ", {} mode", self.mode)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Pretty for UsbmuxdDevice {
+    fn pretty(&self) -> String {
+        format!(
+            "UDID: {}\nConnection: {}\nDevice ID: {}\nMode: {}",
+            self.udid, self.connection_type, self.device_id, self.mode
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum UsbmuxdEvent {
+    Attached(UsbmuxdDevice),
+    /// The device ID of the device that went away
+    Detached(u32),
+    Paired(u32),
 }
 
 pub struct UsbmuxdConnection {
@@ -51,8 +130,8 @@ impl UsbmuxdAddr {
     pub async fn to_socket(&self) -> Result<Box<dyn ReadWrite>, IdeviceError> {
         Ok(match self {
             #[cfg(unix)]
-            Self::UnixSocket(addr) => Box::new(tokio::net::UnixStream::connect(addr).await?),
-            Self::TcpSocket(addr) => Box::new(tokio::net::TcpStream::connect(addr).await?),
+            Self::UnixSocket(addr) => runtime::connect_unix(addr.as_ref()).await?,
+            Self::TcpSocket(addr) => runtime::connect_tcp(*addr).await?,
         })
     }
 
@@ -124,64 +203,59 @@ impl UsbmuxdConnection {
 
         let mut devs = Vec::new();
         for dev in res.device_list {
-            let connection_type = match dev.properties.connection_type.as_str() {
-                "Network" => {
-                    if let Some(addr) = dev.properties.network_address {
-                        let addr = &Into::<Vec<u8>>::into(addr);
-                        if addr.len() < 8 {
-                            warn!("Device address bytes len < 8");
-                            return Err(IdeviceError::UnexpectedResponse);
-                        }
-
-                        match addr[0] {
-                            0x02 => {
-                                // ipv4
-                                Connection::Network(IpAddr::V4(Ipv4Addr::new(
-                                    addr[4], addr[5], addr[6], addr[7],
-                                )))
-                            }
-                            0x1E => {
-                                // ipv6
-                                if addr.len() < 24 {
-                                    warn!("IPv6 address is less than 24 bytes");
-                                    return Err(IdeviceError::UnexpectedResponse);
-                                }
-
-                                Connection::Network(IpAddr::V6(Ipv6Addr::new(
-                                    u16::from_be_bytes([addr[8], addr[9]]),
-                                    u16::from_be_bytes([addr[10], addr[11]]),
-                                    u16::from_be_bytes([addr[12], addr[13]]),
-                                    u16::from_be_bytes([addr[14], addr[15]]),
-                                    u16::from_be_bytes([addr[16], addr[17]]),
-                                    u16::from_be_bytes([addr[18], addr[19]]),
-                                    u16::from_be_bytes([addr[20], addr[21]]),
-                                    u16::from_be_bytes([addr[22], addr[23]]),
-                                )))
-                            }
-                            _ => {
-                                warn!("Unknown IP address protocol: {:02X}", addr[0]);
-                                Connection::Unknown(format!("Network {:02X}", addr[0]))
-                            }
-                        }
-                    } else {
-                        warn!("Device is network attached, but has no network info");
-                        return Err(IdeviceError::UnexpectedResponse);
-                    }
-                }
-                "USB" => Connection::Usb,
-                _ => Connection::Unknown(dev.properties.connection_type),
-            };
-            debug!("Connection type: {connection_type:?}");
-            devs.push(UsbmuxdDevice {
-                connection_type,
-                udid: dev.properties.serial_number,
-                device_id: dev.device_id,
-            })
+            devs.push(dev.try_into()?);
         }
 
         Ok(devs)
     }
 
+    /// Subscribes to attach and detach events. Read them with ``next_event``.
+    /// The connection can't be used for other requests afterwards.
+    pub async fn listen(&mut self) -> Result<(), IdeviceError> {
+        let mut req = plist::Dictionary::new();
+        req.insert("MessageType".into(), "Listen".into());
+        req.insert("ClientVersionString".into(), "idevice-rs".into());
+        req.insert("kLibUSBMuxVersion".into(), 3.into());
+        self.write_plist(req).await?;
+        match self.read_plist().await?.get("Number") {
+            Some(plist::Value::Integer(i)) => match i.as_unsigned() {
+                Some(0) => Ok(()),
+                Some(1) => Err(IdeviceError::UsbBadCommand),
+                Some(6) => Err(IdeviceError::UsbBadVersion),
+                _ => Err(IdeviceError::UnexpectedResponse),
+            },
+            _ => Err(IdeviceError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn next_event(&mut self) -> Result<UsbmuxdEvent, IdeviceError> {
+        loop {
+            let res = self.read_plist().await?;
+            let device_id = res
+                .get("DeviceID")
+                .and_then(|d| d.as_unsigned_integer())
+                .map(|d| d as u32);
+
+            match (
+                res.get("MessageType").and_then(|m| m.as_string()),
+                device_id,
+            ) {
+                (Some("Attached"), _) => {
+                    let dev = plist::from_value::<des::DeviceListResponse>(
+                        &plist::Value::Dictionary(res),
+                    )?;
+                    return Ok(UsbmuxdEvent::Attached(dev.try_into()?));
+                }
+                (Some("Detached"), Some(id)) => return Ok(UsbmuxdEvent::Detached(id)),
+                (Some("Paired"), Some(id)) => return Ok(UsbmuxdEvent::Paired(id)),
+                (m, _) => {
+                    debug!("Ignoring muxer message {m:?}");
+                    continue;
+                }
+            }
+        }
+    }
+
     pub async fn get_device(&mut self, udid: &str) -> Result<UsbmuxdDevice, IdeviceError> {
         let devices = self.get_devices().await?;
         match devices.into_iter().find(|x| x.udid == udid) {
@@ -190,6 +264,16 @@ impl UsbmuxdConnection {
         }
     }
 
+    /// Every way usbmuxd can reach a device. One with Wi-Fi sync on that's also plugged in
+    /// is listed once over USB and once over the network.
+    pub async fn get_device_connections(
+        &mut self,
+        udid: &str,
+    ) -> Result<Vec<UsbmuxdDevice>, IdeviceError> {
+        let devices = self.get_devices().await?;
+        Ok(devices.into_iter().filter(|x| x.udid == udid).collect())
+    }
+
     pub async fn get_pair_record(&mut self, udid: &str) -> Result<PairingFile, IdeviceError> {
         debug!("Getting pair record for {udid}");
         let mut req = plist::Dictionary::new();
@@ -251,27 +335,83 @@ impl UsbmuxdConnection {
             self.tag,
         );
 
-        let raw: Vec<u8> = raw.into();
-        self.socket.write_all(&raw).await?;
+        self.socket.write_all(&raw.encode()?).await?;
 
         Ok(())
     }
 
     async fn read_plist(&mut self) -> Result<plist::Dictionary, IdeviceError> {
-        let mut header_buffer = [0; 16];
-        self.socket.read_exact(&mut header_buffer).await?;
+        let raw: raw_packet::RawPacket = read_frame(&mut self.socket).await?;
+        let res = raw.plist;
+        debug!("Read from muxer: {}", crate::pretty_print_dictionary(&res));
 
-        // We are safe to unwrap as it only panics if the buffer isn't 4
-        let packet_size = u32::from_le_bytes(header_buffer[..4].try_into().unwrap()) - 16;
-        debug!("Reading {packet_size} bytes from muxer");
+        Ok(res)
+    }
+}
 
-        let mut body_buffer = vec![0; packet_size as usize];
-        self.socket.read_exact(&mut body_buffer).await?;
+impl TryFrom<des::DeviceListResponse> for UsbmuxdDevice {
+    type Error = IdeviceError;
 
-        let res = plist::from_bytes(&body_buffer)?;
-        debug!("Read from muxer: {}", crate::pretty_print_dictionary(&res));
+    fn try_from(dev: des::DeviceListResponse) -> Result<Self, Self::Error> {
+        let connection_type = match dev.properties.connection_type.as_str() {
+            "Network" => {
+                if let Some(addr) = dev.properties.network_address {
+                    let addr = &Into::<Vec<u8>>::into(addr);
+                    if addr.len() < 8 {
+                        warn!("Device address bytes len < 8");
+                        return Err(IdeviceError::UnexpectedResponse);
+                    }
 
-        Ok(res)
+                    match addr[0] {
+                        0x02 => {
+                            // ipv4
+                            Connection::Network(IpAddr::V4(Ipv4Addr::new(
+                                addr[4], addr[5], addr[6], addr[7],
+                            )))
+                        }
+                        0x1E => {
+                            // ipv6
+                            if addr.len() < 24 {
+                                warn!("IPv6 address is less than 24 bytes");
+                                return Err(IdeviceError::UnexpectedResponse);
+                            }
+
+                            Connection::Network(IpAddr::V6(Ipv6Addr::new(
+                                u16::from_be_bytes([addr[8], addr[9]]),
+                                u16::from_be_bytes([addr[10], addr[11]]),
+                                u16::from_be_bytes([addr[12], addr[13]]),
+                                u16::from_be_bytes([addr[14], addr[15]]),
+                                u16::from_be_bytes([addr[16], addr[17]]),
+                                u16::from_be_bytes([addr[18], addr[19]]),
+                                u16::from_be_bytes([addr[20], addr[21]]),
+                                u16::from_be_bytes([addr[22], addr[23]]),
+                            )))
+                        }
+                        _ => {
+                            warn!("Unknown IP address protocol: {:02X}", addr[0]);
+                            Connection::Unknown(format!("Network {:02X}", addr[0]))
+                        }
+                    }
+                } else {
+                    warn!("Device is network attached, but has no network info");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            }
+            "USB" => Connection::Usb,
+            _ => Connection::Unknown(dev.properties.connection_type),
+        };
+        debug!("Connection type: {connection_type:?}");
+        let mode = match dev.properties.product_id {
+            Some(product_id) => DeviceMode::from_product_id(product_id),
+            // The muxer only lists devices it can talk to
+            None => DeviceMode::Normal,
+        };
+        Ok(UsbmuxdDevice {
+            connection_type,
+            udid: dev.properties.serial_number,
+            device_id: dev.device_id,
+            mode,
+        })
     }
 }
 
@@ -1,6 +1,7 @@
 // Jackson Coxson
 
-use crate::util::plist_to_xml_bytes;
+use crate::{codec::Codec, util::plist_to_xml_bytes, IdeviceError};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use log::warn;
 
 #[derive(Debug)]
@@ -26,15 +27,48 @@ impl RawPacket {
     }
 }
 
+impl Codec for RawPacket {
+    const HEADER_LEN: usize = 16;
+
+    fn frame_length(mut header: &[u8]) -> Result<usize, IdeviceError> {
+        Ok(header.get_u32_le() as usize)
+    }
+
+    fn decode_frame(mut frame: &[u8]) -> Result<Self, IdeviceError> {
+        let size = frame.get_u32_le();
+        let version = frame.get_u32_le();
+        let message = frame.get_u32_le();
+        let tag = frame.get_u32_le();
+        let plist = plist::from_bytes(frame)?;
+
+        Ok(RawPacket {
+            size,
+            version,
+            message,
+            tag,
+            plist,
+        })
+    }
+
+    fn encode(&self) -> Result<Bytes, IdeviceError> {
+        let plist = plist_to_xml_bytes(&self.plist);
+        let mut packet = BytesMut::with_capacity(16 + plist.len());
+        packet.put_u32_le(plist.len() as u32 + 16);
+        packet.put_u32_le(self.version);
+        packet.put_u32_le(self.message);
+        packet.put_u32_le(self.tag);
+        packet.put_slice(&plist);
+        Ok(packet.freeze())
+    }
+}
+
 impl From<RawPacket> for Vec<u8> {
     fn from(raw_packet: RawPacket) -> Vec<u8> {
-        let mut packet = vec![];
-        packet.extend_from_slice(&raw_packet.size.to_le_bytes());
-        packet.extend_from_slice(&raw_packet.version.to_le_bytes());
-        packet.extend_from_slice(&raw_packet.message.to_le_bytes());
-        packet.extend_from_slice(&raw_packet.tag.to_le_bytes());
-        packet.extend_from_slice(&plist_to_xml_bytes(&raw_packet.plist));
-        packet
+        // Only the plist can fail to encode, and plist_to_xml_bytes already panics on that
+        raw_packet
+            .encode()
+            .expect("Failed to encode raw packet")
+            .into()
     }
 }
 
@@ -55,62 +89,22 @@ impl TryFrom<&[u8]> for RawPacket {
             return Err(());
         }
 
-        let packet_size = &packet[0..4];
-        let packet_size = u32::from_le_bytes(match packet_size.try_into() {
-            Ok(packet_size) => packet_size,
-            Err(_) => {
-                warn!("Failed to parse packet size");
+        let packet_size = match Self::frame_length(packet) {
+            Ok(s) if s >= 16 => s,
+            _ => {
+                warn!("Invalid raw packet size");
                 return Err(());
             }
-        });
+        };
 
         // Determine if we have enough data to parse
-        if packet.len() < packet_size as usize {
+        if packet.len() < packet_size {
             warn!("Not enough data to parse a raw packet body");
             return Err(());
         }
 
-        let packet_version = &packet[4..8];
-        let packet_version = u32::from_le_bytes(match packet_version.try_into() {
-            Ok(packet_version) => packet_version,
-            Err(_) => {
-                warn!("Failed to parse packet version");
-                return Err(());
-            }
-        });
-
-        let message = &packet[8..12];
-        let message = u32::from_le_bytes(match message.try_into() {
-            Ok(message) => message,
-            Err(_) => {
-                warn!("Failed to parse packet message");
-                return Err(());
-            }
-        });
-
-        let packet_tag = &packet[12..16];
-        let packet_tag = u32::from_le_bytes(match packet_tag.try_into() {
-            Ok(packet_tag) => packet_tag,
-            Err(_) => {
-                warn!("Failed to parse packet tag");
-                return Err(());
-            }
-        });
-
-        let plist = &packet[16..packet_size as usize];
-        let plist = if let Ok(p) = plist::from_bytes(plist) {
-            p
-        } else {
-            warn!("Failed to parse packet plist");
-            return Err(());
-        };
-
-        Ok(RawPacket {
-            size: packet_size,
-            version: packet_version,
-            message,
-            tag: packet_tag,
-            plist,
+        Self::decode_frame(&packet[..packet_size]).map_err(|e| {
+            warn!("Failed to parse raw packet: {e:?}");
         })
     }
 }
@@ -1,7 +1,36 @@
 // Jackson Coxson
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::warn;
 use plist::Value;
 
+use crate::IdeviceError;
+
+/// The default limit for ``set_max_message_size``
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+static MAX_MESSAGE_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE);
+
+/// Sets the largest length prefix accepted from a device before anything is allocated for it.
+/// Raise this if a service legitimately sends larger messages.
+pub fn set_max_message_size(size: usize) {
+    MAX_MESSAGE_SIZE.store(size, Ordering::Relaxed);
+}
+
+pub fn max_message_size() -> usize {
+    MAX_MESSAGE_SIZE.load(Ordering::Relaxed)
+}
+
+/// Rejects a length read off the wire if it's over the limit
+pub(crate) fn check_message_size(len: usize) -> Result<usize, IdeviceError> {
+    if len > max_message_size() {
+        warn!("Peer sent a message length of {len}, over the limit");
+        return Err(IdeviceError::MessageTooLarge(len));
+    }
+    Ok(len)
+}
+
 pub fn plist_to_xml_bytes(p: &plist::Dictionary) -> Vec<u8> {
     let buf = Vec::new();
     let mut writer = std::io::BufWriter::new(buf);
@@ -10,6 +39,23 @@ pub fn plist_to_xml_bytes(p: &plist::Dictionary) -> Vec<u8> {
     writer.into_inner().unwrap()
 }
 
+/// Compares dotted version strings, treating missing components as 0
+pub(crate) fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |v: &str| {
+        v.split('.')
+            .map(|p| p.trim().parse::<u64>().unwrap_or(0))
+            .collect::<Vec<u64>>()
+    };
+    let (a, b) = (parse(a), parse(b));
+    for i in 0..a.len().max(b.len()) {
+        let ord = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ord != std::cmp::Ordering::Equal {
+            return ord;
+        }
+    }
+    std::cmp::Ordering::Equal
+}
+
 pub fn pretty_print_plist(p: &Value) -> String {
     print_plist(p, 0)
 }
@@ -17,11 +63,20 @@ pub fn pretty_print_plist(p: &Value) -> String {
 pub fn pretty_print_dictionary(dict: &plist::Dictionary) -> String {
     let items: Vec<String> = dict
         .iter()
-        .map(|(k, v)| format!("{}: {}", k, print_plist(v, 2)))
+        .map(|(k, v)| format!("{}: {}", k, print_entry(k, v, 2)))
         .collect();
     format!("{{\n{}\n}}", items.join(",\n"))
 }
 
+/// Prints a dictionary value, unless it's a secret
+fn print_entry(key: &str, value: &Value, indentation: usize) -> String {
+    if crate::pretty::is_secret(key) {
+        crate::pretty::REDACTED.to_string()
+    } else {
+        print_plist(value, indentation)
+    }
+}
+
 fn print_plist(p: &Value, indentation: usize) -> String {
     let indent = " ".repeat(indentation);
     match p {
@@ -46,7 +101,7 @@ fn print_plist(p: &Value, indentation: usize) -> String {
                         "{}{}: {}",
                         " ".repeat(indentation + 2),
                         k,
-                        print_plist(v, indentation + 2)
+                        print_entry(k, v, indentation + 2)
                     )
                 })
                 .collect();
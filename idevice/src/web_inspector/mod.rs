@@ -1,115 +1,476 @@
 //! Web Inspector service implementation
+//!
+//! Speaks the remote Web Inspector (RWI) protocol Safari uses to debug web content on the
+//! device. Every message is a plist naming a selector, with an argument dictionary.
+//! Inspector protocol messages for a page are JSON, carried as data inside those plists.
 
-use crate::{IdeviceError, IdeviceService, ServiceProviderType};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+mod page;
+mod watch;
 
-const WEB_INSPECTOR_SERVICE_NAME: &str = "com.apple.webinspector";
+pub use page::Page;
+pub use watch::{watch_pages, PageEvent, PageWatcher};
+
+use log::{debug, warn};
+use plist::Dictionary;
+
+use crate::{codec::WireFormat, lockdownd::LockdowndClient, Idevice, IdeviceError, IdeviceService};
+
+/// An app on the device with web content that can be inspected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Application {
+    /// The identifier the inspector uses for the app, like ``PID:123``
+    pub id: String,
+    pub name: Option<String>,
+    pub bundle_id: Option<String>,
+    pub active: bool,
+    /// Web content processes report the app they show content for as their host
+    pub host_id: Option<String>,
+}
+
+/// What kind of content an inspectable page is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageType {
+    /// A web page, such as a Safari tab or a web view in an app
+    Web,
+    ServiceWorker,
+    /// A JSContext an app created, without a page around it
+    JavaScript,
+    /// A page driven by WebDriver
+    Automation,
+    Other(String),
+}
+
+impl From<&str> for PageType {
+    fn from(t: &str) -> Self {
+        match t {
+            "WIRTypeWeb" | "WIRTypeWebPage" => Self::Web,
+            "WIRTypeServiceWorker" => Self::ServiceWorker,
+            "WIRTypeJavaScript" => Self::JavaScript,
+            "WIRTypeAutomation" => Self::Automation,
+            t => Self::Other(t.to_string()),
+        }
+    }
+}
+
+/// A page, or other inspectable target, listed by an app
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectablePage {
+    /// The app that listed the page
+    pub app: Application,
+    pub id: u64,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub page_type: PageType,
+    /// The connection of the inspector debugging the page, if one is
+    pub connection_id: Option<String>,
+}
 
 /// Web Inspector client for debugging web content
 pub struct WebInspectorClient {
-    socket: tokio::net::TcpStream,
+    pub idevice: Idevice,
+    connection_id: String,
+}
+
+impl IdeviceService for WebInspectorClient {
+    fn service_name() -> &'static str {
+        "com.apple.webinspector"
+    }
+
+    async fn connect(
+        provider: &dyn crate::provider::IdeviceProvider,
+    ) -> Result<Self, IdeviceError> {
+        let mut lockdown = LockdowndClient::connect(provider).await?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await?;
+        let (port, ssl) = lockdown.start_service(Self::service_name()).await?;
+
+        let mut idevice = provider.connect(port).await?;
+        if ssl {
+            idevice
+                .start_session(&provider.get_pairing_file().await?)
+                .await?;
+        }
+
+        Self::from_idevice(idevice).await
+    }
+
+    async fn from_idevice(idevice: Idevice) -> Result<Self, IdeviceError> {
+        let mut client = Self::new(idevice);
+        client.report_identifier().await?;
+        Ok(client)
+    }
 }
 
 impl WebInspectorClient {
-    /// Connect to the Web Inspector service
-    pub async fn connect(provider: &dyn ServiceProviderType) -> Result<Self, IdeviceError> {
-        let service = provider.start_service(WEB_INSPECTOR_SERVICE_NAME).await?;
-        Ok(Self {
-            socket: service.socket,
-        })
+    /// Creates a client on a connection to the service.
+    /// ``report_identifier`` has to be called before anything else is sent.
+    pub fn new(mut idevice: Idevice) -> Self {
+        idevice.default_wire_format(WireFormat::for_service(Self::service_name()));
+        Self {
+            idevice,
+            connection_id: new_identifier(),
+        }
     }
 
-    /// Get list of inspectable applications
-    pub async fn get_applications(&mut self) -> Result<Vec<String>, IdeviceError> {
-        let mut command = [0u8; 4];
-        command[0] = b'L';
-        self.socket.write_all(&command).await?;
-        
-        let mut len_buf = [0u8; 4];
-        self.socket.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-        
-        let mut data = vec![0u8; len];
-        self.socket.read_exact(&mut data).await?;
-        
-        let plist: plist::Value = plist::from_bytes(&data)
-            .map_err(|e| IdeviceError::WebInspectorError(e.to_string()))?;
-
-        // Parse applications from plist structure
-        let applications = plist.as_dictionary()
-            .and_then(|dict| dict.get("Applications"))
-            .and_then(|apps| apps.as_array())
-            .ok_or_else(|| IdeviceError::WebInspectorError("Invalid application list structure".into()))?;
-
-        let mut app_list = Vec::new();
-        for app in applications {
-            if let Some(name) = app.as_dictionary()
-                .and_then(|app_dict| app_dict.get("Name"))
-                .and_then(|name_val| name_val.as_string()) 
-            {
-                app_list.push(name.to_string());
+    /// Introduces this connection to the inspector
+    pub async fn report_identifier(&mut self) -> Result<(), IdeviceError> {
+        self.send("_rpc_reportIdentifier:", Dictionary::new()).await
+    }
+
+    /// Gets the apps that currently have inspectable content
+    pub async fn get_applications(&mut self) -> Result<Vec<Application>, IdeviceError> {
+        self.send("_rpc_getConnectedApplications:", Dictionary::new())
+            .await?;
+        let argument = self
+            .receive_matching("_rpc_reportConnectedApplicationList:", |_| true)
+            .await?;
+
+        let applications = match argument.get("WIRApplicationDictionaryKey") {
+            Some(plist::Value::Dictionary(a)) => a,
+            _ => {
+                warn!("Application list didn't contain an application dictionary");
+                return Err(IdeviceError::UnexpectedResponse);
             }
+        };
+        Ok(applications
+            .values()
+            .filter_map(|a| a.as_dictionary())
+            .filter_map(parse_application)
+            .collect())
+    }
+
+    /// Gets the pages an app has open
+    pub async fn get_pages(
+        &mut self,
+        app: &Application,
+    ) -> Result<Vec<InspectablePage>, IdeviceError> {
+        self.request_listing(&app.id).await?;
+        let argument = self
+            .receive_matching("_rpc_applicationSentListing:", |a| {
+                string(a, "WIRApplicationIdentifierKey") == Some(app.id.as_str())
+            })
+            .await?;
+        parse_listing(app, &argument)
+    }
+
+    /// Gets the pages of every app, like the list ios-webkit-debug-proxy serves
+    pub async fn list_pages(&mut self) -> Result<Vec<InspectablePage>, IdeviceError> {
+        let mut pages = Vec::new();
+        for app in self.get_applications().await? {
+            pages.extend(self.get_pages(&app).await?);
         }
+        Ok(pages)
+    }
 
-        Ok(app_list)
-    }
-
-    /// Connect to a specific web view
-    pub async fn connect_to_webview(&mut self, app_id: &str) -> Result<tokio_tungstenite::WebSocketStream<
-        tokio::net::TcpStream
-    >, IdeviceError> {
-        // Send connect command
-        let mut command = [0u8; 4];
-        command[0] = b'C';
-        self.socket.write_all(&command).await?;
-        
-        // Send application ID
-        let app_id_bytes = app_id.as_bytes();
-        let len = (app_id_bytes.len() as u32).to_be_bytes();
-        self.socket.write_all(&len).await?;
-        self.socket.write_all(app_id_bytes).await?;
-
-        // Read WebSocket connection details
-        let mut len_buf = [0u8; 4];
-        self.socket.read_exact(&mut len_buf).await?;
-        let len = u32::from_be_bytes(len_buf) as usize;
-        
-        let mut data = vec![0u8; len];
-        self.socket.read_exact(&mut data).await?;
-        
-        let plist: plist::Value = plist::from_bytes(&data)
-            .map_err(|e| IdeviceError::WebInspectorError(e.to_string()))?;
-
-        // Parse WebSocket connection details
-        let ws_url = plist.as_dictionary()
-            .and_then(|dict| dict.get("WebSocketURL"))
-            .and_then(|url| url.as_string())
-            .ok_or_else(|| IdeviceError::WebInspectorError("Missing WebSocket URL".into()))?;
-
-        // Establish WebSocket connection
-        let (ws_stream, _) = tokio_tungstenite::connect_async(ws_url)
+    /// Opens an inspector session on a page, to evaluate JavaScript and navigate it
+    pub async fn open_page(
+        &mut self,
+        app_id: &str,
+        page_id: u64,
+    ) -> Result<Page<'_>, IdeviceError> {
+        Page::open(self, app_id, page_id).await
+    }
+
+    /// Asks an app for its pages, which it answers with ``_rpc_applicationSentListing:``
+    async fn request_listing(&mut self, app_id: &str) -> Result<(), IdeviceError> {
+        let mut argument = Dictionary::new();
+        argument.insert("WIRApplicationIdentifierKey".into(), app_id.into());
+        self.send("_rpc_forwardGetListing:", argument).await
+    }
+
+    /// Sends a message with the connection identifier added to its argument
+    async fn send(&mut self, selector: &str, mut argument: Dictionary) -> Result<(), IdeviceError> {
+        argument.insert(
+            "WIRConnectionIdentifierKey".into(),
+            self.connection_id.clone().into(),
+        );
+        let mut message = Dictionary::new();
+        message.insert("__selector".into(), selector.into());
+        message.insert("__argument".into(), argument.into());
+        self.idevice
+            .send_plist(plist::Value::Dictionary(message))
             .await
-            .map_err(|e| IdeviceError::WebInspectorError(e.to_string()))?;
+    }
 
-        Ok(ws_stream)
+    /// Reads the next message, returning its selector and argument
+    async fn receive(&mut self) -> Result<(String, Dictionary), IdeviceError> {
+        loop {
+            let mut message = self.idevice.read_plist().await?;
+            let selector = match message.remove("__selector") {
+                Some(plist::Value::String(s)) => s,
+                _ => {
+                    warn!("Received a web inspector message without a selector");
+                    return Err(IdeviceError::UnexpectedResponse);
+                }
+            };
+            let argument = match message.remove("__argument") {
+                Some(plist::Value::Dictionary(a)) => a,
+                _ => Dictionary::new(),
+            };
+            // The device sends this once after connecting, and it isn't an answer to anything
+            if selector == "_rpc_reportSetup:" {
+                debug!("Web inspector setup: {argument:?}");
+                continue;
+            }
+            return Ok((selector, argument));
+        }
     }
 
-    /// Forward developer tools protocol messages
-    pub async fn forward_messages(
-        ws_stream: &mut tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
-    ) -> Result<(), IdeviceError> {
+    /// Waits for a message with the given selector that ``matches`` accepts,
+    /// dropping anything else received in the meantime
+    async fn receive_matching(
+        &mut self,
+        selector: &str,
+        matches: impl Fn(&Dictionary) -> bool,
+    ) -> Result<Dictionary, IdeviceError> {
         loop {
-            match ws_stream.next().await {
-                Some(Ok(msg)) => {
-                    // Handle incoming WebSocket messages
-                    if let tungstenite::Message::Text(text) = msg {
-                        println!("Received DevTools message: {}", text);
+            let (s, argument) = self.receive().await?;
+            if s == selector && matches(&argument) {
+                return Ok(argument);
+            }
+            debug!("Skipping web inspector message {s}");
+        }
+    }
+}
+
+fn parse_application(app: &Dictionary) -> Option<Application> {
+    Some(Application {
+        id: string(app, "WIRApplicationIdentifierKey")?.to_string(),
+        name: string(app, "WIRApplicationNameKey").map(|s| s.to_string()),
+        bundle_id: string(app, "WIRApplicationBundleIdentifierKey").map(|s| s.to_string()),
+        active: app
+            .get("WIRIsApplicationActiveKey")
+            .and_then(|a| a.as_unsigned_integer())
+            .is_some_and(|a| a != 0),
+        host_id: string(app, "WIRHostApplicationIdentifierKey").map(|s| s.to_string()),
+    })
+}
+
+/// Reads the pages out of an ``_rpc_applicationSentListing:`` argument, sorted by id
+fn parse_listing(
+    app: &Application,
+    argument: &Dictionary,
+) -> Result<Vec<InspectablePage>, IdeviceError> {
+    let listing = match argument.get("WIRListingKey") {
+        Some(plist::Value::Dictionary(l)) => l,
+        _ => {
+            warn!("Listing didn't contain a listing dictionary");
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+    };
+    let mut pages: Vec<InspectablePage> = listing
+        .values()
+        .filter_map(|p| p.as_dictionary())
+        .filter_map(|p| {
+            Some(InspectablePage {
+                app: app.clone(),
+                id: p.get("WIRPageIdentifierKey")?.as_unsigned_integer()?,
+                title: string(p, "WIRTitleKey").map(|s| s.to_string()),
+                url: string(p, "WIRURLKey").map(|s| s.to_string()),
+                page_type: string(p, "WIRTypeKey").unwrap_or("WIRTypeWeb").into(),
+                connection_id: string(p, "WIRConnectionIdentifierKey").map(|s| s.to_string()),
+            })
+        })
+        .collect();
+    pages.sort_by_key(|p| p.id);
+    Ok(pages)
+}
+
+fn string<'a>(dict: &'a Dictionary, key: &str) -> Option<&'a str> {
+    dict.get(key).and_then(|v| v.as_string())
+}
+
+/// The inspector identifies connections and senders by uppercase UUIDs
+fn new_identifier() -> String {
+    uuid::Uuid::new_v4().to_string().to_uppercase()
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::{future::Future, pin::Pin};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing::{MockProvider, MockTransport, Responder};
+
+    /// A Safari with one page, behind a target like on newer iOS versions
+    struct SafariResponder;
+
+    fn message(selector: &str, argument: Dictionary) -> Dictionary {
+        let mut message = Dictionary::new();
+        message.insert("__selector".into(), selector.into());
+        message.insert("__argument".into(), argument.into());
+        message
+    }
+
+    fn page_data(argument: &Dictionary, message: &Value) -> Dictionary {
+        let mut data = Dictionary::new();
+        data.insert("WIRDestinationKey".into(), argument["WIRSenderKey"].clone());
+        data.insert(
+            "WIRMessageDataKey".into(),
+            plist::Value::Data(message.to_string().into_bytes()),
+        );
+        message_data(data)
+    }
+
+    fn message_data(data: Dictionary) -> Dictionary {
+        message("_rpc_applicationSentData:", data)
+    }
+
+    impl Responder for SafariResponder {
+        fn serve(
+            &self,
+            mut transport: MockTransport,
+        ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+            Box::pin(async move {
+                let mut url = "https://example.com/".to_string();
+                loop {
+                    let req = transport.read_plist().await?;
+                    let selector = req["__selector"].as_string().unwrap_or_default();
+                    let argument = req["__argument"].as_dictionary().unwrap().clone();
+                    match selector {
+                        "_rpc_reportIdentifier:" => {
+                            transport
+                                .send_plist(message("_rpc_reportSetup:", Dictionary::new()))
+                                .await?;
+                        }
+                        "_rpc_getConnectedApplications:" => {
+                            let mut app = Dictionary::new();
+                            app.insert("WIRApplicationIdentifierKey".into(), "PID:42".into());
+                            app.insert("WIRApplicationNameKey".into(), "Safari".into());
+                            app.insert(
+                                "WIRApplicationBundleIdentifierKey".into(),
+                                "com.apple.mobilesafari".into(),
+                            );
+                            app.insert("WIRIsApplicationActiveKey".into(), 1.into());
+                            let mut apps = Dictionary::new();
+                            apps.insert("PID:42".into(), app.into());
+                            let mut res = Dictionary::new();
+                            res.insert("WIRApplicationDictionaryKey".into(), apps.into());
+                            transport
+                                .send_plist(message("_rpc_reportConnectedApplicationList:", res))
+                                .await?;
+                        }
+                        "_rpc_forwardGetListing:" => {
+                            let mut page = Dictionary::new();
+                            page.insert("WIRPageIdentifierKey".into(), 1.into());
+                            page.insert("WIRTitleKey".into(), "Example".into());
+                            page.insert("WIRURLKey".into(), url.clone().into());
+                            page.insert("WIRTypeKey".into(), "WIRTypeWebPage".into());
+                            let mut listing = Dictionary::new();
+                            listing.insert("1".into(), page.into());
+                            let mut res = Dictionary::new();
+                            res.insert("WIRApplicationIdentifierKey".into(), "PID:42".into());
+                            res.insert("WIRListingKey".into(), listing.into());
+                            transport
+                                .send_plist(message("_rpc_applicationSentListing:", res))
+                                .await?;
+                        }
+                        "_rpc_forwardSocketSetup:" => {
+                            let created = json!({
+                                "method": "Target.targetCreated",
+                                "params": { "targetInfo": { "targetId": "page-1", "type": "page" } },
+                            });
+                            transport.send_plist(page_data(&argument, &created)).await?;
+                        }
+                        "_rpc_forwardSocketData:" => {
+                            let data = argument["WIRSocketDataKey"].as_data().unwrap();
+                            let outer: Value = serde_json::from_slice(data).unwrap();
+                            if outer["method"] != "Target.sendMessageToTarget" {
+                                let res = json!({
+                                    "id": outer["id"],
+                                    "error": { "message": "'Target' domain required" },
+                                });
+                                transport.send_plist(page_data(&argument, &res)).await?;
+                                continue;
+                            }
+                            let inner: Value =
+                                serde_json::from_str(outer["params"]["message"].as_str().unwrap())
+                                    .unwrap();
+                            let result = match inner["method"].as_str() {
+                                Some("Runtime.evaluate") => {
+                                    match inner["params"]["expression"].as_str() {
+                                        Some("document.title") => {
+                                            json!({ "result": { "type": "string", "value": "Example" } })
+                                        }
+                                        Some("location.href") => {
+                                            json!({ "result": { "type": "string", "value": url } })
+                                        }
+                                        _ => json!({
+                                            "result": { "type": "object", "description": "ReferenceError: Can't find variable: nope" },
+                                            "wasThrown": true,
+                                        }),
+                                    }
+                                }
+                                Some("Page.navigate") => {
+                                    url = inner["params"]["url"].as_str().unwrap().to_string();
+                                    json!({})
+                                }
+                                _ => json!({}),
+                            };
+                            // Another session's traffic, which has to be skipped
+                            let mut other = Dictionary::new();
+                            other.insert("WIRDestinationKey".into(), "SOMEONE-ELSE".into());
+                            other.insert(
+                                "WIRMessageDataKey".into(),
+                                plist::Value::Data(b"{}".to_vec()),
+                            );
+                            transport.send_plist(message_data(other)).await?;
+
+                            let event = json!({ "method": "Page.frameNavigated", "params": {} });
+                            let res = json!({ "id": inner["id"], "result": result });
+                            for m in [event, res] {
+                                let wrapped = json!({
+                                    "method": "Target.dispatchMessageFromTarget",
+                                    "params": { "targetId": "page-1", "message": m.to_string() },
+                                });
+                                transport.send_plist(page_data(&argument, &wrapped)).await?;
+                            }
+                        }
+                        _ => {}
                     }
                 }
-                Some(Err(e)) => return Err(IdeviceError::WebInspectorError(e.to_string())),
-                None => break,
-            }
+            })
         }
-        Ok(())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn pages_are_evaluated() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(WebInspectorClient::service_name(), SafariResponder);
+        let mut client = WebInspectorClient::connect(&provider).await.unwrap();
+
+        let apps = client.get_applications().await.unwrap();
+        assert_eq!(apps.len(), 1);
+        assert_eq!(apps[0].name.as_deref(), Some("Safari"));
+        assert!(apps[0].active);
+        let pages = client.get_pages(&apps[0]).await.unwrap();
+        assert_eq!(pages[0].title.as_deref(), Some("Example"));
+        assert_eq!(pages[0].page_type, PageType::Web);
+        assert_eq!(
+            pages[0].app.bundle_id.as_deref(),
+            Some("com.apple.mobilesafari")
+        );
+        assert_eq!(client.list_pages().await.unwrap(), pages);
+
+        let mut page = client.open_page("PID:42", pages[0].id).await.unwrap();
+        assert_eq!(page.evaluate("document.title").await.unwrap(), "Example");
+        page.navigate("https://example.org/").await.unwrap();
+        assert_eq!(
+            page.evaluate("location.href").await.unwrap(),
+            "https://example.org/"
+        );
+        match page.evaluate("nope").await {
+            Err(IdeviceError::JavaScriptException(e)) => assert!(e.contains("ReferenceError")),
+            r => panic!("Expected an exception, got {r:?}"),
+        }
+        assert_eq!(
+            page.next_event().await.unwrap()["method"],
+            "Page.frameNavigated"
+        );
+        page.close().await.unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Inspector sessions on a single page
+//!
+//! Commands are matched to their results by id, so callers only see the result of what
+//! they sent. Newer iOS versions put each page behind a target, announced with
+//! ``Target.targetCreated`` once the session is set up; commands are then wrapped for that
+//! target, and a command already sent unwrapped is sent again through it.
+
+use std::collections::VecDeque;
+
+use log::{debug, warn};
+use plist::Dictionary;
+use serde_json::{json, Value};
+
+use super::{new_identifier, string, WebInspectorClient};
+use crate::IdeviceError;
+
+/// An open inspector session on a page, created by ``WebInspectorClient::open_page``
+pub struct Page<'a> {
+    client: &'a mut WebInspectorClient,
+    app_id: String,
+    page_id: u64,
+    sender_id: String,
+    target_id: Option<String>,
+    next_id: u64,
+    /// Events received while waiting for a command's result
+    events: VecDeque<Value>,
+}
+
+impl<'a> Page<'a> {
+    pub(super) async fn open(
+        client: &'a mut WebInspectorClient,
+        app_id: &str,
+        page_id: u64,
+    ) -> Result<Self, IdeviceError> {
+        let page = Self {
+            client,
+            app_id: app_id.to_string(),
+            page_id,
+            sender_id: new_identifier(),
+            target_id: None,
+            next_id: 1,
+            events: VecDeque::new(),
+        };
+
+        let mut argument = page.argument();
+        argument.insert("WIRAutomaticallyPause".into(), false.into());
+        page.client
+            .send("_rpc_forwardSocketSetup:", argument)
+            .await?;
+        Ok(page)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.page_id
+    }
+
+    /// Evaluates JavaScript in the page, returning the result as JSON.
+    /// Promises are awaited. Values that can't be represented as JSON come back as null.
+    pub async fn evaluate(&mut self, js: &str) -> Result<Value, IdeviceError> {
+        let res = self
+            .send_command(
+                "Runtime.evaluate",
+                json!({
+                    "expression": js,
+                    "returnByValue": true,
+                    "awaitPromise": true,
+                }),
+            )
+            .await?;
+
+        if res.get("wasThrown").and_then(|t| t.as_bool()) == Some(true) {
+            let description = res
+                .pointer("/result/description")
+                .and_then(|d| d.as_str())
+                .unwrap_or("No description given");
+            return Err(IdeviceError::JavaScriptException(description.to_string()));
+        }
+        Ok(res.pointer("/result/value").cloned().unwrap_or(Value::Null))
+    }
+
+    /// Loads a URL in the page. Returns once the navigation has started, not when it finishes.
+    pub async fn navigate(&mut self, url: &str) -> Result<(), IdeviceError> {
+        self.send_command("Page.navigate", json!({ "url": url }))
+            .await?;
+        Ok(())
+    }
+
+    /// Sends a raw inspector protocol command and waits for its result
+    pub async fn send_command(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, IdeviceError> {
+        let id = self.next_id();
+        let command = json!({ "id": id, "method": method, "params": params });
+        let mut sent_to_target = self.target_id.is_some();
+        self.send_message(&command).await?;
+
+        loop {
+            let (from_target, message) = self.receive_message().await?;
+            if !sent_to_target && self.target_id.is_some() {
+                debug!("Page has a target now, sending {method} again");
+                sent_to_target = true;
+                self.send_message(&command).await?;
+                continue;
+            }
+
+            if message.get("id").and_then(|i| i.as_u64()) == Some(id) {
+                // The answer to the unwrapped copy of a command that was sent again
+                if from_target != sent_to_target {
+                    continue;
+                }
+                if let Some(e) = message.get("error") {
+                    let reason = e
+                        .get("message")
+                        .and_then(|m| m.as_str())
+                        .unwrap_or("No error given");
+                    return Err(IdeviceError::InspectorCommandFailed(reason.to_string()));
+                }
+                return Ok(message.get("result").cloned().unwrap_or(Value::Null));
+            }
+            if message.get("method").is_some() {
+                self.events.push_back(message);
+            }
+        }
+    }
+
+    /// Waits for the next event the page sends, such as ``Page.loadEventFired``.
+    /// Events are only sent for domains that were enabled with a command first.
+    pub async fn next_event(&mut self) -> Result<Value, IdeviceError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let (_, message) = self.receive_message().await?;
+            if message.get("method").is_some() {
+                self.events.push_back(message);
+            }
+        }
+    }
+
+    /// Ends the session
+    pub async fn close(self) -> Result<(), IdeviceError> {
+        let argument = self.argument();
+        self.client.send("_rpc_forwardDidClose:", argument).await
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// The keys every message about this session carries
+    fn argument(&self) -> Dictionary {
+        let mut argument = Dictionary::new();
+        argument.insert(
+            "WIRApplicationIdentifierKey".into(),
+            self.app_id.clone().into(),
+        );
+        argument.insert("WIRPageIdentifierKey".into(), self.page_id.into());
+        argument.insert("WIRSenderKey".into(), self.sender_id.clone().into());
+        argument
+    }
+
+    /// Sends an inspector protocol message, wrapped for the page's target if it has one
+    async fn send_message(&mut self, message: &Value) -> Result<(), IdeviceError> {
+        let message = match self.target_id.clone() {
+            Some(target_id) => json!({
+                "id": self.next_id(),
+                "method": "Target.sendMessageToTarget",
+                "params": {
+                    "targetId": target_id,
+                    "message": message.to_string(),
+                },
+            }),
+            None => message.clone(),
+        };
+
+        let mut argument = self.argument();
+        argument.insert(
+            "WIRSocketDataKey".into(),
+            plist::Value::Data(serde_json::to_vec(&message)?),
+        );
+        self.client.send("_rpc_forwardSocketData:", argument).await
+    }
+
+    /// Reads the next inspector protocol message for this session, unwrapping messages
+    /// from the page's target. Returns whether the message came from the target.
+    async fn receive_message(&mut self) -> Result<(bool, Value), IdeviceError> {
+        let argument = self
+            .client
+            .receive_matching("_rpc_applicationSentData:", |a| {
+                string(a, "WIRDestinationKey") == Some(self.sender_id.as_str())
+            })
+            .await?;
+        let data = match argument.get("WIRMessageDataKey") {
+            Some(plist::Value::Data(d)) => d,
+            Some(plist::Value::String(s)) => s.as_bytes(),
+            _ => {
+                warn!("Inspector data didn't contain a message");
+                return Err(IdeviceError::UnexpectedResponse);
+            }
+        };
+        let message: Value = serde_json::from_slice(data)?;
+
+        match message.get("method").and_then(|m| m.as_str()) {
+            Some("Target.targetCreated") => {
+                let info = &message["params"]["targetInfo"];
+                if info["type"] == "page" {
+                    if let Some(target_id) = info["targetId"].as_str() {
+                        debug!("Page {} is behind target {target_id}", self.page_id);
+                        self.target_id = Some(target_id.to_string());
+                    }
+                }
+                Ok((false, message))
+            }
+            Some("Target.dispatchMessageFromTarget") => {
+                let inner = message["params"]["message"].as_str().unwrap_or_default();
+                Ok((true, serde_json::from_str(inner)?))
+            }
+            _ => Ok((false, message)),
+        }
+    }
+}
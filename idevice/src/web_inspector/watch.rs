@@ -0,0 +1,266 @@
+//! Following the pages on the device as they appear and disappear
+//!
+//! Apps send a new listing on their own whenever their pages change, and are reported as
+//! they start and quit, so nothing has to be polled.
+
+use super::{
+    parse_application, parse_listing, string, Application, InspectablePage, WebInspectorClient,
+};
+use crate::IdeviceError;
+use log::debug;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageEvent {
+    Appeared(InspectablePage),
+    /// The title, URL, debugging connection or app of a page changed
+    Changed(InspectablePage),
+    Disappeared(InspectablePage),
+}
+
+/// Follows the pages of every app, created by ``watch_pages``
+pub struct PageWatcher {
+    client: WebInspectorClient,
+    applications: HashMap<String, Application>,
+    /// Keyed by app and page id
+    pages: BTreeMap<(String, u64), InspectablePage>,
+    pending: VecDeque<PageEvent>,
+}
+
+/// Starts following the pages of every app.
+/// What's already open when this is called doesn't produce events.
+pub async fn watch_pages(mut client: WebInspectorClient) -> Result<PageWatcher, IdeviceError> {
+    let mut applications = HashMap::new();
+    let mut pages = BTreeMap::new();
+    for app in client.get_applications().await? {
+        for page in client.get_pages(&app).await? {
+            pages.insert((app.id.clone(), page.id), page);
+        }
+        applications.insert(app.id.clone(), app);
+    }
+    Ok(PageWatcher {
+        client,
+        applications,
+        pages,
+        pending: VecDeque::new(),
+    })
+}
+
+impl PageWatcher {
+    /// Waits for the next change.
+    /// Changes from the same listing are returned in page order, disappearances first.
+    pub async fn next(&mut self) -> Result<PageEvent, IdeviceError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+
+            let (selector, argument) = self.client.receive().await?;
+            match selector.as_str() {
+                "_rpc_applicationConnected:" | "_rpc_applicationUpdated:" => {
+                    if let Some(app) = parse_application(&argument) {
+                        self.client.request_listing(&app.id).await?;
+                        self.applications.insert(app.id.clone(), app);
+                    }
+                }
+                "_rpc_applicationDisconnected:" => {
+                    if let Some(app_id) = string(&argument, "WIRApplicationIdentifierKey") {
+                        self.applications.remove(app_id);
+                        self.update(app_id, Vec::new());
+                    }
+                }
+                "_rpc_applicationSentListing:" => {
+                    let app = string(&argument, "WIRApplicationIdentifierKey")
+                        .and_then(|id| self.applications.get(id));
+                    // A listing from an app that already quit
+                    let Some(app) = app.cloned() else {
+                        continue;
+                    };
+                    let pages = parse_listing(&app, &argument)?;
+                    self.update(&app.id, pages);
+                }
+                s => debug!("Skipping web inspector message {s}"),
+            }
+        }
+    }
+
+    /// The pages open right now, by app and then page id
+    pub fn pages(&self) -> impl Iterator<Item = &InspectablePage> {
+        self.pages.values()
+    }
+
+    /// Stops watching, returning the client
+    pub fn into_inner(self) -> WebInspectorClient {
+        self.client
+    }
+
+    /// Replaces an app's pages, queueing what changed
+    fn update(&mut self, app_id: &str, pages: Vec<InspectablePage>) {
+        let gone: Vec<(String, u64)> = self
+            .pages
+            .keys()
+            .filter(|(a, id)| a == app_id && !pages.iter().any(|p| p.id == *id))
+            .cloned()
+            .collect();
+        for key in gone {
+            if let Some(page) = self.pages.remove(&key) {
+                self.pending.push_back(PageEvent::Disappeared(page));
+            }
+        }
+
+        for page in pages {
+            match self
+                .pages
+                .insert((app_id.to_string(), page.id), page.clone())
+            {
+                None => self.pending.push_back(PageEvent::Appeared(page)),
+                Some(old) if old != page => self.pending.push_back(PageEvent::Changed(page)),
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::{future::Future, pin::Pin};
+
+    use plist::Dictionary;
+
+    use super::*;
+    use crate::testing::{MockProvider, MockTransport, Responder};
+    use crate::web_inspector::PageType;
+    use crate::IdeviceService;
+
+    /// Safari, which opens a tab and quits, and an app that starts with a JSContext
+    struct ChangingResponder;
+
+    fn message(selector: &str, argument: Dictionary) -> Dictionary {
+        let mut message = Dictionary::new();
+        message.insert("__selector".into(), selector.into());
+        message.insert("__argument".into(), argument.into());
+        message
+    }
+
+    fn app(id: &str, name: &str) -> Dictionary {
+        let mut app = Dictionary::new();
+        app.insert("WIRApplicationIdentifierKey".into(), id.into());
+        app.insert("WIRApplicationNameKey".into(), name.into());
+        app
+    }
+
+    fn listing(app_id: &str, pages: &[(u64, &str, &str)]) -> Dictionary {
+        let mut listing = Dictionary::new();
+        for (id, title, page_type) in pages {
+            let mut page = Dictionary::new();
+            page.insert("WIRPageIdentifierKey".into(), (*id).into());
+            page.insert("WIRTitleKey".into(), (*title).into());
+            page.insert("WIRTypeKey".into(), (*page_type).into());
+            listing.insert(id.to_string(), page.into());
+        }
+        let mut argument = Dictionary::new();
+        argument.insert("WIRApplicationIdentifierKey".into(), app_id.into());
+        argument.insert("WIRListingKey".into(), listing.into());
+        message("_rpc_applicationSentListing:", argument)
+    }
+
+    impl Responder for ChangingResponder {
+        fn serve(
+            &self,
+            mut transport: MockTransport,
+        ) -> Pin<Box<dyn Future<Output = Result<(), IdeviceError>> + Send>> {
+            Box::pin(async move {
+                loop {
+                    let req = transport.read_plist().await?;
+                    let argument = req["__argument"].as_dictionary().unwrap();
+                    let app_id = string(argument, "WIRApplicationIdentifierKey");
+                    match (req["__selector"].as_string().unwrap(), app_id) {
+                        ("_rpc_getConnectedApplications:", _) => {
+                            let mut apps = Dictionary::new();
+                            apps.insert("PID:1".into(), app("PID:1", "Safari").into());
+                            let mut argument = Dictionary::new();
+                            argument.insert("WIRApplicationDictionaryKey".into(), apps.into());
+                            transport
+                                .send_plist(message(
+                                    "_rpc_reportConnectedApplicationList:",
+                                    argument,
+                                ))
+                                .await?;
+                        }
+                        ("_rpc_forwardGetListing:", Some("PID:1")) => {
+                            transport
+                                .send_plist(listing("PID:1", &[(1, "Start", "WIRTypeWebPage")]))
+                                .await?;
+                            // Pushed by the device without being asked
+                            transport
+                                .send_plist(listing(
+                                    "PID:1",
+                                    &[
+                                        (1, "Example", "WIRTypeWebPage"),
+                                        (2, "New tab", "WIRTypeWebPage"),
+                                    ],
+                                ))
+                                .await?;
+                            transport
+                                .send_plist(message(
+                                    "_rpc_applicationConnected:",
+                                    app("PID:2", "Widget"),
+                                ))
+                                .await?;
+                        }
+                        ("_rpc_forwardGetListing:", Some("PID:2")) => {
+                            transport
+                                .send_plist(listing(
+                                    "PID:2",
+                                    &[(1, "JSContext", "WIRTypeJavaScript")],
+                                ))
+                                .await?;
+                            let mut argument = Dictionary::new();
+                            argument.insert("WIRApplicationIdentifierKey".into(), "PID:1".into());
+                            transport
+                                .send_plist(message("_rpc_applicationDisconnected:", argument))
+                                .await?;
+                        }
+                        _ => {}
+                    }
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn page_changes_are_reported() {
+        let provider = MockProvider::new("test")
+            .unwrap()
+            .with_service(WebInspectorClient::service_name(), ChangingResponder);
+        let client = WebInspectorClient::connect(&provider).await.unwrap();
+        let mut watcher = watch_pages(client).await.unwrap();
+        assert_eq!(watcher.pages().count(), 1);
+
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            events.push(match watcher.next().await.unwrap() {
+                PageEvent::Appeared(p) => ("appeared", p),
+                PageEvent::Changed(p) => ("changed", p),
+                PageEvent::Disappeared(p) => ("disappeared", p),
+            });
+        }
+        let summary: Vec<_> = events
+            .iter()
+            .map(|(e, p)| (*e, p.app.id.as_str(), p.id, p.title.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("changed", "PID:1", 1, "Example"),
+                ("appeared", "PID:1", 2, "New tab"),
+                ("appeared", "PID:2", 1, "JSContext"),
+                ("disappeared", "PID:1", 1, "Example"),
+                ("disappeared", "PID:1", 2, "New tab"),
+            ]
+        );
+        assert_eq!(events[2].1.page_type, PageType::JavaScript);
+        assert_eq!(events[2].1.app.name.as_deref(), Some("Widget"));
+        assert_eq!(watcher.pages().count(), 1);
+    }
+}
@@ -225,6 +225,9 @@ impl XPCObject {
     }
 
     pub fn decode(buf: &[u8]) -> Result<Self, XPCError> {
+        if buf.len() < 8 {
+            Err("XPCObject is too short")?
+        }
         let magic = u32::from_le_bytes(buf[0..4].try_into()?);
         if magic != 0x42133742 {
             Err("Invalid magic for XPCObject")?
@@ -290,6 +293,9 @@ impl XPCObject {
                 let l = u32::from_le_bytes(buf_32) as usize;
                 let padding = Self::calculate_padding(l);
 
+                if l as u64 > (cursor.get_ref().len() as u64).saturating_sub(cursor.position()) {
+                    Err("XPCObject length is past the end of the buffer")?
+                }
                 let mut key_buf = vec![0; l];
                 cursor.read_exact(&mut key_buf)?;
                 let key = CString::from_vec_with_nul(key_buf)?.to_str()?.to_string();
@@ -306,6 +312,9 @@ impl XPCObject {
                 let l = u32::from_le_bytes(buf_32) as usize;
                 let padding = Self::calculate_padding(l);
 
+                if l as u64 > (cursor.get_ref().len() as u64).saturating_sub(cursor.position()) {
+                    Err("XPCObject length is past the end of the buffer")?
+                }
                 let mut data = vec![0; l];
                 cursor.read_exact(&mut data)?;
                 BufRead::consume(&mut cursor, padding);
@@ -7,6 +7,7 @@ use crate::{
         self,
         h2::{SettingsFrame, WindowUpdateFrame},
     },
+    lockdownd::needs_developer_image,
     IdeviceError, ReadWrite,
 };
 use error::XPCError;
@@ -131,6 +132,15 @@ impl<R: ReadWrite> XPCDevice<R> {
         })
     }
 
+    /// Looks up a service, telling a missing developer disk image apart from a missing service
+    pub fn service(&self, name: &str) -> Result<&XPCService, IdeviceError> {
+        match self.services.get(name) {
+            Some(s) => Ok(s),
+            None if needs_developer_image(name) => Err(IdeviceError::DeveloperImageNotMounted),
+            None => Err(IdeviceError::NotFound),
+        }
+    }
+
     pub fn into_inner(self) -> R {
         self.connection.inner.stream
     }
@@ -0,0 +1,164 @@
+// Jackson Coxson
+// idevice Rust implementation of AFC file operations
+
+use clap::{Arg, ArgMatches, Command};
+use std::path::Path;
+
+use clap::value_parser;
+use idevice::{
+    afc::{AfcClient, AfcFileInfo, AfcPool},
+    progress::ProgressObserver,
+    IdeviceService, Pretty,
+};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("afc_tool")
+        .about("Interact with iOS device filesystem")
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .short('l')
+                .value_name("PATH")
+                .help("List directory contents"),
+        )
+        .arg(
+            Arg::new("info")
+                .long("info")
+                .short('i')
+                .value_name("PATH")
+                .help("Get file/directory info"),
+        )
+        .arg(
+            Arg::new("mkdir")
+                .long("mkdir")
+                .value_name("PATH")
+                .help("Create directory"),
+        )
+        .arg(
+            Arg::new("remove")
+                .long("remove")
+                .short('r')
+                .value_name("PATH")
+                .help("Remove file or directory"),
+        )
+        .arg(
+            Arg::new("pull")
+                .long("pull")
+                .num_args(2)
+                .value_names(["REMOTE", "LOCAL"])
+                .help("Download a directory"),
+        )
+        .arg(
+            Arg::new("parallelism")
+                .long("parallelism")
+                .short('j')
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value("1")
+                .help("How many AFC connections to pull over at once"),
+        )
+        .arg(
+            Arg::new("device-info")
+                .long("device-info")
+                .short('d')
+                .help("Get device info")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("afc_tool - interact with iOS device filesystem. Reimplementation of libimobiledevice's functionality.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "afc-tool-jkcoxson").await?;
+
+    let mut afc_client = match AfcClient::connect(&*provider).await {
+        Ok(client) => client,
+        Err(e) => return Err(e).context("Failed to connect to AFC service"),
+    };
+
+    if matches.get_flag("device-info") {
+        match afc_client.get_device_info().await {
+            Ok(info) => {
+                println!("Device Info:");
+                for (key, value) in info {
+                    println!("  {}: {}", key, value);
+                }
+            }
+            Err(e) => return Err(e).context("Failed to get device info"),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("list") {
+        match afc_client.read_directory(path).await {
+            Ok(entries) => {
+                println!("Directory contents of '{}':", path);
+                for entry in entries {
+                    println!("  {}", entry);
+                }
+            }
+            Err(e) => return Err(e).context("Failed to list directory"),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("info") {
+        match afc_client.get_file_info(path).await {
+            Ok(info) => {
+                println!("Info for '{}':", path);
+                println!("{}", AfcFileInfo::from(&info).pretty());
+            }
+            Err(e) => return Err(e).context("Failed to get file info"),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("mkdir") {
+        match afc_client.make_directory(path).await {
+            Ok(_) => {
+                println!("Directory '{}' created successfully", path);
+            }
+            Err(e) => return Err(e).context("Failed to create directory"),
+        }
+    }
+
+    if let Some(path) = matches.get_one::<String>("remove") {
+        match afc_client.remove_path(path).await {
+            Ok(_) => {
+                println!("Path '{}' removed successfully", path);
+            }
+            Err(e) => return Err(e).context("Failed to remove path"),
+        }
+    }
+
+    if let Some(mut paths) = matches.get_many::<String>("pull") {
+        let (remote, local) = (paths.next().unwrap(), paths.next().unwrap());
+        let parallelism = *matches.get_one::<usize>("parallelism").unwrap();
+        let (observer, _) = ProgressObserver::new();
+        let res = if parallelism > 1 {
+            let mut pool = AfcPool::new(&*provider, parallelism)
+                .await
+                .context("Failed to connect to AFC service")?;
+            pool.pull_tree(remote, Path::new(local), observer).await
+        } else {
+            afc_client
+                .pull_tree(remote, Path::new(local), observer)
+                .await
+        };
+        match res {
+            Ok(_) => println!("Pulled '{}' into '{}'", remote, local),
+            Err(e) => return Err(e).context("Failed to pull directory"),
+        }
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,39 @@
+// Jackson Coxson
+// Just lists apps for now
+
+use clap::{ArgMatches, Command};
+use idevice::{installation_proxy::InstallationProxyClient, IdeviceService};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("instproxy").about("Manage installed apps")
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("instproxy - query and manage apps installed on a device. Reimplementation of libimobiledevice's binary.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "ideviceinfo-jkcoxson").await?;
+
+    let mut instproxy_client = InstallationProxyClient::connect(&*provider)
+        .await
+        .context("Unable to connect to instproxy")?;
+    let apps = instproxy_client
+        .get_apps(Some("User".to_string()), None)
+        .await?;
+    for app in apps.keys() {
+        println!("{app}");
+    }
+    Ok(())
+}
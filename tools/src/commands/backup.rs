@@ -0,0 +1,60 @@
+use clap::{Arg, ArgMatches, Command};
+use idevice::{mobile_backup::{MobileBackupClient, BackupType}, IdeviceService};
+use std::path::PathBuf;
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("mobile_backup_tool")
+        .about("iOS device backup and restore tool")
+        .arg(Arg::new("backup").long("backup").conflicts_with("restore"))
+        .arg(Arg::new("restore").long("restore").conflicts_with("backup"))
+        .arg(Arg::new("full").long("full").help("Perform full backup"))
+        .arg(Arg::new("encryption-key").long("encryption-key").value_name("KEY"))
+        .arg(Arg::new("target").required(true).value_name("PATH"))
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("mobile_backup_tool - back up and restore the device");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let provider = common::get_provider(
+        matches.get_one::<String>("udid"),
+        matches.get_one::<String>("host"),
+        matches.get_one::<String>("pairing_file"),
+        "mobile-backup-tool"
+    ).await?;
+
+    let mut client = MobileBackupClient::connect(&*provider)
+        .await
+        .context("Failed to connect to the backup service")?;
+    let target = PathBuf::from(matches.get_one::<String>("target").unwrap());
+
+    if matches.get_flag("backup") {
+        let backup_type = if matches.get_flag("full") {
+            BackupType::Full
+        } else {
+            BackupType::Incremental
+        };
+
+        client.start_backup(
+            backup_type,
+            &target,
+            matches.get_one::<String>("encryption-key").map(|s| s.as_str())
+        ).await.context("Failed to start backup")?;
+        println!("Backup initiated successfully");
+    } else if matches.get_flag("restore") {
+        client.start_restore(
+            &target,
+            matches.get_one::<String>("encryption-key").map(|s| s.as_str())
+        ).await.context("Failed to start restore")?;
+        println!("Restore initiated successfully");
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,162 @@
+// Jackson Coxson
+
+use std::{
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{
+    debug_proxy::DebugProxyClient, tunneld::get_tunneld_devices, xpc::XPCDevice, IdeviceError,
+    ReadWrite,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    common,
+    error::{Context, ExitCode, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("debug_proxy")
+        .about("Run debugserver commands through the debug proxy")
+        .arg(
+            Arg::new("tunneld")
+                .long("tunneld")
+                .help("Use tunneld")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .value_name("PORT")
+                .help("Serve gdb-remote on a local port for LLDB instead of running a shell")
+                .value_parser(clap::value_parser!(u16)),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("debug_proxy - connect to the debug proxy and run commands");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    if let Some(port) = matches.get_one::<u16>("listen") {
+        return serve(matches, *port).await;
+    }
+
+    let mut dp = connect(matches).await?;
+    println!("Shell connected!");
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+
+        let mut buf = String::new();
+        std::io::stdin().read_line(&mut buf)?;
+
+        let buf = buf.trim();
+
+        if buf == "exit" {
+            break;
+        }
+
+        let res = dp
+            .send_command(buf.into())
+            .await
+            .context("Failed to send")?;
+        if let Some(res) = res {
+            println!("{res}");
+        }
+    }
+    Ok(())
+}
+
+/// Bridges LLDB connections on a local port to debugserver, one at a time
+async fn serve(matches: &ArgMatches, port: u16) -> Result<(), ToolError> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .context("Failed to listen")?;
+    println!("Listening for LLDB, connect with:");
+    println!("  (lldb) process connect connect://localhost:{port}");
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        println!("Debugger connected from {addr}");
+        // Each debug session needs its own debugserver
+        let dp = connect(matches).await?;
+        match dp.bridge(stream).await {
+            Ok(()) => println!("Debugger disconnected"),
+            Err(e) => eprintln!("Debug session failed: {e}"),
+        }
+    }
+}
+
+async fn connect(matches: &ArgMatches) -> Result<DebugProxyClient<Box<dyn ReadWrite>>, ToolError> {
+    let udid = matches.get_one::<String>("udid");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let host = matches.get_one::<String>("host");
+
+    // A missing developer disk image is reported as such
+    let no_service = |e: IdeviceError| match e {
+        IdeviceError::NotFound => ToolError::new(
+            ExitCode::ServiceUnavailable,
+            "Client did not contain debug proxy service",
+        ),
+        e => ToolError::from(e),
+    };
+
+    let dp: DebugProxyClient<Box<dyn ReadWrite>> = if matches.get_flag("tunneld") {
+        let socket = SocketAddr::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            idevice::tunneld::DEFAULT_PORT,
+        );
+        let mut devices = get_tunneld_devices(socket)
+            .await
+            .context("Failed to get tunneld devices")?;
+
+        let not_found = |message| ToolError::new(ExitCode::DeviceNotFound, message);
+        let (_udid, device) = match udid {
+            Some(u) => (
+                u.to_owned(),
+                devices
+                    .remove(u)
+                    .ok_or_else(|| not_found("Device not in tunneld"))?,
+            ),
+            None => devices
+                .into_iter()
+                .next()
+                .ok_or_else(|| not_found("No devices"))?,
+        };
+
+        // Make the connection to RemoteXPC
+        let client = XPCDevice::new(Box::new(
+            TcpStream::connect((device.tunnel_address.as_str(), device.tunnel_port))
+                .await
+                .context("Failed to connect to the tunnel")?,
+        ))
+        .await?;
+
+        // Get the debug proxy
+        let service = client
+            .service(idevice::debug_proxy::SERVICE_NAME)
+            .map_err(no_service)?;
+
+        let stream = TcpStream::connect(SocketAddr::new(
+            IpAddr::from_str(&device.tunnel_address).unwrap(),
+            service.port,
+        ))
+        .await
+        .context("Failed to connect")?;
+
+        DebugProxyClient::new(Box::new(stream))
+    } else {
+        let provider =
+            common::get_provider(udid, host, pairing_file, "debug-proxy-jkcoxson").await?;
+        // Lockdown or a software tunnel, whichever the device's version needs
+        idevice::debug_proxy::connect(&*provider)
+            .await
+            .map_err(no_service)?
+    };
+    Ok(dp)
+}
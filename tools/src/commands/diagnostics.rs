@@ -0,0 +1,261 @@
+// Jackson Coxson
+// idevice Rust implementation of Diagnostics functionality
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{
+    connectivity::connectivity_report,
+    diagnostics::{DiagnosticsAction, DiagnosticsClient, DiagnosticsDomain},
+    lockdownd::LockdowndClient,
+    usbmuxd::UsbmuxdAddr,
+    IdeviceService, Pretty,
+};
+use std::fs::File;
+use std::io::Write;
+
+use crate::{
+    common,
+    error::{Context, ExitCode, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("diagnostics_tool")
+        .about("Retrieve diagnostic information from iOS devices")
+        .arg(
+            Arg::new("all")
+                .long("all")
+                .short('a')
+                .help("Request all diagnostics")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wifi")
+                .long("wifi")
+                .short('w')
+                .help("Request WiFi diagnostics")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("battery")
+                .long("battery")
+                .short('b')
+                .help("Request battery (GasGauge) diagnostics")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("storage")
+                .long("storage")
+                .short('s')
+                .help("Request storage (NAND) diagnostics")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ioreg")
+                .long("ioreg")
+                .short('i')
+                .help("Request I/O Registry")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("network")
+                .long("network")
+                .short('n')
+                .help("Request network interfaces")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("restart")
+                .long("restart")
+                .short('r')
+                .help("Restart the device")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("shutdown")
+                .long("shutdown")
+                .help("Shutdown the device")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sleep")
+                .long("sleep")
+                .help("Sleep the device")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wifi_sync")
+                .long("wifi-sync")
+                .value_name("STATE")
+                .value_parser(["status", "on", "off"])
+                .help("Show or change whether the device can be reached over Wi-Fi"),
+        )
+        .arg(
+            Arg::new("connectivity")
+                .long("connectivity")
+                .help("Explain how the device can be reached and what's in the way")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Output file path (default: output to console)"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("diagnostics_tool - retrieve diagnostic information from iOS devices. Reimplementation of libimobiledevice's functionality.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let output_path = matches.get_one::<String>("output");
+
+    if matches.get_flag("connectivity") {
+        return connectivity(udid).await;
+    }
+
+    let provider = common::get_provider(udid, host, pairing_file, "diagnostics-tool-jkcoxson").await?;
+
+    if let Some(state) = matches.get_one::<String>("wifi_sync") {
+        let mut lockdown = LockdowndClient::connect(&*provider)
+            .await
+            .context("Unable to connect to lockdown")?;
+        lockdown
+            .start_session(&provider.get_pairing_file().await?)
+            .await
+            .context("Unable to start a session")?;
+        match state.as_str() {
+            "on" => lockdown.set_wifi_sync_enabled(true).await?,
+            "off" => lockdown.set_wifi_sync_enabled(false).await?,
+            _ => {}
+        }
+        let enabled = lockdown.wifi_sync_enabled().await?;
+        println!("Wi-Fi sync: {}", if enabled { "on" } else { "off" });
+        return Ok(());
+    }
+
+    let mut diagnostics_client = match DiagnosticsClient::connect(&*provider).await {
+        Ok(client) => client,
+        Err(e) => return Err(e).context("Failed to connect to Diagnostics service"),
+    };
+
+    // Determine the action to perform
+    let action = if matches.get_flag("all") {
+        DiagnosticsAction::All
+    } else if matches.get_flag("wifi") {
+        DiagnosticsAction::Domain(DiagnosticsDomain::WiFi)
+    } else if matches.get_flag("battery") {
+        DiagnosticsAction::Domain(DiagnosticsDomain::GasGauge)
+    } else if matches.get_flag("storage") {
+        DiagnosticsAction::Domain(DiagnosticsDomain::NAND)
+    } else if matches.get_flag("ioreg") {
+        DiagnosticsAction::IORegistry
+    } else if matches.get_flag("network") {
+        DiagnosticsAction::NetworkInterfaces
+    } else if matches.get_flag("restart") {
+        DiagnosticsAction::Restart
+    } else if matches.get_flag("shutdown") {
+        DiagnosticsAction::Shutdown
+    } else if matches.get_flag("sleep") {
+        DiagnosticsAction::Sleep
+    } else {
+        // Default to All if no action specified
+        DiagnosticsAction::All
+    };
+
+    // Perform the action
+    match action {
+        DiagnosticsAction::Restart => {
+            println!("Restarting device...");
+            match diagnostics_client.restart().await {
+                Ok(_) => println!("Device restart initiated"),
+                Err(e) => return Err(e).context("Failed to restart device"),
+            }
+        }
+        DiagnosticsAction::Shutdown => {
+            println!("Shutting down device...");
+            match diagnostics_client.shutdown().await {
+                Ok(_) => println!("Device shutdown initiated"),
+                Err(e) => return Err(e).context("Failed to shutdown device"),
+            }
+        }
+        DiagnosticsAction::Sleep => {
+            println!("Putting device to sleep...");
+            match diagnostics_client.sleep().await {
+                Ok(_) => println!("Device sleep initiated"),
+                Err(e) => return Err(e).context("Failed to put device to sleep"),
+            }
+        }
+        _ => {
+            // Request diagnostics
+            println!("Requesting diagnostics...");
+            match diagnostics_client.request_diagnostics(action).await {
+                Ok(data) => {
+                    // Convert to pretty XML
+                    let xml = plist::to_format_xml(&data).unwrap_or_else(|_| "Failed to format XML".to_string());
+                    
+                    // Output the data
+                    if let Some(path) = output_path {
+                        // Save to file
+                        match File::create(path) {
+                            Ok(mut file) => {
+                                match file.write_all(xml.as_bytes()) {
+                                    Ok(_) => println!("Diagnostics saved to: {}", path),
+                                    Err(e) => return Err(e).context("Failed to write data to file"),
+                                }
+                            }
+                            Err(e) => return Err(e).context("Failed to create output file"),
+                        }
+                    } else {
+                        // Print to console
+                        println!("{}", xml);
+                    }
+                }
+                Err(e) => return Err(e).context("Failed to request diagnostics"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the connectivity report for the given device, or the first one usbmuxd lists.
+/// The report goes through usbmuxd, so it works for devices no provider could reach.
+async fn connectivity(udid: Option<&String>) -> Result<(), ToolError> {
+    let udid = match udid {
+        Some(u) => u.clone(),
+        None => {
+            let devs = common::connect_usbmuxd()
+                .await?
+                .get_devices()
+                .await
+                .context("Unable to get devices from usbmuxd")?;
+            match devs.into_iter().next() {
+                Some(d) => d.udid,
+                None => {
+                    return Err(ToolError::new(
+                        ExitCode::DeviceNotFound,
+                        "No devices connected!",
+                    ))
+                }
+            }
+        }
+    };
+    let addr = UsbmuxdAddr::from_env_var()
+        .map_err(|e| ToolError::new(ExitCode::Usage, format!("Bad USBMUXD_SOCKET_ADDRESS: {e}")))?;
+    let report = connectivity_report(&addr, &udid)
+        .await
+        .context("Unable to check connectivity")?;
+    println!("{}", report.pretty());
+    if !report.reachable() {
+        return Err(ToolError::new(
+            ExitCode::DeviceNotFound,
+            format!("{udid} can't be reached"),
+        ));
+    }
+    Ok(())
+}
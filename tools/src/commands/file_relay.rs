@@ -0,0 +1,118 @@
+// Jackson Coxson
+// idevice Rust implementation of File Relay functionality
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{file_relay::{FileRelayClient, FileRelaySource}, IdeviceService};
+use std::fs::File;
+use std::io::Write;
+
+use crate::{
+    common,
+    error::{Context, ExitCode, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("file_relay_tool")
+        .about("Retrieve files and logs from iOS devices")
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .short('s')
+                .value_name("SOURCE")
+                .help("Source to request (can be specified multiple times)")
+                .action(clap::ArgAction::Append)
+                .value_parser([
+                    "AppleSupport", "Network", "VPN", "Wifi", "UserDatabases",
+                    "CrashReporter", "Tmp", "SystemConfiguration", "Keyboard",
+                    "Logs", "Lockdown", "MobileInstallation", "CrashReporter-Clearable",
+                    "Diagnostics", "All"
+                ]),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Output file path (default: relay.zip)")
+                .default_value("relay.zip"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("file_relay_tool - retrieve files and logs from iOS devices. Reimplementation of libimobiledevice's functionality.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let output_path = matches.get_one::<String>("output").unwrap();
+
+    let provider = common::get_provider(udid, host, pairing_file, "file-relay-tool-jkcoxson").await?;
+
+    let mut file_relay_client = match FileRelayClient::connect(&*provider).await {
+        Ok(client) => client,
+        Err(e) => return Err(e).context("Failed to connect to File Relay service"),
+    };
+
+    // Parse sources
+    let sources = match matches.get_many::<String>("source") {
+        Some(sources) => {
+            let mut result = Vec::new();
+            for source in sources {
+                match source.as_str() {
+                    "AppleSupport" => result.push(FileRelaySource::AppleSupport),
+                    "Network" => result.push(FileRelaySource::Network),
+                    "VPN" => result.push(FileRelaySource::VPN),
+                    "Wifi" => result.push(FileRelaySource::Wifi),
+                    "UserDatabases" => result.push(FileRelaySource::UserDatabases),
+                    "CrashReporter" => result.push(FileRelaySource::CrashReporter),
+                    "Tmp" => result.push(FileRelaySource::Tmp),
+                    "SystemConfiguration" => result.push(FileRelaySource::SystemConfiguration),
+                    "Keyboard" => result.push(FileRelaySource::Keyboard),
+                    "Logs" => result.push(FileRelaySource::Logs),
+                    "Lockdown" => result.push(FileRelaySource::Lockdown),
+                    "MobileInstallation" => result.push(FileRelaySource::MobileInstallation),
+                    "CrashReporter-Clearable" => result.push(FileRelaySource::CrashReporterClearable),
+                    "Diagnostics" => result.push(FileRelaySource::Diagnostics),
+                    "All" => result.push(FileRelaySource::All),
+                    _ => {
+                        return Err(ToolError::new(
+                            ExitCode::Usage,
+                            format!("Unknown source: {}", source),
+                        ));
+                    }
+                }
+            }
+            result
+        }
+        None => {
+            // Default to All if no sources specified
+            vec![FileRelaySource::All]
+        }
+    };
+
+    println!("Requesting files from sources: {:?}", sources.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+    match file_relay_client.request_files(&sources).await {
+        Ok(data) => {
+            println!("Received {} bytes of data", data.len());
+            
+            // Save the data to a file
+            match File::create(output_path) {
+                Ok(mut file) => {
+                    match file.write_all(&data) {
+                        Ok(_) => {
+                            println!("Data saved to: {}", output_path);
+                        }
+                        Err(e) => return Err(e).context("Failed to write data to file"),
+                    }
+                }
+                Err(e) => return Err(e).context("Failed to create output file"),
+            }
+        }
+        Err(e) => return Err(e).context("Failed to request files"),
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,38 @@
+// Jackson Coxson
+// Heartbeat client
+
+use clap::{ArgMatches, Command};
+use idevice::{heartbeat::HeartbeatClient, IdeviceService};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("heartbeat_client").about("Keep a heartbeat with the device")
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("heartbeat_client - heartbeat a device");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider =
+        common::get_provider(udid, host, pairing_file, "heartbeat_client-jkcoxson").await?;
+    let mut heartbeat_client = HeartbeatClient::connect(&*provider)
+        .await
+        .context("Unable to connect to heartbeat")?;
+
+    let mut interval = 15;
+    loop {
+        interval = heartbeat_client.get_marco(interval).await?;
+        heartbeat_client.send_polo().await?;
+    }
+}
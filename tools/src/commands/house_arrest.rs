@@ -0,0 +1,129 @@
+// Jackson Coxson
+// idevice Rust implementation of House Arrest functionality
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{house_arrest::HouseArrestClient, IdeviceService};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("house_arrest_tool")
+        .about("Access app containers on iOS devices")
+        .arg(
+            Arg::new("list")
+                .long("list")
+                .short('l')
+                .help("List installed applications")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("info")
+                .long("info")
+                .short('i')
+                .value_name("BUNDLE_ID")
+                .help("Get application info"),
+        )
+        .arg(
+            Arg::new("documents")
+                .long("documents")
+                .short('d')
+                .value_name("BUNDLE_ID")
+                .help("List files in app's Documents directory"),
+        )
+        .arg(
+            Arg::new("container")
+                .long("container")
+                .short('c')
+                .value_name("BUNDLE_ID")
+                .help("List files in app's Container directory"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("house_arrest_tool - access app containers on iOS devices. Reimplementation of libimobiledevice's functionality.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "house-arrest-tool-jkcoxson").await?;
+
+    let mut house_arrest_client = match HouseArrestClient::connect(&*provider).await {
+        Ok(client) => client,
+        Err(e) => return Err(e).context("Failed to connect to House Arrest service"),
+    };
+
+    if matches.get_flag("list") {
+        match house_arrest_client.list_installed_applications().await {
+            Ok(apps) => {
+                println!("Installed applications:");
+                for app in apps {
+                    println!("  {}", app);
+                }
+            }
+            Err(e) => return Err(e).context("Failed to list applications"),
+        }
+    }
+
+    if let Some(bundle_id) = matches.get_one::<String>("info") {
+        match house_arrest_client.get_application_info(bundle_id).await {
+            Ok(info) => {
+                println!("Application info for '{}':", bundle_id);
+                for (key, value) in info {
+                    println!("  {}: {:?}", key, value);
+                }
+            }
+            Err(e) => return Err(e).context("Failed to get application info"),
+        }
+    }
+
+    if let Some(bundle_id) = matches.get_one::<String>("documents") {
+        // Vending hands the connection over to AFC, so it gets its own
+        let vend_client = HouseArrestClient::connect(&*provider)
+            .await
+            .context("Failed to connect to House Arrest service")?;
+        match vend_client.documents(bundle_id).await {
+            Ok(mut afc_client) => {
+                match afc_client.read_directory("/").await {
+                    Ok(entries) => {
+                        println!("Files in Documents directory of '{}':", bundle_id);
+                        for entry in entries {
+                            println!("  {}", entry);
+                        }
+                    }
+                    Err(e) => return Err(e).context("Failed to list files"),
+                }
+            }
+            Err(e) => return Err(e).context("Failed to access Documents directory"),
+        }
+    }
+
+    if let Some(bundle_id) = matches.get_one::<String>("container") {
+        // Vending hands the connection over to AFC, so it gets its own
+        let vend_client = HouseArrestClient::connect(&*provider)
+            .await
+            .context("Failed to connect to House Arrest service")?;
+        match vend_client.container(bundle_id).await {
+            Ok(mut afc_client) => {
+                match afc_client.read_directory("/").await {
+                    Ok(entries) => {
+                        println!("Files in Container directory of '{}':", bundle_id);
+                        for entry in entries {
+                            println!("  {}", entry);
+                        }
+                    }
+                    Err(e) => return Err(e).context("Failed to list files"),
+                }
+            }
+            Err(e) => return Err(e).context("Failed to access Container directory"),
+        }
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,70 @@
+// Jackson Coxson
+// idevice Rust implementation of libimobiledevice's ideviceinfo
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{
+    lockdownd::{self, LockdowndClient},
+    IdeviceService, Pretty,
+};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("ideviceinfo")
+        .about("Get information from the device")
+        .arg(
+            Arg::new("domain")
+                .long("domain")
+                .short('q')
+                .value_name("DOMAIN")
+                .help("Show the values in a lockdown domain instead of the default one"),
+        )
+        .arg(
+            Arg::new("list_domains")
+                .long("list-domains")
+                .help("List the known lockdown domains")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("ideviceinfo - get information from the idevice. Reimplementation of libimobiledevice's binary.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    if matches.get_flag("list_domains") {
+        for domain in lockdownd::list_domains() {
+            println!("{domain}");
+        }
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "ideviceinfo-jkcoxson").await?;
+
+    let mut lockdown_client = LockdowndClient::connect(&*provider)
+        .await
+        .context("Unable to connect to lockdown")?;
+
+    println!("{:?}", lockdown_client.get_value("ProductVersion").await);
+
+    let p = provider.get_pairing_file().await?;
+    lockdown_client
+        .start_session(&p)
+        .await
+        .context("Unable to start a session")?;
+    println!("{:?}", lockdown_client.idevice.get_type().await?);
+    match matches.get_one::<String>("domain") {
+        Some(domain) => println!("{:#?}", lockdown_client.get_domain(domain).await?),
+        None => println!("{}", lockdown_client.get_all_values().await?.pretty()),
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+// Jackson Coxson
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{
+    core_device_proxy::CoreDeviceProxy, tunneld::get_tunneld_devices, xpc::XPCDevice, IdeviceError,
+    IdeviceService,
+};
+use tokio::net::TcpStream;
+
+use crate::{
+    common,
+    error::{Context, ExitCode, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("process_control")
+        .mut_arg("udid", |a| a.index(2))
+        .about("Query process control")
+        .arg(
+            Arg::new("tunneld")
+                .long("tunneld")
+                .help("Use tunneld for connection")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bundle_id")
+                .value_name("Bundle ID")
+                .help("Bundle ID of the app to launch")
+                .index(1),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("process_control - launch and manage processes on the device");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let host = matches.get_one::<String>("host");
+    let bundle_id = matches
+        .get_one::<String>("bundle_id")
+        .ok_or_else(|| ToolError::new(ExitCode::Usage, "No bundle ID specified"))?;
+
+    // A missing developer disk image is reported as such
+    let no_dvt = |e: IdeviceError| match e {
+        IdeviceError::NotFound => ToolError::new(
+            ExitCode::ServiceUnavailable,
+            "Client did not contain DVT service",
+        ),
+        e => ToolError::from(e),
+    };
+
+    if matches.get_flag("tunneld") {
+        let socket = SocketAddr::new(
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            idevice::tunneld::DEFAULT_PORT,
+        );
+        let mut devices = get_tunneld_devices(socket)
+            .await
+            .context("Failed to get tunneld devices")?;
+
+        let not_found = |message| ToolError::new(ExitCode::DeviceNotFound, message);
+        let (_udid, device) = match udid {
+            Some(u) => (
+                u.to_owned(),
+                devices
+                    .remove(u)
+                    .ok_or_else(|| not_found("Device not in tunneld"))?,
+            ),
+            None => devices
+                .into_iter()
+                .next()
+                .ok_or_else(|| not_found("No devices"))?,
+        };
+
+        // Make the connection to RemoteXPC
+        let client = XPCDevice::new(Box::new(
+            TcpStream::connect((device.tunnel_address.as_str(), device.tunnel_port))
+                .await
+                .context("Failed to connect to the tunnel")?,
+        ))
+        .await?;
+
+        // Get the debug proxy
+        let service = client.service(idevice::dvt::SERVICE_NAME).map_err(no_dvt)?;
+
+        let stream = TcpStream::connect(SocketAddr::new(
+            IpAddr::from_str(&device.tunnel_address).unwrap(),
+            service.port,
+        ))
+        .await
+        .context("Failed to connect")?;
+
+        let mut rs_client = idevice::dvt::remote_server::RemoteServerClient::new(Box::new(stream));
+        rs_client.read_message(0).await.context("no read??")?;
+        let mut pc_client =
+            idevice::dvt::process_control::ProcessControlClient::new(&mut rs_client).await?;
+
+        let pid = pc_client
+            .launch_app(bundle_id, None, None, true, false)
+            .await
+            .context("no launch??")?;
+        pc_client
+            .disable_memory_limit(pid)
+            .await
+            .context("no disable??")?;
+        println!("PID: {pid}");
+    } else {
+        let provider =
+            common::get_provider(udid, host, pairing_file, "process_control-jkcoxson").await?;
+
+        let proxy = CoreDeviceProxy::connect(&*provider)
+            .await
+            .context("no core proxy")?;
+        let rsd_port = proxy.handshake.server_rsd_port;
+
+        let mut adapter = proxy
+            .create_software_tunnel()
+            .context("no software tunnel")?;
+        adapter.connect(rsd_port).await.context("no RSD connect")?;
+
+        // Make the connection to RemoteXPC
+        let client = XPCDevice::new(Box::new(adapter)).await?;
+
+        // Get the debug proxy
+        let service = client
+            .service(idevice::dvt::SERVICE_NAME)
+            .map_err(no_dvt)?
+            .to_owned();
+
+        let mut adapter = client.into_inner();
+        adapter.connect(service.port).await?;
+
+        let mut rs_client = idevice::dvt::remote_server::RemoteServerClient::new(Box::new(adapter));
+        rs_client.read_message(0).await.context("no read??")?;
+        let mut pc_client =
+            idevice::dvt::process_control::ProcessControlClient::new(&mut rs_client).await?;
+
+        let pid = pc_client
+            .launch_app(bundle_id, None, None, true, false)
+            .await
+            .context("no launch??")?;
+        pc_client
+            .disable_memory_limit(pid)
+            .await
+            .context("no disable??")?;
+        println!("PID: {pid}");
+
+        // let mut adapter = rs_client.into_inner();
+        // adapter.close().await.expect("no close??");
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+// Jackson Coxson
+// Gets the devices from the muxer
+
+use clap::{ArgMatches, Command};
+
+use crate::{common, error::ToolError};
+
+pub fn command() -> Command {
+    Command::new("idevice_id").about("List the devices connected to usbmuxd")
+}
+
+pub async fn run(_matches: &ArgMatches) -> Result<(), ToolError> {
+    let mut muxer = common::connect_usbmuxd().await?;
+    for device in muxer.get_devices().await? {
+        println!("{device}");
+    }
+    Ok(())
+}
@@ -0,0 +1,21 @@
+// Jackson Coxson
+// Each tool's arguments and body, shared by its own binary and the idevice binary
+
+pub mod afc;
+pub mod apps;
+pub mod backup;
+pub mod debug_proxy;
+pub mod diagnostics;
+pub mod file_relay;
+pub mod heartbeat;
+pub mod house_arrest;
+pub mod info;
+pub mod launch;
+pub mod list;
+pub mod mount;
+pub mod notify;
+pub mod profiles;
+pub mod remotexpc;
+pub mod screenshot;
+pub mod syslog;
+pub mod tunnel;
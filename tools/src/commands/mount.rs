@@ -0,0 +1,188 @@
+// Jackson Coxson
+// Just lists apps for now
+
+use std::path::PathBuf;
+
+use clap::{arg, value_parser, ArgMatches, Command};
+use idevice::{
+    capabilities::{Capabilities, DeveloperImageKind},
+    lockdownd::LockdowndClient,
+    mounter::ImageMounter,
+    pretty_print_plist, IdeviceError, IdeviceService,
+};
+
+use crate::{
+    common,
+    error::{Context, ExitCode, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("mounter")
+        .about("Mount developer disk images")
+        .subcommand(Command::new("list").about("Lists the images mounted on the device"))
+        .subcommand(Command::new("unmount").about("Unmounts the developer disk image"))
+        .subcommand(
+            Command::new("mount")
+                .about("Mounts the developer disk image")
+                .arg(
+                    arg!(-i --image <FILE> "the developer disk image to mount")
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
+                )
+                .arg(
+                    arg!(-b --manifest <FILE> "the build manifest (iOS 17+)")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-t --trustcache <FILE> "the trust cache (iOS 17+)")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-s --signature <FILE> "the image signature (iOS < 17.0")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("mounter - query and manage images mounted on a device. Reimplementation of libimobiledevice's binary.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "ideviceinfo-jkcoxson").await?;
+
+    let mut lockdown_client = LockdowndClient::connect(&*provider)
+        .await
+        .context("Unable to connect to lockdown")?;
+
+    let capabilities = match Capabilities::from_lockdown(&mut lockdown_client).await {
+        Ok(c) => c,
+        Err(_) => {
+            lockdown_client
+                .start_session(&provider.get_pairing_file().await?)
+                .await
+                .context("Unable to start session")?;
+            Capabilities::from_lockdown(&mut lockdown_client)
+                .await
+                .context("Unable to get ProductVersion")?
+        }
+    };
+
+    let mut mounter_client = ImageMounter::connect(&*provider)
+        .await
+        .context("Unable to connect to image mounter")?;
+
+    if matches.subcommand_matches("list").is_some() {
+        let images = mounter_client
+            .copy_devices()
+            .await
+            .context("Unable to get images")?;
+        for i in images {
+            println!("{}", pretty_print_plist(&i));
+        }
+    } else if matches.subcommand_matches("unmount").is_some() {
+        mounter_client
+            .unmount_developer()
+            .await
+            .context("Failed to unmount")?;
+    } else if let Some(matches) = matches.subcommand_matches("mount") {
+        let image: &PathBuf = match matches.get_one("image") {
+            Some(i) => i,
+            None => {
+                return Err(ToolError::new(
+                    ExitCode::Usage,
+                    "No image was passed! Pass -h for help",
+                ));
+            }
+        };
+        let image = tokio::fs::read(image)
+            .await
+            .context("Unable to read image")?;
+        if capabilities.developer_image() == DeveloperImageKind::Developer {
+            let signature: &PathBuf = match matches.get_one("signature") {
+                Some(s) => s,
+                None => {
+                    return Err(ToolError::new(
+                        ExitCode::Usage,
+                        "No signature was passed! Pass -h for help",
+                    ));
+                }
+            };
+            let signature = tokio::fs::read(signature)
+                .await
+                .context("Unable to read signature")?;
+
+            mounter_client
+                .mount_developer(&image, signature)
+                .await
+                .context("Unable to mount")?;
+        } else {
+            let manifest: &PathBuf = match matches.get_one("manifest") {
+                Some(s) => s,
+                None => {
+                    return Err(ToolError::new(
+                        ExitCode::Usage,
+                        "No build manifest was passed! Pass -h for help",
+                    ));
+                }
+            };
+            let build_manifest = &tokio::fs::read(manifest)
+                .await
+                .context("Unable to read signature")?;
+
+            let trust_cache: &PathBuf = match matches.get_one("trustcache") {
+                Some(s) => s,
+                None => {
+                    return Err(ToolError::new(
+                        ExitCode::Usage,
+                        "No trust cache was passed! Pass -h for help",
+                    ));
+                }
+            };
+            let trust_cache = tokio::fs::read(trust_cache)
+                .await
+                .context("Unable to read signature")?;
+
+            let unique_chip_id = match lockdown_client.get_value("UniqueChipID").await {
+                Ok(u) => u,
+                Err(_) => {
+                    lockdown_client
+                        .start_session(&provider.get_pairing_file().await?)
+                        .await
+                        .context("Unable to start session")?;
+                    lockdown_client
+                        .get_value("UniqueChipID")
+                        .await
+                        .context("Unable to get UniqueChipID")?
+                }
+            }
+            .as_unsigned_integer()
+            .ok_or(IdeviceError::UnexpectedResponse)
+            .context("Unexpected value for chip IP")?;
+
+            mounter_client
+                .mount_personalized(
+                    &*provider,
+                    image,
+                    trust_cache,
+                    build_manifest,
+                    None,
+                    unique_chip_id,
+                )
+                .await
+                .context("Unable to mount")?;
+        }
+    } else {
+        return Err(ToolError::new(
+            ExitCode::Usage,
+            "Invalid usage, pass -h for help",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+// Jackson Coxson
+// idevice Rust implementation of Notification Proxy functionality
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{notification_proxy::{NotificationProxyClient, NotificationType}, IdeviceService};
+use tokio::time::Duration;
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("notification_proxy_tool")
+        .about("Send and receive notifications to/from iOS devices")
+        .arg(
+            Arg::new("post")
+                .long("post")
+                .short('p')
+                .value_name("NOTIFICATION")
+                .help("Post a notification to the device"),
+        )
+        .arg(
+            Arg::new("observe")
+                .long("observe")
+                .short('o')
+                .value_name("NOTIFICATION")
+                .help("Observe a notification (can be specified multiple times)")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .short('t')
+                .value_name("SECONDS")
+                .help("Timeout in seconds (default: 60)")
+                .default_value("60"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("notification_proxy_tool - send and receive notifications to/from iOS devices. Reimplementation of libimobiledevice's functionality.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let timeout = matches.get_one::<String>("timeout")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap_or(60);
+
+    let provider = common::get_provider(udid, host, pairing_file, "notification-proxy-tool-jkcoxson").await?;
+
+    let mut notification_proxy_client = match NotificationProxyClient::connect(&*provider).await {
+        Ok(client) => client,
+        Err(e) => return Err(e).context("Failed to connect to Notification Proxy service"),
+    };
+
+    // Post a notification if requested
+    if let Some(notification) = matches.get_one::<String>("post") {
+        let notification_type = parse_notification(notification);
+        println!("Posting notification: {:?}", notification_type);
+        
+        match notification_proxy_client.post_notification(notification_type).await {
+            Ok(_) => println!("Notification posted successfully"),
+            Err(e) => return Err(e).context("Failed to post notification"),
+        }
+    }
+
+    // Observe notifications if requested
+    if let Some(notifications) = matches.get_many::<String>("observe") {
+        let notification_types: Vec<_> = notifications
+            .map(|n| parse_notification(n))
+            .collect();
+        
+        println!("Observing notifications: {:?}", notification_types);
+        
+        // Observe each notification
+        for notification_type in &notification_types {
+            match notification_proxy_client.observe_notification(notification_type.clone()).await {
+                Ok(_) => println!("Observing: {:?}", notification_type),
+                Err(e) => return Err(e).context("Failed to observe notification"),
+            }
+        }
+        
+        // Start listening for notifications
+        match notification_proxy_client.start_listening().await {
+            Ok(mut rx) => {
+                println!("Listening for notifications for {} seconds...", timeout);
+                
+                // Set up a timeout
+                let timeout_duration = Duration::from_secs(timeout);
+                let timeout_future = tokio::time::sleep(timeout_duration);
+                
+                tokio::pin!(timeout_future);
+                
+                loop {
+                    tokio::select! {
+                        Some(notification) = rx.recv() => {
+                            println!("Received notification: {:?}", notification);
+                        }
+                        _ = &mut timeout_future => {
+                            println!("Timeout reached");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => return Err(e).context("Failed to start listening for notifications"),
+        }
+    }
+    Ok(())
+}
+
+fn parse_notification(notification: &str) -> NotificationType {
+    match notification {
+        "sync-will-start" => NotificationType::SyncWillStart,
+        "sync-did-finish" => NotificationType::SyncDidFinish,
+        "backup-will-start" => NotificationType::BackupWillStart,
+        "backup-did-finish" => NotificationType::BackupDidFinish,
+        "restore-will-start" => NotificationType::RestoreWillStart,
+        "restore-did-finish" => NotificationType::RestoreDidFinish,
+        "app-installed" => NotificationType::AppInstalled,
+        "app-uninstalled" => NotificationType::AppUninstalled,
+        "pairing-succeeded" => NotificationType::PairingSucceeded,
+        "itunes-sync-will-start" => NotificationType::ITunesSyncWillStart,
+        "itunes-sync-did-finish" => NotificationType::ITunesSyncDidFinish,
+        "itunes-sync-lock-request" => NotificationType::ITunesSyncLockRequest,
+        "itunes-sync-did-start" => NotificationType::ITunesSyncDidStart,
+        "download-will-start" => NotificationType::DownloadWillStart,
+        "download-did-finish" => NotificationType::DownloadDidFinish,
+        "developer-image-mounted" => NotificationType::DeveloperImageMounted,
+        "device-name-changed" => NotificationType::DeviceNameChanged,
+        "language-changed" => NotificationType::LanguageChanged,
+        // Full notification names map to known types, anything else is custom
+        _ => NotificationType::from(notification.to_string()),
+    }
+}
\ No newline at end of file
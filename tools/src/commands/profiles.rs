@@ -0,0 +1,48 @@
+// Jackson Coxson
+
+use clap::{ArgMatches, Command};
+use idevice::{misagent::MisagentClient, pretty_print_plist, IdeviceService};
+
+use crate::{
+    common,
+    error::{Context, ExitCode, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("misagent")
+        .about("Manage provisioning profiles")
+        .subcommand(Command::new("list").about("Lists the images mounted on the device"))
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("mounter - query and manage images mounted on a device. Reimplementation of libimobiledevice's binary.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "misagent-jkcoxson").await?;
+    let mut misagent_client = MisagentClient::connect(&*provider)
+        .await
+        .context("Unable to connect to misagent")?;
+
+    if matches.subcommand_matches("list").is_some() {
+        let images = misagent_client
+            .copy_all()
+            .await
+            .context("Unable to get images")?;
+        for i in images {
+            println!("{}", pretty_print_plist(&i));
+        }
+        Ok(())
+    } else {
+        Err(ToolError::new(
+            ExitCode::Usage,
+            "Invalid usage, pass -h for help",
+        ))
+    }
+}
@@ -0,0 +1,44 @@
+// Jackson Coxson
+// Print out all the RemoteXPC services
+
+use clap::{ArgMatches, Command};
+use idevice::{core_device_proxy::CoreDeviceProxy, xpc::XPCDevice, IdeviceService};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("remotexpc").about("Get services from RemoteXPC")
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("remotexpc - get info from RemoteXPC");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let host = matches.get_one::<String>("host");
+
+    let provider = common::get_provider(udid, host, pairing_file, "remotexpc-jkcoxson").await?;
+
+    let proxy = CoreDeviceProxy::connect(&*provider)
+        .await
+        .context("no core proxy")?;
+    let rsd_port = proxy.handshake.server_rsd_port;
+
+    let mut adapter = proxy
+        .create_software_tunnel()
+        .context("no software tunnel")?;
+    adapter.connect(rsd_port).await.context("no RSD connect")?;
+
+    // Make the connection to RemoteXPC
+    let client = XPCDevice::new(Box::new(adapter)).await?;
+
+    println!("{:#?}", client.services);
+    Ok(())
+}
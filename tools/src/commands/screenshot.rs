@@ -0,0 +1,53 @@
+// Jackson Coxson
+// idevice Rust implementation of screenshot functionality
+
+use clap::{Arg, ArgMatches, Command};
+use idevice::{screenshot::ScreenshotClient, IdeviceService};
+use std::path::Path;
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("screenshot_tool")
+        .about("Capture screenshots from iOS devices")
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FILE")
+                .help("Output file path (default: screenshot.png)")
+                .default_value("screenshot.png"),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("screenshot_tool - capture screenshots from iOS devices. Reimplementation of libimobiledevice's functionality.");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+    let output_path = matches.get_one::<String>("output").unwrap();
+
+    let provider = common::get_provider(udid, host, pairing_file, "screenshot-tool-jkcoxson").await?;
+
+    let mut screenshot_client = match ScreenshotClient::connect(&*provider).await {
+        Ok(client) => client,
+        Err(e) => return Err(e).context("Failed to connect to screenshot service"),
+    };
+
+    println!("Taking screenshot...");
+    match screenshot_client.save_screenshot(output_path).await {
+        Ok(_) => {
+            println!("Screenshot saved to: {}", output_path);
+        }
+        Err(e) => return Err(e).context("Failed to take screenshot"),
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,130 @@
+// Jackson Coxson
+// Streams the device's unified log through os_trace_relay
+
+use std::{io::Write, time::UNIX_EPOCH};
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use idevice::{
+    mcinstall::{install_logging_profile, remove_logging_profile, LoggingProfile},
+    os_trace_relay::{LogLevel, OsTraceRelayClient},
+    IdeviceService,
+};
+
+use crate::{
+    common,
+    error::{Context, ToolError},
+};
+
+pub fn command() -> Command {
+    common::device_command("syslog")
+        .about("Stream the device's logs")
+        .arg(
+            Arg::new("pid")
+                .long("pid")
+                .value_name("PID")
+                .help("Only show messages from this process")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("errors")
+                .long("errors")
+                .help("Only show errors and faults")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("private")
+                .long("private")
+                .help("Install a logging profile that shows private data, removed on exit")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("yes")
+                .long("yes")
+                .help("Don't ask before installing the logging profile")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub async fn run(matches: &ArgMatches) -> Result<(), ToolError> {
+    if matches.get_flag("about") {
+        println!("syslog - stream the device's unified log");
+        println!("Copyright (c) 2025 Jackson Coxson");
+        return Ok(());
+    }
+
+    let udid = matches.get_one::<String>("udid");
+    let host = matches.get_one::<String>("host");
+    let pairing_file = matches.get_one::<String>("pairing_file");
+
+    let provider = common::get_provider(udid, host, pairing_file, "syslog-jkcoxson").await?;
+
+    let private = if matches.get_flag("private") {
+        let yes = matches.get_flag("yes");
+        install_logging_profile(&*provider, &LoggingProfile::new(), |warning| {
+            yes || confirm(warning)
+        })
+        .await
+        .context("Unable to install the logging profile")?
+    } else {
+        false
+    };
+
+    let min_level = if matches.get_flag("errors") {
+        LogLevel::Error
+    } else {
+        LogLevel::Debug
+    };
+    let res = tokio::select! {
+        r = stream(&*provider, matches.get_one::<u32>("pid").copied(), min_level) => r,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    if private {
+        remove_logging_profile(&*provider)
+            .await
+            .context("Unable to remove the logging profile")?;
+    }
+    res
+}
+
+/// Asks on the terminal, defaulting to no
+fn confirm(warning: &str) -> bool {
+    println!("{warning}");
+    print!("Continue? [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+async fn stream(
+    provider: &dyn idevice::provider::IdeviceProvider,
+    pid: Option<u32>,
+    min_level: LogLevel,
+) -> Result<(), ToolError> {
+    let mut client = OsTraceRelayClient::connect(provider)
+        .await
+        .context("Unable to connect to os_trace_relay")?;
+    client
+        .start_trace(pid)
+        .await
+        .context("Unable to start the trace")?;
+
+    loop {
+        let entry = client.next_log().await.context("Log stream ended")?;
+        if entry.level < min_level {
+            continue;
+        }
+        let time = entry
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        println!(
+            "{time:.3} {}[{}] <{:?}>: {}",
+            entry.image_name, entry.pid, entry.level, entry.message
+        );
+    }
+}
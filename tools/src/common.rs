@@ -6,51 +6,126 @@ use std::{
     str::FromStr,
 };
 
+use clap::{value_parser, Arg, ArgMatches, Command};
+use clap_complete::Shell;
+
 use idevice::{
     pairing_file::PairingFile,
     provider::{IdeviceProvider, TcpProvider},
     usbmuxd::{UsbmuxdAddr, UsbmuxdConnection},
 };
 
+use crate::error::{Context, ExitCode, ToolError};
+
+/// A command with the arguments every tool uses to pick a device.
+/// The UDID is the first positional argument unless the tool moves it.
+pub fn device_command(name: &'static str) -> Command {
+    Command::new(name)
+        .arg(
+            Arg::new("host")
+                .long("host")
+                .value_name("HOST")
+                .help("IP address of the device"),
+        )
+        .arg(
+            Arg::new("pairing_file")
+                .long("pairing-file")
+                .value_name("PATH")
+                .help("Path to the pairing file"),
+        )
+        .arg(
+            Arg::new("udid")
+                .value_name("UDID")
+                .help("UDID of the device (overrides host/pairing file)")
+                .index(1),
+        )
+        .arg(
+            Arg::new("about")
+                .long("about")
+                .help("Show about information")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+/// Parses the arguments, handling the ``completions`` and ``manpage`` subcommands that
+/// every tool has. Those print to stdout and exit, so packagers can run
+/// ``<tool> completions bash`` or ``<tool> manpage`` at build time.
+pub fn get_matches(command: Command) -> ArgMatches {
+    let man = command.clone();
+    let mut command = command
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .required(true)
+                        .value_parser(value_parser!(Shell)),
+                ),
+        )
+        .subcommand(Command::new("manpage").about("Print a man page"));
+
+    let matches = match command.clone().try_get_matches() {
+        Ok(m) => m,
+        // Help and version requests are errors to clap too, but aren't failures
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let _ = e.print();
+            std::process::exit(ExitCode::Usage as i32);
+        }
+    };
+    match matches.subcommand() {
+        Some(("completions", sub)) => {
+            let shell = *sub.get_one::<Shell>("shell").unwrap();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        }
+        Some(("manpage", _)) => {
+            clap_mangen::Man::new(man)
+                .render(&mut std::io::stdout())
+                .expect("Unable to write the man page");
+        }
+        _ => return matches,
+    }
+    std::process::exit(0);
+}
+
 pub async fn get_provider(
     udid: Option<&String>,
     host: Option<&String>,
     pairing_file: Option<&String>,
     label: &str,
-) -> Result<Box<dyn IdeviceProvider>, String> {
-    let provider: Box<dyn IdeviceProvider> = if udid.is_some() {
-        let udid = udid.unwrap();
-
-        let mut usbmuxd = if let Ok(var) = std::env::var("USBMUXD_SOCKET_ADDRESS") {
-            let socket = SocketAddr::from_str(&var).expect("Bad USBMUXD_SOCKET_ADDRESS");
-            let socket = tokio::net::TcpStream::connect(socket)
-                .await
-                .expect("unable to connect to socket address");
-            UsbmuxdConnection::new(Box::new(socket), 1)
-        } else {
-            UsbmuxdConnection::default()
-                .await
-                .expect("Unable to connect to usbmxud")
-        };
-
+) -> Result<Box<dyn IdeviceProvider>, ToolError> {
+    let provider: Box<dyn IdeviceProvider> = if let Some(udid) = udid {
+        let mut usbmuxd = connect_usbmuxd().await?;
         let dev = match usbmuxd.get_device(udid).await {
             Ok(d) => d,
             Err(e) => {
-                return Err(format!("Device not found: {e:?}"));
+                return Err(ToolError::new(
+                    ExitCode::DeviceNotFound,
+                    format!("Device not found: {e:?}"),
+                ));
             }
         };
         Box::new(dev.to_provider(UsbmuxdAddr::from_env_var().unwrap(), 1, label))
-    } else if host.is_some() && pairing_file.is_some() {
-        let host = match IpAddr::from_str(host.unwrap()) {
+    } else if let (Some(host), Some(pairing_file)) = (host, pairing_file) {
+        let host = match IpAddr::from_str(host) {
             Ok(h) => h,
             Err(e) => {
-                return Err(format!("Invalid host: {e:?}"));
+                return Err(ToolError::new(
+                    ExitCode::Usage,
+                    format!("Invalid host: {e:?}"),
+                ));
             }
         };
-        let pairing_file = match PairingFile::read_from_file(pairing_file.unwrap()) {
+        let pairing_file = match PairingFile::read_from_file(pairing_file) {
             Ok(p) => p,
             Err(e) => {
-                return Err(format!("Unable to read pairing file: {e:?}"));
+                return Err(ToolError::new(
+                    ExitCode::PairingRequired,
+                    format!("Unable to read pairing file: {e:?}"),
+                ));
             }
         };
 
@@ -60,27 +135,37 @@ pub async fn get_provider(
             label: "ideviceinfo-jkcoxson".to_string(),
         })
     } else {
-        let mut usbmuxd = if let Ok(var) = std::env::var("USBMUXD_SOCKET_ADDRESS") {
-            let socket = SocketAddr::from_str(&var).expect("Bad USBMUXD_SOCKET_ADDRESS");
-            let socket = tokio::net::TcpStream::connect(socket)
-                .await
-                .expect("unable to connect to socket address");
-            UsbmuxdConnection::new(Box::new(socket), 1)
-        } else {
-            UsbmuxdConnection::default()
-                .await
-                .expect("Unable to connect to usbmxud")
-        };
-        let devs = match usbmuxd.get_devices().await {
-            Ok(d) => d,
-            Err(e) => {
-                return Err(format!("Unable to get devices from usbmuxd: {e:?}"));
-            }
-        };
+        let mut usbmuxd = connect_usbmuxd().await?;
+        let devs = usbmuxd
+            .get_devices()
+            .await
+            .context("Unable to get devices from usbmuxd")?;
         if devs.is_empty() {
-            return Err("No devices connected!".to_string());
+            return Err(ToolError::new(
+                ExitCode::DeviceNotFound,
+                "No devices connected!",
+            ));
         }
         Box::new(devs[0].to_provider(UsbmuxdAddr::from_env_var().unwrap(), 0, label))
     };
     Ok(provider)
 }
+
+/// Connects to usbmuxd, or to ``USBMUXD_SOCKET_ADDRESS`` when it's set.
+/// Without usbmuxd there are no devices, so failing to connect is ``DeviceNotFound``.
+pub async fn connect_usbmuxd() -> Result<UsbmuxdConnection, ToolError> {
+    let not_found = |e: String| ToolError::new(ExitCode::DeviceNotFound, e);
+    if let Ok(var) = std::env::var("USBMUXD_SOCKET_ADDRESS") {
+        let socket = SocketAddr::from_str(&var).map_err(|e| {
+            ToolError::new(ExitCode::Usage, format!("Bad USBMUXD_SOCKET_ADDRESS: {e}"))
+        })?;
+        let socket = tokio::net::TcpStream::connect(socket)
+            .await
+            .map_err(|e| not_found(format!("Unable to connect to {socket}: {e}")))?;
+        Ok(UsbmuxdConnection::new(Box::new(socket), 1))
+    } else {
+        UsbmuxdConnection::default()
+            .await
+            .map_err(|e| not_found(format!("Unable to connect to usbmuxd: {e:?}")))
+    }
+}
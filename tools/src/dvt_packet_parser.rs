@@ -15,7 +15,7 @@ async fn main() {
         for v in aux.values {
             match v {
                 idevice::dvt::message::AuxValue::Array(a) => {
-                    match ns_keyed_archive::decode::from_bytes(&a) {
+                    match idevice::nskeyed::decode(&a) {
                         Ok(a) => {
                             println!("{a:#?}");
                         }
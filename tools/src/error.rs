@@ -0,0 +1,113 @@
+// Jackson Coxson
+// Exit codes shared by every tool, so scripts can tell failures apart without reading stderr.
+// The numbers are stable; new outcomes get new numbers instead of reusing old ones.
+
+use std::fmt;
+
+use idevice::{IdeviceError, Pretty};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Anything without a more specific code
+    Failure = 1,
+    /// No device is connected, or not the one asked for
+    DeviceNotFound = 2,
+    /// The device isn't paired with this host, or the pairing file is missing or invalid
+    PairingRequired = 3,
+    /// The device refused to start or connect to a service
+    ServiceUnavailable = 4,
+    /// The device has to be unlocked first
+    DeviceLocked = 5,
+    Timeout = 6,
+    /// The service needs a developer disk image mounted
+    DeveloperImageRequired = 7,
+    /// The arguments couldn't be parsed
+    Usage = 64,
+}
+
+impl From<&IdeviceError> for ExitCode {
+    fn from(e: &IdeviceError) -> Self {
+        match e {
+            IdeviceError::DeviceNotFound | IdeviceError::UsbBadDevice => Self::DeviceNotFound,
+            IdeviceError::InvalidHostID
+            | IdeviceError::SessionInactive
+            | IdeviceError::InvalidPairingFile(_)
+            | IdeviceError::InvalidPairingOffer
+            | IdeviceError::PairingOfferMismatch => Self::PairingRequired,
+            IdeviceError::UsbConnectionRefused | IdeviceError::NoEstablishedConnection => {
+                Self::ServiceUnavailable
+            }
+            IdeviceError::Socket(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                Self::ServiceUnavailable
+            }
+            IdeviceError::DeviceLocked => Self::DeviceLocked,
+            IdeviceError::Timeout | IdeviceError::HeartbeatTimeout => Self::Timeout,
+            IdeviceError::ImageNotMounted | IdeviceError::DeveloperImageNotMounted => {
+                Self::DeveloperImageRequired
+            }
+            _ => Self::Failure,
+        }
+    }
+}
+
+/// An error ending a tool, with the message to print and the code to exit with
+#[derive(Debug)]
+pub struct ToolError {
+    pub code: ExitCode,
+    pub message: String,
+}
+
+impl ToolError {
+    pub fn new(code: ExitCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Prints the message and exits the process with the error's code
+    pub fn exit(&self) -> ! {
+        eprintln!("{}", self.message);
+        std::process::exit(self.code as i32);
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<IdeviceError> for ToolError {
+    fn from(e: IdeviceError) -> Self {
+        let message = match e {
+            // Say how to fix it rather than just what went wrong
+            IdeviceError::DeveloperImageNotMounted => {
+                format!("{e}, for example with `idevice mount mount`")
+            }
+            _ => e.pretty(),
+        };
+        Self::new(ExitCode::from(&e), message)
+    }
+}
+
+impl From<std::io::Error> for ToolError {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(ExitCode::Failure, e.to_string())
+    }
+}
+
+/// Adds what was being done to an error, like ``Unable to connect to lockdown: ...``
+pub trait Context<T> {
+    fn context(self, what: &str) -> Result<T, ToolError>;
+}
+
+impl<T, E: Into<ToolError>> Context<T> for Result<T, E> {
+    fn context(self, what: &str) -> Result<T, ToolError> {
+        self.map_err(|e| {
+            let e = e.into();
+            ToolError::new(e.code, format!("{what}: {}", e.message))
+        })
+    }
+}
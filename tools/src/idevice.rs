@@ -0,0 +1,58 @@
+// Jackson Coxson
+// Every tool as a subcommand of one binary
+
+use clap::Command;
+use idevice_tools::{commands::*, common};
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let command = Command::new("idevice")
+        .about("Interact with services on iOS devices")
+        .subcommand_required(true)
+        .subcommand(afc::command().name("afc"))
+        .subcommand(apps::command().name("apps"))
+        .subcommand(backup::command().name("backup"))
+        .subcommand(debug_proxy::command().name("debug-proxy"))
+        .subcommand(diagnostics::command().name("diagnostics"))
+        .subcommand(file_relay::command().name("file-relay"))
+        .subcommand(heartbeat::command().name("heartbeat"))
+        .subcommand(house_arrest::command().name("house-arrest"))
+        .subcommand(info::command().name("info"))
+        .subcommand(launch::command().name("launch"))
+        .subcommand(list::command().name("list"))
+        .subcommand(mount::command().name("mount"))
+        .subcommand(notify::command().name("notify"))
+        .subcommand(profiles::command().name("profiles"))
+        .subcommand(remotexpc::command().name("remotexpc"))
+        .subcommand(screenshot::command().name("screenshot"))
+        .subcommand(syslog::command().name("syslog"))
+        .subcommand(tunnel::command().name("tunnel"));
+
+    let matches = common::get_matches(command);
+    let res = match matches.subcommand() {
+        Some(("afc", m)) => afc::run(m).await,
+        Some(("apps", m)) => apps::run(m).await,
+        Some(("backup", m)) => backup::run(m).await,
+        Some(("debug-proxy", m)) => debug_proxy::run(m).await,
+        Some(("diagnostics", m)) => diagnostics::run(m).await,
+        Some(("file-relay", m)) => file_relay::run(m).await,
+        Some(("heartbeat", m)) => heartbeat::run(m).await,
+        Some(("house-arrest", m)) => house_arrest::run(m).await,
+        Some(("info", m)) => info::run(m).await,
+        Some(("launch", m)) => launch::run(m).await,
+        Some(("list", m)) => list::run(m).await,
+        Some(("mount", m)) => mount::run(m).await,
+        Some(("notify", m)) => notify::run(m).await,
+        Some(("profiles", m)) => profiles::run(m).await,
+        Some(("remotexpc", m)) => remotexpc::run(m).await,
+        Some(("screenshot", m)) => screenshot::run(m).await,
+        Some(("syslog", m)) => syslog::run(m).await,
+        Some(("tunnel", m)) => tunnel::run(m).await,
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    if let Err(e) = res {
+        e.exit();
+    }
+}
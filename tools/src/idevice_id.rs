@@ -1,13 +1,14 @@
 // Jackson Coxson
-// Gets the devices from the muxer
+// Standalone idevice_id, the same as running idevice list
 
-use idevice::usbmuxd::UsbmuxdConnection;
+use idevice_tools::{commands::list, common};
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let mut muxer = UsbmuxdConnection::default().await.unwrap();
-    let res = muxer.get_devices().await.unwrap();
-    println!("{res:#?}");
+    let matches = common::get_matches(list::command());
+    if let Err(e) = list::run(&matches).await {
+        e.exit();
+    }
 }
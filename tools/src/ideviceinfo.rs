@@ -1,74 +1,14 @@
 // Jackson Coxson
-// idevice Rust implementation of libimobiledevice's ideviceinfo
+// Standalone ideviceinfo, the same as running idevice info
 
-use clap::{Arg, Command};
-use idevice::{lockdownd::LockdowndClient, pairing_file::PairingFile, IdeviceService};
-
-mod common;
+use idevice_tools::{commands::info, common};
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let matches = Command::new("core_device_proxy_tun")
-        .about("Start a tunnel")
-        .arg(
-            Arg::new("host")
-                .long("host")
-                .value_name("HOST")
-                .help("IP address of the device"),
-        )
-        .arg(
-            Arg::new("pairing_file")
-                .long("pairing-file")
-                .value_name("PATH")
-                .help("Path to the pairing file"),
-        )
-        .arg(
-            Arg::new("udid")
-                .value_name("UDID")
-                .help("UDID of the device (overrides host/pairing file)")
-                .index(1),
-        )
-        .arg(
-            Arg::new("about")
-                .long("about")
-                .help("Show about information")
-                .action(clap::ArgAction::SetTrue),
-        )
-        .get_matches();
-
-    if matches.get_flag("about") {
-        println!("ideviceinfo - get information from the idevice. Reimplementation of libimobiledevice's binary.");
-        println!("Copyright (c) 2025 Jackson Coxson");
-        return;
+    let matches = common::get_matches(info::command());
+    if let Err(e) = info::run(&matches).await {
+        e.exit();
     }
-
-    let udid = matches.get_one::<String>("udid");
-    let host = matches.get_one::<String>("host");
-    let pairing_file = matches.get_one::<String>("pairing_file");
-
-    let provider =
-        match common::get_provider(udid, host, pairing_file, "ideviceinfo-jkcoxson").await {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("{e}");
-                return;
-            }
-        };
-
-    let mut lockdown_client = match LockdowndClient::connect(&*provider).await {
-        Ok(l) => l,
-        Err(e) => {
-            eprintln!("Unable to connect to lockdown: {e:?}");
-            return;
-        }
-    };
-
-    println!("{:?}", lockdown_client.get_value("ProductVersion").await);
-
-    let p = PairingFile::read_from_file(pairing_file.unwrap()).unwrap();
-    println!("{:?}", lockdown_client.start_session(&p).await);
-    println!("{:?}", lockdown_client.idevice.get_type().await.unwrap());
-    println!("{:#?}", lockdown_client.get_all_values().await);
 }
@@ -0,0 +1,6 @@
+// Jackson Coxson
+// The tools as a library, so each can be its own binary and a subcommand of idevice
+
+pub mod commands;
+pub mod common;
+pub mod error;